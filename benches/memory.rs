@@ -0,0 +1,67 @@
+//! Measures the heap memory a parsed 10k-entry archive holds, by counting
+//! allocations.  It runs on the stable compiler, so it doesn't use the
+//! unstable `test` harness:
+//!
+//!     $ cargo bench --bench memory
+extern crate earth;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use earth::parser::{ParseDiagnostics, ParseOptions, parse};
+use earth::test_utils::atom_feed;
+
+const ENTRIES: usize = 10000;
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) +
+                   layout.size();
+        PEAK.fetch_max(size, Ordering::SeqCst);
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    let xml = atom_feed(ENTRIES);
+    let base = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(base, Ordering::SeqCst);
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst);
+    let feed = parse(xml.as_bytes(), "http://example.com/feed.xml",
+                     &ParseOptions::default(), &ParseDiagnostics::new())
+        .unwrap();
+    let held = ALLOCATED.load(Ordering::SeqCst) - base;
+    let peak = PEAK.load(Ordering::SeqCst) - base;
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst) - allocations;
+    let links = feed.entries.iter().flat_map(|e| e.links.iter());
+    let (borrowed, owned) = links.fold((0, 0), |(b, o), link| {
+        match link.mimetype {
+            Some(Cow::Borrowed(_)) => (b + 1, o),
+            Some(Cow::Owned(_)) => (b, o + 1),
+            None => (b, o),
+        }
+    });
+    println!("entries:          {}", feed.entries.len());
+    println!("document:         {} bytes", xml.len());
+    println!("held:             {} bytes ({} bytes/entry)",
+             held, held / ENTRIES);
+    println!("peak:             {} bytes", peak);
+    println!("allocations:      {}", allocations);
+    println!("interned types:   {} borrowed, {} owned", borrowed, owned);
+}
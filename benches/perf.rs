@@ -0,0 +1,98 @@
+//! Timing benchmarks to catch performance regressions.  The unstable `test`
+//! harness requires the nightly compiler, so these run with a small harness
+//! of their own which prints results in the same format:
+//!
+//!     $ cargo bench --bench perf
+//!     $ cargo bench --bench perf -- merge    # only ones containing "merge"
+//!
+//! Inputs are made by generators in `earth::test_utils`, so that changes
+//! can be compared against the same documents.
+extern crate earth;
+
+use std::env;
+use std::time::{Duration, Instant};
+
+use earth::parser::{ParseDiagnostics, ParseOptions, parse};
+use earth::repository::{FileSystemRepository, Repository};
+use earth::schema::Mergeable;
+use earth::stage::DirtyBuffer;
+use earth::test_utils::{atom_feed_of_size, feed_of_entries, long_html,
+                        temp_dir};
+
+/// How long each benchmark runs at least, after a warming up iteration.
+const MEASURE_FOR: Duration = Duration::from_secs(2);
+
+fn bench<F>(filter: &Option<String>, name: &str, mut f: F)
+    where F: FnMut()
+{
+    if let Some(ref filter) = *filter {
+        if !name.contains(&filter[..]) {
+            return;
+        }
+    }
+    f();
+    let mut times = vec![];
+    let started_at = Instant::now();
+    while started_at.elapsed() < MEASURE_FOR || times.len() < 5 {
+        let t = Instant::now();
+        f();
+        times.push(t.elapsed());
+    }
+    times.sort();
+    let nanos = |d: Duration| d.as_secs() * 1_000_000_000 +
+                              d.subsec_nanos() as u64;
+    let median = nanos(times[times.len() / 2]);
+    let spread = nanos(times[times.len() - 1]) - nanos(times[0]);
+    println!("test {:<32} ... bench: {:>14} ns/iter (+/- {})",
+             name, median, spread);
+}
+
+fn main() {
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("-"));
+
+    let xml = atom_feed_of_size(5 * 1024 * 1024);
+    bench(&filter, "parse_atom_5mb", || {
+        parse(xml.as_bytes(), "http://example.com/feed.xml",
+              &ParseOptions::default(), &ParseDiagnostics::new()).unwrap();
+    });
+
+    let html = long_html(2000);
+    bench(&filter, "sanitize_long_html", || {
+        sanitize(&html);
+    });
+
+    let tmpdir = temp_dir();
+    let doc = atom_feed_of_size(4096).into_bytes();
+    let mut round = 0;
+    bench(&filter, "flush_dirty_buffer_1k", || {
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true)
+            .unwrap();
+        let mut buffer = DirtyBuffer::new(repo);
+        round += 1;
+        for i in 0..1000 {
+            let key = [format!("round{}", round), format!("doc{}", i)];
+            buffer.write(&key, Some(&doc[..])).unwrap();
+        }
+        buffer.flush().unwrap();
+    });
+
+    let a = feed_of_entries(0, 1000);
+    let b = feed_of_entries(500, 1000);
+    bench(&filter, "merge_feeds_1k", || {
+        let mut merged = a.clone();
+        merged.merge_with(b.clone());
+    });
+}
+
+#[cfg(html_sanitizer)]
+fn sanitize(html: &str) -> String {
+    use earth::sanitizer::sanitize_html;
+    format!("{}", sanitize_html(html, Some("http://example.com/")))
+}
+
+/// Without the HTML sanitizer, HTML is escaped as a whole.
+#[cfg(not(html_sanitizer))]
+fn sanitize(html: &str) -> String {
+    use earth::sanitizer::escape;
+    format!("{}", escape(html, true))
+}
@@ -0,0 +1,242 @@
+//! A minimal feed reader on the command line, which shows how apps wire the
+//! stage, the crawler and the renderer together:
+//!
+//!     $ cargo run --example earth-cli --features crawler -- \
+//!           ~/.earth subscribe http://blog.earthreader.org/
+//!     $ cargo run --example earth-cli --features crawler -- ~/.earth refresh
+//!     $ cargo run --example earth-cli --features crawler -- ~/.earth unread
+//!     $ cargo run --example earth-cli --features crawler -- \
+//!           ~/.earth show FEED_ID ENTRY_ID
+//!     $ cargo run --example earth-cli --features crawler -- \
+//!           ~/.earth read FEED_ID ENTRY_ID
+//!
+//! The crate doesn't bundle any HTTP client, so this example brings its own
+//! `Backend` on `std::net`.  It speaks plain HTTP/1.0 only; real apps
+//! should plug in a client which supports HTTPS and the rest of their
+//! `TransportConfig`.
+extern crate earth;
+extern crate url;
+
+use std::env;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process;
+
+use url::Url;
+
+use earth::crawler::{Backend, Redirect, Request, Response};
+use earth::feed::MarkKind;
+use earth::parser::ParseOptions;
+use earth::render::{TimeDisplay, entry_html};
+use earth::repository::FileSystemRepository;
+use earth::stage::Stage;
+use earth::subscribe::SubscriptionSet;
+
+/// How many redirects are followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+const USAGE: &'static str = "\
+usage: earth-cli DIR subscribe URL
+       earth-cli DIR refresh
+       earth-cli DIR unread
+       earth-cli DIR show FEED_ID ENTRY_ID
+       earth-cli DIR read FEED_ID ENTRY_ID";
+
+type CliResult = Result<(), Box<Error>>;
+
+/// `Backend` over plain TCP connections, one per request.
+struct TcpBackend;
+
+impl Backend for TcpBackend {
+    fn fetch(&self, request: &Request) -> io::Result<Response> {
+        let mut url = try!(parse_url(&request.url));
+        let mut redirects = vec![];
+        loop {
+            let mut response = try!(get(&url, request));
+            let location = match response.status {
+                301 | 302 | 303 | 307 | 308 => {
+                    response.header("Location").map(|l| l.to_owned())
+                }
+                _ => None,
+            };
+            let location = match location {
+                Some(location) if redirects.len() < MAX_REDIRECTS => location,
+                _ => {
+                    response.redirects = redirects;
+                    return Ok(response);
+                }
+            };
+            url = try!(url.join(&location).map_err(invalid_url));
+            redirects.push(Redirect::new(response.status, url.as_str()));
+        }
+    }
+}
+
+fn parse_url(url: &str) -> io::Result<Url> {
+    let url = try!(Url::parse(url).map_err(invalid_url));
+    if url.scheme() != "http" {
+        return Err(io::Error::other("only http: URLs are supported"));
+    }
+    Ok(url)
+}
+
+fn invalid_url(err: url::ParseError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+}
+
+/// Make a single GET request without following redirects.
+fn get(url: &Url, request: &Request) -> io::Result<Response> {
+    let host = match url.host_str() {
+        Some(host) => host,
+        None => { return Err(invalid_url(url::ParseError::EmptyHost)); }
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut stream = try!(TcpStream::connect((host, port)));
+    try!(stream.set_read_timeout(request.timeout));
+    let mut target = url.path().to_owned();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    let mut head = format!("GET {} HTTP/1.0\r\nHost: {}\r\n", target, host);
+    for (name, value) in request.headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    try!(stream.write_all(head.as_bytes()));
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    try!(reader.read_line(&mut line));
+    let status = match line.split_whitespace().nth(1)
+                           .and_then(|s| s.parse().ok()) {
+        Some(status) => status,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "malformed status line"));
+        }
+    };
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        if try!(reader.read_line(&mut line)) == 0 {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let mut fields = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+            headers.push((name.to_owned(), value.trim().to_owned()));
+        }
+    }
+    let mut body = vec![];
+    try!(reader.read_to_end(&mut body));
+    Ok(Response {
+        url: url.as_str().to_owned(),
+        status: status,
+        headers: headers,
+        body: body,
+        redirects: vec![],
+    })
+}
+
+fn subscribe(stage: &mut Stage<FileSystemRepository>, url: &str)
+             -> CliResult {
+    let root: &[&str] = &[];
+    let subscribed = try!(stage.subscribe_by_url(&TcpBackend, url, root,
+                                                 &ParseOptions::default()));
+    if subscribed.subscribed {
+        println!("subscribed to {} as {}", subscribed.subscription.label,
+                 subscribed.feed_id);
+    } else {
+        println!("already subscribed as {}", subscribed.feed_id);
+    }
+    Ok(())
+}
+
+fn refresh(stage: &mut Stage<FileSystemRepository>) -> CliResult {
+    let subscriptions = try!(stage.read_subscriptions());
+    let options = ParseOptions::default();
+    for subscription in subscriptions.recursive_subscriptions() {
+        let feed_id = &subscription.feed_id;
+        let outcome = match try!(stage.refresh(&TcpBackend, feed_id,
+                                               &options)) {
+            Some(outcome) => outcome,
+            None => { continue; }
+        };
+        if let Some(err) = outcome.error {
+            println!("{}: {}", feed_id, err);
+        } else if let Some(insanity) = outcome.quarantined {
+            println!("{}: quarantined: {}", feed_id, insanity);
+        } else {
+            println!("{}: {} new, {} updated", feed_id, outcome.new_entries,
+                     outcome.updated_entries);
+        }
+    }
+    Ok(())
+}
+
+fn unread(stage: &Stage<FileSystemRepository>) -> CliResult {
+    for (feed_id, entry) in try!(stage.unread_entries()) {
+        println!("{}\t{}\t{}", feed_id, entry.id,
+                 entry.title.as_plain_text());
+    }
+    Ok(())
+}
+
+fn show(stage: &Stage<FileSystemRepository>, feed_id: &str, entry_id: &str)
+        -> CliResult {
+    let feed = try!(stage.read_feed(feed_id));
+    let entry = match feed.entries.iter().find(|e| e.id == entry_id) {
+        Some(entry) => entry,
+        None => { return Err(From::from("no such entry")); }
+    };
+    let settings = try!(stage.read_settings(feed_id));
+    let policy = settings.sanitizer_policy(Default::default());
+    let time = TimeDisplay::default();
+    println!("{}", entry_html(entry, &time).preset(policy.preset));
+    Ok(())
+}
+
+fn mark_read(stage: &mut Stage<FileSystemRepository>, feed_id: &str,
+             entry_id: &str) -> CliResult {
+    if !try!(stage.set_mark(feed_id, entry_id, MarkKind::Read, true)) {
+        println!("already read");
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> CliResult {
+    let (dir, command) = match (args.get(1), args.get(2)) {
+        (Some(dir), Some(command)) => (dir, &command[..]),
+        _ => { return Err(From::from(USAGE)); }
+    };
+    let operands: Vec<&str> = args[3..].iter().map(|a| &a[..]).collect();
+    let repository = try!(FileSystemRepository::from_path(dir, true));
+    let mut stage = try!(Stage::open(repository));
+    match (command, &operands[..]) {
+        ("subscribe", [url]) => { try!(subscribe(&mut stage, url)); }
+        ("refresh", []) => { try!(refresh(&mut stage)); }
+        ("unread", []) => { try!(unread(&stage)); }
+        ("show", [feed_id, entry_id]) => {
+            try!(show(&stage, feed_id, entry_id));
+        }
+        ("read", [feed_id, entry_id]) => {
+            try!(mark_read(&mut stage, feed_id, entry_id));
+        }
+        _ => { return Err(From::from(USAGE)); }
+    }
+    try!(stage.flush());
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Err(err) = run(&args) {
+        let _ = writeln!(io::stderr(), "{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,499 @@
+//! Storing large binary assets e.g. podcast audio, images, which don't fit
+//! documents.
+//!
+//! `BlobStore` stores blobs by their contents: a blob is identified by the
+//! SHA-1 hexdigest of its content, so the same content is stored only once
+//! however many entries refer to it.  Users of blobs, e.g. entries, record
+//! themselves as *referrers* of blobs, and blobs without referrers are
+//! removed by `BlobStore::collect_garbage()`.
+//!
+//! Blobs being downloaded are kept as *partial* blobs, which can be
+//! appended to when the download is resumed, and become blobs when they're
+//! finished.
+//!
+//! ```
+//! # use earth::blobs::{BlobStore, entry_referrer};
+//! # use earth::repository::FileSystemRepository;
+//! # use earth::test_utils::temp_dir;
+//! # let tmpdir = temp_dir();
+//! # let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+//! let mut blobs = BlobStore::new(repo, &["blobs"]);
+//! blobs.append_partial("episode", b"Earth ").unwrap();
+//! blobs.append_partial("episode", b"Reader").unwrap();
+//! let hash = blobs.finish_partial("episode").unwrap();
+//! blobs.add_ref(&hash, &entry_referrer("feed", "urn:entry:1")).unwrap();
+//! assert_eq!(blobs.read_range(&hash, 6, Some(3)).unwrap(), b"Rea");
+//! assert!(blobs.collect_garbage().unwrap().is_empty());
+//! ```
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::io::{self, Read};
+
+use digest::sha1_hex;
+use repository as repo;
+use repository::{Repository, write_atomically};
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Repository(repo::Error),
+
+    /// There's no blob of the hash.
+    NotFound(String),
+
+    /// The referrer can't be recorded since it contains a line break.
+    InvalidReferrer(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.description()));
+        match *self {
+            Error::NotFound(ref hash) => { try!(write!(f, ": {}", hash)); }
+            Error::InvalidReferrer(ref referrer) => {
+                try!(write!(f, ": {:?}", referrer));
+            }
+            Error::Repository(_) => { }
+        }
+        if let Some(cause) = self.cause() {
+            try!(write!(f, " caused by `{}`", cause));
+        }
+        Ok(())
+    }
+}
+
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Repository(_) => "repository error",
+            Error::NotFound(_) => "no such blob",
+            Error::InvalidReferrer(_) => "invalid referrer",
+        }
+    }
+
+    fn cause(&self) -> Option<&ErrorTrait> {
+        match *self {
+            Error::Repository(ref err) => Some(err as &ErrorTrait),
+            _ => None,
+        }
+    }
+}
+
+impl From<repo::Error> for Error {
+    fn from(err: repo::Error) -> Error { Error::Repository(err) }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Repository(repo::Error::Io(err))
+    }
+}
+
+/// The referrer name of the entry.  See the module documentation.
+pub fn entry_referrer(feed_id: &str, entry_id: &str) -> String {
+    format!("entry:{}:{}", feed_id, entry_id)
+}
+
+/// The referrer name of the icon of the feed.  See also
+/// `Stage::fetch_icon()`.
+pub fn icon_referrer(feed_id: &str) -> String {
+    format!("icon:{}", feed_id)
+}
+
+/// Stores blobs under the directory of the repository:
+///
+/// - `<key>/data/<shard>/<hash>`: contents of blobs, where `<shard>` is
+///   the first two digits of the hash.
+/// - `<key>/refs/<shard>/<hash>`: referrers of the blob, a line each.
+/// - `<key>/partial/<digest>/<offset>`: chunks of partial blobs, where
+///   `<digest>` is the SHA-1 hexdigest of the name of the partial blob,
+///   and `<offset>` is 16 hexadecimal digits of the offset the chunk
+///   starts at.  Each append writes a new chunk rather than rewriting the
+///   whole partial blob, and chunks are joined by `finish_partial()`.
+pub struct BlobStore<R: Repository> {
+    repository: R,
+    key: Vec<String>,
+}
+
+impl<R: Repository> BlobStore<R> {
+    /// Store blobs under the `key` directory of the `repository`.
+    pub fn new<T: AsRef<str>>(repository: R, key: &[T]) -> BlobStore<R> {
+        BlobStore {
+            repository: repository,
+            key: key.iter().map(|k| k.as_ref().to_owned()).collect(),
+        }
+    }
+
+    pub fn into_inner(self) -> R { self.repository }
+
+    /// Store the content, and return its hash.  It does nothing but
+    /// returning the hash if the same content is already stored.
+    pub fn put(&mut self, content: &[u8]) -> Result<String> {
+        let hash = sha1_hex(content);
+        let key = self.blob_key("data", &hash);
+        if !self.repository.exists(&key) {
+            try!(write_atomically(&mut self.repository, &key,
+                                  Some(content)));
+        }
+        Ok(hash)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        is_hash(hash) && self.repository.exists(&self.blob_key("data", hash))
+    }
+
+    /// The size of the blob in bytes.
+    pub fn size(&self, hash: &str) -> Result<u64> {
+        let key = try!(self.data_key(hash));
+        Ok(try!(self.repository.usage(&key)))
+    }
+
+    pub fn read(&self, hash: &str) -> Result<Vec<u8>> {
+        self.read_range(hash, 0, None)
+    }
+
+    /// Read `length` bytes of the blob from `offset`, or to the end if
+    /// `length` is `None`.  It returns less bytes if the blob ends before.
+    pub fn read_range(&self, hash: &str, offset: u64, length: Option<u64>)
+                      -> Result<Vec<u8>> {
+        let key = try!(self.data_key(hash));
+        let reader = try!(self.repository.get_reader_range(&key, offset,
+                                                           length));
+        read_all(reader)
+    }
+
+    /// Hashes of all stored blobs.
+    pub fn hashes(&self) -> Result<Vec<String>> {
+        let mut hashes = vec![];
+        let mut key = self.key.clone();
+        key.push("data".to_owned());
+        if !self.repository.exists(&key) {
+            return Ok(hashes);
+        }
+        let shards: Vec<String> = try!(try!(self.repository.list(&key))
+                                           .collect());
+        for shard in shards {
+            key.push(shard);
+            let names: Vec<String> = try!(try!(self.repository.list(&key))
+                                              .collect());
+            hashes.extend(names.into_iter().filter(|n| is_hash(n)));
+            key.pop();
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    /// Referrers of the blob, sorted.
+    pub fn refs(&self, hash: &str) -> Result<Vec<String>> {
+        let key = self.blob_key("refs", hash);
+        if !is_hash(hash) || !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let content = try!(self.repository.read(&key));
+        let content = String::from_utf8_lossy(&content);
+        Ok(content.lines().filter(|l| !l.is_empty())
+                  .map(|l| l.to_owned()).collect())
+    }
+
+    /// Record the referrer of the blob.  It returns `false` if it's
+    /// already recorded.
+    pub fn add_ref(&mut self, hash: &str, referrer: &str) -> Result<bool> {
+        try!(self.data_key(hash));
+        if referrer.is_empty() || referrer.contains(['\n', '\r']) {
+            return Err(Error::InvalidReferrer(referrer.to_owned()));
+        }
+        let mut refs = try!(self.refs(hash));
+        if refs.iter().any(|r| r == referrer) {
+            return Ok(false);
+        }
+        refs.push(referrer.to_owned());
+        refs.sort();
+        try!(self.write_refs(hash, &refs));
+        Ok(true)
+    }
+
+    /// Forget the referrer of the blob.  It returns `false` if it wasn't
+    /// recorded.  The blob remains until `collect_garbage()` even if it
+    /// loses all referrers.
+    pub fn remove_ref(&mut self, hash: &str, referrer: &str) -> Result<bool> {
+        let mut refs = try!(self.refs(hash));
+        let count = refs.len();
+        refs.retain(|r| r != referrer);
+        if refs.len() == count {
+            return Ok(false);
+        }
+        try!(self.write_refs(hash, &refs));
+        Ok(true)
+    }
+
+    /// Remove all blobs that have no referrers, and return their hashes.
+    /// Partial blobs aren't touched.
+    pub fn collect_garbage(&mut self) -> Result<Vec<String>> {
+        let mut removed = vec![];
+        for hash in try!(self.hashes()) {
+            if !try!(self.refs(&hash)).is_empty() {
+                continue;
+            }
+            try!(self.repository.delete(&self.blob_key("data", &hash)));
+            let refs_key = self.blob_key("refs", &hash);
+            if self.repository.exists(&refs_key) {
+                try!(self.repository.delete(&refs_key));
+            }
+            removed.push(hash);
+        }
+        Ok(removed)
+    }
+
+    /// The number of bytes of the partial blob written so far; `0` if
+    /// there's no such partial blob.  Downloads should be resumed from
+    /// this offset.
+    pub fn partial_len(&self, name: &str) -> Result<u64> {
+        let chunks = try!(self.partial_chunks(name));
+        Ok(chunks.last().map_or(0, |&(_, end)| end))
+    }
+
+    /// Append the data to the partial blob, which is created if it doesn't
+    /// exist.  It returns the length of the partial blob.  Only the data is
+    /// written, as a new chunk.
+    pub fn append_partial(&mut self, name: &str, data: &[u8])
+                          -> Result<u64> {
+        let len = try!(self.partial_len(name));
+        if data.is_empty() {
+            return Ok(len);
+        }
+        let key = self.chunk_key(name, len);
+        try!(write_atomically(&mut self.repository, &key, Some(data)));
+        Ok(len + data.len() as u64)
+    }
+
+    /// Read the range of the partial blob.  See `read_range()`.
+    pub fn read_partial_range(&self, name: &str, offset: u64,
+                              length: Option<u64>) -> Result<Vec<u8>> {
+        let chunks = try!(self.partial_chunks(name));
+        if chunks.is_empty() {
+            return Err(Error::NotFound(name.to_owned()));
+        }
+        let end = length.map(|l| offset.saturating_add(l));
+        let mut buf = vec![];
+        for (start, chunk_end) in chunks {
+            if chunk_end <= offset || end.is_some_and(|e| start >= e) {
+                continue;
+            }
+            let from = offset.saturating_sub(start);
+            let to = end.map_or(chunk_end, |e| e.min(chunk_end)) - start;
+            let key = self.chunk_key(name, start);
+            let reader = try!(self.repository.get_reader_range(
+                &key, from, Some(to - from)
+            ));
+            buf.extend(try!(read_all(reader)));
+        }
+        Ok(buf)
+    }
+
+    /// Turn the partial blob into a blob by joining its chunks, and return
+    /// its hash.
+    pub fn finish_partial(&mut self, name: &str) -> Result<String> {
+        let chunks = try!(self.partial_chunks(name));
+        if chunks.is_empty() {
+            return Err(Error::NotFound(name.to_owned()));
+        }
+        let mut content = vec![];
+        for &(start, _) in chunks.iter() {
+            let key = self.chunk_key(name, start);
+            content.extend(try!(self.repository.read(&key)));
+        }
+        let hash = try!(self.put(&content));
+        try!(self.discard_partial(name));
+        Ok(hash)
+    }
+
+    /// Remove the partial blob e.g. when the download is cancelled.
+    pub fn discard_partial(&mut self, name: &str) -> Result<()> {
+        for (start, _) in try!(self.partial_chunks(name)) {
+            let key = self.chunk_key(name, start);
+            try!(self.repository.delete(&key));
+        }
+        Ok(())
+    }
+
+    /// The ranges of chunks of the partial blob, in order.
+    fn partial_chunks(&self, name: &str) -> Result<Vec<(u64, u64)>> {
+        let mut key = self.partial_key(name);
+        if !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let names: Vec<String> = try!(try!(self.repository.list(&key))
+                                          .collect());
+        let mut chunks = vec![];
+        for chunk in names {
+            let start = match u64::from_str_radix(&chunk, 16) {
+                Ok(start) if chunk.len() == 16 => start,
+                _ => { continue; }
+            };
+            key.push(chunk);
+            let size = try!(self.repository.usage(&key));
+            key.pop();
+            chunks.push((start, start + size));
+        }
+        chunks.sort();
+        Ok(chunks)
+    }
+
+    fn blob_key(&self, kind: &str, hash: &str) -> Vec<String> {
+        let mut key = self.key.clone();
+        key.push(kind.to_owned());
+        key.push(hash.chars().take(2).collect());
+        key.push(hash.to_owned());
+        key
+    }
+
+    /// The key of the blob content, or `NotFound` if there's no blob.
+    fn data_key(&self, hash: &str) -> Result<Vec<String>> {
+        if !self.contains(hash) {
+            return Err(Error::NotFound(hash.to_owned()));
+        }
+        Ok(self.blob_key("data", hash))
+    }
+
+    fn partial_key(&self, name: &str) -> Vec<String> {
+        let mut key = self.key.clone();
+        key.push("partial".to_owned());
+        key.push(sha1_hex(name.as_bytes()));
+        key
+    }
+
+    fn chunk_key(&self, name: &str, start: u64) -> Vec<String> {
+        let mut key = self.partial_key(name);
+        key.push(format!("{:016x}", start));
+        key
+    }
+
+    fn write_refs(&mut self, hash: &str, refs: &[String]) -> Result<()> {
+        let key = self.blob_key("refs", hash);
+        if refs.is_empty() {
+            if self.repository.exists(&key) {
+                try!(self.repository.delete(&key));
+            }
+            return Ok(());
+        }
+        let lines: Vec<String> = refs.iter().map(|r| format!("{}\n", r))
+                                     .collect();
+        try!(write_atomically(&mut self.repository, &key,
+                              lines.iter().map(|l| l.as_bytes())));
+        Ok(())
+    }
+}
+
+/// Whether the string is a SHA-1 hexdigest.
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 40 &&
+        hash.chars().all(|c| c.is_ascii_hexdigit() && !c.is_uppercase())
+}
+
+fn read_all<T: Read>(mut reader: T) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    try!(reader.read_to_end(&mut buf));
+    Ok(buf)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{BlobStore, Error, entry_referrer};
+
+    use digest::sha1_hex;
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_blob_store() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut blobs = BlobStore::new(repo, &["blobs"]);
+        let hash = unwrap!(blobs.put(b"Earth Reader"));
+        assert_eq!(hash, sha1_hex(b"Earth Reader"));
+        assert_eq!(unwrap!(blobs.put(b"Earth Reader")), hash);
+        assert!(blobs.contains(&hash));
+        assert_eq!(unwrap!(blobs.size(&hash)), 12);
+        assert_eq!(unwrap!(blobs.read(&hash)), b"Earth Reader");
+        assert_eq!(unwrap!(blobs.read_range(&hash, 6, None)), b"Reader");
+        assert_eq!(unwrap!(blobs.read_range(&hash, 0, Some(5))), b"Earth");
+        assert_eq!(unwrap!(blobs.read_range(&hash, 20, Some(5))), b"");
+        assert_err!(blobs.read(&sha1_hex(b"")), Error::NotFound(_) => { });
+        assert_err!(blobs.read("../../etc"), Error::NotFound(_) => { });
+        let other = unwrap!(blobs.put(b"other"));
+        assert_eq!(unwrap!(blobs.hashes()).len(), 2);
+
+        let referrer = entry_referrer("feed", "urn:entry:1");
+        assert!(unwrap!(blobs.add_ref(&hash, &referrer)));
+        assert!(!unwrap!(blobs.add_ref(&hash, &referrer)));
+        assert!(unwrap!(blobs.add_ref(&hash, "snapshot:1")));
+        assert_eq!(unwrap!(blobs.refs(&hash)), ["entry:feed:urn:entry:1",
+                                                "snapshot:1"]);
+        assert_err!(blobs.add_ref(&hash, "a\nb"),
+                    Error::InvalidReferrer(_) => { });
+        assert_err!(blobs.add_ref(&sha1_hex(b""), "a"),
+                    Error::NotFound(_) => { });
+        assert_eq!(unwrap!(blobs.collect_garbage()), [&other[..]]);
+        assert!(!blobs.contains(&other));
+        assert!(unwrap!(blobs.remove_ref(&hash, &referrer)));
+        assert!(!unwrap!(blobs.remove_ref(&hash, &referrer)));
+        assert!(unwrap!(blobs.collect_garbage()).is_empty());
+        assert!(unwrap!(blobs.remove_ref(&hash, "snapshot:1")));
+        assert_eq!(unwrap!(blobs.collect_garbage()), [hash]);
+        assert!(unwrap!(blobs.hashes()).is_empty());
+    }
+
+    #[test]
+    fn test_blob_store_partial() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut blobs = BlobStore::new(repo, &["blobs"]);
+        let url = "http://earthreader.org/episode.mp3";
+        assert_eq!(unwrap!(blobs.partial_len(url)), 0);
+        assert_eq!(unwrap!(blobs.append_partial(url, b"Earth")), 5);
+        assert_eq!(unwrap!(blobs.append_partial(url, b" Reader")), 12);
+        assert_eq!(unwrap!(blobs.partial_len(url)), 12);
+        assert_eq!(unwrap!(blobs.read_partial_range(url, 6, Some(3))),
+                   b"Rea");
+        assert_eq!(unwrap!(blobs.read_partial_range(url, 3, Some(5))),
+                   b"th Re");
+        assert_eq!(unwrap!(blobs.read_partial_range(url, 3, None)),
+                   b"th Reader");
+        assert_eq!(unwrap!(blobs.append_partial(url, b"")), 12);
+        let hash = unwrap!(blobs.finish_partial(url));
+        assert_eq!(hash, sha1_hex(b"Earth Reader"));
+        assert_eq!(unwrap!(blobs.partial_len(url)), 0);
+        assert_err!(blobs.finish_partial(url), Error::NotFound(_) => { });
+        unwrap!(blobs.append_partial(url, b"Earth"));
+        unwrap!(blobs.discard_partial(url));
+        assert_eq!(unwrap!(blobs.partial_len(url)), 0);
+        assert!(unwrap!(blobs.collect_garbage()).contains(&hash));
+    }
+
+    #[test]
+    fn test_blob_store_append_partial() {
+        use repository::MeteredRepository;
+
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut blobs = BlobStore::new(MeteredRepository::new(repo),
+                                       &["blobs"]);
+        let url = "http://earthreader.org/episode.mp3";
+        let chunk = [0u8; 1024];
+        for i in 0..100 {
+            assert_eq!(unwrap!(blobs.append_partial(url, &chunk)),
+                       (i + 1) * 1024);
+        }
+        // appends never read nor rewrite what was written before
+        let metrics = blobs.repository.metrics();
+        assert_eq!(metrics.bytes_read, 0);
+        assert_eq!(metrics.bytes_written, 100 * 1024);
+        let hash = unwrap!(blobs.finish_partial(url));
+        assert_eq!(unwrap!(blobs.size(&hash)), 100 * 1024);
+    }
+}
@@ -53,10 +53,10 @@ impl Codec<DateTime<FixedOffset>> for RFC3339 {
     fn encode(&self, value: &DateTime<FixedOffset>, w: &mut io::Write) -> SchemaResult<()> {
         let dt = value.format("%Y-%m-%dT%H:%M:%S");
         try_encode!(write!(w, "{}", dt));
-        let nsec = value.nanosecond();
-        if nsec != 0 {
-            let nsec = format!("{:06}", nsec);
-            try_encode!(write!(w, ".{}", nsec.trim_right_matches('0')));
+        let usec = value.nanosecond() / 1000;
+        if usec != 0 {
+            let usec = format!("{:06}", usec);
+            try_encode!(write!(w, ".{}", usec.trim_end_matches('0')));
         }
         let off_d = value.offset().local_minus_utc();
         if off_d.is_zero() {
@@ -96,6 +96,7 @@ impl Codec<DateTime<FixedOffset>> for RFC3339 {
             FixedOffset::east(0)  // UTC
         };
         let mut microsecond = caps.name("microsecond").unwrap_or("").to_string();
+        microsecond.truncate(6);
         for _ in 0..(6 - microsecond.len()) {
             microsecond.push('0');
         }
@@ -119,6 +120,83 @@ impl Codec<DateTime<FixedOffset>> for RFC3339 {
     }
 }
 
+/// Codec for [RFC 822][] date time, which is used by RSS 2.0.  It decodes
+/// some common variants in the wild as well e.g. two-digit years, missing
+/// weekday or seconds, and North American time zone names.
+///
+/// [RFC 822]: https://tools.ietf.org/html/rfc822#section-5
+#[allow(missing_copy_implementations)]
+pub struct RFC822;
+
+static MONTHS: [&'static str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun",
+    "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+impl Codec<DateTime<FixedOffset>> for RFC822 {
+    fn encode(&self, value: &DateTime<FixedOffset>, w: &mut io::Write) -> SchemaResult<()> {
+        try_encode!(write!(w, "{}", value.format("%a, %d %b %Y %H:%M:%S %z")));
+        Ok(())
+    }
+
+    fn decode(&self, r: &str) -> SchemaResult<DateTime<FixedOffset>> {
+        let pattern = Regex::new(concat!(
+            r#"(?i)^\s*(?:[a-z]{3},?\s*)?"#,
+            r#"(?P<day>\d{1,2})\s+(?P<month>[a-z]{3})\s+(?P<year>\d{4}|\d{2})"#,
+            r#"\s+(?P<hour>\d{1,2}):(?P<minute>\d{2})(?::(?P<second>\d{2}))?"#,
+            r#"\s*(?P<tz>[a-z]{1,3}|[+-]\d{4})?\s*$"#,
+        )).unwrap();
+        let caps = try_opt!(pattern.captures(r),
+                            "invalid RFC 822 date time string",
+                            r.to_owned());
+        let month = caps.name("month").unwrap().to_lowercase();
+        let month = try_opt!(MONTHS.iter().position(|&m| m == month),
+                             "invalid value for month", month) as u32 + 1;
+        let year: i32 = parse_field!(caps, "year");
+        let year = match caps.name("year").unwrap().len() {
+            2 if year < 50 => year + 2000,
+            2 => year + 1900,
+            _ => year,
+        };
+        let second = match caps.name("second") {
+            Some(_) => parse_field!(caps, "second"),
+            None => 0,
+        };
+        let tz = caps.name("tz").unwrap_or("").to_uppercase();
+        let offset_hours = match &tz[..] {
+            "" | "UT" | "GMT" | "Z" => 0,
+            "EDT" => -4,
+            "EST" | "CDT" => -5,
+            "CST" | "MDT" => -6,
+            "MST" | "PDT" => -7,
+            "PST" => -8,
+            _ if tz.len() == 5 => {
+                let sign = if tz.starts_with('-') { -1 } else { 1 };
+                let hh: i32 = FromStr::from_str(&tz[1..3]).unwrap();
+                let mm: i32 = FromStr::from_str(&tz[3..5]).unwrap();
+                return self.build(&caps, year, month, second,
+                                  sign * (hh * 60 + mm) * 60);
+            }
+            _ => 0,  // military zones are unreliable, RFC 1123 (5.2.14)
+        };
+        self.build(&caps, year, month, second, offset_hours * 60 * 60)
+    }
+}
+
+impl RFC822 {
+    fn build(&self, caps: &::regex::Captures, year: i32, month: u32,
+             second: u32, offset: i32) -> SchemaResult<DateTime<FixedOffset>>
+    {
+        let date = try_opt!(FixedOffset::east(offset)
+                                .ymd_opt(year, month, parse_field!(caps, "day"))
+                                .single(),
+                            "invalid date");
+        Ok(try_opt!(date.and_hms_opt(parse_field!(caps, "hour"),
+                                     parse_field!(caps, "minute"), second),
+                    "invalid time"))
+    }
+}
+
 pub struct Boolean {
     true_texts: Vec<Cow<'static, str>>,
     false_texts: Vec<Cow<'static, str>>,
@@ -175,9 +253,107 @@ impl Codec<bool> for Boolean {
     }
 }
 
+/// Functions for `#[serde(with = "...")]` attributes, which (de)serialize
+/// date times as RFC 3339 strings through the `RFC3339` codec, as they are
+/// in the stored documents.
+#[cfg(feature = "serde")]
+pub mod serde {
+    use std::str::from_utf8;
+
+    use chrono::{DateTime, FixedOffset};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    use schema::Codec;
+    use super::RFC3339;
+
+    fn encode<S>(value: &DateTime<FixedOffset>, serializer: S)
+                 -> Result<S::Ok, S::Error> where S: Serializer
+    {
+        let mut buf = Vec::new();
+        match RFC3339.encode(value, &mut buf) {
+            Ok(()) => serializer.serialize_str(from_utf8(&buf).unwrap()),
+            Err(e) => Err(::serde::ser::Error::custom(e)),
+        }
+    }
+
+    /// `DateTime<FixedOffset>` as an RFC 3339 string.
+    pub mod rfc3339 {
+        use chrono::{DateTime, FixedOffset};
+        use serde::{Deserializer, Serializer};
+
+        pub fn serialize<S>(value: &DateTime<FixedOffset>, serializer: S)
+                            -> Result<S::Ok, S::Error> where S: Serializer
+        {
+            super::encode(value, serializer)
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D)
+                                   -> Result<DateTime<FixedOffset>, D::Error>
+            where D: Deserializer<'de>
+        {
+            super::decode(deserializer)
+        }
+    }
+
+    /// `Option<DateTime<FixedOffset>>` as an RFC 3339 string or null.
+    pub mod rfc3339_option {
+        use chrono::{DateTime, FixedOffset};
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S>(value: &Option<DateTime<FixedOffset>>,
+                            serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            match *value {
+                Some(ref dt) => {
+                    serializer.serialize_some(&super::Wrapper(*dt))
+                }
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D)
+                                   -> Result<Option<DateTime<FixedOffset>>,
+                                             D::Error>
+            where D: Deserializer<'de>
+        {
+            let wrapped: Option<super::Wrapper> =
+                try!(Deserialize::deserialize(deserializer));
+            Ok(wrapped.map(|w| w.0))
+        }
+    }
+
+    fn decode<'de, D>(deserializer: D)
+                      -> Result<DateTime<FixedOffset>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s: String = try!(Deserialize::deserialize(deserializer));
+        RFC3339.decode(&s).map_err(D::Error::custom)
+    }
+
+    struct Wrapper(DateTime<FixedOffset>);
+
+    impl ::serde::Serialize for Wrapper {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            encode(&self.0, serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Wrapper {
+        fn deserialize<D>(deserializer: D) -> Result<Wrapper, D::Error>
+            where D: Deserializer<'de>
+        {
+            decode(deserializer).map(Wrapper)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::RFC3339;
+    use super::{RFC3339, RFC822};
     use std::str;
     use chrono::{DateTime, FixedOffset};
     use chrono::{TimeZone};
@@ -240,6 +416,61 @@ def test_rfc3339_with_white_spaces():
 
     assert codec.decode(rfc_string) == rfc_datetime
 */
+    #[test]
+    fn test_rfc3339_nanosecond() {
+        let dt = FixedOffset::east(0).ymd(2003, 12, 13)
+                                     .and_hms_nano(18, 30, 2, 123456789);
+        assert_eq!(to_string(RFC3339, dt), "2003-12-13T18:30:02.123456Z");
+        assert_eq!(RFC3339.decode("2003-12-13T18:30:02.123456789Z").unwrap(),
+                   FixedOffset::east(0).ymd(2003, 12, 13)
+                                       .and_hms_micro(18, 30, 2, 123456));
+    }
+
+    #[test]
+    fn test_rfc822() {
+        let dt = FixedOffset::east(0).ymd(2002, 9, 7).and_hms(0, 0, 1);
+        assert_eq!(to_string(RFC822, dt), "Sat, 07 Sep 2002 00:00:01 +0000");
+        for &(rfc822_str, ref dt) in [
+            ("Sat, 07 Sep 2002 00:00:01 GMT", dt),
+            ("07 Sep 2002 00:00:01 +0000", dt),
+            ("Sat,07 sep 02 00:00:01", dt),
+            ("Fri, 06 Sep 2002 19:00:01 EST", dt),
+            ("Sat, 7 Sep 2002 09:30 +0930",
+             FixedOffset::east(9 * 60 * 60 + 30 * 60)
+                 .ymd(2002, 9, 7).and_hms(9, 30, 0)),
+        ].iter() {
+            assert_eq!(RFC822.decode(rfc822_str).unwrap(), *dt);
+        }
+        assert!(RFC822.decode("2002-09-07T00:00:01Z").is_err());
+        assert!(RFC822.decode("Sat, 31 Feb 2002 00:00:01 GMT").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rfc3339() {
+        use serde_json;
+
+        #[derive(Serialize, Deserialize, PartialEq, Debug)]
+        struct Dates {
+            #[serde(with = "::codecs::serde::rfc3339")]
+            at: DateTime<FixedOffset>,
+            #[serde(with = "::codecs::serde::rfc3339_option")]
+            maybe: Option<DateTime<FixedOffset>>,
+        }
+        for &(rfc3339_str, ref dt) in sample_data().iter() {
+            let dates = Dates { at: *dt, maybe: Some(*dt) };
+            let json = serde_json::to_string(&dates).unwrap();
+            assert_eq!(json, format!(r#"{{"at":"{0}","maybe":"{0}"}}"#,
+                                     rfc3339_str));
+            assert_eq!(serde_json::from_str::<Dates>(&json).unwrap(), dates);
+        }
+        let json = r#"{"at":"2005-07-31T12:29:29Z","maybe":null}"#;
+        let dates: Dates = serde_json::from_str(json).unwrap();
+        assert_eq!(dates.maybe, None);
+        assert!(serde_json::from_str::<Dates>(
+            r#"{"at":"Sat, 07 Sep 2002 00:00:01 GMT","maybe":null}"#).is_err());
+    }
+
     #[test]
     fn test_rfc3339_with_white_spaces() {
         let rfc_str = r#"
@@ -0,0 +1,504 @@
+//! Authenticating to servers of feeds which require credentials e.g. paid
+//! or corporate feeds.
+//!
+//! How a subscription authenticates is configured in its settings (see
+//! `SubscriptionSettings::auth()`), which are synchronized between devices,
+//! while the password or the token is kept in a `SecretStore` under the name
+//! `secret_name()` gives.
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+use digest::{md5_hex, sha1_hex};
+use secrets::{self, SecretStore};
+use subscribe::SubscriptionSettings;
+use super::{Backend, Request, Response};
+
+/// How the crawler authenticates to the server.  The secret, i.e. the
+/// password, the token, or the header value, isn't here but in
+/// `Credentials`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Auth {
+    /// HTTP Basic authentication.  It's sent with every request.
+    Basic { username: String },
+
+    /// HTTP Digest authentication.  It's answered to the challenge of the
+    /// server.  Only MD5 and MD5-sess algorithms are supported.
+    Digest { username: String },
+
+    /// The bearer token e.g. OAuth 2.0 access token.
+    Bearer,
+
+    /// The custom request header e.g. `X-API-Key`.
+    Header { name: String },
+}
+
+/// `Auth` along with its secret.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub auth: Auth,
+    pub secret: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+         .field("auth", &self.auth)
+         .field("secret", &"...")
+         .finish()
+    }
+}
+
+/// The name of the secret of the feed in the `SecretStore`.
+pub fn secret_name(feed_id: &str) -> String {
+    format!("crawler.auth:{}", feed_id)
+}
+
+impl Request {
+    /// Authenticate the request with the credentials.
+    pub fn credentials(mut self, credentials: Credentials) -> Request {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Authenticate the request as the subscription `settings` configure,
+    /// with the secret of the feed in the store.  The request is left as
+    /// it is if no authentication is configured, or the secret is missing.
+    pub fn authenticate<S>(self, feed_id: &str,
+                           settings: &SubscriptionSettings, store: &S)
+                           -> secrets::Result<Request>
+        where S: SecretStore + ?Sized
+    {
+        let auth = match settings.auth() {
+            Some(auth) => auth,
+            None => { return Ok(self); }
+        };
+        Ok(match try!(store.get(&secret_name(feed_id))) {
+            Some(secret) => {
+                self.credentials(Credentials { auth: auth, secret: secret })
+            }
+            None => self,
+        })
+    }
+
+    /// The headers to send to the `url` the request is redirected to.
+    /// Authentication headers, i.e. `Authorization` and the header of
+    /// `Auth::Header`, are dropped if the redirect changes the scheme, the
+    /// host or the port, so that credentials don't leak to other servers.
+    /// Backends should send these rather than `headers` when they follow
+    /// redirects; see `Backend`.
+    pub fn redirect_headers(&self, url: &str) -> Vec<(String, String)> {
+        if same_origin(&self.url, url) {
+            return self.headers.clone();
+        }
+        let custom = match self.credentials {
+            Some(Credentials { auth: Auth::Header { ref name }, .. }) => {
+                Some(&name[..])
+            }
+            _ => None,
+        };
+        self.headers.iter().filter(|&(n, _)| {
+            !n.eq_ignore_ascii_case("Authorization") &&
+                !custom.is_some_and(|c| n.eq_ignore_ascii_case(c))
+        }).cloned().collect()
+    }
+}
+
+/// Whether the URLs have the same scheme, host and port.  Unparsable URLs
+/// are regarded as of no origin.
+fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => {
+            a.scheme() == b.scheme() && a.host_str() == b.host_str() &&
+                a.port_or_known_default() == b.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+/// Fetch the document through the backend, authenticating with the
+/// credentials of the request.  Use this rather than `Backend::fetch()` for
+/// every request on behalf of a subscription, e.g. autodiscovery as well as
+/// crawling, so that they're authenticated alike.
+pub fn fetch<B: Backend + ?Sized>(backend: &B, request: &Request)
+                                  -> io::Result<Response>
+{
+    let credentials = match request.credentials {
+        Some(ref credentials) => credentials,
+        None => { return backend.fetch(request); }
+    };
+    let (name, value) = match credentials.auth {
+        Auth::Basic { ref username } => {
+            let pair = format!("{}:{}", username, credentials.secret);
+            ("Authorization".to_owned(),
+             format!("Basic {}", encode_base64(pair.as_bytes())))
+        }
+        Auth::Bearer => {
+            ("Authorization".to_owned(),
+             format!("Bearer {}", credentials.secret))
+        }
+        Auth::Header { ref name } => {
+            (name.clone(), credentials.secret.clone())
+        }
+        Auth::Digest { ref username } => {
+            let response = try!(backend.fetch(request));
+            if response.status != 401 {
+                return Ok(response);
+            }
+            let challenge = response.headers.iter().filter(|&(n, _)| {
+                n.eq_ignore_ascii_case("WWW-Authenticate")
+            }).filter_map(|(_, v)| parse_digest_challenge(v)).next();
+            // the challenge of another server redirected to isn't
+            // answered, since the credentials aren't for it
+            if !same_origin(&request.url, &response.url) {
+                return Ok(response);
+            }
+            let value = challenge.and_then(|c| {
+                digest_authorization(username, &credentials.secret,
+                                     request.method.as_str(),
+                                     &request_uri(&request.url), &c,
+                                     &new_cnonce())
+            });
+            match value {
+                Some(value) => ("Authorization".to_owned(), value),
+                None => { return Ok(response); }
+            }
+        }
+    };
+    let mut request = request.clone();
+    request.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+    backend.fetch(&request.header(name, value))
+}
+
+static BASE64_CHARS: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16 |
+                (*chunk.get(1).unwrap_or(&0) as u32) << 8 |
+                *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (n >> (18 - i * 6)) & 0x3f;
+                result.push(BASE64_CHARS[index as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Parameters of the `WWW-Authenticate: Digest` challenge.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct DigestChallenge {
+    params: Vec<(String, String)>,
+}
+
+impl DigestChallenge {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|&(n, _)| n.eq_ignore_ascii_case(name))
+                          .map(|(_, v)| &v[..])
+    }
+}
+
+fn parse_digest_challenge(header: &str) -> Option<DigestChallenge> {
+    let header = header.trim();
+    if header.len() < 7 || !header[..7].eq_ignore_ascii_case("digest ") {
+        return None;
+    }
+    let mut params = vec![];
+    let mut rest = header[7..].trim_start();
+    while !rest.is_empty() {
+        let eq = match rest.find('=') {
+            Some(i) => i,
+            None => { break; }
+        };
+        let name = rest[..eq].trim().to_owned();
+        rest = rest[eq + 1..].trim_start();
+        let value;
+        if rest.starts_with('"') {
+            let mut buf = String::new();
+            let mut chars = rest[1..].char_indices();
+            let mut end = rest.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, c)) = chars.next() { buf.push(c); }
+                    }
+                    '"' => { end = i + 2; break; }
+                    c => { buf.push(c); }
+                }
+            }
+            value = buf;
+            rest = &rest[end..];
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+        }
+        params.push((name, value));
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    Some(DigestChallenge { params: params })
+}
+
+/// The path and the query of the URL, which Digest authentication signs.
+fn request_uri(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        },
+        Err(_) => url.to_owned(),
+    }
+}
+
+/// The number of client nonces made so far.
+static CNONCE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn new_cnonce() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+                               .unwrap_or_default();
+    let count = CNONCE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let seed = format!("{}.{}:{}", now.as_secs(), now.subsec_nanos(), count);
+    sha1_hex(seed.as_bytes())[..16].to_owned()
+}
+
+/// The `Authorization` header value answering the challenge, as
+/// [RFC 2617][] describes.  It returns `None` if the challenge requires
+/// what isn't supported.
+///
+/// [RFC 2617]: https://tools.ietf.org/html/rfc2617#section-3.2.2
+fn digest_authorization(username: &str, password: &str, method: &str,
+                        uri: &str, challenge: &DigestChallenge,
+                        cnonce: &str)
+                        -> Option<String>
+{
+    let realm = challenge.get("realm").unwrap_or("");
+    let nonce = match challenge.get("nonce") {
+        Some(nonce) => nonce,
+        None => { return None; }
+    };
+    let algorithm = challenge.get("algorithm");
+    let mut ha1 = md5_hex(format!("{}:{}:{}", username, realm, password)
+                              .as_bytes());
+    match algorithm {
+        None => { }
+        Some(a) if a.eq_ignore_ascii_case("MD5") => { }
+        Some(a) if a.eq_ignore_ascii_case("MD5-sess") => {
+            ha1 = md5_hex(format!("{}:{}:{}", ha1, nonce, cnonce).as_bytes());
+        }
+        Some(_) => { return None; }
+    }
+    let ha2 = md5_hex(format!("{}:{}", method, uri).as_bytes());
+    let qop = match challenge.get("qop") {
+        Some(qop) => {
+            if !qop.split(',').any(|q| q.trim() == "auth") {
+                return None;
+            }
+            Some("auth")
+        }
+        None => None,
+    };
+    let response = match qop {
+        Some(qop) => md5_hex(format!("{}:{}:00000001:{}:{}:{}", ha1, nonce,
+                                     cnonce, qop, ha2).as_bytes()),
+        None => md5_hex(format!("{}:{}:{}", ha1, nonce, ha2).as_bytes()),
+    };
+    let mut value = format!(r#"Digest username="{}", realm="{}""#,
+                            quote(username), quote(realm));
+    value.push_str(&format!(r#", nonce="{}", uri="{}", response="{}""#,
+                            quote(nonce), quote(uri), response));
+    if let Some(algorithm) = algorithm {
+        value.push_str(&format!(", algorithm={}", algorithm));
+    }
+    if let Some(opaque) = challenge.get("opaque") {
+        value.push_str(&format!(r#", opaque="{}""#, quote(opaque)));
+    }
+    if let Some(qop) = qop {
+        value.push_str(&format!(r#", qop={}, nc=00000001, cnonce="{}""#,
+                                qop, cnonce));
+    }
+    Some(value)
+}
+
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Auth, Credentials, digest_authorization, encode_base64,
+                fetch, parse_digest_challenge, request_uri, secret_name};
+
+    use std::collections::HashMap;
+    use std::io;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crawler::{Backend, Request, Response};
+    use secrets::SecretStore;
+    use subscribe::SubscriptionSettings;
+
+    /// The backend which responds with 200 only if the request has the
+    /// header, and otherwise 401 with the challenge.
+    struct AuthBackend {
+        header: (String, Option<String>),
+        challenge: Option<String>,
+    }
+
+    impl Backend for AuthBackend {
+        fn fetch(&self, request: &Request) -> io::Result<Response> {
+            let (ref name, ref value) = self.header;
+            let authorized = request.headers.iter().any(|(n, v)| {
+                n.eq_ignore_ascii_case(name) &&
+                    value.as_ref().map(|value| v == value).unwrap_or(true)
+            });
+            let headers = match self.challenge {
+                Some(ref c) if !authorized => {
+                    vec![("WWW-Authenticate".to_owned(), c.clone())]
+                }
+                _ => vec![],
+            };
+            Ok(Response {
+                url: request.url.clone(),
+                status: if authorized { 200 } else { 401 },
+                headers: headers,
+                body: vec![],
+                redirects: vec![],
+            })
+        }
+    }
+
+    #[test]
+    fn test_encode_base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(encode_base64(b"Aladdin:open sesame"),
+                   "QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+    }
+
+    #[test]
+    fn test_digest_authorization() {
+        let challenge = parse_digest_challenge(concat!(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", "#,
+            r#"nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", "#,
+            r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+        )).unwrap();
+        assert_eq!(challenge.get("realm"), Some("testrealm@host.com"));
+        assert_eq!(challenge.get("QOP"), Some("auth,auth-int"));
+        assert_eq!(request_uri("http://www.nowhere.org/dir/index.html"),
+                   "/dir/index.html");
+        let value = digest_authorization("Mufasa", "Circle Of Life", "GET",
+                                         "/dir/index.html", &challenge,
+                                         "0a4f113b").unwrap();
+        assert!(value.contains(
+            r#"response="6629fae49393a05397450978507c4ef1""#
+        ));
+        assert!(value.contains(
+            r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+        ));
+        assert!(value.contains(r#"cnonce="0a4f113b""#));
+        // the method is signed as well, e.g. HEAD of liveness checks
+        let value = digest_authorization("Mufasa", "Circle Of Life", "HEAD",
+                                         "/dir/index.html", &challenge,
+                                         "0a4f113b").unwrap();
+        assert!(value.contains(
+            r#"response="06362368da76a7d08830e3b1cc38374d""#
+        ));
+        let sha = parse_digest_challenge(
+            r#"Digest realm="a", nonce="b", algorithm=SHA-256"#
+        ).unwrap();
+        assert_eq!(digest_authorization("a", "b", "GET", "/", &sha, "c"),
+                   None);
+        assert_eq!(parse_digest_challenge(r#"Basic realm="a""#), None);
+    }
+
+    #[test]
+    fn test_fetch_auth() {
+        let url = "http://earthreader.org/private?page=2";
+        let basic = AuthBackend {
+            header: ("Authorization".to_owned(),
+                     Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_owned())),
+            challenge: None,
+        };
+        assert_eq!(unwrap!(fetch(&basic, &Request::new(url))).status, 401);
+        let request = Request::new(url).credentials(Credentials {
+            auth: Auth::Basic { username: "Aladdin".to_owned() },
+            secret: "open sesame".to_owned(),
+        });
+        assert_eq!(unwrap!(fetch(&basic, &request)).status, 200);
+        assert!(!format!("{:?}", request).contains("open sesame"));
+
+        let header = AuthBackend {
+            header: ("X-API-Key".to_owned(), Some("key".to_owned())),
+            challenge: None,
+        };
+        let request = Request::new(url).credentials(Credentials {
+            auth: Auth::Header { name: "X-API-Key".to_owned() },
+            secret: "key".to_owned(),
+        });
+        assert_eq!(unwrap!(fetch(&header, &request)).status, 200);
+
+        let digest = AuthBackend {
+            header: ("Authorization".to_owned(), None),
+            challenge: Some(r#"Digest realm="a", nonce="b""#.to_owned()),
+        };
+        let request = Request::new(url).credentials(Credentials {
+            auth: Auth::Digest { username: "Mufasa".to_owned() },
+            secret: "Circle Of Life".to_owned(),
+        });
+        assert_eq!(unwrap!(fetch(&digest, &request)).status, 200);
+    }
+
+    #[test]
+    fn test_redirect_headers() {
+        let request = Request::new("https://earthreader.org/private")
+            .header("Accept", "application/atom+xml")
+            .header("X-API-Key", "key")
+            .header("Authorization", "Bearer token")
+            .credentials(Credentials {
+                auth: Auth::Header { name: "X-API-Key".to_owned() },
+                secret: "key".to_owned(),
+            });
+        assert_eq!(request.redirect_headers("https://earthreader.org/feed"),
+                   request.headers);
+        let accept = vec![("Accept".to_owned(),
+                           "application/atom+xml".to_owned())];
+        for url in &["http://earthreader.org/feed",
+                     "https://earthreader.org:8443/feed",
+                     "https://example.com/feed"] {
+            assert_eq!(request.redirect_headers(url), accept);
+        }
+    }
+
+    #[test]
+    fn test_request_authenticate() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 0, 0);
+        let mut settings: SubscriptionSettings = Default::default();
+        let mut store: HashMap<String, String> = HashMap::new();
+        let request = unwrap!(Request::new("http://earthreader.org/")
+                                  .authenticate("feed", &settings, &store));
+        assert_eq!(request.credentials, None);
+        settings.set_auth(Some(&Auth::Bearer), t);
+        assert_eq!(settings.auth(), Some(Auth::Bearer));
+        unwrap!(store.put(&secret_name("feed"), "token"));
+        let request = unwrap!(Request::new("http://earthreader.org/")
+                                  .authenticate("feed", &settings, &store));
+        assert_eq!(request.credentials,
+                   Some(Credentials { auth: Auth::Bearer,
+                                      secret: "token".to_owned() }));
+        let request = unwrap!(Request::new("http://earthreader.org/")
+                                  .authenticate("other", &settings, &store));
+        assert_eq!(request.credentials, None);
+    }
+}
@@ -0,0 +1,293 @@
+//! Finding the feed of a website, for subscribing by the URL people know,
+//! which is usually of the web page rather than of the feed.
+//!
+//! `discover()` tries, in order:
+//!
+//! 1. the URL itself, if it's a feed;
+//! 2. [autodiscovery][] `<link rel="alternate">` elements of the page;
+//! 3. `<meta>` hints some publishing tools leave, e.g.
+//!    `<meta name="feed" content="...">`;
+//! 4. paths feeds are commonly served at, e.g. `/feed`; see
+//!    `COMMON_PATHS`.
+//!
+//! [autodiscovery]: http://www.rssboard.org/rss-autodiscovery
+use regex::Regex;
+use url::Url;
+
+use parser::{ParseOptions, detect_format};
+use sanitizer::strip_tags;
+
+use super::{Backend, CrawlResult, Error, Request, Result, crawl,
+            fetch_with_retries, parse_response};
+
+/// Paths feeds are commonly served at, tried against the origin of the
+/// website when the page doesn't tell its feed.
+pub const COMMON_PATHS: &'static [&'static str] = &[
+    "/feed", "/rss", "/atom.xml", "/feed.xml", "/rss.xml", "/index.xml",
+];
+
+/// Media types of `<link>` elements which are feeds the parser understands.
+const FEED_MIMETYPES: &'static [&'static str] = &[
+    "application/atom+xml", "application/rss+xml",
+];
+
+/// Names of `<meta>` elements which hint feed URLs.
+const META_NAMES: &'static [&'static str] = &["feed", "rss", "atom"];
+
+/// Why the feed was chosen by `discover()`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Reason {
+    /// The URL is of the feed itself.
+    Direct,
+
+    /// The page links to the feed by `<link rel="alternate">`.
+    LinkElement,
+
+    /// The page hints the feed by `<meta>`.
+    MetaHint,
+
+    /// The feed is at one of `COMMON_PATHS`.
+    CommonPath,
+}
+
+/// The URL `discover()` may try, and why.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Candidate {
+    pub url: String,
+    pub reason: Reason,
+}
+
+/// The feed `discover()` found.
+#[derive(Debug)]
+pub struct Discovery {
+    pub reason: Reason,
+
+    /// The crawled feed.  Its `url` is the final URL of the feed.
+    pub result: CrawlResult,
+
+    /// Candidates tried before the chosen one, and why each failed.
+    pub rejected: Vec<(Candidate, Error)>,
+}
+
+/// Find the best feed for the URL of a website or a feed, and crawl it.
+/// It fails with `Error::NoFeed` if no candidate is a feed.
+pub fn discover<B: Backend + ?Sized>(backend: &B, url: &str,
+                                     options: &ParseOptions)
+                                     -> Result<Discovery>
+{
+    let response = try!(fetch_with_retries(backend, &Request::new(url)));
+    let successful = response.status >= 200 && response.status < 300;
+    if successful && detect_format(&response.body).is_some() {
+        return Ok(Discovery {
+            reason: Reason::Direct,
+            result: try!(parse_response(response, options)),
+            rejected: vec![],
+        });
+    }
+    let mut candidates = if successful {
+        let html = String::from_utf8_lossy(&response.body);
+        autodiscover(&html, &response.url)
+    } else {
+        vec![]
+    };
+    candidates.extend(common_paths(&response.url));
+    let mut rejected: Vec<(Candidate, Error)> = vec![];
+    for candidate in candidates {
+        if rejected.iter().any(|(c, _)| c.url == candidate.url) {
+            continue;
+        }
+        match crawl(backend, &Request::new(&candidate.url[..]), options) {
+            Ok(result) => {
+                return Ok(Discovery {
+                    reason: candidate.reason,
+                    result: result,
+                    rejected: rejected,
+                });
+            }
+            Err(err) => { rejected.push((candidate, err)); }
+        }
+    }
+    Err(Error::NoFeed)
+}
+
+/// Find feed URLs the HTML page tells, by `<link>` elements first and then
+/// `<meta>` hints, in the order they appear.  Relative URLs are resolved
+/// against the `base_url`.
+pub fn autodiscover(html: &str, base_url: &str) -> Vec<Candidate> {
+    let base = Url::parse(base_url).ok();
+    let resolve = |href: &str| -> Option<String> {
+        let href = strip_tags(href.trim());
+        match base {
+            Some(ref base) => base.join(&href).ok().map(|u| u.to_string()),
+            None => Url::parse(&href).ok().map(|u| u.to_string()),
+        }
+    };
+    let tag_pattern = Regex::new(r"(?i)<(link|meta)\s[^>]*>").unwrap();
+    let mut links = vec![];
+    let mut metas = vec![];
+    for tag in tag_pattern.captures_iter(html) {
+        let attrs = attributes(tag.at(0).unwrap());
+        let attr = |name: &str| -> Option<&str> {
+            attrs.iter().find(|&(n, _)| n == name)
+                        .map(|&(_, v)| v)
+        };
+        if tag.at(1).unwrap().eq_ignore_ascii_case("link") {
+            let rel = attr("rel").unwrap_or("").to_lowercase();
+            let type_ = attr("type").unwrap_or("").to_lowercase();
+            let alternate = rel.split_whitespace().any(|r| r == "alternate");
+            if alternate && FEED_MIMETYPES.contains(&type_.trim()) {
+                if let Some(url) = attr("href").and_then(&resolve) {
+                    links.push(Candidate { url: url,
+                                           reason: Reason::LinkElement });
+                }
+            }
+        } else {
+            let name = attr("name").or(attr("property")).unwrap_or("")
+                                   .to_lowercase();
+            if META_NAMES.contains(&name.trim()) {
+                if let Some(url) = attr("content").and_then(&resolve) {
+                    metas.push(Candidate { url: url,
+                                           reason: Reason::MetaHint });
+                }
+            }
+        }
+    }
+    links.extend(metas);
+    links
+}
+
+/// Find icon URLs the HTML page links by `<link rel="icon">`, including
+/// the legacy `shortcut icon` and `apple-touch-icon`, in the order they
+/// appear.  Relative URLs are resolved against the `base_url`.  See also
+/// `Stage::fetch_icon()`.
+pub fn autodiscover_icons(html: &str, base_url: &str) -> Vec<String> {
+    let base = Url::parse(base_url).ok();
+    let tag_pattern = Regex::new(r"(?i)<link\s[^>]*>").unwrap();
+    let mut icons = vec![];
+    for tag in tag_pattern.captures_iter(html) {
+        let attrs = attributes(tag.at(0).unwrap());
+        let attr = |name: &str| -> Option<&str> {
+            attrs.iter().find(|&(n, _)| n == name)
+                        .map(|&(_, v)| v)
+        };
+        let rel = attr("rel").unwrap_or("").to_lowercase();
+        if !rel.split_whitespace().any(|r| r == "icon" ||
+                                           r == "apple-touch-icon") {
+            continue;
+        }
+        let href = match attr("href") {
+            Some(href) => strip_tags(href.trim()),
+            None => { continue; }
+        };
+        let url = match base {
+            Some(ref base) => base.join(&href).ok(),
+            None => Url::parse(&href).ok(),
+        };
+        if let Some(url) = url {
+            icons.push(url.to_string());
+        }
+    }
+    icons
+}
+
+/// Attributes of the start tag as pairs of a lowercased name and a value.
+fn attributes(tag: &str) -> Vec<(String, &str)> {
+    let pattern = Regex::new(concat!(
+        r#"([A-Za-z_:][-A-Za-z0-9_:.]*)\s*=\s*"#,
+        r#"(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#
+    )).unwrap();
+    pattern.captures_iter(tag).map(|c| {
+        let value = c.at(2).or(c.at(3)).or(c.at(4)).unwrap_or("");
+        (c.at(1).unwrap().to_lowercase(), value)
+    }).collect()
+}
+
+fn common_paths(url: &str) -> Vec<Candidate> {
+    let base = match Url::parse(url) {
+        Ok(base) => base,
+        Err(_) => { return vec![]; }
+    };
+    COMMON_PATHS.iter().filter_map(|path| base.join(path).ok()).map(|u| {
+        Candidate { url: u.to_string(), reason: Reason::CommonPath }
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Candidate, Reason, autodiscover, autodiscover_icons,
+                discover};
+    use super::super::Error;
+    use super::super::test::{FX_RSS, MockBackend};
+
+    static FX_HTML: &'static str = r#"<html><head>
+        <meta name="feed" content="/hinted.xml">
+        <link rel="stylesheet" href="/style.css">
+        <link rel="alternate" type="text/html" href="/ko/">
+        <LINK REL="Alternate" TYPE="application/atom+xml"
+              HREF='atom.xml?a=1&amp;b=2'>
+        <link rel="alternate" type="application/rss+xml"
+              href="http://feeds.example.com/rss">
+    </head><body></body></html>"#;
+
+    #[test]
+    fn test_autodiscover() {
+        let candidates = autodiscover(FX_HTML, "http://example.com/blog/");
+        assert_eq!(candidates, [
+            Candidate { url: "http://example.com/blog/atom.xml?a=1&b=2"
+                                 .to_owned(),
+                        reason: Reason::LinkElement },
+            Candidate { url: "http://feeds.example.com/rss".to_owned(),
+                        reason: Reason::LinkElement },
+            Candidate { url: "http://example.com/hinted.xml".to_owned(),
+                        reason: Reason::MetaHint },
+        ]);
+        assert!(autodiscover("<p>Hello</p>", "http://example.com/")
+                    .is_empty());
+    }
+
+    #[test]
+    fn test_autodiscover_icons() {
+        let html = r#"<link rel="stylesheet" href="/style.css">
+            <LINK REL="Shortcut Icon" HREF="/favicon.png">
+            <link rel="apple-touch-icon" href='http://cdn.example.com/t.png'>
+            <link rel="icon" sizes="32x32">"#;
+        assert_eq!(autodiscover_icons(html, "http://example.com/blog/"), [
+            "http://example.com/favicon.png",
+            "http://cdn.example.com/t.png",
+        ]);
+        assert!(autodiscover_icons(FX_HTML, "http://example.com/")
+                    .is_empty());
+    }
+
+    #[test]
+    fn test_discover() {
+        let options = Default::default();
+        let backend = MockBackend::default()
+            .with("http://example.com/rss", 200, FX_RSS)
+            .with("http://example.com/", 200, b"<html></html>")
+            .with("http://example.com/feed", 404, b"")
+            .with("http://blog.example.com/", 200, concat!(
+                r#"<link rel="alternate" type="application/rss+xml""#,
+                r#" href="/missing"><meta name="rss" content="/rss.xml">"#
+            ).as_bytes())
+            .with("http://blog.example.com/rss.xml", 200, FX_RSS)
+            .with("http://empty.example.com/", 200, FX_HTML.as_bytes());
+        let found = unwrap!(discover(&backend, "http://example.com/rss",
+                                     &options));
+        assert_eq!(found.reason, Reason::Direct);
+        assert_eq!(found.result.url, "http://example.com/rss");
+        let found = unwrap!(discover(&backend, "http://example.com/",
+                                     &options));
+        assert_eq!(found.reason, Reason::CommonPath);
+        assert_eq!(found.result.url, "http://example.com/rss");
+        assert_eq!(found.rejected.len(), 1);
+        let found = unwrap!(discover(&backend, "http://blog.example.com/",
+                                     &options));
+        assert_eq!(found.reason, Reason::MetaHint);
+        assert_eq!(found.rejected[0].0.url, "http://blog.example.com/missing");
+        assert_err!(discover(&backend, "http://empty.example.com/",
+                             &options),
+                    Error::NoFeed => { });
+    }
+}
@@ -0,0 +1,186 @@
+//! Cheap checks whether dormant feeds have changed, before crawling them in
+//! full.
+//!
+//! Feeds which haven't been updated for months are still crawled at some
+//! interval, and most of the crawls download the whole feed only to find
+//! nothing new.  For such feeds (see `is_dormant()`), the scheduler can
+//! make a HEAD request by `check_liveness()` first, and crawl the feed only
+//! if its validators, i.e. `ETag` and `Last-Modified`, have changed.
+//!
+//! Servers often mishandle HEAD, e.g. respond with 405, 501, or even 404
+//! to the URL GET works for, or redirect HEAD somewhere the backend doesn't
+//! follow.  So if HEAD isn't responded with 2xx or 304, the check is made
+//! again by a GET request which reads only the first `PROBE_BODY_LIMIT`
+//! bytes of the body, and `Liveness::head_supported` tells the scheduler to
+//! skip HEAD for the feed next time.
+use chrono::{DateTime, FixedOffset};
+
+use stats::{Cadence, FeedStats};
+
+use super::{Backend, Error, Method, Request, Response, Result,
+            fetch_with_retries};
+
+/// The number of bytes of the body a GET request reads when it checks
+/// instead of HEAD.
+pub const PROBE_BODY_LIMIT: usize = 512;
+
+/// The result of `check_liveness()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Liveness {
+    /// Whether the feed has to be crawled, i.e. its validators have changed
+    /// or the server didn't tell them.
+    pub modified: bool,
+
+    /// The `ETag` of the feed, or the one checked against if the server
+    /// responded with 304.
+    pub etag: Option<String>,
+
+    /// The `Last-Modified` of the feed, or the one checked against if the
+    /// server responded with 304.
+    pub last_modified: Option<String>,
+
+    /// Whether the server handled HEAD.  If it's `false` the check was made
+    /// by GET, so the scheduler may request GET directly next time.
+    pub head_supported: bool,
+}
+
+/// Whether the feed is dormant enough to be checked by `check_liveness()`
+/// before it's crawled, i.e. its cadence is `Cadence::Dormant`.
+pub fn is_dormant(stats: &FeedStats, now: DateTime<FixedOffset>) -> bool {
+    stats.cadence(now) == Some(Cadence::Dormant)
+}
+
+/// Check whether the feed has changed since the validators of the last
+/// crawl were taken, without downloading it: by HEAD if `head` is true,
+/// falling back to GET of the first `PROBE_BODY_LIMIT` bytes if the server
+/// responds to it with other than 2xx or 304, e.g. a redirect the backend
+/// didn't follow, or by the GET only otherwise.  The request is made
+/// conditional on the validators; see `Request::conditional()`.
+///
+/// It fails with `Error::Status` if the GET fails as well.
+pub fn check_liveness<B: Backend + ?Sized>(backend: &B, request: &Request,
+                                           etag: Option<&str>,
+                                           last_modified: Option<&str>,
+                                           head: bool)
+                                           -> Result<Liveness>
+{
+    let request = request.clone().conditional(etag, last_modified);
+    if head {
+        let mut head_request = request.clone();
+        head_request.method = Method::Head;
+        head_request.body_limit = Some(0);
+        let response = try!(fetch_with_retries(backend, &head_request));
+        if response.status == 304 ||
+            response.status >= 200 && response.status < 300 {
+            return liveness(response, etag, last_modified, true);
+        }
+    }
+    let mut probe = request;
+    probe.method = Method::Get;
+    probe.body_limit = Some(PROBE_BODY_LIMIT);
+    let response = try!(fetch_with_retries(backend, &probe));
+    liveness(response, etag, last_modified, false)
+}
+
+fn liveness(response: Response, etag: Option<&str>,
+            last_modified: Option<&str>, head_supported: bool)
+            -> Result<Liveness>
+{
+    let owned = |v: Option<&str>| v.map(|v| v.to_owned());
+    if response.status == 304 {
+        return Ok(Liveness {
+            modified: false,
+            etag: owned(etag),
+            last_modified: owned(last_modified),
+            head_supported: head_supported,
+        });
+    } else if response.status < 200 || response.status >= 300 {
+        return Err(Error::Status(response.status));
+    }
+    let new_etag = response.header("ETag");
+    let new_last_modified = response.header("Last-Modified");
+    // servers which ignore conditional requests still tell validators
+    let unchanged = match (new_etag, new_last_modified) {
+        (Some(e), _) if etag.is_some() => Some(e) == etag,
+        (_, Some(l)) if last_modified.is_some() => Some(l) == last_modified,
+        _ => false,
+    };
+    Ok(Liveness {
+        modified: !unchanged,
+        etag: owned(new_etag),
+        last_modified: owned(new_last_modified),
+        head_supported: head_supported,
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{check_liveness, is_dormant};
+    use super::super::{Error, Request};
+    use super::super::test::{FX_RSS, MockBackend};
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use digest::sha1_hex;
+    use feed::{Entry, Feed, Text};
+    use stats::FeedStats;
+
+    #[test]
+    fn test_check_liveness() {
+        let url = "http://earthreader.org/rss";
+        let mut backend = MockBackend::default().with(url, 200, FX_RSS);
+        let request = Request::new(url);
+        let etag = format!("\"{}\"", sha1_hex(FX_RSS));
+
+        let result = unwrap!(check_liveness(&backend, &request, None, None,
+                                            true));
+        assert!(result.modified);
+        assert!(result.head_supported);
+        assert_eq!(result.etag, Some(etag.clone()));
+        let result = unwrap!(check_liveness(&backend, &request, Some(&etag),
+                                            None, true));
+        assert!(!result.modified);
+        assert_eq!(result.etag, Some(etag.clone()));
+        let result = unwrap!(check_liveness(&backend, &request,
+                                            Some("\"stale\""), None, true));
+        assert!(result.modified);
+
+        // HEAD is mishandled, so it falls back to GET
+        backend.head_status = Some(405);
+        let result = unwrap!(check_liveness(&backend, &request, Some(&etag),
+                                            None, true));
+        assert!(!result.modified);
+        assert!(!result.head_supported);
+        let result = unwrap!(check_liveness(&backend, &request, None, None,
+                                            false));
+        assert!(result.modified);
+        assert!(!result.head_supported);
+        // so is HEAD redirected to where the backend didn't follow
+        backend.head_status = Some(302);
+        let result = unwrap!(check_liveness(&backend, &request, Some(&etag),
+                                            None, true));
+        assert!(!result.modified);
+        assert!(!result.head_supported);
+
+        let backend = backend.with("http://earthreader.org/gone", 410, b"");
+        let gone = Request::new("http://earthreader.org/gone");
+        assert_err!(check_liveness(&backend, &gone, None, None, true),
+                    Error::Status(410) => { });
+    }
+
+    #[test]
+    fn test_is_dormant() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(0, 0, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        assert!(!is_dormant(&FeedStats::new(&feed), t));
+        for day in 0..3 {
+            feed.entries.push(Entry::new(format!("urn:{}", day),
+                                         Text::plain("Entry"),
+                                         t - Duration::days(day)));
+        }
+        let stats = FeedStats::new(&feed);
+        assert!(!is_dormant(&stats, t));
+        assert!(is_dormant(&stats, t + Duration::days(90)));
+    }
+}
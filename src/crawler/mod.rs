@@ -0,0 +1,711 @@
+//! Crawling feeds.
+//!
+//! The crate doesn't bundle any HTTP client; apps plug one into the crawler
+//! by implementing `Backend`, so that they can use whatever their platform
+//! provides e.g. `NSURLSession` on iOS.
+//!
+//! Feeds which require credentials are crawled by authenticating requests;
+//! see the `auth` module.  Feeds of websites are found by the `discovery`
+//! module.  Proxies, TLS, timeouts and retries are configured through the
+//! `transport` module.  Dormant feeds can be checked cheaply before they're
+//! crawled in full; see the `liveness` module.  Fetched documents can be
+//! verified by pinned fingerprints or signatures; see the `verify` module.
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{self, DateTime, FixedOffset};
+
+use feed::Feed;
+use job::{self, CancellationToken, JobHandle};
+use parser;
+use parser::{ParseDiagnostics, ParseOptions, Warning};
+use parser::base::DecodeError;
+use stats::FeedStats;
+use subscribe::SubscriptionSettings;
+
+pub use self::auth::{Auth, Credentials, fetch, secret_name};
+pub use self::discovery::{Discovery, Reason, discover};
+pub use self::liveness::{Liveness, check_liveness, is_dormant};
+pub use self::transport::{Proxy, RetryPolicy, TlsOptions, TlsVersion,
+                          TransportConfig, fetch_cancellable,
+                          fetch_with_retries};
+pub use self::verify::{Verifier, verify_response};
+
+pub mod auth;
+pub mod discovery;
+pub mod liveness;
+pub mod transport;
+pub mod verify;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The backend failed to fetch the document.
+    Io(io::Error),
+
+    /// The server responded with the HTTP status which isn't successful.
+    Status(u16),
+
+    /// The fetched document isn't a feed that can be parsed.
+    Decode(DecodeError),
+
+    /// No feed is found for the website; see `discover()`.
+    NoFeed,
+
+    /// The request header of the subscription settings is invalid; see
+    /// `Request::with_settings()`.
+    InvalidHeader(String),
+
+    /// The feed hasn't changed since the conditional request's validators
+    /// were taken, i.e. the server responded with 304; see
+    /// `Request::conditional()`.
+    NotModified,
+
+    /// The fetched document is larger than the limit in bytes; see
+    /// `feed::Content::fetch()`.
+    TooLarge(usize),
+
+    /// The fetched document isn't of the expected mimetype, but of the
+    /// one the `Content-Type` tells; see `feed::Content::fetch()`.
+    UnexpectedType(String),
+
+    /// The fetched document failed verification for the reason, e.g. its
+    /// fingerprint doesn't match the pinned one; see `verify_response()`.
+    Unverified(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.description()));
+        match *self {
+            Error::Status(status) => { try!(write!(f, ": {}", status)); }
+            Error::InvalidHeader(ref name) => {
+                try!(write!(f, ": {:?}", name));
+            }
+            Error::TooLarge(limit) => {
+                try!(write!(f, ": over {} bytes", limit));
+            }
+            Error::UnexpectedType(ref mimetype) => {
+                try!(write!(f, ": {}", mimetype));
+            }
+            Error::Unverified(ref reason) => {
+                try!(write!(f, ": {}", reason));
+            }
+            _ => { }
+        }
+        if let Some(cause) = self.cause() {
+            try!(write!(f, " caused by `{}`", cause));
+        }
+        Ok(())
+    }
+}
+
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "failed to fetch the feed",
+            Error::Status(_) => "unsuccessful HTTP status",
+            Error::Decode(_) => "failed to parse the feed",
+            Error::NoFeed => "no feed is found",
+            Error::InvalidHeader(_) => "invalid request header",
+            Error::NotModified => "the feed is not modified",
+            Error::TooLarge(_) => "the document is too large",
+            Error::UnexpectedType(_) => "unexpected mimetype",
+            Error::Unverified(_) => "the document failed verification",
+        }
+    }
+
+    fn cause(&self) -> Option<&ErrorTrait> {
+        match *self {
+            Error::Io(ref err) => Some(err as &ErrorTrait),
+            Error::Status(_) | Error::NoFeed | Error::InvalidHeader(_) |
+            Error::NotModified | Error::TooLarge(_) |
+            Error::UnexpectedType(_) | Error::Unverified(_) => None,
+            Error::Decode(ref err) => Some(err as &ErrorTrait),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Io(err) }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Error { Error::Decode(err) }
+}
+
+/// HTTP request methods the crawler uses.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub enum Method {
+    #[default]
+    Get,
+
+    /// Only for the headers of the response, e.g. `Last-Modified`; see
+    /// `check_liveness()`.
+    Head,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+        }
+    }
+}
+
+/// An HTTP request the crawler makes, which is GET unless `method` tells
+/// otherwise.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Request {
+    pub method: Method,
+
+    pub url: String,
+
+    /// Additional request headers as pairs of a name and a value.
+    pub headers: Vec<(String, String)>,
+
+    /// The credentials to authenticate with, if the server requires.
+    pub credentials: Option<Credentials>,
+
+    /// How long the backend waits for the response.  `None` means the
+    /// default timeout of `TransportConfig`.
+    pub timeout: Option<Duration>,
+
+    /// How the crawler retries the request if it fails.
+    pub retry: RetryPolicy,
+
+    /// The number of bytes of the response body the backend reads at
+    /// most, e.g. to check headers by a GET request where HEAD isn't
+    /// handled.  `None` means the whole body.
+    pub body_limit: Option<usize>,
+}
+
+impl Request {
+    pub fn new<T: Into<String>>(url: T) -> Request {
+        Request {
+            method: Method::Get,
+            url: url.into(),
+            headers: vec![],
+            credentials: None,
+            timeout: None,
+            retry: Default::default(),
+            body_limit: None,
+        }
+    }
+
+    /// Add the request header.
+    pub fn header<N, V>(mut self, name: N, value: V) -> Request
+        where N: Into<String>, V: Into<String>
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the header, replacing ones of the same name compared
+    /// case-insensitively.
+    pub fn set_header<N, V>(mut self, name: N, value: V) -> Request
+        where N: Into<String>, V: Into<String>
+    {
+        let name = name.into();
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+        self.header(name, value)
+    }
+
+    /// Apply the `User-Agent` and additional headers the subscription
+    /// `settings` override.  It fails with `Error::InvalidHeader` if any of
+    /// them isn't valid; see `is_valid_header()`.
+    pub fn with_settings(self, settings: &SubscriptionSettings)
+                         -> Result<Request> {
+        let mut request = self;
+        if let Some(user_agent) = settings.user_agent() {
+            if !is_valid_header("User-Agent", user_agent) {
+                return Err(Error::InvalidHeader("User-Agent".to_owned()));
+            }
+            request = request.set_header("User-Agent", user_agent);
+        }
+        for (name, value) in settings.request_headers() {
+            if !is_valid_header(name, value) {
+                return Err(Error::InvalidHeader(name.to_owned()));
+            }
+            request = request.set_header(name, value);
+        }
+        Ok(request)
+    }
+
+    /// Make the request conditional on the validators of the last response,
+    /// i.e. its `ETag` and `Last-Modified` headers, so that the server can
+    /// respond with 304 instead of the whole feed if it hasn't changed.
+    /// `crawl()` fails with `Error::NotModified` then.
+    pub fn conditional(self, etag: Option<&str>, last_modified: Option<&str>)
+                       -> Request {
+        let mut request = self;
+        if let Some(etag) = etag {
+            request = request.set_header("If-None-Match", etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.set_header("If-Modified-Since", last_modified);
+        }
+        request
+    }
+}
+
+/// Headers which describe the connection or the message framing rather
+/// than the request, so subscriptions can't override them.
+const RESERVED_HEADERS: &'static [&'static str] = &[
+    "Connection", "Content-Length", "Host", "Keep-Alive", "TE", "Trailer",
+    "Transfer-Encoding", "Upgrade",
+];
+
+/// Whether the request header can be sent on behalf of a subscription: the
+/// name is an HTTP token which isn't reserved, and the value doesn't
+/// contain any control characters e.g. newlines.
+pub fn is_valid_header(name: &str, value: &str) -> bool {
+    let is_tchar = |c: char| {
+        c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+    };
+    !name.is_empty() && name.chars().all(is_tchar) &&
+        !RESERVED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) &&
+        !value.chars().any(|c| c.is_control() && c != '\t')
+}
+
+/// A redirect the backend followed.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Redirect {
+    /// The HTTP status of the redirect, e.g. 301.
+    pub status: u16,
+
+    /// The URL redirected to, i.e. the `Location` resolved.
+    pub url: String,
+}
+
+impl Redirect {
+    pub fn new<T: Into<String>>(status: u16, url: T) -> Redirect {
+        Redirect { status: status, url: url.into() }
+    }
+
+    /// Whether the resource has moved for good, i.e. the status is 301 or
+    /// 308, so that the old URL should no longer be requested.
+    pub fn is_permanent(&self) -> bool {
+        self.status == 301 || self.status == 308
+    }
+}
+
+/// The HTTP response a `Backend` returns.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Response {
+    /// The final URL of the response, after following redirects.
+    pub url: String,
+
+    pub status: u16,
+
+    /// Response headers as pairs of a name and a value.
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+
+    /// Redirects followed from the requested URL to the final `url`, in
+    /// order.
+    pub redirects: Vec<Redirect>,
+}
+
+impl Response {
+    /// The first value of the header, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|&(n, _)| n.eq_ignore_ascii_case(name))
+                           .map(|(_, v)| &v[..])
+    }
+}
+
+/// HTTP client the crawler fetches documents through.  Implementations
+/// should follow redirects and report them in `Response::redirects`, with
+/// headers `Request::redirect_headers()` gives for each location, and
+/// return responses of any status rather than errors; errors are only for
+/// failures of the transport.  They should send the request by its
+/// `method`, and stop reading the body at its `body_limit`.
+pub trait Backend {
+    fn fetch(&self, request: &Request) -> io::Result<Response>;
+
+    /// Apply the configuration of connections to following requests.  It
+    /// should fail with `io::ErrorKind::Other` if the backend can't apply
+    /// any part of it, rather than ignore it.  Backends which can't be
+    /// configured at all accept only the default configuration.
+    fn configure(&mut self, config: &TransportConfig) -> io::Result<()> {
+        if *config == Default::default() {
+            Ok(())
+        } else {
+            Err(io::Error::other("the backend can't be configured"))
+        }
+    }
+}
+
+/// The result of crawling a feed.
+#[derive(Clone, Debug)]
+pub struct CrawlResult {
+    /// The final URL of the feed.
+    pub url: String,
+
+    pub feed: Feed,
+
+    /// Problems found while parsing the feed, which apps may log.
+    pub warnings: Vec<Warning>,
+
+    /// Redirects followed from the requested URL to the final `url`.
+    pub redirects: Vec<Redirect>,
+
+    /// The `ETag` header of the response, to make the next request
+    /// conditional on; see `Request::conditional()`.
+    pub etag: Option<String>,
+
+    /// The `Last-Modified` header of the response.
+    pub last_modified: Option<String>,
+
+    /// IDs of entries skipped as already known, which aren't in the
+    /// `feed`; see `ParseOptions::known_entries`.
+    pub skipped_entries: Vec<String>,
+}
+
+impl CrawlResult {
+    /// The URL the feed has permanently moved to, i.e. where the leading
+    /// permanent redirects end.  It's `None` if the first redirect isn't
+    /// permanent; a temporary redirect after permanent ones stops there,
+    /// since only the permanent ones should be followed for good.  See
+    /// also `Stage::apply_redirects()`.
+    pub fn permanent_url(&self) -> Option<&str> {
+        self.redirects.iter().take_while(|r| r.is_permanent()).last()
+                             .map(|r| &r.url[..])
+    }
+}
+
+/// Fetch the feed through the backend, and parse it.  The request is
+/// authenticated if it has credentials, and retried as it tells.
+pub fn crawl<B: Backend + ?Sized>(backend: &B, request: &Request,
+                                  options: &ParseOptions)
+                                  -> Result<CrawlResult>
+{
+    crawl_cancellable(backend, request, options, &CancellationToken::new())
+}
+
+/// `crawl()` which makes no more retries once the `cancel` token is
+/// cancelled.  See `fetch_cancellable()`.
+pub fn crawl_cancellable<B>(backend: &B, request: &Request,
+                            options: &ParseOptions,
+                            cancel: &CancellationToken)
+                            -> Result<CrawlResult>
+    where B: Backend + ?Sized
+{
+    let response = try!(fetch_cancellable(backend, request, cancel));
+    parse_response(response, options)
+}
+
+/// Parse the fetched response like `crawl()` does, e.g. after keeping the
+/// document as it was fetched.
+pub fn parse_response(response: Response, options: &ParseOptions)
+                      -> Result<CrawlResult>
+{
+    if response.status == 304 {
+        return Err(Error::NotModified);
+    } else if response.status < 200 || response.status >= 300 {
+        return Err(Error::Status(response.status));
+    }
+    let etag = response.header("ETag").map(|v| v.to_owned());
+    let last_modified = response.header("Last-Modified").map(|v| v.to_owned());
+    let diagnostics = ParseDiagnostics::new();
+    let feed = try!(parser::parse(&response.body, &response.url, options,
+                                  &diagnostics));
+    let skipped_entries = diagnostics.skipped_ids();
+    Ok(CrawlResult {
+        url: response.url,
+        feed: feed,
+        warnings: diagnostics.into_warnings(),
+        redirects: response.redirects,
+        etag: etag,
+        last_modified: last_modified,
+        skipped_entries: skipped_entries,
+    })
+}
+
+/// `crawl()` in a background job.  See also the `job` module.
+pub fn spawn<B>(backend: Arc<B>, request: Request, options: ParseOptions)
+                -> JobHandle<Result<CrawlResult>>
+    where B: Backend + Send + Sync + 'static
+{
+    job::spawn(move || crawl(&*backend, &request, &options))
+}
+
+/// Whether the feed should be crawled again at `now`, given when it was
+/// crawled last.  The app-wide `default_interval` is used unless the
+/// subscription `settings` override it.
+pub fn is_due(settings: &SubscriptionSettings,
+              last_crawled_at: Option<DateTime<FixedOffset>>,
+              now: DateTime<FixedOffset>, default_interval: Duration)
+              -> bool
+{
+    let last_crawled_at = match last_crawled_at {
+        Some(t) => t,
+        None => { return true; }
+    };
+    let interval = settings.crawl_interval().unwrap_or(default_interval);
+    last_crawled_at + chrono::Duration::seconds(interval.as_secs() as i64)
+        <= now
+}
+
+/// The interval to crawl the feed at by its cadence, or the app-wide
+/// `default_interval` if its cadence can't be told.  Pass it to `is_due()`
+/// as the default, so that subscription settings still override it.
+pub fn suggested_interval(stats: &FeedStats, now: DateTime<FixedOffset>,
+                          default_interval: Duration) -> Duration {
+    match stats.cadence(now) {
+        Some(cadence) => cadence.crawl_interval(),
+        None => default_interval,
+    }
+}
+
+
+#[cfg(test)]
+pub mod test {
+    use super::{Backend, Error, Method, Redirect, Request, Response, crawl,
+                is_due, is_valid_header, spawn, suggested_interval};
+
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use digest::sha1_hex;
+    use feed::{Entry, Feed, Text};
+    use stats::FeedStats;
+    use subscribe::SubscriptionSettings;
+
+    /// The backend which responds with documents in the map, following
+    /// redirects in the other map.  Successful responses have `ETag`s, and
+    /// requests conditional on them are responded with 304.  HEAD requests
+    /// are responded with `head_status` instead if it's set, like servers
+    /// which mishandle HEAD do.
+    #[derive(Default)]
+    pub struct MockBackend {
+        pub documents: HashMap<String, (u16, Vec<u8>)>,
+        pub redirects: HashMap<String, Redirect>,
+        pub head_status: Option<u16>,
+    }
+
+    impl MockBackend {
+        pub fn with<T: Into<String>>(mut self, url: T, status: u16,
+                                     body: &[u8]) -> MockBackend {
+            self.documents.insert(url.into(), (status, body.to_vec()));
+            self
+        }
+
+        pub fn redirect<T, U>(mut self, from: T, status: u16, to: U)
+                              -> MockBackend
+            where T: Into<String>, U: Into<String>
+        {
+            self.redirects.insert(from.into(), Redirect::new(status, to));
+            self
+        }
+    }
+
+    impl Backend for MockBackend {
+        fn fetch(&self, request: &Request) -> io::Result<Response> {
+            let mut url = request.url.clone();
+            let mut redirects = vec![];
+            while let Some(redirect) = self.redirects.get(&url) {
+                url = redirect.url.clone();
+                redirects.push(redirect.clone());
+            }
+            match self.documents.get(&url) {
+                Some(&(status, ref body)) => {
+                    let etag = format!("\"{}\"", sha1_hex(body));
+                    let not_modified = request.headers.iter().any(|h| {
+                        h.0.eq_ignore_ascii_case("If-None-Match") &&
+                            h.1 == etag
+                    });
+                    let head = request.method == Method::Head;
+                    let (status, headers, mut body) = match self.head_status {
+                        Some(s) if head => (s, vec![], vec![]),
+                        _ if status != 200 => (status, vec![], body.clone()),
+                        _ if not_modified => (304, vec![], vec![]),
+                        _ => {
+                            let headers = vec![("ETag".to_owned(), etag)];
+                            (200, headers, body.clone())
+                        }
+                    };
+                    if head {
+                        body.clear();
+                    } else if let Some(limit) = request.body_limit {
+                        body.truncate(limit);
+                    }
+                    Ok(Response {
+                        url: url,
+                        status: status,
+                        headers: headers,
+                        body: body,
+                        redirects: redirects,
+                    })
+                }
+                None => Err(io::Error::new(io::ErrorKind::NotFound,
+                                           "no such document")),
+            }
+        }
+    }
+
+    pub static FX_RSS: &'static [u8] = br#"<rss version="2.0"><channel>
+        <title>Earth Reader</title>
+        <item><title>A</title><guid>urn:earthreader:a</guid></item>
+    </channel></rss>"#;
+
+    fn fx_backend() -> MockBackend {
+        MockBackend::default()
+            .with("http://earthreader.org/rss", 200, FX_RSS)
+            .with("http://earthreader.org/gone", 410, b"")
+    }
+
+    #[test]
+    fn test_crawl() {
+        let backend = fx_backend();
+        let options = Default::default();
+        let result = unwrap!(crawl(&backend,
+                                   &Request::new("http://earthreader.org/rss"),
+                                   &options));
+        assert_eq!(result.feed.title, Text::plain("Earth Reader"));
+        assert_eq!(result.feed.entries.len(), 1);
+        assert!(result.warnings.is_empty());
+        assert_err!(crawl(&backend,
+                          &Request::new("http://earthreader.org/gone"),
+                          &options),
+                    Error::Status(410) => { });
+        assert_err!(crawl(&backend, &Request::new("http://example.com/"),
+                          &options),
+                    Error::Io(_) => { });
+    }
+
+    #[test]
+    fn test_crawl_redirects() {
+        let backend = fx_backend()
+            .redirect("http://earthreader.org/feed", 301,
+                      "http://earthreader.org/atom")
+            .redirect("http://earthreader.org/atom", 302,
+                      "http://earthreader.org/rss")
+            .redirect("http://earthreader.org/old", 308,
+                      "http://earthreader.org/feed");
+        let options = Default::default();
+        let result = unwrap!(crawl(&backend,
+                                   &Request::new("http://earthreader.org/rss"),
+                                   &options));
+        assert!(result.redirects.is_empty());
+        assert_eq!(result.permanent_url(), None);
+        let result = unwrap!(crawl(&backend,
+                                   &Request::new("http://earthreader.org/old"),
+                                   &options));
+        assert_eq!(result.url, "http://earthreader.org/rss");
+        assert_eq!(result.redirects, [
+            Redirect::new(308, "http://earthreader.org/feed"),
+            Redirect::new(301, "http://earthreader.org/atom"),
+            Redirect::new(302, "http://earthreader.org/rss"),
+        ]);
+        assert_eq!(result.permanent_url(),
+                   Some("http://earthreader.org/atom"));
+        let request = Request::new("http://earthreader.org/atom");
+        let result = unwrap!(crawl(&backend, &request, &options));
+        assert_eq!(result.permanent_url(), None);
+    }
+
+    #[test]
+    fn test_crawl_conditional() {
+        let backend = fx_backend();
+        let options = Default::default();
+        let url = "http://earthreader.org/rss";
+        let result = unwrap!(crawl(&backend, &Request::new(url), &options));
+        assert!(result.etag.is_some());
+        assert_eq!(result.last_modified, None);
+        let request = Request::new(url)
+            .conditional(result.etag.as_ref().map(|v| &v[..]), None);
+        assert_err!(crawl(&backend, &request, &options),
+                    Error::NotModified => { });
+        let request = Request::new(url).conditional(Some("\"stale\""), None);
+        assert!(crawl(&backend, &request, &options).is_ok());
+    }
+
+    #[test]
+    fn test_crawl_spawn() {
+        let backend = Arc::new(fx_backend());
+        let handle = spawn(backend, Request::new("http://earthreader.org/rss"),
+                           Default::default());
+        let result = unwrap!(handle.join());
+        assert_eq!(result.url, "http://earthreader.org/rss");
+    }
+
+    #[test]
+    fn test_response_header() {
+        let response = Response {
+            url: "http://earthreader.org/".to_owned(),
+            status: 200,
+            headers: vec![("Content-Type".to_owned(), "text/xml".to_owned())],
+            body: vec![],
+            redirects: vec![],
+        };
+        assert_eq!(response.header("content-type"), Some("text/xml"));
+        assert_eq!(response.header("ETag"), None);
+    }
+
+    #[test]
+    fn test_is_due() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 0, 0);
+        let day = Duration::from_secs(24 * 3600);
+        let mut settings: SubscriptionSettings = Default::default();
+        assert!(is_due(&settings, None, t, day));
+        let later = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(15, 0, 0);
+        assert!(!is_due(&settings, Some(t), later, day));
+        settings.set_crawl_interval(Some(Duration::from_secs(3600)), t);
+        assert!(is_due(&settings, Some(t), later, day));
+    }
+
+    #[test]
+    fn test_suggested_interval() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 0, 0);
+        let day = Duration::from_secs(24 * 3600);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        let stats = FeedStats::new(&feed);
+        assert_eq!(suggested_interval(&stats, t, day), day);
+        for i in 0..3 {
+            let posted_at = t - ::chrono::Duration::hours(i);
+            feed.entries.push(Entry::new(format!("urn:{}", i),
+                                         Text::plain("Entry"), posted_at));
+        }
+        let stats = FeedStats::new(&feed);
+        assert_eq!(suggested_interval(&stats, t, day),
+                   Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_request_with_settings() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 0, 0);
+        let mut settings: SubscriptionSettings = Default::default();
+        settings.set_user_agent(Some("Mozilla/5.0"), t);
+        settings.set_request_header("Cookie", Some("session=1"), t);
+        let request = Request::new("http://example.com/")
+            .header("user-agent", "rust-earth")
+            .header("Accept", "*/*");
+        let request = unwrap!(request.with_settings(&settings));
+        assert_eq!(request.headers, [
+            ("Accept".to_owned(), "*/*".to_owned()),
+            ("User-Agent".to_owned(), "Mozilla/5.0".to_owned()),
+            ("Cookie".to_owned(), "session=1".to_owned()),
+        ]);
+        settings.set_request_header("Host", Some("example.org"), t);
+        assert_err!(Request::new("http://example.com/")
+                        .with_settings(&settings),
+                    Error::InvalidHeader(ref name) => {
+                        assert_eq!(name, "Host");
+                    });
+        assert!(is_valid_header("X-API-Key", "a\tb"));
+        assert!(!is_valid_header("X API", "1"));
+        assert!(!is_valid_header("X-Injected", "1\r\nHost: evil"));
+    }
+}
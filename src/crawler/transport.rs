@@ -0,0 +1,254 @@
+//! Configuring how backends connect to servers: HTTP proxies, TLS, timeouts
+//! and retries, which mobile and corporate networks often need.
+//!
+//! The crate doesn't make connections itself, so `TransportConfig` is
+//! passed to the backend through `Backend::configure()`, and backends apply
+//! what their HTTP clients support.  Timeouts and retries are of each
+//! `Request`, so that they can differ by subscriptions; retries are made by
+//! the crawler, not by backends.
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use job::CancellationToken;
+use secrets::{self, SecretStore};
+use super::{Backend, Request, Response, fetch};
+
+/// The HTTP proxy requests go through.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Proxy {
+    pub host: String,
+    pub port: u16,
+
+    /// The username to authenticate to the proxy with, if it requires.
+    pub username: Option<String>,
+
+    /// The password, which is loaded from a `SecretStore` by
+    /// `with_secret()` rather than configured.
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Proxy")
+         .field("host", &self.host)
+         .field("port", &self.port)
+         .field("username", &self.username)
+         .field("password", &self.password.as_ref().map(|_| "..."))
+         .finish()
+    }
+}
+
+impl Proxy {
+    pub fn new<T: Into<String>>(host: T, port: u16) -> Proxy {
+        Proxy { host: host.into(), port: port, username: None,
+                password: None }
+    }
+
+    /// The name of the proxy password in the `SecretStore`.
+    pub fn secret_name(&self) -> String {
+        format!("crawler.proxy:{}:{}", self.host, self.port)
+    }
+
+    /// Load the password from the store.  It's left as it is if the
+    /// proxy has no username, or the secret is missing.
+    pub fn with_secret<S>(mut self, store: &S) -> secrets::Result<Proxy>
+        where S: SecretStore + ?Sized
+    {
+        if self.username.is_some() {
+            if let Some(password) = try!(store.get(&self.secret_name())) {
+                self.password = Some(password);
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Versions of TLS, in order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TlsVersion { Tls10, Tls11, Tls12, Tls13 }
+
+/// How backends verify and negotiate TLS connections.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TlsOptions {
+    /// Root certificates trusted in addition to the system ones, e.g. of
+    /// the corporate CA, in PEM.
+    pub extra_root_certificates: Vec<String>,
+
+    /// The lowest version allowed.  `None` leaves it to the backend.
+    pub min_version: Option<TlsVersion>,
+}
+
+/// How failed requests are retried.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RetryPolicy {
+    /// How many times a request is retried after the first try.
+    pub max_retries: u32,
+
+    /// How long to wait before the first retry.  It's doubled for each
+    /// following retry.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Requests aren't retried.
+    fn default() -> RetryPolicy {
+        RetryPolicy { max_retries: 0, backoff: Duration::from_secs(1) }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before the `retry`-th retry, starting from 0.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.backoff * 2u32.saturating_pow(retry)
+    }
+
+    /// Whether the result of a try is worth retrying: transient transport
+    /// errors e.g. timeouts, and statuses of overloaded or unavailable
+    /// servers.
+    pub fn is_retryable(result: &io::Result<Response>) -> bool {
+        match *result {
+            Ok(ref response) => {
+                [429, 502, 503, 504].contains(&response.status)
+            }
+            Err(ref err) => matches!(err.kind(),
+                                     io::ErrorKind::TimedOut |
+                                     io::ErrorKind::Interrupted |
+                                     io::ErrorKind::ConnectionReset |
+                                     io::ErrorKind::ConnectionAborted),
+        }
+    }
+}
+
+/// The configuration of connections `Backend::configure()` applies.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TransportConfig {
+    /// The proxy to go through, or a direct connection if `None`.
+    pub proxy: Option<Proxy>,
+
+    pub tls: TlsOptions,
+
+    /// The timeout of requests which don't have their own.
+    pub default_timeout: Option<Duration>,
+}
+
+/// Fetch the document like `fetch()`, retrying as the `retry` policy of
+/// the request tells.  The last result is returned when retries run out.
+pub fn fetch_with_retries<B: Backend + ?Sized>(backend: &B,
+                                               request: &Request)
+                                               -> io::Result<Response>
+{
+    fetch_cancellable(backend, request, &CancellationToken::new())
+}
+
+/// `fetch_with_retries()` which makes no more retries once the `cancel`
+/// token is cancelled; the last failure is returned then.  A fetch being
+/// made isn't interrupted, since backends can't be.
+pub fn fetch_cancellable<B: Backend + ?Sized>(backend: &B, request: &Request,
+                                              cancel: &CancellationToken)
+                                              -> io::Result<Response>
+{
+    let mut result = fetch(backend, request);
+    for retry in 0..request.retry.max_retries {
+        if !RetryPolicy::is_retryable(&result) || cancel.is_cancelled() {
+            break;
+        }
+        thread::sleep(request.retry.delay(retry));
+        if cancel.is_cancelled() {
+            break;
+        }
+        result = fetch(backend, request);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Proxy, RetryPolicy, TransportConfig, fetch_cancellable,
+                fetch_with_retries};
+    use super::super::{Backend, Request, Response};
+
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::io;
+    use std::time::Duration;
+
+    use job::CancellationToken;
+    use secrets::SecretStore;
+
+    struct FlakyBackend {
+        failures: Cell<usize>,
+    }
+
+    impl Backend for FlakyBackend {
+        fn fetch(&self, request: &Request) -> io::Result<Response> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "slow"));
+            }
+            Ok(Response { url: request.url.clone(), status: 200,
+                          headers: vec![], body: vec![], redirects: vec![] })
+        }
+    }
+
+    #[test]
+    fn test_fetch_with_retries() {
+        let backend = FlakyBackend { failures: Cell::new(2) };
+        let mut request = Request::new("http://example.com/");
+        assert!(fetch_with_retries(&backend, &request).is_err());
+        request.retry = RetryPolicy { max_retries: 2,
+                                      backoff: Duration::from_millis(0) };
+        assert!(fetch_with_retries(&backend, &request).is_ok());
+        backend.failures.set(3);
+        assert!(fetch_with_retries(&backend, &request).is_err());
+        let policy = RetryPolicy { max_retries: 3,
+                                   backoff: Duration::from_secs(1) };
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_fetch_cancellable() {
+        let backend = FlakyBackend { failures: Cell::new(2) };
+        let mut request = Request::new("http://example.com/");
+        request.retry = RetryPolicy { max_retries: 2,
+                                      backoff: Duration::from_millis(0) };
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        // no retries are made once cancelled
+        assert!(fetch_cancellable(&backend, &request, &cancel).is_err());
+        assert_eq!(backend.failures.get(), 1);
+        let cancel = CancellationToken::new();
+        assert!(fetch_cancellable(&backend, &request, &cancel).is_ok());
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut backend = FlakyBackend { failures: Cell::new(0) };
+        unwrap!(backend.configure(&Default::default()));
+        let config = TransportConfig {
+            proxy: Some(Proxy::new("proxy.example.com", 3128)),
+            ..Default::default()
+        };
+        assert_eq!(backend.configure(&config).unwrap_err().kind(),
+                   io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_proxy_with_secret() {
+        let mut store: HashMap<String, String> = HashMap::new();
+        let mut proxy = Proxy::new("proxy.example.com", 3128);
+        unwrap!(store.put(&proxy.secret_name(), "hunter2"));
+        assert_eq!(unwrap!(proxy.clone().with_secret(&store)).password,
+                   None);
+        proxy.username = Some("me".to_owned());
+        let proxy = unwrap!(proxy.with_secret(&store));
+        assert_eq!(proxy.password, Some("hunter2".to_owned()));
+        assert!(!format!("{:?}", proxy).contains("hunter2"));
+    }
+}
@@ -0,0 +1,106 @@
+//! Verifying fetched feed documents, e.g. private feeds distributed with
+//! detached signatures or expected fingerprints.
+//!
+//! A subscription can pin the SHA-256 fingerprint of its document by
+//! `SubscriptionSettings::set_fingerprint()`, and apps can check anything
+//! else, e.g. signatures, by a `Verifier` they plug in.  Either failing
+//! fails the refresh with `Error::Unverified`, before the document is
+//! parsed or kept.
+use digest::sha256_hex;
+use subscribe::SubscriptionSettings;
+
+use super::{Error, Response, Result};
+
+/// Checks the raw body of the fetched document along with its response
+/// headers, and tells why it's rejected if it is.  Closures taking the
+/// same arguments as `verify()` are verifiers as well.
+pub trait Verifier {
+    fn verify(&self, body: &[u8], headers: &[(String, String)])
+              -> ::std::result::Result<(), String>;
+}
+
+impl<F> Verifier for F
+    where F: Fn(&[u8], &[(String, String)])
+             -> ::std::result::Result<(), String>
+{
+    fn verify(&self, body: &[u8], headers: &[(String, String)])
+              -> ::std::result::Result<(), String> {
+        self(body, headers)
+    }
+}
+
+/// Verify the successful response by the fingerprint pinned in the
+/// `settings`, if any, and then by the `verifier`, if any.  Responses of
+/// other statuses, e.g. 304, have nothing to verify.
+pub fn verify_response(response: &Response, settings: &SubscriptionSettings,
+                       verifier: Option<&Verifier>) -> Result<()> {
+    if response.status < 200 || response.status >= 300 {
+        return Ok(());
+    }
+    if let Some(expected) = settings.fingerprint() {
+        let actual = sha256_hex(&response.body);
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            return Err(Error::Unverified(format!(
+                "fingerprint {} doesn't match the pinned {}", actual,
+                expected.trim())));
+        }
+    }
+    if let Some(verifier) = verifier {
+        try!(verifier.verify(&response.body, &response.headers)
+                     .map_err(Error::Unverified));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Verifier, verify_response};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crawler::{Error, Response};
+    use digest::sha256_hex;
+    use subscribe::SubscriptionSettings;
+
+    fn fx_response(status: u16, body: &[u8]) -> Response {
+        Response {
+            url: "http://example.com/feed".to_owned(),
+            status: status,
+            headers: vec![("X-Signature".to_owned(), "good".to_owned())],
+            body: body.to_vec(),
+            redirects: vec![],
+        }
+    }
+
+    #[test]
+    fn test_verify_response() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let response = fx_response(200, b"<feed/>");
+        let mut settings = SubscriptionSettings::default();
+        unwrap!(verify_response(&response, &settings, None));
+        let pinned = sha256_hex(b"<feed/>").to_uppercase();
+        settings.set_fingerprint(Some(&pinned), t);
+        unwrap!(verify_response(&response, &settings, None));
+        assert_err!(verify_response(&fx_response(200, b"<forged/>"),
+                                    &settings, None),
+                    Error::Unverified(_) => { });
+        unwrap!(verify_response(&fx_response(304, b""), &settings, None));
+
+        let signed = |_: &[u8], headers: &[(String, String)]| {
+            match headers.iter().find(|h| h.0 == "X-Signature") {
+                Some(h) if h.1 == "good" => Ok(()),
+                _ => Err("bad signature".to_owned()),
+            }
+        };
+        unwrap!(verify_response(&response, &settings,
+                                Some(&signed as &Verifier)));
+        let mut unsigned = response.clone();
+        unsigned.headers.clear();
+        assert_err!(verify_response(&unsigned, &settings,
+                                    Some(&signed as &Verifier)),
+                    Error::Unverified(ref reason) => {
+                        assert_eq!(reason, "bad signature");
+                    });
+    }
+}
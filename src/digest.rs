@@ -0,0 +1,325 @@
+//! Message digests used to derive stable identifiers from contents.
+//!
+//! It implements [SHA-1][] mainly, which is enough for identifiers and
+//! not meant for any security purpose.  [HMAC][]-SHA1 is also provided,
+//! which is still considered sound as a keyed function.  [MD5][] is
+//! there just because HTTP Digest authentication requires it.  [SHA-256][]
+//! is for fingerprints pinning fetched documents, where collisions must
+//! not be made on purpose.
+//!
+//! [SHA-1]: https://tools.ietf.org/html/rfc3174
+//! [HMAC]: https://tools.ietf.org/html/rfc2104
+//! [MD5]: https://tools.ietf.org/html/rfc1321
+//! [SHA-256]: https://tools.ietf.org/html/rfc6234
+use std::fmt::Write;
+
+/// The incremental SHA-1 hasher.
+#[derive(Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    /// Feed the data to the hasher.
+    pub fn update(&mut self, data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        self.buffer.extend_from_slice(data);
+        let blocks = self.buffer.len() / 64;
+        for i in 0..blocks {
+            let mut block = [0u8; 64];
+            block.copy_from_slice(&self.buffer[i * 64..(i + 1) * 64]);
+            process_block(&mut self.state, &block);
+        }
+        self.buffer.drain(..blocks * 64);
+    }
+
+    /// Finish hashing and return the 20 bytes digest.
+    pub fn digest(mut self) -> [u8; 20] {
+        let bits = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        while (self.buffer.len() + padding.len()) % 64 != 56 {
+            padding.push(0);
+        }
+        for i in 0..8 {
+            padding.push((bits >> (56 - i * 8)) as u8);
+        }
+        self.length = 0;
+        self.update(&padding);
+        debug_assert!(self.buffer.is_empty());
+        let mut result = [0u8; 20];
+        for (i, word) in self.state.iter().enumerate() {
+            for j in 0..4 {
+                result[i * 4 + j] = (word >> (24 - j * 8)) as u8;
+            }
+        }
+        result
+    }
+
+    /// Finish hashing and return the digest in lowercase hexadecimal.
+    pub fn hexdigest(self) -> String {
+        let mut result = String::with_capacity(40);
+        for b in self.digest().iter() {
+            write!(result, "{:02x}", b).unwrap();
+        }
+        result
+    }
+}
+
+fn process_block(state: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for i in 0..16 {
+        w[i] = (block[i * 4] as u32) << 24 | (block[i * 4 + 1] as u32) << 16 |
+               (block[i * 4 + 2] as u32) << 8 | block[i * 4 + 3] as u32;
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let (mut a, mut b, mut c, mut d, mut e) =
+        (state[0], state[1], state[2], state[3], state[4]);
+    for (i, &word) in w.iter().enumerate() {
+        let (f, k) = if i < 20 {
+            ((b & c) | (!b & d), 0x5A827999)
+        } else if i < 40 {
+            (b ^ c ^ d, 0x6ED9EBA1)
+        } else if i < 60 {
+            ((b & c) | (b & d) | (c & d), 0x8F1BBCDC)
+        } else {
+            (b ^ c ^ d, 0xCA62C1D6)
+        };
+        let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e)
+                    .wrapping_add(k).wrapping_add(word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+    state[4] = state[4].wrapping_add(e);
+}
+
+/// Shortcut to get the SHA-1 hexdigest of the data at once.
+pub fn sha1_hex(data: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.hexdigest()
+}
+
+/// HMAC-SHA1 of the data with the key ([RFC 2104][]).
+///
+/// [RFC 2104]: https://tools.ietf.org/html/rfc2104
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        let mut hasher = Sha1::new();
+        hasher.update(key);
+        block[..20].copy_from_slice(&hasher.digest());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha1::new();
+    inner.update(&block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    inner.update(data);
+    let mut outer = Sha1::new();
+    outer.update(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.update(&inner.digest());
+    outer.digest()
+}
+
+/// Per-round shift amounts of MD5.
+static MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20,
+                                4, 11, 16, 23, 6, 10, 15, 21];
+
+/// The MD5 digest of the data in lowercase hexadecimal.
+pub fn md5_hex(data: &[u8]) -> String {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend((0..8).map(|i| (bits >> (i * 8)) as u8));
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let mut m = [0u32; 16];
+        for i in 0..16 {
+            m[i] = block[i * 4] as u32 | (block[i * 4 + 1] as u32) << 8 |
+                   (block[i * 4 + 2] as u32) << 16 |
+                   (block[i * 4 + 3] as u32) << 24;
+        }
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2],
+                                            state[3]);
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let k = ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32;
+            let shift = MD5_SHIFTS[(i / 16) * 4 + i % 4];
+            let rotated = a.wrapping_add(f).wrapping_add(k)
+                           .wrapping_add(m[g]).rotate_left(shift);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+    let mut result = String::with_capacity(32);
+    for word in state.iter() {
+        for i in 0..4 {
+            write!(result, "{:02x}", (word >> (i * 8)) as u8).unwrap();
+        }
+    }
+    result
+}
+
+/// Round constants of SHA-256.
+static SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The SHA-256 digest of the data in lowercase hexadecimal.
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend((0..8).map(|i| (bits >> (56 - i * 8)) as u8));
+    let mut state: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                               0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (block[i * 4] as u32) << 24 |
+                   (block[i * 4 + 1] as u32) << 16 |
+                   (block[i * 4 + 2] as u32) << 8 | block[i * 4 + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^
+                     w[i - 15] >> 3;
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^
+                     w[i - 2] >> 10;
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7])
+                            .wrapping_add(s1);
+        }
+        let mut v = state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^
+                     v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let temp1 = v[7].wrapping_add(s1).wrapping_add(ch)
+                            .wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^
+                     v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let temp2 = s0.wrapping_add(maj);
+            v = [temp1.wrapping_add(temp2), v[0], v[1], v[2],
+                 v[3].wrapping_add(temp1), v[4], v[5], v[6]];
+        }
+        for (s, x) in state.iter_mut().zip(v.iter()) {
+            *s = s.wrapping_add(*x);
+        }
+    }
+    let mut result = String::with_capacity(64);
+    for word in state.iter() {
+        write!(result, "{:08x}", word).unwrap();
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Sha1, hmac_sha1, md5_hex, sha1_hex, sha256_hex};
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(sha1_hex(b"abc"),
+                   "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklm\
+                               klmnlmnomnopnopq"),
+                   "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+        let mut hasher = Sha1::new();
+        for _ in 0..1000 {
+            hasher.update(&[b'a'; 1000]);
+        }
+        assert_eq!(hasher.hexdigest(),
+                   "34aa973cd4c4daa4f61eeb2bdbad27316534016f");
+    }
+
+    #[test]
+    fn test_hmac_sha1() {
+        let hex = |d: [u8; 20]| {
+            d.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        };
+        assert_eq!(hex(hmac_sha1(&[0x0b; 20], b"Hi There")),
+                   "b617318655057264e28bc0b6fb378c8ef146be00");
+        assert_eq!(hex(hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+                   "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+        assert_eq!(hex(hmac_sha1(&[0xaa; 80], b"Test Using Larger Than \
+                                                 Block-Size Key - Hash Key \
+                                                 First")),
+                   "aa4ae5e15272d00e95705637ce8a3b55ed402112");
+    }
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(md5_hex(b"12345678901234567890123456789012345678901234\
+                              567890123456789012345678901234567890"),
+                   "57edf4a22be3c955ac49da2e2107b67a");
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(sha256_hex(b""),
+                   "e3b0c44298fc1c149afbf4c8996fb924\
+                    27ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"),
+                   "ba7816bf8f01cfea414140de5dae2223\
+                    b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklm\
+                                 klmnlmnomnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039\
+                    a33ce45964ff2167f6ecedd419db06c1");
+    }
+}
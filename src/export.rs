@@ -0,0 +1,877 @@
+//! Exporting entries to other apps, e.g. sharing them to note-taking apps
+//! which take Markdown rather than HTML.
+//!
+//! Converters take HTML already filtered by a `SanitizerPreset`, and apps
+//! can plug in their own by implementing `Converter`.  `Markdown` converts
+//! the subset the presets keep:
+//!
+//! - headings, paragraphs, quotations, lists and horizontal rules;
+//! - emphasis, strong emphasis, inline code and code blocks;
+//! - links and images.
+//!
+//! Elements Markdown has no syntax for, e.g. tables, are reduced to their
+//! text.
+//!
+//! For share sheets of mobile platforms, which take a short text rather
+//! than the whole entry, `Entry::share_payload()` gives a `SharePayload`.
+use std::cmp::{max, min};
+use std::fmt;
+use std::io;
+
+use feed::{Blob, Entry, LinkSliceExt};
+use job::CancellationToken;
+use render::DEFAULT_SOFT_WRAP;
+use sanitizer::{SanitizerPreset, Tag, decode_references, parse_tag};
+use util::{FileNames, truncate_graphemes};
+
+/// The maximum length of `SharePayload::summary` in grapheme clusters,
+/// excluding the ellipsis.
+pub const SHARE_SUMMARY_LENGTH: usize = 200;
+
+/// Converts sanitized HTML to other markup.
+pub trait Converter {
+    fn convert(&self, html: &str) -> String;
+}
+
+/// Convert the entry, i.e. its title and content (or summary) as
+/// `render::entry_html()` renders them, filtered by the `preset`.
+pub fn export_entry<C>(entry: &Entry, preset: SanitizerPreset, converter: &C)
+                       -> String
+    where C: Converter + ?Sized
+{
+    converter.convert(&EntryHtml(entry, preset).to_string())
+}
+
+/// Export the entries in turn as `export_entry()` does, and pass each to
+/// `write` along with its file name made by `entry_file_name()`.  It's
+/// checked whether `cancel` is cancelled before each entry, and it stops
+/// there if it is.  It returns the number of exported entries, so that the
+/// rest can be exported on resumption, or the first error of `write`.
+pub fn export_entries<'a, I, C, W>(entries: I, preset: SanitizerPreset,
+                                   converter: &C, names: &mut FileNames,
+                                   extension: &str,
+                                   cancel: &CancellationToken, mut write: W)
+                                   -> io::Result<usize>
+    where I: IntoIterator<Item=&'a Entry>, C: Converter + ?Sized,
+          W: FnMut(&str, &str) -> io::Result<()>
+{
+    let mut count = 0;
+    for entry in entries {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let name = entry_file_name(entry, names, extension);
+        try!(write(&name, &export_entry(entry, preset, converter)));
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The name of the file the entry is exported to, after its title with the
+/// `extension` e.g. `md`, which isn't taken in `names` yet.  See
+/// `util::filename`.
+pub fn entry_file_name(entry: &Entry, names: &mut FileNames, extension: &str)
+                       -> String {
+    names.name_for(&entry.title.as_plain_text(), extension)
+}
+
+/// What's shared to other apps, e.g. through share sheets, made by
+/// `Entry::share_payload()`.  Every field is plain text on a single line,
+/// so that it's safe to put as it is in text fields of other apps.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SharePayload {
+    pub title: String,
+
+    /// The URL of the web page of the entry, i.e. its permalink, or its ID
+    /// if it's an HTTP URL.
+    pub url: Option<String>,
+
+    /// The summary (or content) cut at `SHARE_SUMMARY_LENGTH`.
+    pub summary: Option<String>,
+
+    /// Categories of the entry as hashtags e.g. `#OpenSource`; see
+    /// `hashtag()`.
+    pub hashtags: Vec<String>,
+}
+
+impl SharePayload {
+    pub fn new(entry: &Entry) -> SharePayload {
+        let url = entry.links.permalink().map(|l| l.uri.clone()).or_else(|| {
+            if entry.id.starts_with("http://") ||
+               entry.id.starts_with("https://") {
+                Some(entry.id.clone())
+            } else {
+                None
+            }
+        });
+        let body: Option<&Blob> = match (&entry.content, &entry.summary) {
+            (_, Some(summary)) => Some(summary),
+            (Some(content), None) => Some(content),
+            (None, None) => None,
+        };
+        let summary = body.map(|b| b.normalized_text()).and_then(|text| {
+            let cut = truncate_graphemes(&text, SHARE_SUMMARY_LENGTH);
+            if cut.is_empty() {
+                None
+            } else if cut.len() < text.len() {
+                Some(format!("{}\u{2026}", cut.trim_end()))
+            } else {
+                Some(cut.to_owned())
+            }
+        });
+        let mut hashtags: Vec<String> = vec![];
+        for category in entry.categories.iter() {
+            let label = category.label.as_ref()
+                                .map_or(&category.term[..], |l| l);
+            if let Some(tag) = hashtag(label) {
+                let lower = tag.to_lowercase();
+                if !hashtags.iter().any(|t| t.to_lowercase() == lower) {
+                    hashtags.push(tag);
+                }
+            }
+        }
+        SharePayload {
+            title: entry.title.normalized_text().into_owned(),
+            url: url,
+            summary: summary,
+            hashtags: hashtags,
+        }
+    }
+
+    /// Lines of the title, the summary, the URL and hashtags, separated by
+    /// blank lines, without missing ones.
+    pub fn to_plain_text(&self) -> String {
+        let hashtags = self.hashtags.join(" ");
+        let parts = [Some(&self.title[..]),
+                     self.summary.as_ref().map(|s| &s[..]),
+                     self.url.as_ref().map(|u| &u[..]),
+                     Some(&hashtags[..])];
+        parts.iter().filter_map(|p| *p).filter(|p| !p.is_empty())
+             .collect::<Vec<_>>().join("\n\n")
+    }
+
+    /// The title linked to the URL, the summary quoted, and hashtags, in
+    /// Markdown.
+    pub fn to_markdown(&self) -> String {
+        fn escape_line(buf: &mut String, text: &str) {
+            if text.starts_with(|c: char| ESCAPED_AT_LINE_START.contains(c)) {
+                buf.push('\\');
+            }
+            escape_into(buf, text);
+        }
+        let mut blocks = vec![match self.url {
+            Some(ref url) => {
+                let mut title = String::new();
+                escape_into(&mut title, &self.title);
+                format!("[{}]({})", title, destination(url))
+            }
+            None => {
+                let mut title = String::new();
+                escape_line(&mut title, &self.title);
+                title
+            }
+        }];
+        if let Some(ref summary) = self.summary {
+            let mut quote = String::from("> ");
+            escape_line(&mut quote, summary);
+            blocks.push(quote);
+        }
+        if !self.hashtags.is_empty() {
+            blocks.push(self.hashtags.join(" "));
+        }
+        blocks.join("\n\n") + "\n"
+    }
+}
+
+/// Make the category label a hashtag: words of letters, digits and
+/// underscores are joined, capitalized if there are several.  It's `None`
+/// if no word is left, or only digits are, which aren't hashtags.
+///
+/// ```
+/// # use earth::export::hashtag;
+/// assert_eq!(hashtag("rust").unwrap(), "#rust");
+/// assert_eq!(hashtag("open source").unwrap(), "#OpenSource");
+/// assert_eq!(hashtag("C++"), Some("#C".to_owned()));
+/// assert_eq!(hashtag("2013"), None);
+/// ```
+pub fn hashtag(label: &str) -> Option<String> {
+    let words: Vec<&str> = label.split(|c: char| {
+        !c.is_alphanumeric() && c != '_'
+    }).filter(|w| !w.is_empty()).collect();
+    if words.iter().all(|w| w.chars().all(|c| c.is_numeric())) {
+        return None;
+    }
+    let mut tag = String::from("#");
+    for word in words.iter() {
+        if words.len() == 1 {
+            tag.push_str(word);
+            break;
+        }
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            tag.extend(first.to_uppercase());
+            tag.push_str(chars.as_str());
+        }
+    }
+    Some(tag)
+}
+
+struct EntryHtml<'a>(&'a Entry, SanitizerPreset);
+
+impl<'a> fmt::Display for EntryHtml<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.write_html(f, None, self.1, DEFAULT_SOFT_WRAP)
+    }
+}
+
+/// The converter to [CommonMark][].
+///
+/// [CommonMark]: http://commonmark.org/
+///
+/// ### Example
+///
+/// ```
+/// # use earth::export::{Converter, Markdown};
+/// let html = "<h2>Fish &amp; chips</h2><p>It's <em>great</em>.</p>";
+/// assert_eq!(Markdown.convert(html), "## Fish & chips\n\nIt's *great*.\n");
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub struct Markdown;
+
+impl Converter for Markdown {
+    fn convert(&self, html: &str) -> String {
+        let mut writer = MarkdownWriter::default();
+        let mut rest = html;
+        let mut skip_until: Option<String> = None;
+        while let Some(i) = rest.find('<') {
+            if skip_until.is_none() {
+                writer.text(&rest[..i]);
+            }
+            rest = &rest[i..];
+            if rest.starts_with("<!--") {
+                rest = rest.find("-->").map_or("", |e| &rest[e + 3..]);
+                continue;
+            }
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |e| &rest[e + 1..]);
+                continue;
+            }
+            let (tag, len) = match parse_tag(rest) {
+                Some(parsed) => parsed,
+                None => {
+                    if skip_until.is_none() {
+                        writer.text("&lt;");
+                    }
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            rest = &rest[len..];
+            if let Some(ref skipped) = skip_until {
+                if !(tag.end && tag.name == *skipped) {
+                    continue;
+                }
+            }
+            if skip_until.take().is_some() {
+                continue;
+            }
+            if !tag.end && (tag.name == "script" || tag.name == "style") {
+                skip_until = Some(tag.name);
+                continue;
+            }
+            if tag.end {
+                writer.end(&tag.name);
+            } else {
+                writer.start(&tag);
+            }
+        }
+        if skip_until.is_none() {
+            writer.text(rest);
+        }
+        writer.finish()
+    }
+}
+
+/// Characters escaped wherever they are in texts.
+const ESCAPED: &'static str = "\\`*_[]<";
+
+/// Characters escaped at the start of lines, where they'd begin blocks.
+const ESCAPED_AT_LINE_START: &'static str = "#>-+=";
+
+#[derive(Default)]
+struct MarkdownWriter {
+    out: String,
+
+    /// Prefixes of lines by nested quotations and list items.
+    prefixes: Vec<String>,
+
+    /// The marker of the list item starting at the next line, which
+    /// replaces the last prefix there.
+    marker: Option<String>,
+
+    /// Newlines to write before the next markup: 1 for a line break, and
+    /// 2 for a new block.
+    breaks: usize,
+
+    /// The number of prefixes blank lines between blocks have, i.e. the
+    /// shallowest nesting since the breaks were requested.
+    break_depth: usize,
+
+    /// Whether nothing but prefixes is written to the current line.
+    line_start: bool,
+
+    /// Whether whitespace is pending, which is collapsed into a space.
+    space: bool,
+
+    /// Destinations of open links; `None` for anchors without them.
+    links: Vec<Option<String>>,
+
+    /// Numbers of the next items of open lists; `None` for unordered ones.
+    lists: Vec<Option<u64>>,
+
+    /// The text of the open `<code>` element, which is written as is.
+    code: Option<String>,
+
+    /// The text of the open `<pre>` element.
+    pre: Option<String>,
+}
+
+impl MarkdownWriter {
+    fn start(&mut self, tag: &Tag) {
+        if self.pre.is_some() {
+            return;
+        }
+        match &tag.name[..] {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = tag.name[1..].parse().unwrap_or(1);
+                self.block();
+                self.markup(&format!("{} ", "#".repeat(level)), true);
+            }
+            "p" | "div" | "article" | "section" | "figure" | "figcaption" |
+            "table" | "caption" | "thead" | "tbody" | "tfoot" | "dl" => {
+                self.block();
+            }
+            "tr" | "dt" | "dd" => { self.line_break(); }
+            "td" | "th" => { self.space = true; }
+            "br" => {
+                self.markup("\\", false);
+                self.line_break();
+            }
+            "hr" => {
+                self.block();
+                self.markup("---", true);
+                self.block();
+            }
+            "blockquote" => {
+                self.block();
+                self.prefixes.push("> ".to_owned());
+            }
+            "ul" | "ol" => {
+                self.list_break();
+                self.lists.push(if tag.name == "ol" {
+                    Some(tag.attribute("start").and_then(|s| s.parse().ok())
+                            .unwrap_or(1))
+                } else {
+                    None
+                });
+            }
+            "li" => {
+                self.line_break();
+                let marker = match self.lists.last_mut() {
+                    Some(&mut Some(ref mut n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".to_owned(),
+                };
+                self.prefixes.push(" ".repeat(marker.len()));
+                self.marker = Some(marker);
+            }
+            "pre" => { self.pre = Some(String::new()); }
+            "code" => { self.code = Some(String::new()); }
+            "em" | "i" => { self.markup("*", true); }
+            "strong" | "b" => { self.markup("**", true); }
+            "a" => {
+                let href = tag.attribute("href").map(|h| h.to_owned());
+                if href.is_some() {
+                    self.markup("[", true);
+                }
+                self.links.push(href);
+            }
+            "img" => {
+                if let Some(src) = tag.attribute("src") {
+                    let alt = tag.attribute("alt").unwrap_or("");
+                    let mut image = "![".to_owned();
+                    escape_into(&mut image, alt);
+                    image.push_str(&format!("]({})", destination(src)));
+                    self.markup(&image, true);
+                }
+            }
+            _ => { }
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if let Some(pre) = self.pre.take() {
+            if name == "pre" {
+                self.code_block(&pre);
+            } else {
+                self.pre = Some(pre);
+            }
+            return;
+        }
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "p" | "div" |
+            "article" | "section" | "figure" | "figcaption" | "table" |
+            "caption" | "thead" | "tbody" | "tfoot" | "dl" => {
+                self.block();
+            }
+            "tr" | "dt" | "dd" => { self.line_break(); }
+            "blockquote" => {
+                self.block();
+                self.pop_prefix();
+            }
+            "ul" | "ol" => {
+                self.lists.pop();
+                self.list_break();
+            }
+            "li" => {
+                self.line_break();
+                self.marker = None;
+                self.pop_prefix();
+            }
+            "code" => {
+                if let Some(code) = self.code.take() {
+                    self.code_span(&code);
+                }
+            }
+            "em" | "i" => { self.markup("*", false); }
+            "strong" | "b" => { self.markup("**", false); }
+            "a" => {
+                if let Some(Some(href)) = self.links.pop() {
+                    self.markup(&format!("]({})", destination(&href)), false);
+                }
+            }
+            _ => { }
+        }
+    }
+
+    /// Write the text of HTML, i.e. with character references.
+    fn text(&mut self, html: &str) {
+        let text = decode_references(html);
+        if let Some(ref mut pre) = self.pre {
+            pre.push_str(&text);
+            return;
+        }
+        if let Some(ref mut code) = self.code {
+            code.push_str(&text);
+            return;
+        }
+        let mut buf = String::new();
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !buf.is_empty() {
+                    self.markup(&buf, true);
+                    buf.clear();
+                }
+                self.space = true;
+                continue;
+            }
+            if buf.is_empty() && self.line_start_after_space() &&
+               ESCAPED_AT_LINE_START.contains(c) ||
+               ESCAPED.contains(c) {
+                buf.push('\\');
+            }
+            buf.push(c);
+        }
+        if !buf.is_empty() {
+            self.markup(&buf, true);
+        }
+    }
+
+    /// Whether the next markup starts a line.
+    fn line_start_after_space(&self) -> bool {
+        self.breaks > 0 || self.out.is_empty() ||
+            self.line_start && !self.space
+    }
+
+    /// Write the markup.  Pending whitespace is written before it if
+    /// `after_space`, or kept pending after it otherwise, e.g. for closing
+    /// delimiters which can't follow whitespace.
+    fn markup(&mut self, s: &str, after_space: bool) {
+        if s.is_empty() {
+            return;
+        }
+        if self.breaks > 0 || self.out.is_empty() {
+            self.start_line();
+        } else if self.space && after_space && !self.line_start {
+            self.out.push(' ');
+            self.space = false;
+        }
+        self.out.push_str(s);
+        self.line_start = false;
+    }
+
+    fn start_line(&mut self) {
+        if !self.out.is_empty() {
+            let blank = self.prefixes[..self.break_depth].concat();
+            for i in 0..self.breaks {
+                self.out.push('\n');
+                if i + 1 < self.breaks {
+                    self.out.push_str(blank.trim_end());
+                }
+            }
+        }
+        let last = self.prefixes.len().saturating_sub(1);
+        for (i, prefix) in self.prefixes.iter().enumerate() {
+            match self.marker {
+                Some(ref marker) if i == last => {
+                    self.out.push_str(marker);
+                }
+                _ => { self.out.push_str(prefix); }
+            }
+        }
+        self.marker = None;
+        self.breaks = 0;
+        self.space = false;
+        self.line_start = true;
+    }
+
+    fn request_breaks(&mut self, breaks: usize) {
+        if self.breaks == 0 {
+            self.break_depth = self.prefixes.len();
+        }
+        self.breaks = max(self.breaks, breaks);
+        self.space = false;
+    }
+
+    fn block(&mut self) { self.request_breaks(2); }
+
+    fn line_break(&mut self) { self.request_breaks(1); }
+
+    /// Lists are blocks of their own, unless they're nested in items.
+    fn list_break(&mut self) {
+        if self.lists.is_empty() {
+            self.block();
+        } else {
+            self.line_break();
+        }
+    }
+
+    fn pop_prefix(&mut self) {
+        self.prefixes.pop();
+        self.break_depth = min(self.break_depth, self.prefixes.len());
+    }
+
+    fn code_span(&mut self, code: &str) {
+        let code = code.replace('\n', " ");
+        let fence = "`".repeat(longest_run(&code, '`') + 1);
+        let padding = if code.starts_with('`') || code.ends_with('`') {
+            " "
+        } else {
+            ""
+        };
+        self.markup(&format!("{}{}{}{}{}", fence, padding, code, padding,
+                             fence), true);
+    }
+
+    fn code_block(&mut self, code: &str) {
+        let code = code.trim_end_matches('\n');
+        let code = code.strip_prefix('\n').unwrap_or(code);
+        let fence = "`".repeat(max(longest_run(code, '`') + 1, 3));
+        self.block();
+        self.markup(&fence, true);
+        for line in code.split('\n') {
+            self.line_break();
+            self.start_line();
+            self.out.push_str(line);
+        }
+        self.line_break();
+        self.markup(&fence, true);
+        self.block();
+    }
+
+    fn finish(self) -> String {
+        let mut out = self.out;
+        let len = out.trim_end().len();
+        out.truncate(len);
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn escape_into(buf: &mut String, text: &str) {
+    for c in text.chars() {
+        if ESCAPED.contains(c) {
+            buf.push('\\');
+        }
+        buf.push(c);
+    }
+}
+
+/// The destination of the link or the image, in angle brackets if it has
+/// characters which would end it early.
+fn destination(url: &str) -> String {
+    let url = url.trim();
+    if url.is_empty() || url.contains(|c: char| {
+        c.is_whitespace() || c == '(' || c == ')'
+    }) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_owned()
+    }
+}
+
+fn longest_run(s: &str, c: char) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    for d in s.chars() {
+        run = if d == c { run + 1 } else { 0 };
+        longest = max(longest, run);
+    }
+    longest
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Converter, Markdown, SharePayload, entry_file_name,
+                export_entries, export_entry};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Category, Entry, Link, Text};
+    use job::CancellationToken;
+    use sanitizer::{SanitizerPreset, escape};
+    use util::FileNames;
+
+    /// Render the subset of Markdown `Markdown` writes for headings,
+    /// paragraphs, code blocks and inlines back to HTML.
+    fn render(markdown: &str) -> String {
+        let mut html = String::new();
+        let mut paragraph: Vec<&str> = vec![];
+        let mut lines = markdown.lines();
+        while let Some(line) = lines.next() {
+            if (line.is_empty() || line.starts_with('#') ||
+                line.starts_with("```")) && !paragraph.is_empty() {
+                html.push_str(&format!("<p>{}</p>",
+                                       render_inline(&paragraph.join("\n"))));
+                paragraph.clear();
+            }
+            if line.starts_with("```") {
+                html.push_str("<pre>");
+                for code in lines.by_ref().take_while(|l| !l.starts_with("`"))
+                {
+                    html.push_str(&format!("{}\n", escape(code, false)));
+                }
+                html.push_str("</pre>");
+            } else if line.starts_with('#') {
+                let level = line.find(' ').unwrap();
+                html.push_str(&format!("<h{}>{}</h{}>", level,
+                                       render_inline(&line[level + 1..]),
+                                       level));
+            } else if !line.is_empty() {
+                paragraph.push(line);
+            }
+        }
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>",
+                                   render_inline(&paragraph.join("\n"))));
+        }
+        html
+    }
+
+    fn render_inline(markdown: &str) -> String {
+        let mut html = String::new();
+        let (mut em, mut strong) = (false, false);
+        let mut rest = markdown;
+        while let Some(c) = rest.chars().next() {
+            if c == '\\' {
+                let escaped = rest[1..].chars().next().unwrap();
+                html.push_str(&escape(&escaped.to_string(), false)
+                                  .to_string());
+                rest = &rest[1 + escaped.len_utf8()..];
+            } else if c == '`' {
+                let end = rest[1..].find('`').unwrap() + 1;
+                html.push_str(&format!("<code>{}</code>",
+                                       escape(&rest[1..end], false)));
+                rest = &rest[end + 1..];
+            } else if rest.starts_with("**") {
+                html.push_str(if strong { "</strong>" } else { "<strong>" });
+                strong = !strong;
+                rest = &rest[2..];
+            } else if c == '*' {
+                html.push_str(if em { "</em>" } else { "<em>" });
+                em = !em;
+                rest = &rest[1..];
+            } else if c == '!' && rest[1..].starts_with('[') {
+                let alt_end = rest.find("](").unwrap();
+                let end = rest.find(')').unwrap();
+                html.push_str(&format!("<img src=\"{}\" alt=\"{}\">",
+                                       &rest[alt_end + 2..end],
+                                       &rest[2..alt_end]));
+                rest = &rest[end + 1..];
+            } else if c == '[' {
+                let text_end = rest.find("](").unwrap();
+                let end = rest.find(')').unwrap();
+                html.push_str(&format!("<a href=\"{}\">{}</a>",
+                                       &rest[text_end + 2..end],
+                                       render_inline(&rest[1..text_end])));
+                rest = &rest[end + 1..];
+            } else {
+                html.push_str(&escape(&c.to_string(), false).to_string());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        html
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let fragments = [
+            "<h1>Earth Reader</h1><p>Fish &amp; <em>chips</em></p>",
+            "<h3>A <strong>bold</strong> claim</h3>",
+            "<p>Read <a href=\"http://earthreader.org/\">the <em>site</em>\
+             </a>.</p>",
+            "<p><img src=\"http://earthreader.org/logo.png\" alt=\"Logo\">\
+             </p>",
+            "<p>Call <code>refresh()</code> with 2 * 3 [args] &lt;x&gt;.</p>",
+            "<pre>fn main() {\n\n    println!(\"*\");\n}\n</pre>",
+        ];
+        for fragment in fragments.iter() {
+            let sanitized = SanitizerPreset::Article.filter(fragment)
+                                                    .to_string();
+            let markdown = Markdown.convert(&sanitized);
+            assert_eq!(render(&markdown), sanitized);
+        }
+    }
+
+    #[test]
+    fn test_markdown() {
+        let convert = |html: &str| Markdown.convert(html);
+        assert_eq!(convert(""), "");
+        assert_eq!(convert("<p>a  \n b</p><p>c<br>d</p>"),
+                   "a b\n\nc\\\nd\n");
+        assert_eq!(convert("<p><em>a </em>b</p>"), "*a* b\n");
+        assert_eq!(convert("<p># 1 - <b>*</b></p>"), "\\# 1 - **\\***\n");
+        assert_eq!(convert("<p>x</p><hr><p>y</p>"), "x\n\n---\n\ny\n");
+        assert_eq!(convert("<blockquote><p>a</p><p>b</p></blockquote>c"),
+                   "> a\n>\n> b\n\nc\n");
+        assert_eq!(convert("<ul><li>a<ol start=\"3\"><li>b</li><li>c</li>\
+                            </ol></li><li>d</li></ul>"),
+                   "- a\n  3. b\n  4. c\n- d\n");
+        assert_eq!(convert("<blockquote><pre>a\n\nb</pre></blockquote>"),
+                   "> ```\n> a\n> \n> b\n> ```\n");
+        assert_eq!(convert("<code>a`b</code> <code>`</code>"),
+                   "``a`b`` `` ` ``\n");
+        assert_eq!(convert("<a href=\"a b\">x</a><a name=\"y\">y</a>"),
+                   "[x](<a b>)y\n");
+        assert_eq!(convert("<table><tr><th>a</th><th>b</th></tr>\
+                            <tr><td>1</td><td>2</td></tr></table>"),
+                   "a b\n1 2\n");
+        assert_eq!(convert("x<script>*</script>"), "x\n");
+    }
+
+    #[test]
+    fn test_export_entry() {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:earthreader:1".to_owned(),
+                                   Text::plain("Hello"), date);
+        entry.links.push(Link::new("http://earthreader.org/1"));
+        entry.summary = Some(Text::html(
+            "<p>It's <strong>here</strong>.</p><img src=\"x.png\">"));
+        assert_eq!(export_entry(&entry, SanitizerPreset::Strict, &Markdown),
+                   "# [Hello](http://earthreader.org/1)\n\nIt's **here**.\n");
+        assert_eq!(export_entry(&entry, SanitizerPreset::Article, &Markdown),
+                   "# [Hello](http://earthreader.org/1)\n\n\
+                    It's **here**.\n\n![](x.png)\n");
+    }
+
+    #[test]
+    fn test_export_entries() {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let entries: Vec<Entry> = ["Fish", "Chips", "Fish"].iter()
+            .map(|t| Entry::new(format!("urn:{}", t), Text::plain(*t), date))
+            .collect();
+        let cancel = CancellationToken::new();
+        let mut names = FileNames::new();
+        let mut files = vec![];
+        let count = unwrap!(export_entries(
+            &entries[..2], SanitizerPreset::Strict, &Markdown, &mut names,
+            "md", &cancel, |name, text| {
+                files.push((name.to_owned(), text.to_owned()));
+                if files.len() == 1 { cancel.cancel(); }
+                Ok(())
+            }));
+        assert_eq!(count, 1);
+        assert_eq!(files, [("fish.md".to_owned(), "# Fish\n".to_owned())]);
+        // resumed with the rest, and names taken so far aren't reused
+        let count = unwrap!(export_entries(
+            &entries[1..], SanitizerPreset::Strict, &Markdown, &mut names,
+            "md", &CancellationToken::new(), |name, _| {
+                files.push((name.to_owned(), String::new()));
+                Ok(())
+            }));
+        assert_eq!(count, 2);
+        assert_eq!(files[1].0, "chips.md");
+        assert_eq!(files[2].0, "fish-2.md");
+    }
+
+    #[test]
+    fn test_entry_file_name() {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let entry = Entry::new("urn:earthreader:1".to_owned(),
+                               Text::html("<b>Fish</b> and chips"), date);
+        let mut names = FileNames::new();
+        assert_eq!(entry_file_name(&entry, &mut names, "md"),
+                   "fish-and-chips.md");
+        assert_eq!(entry_file_name(&entry, &mut names, "md"),
+                   "fish-and-chips-2.md");
+    }
+
+    #[test]
+    fn test_share_payload() {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("http://earthreader.org/1".to_owned(),
+                                   Text::html("Fish &amp;\n chips"), date);
+        let payload = entry.share_payload();
+        assert_eq!(payload, SharePayload {
+            title: "Fish & chips".to_owned(),
+            url: Some("http://earthreader.org/1".to_owned()),
+            summary: None,
+            hashtags: vec![],
+        });
+        assert_eq!(payload.to_plain_text(),
+                   "Fish & chips\n\nhttp://earthreader.org/1");
+
+        entry.id = "urn:earthreader:1".to_owned();
+        entry.links.push(Link::new("http://earthreader.org/fish"));
+        let long: String = (0..300).map(|_| "가 ").collect();
+        entry.summary = Some(Text::html(format!("<p>{}</p>", long)));
+        for term in &["Food", "food", "fish *and* chips", "2013"] {
+            entry.categories.push(Category {
+                term: term.to_string().into(),
+                ..Default::default()
+            });
+        }
+        let payload = entry.share_payload();
+        assert_eq!(payload.url,
+                   Some("http://earthreader.org/fish".to_owned()));
+        let summary = payload.summary.clone().unwrap();
+        assert!(summary.starts_with("가 가 "));
+        assert!(summary.ends_with("가\u{2026}"));
+        assert_eq!(payload.hashtags, ["#Food", "#FishAndChips"]);
+        assert_eq!(payload.to_markdown(),
+                   format!("[Fish & chips](http://earthreader.org/fish)\n\n\
+                            > {}\n\n#Food #FishAndChips\n", summary));
+        let payload = SharePayload {
+            title: "#1 *pick*".to_owned(),
+            url: None,
+            summary: Some("> quoted".to_owned()),
+            hashtags: vec![],
+        };
+        assert_eq!(payload.to_markdown(), "\\#1 \\*pick\\*\n\n> \\> quoted\n");
+    }
+}
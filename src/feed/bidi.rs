@@ -0,0 +1,149 @@
+use std::fmt;
+
+/// The text direction, which corresponds to HTML `dir` attribute.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Direction {
+    /// Left-to-right, e.g. English, Korean.
+    Ltr,
+
+    /// Right-to-left, e.g. Arabic, Hebrew.
+    Rtl,
+}
+
+impl Direction {
+    /// The value of HTML `dir` attribute.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Direction::Ltr => "ltr",
+            Direction::Rtl => "rtl",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// ISO 639 codes of languages written right-to-left.
+static RTL_LANGUAGES: [&'static str; 16] = [
+    "ar", "arc", "ckb", "dv", "fa", "he", "iw", "ji", "khw", "ks", "ps",
+    "sd", "syr", "ug", "ur", "yi",
+];
+
+/// ISO 15924 codes of scripts written right-to-left.
+static RTL_SCRIPTS: [&'static str; 8] = [
+    "adlm", "arab", "hebr", "mand", "nkoo", "samr", "syrc", "thaa",
+];
+
+/// Guess the direction from the language tag ([BCP 47][]) e.g. `"ar"`,
+/// `"he-IL"`, `"az-Arab"`.  The script subtag takes precedence over the
+/// language.
+///
+/// [BCP 47]: https://tools.ietf.org/html/bcp47
+pub fn language_direction(lang: &str) -> Direction {
+    let subtags: Vec<String> = lang.split(['-', '_'])
+                                   .map(|s| s.to_ascii_lowercase())
+                                   .collect();
+    let script = subtags.iter().skip(1).find(|s| {
+        s.len() == 4 && s.chars().all(|c| c.is_alphabetic())
+    });
+    let rtl = match script {
+        Some(script) => RTL_SCRIPTS.contains(&&script[..]),
+        None => RTL_LANGUAGES.contains(&&subtags[0][..]),
+    };
+    if rtl { Direction::Rtl } else { Direction::Ltr }
+}
+
+/// Unicode blocks of right-to-left scripts.
+static RTL_RANGES: [(u32, u32); 5] = [
+    (0x0590, 0x08FF), (0xFB1D, 0xFDFF), (0xFE70, 0xFEFF),
+    (0x10800, 0x10FFF), (0x1E800, 0x1EFFF),
+];
+
+/// Whether the character is a strong right-to-left character, i.e. of
+/// Hebrew, Arabic, Syriac, Thaana, N'Ko, and so on.
+fn is_rtl_char(c: char) -> bool {
+    let c = c as u32;
+    RTL_RANGES.iter().any(|&(start, end)| start <= c && c <= end)
+}
+
+/// Guess the direction of the text by its first strong character, as
+/// the [Unicode Bidirectional Algorithm][uax9] does for paragraphs.
+/// Markup tags and character references are skipped if it's `html`.
+/// It returns `None` if there's no strong character.
+///
+/// [uax9]: http://www.unicode.org/reports/tr9/#P2
+pub fn text_direction(text: &str, html: bool) -> Option<Direction> {
+    let mut in_tag = false;
+    let mut in_reference = false;
+    for c in text.chars() {
+        if html {
+            match c {
+                '<' => { in_tag = true; continue; }
+                '>' if in_tag => { in_tag = false; continue; }
+                '&' if !in_tag => { in_reference = true; continue; }
+                ';' if in_reference => { in_reference = false; continue; }
+                _ if in_tag => { continue; }
+                c if in_reference && (c.is_alphanumeric() || c == '#') => {
+                    continue;
+                }
+                _ => { in_reference = false; }
+            }
+        }
+        if is_rtl_char(c) {
+            return Some(Direction::Rtl);
+        } else if c.is_alphabetic() {
+            return Some(Direction::Ltr);
+        }
+    }
+    None
+}
+
+/// Decide the direction of the text.  The language tag is used if it's
+/// given, and otherwise the text is inspected.  It's left-to-right if
+/// neither tells.
+pub fn detect_direction(text: &str, html: bool, lang: Option<&str>)
+                        -> Direction {
+    match lang {
+        Some(lang) if !lang.trim().is_empty() => language_direction(lang),
+        _ => text_direction(text, html).unwrap_or(Direction::Ltr),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, detect_direction, language_direction,
+                text_direction};
+
+    #[test]
+    fn test_language_direction() {
+        assert_eq!(language_direction("ar"), Direction::Rtl);
+        assert_eq!(language_direction("he-IL"), Direction::Rtl);
+        assert_eq!(language_direction("FA_ir"), Direction::Rtl);
+        assert_eq!(language_direction("az-Arab"), Direction::Rtl);
+        assert_eq!(language_direction("ar-Latn"), Direction::Ltr);
+        assert_eq!(language_direction("en-US"), Direction::Ltr);
+        assert_eq!(language_direction("ko"), Direction::Ltr);
+    }
+
+    #[test]
+    fn test_text_direction() {
+        assert_eq!(text_direction("مرحبا بالعالم", false),
+                   Some(Direction::Rtl));
+        assert_eq!(text_direction("  123 שלום", false), Some(Direction::Rtl));
+        assert_eq!(text_direction("Hello שלום", false), Some(Direction::Ltr));
+        assert_eq!(text_direction("12, 34!", false), None);
+        let html = r#"<p class="intro">&nbsp;&#160;مرحبا</p>"#;
+        assert_eq!(text_direction(html, true), Some(Direction::Rtl));
+        assert_eq!(text_direction(html, false), Some(Direction::Ltr));
+        assert_eq!(detect_direction("Hello", false, Some("ar")),
+                   Direction::Rtl);
+        assert_eq!(detect_direction("12", false, None), Direction::Ltr);
+        assert_eq!(detect_direction("שלום", false, Some("")),
+                   Direction::Rtl);
+    }
+}
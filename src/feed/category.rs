@@ -1,16 +1,35 @@
 use std::borrow::{Cow, ToOwned};
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
 
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
 use parser::base::{DecodeResult, XmlElement};
-use schema::{Entity, FromSchemaReader, Mergeable};
+use schema::{Entity, FromSchemaReader, Mergeable, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+/// The scheme URI of tags the user applied, e.g. in the app, to tell them
+/// from categories the publisher specified.  See also
+/// `Metadata::user_tags()`.
+pub const USER_TAG_SCHEME: &'static str = "http://earthreader.org/user-tag/";
 
 /// Category element defined in :rfc:`4287#section-4.2.2` (section 4.2.2).
-#[derive(Default, Debug)]
+///
+/// Categories are compared by their `term` and `scheme_uri`, and ordered by
+/// `term` first.  `label` is only for display, so it's ignored.
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Category {
     /// The required machine-readable identifier string of the cateogry.
     /// It corresponds to ``term`` attribute of :rfc:`4287#section-4.2.2.1` (section 4.2.2.1).
-    pub term: String,
+    ///
+    /// Like `Link::mimetype` it's a `Cow`, so that terms known in advance
+    /// can be borrowed from static strings rather than allocated, while
+    /// parsed terms are owned.  It was a `String` before; build it with
+    /// `.into()` from either a `&'static str` or a `String`.
+    pub term: Cow<'static, str>,
 
     /// The URI that identifies a categorization scheme.  It corresponds to
     /// ``scheme`` attribute of :rfc:`4287#section-4.2.2.2` (section 4.2.2.2).
@@ -29,10 +48,63 @@ pub struct Category {
     pub label: Option<String>,
 }
 
+impl Category {
+    /// The tag the user applied, of `USER_TAG_SCHEME`.
+    pub fn user_tag<T: Into<String>>(term: T) -> Category {
+        Category {
+            term: Cow::Owned(term.into()),
+            scheme_uri: Some(USER_TAG_SCHEME.to_owned()),
+            label: None,
+        }
+    }
+
+    /// Whether it's a tag the user applied rather than a category of the
+    /// publisher.
+    pub fn is_user_tag(&self) -> bool {
+        self.scheme_uri.as_ref().is_some_and(|s| s == USER_TAG_SCHEME)
+    }
+}
+
 impl Entity for Category {
     type Id = str;
+
+    /// The `term`, qualified by the `scheme_uri` if there is, so that
+    /// categories of the same term in different schemes, e.g. a user tag
+    /// and a publisher category, aren't merged into one.
     fn entity_id(&self) -> Cow<str> {
-        Cow::Borrowed(&self.term[..])
+        match self.scheme_uri {
+            Some(ref scheme_uri) => {
+                Cow::Owned(format!("{}\u{0}{}", scheme_uri, self.term))
+            }
+            None => Cow::Borrowed(&self.term[..]),
+        }
+    }
+}
+
+impl PartialEq for Category {
+    fn eq(&self, other: &Category) -> bool {
+        self.term == other.term && self.scheme_uri == other.scheme_uri
+    }
+}
+
+impl Eq for Category { }
+
+impl Hash for Category {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.term.hash(state);
+        self.scheme_uri.hash(state);
+    }
+}
+
+impl PartialOrd for Category {
+    fn partial_cmp(&self, other: &Category) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Category {
+    fn cmp(&self, other: &Category) -> Ordering {
+        (&self.term, &self.scheme_uri).cmp(&(&other.term, &other.scheme_uri))
     }
 }
 
@@ -46,7 +118,7 @@ impl Mergeable for Category {
 
 impl fmt::Display for Category {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.label.as_ref().unwrap_or(&self.term))
+        write!(f, "{}", self.label.as_ref().map_or(&self.term[..], |l| l))
     }
 }
 
@@ -54,7 +126,7 @@ impl FromSchemaReader for Category {
     fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
                                  -> DecodeResult<()>
     {
-        self.term = try!(element.get_attr("term")).to_owned();
+        self.term = Cow::Owned(try!(element.get_attr("term")).to_owned());
         self.scheme_uri = element.get_attr("scheme").ok()
                                  .map(|v| v.to_string());
         self.label = element.get_attr("label").ok().map(|v| v.to_string());
@@ -62,21 +134,84 @@ impl FromSchemaReader for Category {
     }
 }
 
+impl ToSchemaWriter for Category {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let mut element = element.attr("term", &self.term);
+        if let Some(ref scheme_uri) = self.scheme_uri {
+            element = element.attr("scheme", scheme_uri);
+        }
+        if let Some(ref label) = self.label {
+            element = element.attr("label", label);
+        }
+        try!(writer.write(element));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::Category;
 
+    use std::borrow::Cow;
     use std::default::Default;
 
+    use schema::Mergeable;
+
     #[test]
     fn test_category_str() {
-        assert_eq!(Category { term: "rust".to_string(),
+        assert_eq!(Category { term: "rust".into(),
                               ..Default::default() }.to_string(),
                    "rust");
-        assert_eq!(Category { term: "rust".to_string(),
+        assert_eq!(Category { term: "rust".into(),
                               label: Some("Rust".to_string()),
                               ..Default::default() }.to_string(),
                    "Rust");
     }
+
+    #[test]
+    fn test_category_cmp() {
+        let a = Category { term: "rust".into(),
+                           label: Some("Rust".to_string()),
+                           ..Default::default() };
+        let b = Category { term: "rust".into(), ..Default::default() };
+        let c = Category { term: "rust".into(),
+                           scheme_uri: Some("http://example.com/".to_string()),
+                           ..Default::default() };
+        let d = Category { term: "python".into(), ..Default::default() };
+        assert_eq!(a, b);
+        assert!(a != c);
+        let mut categories = [c, a, d];
+        categories.sort();
+        assert_eq!(categories.iter().map(|c| &c.term[..]).collect::<Vec<_>>(),
+                   ["python", "rust", "rust"]);
+        assert_eq!(categories[1].scheme_uri, None);
+    }
+
+    #[test]
+    fn test_category_static_term() {
+        let borrowed = Category { term: "rust".into(), ..Default::default() };
+        assert!(match borrowed.term { Cow::Borrowed(_) => true,
+                                      Cow::Owned(_) => false });
+        let owned = Category { term: "rust".to_owned().into(),
+                               ..Default::default() };
+        assert_eq!(borrowed, owned);
+    }
+
+    #[test]
+    fn test_category_user_tag() {
+        let tag = Category::user_tag("rust");
+        assert!(tag.is_user_tag());
+        let publisher = Category { term: "rust".into(),
+                                   ..Default::default() };
+        assert!(!publisher.is_user_tag());
+        assert!(tag != publisher);
+        let mut categories = vec![publisher.clone()];
+        categories.merge_with(vec![tag.clone(), publisher]);
+        assert_eq!(categories.len(), 2);
+        assert!(categories.contains(&tag));
+    }
 }
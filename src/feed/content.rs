@@ -1,21 +1,46 @@
-use super::Blob;
+use super::{Blob, DebugBody, Direction, detect_direction};
 
 use std::default::Default;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::str::{Utf8Error, from_utf8, from_utf8_unchecked};
 
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+#[cfg(feature = "crawler")]
+use crawler::{self, Backend, Request, fetch_with_retries};
 use mimetype::MimeType;
 use parser::base::{DecodeError, DecodeResult, XmlElement};
-use schema::{FromSchemaReader};
+use sanitizer::{code_language, decode_references, parse_tag};
+use schema::{FromSchemaReader, SchemaResult, ToSchemaWriter, XmlWriter};
 
 /// Content construct defined in :rfc:`4287#section-4.1.3` (section 4.1.3).
-#[derive(Clone, Debug)]
+///
+/// Two contents are equal if both link to the same `source_uri` with the same
+/// `mimetype`, or if both are inline and have the same body.  The mimetype
+/// of inline contents is not compared.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct Content {
     mimetype: MimeType,
     body: Vec<u8>,
     source_uri: Option<String>,
 }
 
+/// A code block in HTML, i.e. `<pre><code>`, which apps can highlight on
+/// their side.  See `Content::code_blocks()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CodeBlock {
+    /// The language its class tells, e.g. `rust` of `language-rust`.  See
+    /// `sanitizer::code_language()`.
+    pub language: Option<String>,
+
+    /// The code, with markup e.g. highlighting already applied stripped
+    /// and character references decoded.
+    pub code: String,
+}
+
 impl Content {
     pub fn new<T>(mimetype: MimeType, body: Vec<u8>,
                   source_uri: Option<T>)
@@ -55,6 +80,112 @@ impl Content {
     pub fn source_uri(&self) -> Option<&str> {
         self.source_uri.as_ref().map(|e| &e[..])
     }
+
+    /// Decide the direction of the content like `Text::direction()` does.
+    /// Binary contents are always left-to-right.
+    pub fn direction(&self, lang: Option<&str>) -> Direction {
+        let html = matches!(self.mimetype, MimeType::Html | MimeType::Xhtml);
+        match self.as_str() {
+            Some(text) if self.is_text() => detect_direction(text, html, lang),
+            _ => Direction::Ltr,
+        }
+    }
+
+    /// Code blocks, i.e. `<code>` elements in `<pre>`, of HTML content in
+    /// the order they appear.  The language is taken from the class of
+    /// `<code>`, or of `<pre>` if `<code>` tells none.  It's empty for
+    /// other than HTML.
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        let html = match (&self.mimetype, self.as_str()) {
+            (&MimeType::Html, Some(html)) |
+            (&MimeType::Xhtml, Some(html)) => html,
+            _ => { return vec![]; }
+        };
+        let mut blocks = vec![];
+        let mut pre: Option<Option<String>> = None;
+        let mut current: Option<CodeBlock> = None;
+        let mut rest = html;
+        while let Some(i) = rest.find('<') {
+            if let Some(ref mut block) = current {
+                block.code.push_str(&decode_references(&rest[..i]));
+            }
+            rest = &rest[i..];
+            let (tag, len) = match parse_tag(rest) {
+                Some(parsed) => parsed,
+                None => {
+                    if let Some(ref mut block) = current {
+                        block.code.push('<');
+                    }
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            rest = &rest[len..];
+            let language = || {
+                tag.attribute("class").and_then(code_language)
+                                      .map(|l| l.to_owned())
+            };
+            match (&tag.name[..], tag.end) {
+                ("pre", false) => { pre = Some(language()); }
+                ("pre", true) => { pre = None; }
+                ("code", false) if current.is_none() => {
+                    if let Some(ref pre_language) = pre {
+                        current = Some(CodeBlock {
+                            language: language().or(pre_language.clone()),
+                            code: String::new(),
+                        });
+                    }
+                }
+                ("code", true) => {
+                    if let Some(block) = current.take() {
+                        blocks.push(block);
+                    }
+                }
+                _ => { }
+            }
+        }
+        blocks
+    }
+
+    /// Fetch the body of the out-of-line content from its `source_uri`
+    /// through the `backend`, and return the content having it inline.
+    /// The inline content is returned as it is.  It fails with
+    /// `Error::TooLarge` if the body is larger than `max_size` bytes, and
+    /// with `Error::UnexpectedType` if the `Content-Type` of the response
+    /// is other than the `mimetype`.
+    #[cfg(feature = "crawler")]
+    pub fn fetch<B>(&self, backend: &B, max_size: Option<usize>)
+                    -> crawler::Result<Content>
+        where B: Backend + ?Sized
+    {
+        let uri = match self.source_uri {
+            Some(ref uri) => uri,
+            None => { return Ok(self.clone()); }
+        };
+        let mut request = Request::new(&uri[..]);
+        // a byte over the limit tells the body is larger than it
+        request.body_limit = max_size.map(|size| size + 1);
+        let response = try!(fetch_with_retries(backend, &request));
+        if response.status < 200 || response.status >= 300 {
+            return Err(crawler::Error::Status(response.status));
+        }
+        if let Some(size) = max_size {
+            if response.body.len() > size {
+                return Err(crawler::Error::TooLarge(size));
+            }
+        }
+        if let Some(content_type) = response.header("Content-Type") {
+            let essence = content_type.split(';').next().unwrap_or("")
+                                      .trim().to_lowercase();
+            if MimeType::from_str(&essence) != Some(self.mimetype.clone()) {
+                return Err(crawler::Error::UnexpectedType(essence));
+            }
+        }
+        Content::new(self.mimetype.clone(), response.body,
+                     Some(&uri[..])).map_err(|e| {
+            crawler::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+    }
 }
 
 impl Blob for Content {
@@ -93,6 +224,40 @@ impl super::HtmlBlob for Content {
     }
 }
 
+/// Deserialized contents are checked the same as `Content::new()` does,
+/// so that text bodies are always valid UTF-8.
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for Content {
+    fn deserialize<D>(deserializer: D) -> Result<Content, D::Error>
+        where D: ::serde::Deserializer<'de>
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        struct RawContent {
+            mimetype: MimeType,
+            body: Vec<u8>,
+            #[serde(default)]
+            source_uri: Option<String>,
+        }
+
+        let raw: RawContent =
+            try!(::serde::Deserialize::deserialize(deserializer));
+        Content::new(raw.mimetype, raw.body, raw.source_uri)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl fmt::Debug for Content {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Content")
+         .field("mimetype", &self.mimetype)
+         .field("body", &DebugBody(&self.body))
+         .field("source_uri", &self.source_uri)
+         .finish()
+    }
+}
+
 impl Default for Content {
     fn default() -> Content {
         Content {
@@ -105,11 +270,24 @@ impl Default for Content {
 
 impl PartialEq for Content {
     fn eq(&self, other: &Content) -> bool {
-        if self.source_uri.is_some() {
-            (self.mimetype == other.mimetype &&
-             self.source_uri.as_ref() == other.source_uri.as_ref())
-        } else {
-            self.body == other.body
+        match (self.source_uri.as_ref(), other.source_uri.as_ref()) {
+            (Some(a), Some(b)) => self.mimetype == other.mimetype && a == b,
+            (None, None) => self.body == other.body,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Content { }
+
+impl Hash for Content {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.source_uri {
+            Some(ref source_uri) => {
+                self.mimetype.hash(state);
+                source_uri.hash(state);
+            }
+            None => { self.body.hash(state); }
         }
     }
 }
@@ -141,6 +319,115 @@ impl FromSchemaReader for Content {
     }
 }
 
+impl ToSchemaWriter for Content {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let type_ = match self.mimetype {
+            MimeType::Text => "text",
+            MimeType::Html => "html",
+            MimeType::Xhtml => "xhtml",
+            MimeType::Other(ref mimetype) => mimetype,
+        };
+        let mut element = element.attr("type", type_);
+        if let Some(ref source_uri) = self.source_uri {
+            element = element.attr("src", source_uri);
+        }
+        try!(writer.write(element));
+        // TODO: if mimetype is binary, content should be encoded by base64
+        if let Some(body) = self.as_str() {
+            try!(writer.write(XmlEvent::characters(body)));
+        }
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_cmp {
+    use super::{CodeBlock, Content};
+
+    use std::collections::HashSet;
+
+    use mimetype::MimeType;
+
+    #[test]
+    fn test_content_eq() {
+        let none: Option<&str> = None;
+        let a = Content::new(MimeType::Text, b"Hello".to_vec(), none).unwrap();
+        let b = Content::new(MimeType::Html, b"Hello".to_vec(), none).unwrap();
+        let c = Content::new(MimeType::Html, vec![],
+                             Some("http://example.com/")).unwrap();
+        let d = Content::new(MimeType::Text, vec![],
+                             Some("http://example.com/")).unwrap();
+        let e = Content::new(MimeType::Text, vec![], none).unwrap();
+        assert_eq!(a, b);
+        assert!(c != d);
+        assert!(c != e);
+        assert!(e != c);
+        let set: HashSet<_> = vec![a, b, c, d, e].into_iter().collect();
+        assert_eq!(set.len(), 4);
+    }
+
+    #[test]
+    fn test_content_code_blocks() {
+        let none: Option<&str> = None;
+        let html = "<p><code>inline</code></p>\
+                    <pre class=\"lang-sh\"><code>$ cargo build</code></pre>\
+                    <pre><code class=\"language-rust\"><span class=k>fn\
+                    </span> a() -&gt; u8 { 1 &lt; 2 }</code></pre>";
+        let content = Content::new(MimeType::Html, html.as_bytes().to_vec(),
+                                   none).unwrap();
+        assert_eq!(content.code_blocks(), [
+            CodeBlock { language: Some("sh".to_owned()),
+                        code: "$ cargo build".to_owned() },
+            CodeBlock { language: Some("rust".to_owned()),
+                        code: "fn a() -> u8 { 1 < 2 }".to_owned() },
+        ]);
+        let text = Content::new(MimeType::Text, html.as_bytes().to_vec(),
+                                none).unwrap();
+        assert!(text.code_blocks().is_empty());
+    }
+
+    #[test]
+    fn test_content_debug() {
+        let none: Option<&str> = None;
+        let huge = Content::new(MimeType::Html, vec![b'a'; 1000], none)
+            .unwrap();
+        assert_eq!(format!("{:?}", huge),
+                   concat!("Content { mimetype: Html, body: <1000 bytes>, ",
+                           "source_uri: None }"));
+    }
+
+    #[cfg(feature = "crawler")]
+    #[test]
+    fn test_content_fetch() {
+        use crawler::Error;
+        use crawler::test::MockBackend;
+        use feed::Blob;
+
+        let backend = MockBackend::default()
+            .with("http://example.com/a", 200, b"<p>Hi</p>")
+            .with("http://example.com/gone", 410, b"");
+        let none: Option<&str> = None;
+        let inline = Content::new(MimeType::Html, b"<p>Hey</p>".to_vec(),
+                                  none).unwrap();
+        assert_eq!(inline.fetch(&backend, None).unwrap().as_str(),
+                   Some("<p>Hey</p>"));
+        let linked = Content::new(MimeType::Html, vec![],
+                                  Some("http://example.com/a")).unwrap();
+        let fetched = linked.fetch(&backend, Some(9)).unwrap();
+        assert_eq!(fetched.as_str(), Some("<p>Hi</p>"));
+        assert_eq!(fetched.source_uri(), Some("http://example.com/a"));
+        assert_err!(linked.fetch(&backend, Some(8)),
+                    Error::TooLarge(8) => { });
+        let gone = Content::new(MimeType::Html, vec![],
+                                Some("http://example.com/gone")).unwrap();
+        assert_err!(gone.fetch(&backend, None), Error::Status(410) => { });
+    }
+}
+
 #[cfg(nocompile)]
 mod test {
     use super::{Content, MimeType};
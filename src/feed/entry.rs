@@ -1,22 +1,42 @@
 use std::borrow::Cow;
+use std::cmp::{Ordering, max};
 use std::default::Default;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
 use chrono::{DateTime, FixedOffset};
+use xml::writer::XmlEvent;
 
+#[cfg(feature = "export")]
+use export::SharePayload;
+use html::ForHtml;
+use mimetype::MimeType;
 use parser::base::{DecodeResult, XmlElement, XmlName};
-use schema::{DocumentElement, Entity, FromSchemaReader, Mergeable};
+use schema::{DocumentElement, Entity, FromSchemaReader, Mergeable,
+             SchemaResult, ToSchemaWriter, XmlWriter};
 
-use util::set_default;
+use render::DEFAULT_SOFT_WRAP;
+use sanitizer::{ImageSizes, SanitizerPreset, escape, size_images, soft_wrap};
+use stats::FeedStats;
+use util::{IdGenerator, set_default};
 
-use super::{ATOM_XMLNS, MARK_XMLNS, Content, Mark, Metadata, Source, Text,
-            parse_datetime};
+use super::{ATOM_XMLNS, GEORSS_XMLNS, MARK_XMLNS, THREADING_XMLNS, Blob,
+            Content, Direction, Feed, GeoPoint, LinkIteratorExt, Mark,
+            MarkKind, Metadata, Person, Provenance, Replies, Source, Text,
+            parse_datetime, write_datetime};
 
 /// Represent an individual entry, acting as a container for metadata and data
 /// associated with the entry.  It corresponds to `atom:entry` element of
 /// :rfc:`4287#section-4.1.2` (section 4.1.2).
-#[derive(Default)]
+///
+/// Entries are compared by their `id` and `updated_at`, i.e. two entries are
+/// equal if they are the same revision of the same entry.  They are ordered
+/// by `updated_at` first, and then `id`.
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Entry {
     pub metadata: Metadata,
 
@@ -26,6 +46,8 @@ pub struct Entry {
     /// or first availability of the resource.
     /// It corresponds to `atom:published` element of :rfc:`4287#section-4.2.9`
     /// (section 4.2.9).
+    #[cfg_attr(feature = "serde",
+               serde(default, with = "::codecs::serde::rfc3339_option"))]
     pub published_at: Option<DateTime<FixedOffset>>,
 
     /// The text field that conveys a short summary, abstract, or excerpt of
@@ -55,6 +77,25 @@ pub struct Entry {
 
     /// Whether and when it's starred or unstarred.
     pub starred: Mark,
+
+    /// The location the entry is about, if the feed provides it.
+    pub location: Option<GeoPoint>,
+
+    /// The total number of replies to the entry, e.g. comments, if the
+    /// feed tells.  It corresponds to `thr:total` element of
+    /// :rfc:`4685#section-5` (section 5).  See also `replies()`.
+    pub reply_count: Option<u64>,
+
+    /// When and from where the entry was first and last crawled.  It's
+    /// recorded by `Stage::refresh()`, and `None` for entries which were
+    /// never crawled e.g. ones created locally.
+    pub seen: Option<Provenance>,
+
+    /// The preset the HTML of `content` and `summary` was sanitized with
+    /// when it was stored, if it was; see `sanitize()`.  Such HTML isn't
+    /// filtered again when it's rendered with the same preset.  It
+    /// corresponds to `mark:sanitized` element.
+    pub sanitized: Option<SanitizerPreset>,
 }
 
 impl Deref for Entry {
@@ -77,6 +118,291 @@ impl Entry {
     pub fn new(id: String, title: Text, updated_at: DateTime<FixedOffset>) -> Entry {
         Entry::new_inherited(id, title, updated_at)
     }
+
+    /// Create the entry of a new ID the `generator` makes, e.g. for notes
+    /// created locally.  See also the `util::idgen` module.
+    pub fn generate<G>(generator: &G, title: Text,
+                       updated_at: DateTime<FixedOffset>) -> Entry
+        where G: IdGenerator + ?Sized
+    {
+        Entry::new(generator.generate(), title, updated_at)
+    }
+
+    /// The mark of the given kind.
+    pub fn mark(&self, kind: MarkKind) -> &Mark {
+        match kind {
+            MarkKind::Read => &self.read,
+            MarkKind::Starred => &self.starred,
+        }
+    }
+
+    pub fn mark_mut(&mut self, kind: MarkKind) -> &mut Mark {
+        match kind {
+            MarkKind::Read => &mut self.read,
+            MarkKind::Starred => &mut self.starred,
+        }
+    }
+
+    /// When the entry was first crawled, e.g. to tell entries new since
+    /// the last visit even if the feed rewrites their `published_at`.
+    pub fn first_seen_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.seen.as_ref().map(|seen| seen.first_seen_at)
+    }
+
+    /// When the entry was last crawled, i.e. it was still in the feed.
+    pub fn last_seen_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.seen.as_ref().map(|seen| seen.last_seen_at)
+    }
+
+    /// Estimate how long it takes to read the entry at the given reading
+    /// speed, e.g. to display in entry lists.  Words of `content` are
+    /// counted, or of `summary` if there's no content; see
+    /// `Blob::word_count()`.  The body is counted on every call, so apps
+    /// which list many entries may keep estimates by entry ids.
+    pub fn reading_time(&self, words_per_minute: u32) -> Duration {
+        let words = self.body_word_count() as u64;
+        let wpm = max(words_per_minute, 1) as u64;
+        Duration::from_secs((words * 60).div_ceil(wpm))
+    }
+
+    fn body_word_count(&self) -> usize {
+        match (&self.content, &self.summary) {
+            (Some(content), _) => content.word_count(),
+            (None, Some(summary)) => summary.word_count(),
+            (None, None) => 0,
+        }
+    }
+
+    /// The title, the URL, the summary and categories to share the entry
+    /// to other apps, e.g. through share sheets of mobile platforms.
+    #[cfg(feature = "export")]
+    pub fn share_payload(&self) -> SharePayload { SharePayload::new(self) }
+
+    /// Sanitize the HTML of `content` and `summary` with the `preset`, and
+    /// record it to `sanitized`.  It returns `false` and does nothing if
+    /// it's already sanitized with the preset.  Elements a stricter preset
+    /// dropped can't be restored by sanitizing with a looser one.
+    pub fn sanitize(&mut self, preset: SanitizerPreset) -> bool {
+        if self.sanitized == Some(preset) {
+            return false;
+        }
+        let filtered = match self.content {
+            Some(ref content) => match (content.mimetype(), content.as_str()) {
+                (MimeType::Html, Some(html)) |
+                (MimeType::Xhtml, Some(html)) => {
+                    Content::new(content.mimetype(),
+                                 preset.filter(html).to_string().into_bytes(),
+                                 content.source_uri()).ok()
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        if filtered.is_some() {
+            self.content = filtered;
+        }
+        if let Some(Text::Html(ref mut html)) = self.summary {
+            let filtered = preset.filter(html).to_string();
+            *html = filtered;
+        }
+        self.sanitized = Some(preset);
+        true
+    }
+
+    /// Make images in the HTML of `content` and `summary` have both
+    /// `width` and `height` by sizes the `sizes` know, so that layouts
+    /// don't jump while they're loaded.  It returns whether anything is
+    /// changed.  See `sanitizer::size_images()`.
+    pub fn size_images<S: ImageSizes + ?Sized>(&mut self, sizes: &S) -> bool {
+        let mut changed = false;
+        let sized = match self.content {
+            Some(ref content) => match (content.mimetype(), content.as_str()) {
+                (MimeType::Html, Some(html)) |
+                (MimeType::Xhtml, Some(html)) => {
+                    let sized = size_images(html, sizes);
+                    if sized == html {
+                        None
+                    } else {
+                        Content::new(content.mimetype(), sized.into_bytes(),
+                                     content.source_uri()).ok()
+                    }
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        if sized.is_some() {
+            self.content = sized;
+            changed = true;
+        }
+        if let Some(Text::Html(ref mut html)) = self.summary {
+            let sized = size_images(html, sizes);
+            if sized != *html {
+                *html = sized;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Where replies to the entry are and how many, i.e. its
+    /// `LinkRel::Replies` links and `reply_count`.
+    pub fn replies(&self) -> Replies {
+        Replies::from_links(&self.links[..], self.reply_count)
+    }
+
+    /// Decide the direction of the entry to render.  It's decided by
+    /// `content` if there is, and otherwise `summary` or `title`.
+    pub fn direction(&self, lang: Option<&str>) -> Direction {
+        if let Some(ref content) = self.content {
+            content.direction(lang)
+        } else if let Some(ref summary) = self.summary {
+            summary.direction(lang)
+        } else {
+            self.title.direction(lang)
+        }
+    }
+
+    /// The authors of the entry, following the inheritance of
+    /// :rfc:`4287#section-4.2.1` (section 4.2.1): if the entry has no
+    /// authors, authors of its `source` apply, and then of the `feed`
+    /// containing it.
+    pub fn display_authors<'a>(&'a self, feed: Option<&'a Feed>)
+                               -> &'a [Person] {
+        if !self.authors.is_empty() {
+            return &self.authors;
+        }
+        if let Some(ref source) = self.source {
+            if !source.authors.is_empty() {
+                return &source.authors;
+            }
+        }
+        match feed {
+            Some(feed) => &feed.authors,
+            None => &[],
+        }
+    }
+
+    /// The title to display in the list of entries of its feed, without
+    /// the prefix and the suffix every title of the feed has, e.g. the
+    /// name of the blog; see `FeedStats::title_prefix`.  The cleanup is
+    /// opt-in and only for display: `title` isn't changed.  The title is
+    /// returned as it is if nothing would be left.
+    pub fn display_title(&self, stats: &FeedStats) -> Text {
+        if stats.title_prefix.is_empty() && stats.title_suffix.is_empty() {
+            return self.title.clone();
+        }
+        let title = self.title.as_plain_text();
+        let mut stripped = &title[..];
+        if stripped.starts_with(&stats.title_prefix[..]) {
+            stripped = &stripped[stats.title_prefix.len()..];
+        }
+        if stripped.ends_with(&stats.title_suffix[..]) {
+            stripped = &stripped[..stripped.len() -
+                                   stats.title_suffix.len()];
+        }
+        let stripped = stripped.trim();
+        if stripped.is_empty() {
+            return self.title.clone();
+        }
+        Text::plain(stripped)
+    }
+}
+
+/// Write the blob as HTML.  Plain texts are escaped, and HTML is filtered
+/// by the `preset`, or written as it is if it's `None`.
+fn write_blob_html<B: Blob>(f: &mut fmt::Formatter, blob: &B,
+                            preset: Option<SanitizerPreset>, wrap: usize)
+                            -> fmt::Result {
+    match (blob.mimetype(), blob.as_str(), preset) {
+        (MimeType::Html, Some(html), Some(preset)) |
+        (MimeType::Xhtml, Some(html), Some(preset)) => {
+            write!(f, "{}", preset.filter(html))
+        }
+        (MimeType::Html, Some(html), None) |
+        (MimeType::Xhtml, Some(html), None) => write!(f, "{}", html),
+        (MimeType::Text, Some(text), _) => {
+            write!(f, "{}", soft_wrap(text, false, wrap))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl<'a> fmt::Display for ForHtml<'a, Entry> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_html(f, None, SanitizerPreset::default(),
+                        DEFAULT_SOFT_WRAP)
+    }
+}
+
+impl Entry {
+    /// Write the entry as an HTML `<article>`, with the `time` element
+    /// after its title if any.  Its HTML is filtered by the `preset`, and
+    /// plain texts get break opportunities every `wrap` characters without
+    /// whitespace (0 for none).  See `render::entry_html()`.
+    #[doc(hidden)]
+    pub fn write_html(&self, f: &mut fmt::Formatter,
+                      time: Option<&fmt::Display>, preset: SanitizerPreset,
+                      wrap: usize) -> fmt::Result {
+        try!(write!(f, "<article"));
+        if self.direction(None) == Direction::Rtl {
+            try!(write!(f, " dir=\"{}\"", Direction::Rtl));
+        }
+        try!(write!(f, "><h1>"));
+        let permalink = self.links.iter().permalink();
+        if let Some(link) = permalink {
+            try!(write!(f, "<a href=\"{}\">", escape(&link.uri, true)));
+        }
+        try!(write_blob_html(f, &self.title, Some(SanitizerPreset::Strict),
+                             wrap));
+        if permalink.is_some() {
+            try!(write!(f, "</a>"));
+        }
+        try!(write!(f, "</h1>"));
+        if let Some(time) = time {
+            try!(write!(f, "{}", time));
+        }
+        // HTML sanitized with the preset when it was stored is kept as is
+        let preset = if self.sanitized == Some(preset) { None }
+                     else { Some(preset) };
+        if let Some(ref content) = self.content {
+            try!(write!(f, "<div>"));
+            try!(write_blob_html(f, content, preset, wrap));
+            try!(write!(f, "</div>"));
+        } else if let Some(ref summary) = self.summary {
+            try!(write!(f, "<div>"));
+            try!(write_blob_html(f, summary, preset, wrap));
+            try!(write!(f, "</div>"));
+        }
+        write!(f, "</article>")
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.updated_at == other.updated_at && self.id == other.id
+    }
+}
+
+impl Eq for Entry { }
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.updated_at.hash(state);
+        self.id.hash(state);
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        (&self.updated_at, &self.id).cmp(&(&other.updated_at, &other.id))
+    }
 }
 
 impl DocumentElement for Entry {
@@ -109,12 +435,75 @@ impl FromSchemaReader for Entry {
             (Some(MARK_XMLNS), "starred") => {
                 self.starred = try!(FromSchemaReader::build_from(child));
             }
+            (Some(MARK_XMLNS), "seen") => {
+                self.seen = Some(try!(FromSchemaReader::build_from(child)));
+            }
+            (Some(MARK_XMLNS), "sanitized") => {
+                self.sanitized = try!(child.read_whole_text()).parse().ok();
+            }
+            (Some(GEORSS_XMLNS), "point") => {
+                self.location = Some(try!(FromSchemaReader::build_from(child)));
+            }
+            (Some(THREADING_XMLNS), "total") => {
+                let text = try!(child.read_whole_text());
+                self.reply_count = text.trim().parse().ok();
+            }
             _ => { return self.metadata.match_child(name, child); }
         }
         Ok(())
     }
 }
 
+impl ToSchemaWriter for Entry {
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        try!(self.metadata.write_children(writer));
+        if let Some(ref published_at) = self.published_at {
+            try!(write_datetime(writer, "published", published_at));
+        }
+        if let Some(ref summary) = self.summary {
+            try!(summary.write_to(writer, XmlEvent::start_element("summary")));
+        }
+        if let Some(ref content) = self.content {
+            try!(content.write_to(writer, XmlEvent::start_element("content")));
+        }
+        if let Some(ref source) = self.source {
+            try!(source.write_to(writer, XmlEvent::start_element("source")));
+        }
+        if let Some(ref location) = self.location {
+            try!(location.write_to(writer,
+                                   XmlEvent::start_element("georss:point")));
+        }
+        if let Some(count) = self.reply_count {
+            let element = XmlEvent::start_element("thr:total")
+                                   .ns("thr", THREADING_XMLNS);
+            try!(writer.write(element));
+            try!(writer.write(XmlEvent::characters(&count.to_string())));
+            try!(writer.write(XmlEvent::end_element()));
+        }
+        // unmarked marks that were never touched don't need to be stored
+        if self.read != Mark::default() {
+            try!(self.read.write_to(writer,
+                                    XmlEvent::start_element("mark:read")));
+        }
+        if self.starred != Mark::default() {
+            try!(self.starred.write_to(writer,
+                                       XmlEvent::start_element("mark:starred")));
+        }
+        if let Some(ref seen) = self.seen {
+            try!(seen.write_to(writer, XmlEvent::start_element("mark:seen")));
+        }
+        if let Some(preset) = self.sanitized {
+            try!(writer.write(XmlEvent::start_element("mark:sanitized")
+                                       .ns("mark", MARK_XMLNS)));
+            try!(writer.write(XmlEvent::characters(preset.name())));
+            try!(writer.write(XmlEvent::end_element()));
+        }
+        Ok(())
+    }
+}
+
 impl Entity for Entry {
     type Id = str;
     fn entity_id(&self) -> Cow<str> {
@@ -122,4 +511,194 @@ impl Entity for Entry {
     }
 }
 
-impl_mergeable!(Entry, read, starred);
+impl_mergeable!(Entry, read, starred, location, seen);
+
+
+#[cfg(test)]
+mod test {
+    use super::Entry;
+
+    use std::collections::HashSet;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Category, Content, Direction, Feed, Link, Person, Source,
+               Text};
+    use html::ToHtml;
+    use schema::{Mergeable, read, write};
+    use stats::FeedStats;
+
+    #[test]
+    fn test_entry_cmp() {
+        let t1 = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let t2 = FixedOffset::east(9 * 3600).ymd(2013, 11, 7).and_hms(0, 0, 0);
+        let a = Entry::new("urn:a".to_string(), Text::plain("A"), t2);
+        let b = Entry::new("urn:b".to_string(), Text::plain("B"), t1);
+        let c = Entry::new("urn:c".to_string(), Text::plain("C"), t1);
+        let a2 = Entry::new("urn:a".to_string(), Text::plain("A'"), t2);
+        assert!(a == a2);
+        assert!(b != c);
+        let mut entries = vec![a, c, b];
+        entries.sort();
+        assert_eq!(entries.iter().map(|e| &e.id[..]).collect::<Vec<_>>(),
+                   ["urn:b", "urn:c", "urn:a"]);
+        let set: HashSet<_> = entries.into_iter().chain(Some(a2)).collect();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn test_entry_user_tags() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_string(), Text::plain("A"), t);
+        entry.categories.push(Category { term: "rust".into(),
+                                         ..Default::default() });
+        assert!(entry.add_user_tag("rust"));
+        assert!(!entry.add_user_tag("rust"));
+        assert!(entry.add_user_tag("later"));
+        let terms = |c: Vec<&Category>| {
+            c.into_iter().map(|c| c.term.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(terms(entry.user_tags()), ["rust", "later"]);
+        assert_eq!(terms(entry.publisher_categories()), ["rust"]);
+
+        let mut newer = entry.clone();
+        assert!(newer.remove_user_tag("later"));
+        assert!(!newer.remove_user_tag("later"));
+        newer.categories.push(Category { term: "later".into(),
+                                         ..Default::default() });
+        newer.categories.merge_with(entry.categories.clone());
+        assert_eq!(newer.categories.len(), 4);
+        assert_eq!(newer.user_tags().len(), 2);
+        assert_eq!(newer.publisher_categories().len(), 2);
+    }
+
+    #[test]
+    fn test_entry_direction() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_string(),
+                                   Text::plain("Hello"), t);
+        assert_eq!(entry.direction(None), Direction::Ltr);
+        assert_eq!(entry.direction(Some("he")), Direction::Rtl);
+        entry.links.push(Link::new("http://example.com/?a=1&b=2"));
+        assert_html!(entry,
+                     "<article><h1><a href=\"http://example.com/?a=1&amp;b=2\">\
+                      Hello</a></h1></article>");
+        entry.links.clear();
+        entry.content = Content::from_str("html",
+                                          "<p>مرحبا &amp; 1</p>".to_owned(),
+                                          None::<String>);
+        assert_eq!(entry.direction(None), Direction::Rtl);
+        assert_html!(entry,
+                     "<article dir=\"rtl\"><h1>Hello</h1>\
+                      <div><p>مرحبا &amp; 1</p></div></article>");
+        entry.content = None;
+        entry.summary = Some(Text::plain("שלום <world>"));
+        assert_html!(entry,
+                     "<article dir=\"rtl\"><h1>Hello</h1>\
+                      <div>שלום &lt;world&gt;</div></article>");
+    }
+
+    #[test]
+    fn test_entry_reading_time() {
+        use std::time::Duration;
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("A"), t);
+        assert_eq!(entry.reading_time(200), Duration::from_secs(0));
+        entry.summary = Some(Text::plain("one two three"));
+        assert_eq!(entry.reading_time(180), Duration::from_secs(1));
+        let words = vec!["word"; 400].join(" ");
+        entry.content = Content::from_str(
+            "html", format!("<p>{}</p><script>var a, b;</script>", words),
+            None::<String>);
+        assert_eq!(entry.reading_time(200), Duration::from_secs(120));
+        assert_eq!(entry.reading_time(0), Duration::from_secs(400 * 60));
+        entry.content = Content::from_str("text", "日本語です".to_owned(),
+                                          None::<String>);
+        assert_eq!(entry.reading_time(300), Duration::from_secs(1));
+        assert_eq!(entry.clone().reading_time(60), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_entry_sync() {
+        // entries and feeds can be shared by reference across threads
+        fn assert_sync<T: Sync>() { }
+        assert_sync::<Entry>();
+        assert_sync::<Feed>();
+    }
+
+    #[test]
+    fn test_entry_reply_count() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("A"), t);
+        entry.reply_count = Some(12);
+        let mut buf = vec![];
+        unwrap!(write(&entry, &mut buf));
+        assert!(String::from_utf8(buf.clone()).unwrap()
+                       .contains("<thr:total"));
+        let written: Entry = unwrap!(read(&buf[..]));
+        assert_eq!(written.replies().count, Some(12));
+    }
+
+    #[test]
+    fn test_entry_display_authors() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let person = |name: &str| Person {
+            name: name.to_owned(), uri: None, email: None,
+        };
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        feed.authors.push(person("Feed Author"));
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("A"), t);
+        assert!(entry.display_authors(None).is_empty());
+        assert_eq!(entry.display_authors(Some(&feed)),
+                   [person("Feed Author")]);
+        let mut source = Source::new("urn:source".to_owned(),
+                                     Text::plain("Source"), t);
+        source.authors.push(person("Source Author"));
+        entry.source = Some(source);
+        assert_eq!(entry.display_authors(Some(&feed)),
+                   [person("Source Author")]);
+        entry.authors.push(person("Entry Author"));
+        assert_eq!(entry.display_authors(Some(&feed)),
+                   [person("Entry Author")]);
+    }
+
+    #[test]
+    fn test_entry_display_title() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let feed_of = |titles: &[Text]| {
+            let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+            for (i, title) in titles.iter().enumerate() {
+                feed.entries.push(Entry::new(format!("urn:{}", i),
+                                             title.clone(), t));
+            }
+            feed
+        };
+        let feed = feed_of(&[Text::plain("Blog: Hello – Example Site"),
+                             Text::html("Blog: <b>Help</b> – Example Site"),
+                             Text::plain("Blog: Rust – Example Site")]);
+        let stats = FeedStats::new(&feed);
+        assert_eq!(stats.title_prefix, "Blog: ");
+        assert_eq!(stats.title_suffix, " – Example Site");
+        assert_eq!(feed.entries[0].display_title(&stats),
+                   Text::plain("Hello"));
+        assert_eq!(feed.entries[1].display_title(&stats),
+                   Text::plain("Help"));
+        assert_eq!(feed.entries[0].title,
+                   Text::plain("Blog: Hello – Example Site"));
+        let other = Entry::new("urn:other".to_owned(),
+                               Text::plain("Blog:  – Example Site"), t);
+        assert_eq!(other.display_title(&stats), other.title);
+
+        // titles which just begin with the same word aren't cut
+        let feed = feed_of(&[Text::plain("Rust 1.0 released"),
+                             Text::plain("Rust in production"),
+                             Text::plain("Rust - a retrospective")]);
+        let stats = FeedStats::new(&feed);
+        assert_eq!(stats.title_prefix, "");
+        assert_eq!(stats.title_suffix, "");
+        assert_eq!(feed.entries[2].display_title(&stats),
+                   Text::plain("Rust - a retrospective"));
+        let feed = feed_of(&[Text::plain("A: B"), Text::plain("A: C")]);
+        assert_eq!(FeedStats::new(&feed).title_prefix, "");
+    }
+}
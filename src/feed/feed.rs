@@ -1,13 +1,20 @@
+use std::cmp::Ordering;
 use std::default::Default;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::ops::{Deref, DerefMut};
 
 use chrono::{DateTime, FixedOffset};
+use url::Url;
+use xml::writer::events::{StartElementBuilder, XmlEvent};
 
 use parser::base::{DecodeResult, XmlElement, XmlName};
-use schema::{DocumentElement, FromSchemaReader, Mergeable};
+use schema::{DocumentElement, FromSchemaReader, Mergeable, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+use util::IdGenerator;
 
-use super::{ATOM_XMLNS, Entry, Source, Text};
+use super::{ATOM_XMLNS, MARK_XMLNS, Blob, Entry, LinkIteratorExt, Source,
+            Text};
 
 
 /// Atom feed document, acting as a container for metadata and data associated
@@ -15,7 +22,11 @@ use super::{ATOM_XMLNS, Entry, Source, Text};
 ///
 /// It corresponds to ``atom:feed`` element of :rfc:`4287#section-4.1.1`
 /// (section 4.1.1).
-#[derive(Default)]
+///
+/// Like `Entry`, feeds are compared by their `id` and `updated_at`, and
+/// ordered by `updated_at` first, and then `id`.  Entries are not compared.
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Feed {
     pub source: Source,
 
@@ -49,6 +60,80 @@ impl Feed {
     {
         Feed::new_inherited(id.into(), title, updated_at)
     }
+
+    /// Create the feed of a new ID the `generator` makes, e.g. for a
+    /// collection of saved pages.  See also the `util::idgen` module.
+    pub fn generate<G>(generator: &G, title: Text,
+                       updated_at: DateTime<FixedOffset>) -> Feed
+        where G: IdGenerator + ?Sized
+    {
+        Feed::new(generator.generate(), title, updated_at)
+    }
+
+    /// Copy authors into entries which have no authors, from their
+    /// `source` or the feed, as `Entry::display_authors()` resolves.
+    pub fn inherit_authors(&mut self) {
+        let authors = self.source.authors.clone();
+        for entry in self.entries.iter_mut() {
+            if !entry.authors.is_empty() {
+                continue;
+            }
+            let inherited = match entry.source {
+                Some(ref source) if !source.authors.is_empty() => {
+                    source.authors.clone()
+                }
+                _ => authors.clone(),
+            };
+            entry.authors = inherited;
+        }
+    }
+
+    /// The title to display, which falls back to `subtitle`, and then the
+    /// host of the permalink if `title` is empty.  The last resort is `id`.
+    pub fn display_title(&self) -> Text {
+        let is_blank = |t: &Text| {
+            t.as_str().is_none_or(|s| s.trim().is_empty())
+        };
+        if !is_blank(&self.title) {
+            return self.title.clone();
+        }
+        if let Some(ref subtitle) = self.subtitle {
+            if !is_blank(subtitle) {
+                return subtitle.clone();
+            }
+        }
+        let host = self.links.iter().permalink()
+                       .and_then(|l| Url::parse(&l.uri).ok())
+                       .and_then(|u| u.host_str().map(|h| h.to_owned()));
+        Text::plain(host.unwrap_or_else(|| self.id.clone()))
+    }
+}
+
+impl PartialEq for Feed {
+    fn eq(&self, other: &Feed) -> bool {
+        self.updated_at == other.updated_at && self.id == other.id
+    }
+}
+
+impl Eq for Feed { }
+
+impl Hash for Feed {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.updated_at.hash(state);
+        self.id.hash(state);
+    }
+}
+
+impl PartialOrd for Feed {
+    fn partial_cmp(&self, other: &Feed) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Feed {
+    fn cmp(&self, other: &Feed) -> Ordering {
+        (&self.updated_at, &self.id).cmp(&(&other.updated_at, &other.id))
+    }
 }
 
 impl DocumentElement for Feed {
@@ -71,6 +156,28 @@ impl FromSchemaReader for Feed {
     }
 }
 
+impl ToSchemaWriter for Feed {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.ns("mark", MARK_XMLNS)));
+        try!(self.write_children(writer));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        try!(self.source.write_children(writer));
+        for entry in self.entries.iter() {
+            try!(entry.write_to(writer, XmlEvent::start_element("entry")));
+        }
+        Ok(())
+    }
+}
+
 impl_mergeable!(Feed, source, entries);
 
 
@@ -81,18 +188,20 @@ mod test {
     use std::default::Default;
     use std::io;
 
-    use chrono::{TimeZone, UTC};
+    use chrono::{FixedOffset, TimeZone, UTC};
     use xml;
 
-    use feed::{Link, Person, Text};
+    use feed::{GeoPoint, Link, Person, Text};
     use parser::base::NestedEventReader;
     use parser::base::NestedEvent::Nested;
+    use schema;
     use schema::FromSchemaReader;
 
     fn fx_feed() -> Feed {
         read_feed(r##"
         <feed xmlns="http://www.w3.org/2005/Atom"
-              xmlns:mark="http://earthreader.org/mark/">
+              xmlns:mark="http://earthreader.org/mark/"
+              xmlns:georss="http://www.georss.org/georss">
             <title>Example Feed</title>
             <link href="http://example.org/"/>
             <updated>2003-12-13T18:30:02Z</updated>
@@ -110,6 +219,7 @@ mod test {
                 <summary>Some text.</summary>
                 <author><name>Jane Doe</name></author>
                 <mark:read updated="2013-11-06T14:36:00Z">true</mark:read>
+                <georss:point>45.256 -71.92</georss:point>
             </entry>
             <entry>
                 <title>Danger, Will Robinson!</title>
@@ -137,6 +247,34 @@ mod test {
         feed
     }
 
+    #[test]
+    fn test_feed_write() {
+        let feed = fx_feed();
+        let mut buf = vec![];
+        schema::write(&feed, &mut buf).unwrap();
+        let written: Feed = schema::read(&buf[..]).unwrap();
+        assert_eq!(written.id, feed.id);
+        assert_eq!(written.title, feed.title);
+        assert_eq!(written.links, feed.links);
+        assert_eq!(written.updated_at, feed.updated_at);
+        assert_eq!(written.authors, feed.authors);
+        assert_eq!(written.rights, feed.rights);
+        assert_eq!(written.categories.iter().map(|c| &c.term[..])
+                          .collect::<Vec<_>>(),
+                   ["technology", "business"]);
+        assert_eq!(written.entries.len(), 2);
+        for (a, b) in written.entries.iter().zip(feed.entries.iter()) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.title, b.title);
+            assert_eq!(a.links, b.links);
+            assert_eq!(a.summary, b.summary);
+            assert_eq!(a.authors, b.authors);
+            assert_eq!(a.read, b.read);
+            assert_eq!(a.location, b.location);
+        }
+        assert!(written.entries[0].read.marked);
+    }
+
     #[test]
     fn test_feed_read() {
         let feed = fx_feed();
@@ -168,6 +306,8 @@ mod test {
                    UTC.ymd(2003, 12, 13).and_hms(18, 30, 2));
         assert_eq!(entries[0].summary, Some(Text::plain("Some text.")));
         assert_eq!(&entries[0].authors[..], [Person::new("Jane Doe")]);
+        assert_eq!(entries[0].location, GeoPoint::new(45.256, -71.92));
+        assert!(entries[1].location.is_none());
         assert_eq!(entries[1].title, Text::plain("Danger, Will Robinson!"));
         assert_eq!(&entries[1].links[..],
                    [Link::new("http://example.org/2003/12/13/lost")]);
@@ -177,4 +317,18 @@ mod test {
                    UTC.ymd(2003, 12, 13).and_hms(18, 30, 2));
         assert_eq!(entries[1].summary, Some(Text::plain("Don't Panic!")));
     }
+
+    #[test]
+    fn test_feed_display_title() {
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 UTC.ymd(2013, 11, 6).and_hms(14, 36, 0)
+                                    .with_timezone(&FixedOffset::east(0)));
+        assert_eq!(feed.display_title(), Text::plain("Test"));
+        feed.title = Text::html("  ");
+        assert_eq!(feed.display_title(), Text::plain("urn:earthreader:test"));
+        feed.links.push(Link::new("http://earthreader.org/blog/"));
+        assert_eq!(feed.display_title(), Text::plain("earthreader.org"));
+        feed.subtitle = Some(Text::html("<b>Subtitle</b>"));
+        assert_eq!(feed.display_title(), Text::html("<b>Subtitle</b>"));
+    }
 }
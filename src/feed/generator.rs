@@ -1,17 +1,25 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::io;
 
 use html::ForHtml;
 use sanitizer::escape;
 
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
 use parser::base::{DecodeResult, XmlElement};
-use schema::{FromSchemaReader, Mergeable};
+use schema::{FromSchemaReader, Mergeable, SchemaResult, ToSchemaWriter,
+             XmlWriter};
 
 
 /// Identify the agent used to generate a feed, for debugging and other
 /// purposes.  It's corresponds to ``atom:generator`` element of
 /// :rfc:`4287#section-4.2.4` (section 4.2.4).
-#[derive(Default, PartialEq, Eq)]
+///
+/// Generators are compared by all their fields, and ordered by `value`
+/// first, then `version` and `uri`.
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Generator {
     /// A URI that represents something relavent to the agent.
     pub uri: Option<String>,
@@ -21,6 +29,19 @@ pub struct Generator {
     pub value: String,
 }
 
+impl PartialOrd for Generator {
+    fn partial_cmp(&self, other: &Generator) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Generator {
+    fn cmp(&self, other: &Generator) -> Ordering {
+        (&self.value, &self.version, &self.uri)
+            .cmp(&(&other.value, &other.version, &other.uri))
+    }
+}
+
 impl fmt::Display for Generator {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         try!(write!(f, "{}", self.value));
@@ -58,6 +79,25 @@ impl FromSchemaReader for Generator {
     }
 }
 
+impl ToSchemaWriter for Generator {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let mut element = element;
+        if let Some(ref uri) = self.uri {
+            element = element.attr("uri", uri);
+        }
+        if let Some(ref version) = self.version {
+            element = element.attr("version", version);
+        }
+        try!(writer.write(element));
+        try!(writer.write(XmlEvent::characters(&self.value)));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
 impl Mergeable for Generator { }
 
 
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use parser::base::{DecodeError, DecodeResult, XmlElement};
+use schema::{FromSchemaReader, Mergeable, SchemaError, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+/// The XML namespace name used for [GeoRSS Simple][].
+///
+/// [GeoRSS Simple]: http://www.georss.org/simple.html
+pub const GEORSS_XMLNS: &'static str = "http://www.georss.org/georss";
+
+/// The XML namespace name used for [W3C Basic Geo][] vocabulary.
+///
+/// [W3C Basic Geo]: http://www.w3.org/2003/01/geo/
+pub const W3C_GEO_XMLNS: &'static str =
+    "http://www.w3.org/2003/01/geo/wgs84_pos#";
+
+/// The WGS84 coordinates the entry is about.  It corresponds to
+/// `georss:point` element, or a pair of `geo:lat` and `geo:long` elements.
+///
+/// It's not a part of [RFC 4287 Atom standard][rfc-atom], but it's stored
+/// as `georss:point` element.
+///
+/// [rfc-atom]: https://tools.ietf.org/html/rfc4287
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoPoint {
+    /// Make a point if the coordinates are in the valid range.
+    pub fn new(latitude: f64, longitude: f64) -> Option<GeoPoint> {
+        if latitude.abs() <= 90.0 && longitude.abs() <= 180.0 {
+            Some(GeoPoint { latitude: latitude, longitude: longitude })
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse the text of `georss:point` element, that is latitude and longitude
+/// separated by whitespace e.g. `"45.256 -71.92"`.
+impl FromStr for GeoPoint {
+    type Err = SchemaError;
+
+    fn from_str(s: &str) -> Result<GeoPoint, SchemaError> {
+        let mut coordinates = s.split_whitespace().map(f64::from_str);
+        match (coordinates.next(), coordinates.next(), coordinates.next()) {
+            (Some(Ok(lat)), Some(Ok(long)), None) => {
+                GeoPoint::new(lat, long).ok_or(SchemaError::DecodeError(
+                    "coordinates out of range", Some(s.to_owned())))
+            }
+            _ => Err(SchemaError::DecodeError("invalid point",
+                                              Some(s.to_owned()))),
+        }
+    }
+}
+
+impl fmt::Display for GeoPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.latitude, self.longitude)
+    }
+}
+
+impl Mergeable for GeoPoint { }
+
+impl FromSchemaReader for GeoPoint {
+    fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        match try!(element.read_whole_text()).parse() {
+            Ok(point) => { *self = point; }
+            Err(e) => { return Err(DecodeError::SchemaError(e)); }
+        }
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for GeoPoint {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.ns("georss", GEORSS_XMLNS)));
+        try!(writer.write(XmlEvent::characters(&self.to_string())));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::GeoPoint;
+
+    #[test]
+    fn test_geo_point_from_str() {
+        assert_eq!("45.256 -71.92".parse::<GeoPoint>().unwrap(),
+                   GeoPoint { latitude: 45.256, longitude: -71.92 });
+        assert_eq!(" 0\t0 ".parse::<GeoPoint>().unwrap(), GeoPoint::default());
+        assert!("45.256".parse::<GeoPoint>().is_err());
+        assert!("45.256 -71.92 1".parse::<GeoPoint>().is_err());
+        assert!("91 0".parse::<GeoPoint>().is_err());
+        assert!("north east".parse::<GeoPoint>().is_err());
+    }
+}
@@ -1,23 +1,29 @@
-use std::borrow::{Borrow, ToOwned};
+use std::borrow::{Borrow, Cow, ToOwned};
 use std::default::Default;
 use std::fmt;
 use std::io;
 use std::iter::{FromIterator, IntoIterator};
 use std::mem::swap;
 use std::ops::{Deref, DerefMut};
+use std::slice;
 use std::str::FromStr;
 
 use regex::Regex;
+use xml::writer::events::{StartElementBuilder, XmlEvent};
 
 use html::ForHtml;
+use mimetype::intern;
 use parser::base::{DecodeResult, XmlElement};
-use schema::{FromSchemaReader, Mergeable};
+use schema::{FromSchemaReader, Mergeable, SchemaResult, ToSchemaWriter,
+             XmlWriter};
 use util::merge_vec;
+use super::LinkRel;
 
 /// Link element defined in RFC 4287 (section 4.2.7).
 ///
 /// RFC: <https://tools.ietf.org/html/rfc4287#section-4.2.7>.
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Link {
     /// The link's required URI.  It corresponds to `href` attribute of
     /// [RFC 4287 (section 4.2.7.1)][rfc-link-1].
@@ -40,14 +46,17 @@ pub struct Link {
     /// [rfc-link-2]: https://tools.ietf.org/html/rfc4287#section-4.2.7.2
     /// [rel-values]: http://microformats.org/wiki/existing-rel-values
     /// [POSH]: http://microformats.org/wiki/POSH
-    pub relation: String,
+    pub relation: LinkRel,
 
     /// The optional hint for the MIME media type of the linked content.
     /// It corresponds to `type` attribute of
     /// [RFC 4287 (section 4.2.7.3)][rfc-link-3].
     ///
+    /// Media types common in feeds are borrowed from static strings rather
+    /// than allocated; see `mimetype::intern()`.
+    ///
     /// [rfc-link-3]: https://tools.ietf.org/html/rfc4287#section-4.2.7.3
-    pub mimetype: Option<String>,
+    pub mimetype: Option<Cow<'static, str>>,
 
     /// The language of the linked content.  It corresponds to `hreflang`
     /// attribute of [RFC 4287 (section 4.2.7.4)][rfc-link-4].
@@ -74,7 +83,7 @@ impl Link {
         where T: Into<String>
     {
         Link {
-            uri: uri.into(), relation: "alternate".to_owned(),
+            uri: uri.into(), relation: LinkRel::Alternate,
             mimetype: None, language: None, title: None, byte_size: None
         }   
     }
@@ -126,10 +135,9 @@ impl FromSchemaReader for Link {
                                  -> DecodeResult<()>
     {
         self.uri = try!(element.get_attr("href")).to_owned();
-        self.relation = element.get_attr("rel")
-                               .unwrap_or("alternate").to_owned();
-        self.mimetype = element.get_attr("type").ok()
-                               .map(ToOwned::to_owned);
+        self.relation = LinkRel::from(element.get_attr("rel")
+                                             .unwrap_or("alternate"));
+        self.mimetype = element.get_attr("type").ok().map(intern);
         self.language = element.get_attr("hreflang").ok()
                                .map(ToOwned::to_owned);
         self.title = element.get_attr("title").ok()
@@ -140,11 +148,39 @@ impl FromSchemaReader for Link {
     }
 }
 
+impl ToSchemaWriter for Link {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let byte_size = self.byte_size.map(|v| v.to_string());
+        let mut element = element.attr("href", &self.uri)
+                                 .attr("rel", self.relation.as_str());
+        if let Some(ref mimetype) = self.mimetype {
+            element = element.attr("type", mimetype);
+        }
+        if let Some(ref language) = self.language {
+            element = element.attr("hreflang", language);
+        }
+        if let Some(ref title) = self.title {
+            element = element.attr("title", title);
+        }
+        if let Some(ref byte_size) = byte_size {
+            element = element.attr("length", byte_size);
+        }
+        try!(writer.write(element));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct FilterByMimeType<'a, I, T> where I: Iterator<Item=T>, T: Borrow<Link> {
     inner: I,
     condition: Condition<'a>,
 }
 
+#[derive(Clone, Debug)]
 pub enum Condition<'a> {
     Regex(Regex),
     Simple(&'a str),
@@ -181,7 +217,11 @@ impl<'a, I, T> Iterator for FilterByMimeType<'a, I, T>
 }
 
 
-pub trait LinkIteratorExt<'a>: Iterator<Item=&'a Link> + Sized {
+/// Methods to pick links out of iterators of links.  Iterators of borrowed
+/// links e.g. `links.iter()` and of owned ones e.g. `links.into_iter()`
+/// work alike, since items only have to be `Borrow<Link>`.  See also
+/// `LinkSliceExt`.
+pub trait LinkIteratorExt: Iterator + Sized where Self::Item: Borrow<Link> {
     /// Filter links by their `mimetype` e.g.:
     ///
     /// ```
@@ -200,7 +240,7 @@ pub trait LinkIteratorExt<'a>: Iterator<Item=&'a Link> + Sized {
     /// # ;
     /// ```
     fn filter_by_mimetype<'b>(self, pattern: &'b str) ->
-        FilterByMimeType<Self, &'a Link>
+        FilterByMimeType<'b, Self, Self::Item>
     {
         use regex;
         let cond = if pattern.contains('*') {
@@ -224,12 +264,14 @@ pub trait LinkIteratorExt<'a>: Iterator<Item=&'a Link> + Sized {
         FilterByMimeType { inner: self, condition: cond }
     }
 
-    fn permalink(self) -> Option<&'a Link> {
+    fn permalink(self) -> Option<Self::Item> {
         let mut result = None;
         let mut score = (false, false);
         for link in self {
-            let rel_is_alternate = link.relation == "alternate";
-            let new_score = (link.is_html(), rel_is_alternate);
+            let new_score = {
+                let l = link.borrow();
+                (l.is_html(), l.relation == LinkRel::Alternate)
+            };
             if score < new_score {
                 result = Some(link);
                 score = new_score;
@@ -238,9 +280,9 @@ pub trait LinkIteratorExt<'a>: Iterator<Item=&'a Link> + Sized {
         result
     }
 
-    fn favicon(self) -> Option<&'a Link> {
+    fn favicon(self) -> Option<Self::Item> {
         for link in self {
-            if link.relation.split(' ').any(|i| i == "icon") {
+            if link.borrow().relation.contains(&LinkRel::Icon) {
                 return Some(link);
             }
         }
@@ -248,10 +290,34 @@ pub trait LinkIteratorExt<'a>: Iterator<Item=&'a Link> + Sized {
     }
 }
 
-impl<'a, I: Iterator<Item=&'a Link>> LinkIteratorExt<'a> for I { }
+impl<I> LinkIteratorExt for I where I: Iterator, I::Item: Borrow<Link> { }
 
+/// `LinkIteratorExt` methods right on slices and vectors of links e.g.
+/// `entry.links.permalink()`.
+pub trait LinkSliceExt {
+    fn filter_by_mimetype<'a, 'b>(&'a self, pattern: &'b str) ->
+        FilterByMimeType<'b, slice::Iter<'a, Link>, &'a Link>;
 
-#[derive(Default, Debug)]
+    fn permalink(&self) -> Option<&Link>;
+
+    fn favicon(&self) -> Option<&Link>;
+}
+
+impl LinkSliceExt for [Link] {
+    fn filter_by_mimetype<'a, 'b>(&'a self, pattern: &'b str) ->
+        FilterByMimeType<'b, slice::Iter<'a, Link>, &'a Link>
+    {
+        self.iter().filter_by_mimetype(pattern)
+    }
+
+    fn permalink(&self) -> Option<&Link> { self.iter().permalink() }
+
+    fn favicon(&self) -> Option<&Link> { self.iter().favicon() }
+}
+
+
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LinkList(pub Vec<Link>);
 
 impl LinkList {
@@ -283,7 +349,8 @@ impl Mergeable for Vec<Link> {
 
 #[cfg(test)]
 mod test {
-    use super::{Link, LinkIteratorExt};
+    use super::{Link, LinkIteratorExt, LinkSliceExt};
+    use super::super::LinkRel;
 
     use std::default::Default;
 
@@ -292,11 +359,11 @@ mod test {
     #[test]
     fn test_link_html_property() {
         let mut link = Link::new("http://dahlia.kr/");
-        link.mimetype = Some("text/html".to_string());
+        link.mimetype = Some("text/html".into());
         assert!(link.is_html());
-        link.mimetype = Some("application/xhtml+xml".to_string());
+        link.mimetype = Some("application/xhtml+xml".into());
         assert!(link.is_html());
-        link.mimetype = Some("application/xml".to_string());
+        link.mimetype = Some("application/xml".into());
         assert!(!link.is_html());
     }
 
@@ -304,8 +371,8 @@ mod test {
     fn test_link_str() {
         let link = Link {
             uri: "http://dahlia.kr/".to_string(),
-            relation: "alternate".to_string(),
-            mimetype: Some("text/html".to_string()),
+            relation: LinkRel::Alternate,
+            mimetype: Some("text/html".into()),
             title: Some("Hong Minhee's website".to_string()),
             language: None, byte_size: None,
         };
@@ -319,8 +386,8 @@ mod test {
                      "<link rel=\"alternate\" href=\"http://dahlia.kr/\">");
         let link = Link {
             uri: "http://dahlia.kr/".to_string(),
-            relation: "alternate".to_string(),
-            mimetype: Some("text/html".to_string()),
+            relation: LinkRel::Alternate,
+            mimetype: Some("text/html".into()),
             title: Some("Hong Minhee's website".to_string()),
             language: Some("en".to_string()),
             byte_size: None
@@ -335,50 +402,50 @@ mod test {
         vec![
             Link::new("http://example.org/"),
             Link {
-                relation: "alternate".to_string(),
-                mimetype: Some("text/html".to_string()),
+                relation: LinkRel::Alternate,
+                mimetype: Some("text/html".into()),
                 uri: "http://example.com/index.html".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "alternate".to_string(),
-                mimetype: Some("text/html".to_string()),
+                relation: LinkRel::Alternate,
+                mimetype: Some("text/html".into()),
                 uri: "http://example.com/index2.html".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "alternate".to_string(),
-                mimetype: Some("text/xml".to_string()),
+                relation: LinkRel::Alternate,
+                mimetype: Some("text/xml".into()),
                 uri: "http://example.com/index.xml".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "alternate".to_string(),
-                mimetype: Some("application/json".to_string()),
+                relation: LinkRel::Alternate,
+                mimetype: Some("application/json".into()),
                 uri: "http://example.com/index.json".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "alternate".to_string(),
-                mimetype: Some("text/javascript".to_string()),
+                relation: LinkRel::Alternate,
+                mimetype: Some("text/javascript".into()),
                 uri: "http://example.com/index.js".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "alternate".to_string(),
-                mimetype: Some("application/xml+atom".to_string()),
+                relation: LinkRel::Alternate,
+                mimetype: Some("application/xml+atom".into()),
                 uri: "http://example.com/index.atom".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "alternate".to_string(),  // remove it if available
-                mimetype: Some("application/xml+rss".to_string()),
+                relation: LinkRel::Alternate,  // remove it if available
+                mimetype: Some("application/xml+rss".into()),
                 uri: "http://example.com/index.atom".to_string(),
                 title: None, language: None, byte_size: None,
             },
             Link {
-                relation: "icon".to_string(),
-                mimetype: Some("image/png".to_string()),
+                relation: LinkRel::Icon,
+                mimetype: Some("image/png".into()),
                 uri: "http://example.com/favicon.png".to_string(),
                 title: None, language: None, byte_size: None,
             },
@@ -412,10 +479,10 @@ mod test {
     fn test_link_list_permalink() {
         let mut links = fx_feed_links();
         let mut other_link = Link::new("http://example.com/");
-        other_link.relation = "other".to_string();
+        other_link.relation = LinkRel::from("other");
         let mut html_link = Link::new("http://example.com/");
-        html_link.relation = "other".to_string();
-        html_link.mimetype = Some("text/html".to_string());
+        html_link.relation = LinkRel::from("other");
+        html_link.mimetype = Some("text/html".into());
         links.extend(vec![other_link, html_link.clone()].into_iter());
         assert_eq!(links.iter().permalink(), Some(&links[1]));
         links.remove(1);
@@ -431,10 +498,26 @@ mod test {
         let mut links = fx_feed_links();
         assert_eq!(links.iter().favicon(), links.last());
         links[0] = Link {
-            relation: "shortcut icon".to_string(),
+            relation: LinkRel::from("shortcut icon"),
             uri: "http://example.com/favicon.ico".to_string(),
             ..Default::default()
         };
         assert_eq!(links.iter().favicon(), links.first());
     }
+
+    #[test]
+    fn test_owned_links() {
+        let links = fx_feed_links();
+        assert_eq!(links.permalink(), Some(&links[1]));
+        assert_eq!(links.favicon(), links.last());
+        assert_eq!(links.filter_by_mimetype("text/*").count(), 4);
+        assert_eq!(links.clone().into_iter().permalink().as_ref(),
+                   Some(&links[1]));
+        let html: Vec<Link> = links.clone().into_iter()
+                                   .filter_by_mimetype("text/html")
+                                   .collect();
+        assert_eq!(html, &links[1..3]);
+        assert_eq!(links[..1].permalink(), Some(&links[0]));
+        assert_eq!(Vec::<Link>::new().into_iter().favicon(), None);
+    }
 }
@@ -1,12 +1,18 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::io;
+use std::str::from_utf8;
 
 use chrono::{DateTime, FixedOffset};
+use xml::writer::events::{StartElementBuilder, XmlEvent};
 
 use codecs;    
 use parser::base::{DecodeResult, XmlElement};
-use schema::{Codec, Entity, FromSchemaReader, Mergeable};
+use schema::{Codec, Entity, FromSchemaReader, Mergeable, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+use super::{MARK_XMLNS, format_datetime};
 
 /// Represent whether the entry is read, starred, or tagged by user.
 ///
@@ -14,13 +20,46 @@ use schema::{Codec, Entity, FromSchemaReader, Mergeable};
 /// for Earth Reader.
 ///
 /// [rfc-atom]: https://tools.ietf.org/html/rfc4287
+///
+/// Elements are written with the `mark:version` attribute of
+/// `MARK_VERSION`.  Attributes unknown to this version, e.g. ones added by
+/// newer clients, are kept in `extras` and written back as they are, so
+/// that clients of different versions can share the same repository.
 #[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Mark {
     /// Whether it's marked or not.
     pub marked: bool,
 
     /// Updated time.
+    #[cfg_attr(feature = "serde",
+               serde(default, with = "::codecs::serde::rfc3339_option"))]
     pub updated_at: Option<DateTime<FixedOffset>>,
+
+    /// The session which updated the mark last, e.g. an identifier of the
+    /// device.  Marks updated at the same `updated_at` by different sessions
+    /// are merged by this, so that every side picks the same winner.  It's
+    /// written as the `session` attribute, which older clients keep as
+    /// their extras.
+    pub session: Option<String>,
+
+    /// Unknown attributes without namespaces, by their names.
+    pub extras: BTreeMap<String, String>,
+}
+
+/// The version of the `mark:` extension this library writes.
+pub const MARK_VERSION: &'static str = "1";
+
+/// Attributes of mark elements this version understands.
+const KNOWN_ATTRIBUTES: &'static [&'static str] = &["updated", "version",
+                                                    "session"];
+
+/// The kind of marks an `Entry` has.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MarkKind {
+    Read,
+    Starred,
 }
 
 impl Entity for Mark {
@@ -31,14 +70,22 @@ impl Entity for Mark {
     fn entity_id(&self) -> Cow<()> { Cow::Owned(()) }
 }
 
+/// The later updated mark wins.  Ties of `updated_at` are broken by
+/// `session`, and marks without sessions lose to ones with sessions.
 impl Mergeable for Mark {
     fn merge_with(&mut self, other: Mark) {
         use std::cmp::Ordering::Less;
-        let cmp = self.updated_at.cmp(&other.updated_at);
+        let cmp = (&self.updated_at, &self.session)
+                      .cmp(&(&other.updated_at, &other.session));
+        let mut other = other;
         match cmp {
-            Less => { self.clone_from(&other); }
+            Less => { ::std::mem::swap(self, &mut other); }
             _    => { }
         }
+        // extras only the older one has are kept as well
+        for (name, value) in other.extras {
+            self.extras.entry(name).or_insert(value);
+        }
     }
 }
 
@@ -46,10 +93,17 @@ impl FromSchemaReader for Mark {
     fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
                                  -> DecodeResult<()>
     {
-        self.updated_at = {
-            let updated_at = try!(element.get_attr("updated"));
-            Some(try!(codecs::RFC3339.decode(updated_at)))
+        self.updated_at = match element.get_attr("updated") {
+            Ok(updated_at) => Some(try!(codecs::RFC3339.decode(updated_at))),
+            Err(_) => None,
         };
+        self.session = element.get_attr("session").ok().map(|s| s.to_owned());
+        self.extras = element.attributes.iter().filter(|attr| {
+            attr.name.namespace.is_none() &&
+                !KNOWN_ATTRIBUTES.contains(&&attr.name.local_name[..])
+        }).map(|attr| {
+            (attr.name.local_name.clone(), attr.value.clone())
+        }).collect();
         let content = try!(element.read_whole_text());
         let codec: codecs::Boolean = Default::default();
         self.marked = try!(codec.decode(&content));
@@ -57,6 +111,35 @@ impl FromSchemaReader for Mark {
     }        
 }
 
+impl ToSchemaWriter for Mark {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let updated_at = match self.updated_at {
+            Some(ref v) => Some(try!(format_datetime(v))),
+            None => None,
+        };
+        let mut element = element.ns("mark", MARK_XMLNS)
+                                 .attr("version", MARK_VERSION);
+        if let Some(ref updated_at) = updated_at {
+            element = element.attr("updated", updated_at);
+        }
+        if let Some(ref session) = self.session {
+            element = element.attr("session", &session[..]);
+        }
+        for (name, value) in self.extras.iter() {
+            element = element.attr(&name[..], &value[..]);
+        }
+        let mut content = vec![];
+        let codec: codecs::Boolean = Default::default();
+        try!(codec.encode(&self.marked, &mut content));
+        try!(writer.write(element));
+        try!(writer.write(XmlEvent::characters(from_utf8(&content).unwrap())));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 #[allow(dead_code)]
@@ -65,11 +148,15 @@ mod test {
 
     use chrono::{FixedOffset, TimeZone};
 
+    use feed::{Entry, Text};
+    use schema::{Mergeable, read, write};
+
     fn fx_mark_true() -> Mark {
         Mark {
             marked: true,
             updated_at: Some(FixedOffset::east(0).ymd(2013, 11, 6)
                              .and_hms(14, 36, 0)),
+            ..Default::default()
         }
     }
 
@@ -78,6 +165,83 @@ mod test {
             marked: false,
             updated_at: Some(FixedOffset::east(0).ymd(2013, 11, 6)
                              .and_hms(14, 36, 0)),
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn test_mark_extras() {
+        let xml = br#"<entry xmlns="http://www.w3.org/2005/Atom"
+                             xmlns:mark="http://earthreader.org/mark/">
+            <id>urn:entry</id><title>Entry</title>
+            <updated>2013-11-06T14:36:00Z</updated>
+            <mark:starred updated="2013-11-06T14:36:00Z" version="2"
+                          device="phone" tags="a b">true</mark:starred>
+        </entry>"#;
+        let entry: Entry = unwrap!(read(&xml[..]));
+        assert!(entry.starred.marked);
+        assert_eq!(entry.starred.extras.len(), 2);
+        assert_eq!(entry.starred.extras["device"], "phone");
+        let mut buf = vec![];
+        unwrap!(write(&entry, &mut buf));
+        let written = String::from_utf8(buf).unwrap();
+        assert!(written.contains(r#"version="1""#));
+        let entry2: Entry = unwrap!(read(written.as_bytes()));
+        assert_eq!(entry2.starred, entry.starred);
+
+        let mut older = fx_mark_false();
+        older.extras.insert("device".to_owned(), "desktop".to_owned());
+        older.extras.insert("color".to_owned(), "red".to_owned());
+        let mut newer = entry.starred.clone();
+        newer.updated_at = Some(FixedOffset::east(0).ymd(2013, 11, 7)
+                                                    .and_hms(0, 0, 0));
+        older.merge_with(newer);
+        assert!(older.marked);
+        assert_eq!(older.extras["device"], "phone");
+        assert_eq!(older.extras["color"], "red");
+    }
+
+    #[test]
+    fn test_mark_session() {
+        let mut phone = fx_mark_true();
+        phone.session = Some("phone".to_owned());
+        let mut desktop = fx_mark_false();
+        desktop.session = Some("desktop".to_owned());
+
+        let mut a = phone.clone();
+        a.merge_with(desktop.clone());
+        let mut b = desktop.clone();
+        b.merge_with(phone.clone());
+        assert_eq!(a, phone);
+        assert_eq!(b, phone);
+        let mut c = fx_mark_false();
+        c.merge_with(desktop.clone());
+        assert_eq!(c, desktop);
+
+        let mut entry = Entry::new("urn:entry".to_owned(), Text::plain("Entry"),
+                                   phone.updated_at.unwrap());
+        entry.read = phone.clone();
+        let mut buf = vec![];
+        unwrap!(write(&entry, &mut buf));
+        let written = String::from_utf8(buf).unwrap();
+        assert!(written.contains(r#"session="phone""#));
+        let entry2: Entry = unwrap!(read(written.as_bytes()));
+        assert_eq!(entry2.read, phone);
+        assert!(entry2.read.extras.is_empty());
+    }
+    #[test]
+    fn test_mark_without_updated_at() {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:entry".to_owned(), Text::plain("Entry"),
+                                   date);
+        entry.read.marked = true;
+        let mut buf = vec![];
+        unwrap!(write(&entry, &mut buf));
+        let written = String::from_utf8(buf).unwrap();
+        assert!(written.contains("mark:read"));
+        assert!(!written.contains("mark:starred"));
+        let entry2: Entry = unwrap!(read(written.as_bytes()));
+        assert_eq!(entry2.read, entry.read);
+        assert_eq!(entry2.starred, Default::default());
+    }
 }
@@ -3,14 +3,19 @@ use std::default::Default;
 use std::io;
 
 use chrono::{DateTime, FixedOffset};
+use xml::writer::XmlEvent;
 
 use parser::base::{DecodeResult, XmlElement, XmlName};
-use schema::{Entity, FromSchemaReader, Mergeable};
+use schema::{Entity, FromSchemaReader, Mergeable, SchemaResult,
+             ToSchemaWriter, XmlWriter};
 use util::set_default;
 
-use super::{ATOM_XMLNS, Category, Link, Person, Text, parse_datetime};
+use super::{ATOM_XMLNS, Category, Link, Person, Text, parse_datetime,
+            write_datetime, write_text_element};
 
 /// Common metadata shared by `Source`, `Entry`, and `Feed`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Metadata {
     /// The URI that conveys a permanent, universally unique identifier for an
     /// entry or feed.  It corresponds to `atom:id` element of :rfc:`4287#section-4.2.6` (section 4.2.6).
@@ -30,6 +35,7 @@ pub struct Metadata {
     /// publisher considers significant.  Therefore, not all modifications
     /// necessarily result in a changed `updated_at` value.
     /// It corresponds to `atom:updated` element of :rfc:`4287#section-4.2.15` (section 4.2.15).
+    #[cfg_attr(feature = "serde", serde(with = "::codecs::serde::rfc3339"))]
     pub updated_at: DateTime<FixedOffset>,
 
     /// The list of `Person` values which indicates the author of the entry or
@@ -61,6 +67,34 @@ impl Metadata {
             ..Default::default()
         }            
     }
+
+    /// Tags the user applied, i.e. categories of `USER_TAG_SCHEME`.
+    pub fn user_tags(&self) -> Vec<&Category> {
+        self.categories.iter().filter(|c| c.is_user_tag()).collect()
+    }
+
+    /// Categories the publisher specified, i.e. ones that aren't user tags.
+    pub fn publisher_categories(&self) -> Vec<&Category> {
+        self.categories.iter().filter(|c| !c.is_user_tag()).collect()
+    }
+
+    /// Apply the user tag.  It returns `false` if it's already applied.
+    /// Publisher categories of the same term don't matter.
+    pub fn add_user_tag<T: Into<String>>(&mut self, term: T) -> bool {
+        let tag = Category::user_tag(term);
+        if self.categories.contains(&tag) {
+            return false;
+        }
+        self.categories.push(tag);
+        true
+    }
+
+    /// Remove the user tag.  It returns `false` if it isn't applied.
+    pub fn remove_user_tag(&mut self, term: &str) -> bool {
+        let len = self.categories.len();
+        self.categories.retain(|c| !(c.is_user_tag() && c.term == term));
+        self.categories.len() != len
+    }
 }
 
 impl Default for Metadata {
@@ -126,6 +160,34 @@ impl FromSchemaReader for Metadata {
     }
 }
 
+impl ToSchemaWriter for Metadata {
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        try!(write_text_element(writer, "id", &self.id));
+        try!(self.title.write_to(writer, XmlEvent::start_element("title")));
+        for link in self.links.iter() {
+            try!(link.write_to(writer, XmlEvent::start_element("link")));
+        }
+        try!(write_datetime(writer, "updated", &self.updated_at));
+        for author in self.authors.iter() {
+            try!(author.write_to(writer, XmlEvent::start_element("author")));
+        }
+        for contributor in self.contributors.iter() {
+            try!(contributor.write_to(writer,
+                                      XmlEvent::start_element("contributor")));
+        }
+        for category in self.categories.iter() {
+            try!(category.write_to(writer,
+                                   XmlEvent::start_element("category")));
+        }
+        if let Some(ref rights) = self.rights {
+            try!(rights.write_to(writer, XmlEvent::start_element("rights")));
+        }
+        Ok(())
+    }
+}
+
 impl Entity for Metadata {
     type Id = str;
     fn entity_id(&self) -> Cow<str> {
@@ -9,43 +9,65 @@
 //!
 //! [libearth]: https://github.com/earthreader/libearth
 //! [RFC 4287]: https://tools.ietf.org/html/rfc4287
+use std::borrow::Cow;
+use std::fmt;
 use std::io;
 use std::str::from_utf8;
 
 use chrono::{DateTime, FixedOffset};
+use xml::writer::XmlEvent;
 
 use codecs;
 use mimetype::MimeType;
 use parser::base::{DecodeResult, DecodeError, XmlElement};
-use schema::Codec;
+use sanitizer::strip_tags;
+use schema::{Codec, SchemaResult, XmlWriter};
+use util::{collapse_whitespace, word_count};
 
-pub use self::category::Category;
-pub use self::content::Content;
+pub use self::bidi::{Direction, detect_direction};
+pub use self::category::{Category, USER_TAG_SCHEME};
+pub use self::content::{CodeBlock, Content};
 pub use self::entry::Entry;
 pub use self::feed::Feed;
 pub use self::generator::Generator;
-pub use self::link::{Link, LinkIteratorExt, LinkList};
-pub use self::mark::Mark;
+pub use self::geo::{GEORSS_XMLNS, W3C_GEO_XMLNS, GeoPoint};
+pub use self::link::{Link, LinkIteratorExt, LinkList, LinkSliceExt};
+pub use self::mark::{MARK_VERSION, Mark, MarkKind};
 pub use self::metadata::Metadata;
+pub use self::order::SortPolicy;
 pub use self::person::Person;
+pub use self::provenance::Provenance;
+pub use self::rel::{IANA_RELATION_PREFIX, LinkRel};
 pub use self::source::Source;
 pub use self::text::Text;
+pub use self::thread::{SLASH_XMLNS, THREADING_XMLNS, WFW_XMLNS,
+                       Replies};
+pub use self::writer::FeedWriter;
 
+mod bidi;
 mod category;
 mod content;
 mod entry;
 mod feed;
 mod generator;
+mod geo;
 mod link;
 mod mark;
 mod metadata;
+mod order;
 mod person;
+mod provenance;
+mod rel;
 mod source;
 mod text;
+mod thread;
+mod writer;
+
+pub mod paging;
 
 
 /// The XML namespace name used for Atom (RFC 4287).
-const ATOM_XMLNS: &'static str = "http://www.w3.org/2005/Atom";
+pub const ATOM_XMLNS: &'static str = "http://www.w3.org/2005/Atom";
 
 /// The XML namespace name used for Earth Reader `Mark` metadata.
 const MARK_XMLNS: &'static str = "http://earthreader.org/mark/";
@@ -59,6 +81,41 @@ pub trait Blob {
     fn as_bytes(&self) -> &[u8];
 
     fn as_str(&self) -> Option<&str> { from_utf8(self.as_bytes()).ok() }
+
+    /// The plain text for indexing, e.g. search, reading time and duplicate
+    /// detection: markup tags are stripped and entities are decoded if
+    /// it's HTML, and whitespaces are collapsed; see
+    /// `util::collapse_whitespace()`.  It's empty for binary data.  Plain
+    /// texts already normalized are borrowed as they are.
+    ///
+    /// ```
+    /// # use earth::feed::{Blob, Text};
+    /// let html = Text::html("<p>Fish &amp;\n  <em>chips</em></p>");
+    /// assert_eq!(html.normalized_text(), "Fish & chips");
+    /// ```
+    fn normalized_text(&self) -> Cow<str> {
+        let text = match self.as_str() {
+            Some(text) if self.is_text() => text,
+            _ => { return Cow::Borrowed(""); }
+        };
+        match self.mimetype() {
+            MimeType::Html | MimeType::Xhtml => {
+                let stripped = strip_tags(text);
+                if let Cow::Owned(collapsed) = collapse_whitespace(&stripped) {
+                    return Cow::Owned(collapsed);
+                }
+                Cow::Owned(stripped)
+            }
+            _ => collapse_whitespace(text),
+        }
+    }
+
+    /// The number of words of `normalized_text()`.  It's zero for binary
+    /// data.  See `util::word_count()` for how words of CJK scripts are
+    /// counted.
+    fn word_count(&self) -> usize {
+        word_count(&self.normalized_text())
+    }
 }
 
 #[cfg(html_sanitizer)]
@@ -79,6 +136,25 @@ pub trait HtmlBlob: Blob {
         Box<fmt::Display + 'a>;
 }
 
+/// Bodies longer than this are summarized to their length in `Debug`
+/// output, so that huge contents don't flood logs and test failures.
+const DEBUG_BODY_LIMIT: usize = 256;
+
+/// The `Debug` representation of a body of `Text` or `Content`.
+struct DebugBody<'a>(&'a [u8]);
+
+impl<'a> fmt::Debug for DebugBody<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.len() > DEBUG_BODY_LIMIT {
+            write!(f, "<{} bytes>", self.0.len())
+        } else if let Ok(s) = from_utf8(self.0) {
+            write!(f, "{:?}", s)
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
 fn parse_datetime<B: io::BufRead>(element: XmlElement<B>)
                                   -> DecodeResult<DateTime<FixedOffset>>
 {
@@ -87,3 +163,26 @@ fn parse_datetime<B: io::BufRead>(element: XmlElement<B>)
         Err(e) => Err(DecodeError::SchemaError(e)),
     }
 }
+
+fn format_datetime(value: &DateTime<FixedOffset>) -> SchemaResult<String> {
+    let mut buf = vec![];
+    try!(codecs::RFC3339.encode(value, &mut buf));
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+fn write_datetime<W: io::Write>(writer: &mut XmlWriter<W>, name: &str,
+                                value: &DateTime<FixedOffset>)
+                                -> SchemaResult<()>
+{
+    let text = try!(format_datetime(value));
+    write_text_element(writer, name, &text)
+}
+
+fn write_text_element<W: io::Write>(writer: &mut XmlWriter<W>, name: &str,
+                                    value: &str) -> SchemaResult<()>
+{
+    try!(writer.write(XmlEvent::start_element(name)));
+    try!(writer.write(XmlEvent::characters(value)));
+    try!(writer.write(XmlEvent::end_element()));
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset};
+
+use super::Entry;
+
+/// Which date entries of a feed are listed by, newest first.  Feeds
+/// sometimes set only one of `published_at` and `updated_at`, or bump
+/// `updated_at` on every rebuild, so it's chosen per feed; see
+/// `SubscriptionSettings::sort_policy()`.
+///
+/// Policies fall back to the other dates when the entry lacks the one they
+/// prefer, and entries of the same date are ordered by their `id`, so the
+/// order is the same however the feed lists them, e.g. when pages of
+/// entries are taken from it between refreshes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SortPolicy {
+    /// By `updated_at`.  It's the default.
+    #[default]
+    UpdatedFirst,
+
+    /// By `published_at`, or `updated_at` if it isn't published.
+    PublishedFirst,
+
+    /// By when the entry was first crawled (`seen`), or the same as
+    /// `PublishedFirst` if it was never crawled.
+    CrawlOrder,
+}
+
+impl SortPolicy {
+    /// The name used in settings, e.g. `"published"`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SortPolicy::UpdatedFirst => "updated",
+            SortPolicy::PublishedFirst => "published",
+            SortPolicy::CrawlOrder => "crawled",
+        }
+    }
+
+    /// The date the entry is sorted by.
+    pub fn date(&self, entry: &Entry) -> DateTime<FixedOffset> {
+        let published = entry.published_at.unwrap_or(entry.updated_at);
+        match *self {
+            SortPolicy::UpdatedFirst => entry.updated_at,
+            SortPolicy::PublishedFirst => published,
+            SortPolicy::CrawlOrder => match entry.seen {
+                Some(ref seen) => seen.first_seen_at,
+                None => published,
+            },
+        }
+    }
+
+    /// Compare entries so that newer ones come first, and ones of the same
+    /// date in the order of their `id`.
+    pub fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        self.date(b).cmp(&self.date(a)).then_with(|| a.id.cmp(&b.id))
+    }
+
+    /// Sort the entries by the policy.
+    pub fn sort(&self, entries: &mut [Entry]) {
+        entries.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+impl fmt::Display for SortPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SortPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SortPolicy, ()> {
+        match &s.to_lowercase()[..] {
+            "updated" => Ok(SortPolicy::UpdatedFirst),
+            "published" => Ok(SortPolicy::PublishedFirst),
+            "crawled" => Ok(SortPolicy::CrawlOrder),
+            _ => Err(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::SortPolicy;
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use feed::{Entry, Provenance, Text};
+
+    #[test]
+    fn test_sort_policy() {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut a = Entry::new("urn:a".to_owned(), Text::plain("A"), date);
+        a.published_at = Some(date - Duration::days(3));
+        let b = Entry::new("urn:b".to_owned(), Text::plain("B"),
+                           date - Duration::days(1));
+        let mut c = Entry::new("urn:c".to_owned(), Text::plain("C"),
+                               date - Duration::days(1));
+        c.seen = Some(Provenance {
+            first_seen_at: date + Duration::days(1),
+            first_seen_uri: "http://example.com/".to_owned(),
+            last_seen_at: date + Duration::days(1),
+            last_seen_uri: "http://example.com/".to_owned(),
+        });
+        let ids = |policy: SortPolicy, entries: &[&Entry]| {
+            let mut entries: Vec<Entry> =
+                entries.iter().map(|&e| e.clone()).collect();
+            policy.sort(&mut entries);
+            entries.into_iter().map(|e| e.id.clone()).collect::<Vec<_>>()
+        };
+        for entries in &[[&a, &b, &c], [&c, &b, &a], [&b, &c, &a]] {
+            assert_eq!(ids(SortPolicy::UpdatedFirst, &entries[..]),
+                       ["urn:a", "urn:b", "urn:c"]);
+            assert_eq!(ids(SortPolicy::PublishedFirst, &entries[..]),
+                       ["urn:b", "urn:c", "urn:a"]);
+            assert_eq!(ids(SortPolicy::CrawlOrder, &entries[..]),
+                       ["urn:c", "urn:b", "urn:a"]);
+        }
+        assert_eq!(SortPolicy::default(), SortPolicy::UpdatedFirst);
+        for policy in &[SortPolicy::UpdatedFirst, SortPolicy::PublishedFirst,
+                        SortPolicy::CrawlOrder] {
+            assert_eq!(policy.name().parse(), Ok(*policy));
+        }
+        assert!("random".parse::<SortPolicy>().is_err());
+    }
+}
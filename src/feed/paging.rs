@@ -0,0 +1,187 @@
+//! Feed paging and archiving defined in [RFC 5005][].
+//!
+//! [RFC 5005]: https://tools.ietf.org/html/rfc5005
+use std::io;
+
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use schema::{DocumentElement, SchemaResult, ToSchemaWriter, XmlWriter};
+
+use super::{ATOM_XMLNS, MARK_XMLNS, Entry, Feed, Link, LinkRel};
+
+/// The XML namespace name used for feed history (RFC 5005).
+pub const HISTORY_XMLNS: &'static str =
+    "http://purl.org/syndication/history/1.0";
+
+/// Whether the link navigates between pages of a paged or archived feed.
+pub fn is_paging_link(link: &Link) -> bool {
+    link.relation.is_paging()
+}
+
+/// A single document of a feed split by `paginate()`.
+#[derive(Clone, Debug)]
+pub struct Page<'a> {
+    /// The feed the page belongs to.  Its entries are ignored; `entries`
+    /// are written instead.
+    pub feed: &'a Feed,
+
+    /// The entries the page contains.
+    pub entries: &'a [Entry],
+
+    /// Links to the other pages.
+    pub links: Vec<Link>,
+
+    /// Whether the page is an archive document rather than the current
+    /// (subscription) document.  It corresponds to `fh:archive` element of
+    /// RFC 5005 (section 4).
+    pub archive: bool,
+}
+
+impl<'a> DocumentElement for Page<'a> {
+    fn tag() -> &'static str { "feed" }
+    fn xmlns() -> Option<&'static str> { Some(ATOM_XMLNS) }
+}
+
+impl<'a> ToSchemaWriter for Page<'a> {
+    fn write_to<'b, W: io::Write>(&'b self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'b>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.ns("mark", MARK_XMLNS)
+                                 .ns("fh", HISTORY_XMLNS)));
+        try!(self.write_children(writer));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        try!(self.feed.source.write_children(writer));
+        for link in self.links.iter() {
+            try!(link.write_to(writer, XmlEvent::start_element("link")));
+        }
+        if self.archive {
+            try!(writer.write(XmlEvent::start_element("fh:archive")));
+            try!(writer.write(XmlEvent::end_element()));
+        }
+        for entry in self.entries.iter() {
+            try!(entry.write_to(writer, XmlEvent::start_element("entry")));
+        }
+        Ok(())
+    }
+}
+
+fn page_link(relation: LinkRel, uri: String) -> Link {
+    let mut link = Link::new(uri);
+    link.relation = relation;
+    link
+}
+
+/// Links of the `index`-th page to the other pages, as `paginate()` makes.
+/// `has_next` tells whether there's a following page.
+pub fn page_links<F>(index: usize, has_next: bool, href: F) -> Vec<Link>
+    where F: Fn(usize) -> String
+{
+    let mut links = vec![];
+    if index > 0 {
+        links.push(page_link(LinkRel::Current, href(0)));
+        links.push(page_link(LinkRel::Previous, href(index - 1)));
+        links.push(page_link(LinkRel::NextArchive, href(index - 1)));
+    }
+    if has_next {
+        links.push(page_link(LinkRel::Next, href(index + 1)));
+        links.push(page_link(LinkRel::PrevArchive, href(index + 1)));
+    }
+    links
+}
+
+/// Split the feed into pages which contain at most `entries_per_page`
+/// entries.  The first page is the current document that contains the
+/// first entries of `feed.entries`, and the rest are archive documents.
+///
+/// `href` generates the URI of the page from its zero-based index, which is
+/// used for `rel="next"`/`rel="prev-archive"` links to the following page,
+/// and `rel="previous"`/`rel="next-archive"`/`rel="current"` links to
+/// the preceding and the current pages.
+///
+/// It always returns at least one page even if the feed has no entries.
+/// If `entries_per_page` is zero, the feed isn't split, i.e. it's the only
+/// page.
+pub fn paginate<'a, F>(feed: &'a Feed, entries_per_page: usize, href: F)
+                       -> Vec<Page<'a>>
+    where F: Fn(usize) -> String
+{
+    let chunks: Vec<&[Entry]> = if feed.entries.is_empty() ||
+                                   entries_per_page == 0 {
+        vec![&feed.entries[..]]
+    } else {
+        feed.entries.chunks(entries_per_page).collect()
+    };
+    let count = chunks.len();
+    chunks.into_iter().enumerate().map(|(i, entries)| {
+        let links = page_links(i, i + 1 < count, &href);
+        Page { feed: feed, entries: entries, links: links, archive: i > 0 }
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{is_paging_link, paginate};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Link, Text};
+
+    fn fx_feed(entries: usize) -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        for i in 0..entries {
+            feed.entries.push(Entry::new(format!("urn:entry:{}", i),
+                                         Text::plain(format!("{}", i)),
+                                         updated_at));
+        }
+        feed
+    }
+
+    fn relations(links: &[Link]) -> Vec<(&str, &str)> {
+        links.iter().map(|l| (l.relation.as_str(), &l.uri[..])).collect()
+    }
+
+    #[test]
+    fn test_paginate() {
+        let feed = fx_feed(5);
+        let pages = paginate(&feed, 2, |i| format!("page{}", i));
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages.iter().map(|p| p.entries.len()).collect::<Vec<_>>(),
+                   [2, 2, 1]);
+        assert!(!pages[0].archive);
+        assert!(pages[1].archive && pages[2].archive);
+        assert_eq!(relations(&pages[0].links),
+                   [("next", "page1"), ("prev-archive", "page1")]);
+        assert_eq!(relations(&pages[1].links),
+                   [("current", "page0"),
+                    ("previous", "page0"), ("next-archive", "page0"),
+                    ("next", "page2"), ("prev-archive", "page2")]);
+        assert_eq!(relations(&pages[2].links),
+                   [("current", "page0"),
+                    ("previous", "page1"), ("next-archive", "page1")]);
+        assert!(pages.iter().all(|p| p.links.iter().all(is_paging_link)));
+    }
+
+    #[test]
+    fn test_paginate_empty() {
+        let feed = fx_feed(0);
+        let pages = paginate(&feed, 10, |i| format!("page{}", i));
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].links.is_empty());
+        let feed = fx_feed(5);
+        let pages = paginate(&feed, 0, |i| format!("page{}", i));
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].entries.len(), 5);
+        assert!(pages[0].links.is_empty());
+    }
+}
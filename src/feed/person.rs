@@ -7,13 +7,17 @@ use html::ForHtml;
 use parser::base::{DecodeResult, DecodeError, XmlElement, XmlName};
 use parser::base::NestedEvent::Nested;
 use sanitizer::escape;
-use schema::{FromSchemaReader, Mergeable};
+use schema::{FromSchemaReader, Mergeable, SchemaResult, ToSchemaWriter,
+             XmlWriter};
 use util::{merge_vec, set_default};
 
+use super::write_text_element;
+
 /// Person construct defined in RFC 4287 (section 3.2).
 ///
 /// RFC: <https://tools.ietf.org/html/rfc4287#section-3.2>
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Person {
     /// The human-readable name for the person.  It corresponds to
     /// `atom:name` element of [RFC 4287 (section 3.2.1)][rfc-person-1].
@@ -125,6 +129,21 @@ impl FromSchemaReader for Option<Person> {
     }
 }
 
+impl ToSchemaWriter for Person {
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        try!(write_text_element(writer, "name", &self.name));
+        if let Some(ref uri) = self.uri {
+            try!(write_text_element(writer, "uri", uri));
+        }
+        if let Some(ref email) = self.email {
+            try!(write_text_element(writer, "email", email));
+        }
+        Ok(())
+    }
+}
+
 impl Mergeable for Vec<Person> {
     fn merge_with(&mut self, mut other: Vec<Person>) {
         swap(self, &mut other);
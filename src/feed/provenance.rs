@@ -0,0 +1,156 @@
+use std::cmp::{max, min};
+use std::default::Default;
+use std::io;
+
+use chrono::{DateTime, FixedOffset};
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use codecs;
+use parser::base::{DecodeError, DecodeResult, XmlElement};
+use schema::{Codec, FromSchemaReader, Mergeable, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+use super::{MARK_XMLNS, format_datetime};
+
+/// When and from which URL an entry was first and last seen by crawling,
+/// i.e. its crawl provenance.  It's useful for e.g. showing entries new
+/// since the last visit regardless of their `published_at`, which feeds
+/// may rewrite, and for debugging such feeds.
+///
+/// It's an extension for Earth Reader, written as `mark:seen` element:
+///
+/// ```xml
+/// <mark:seen first="2013-11-06T14:36:00Z" first-uri="http://..."
+///            last="2013-11-07T09:00:00Z" last-uri="http://..."/>
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Provenance {
+    #[cfg_attr(feature = "serde", serde(with = "::codecs::serde::rfc3339"))]
+    pub first_seen_at: DateTime<FixedOffset>,
+
+    /// The URL of the feed (or its page) the entry was first crawled from.
+    pub first_seen_uri: String,
+
+    #[cfg_attr(feature = "serde", serde(with = "::codecs::serde::rfc3339"))]
+    pub last_seen_at: DateTime<FixedOffset>,
+
+    /// The URL of the feed (or its page) the entry was last crawled from.
+    pub last_seen_uri: String,
+}
+
+impl Provenance {
+    /// The provenance of an entry seen just once.
+    pub fn new<T: Into<String>>(seen_at: DateTime<FixedOffset>, uri: T)
+                                -> Provenance {
+        let uri = uri.into();
+        Provenance {
+            first_seen_at: seen_at,
+            first_seen_uri: uri.clone(),
+            last_seen_at: seen_at,
+            last_seen_uri: uri,
+        }
+    }
+}
+
+impl Default for Provenance {
+    fn default() -> Provenance {
+        use chrono::NaiveDateTime;
+        let default_datetime = DateTime::from_utc(
+            NaiveDateTime::from_num_seconds_from_unix_epoch(0, 0),
+            FixedOffset::east(0)
+        );
+        Provenance::new(default_datetime, String::new())
+    }
+}
+
+/// Merged provenances span both of them, i.e. the earliest first sighting
+/// and the latest last sighting win.
+impl Mergeable for Provenance {
+    fn merge_with(&mut self, other: Provenance) {
+        if other.first_seen_at < self.first_seen_at {
+            self.first_seen_at = other.first_seen_at;
+            self.first_seen_uri = other.first_seen_uri;
+        }
+        if other.last_seen_at > self.last_seen_at {
+            self.last_seen_at = other.last_seen_at;
+            self.last_seen_uri = other.last_seen_uri;
+        }
+        // sightings of a clock going backwards still make a valid span
+        let (first, last) = (self.first_seen_at, self.last_seen_at);
+        self.first_seen_at = min(first, last);
+        self.last_seen_at = max(first, last);
+    }
+}
+
+fn decode_attr<B: io::BufRead>(element: &XmlElement<B>, key: &str)
+                               -> DecodeResult<DateTime<FixedOffset>> {
+    match codecs::RFC3339.decode(try!(element.get_attr(key))) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(DecodeError::SchemaError(e)),
+    }
+}
+
+impl FromSchemaReader for Provenance {
+    fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        self.first_seen_at = try!(decode_attr(&element, "first"));
+        self.first_seen_uri = try!(element.get_attr("first-uri")).to_owned();
+        self.last_seen_at = try!(decode_attr(&element, "last"));
+        self.last_seen_uri = try!(element.get_attr("last-uri")).to_owned();
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for Provenance {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let first = try!(format_datetime(&self.first_seen_at));
+        let last = try!(format_datetime(&self.last_seen_at));
+        try!(writer.write(element.ns("mark", MARK_XMLNS)
+                                 .attr("first", &first)
+                                 .attr("first-uri", &self.first_seen_uri)
+                                 .attr("last", &last)
+                                 .attr("last-uri", &self.last_seen_uri)));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Provenance;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Text};
+    use schema::{Mergeable, read, write};
+
+    #[test]
+    fn test_provenance() {
+        let t1 = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let t2 = FixedOffset::east(0).ymd(2013, 11, 7).and_hms(9, 0, 0);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("A"), t1);
+        assert_eq!(entry.first_seen_at(), None);
+        entry.seen = Some(Provenance::new(t2, "http://example.com/feed?p=2"));
+        let mut older = entry.clone();
+        older.seen = Some(Provenance::new(t1, "http://example.com/feed"));
+        entry.merge_with(older);
+        assert_eq!(entry.first_seen_at(), Some(t1));
+        assert_eq!(entry.last_seen_at(), Some(t2));
+        let seen = entry.seen.clone().unwrap();
+        assert_eq!(seen.first_seen_uri, "http://example.com/feed");
+        assert_eq!(seen.last_seen_uri, "http://example.com/feed?p=2");
+
+        let mut buf = vec![];
+        unwrap!(write(&entry, &mut buf));
+        assert!(String::from_utf8(buf.clone()).unwrap()
+                       .contains("<mark:seen"));
+        let written: Entry = unwrap!(read(&buf[..]));
+        assert_eq!(written.seen, Some(seen));
+    }
+}
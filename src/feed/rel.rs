@@ -0,0 +1,219 @@
+use std::default::Default;
+use std::fmt;
+use std::str::FromStr;
+
+/// The prefix of relation types in their IRI forms, e.g.
+/// `http://www.iana.org/assignments/relation/alternate` is the same as
+/// `alternate` ([RFC 4287 (section 4.2.7.2)][rfc-link-2]).
+///
+/// [rfc-link-2]: https://tools.ietf.org/html/rfc4287#section-4.2.7.2
+pub const IANA_RELATION_PREFIX: &'static str =
+    "http://www.iana.org/assignments/relation/";
+
+macro_rules! link_rels {
+    ($($(#[$attr:meta])* $variant:ident => $name:expr),+) => {
+        /// The relation type of `Link`.  It covers relations registered in
+        /// the [IANA Link Relations registry][iana] which are common in
+        /// feeds, and the others are kept as they are in `Other`.
+        ///
+        /// Registered relations are case-insensitive, and are normalized to
+        /// lowercase when they're parsed.
+        ///
+        /// [iana]: http://www.iana.org/assignments/link-relations/
+        #[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+        pub enum LinkRel {
+            $($(#[$attr])* $variant,)+
+
+            /// Relations which aren't registered, or not covered by this
+            /// type, e.g. multiple relations separated by spaces.
+            Other(String),
+        }
+
+        impl LinkRel {
+            /// The relation name, e.g. `"alternate"`.
+            pub fn as_str(&self) -> &str {
+                match *self {
+                    $(LinkRel::$variant => $name,)+
+                    LinkRel::Other(ref name) => name,
+                }
+            }
+
+            fn from_registered(name: &str) -> Option<LinkRel> {
+                $(
+                    if name.eq_ignore_ascii_case($name) {
+                        return Some(LinkRel::$variant);
+                    }
+                )+
+                None
+            }
+        }
+    }
+}
+
+link_rels! {
+    /// An alternate version of the resource, e.g. the web page of an entry.
+    /// It's the default.
+    #[default]
+    Alternate => "alternate",
+    /// The resource related to the resource.
+    Related => "related",
+    /// The resource itself, e.g. the URI of the feed document.
+    SelfLink => "self",
+    /// The related resource which is potentially large, e.g. podcasts.
+    Enclosure => "enclosure",
+    /// The source of the information of the resource.
+    Via => "via",
+    /// The [WebSub][] (formerly PubSubHubbub) hub of the feed.
+    ///
+    /// [WebSub]: https://www.w3.org/TR/websub/
+    Hub => "hub",
+    /// The first page of a paged feed ([RFC 5005][]).
+    ///
+    /// [RFC 5005]: https://tools.ietf.org/html/rfc5005
+    First => "first",
+    /// The last page of a paged feed.
+    Last => "last",
+    /// The next page of a paged feed.
+    Next => "next",
+    /// The previous page of a paged feed.
+    Previous => "previous",
+    /// The current (subscription) document of an archived feed.
+    Current => "current",
+    /// The previous archive document of an archived feed.
+    PrevArchive => "prev-archive",
+    /// The next archive document of an archived feed.
+    NextArchive => "next-archive",
+    /// The icon of the resource.
+    Icon => "icon",
+    /// The comments of the resource ([RFC 4685][]).
+    ///
+    /// [RFC 4685]: https://tools.ietf.org/html/rfc4685
+    Replies => "replies",
+    /// The license of the resource.
+    License => "license",
+    /// The editable version of the resource (Atom Publishing Protocol).
+    Edit => "edit",
+    /// The resource to support the author financially.
+    Payment => "payment",
+    /// [OpenSearch][] description document.
+    ///
+    /// [OpenSearch]: http://www.opensearch.org/
+    Search => "search"
+}
+
+impl LinkRel {
+    /// Whether the relation navigates between pages of a paged or archived
+    /// feed ([RFC 5005][]).
+    ///
+    /// [RFC 5005]: https://tools.ietf.org/html/rfc5005
+    pub fn is_paging(&self) -> bool {
+        matches!(*self,
+                 LinkRel::First | LinkRel::Last | LinkRel::Next |
+                 LinkRel::Previous | LinkRel::Current |
+                 LinkRel::PrevArchive | LinkRel::NextArchive)
+    }
+
+    /// Whether the relation contains the given relation, considering
+    /// multiple relations separated by spaces e.g. `"shortcut icon"`.
+    pub fn contains(&self, rel: &LinkRel) -> bool {
+        self == rel || match *self {
+            LinkRel::Other(ref names) => {
+                names.split(' ').any(|n| &LinkRel::from(n) == rel)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for LinkRel {
+    fn from(name: &'a str) -> LinkRel {
+        let name = name.trim();
+        let short = if name.len() > IANA_RELATION_PREFIX.len() &&
+                        name[..IANA_RELATION_PREFIX.len()]
+                            .eq_ignore_ascii_case(IANA_RELATION_PREFIX) {
+            &name[IANA_RELATION_PREFIX.len()..]
+        } else {
+            name
+        };
+        LinkRel::from_registered(short)
+            .unwrap_or_else(|| LinkRel::Other(name.to_owned()))
+    }
+}
+
+impl FromStr for LinkRel {
+    type Err = ();
+
+    /// Parse the relation.  It never fails; unknown relations become
+    /// `Other`.
+    fn from_str(s: &str) -> Result<LinkRel, ()> { Ok(LinkRel::from(s)) }
+}
+
+impl fmt::Display for LinkRel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl<'a> PartialEq<&'a str> for LinkRel {
+    fn eq(&self, other: &&'a str) -> bool {
+        let other = LinkRel::from(*other);
+        *self == other
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for LinkRel {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ::serde::Serializer
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for LinkRel {
+    fn deserialize<D>(deserializer: D) -> Result<LinkRel, D::Error>
+        where D: ::serde::Deserializer<'de>
+    {
+        let name: String = try!(::serde::Deserialize::deserialize(deserializer));
+        Ok(LinkRel::from(&name[..]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LinkRel;
+
+    #[test]
+    fn test_link_rel_from_str() {
+        assert_eq!("alternate".parse(), Ok(LinkRel::Alternate));
+        assert_eq!("Self".parse(), Ok(LinkRel::SelfLink));
+        assert_eq!(" prev-archive ".parse(), Ok(LinkRel::PrevArchive));
+        assert_eq!("http://www.iana.org/assignments/relation/enclosure"
+                       .parse(),
+                   Ok(LinkRel::Enclosure));
+        assert_eq!("alternte".parse(),
+                   Ok(LinkRel::Other("alternte".to_owned())));
+        assert_eq!(LinkRel::from("http://example.com/rel/custom"),
+                   LinkRel::Other("http://example.com/rel/custom".to_owned()));
+    }
+
+    #[test]
+    fn test_link_rel_display() {
+        assert_eq!(LinkRel::SelfLink.to_string(), "self");
+        assert_eq!(LinkRel::NextArchive.to_string(), "next-archive");
+        assert_eq!(LinkRel::Other("shortcut icon".to_owned()).to_string(),
+                   "shortcut icon");
+        assert!(LinkRel::Hub == "HUB");
+    }
+
+    #[test]
+    fn test_link_rel_contains() {
+        let rel = LinkRel::from("shortcut icon");
+        assert!(rel.contains(&LinkRel::Icon));
+        assert!(!rel.contains(&LinkRel::Alternate));
+        assert!(LinkRel::Icon.contains(&LinkRel::Icon));
+        assert!(LinkRel::Next.is_paging());
+        assert!(!LinkRel::Alternate.is_paging());
+    }
+}
@@ -3,18 +3,21 @@ use std::io;
 use std::ops::{Deref, DerefMut};
 
 use chrono::{DateTime, FixedOffset};
+use xml::writer::XmlEvent;
 
 use parser::base::{DecodeResult, XmlElement, XmlName};
-use schema::{FromSchemaReader, Mergeable};
+use schema::{FromSchemaReader, Mergeable, SchemaResult, ToSchemaWriter,
+             XmlWriter};
 
 use util::set_default;
 
-use super::{ATOM_XMLNS, Generator, Metadata, Text};
+use super::{ATOM_XMLNS, Generator, Metadata, Text, write_text_element};
 
 /// All metadata for `Feed` excepting `Feed.entries`.
 /// It corresponds to `atom:source` element of :rfc:`4287#section-4.2.10`
 /// (section 4.2.10).
-#[derive(Default)]
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Source {
     pub metadata: Metadata,
 
@@ -86,4 +89,27 @@ impl FromSchemaReader for Source {
     }
 }
 
+impl ToSchemaWriter for Source {
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        try!(self.metadata.write_children(writer));
+        if let Some(ref subtitle) = self.subtitle {
+            try!(subtitle.write_to(writer,
+                                   XmlEvent::start_element("subtitle")));
+        }
+        if let Some(ref generator) = self.generator {
+            try!(generator.write_to(writer,
+                                    XmlEvent::start_element("generator")));
+        }
+        if let Some(ref logo) = self.logo {
+            try!(write_text_element(writer, "logo", logo));
+        }
+        if let Some(ref icon) = self.icon {
+            try!(write_text_element(writer, "icon", icon));
+        }
+        Ok(())
+    }
+}
+
 impl_mergeable!(Source, metadata, subtitle, generator, logo, icon);
@@ -1,14 +1,18 @@
-use super::Blob;
+use super::{Blob, DebugBody, Direction, detect_direction};
 
-use std::borrow::ToOwned;
+use std::borrow::{Cow, ToOwned};
 use std::default::Default;
 use std::io;
 use std::fmt;
 
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
 use mimetype::MimeType;
 
+use sanitizer::{escape, strip_tags};
 use parser::base::{DecodeResult, DecodeError, XmlElement};
-use schema::{FromSchemaReader, Mergeable};
+use schema::{FromSchemaReader, Mergeable, SchemaResult, ToSchemaWriter,
+             XmlWriter};
 
 
 /// Text construct defined in :rfc:`4287#section-3.1` (section 3.1).
@@ -16,7 +20,9 @@ use schema::{FromSchemaReader, Mergeable};
 /// RFC: <https://tools.ietf.org/html/rfc4287#section-3.1>
 ///
 /// Note: It currently does not support `xhtml`.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Text {
     /// The plain text content.  It corresponds to :rfc:`4287#section-3.1.1.1` (section 3.1.1.1).
     ///
@@ -61,6 +67,48 @@ impl Text {
             Text::Html(_) => "html",
         }
     }
+
+    /// Decide the direction of the text, which can be used for HTML `dir`
+    /// attribute.  The `lang` e.g. `xml:lang` takes precedence over the
+    /// text itself.
+    pub fn direction(&self, lang: Option<&str>) -> Direction {
+        let html = match *self {
+            Text::Plain(_) => false,
+            Text::Html(_) => true,
+        };
+        detect_direction(self.as_str().unwrap(), html, lang)
+    }
+
+    /// Get the text for plain text contexts, e.g. notifications or window
+    /// titles.  Markup tags are stripped if it's HTML.
+    pub fn as_plain_text(&self) -> Cow<str> {
+        match *self {
+            Text::Plain(ref value) => Cow::Borrowed(value),
+            Text::Html(ref value) => Cow::Owned(strip_tags(value)),
+        }
+    }
+
+    /// Get the text for HTML contexts.  It's escaped if it's a plain text,
+    /// and the HTML text is returned as it is; use `sanitized_html()` for
+    /// HTML from untrusted feeds.
+    pub fn as_html(&self) -> Cow<str> {
+        match *self {
+            Text::Plain(ref value) => {
+                Cow::Owned(escape(value, true).to_string())
+            }
+            Text::Html(ref value) => Cow::Borrowed(value),
+        }
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let body = DebugBody(self.as_bytes());
+        match *self {
+            Text::Plain(_) => write!(f, "Plain({:?})", body),
+            Text::Html(_) => write!(f, "Html({:?})", body),
+        }
+    }
 }
 
 impl Default for Text {
@@ -69,11 +117,14 @@ impl Default for Text {
     }
 }
 
+/// It writes the value as it's stored; character references of HTML texts
+/// are never decoded, so it's as safe to put into HTML as it used to be.
+/// Use `as_plain_text()` or `as_html()` to get the text for the context it
+/// is displayed in.
 impl fmt::Display for Text {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Text::Plain(ref value) => write!(f, "{}", value),
-            // TODO: use sanitizer::clean_html()
             Text::Html(ref value) => write!(f, "{}", value),
         }
     }
@@ -114,7 +165,9 @@ impl super::HtmlBlob for Text {
     {
         match *self {
             Text::Plain(ref value) => {
-                let s = sanitizer::Escape(&value, sanitizer::QUOTE_BR);
+                let s = sanitizer::SoftWrap(
+                    sanitizer::Escape(&value, sanitizer::QUOTE_BR),
+                    ::render::DEFAULT_SOFT_WRAP);
                 Box::new(s) as Box<fmt::Display>
             }
             Text::Html(ref value) =>
@@ -142,6 +195,18 @@ impl FromSchemaReader for Text {
     }
 }
 
+impl ToSchemaWriter for Text {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.attr("type", self.type_())));
+        try!(writer.write(XmlEvent::characters(self.as_str().unwrap())));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
 impl Mergeable for Text { }
 
 
@@ -149,11 +214,20 @@ impl Mergeable for Text { }
 mod test {
     use super::Text;
 
+    #[test]
+    fn test_text_debug() {
+        assert_eq!(format!("{:?}", Text::plain("Hello")), "Plain(\"Hello\")");
+        let huge = Text::html(vec!["<p>Hello</p>"; 100].concat());
+        assert_eq!(format!("{:?}", huge), "Html(<1200 bytes>)");
+    }
+
     #[test]
     fn test_text_str() {
         assert_eq!(Text::plain("Hello world").to_string(), "Hello world");
         assert_eq!(Text::plain("<p>Hello <em>world</em></p>").to_string(),
                    "<p>Hello <em>world</em></p>");
+        assert_eq!(Text::html("&lt;script&gt;").to_string(),
+                   "&lt;script&gt;");
         /* TODO: should be enabled after sanitizer is always available
         assert_eq!(Text::html("Hello world").to_string(), "Hello world");
         assert_eq!(Text::html("<p>Hello <em>world</em></p>").to_string(),
@@ -163,6 +237,16 @@ mod test {
         */
     }
 
+    #[test]
+    fn test_text_contexts() {
+        let plain = Text::plain("<Fish & chips>");
+        let html = Text::html("<p>Fish &amp; <em>chips</em></p>");
+        assert_eq!(plain.as_plain_text(), "<Fish & chips>");
+        assert_eq!(plain.as_html(), "&lt;Fish &amp; chips&gt;");
+        assert_eq!(html.as_plain_text(), "Fish & chips");
+        assert_eq!(html.as_html(), "<p>Fish &amp; <em>chips</em></p>");
+    }
+
     macro_rules! assert_sanitized {
         ($text:expr, $expected:expr) => (
             assert_eq!($text.sanitized_html(None).to_string(), $expected);
@@ -0,0 +1,95 @@
+//! Replies to entries, e.g. comments to blog posts.
+//!
+//! Feeds tell where replies to an entry are, and how many there are, in a
+//! few ways.  Atom feeds use [Atom Threading Extensions][RFC 4685]:
+//! `atom:link` elements of `rel="replies"`, optionally with `thr:count`
+//! attributes, and the `thr:total` element.  RSS 2.0 feeds have the
+//! `comments` element for the page of comments, along with commonly used
+//! [`wfw:commentRss`][wfw] for the feed of comments and
+//! [`slash:comments`][slash] for the number of them.
+//!
+//! Parsers turn all of them into `LinkRel::Replies` links of the entry and
+//! `Entry::reply_count`, and `Entry::replies()` gives both together.
+//!
+//! [RFC 4685]: https://tools.ietf.org/html/rfc4685
+//! [wfw]: http://www.sixapart.com/pronet/weblog/2003/09/wfw_commentrss.html
+//! [slash]: http://web.resource.org/rss/1.0/modules/slash/
+use super::{Link, LinkRel};
+
+/// The XML namespace name used for [Atom Threading Extensions][RFC 4685].
+///
+/// [RFC 4685]: https://tools.ietf.org/html/rfc4685
+pub const THREADING_XMLNS: &'static str =
+    "http://purl.org/syndication/thread/1.0";
+
+/// The XML namespace name of the [Well-Formed Web][wfw] comment API.
+///
+/// [wfw]: http://wellformedweb.org/CommentAPI/
+pub const WFW_XMLNS: &'static str = "http://wellformedweb.org/CommentAPI/";
+
+/// The XML namespace name of the [Slash][slash] module of RSS.
+///
+/// [slash]: http://web.resource.org/rss/1.0/modules/slash/
+pub const SLASH_XMLNS: &'static str = "http://purl.org/rss/1.0/modules/slash/";
+
+/// Where replies to an entry are, and how many.  See `Entry::replies()`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Replies<'a> {
+    /// `LinkRel::Replies` links of the entry, e.g. to the page or the feed
+    /// of replies, in order.
+    pub links: Vec<&'a Link>,
+
+    /// The total number of replies, if the feed tells.
+    pub count: Option<u64>,
+}
+
+impl<'a> Replies<'a> {
+    pub fn from_links<I>(links: I, count: Option<u64>) -> Replies<'a>
+        where I: IntoIterator<Item=&'a Link>
+    {
+        Replies {
+            links: links.into_iter()
+                        .filter(|l| l.relation == LinkRel::Replies)
+                        .collect(),
+            count: count,
+        }
+    }
+
+    /// Whether the feed tells nothing about replies.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty() && self.count.is_none()
+    }
+
+    /// The link to show people for replies, i.e. the first HTML one, or
+    /// the first one of any type if there's no HTML one.
+    pub fn page(&self) -> Option<&'a Link> {
+        self.links.iter().find(|l| l.is_html()).or(self.links.first())
+                  .copied()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Replies;
+
+    use feed::{Link, LinkRel};
+
+    #[test]
+    fn test_replies() {
+        let mut feed = Link::new("http://example.com/1/comments.xml");
+        feed.relation = LinkRel::Replies;
+        feed.mimetype = Some("application/rss+xml".into());
+        let mut page = Link::new("http://example.com/1#comments");
+        page.relation = LinkRel::Replies;
+        page.mimetype = Some("text/html".into());
+        let links = vec![Link::new("http://example.com/1"), feed, page];
+        let replies = Replies::from_links(&links, Some(12));
+        assert_eq!(replies.links, [&links[1], &links[2]]);
+        assert_eq!(replies.page(), Some(&links[2]));
+        assert!(!replies.is_empty());
+        let replies = Replies::from_links(&links[..2], None);
+        assert_eq!(replies.page(), Some(&links[1]));
+        assert!(Replies::from_links(&links[..1], None).is_empty());
+    }
+}
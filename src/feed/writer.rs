@@ -0,0 +1,151 @@
+//! Writing feed documents entry by entry, without having the whole `Feed`.
+use std::io;
+
+use xml;
+use xml::writer::XmlEvent;
+
+use schema::{SchemaResult, ToSchemaWriter, XmlWriter};
+
+use super::{ATOM_XMLNS, MARK_XMLNS, Entry, Link, Source};
+use super::paging::HISTORY_XMLNS;
+
+/// Writes an Atom feed document to the stream as its entries are given,
+/// e.g. to store thousands of entries read from another archive.  The
+/// document is the same as `schema::write()` writes for the `Feed`.
+///
+/// ```
+/// # extern crate chrono;
+/// # extern crate earth;
+/// # fn main() {
+/// use chrono::{FixedOffset, TimeZone};
+/// use earth::feed::{Entry, FeedWriter, Source, Text};
+///
+/// let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+/// let source = Source::new_inherited("urn:feed".to_owned(),
+///                                    Text::plain("Feed"), t);
+/// let mut writer = FeedWriter::start_feed(vec![], &source).unwrap();
+/// for i in 0..3 {
+///     let entry = Entry::new(format!("urn:entry:{}", i),
+///                            Text::plain("Entry"), t);
+///     writer.write_entry(&entry).unwrap();
+/// }
+/// let xml = String::from_utf8(writer.finish().unwrap()).unwrap();
+/// assert!(xml.contains("<id>urn:entry:2</id>"));
+/// # }
+/// ```
+pub struct FeedWriter<W: io::Write> {
+    writer: XmlWriter<W>,
+    entries: usize,
+}
+
+impl<W: io::Write> FeedWriter<W> {
+    /// Start the feed document with metadata of the `source`, e.g.
+    /// `Feed::source`.
+    pub fn start_feed(w: W, source: &Source) -> SchemaResult<FeedWriter<W>> {
+        FeedWriter::start(w, source, None)
+    }
+
+    /// Start a page of the paged feed like `paging::Page` writes, with
+    /// `links` to the other pages; see `paging::page_links()`.
+    pub fn start_page(w: W, source: &Source, links: &[Link], archive: bool)
+                      -> SchemaResult<FeedWriter<W>> {
+        FeedWriter::start(w, source, Some((links, archive)))
+    }
+
+    fn start(w: W, source: &Source, page: Option<(&[Link], bool)>)
+             -> SchemaResult<FeedWriter<W>> {
+        let mut writer = xml::EventWriter::new(w);
+        try!(writer.write(XmlEvent::StartDocument {
+            version: xml::common::XmlVersion::Version10,
+            encoding: Some("utf-8"),
+            standalone: None,
+        }));
+        let element = XmlEvent::start_element("feed").default_ns(ATOM_XMLNS)
+                                                     .ns("mark", MARK_XMLNS);
+        match page {
+            Some((links, archive)) => {
+                try!(writer.write(element.ns("fh", HISTORY_XMLNS)));
+                try!(source.write_children(&mut writer));
+                for link in links {
+                    try!(link.write_to(&mut writer,
+                                       XmlEvent::start_element("link")));
+                }
+                if archive {
+                    try!(writer.write(XmlEvent::start_element("fh:archive")));
+                    try!(writer.write(XmlEvent::end_element()));
+                }
+            }
+            None => {
+                try!(writer.write(element));
+                try!(source.write_children(&mut writer));
+            }
+        }
+        Ok(FeedWriter { writer: writer, entries: 0 })
+    }
+
+    pub fn write_entry(&mut self, entry: &Entry) -> SchemaResult<()> {
+        try!(entry.write_to(&mut self.writer,
+                            XmlEvent::start_element("entry")));
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// The number of entries written so far.
+    pub fn entries(&self) -> usize { self.entries }
+
+    /// Close the document, and return the stream.
+    pub fn finish(mut self) -> SchemaResult<W> {
+        try!(self.writer.write(XmlEvent::end_element()));
+        Ok(self.writer.into_inner())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::FeedWriter;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Text};
+    use feed::paging::{page_links, paginate};
+    use schema;
+
+    fn fx_feed() -> Feed {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        for i in 0..3 {
+            feed.entries.push(Entry::new(format!("urn:entry:{}", i),
+                                         Text::plain("Entry"), t));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_feed_writer() {
+        let feed = fx_feed();
+        let mut expected = vec![];
+        unwrap!(schema::write(&feed, &mut expected));
+        let mut writer = unwrap!(FeedWriter::start_feed(vec![],
+                                                        &feed.source));
+        for entry in feed.entries.iter() {
+            unwrap!(writer.write_entry(entry));
+        }
+        assert_eq!(writer.entries(), 3);
+        assert_eq!(unwrap!(writer.finish()), expected);
+    }
+
+    #[test]
+    fn test_feed_writer_page() {
+        let feed = fx_feed();
+        let href = |i| format!("page{}", i);
+        let pages = paginate(&feed, 2, href);
+        let mut expected = vec![];
+        unwrap!(schema::write(&pages[1], &mut expected));
+        let links = page_links(1, false, href);
+        let mut writer = unwrap!(FeedWriter::start_page(vec![], &feed.source,
+                                                        &links, true));
+        unwrap!(writer.write_entry(&feed.entries[2]));
+        assert_eq!(unwrap!(writer.finish()), expected);
+    }
+}
@@ -0,0 +1,267 @@
+//! Running long operations e.g. crawling in background threads, so that
+//! apps can integrate them into their own event loops (e.g. iOS run loops,
+//! Android loopers) without blocking UI threads.
+//!
+//! Jobs are consumed either by polling:
+//!
+//! ```
+//! # use earth::job;
+//! let mut handle = job::spawn(|| 1 + 1);
+//! loop {
+//!     if let Some(result) = handle.poll() {
+//!         assert_eq!(result, 2);
+//!         break;
+//!     }
+//!     // do other things in the event loop
+//! }
+//! ```
+//!
+//! or by getting notified, and then polling on the event loop:
+//!
+//! ```
+//! # use earth::job;
+//! # use std::sync::mpsc::channel;
+//! let (wake, woken) = channel();
+//! let mut handle = job::spawn_with_notify(|| 1 + 1, move || {
+//!     wake.send(()).unwrap();
+//! });
+//! woken.recv().unwrap();
+//! assert_eq!(handle.poll(), Some(2));
+//! ```
+//!
+//! Mobile OSes kill background tasks which run too long, so long operations
+//! e.g. `Stage::refresh_all()` take a `CancellationToken`, and stop at the
+//! next safe point when it's cancelled from another thread.  What they've
+//! done so far is left consistent, so that they can be resumed by being
+//! called again.  They also report how far they went to a
+//! `ProgressReporter`, so that UIs can render progress bars.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+use std::thread;
+
+/// The flag to tell long operations to stop.  Clones share the flag, so a
+/// clone can be moved to a job while the app keeps the other to cancel it.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken { Default::default() }
+
+    /// Tell operations taking the token or its clones to stop.  It can't be
+    /// undone; make a new token for following operations.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// What a long operation is doing, which is reported to `ProgressReporter`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Phase {
+    /// Feeds are being crawled e.g. by `Stage::refresh_all()`.
+    Crawling,
+
+    /// Documents are being imported e.g. by `Stage::import_repository()`.
+    Importing,
+
+    /// Feeds are being exported e.g. by `Stage::export_marks()`.
+    Exporting,
+
+    /// Links are being checked e.g. by `Stage::check_starred_links()`.
+    CheckingLinks,
+}
+
+/// What long operations report their progress to.  Every method does
+/// nothing by default, and `()` reports nowhere, so `&mut ()` can be passed
+/// to ignore progress.  Closures taking the same arguments as `report()`
+/// are reporters as well.
+pub trait ProgressReporter {
+    /// `done` items of the `total` were processed in the `phase`.  `total`
+    /// is `None` if it's unknown.  It's reported once with `done` of 0
+    /// before the first item, and after each item.
+    fn report(&mut self, phase: Phase, done: usize, total: Option<usize>) {
+        let _ = (phase, done, total);
+    }
+}
+
+impl ProgressReporter for () { }
+
+impl<F> ProgressReporter for F
+    where F: FnMut(Phase, usize, Option<usize>)
+{
+    fn report(&mut self, phase: Phase, done: usize, total: Option<usize>) {
+        self(phase, done, total)
+    }
+}
+
+/// The state of a job.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JobState {
+    /// The job is still running.
+    Running,
+
+    /// The job finished, and its result is ready to be taken.
+    Finished,
+
+    /// The result was already taken, or the job panicked.
+    Done,
+}
+
+/// The handle to a job running in the background.  Dropping the handle
+/// doesn't stop the job; its result is simply discarded.
+pub struct JobHandle<T> {
+    receiver: Receiver<T>,
+    result: Option<T>,
+    state: JobState,
+}
+
+impl<T: Send + 'static> JobHandle<T> {
+    fn receive(&mut self) {
+        if self.state != JobState::Running {
+            return;
+        }
+        match self.receiver.try_recv() {
+            Ok(result) => {
+                self.result = Some(result);
+                self.state = JobState::Finished;
+            }
+            Err(TryRecvError::Empty) => { }
+            Err(TryRecvError::Disconnected) => {
+                self.state = JobState::Done;
+            }
+        }
+    }
+
+    /// The current state of the job.  It never blocks.
+    pub fn state(&mut self) -> JobState {
+        self.receive();
+        self.state
+    }
+
+    /// Take the result if the job finished.  It never blocks, and returns
+    /// `None` if the job is still running, or the result was already taken.
+    pub fn poll(&mut self) -> Option<T> {
+        self.receive();
+        let result = self.result.take();
+        if result.is_some() {
+            self.state = JobState::Done;
+        }
+        result
+    }
+
+    /// Block until the job finishes, and return its result.
+    ///
+    /// # Panics
+    ///
+    /// It panics if the job panicked, or the result was already taken by
+    /// `poll()`.
+    pub fn join(mut self) -> T {
+        if let Some(result) = self.result.take() {
+            return result;
+        }
+        match self.receiver.recv() {
+            Ok(result) => result,
+            Err(_) => panic!("the job panicked or its result was taken"),
+        }
+    }
+}
+
+/// Run the function in a new background thread.
+pub fn spawn<T, F>(f: F) -> JobHandle<T>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static
+{
+    spawn_with_notify(f, || ())
+}
+
+/// Run the function in a new background thread, and call `notify` in
+/// the thread when it finishes.  `notify` is called *after* the result
+/// becomes ready to `poll()`, so it can e.g. post an event to wake the
+/// app's event loop up.
+pub fn spawn_with_notify<T, F, N>(f: F, notify: N) -> JobHandle<T>
+    where T: Send + 'static,
+          F: FnOnce() -> T + Send + 'static,
+          N: FnOnce() + Send + 'static
+{
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let result = f();
+        // the handle might have been dropped; the result is just discarded
+        let _ = sender.send(result);
+        notify();
+    });
+    JobHandle { receiver: receiver, result: None, state: JobState::Running }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{CancellationToken, JobState, Phase, ProgressReporter, spawn};
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_job_poll() {
+        let (start, started) = channel::<()>();
+        let mut handle = spawn(move || {
+            started.recv().unwrap();
+            "done"
+        });
+        assert_eq!(handle.state(), JobState::Running);
+        assert_eq!(handle.poll(), None);
+        start.send(()).unwrap();
+        let result = loop {
+            if let Some(result) = handle.poll() {
+                break result;
+            }
+        };
+        assert_eq!(result, "done");
+        assert_eq!(handle.state(), JobState::Done);
+        assert_eq!(handle.poll(), None);
+    }
+
+    #[test]
+    fn test_job_join() {
+        let handle = spawn(|| 42);
+        assert_eq!(handle.join(), 42);
+        let mut handle = spawn(|| -> u32 { panic!("failed") });
+        while handle.state() == JobState::Running { }
+        assert_eq!(handle.state(), JobState::Done);
+        assert_eq!(handle.poll(), None);
+    }
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        let handle = spawn(move || {
+            while !clone.is_cancelled() { }
+            "cancelled"
+        });
+        assert!(!token.is_cancelled());
+        token.cancel();
+        assert_eq!(handle.join(), "cancelled");
+        assert!(token.is_cancelled());
+        assert!(!CancellationToken::new().is_cancelled());
+    }
+
+    #[test]
+    fn test_progress_reporter() {
+        let mut reports = vec![];
+        {
+            let mut reporter = |phase, done, total| {
+                reports.push((phase, done, total));
+            };
+            reporter.report(Phase::Crawling, 0, Some(2));
+            reporter.report(Phase::Crawling, 1, Some(2));
+        }
+        assert_eq!(reports, [(Phase::Crawling, 0, Some(2)),
+                             (Phase::Crawling, 1, Some(2))]);
+        ().report(Phase::Importing, 1, None);
+    }
+}
@@ -8,6 +8,13 @@
 //! actually implements.  Rust-earth is developing to cover the corner cases
 //! which libearth cannot be easily included like mobile devices.
 //!
+//! For such devices the crate is slimmed by cargo features: only the feed
+//! model, the parser, repositories and the stage are built by default, and
+//! the `crawler`, `search` (the `query` module) and `export` (marks export
+//! and the `export` module) subsystems are opt-in.  Without default
+//! features, only the feed model, its schema and codecs are built, and the
+//! `parser` and `repository` layers can be enabled one by one.
+//!
 //! [libearth]: https://github.com/earthreader/libearth
 //! [Earth Reader]: http://earthreader.org/
 //! [web]: https://github.com/earthreader/web
@@ -19,23 +26,37 @@
 
 extern crate chrono;
 extern crate regex;
-extern crate tempdir;
+#[cfg(feature = "repository")] extern crate chacha20poly1305;
+#[cfg(feature = "repository")] extern crate tempdir;
 extern crate url;
 extern crate xml;
 
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(all(test, feature = "serde"))] extern crate serde_json;
+
 #[cfg(html_sanitizer)] extern crate html5ever;
 #[cfg(html_sanitizer)] extern crate string_cache;
 
 pub mod macros;
 pub mod test_utils;
 
+#[cfg(feature = "repository")] pub mod blobs;
 pub mod codecs;
+#[cfg(feature = "crawler")] pub mod crawler;
+pub mod digest;
+#[cfg(feature = "export")] pub mod export;
 pub mod feed;
 pub mod html;
+pub mod job;
 pub mod mimetype;
 pub mod parser;
-pub mod repository;
+#[cfg(feature = "search")] pub mod query;
+pub mod render;
+#[cfg(feature = "repository")] pub mod repository;
 pub mod sanitizer;
 pub mod schema;
-pub mod stage;
+#[cfg(feature = "repository")] pub mod secrets;
+#[cfg(feature = "repository")] pub mod stage;
+pub mod stats;
+pub mod subscribe;
 pub mod util;
@@ -1,9 +1,9 @@
-use std::borrow::ToOwned;
+use std::borrow::{Cow, ToOwned};
 use std::fmt;
 
 use regex;
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum MimeType {
     Text,
     Html,
@@ -11,6 +11,35 @@ pub enum MimeType {
     Other(String),
 }
 
+/// Media types common in feeds, which `intern()` doesn't allocate for.
+pub const COMMON_MIMETYPES: &'static [&'static str] = &[
+    "text/html", "text/plain", "application/xhtml+xml",
+    "application/atom+xml", "application/rss+xml", "application/xml",
+    "text/xml", "application/json", "application/pdf",
+    "audio/mpeg", "audio/mp4", "audio/x-m4a", "audio/ogg",
+    "video/mp4", "video/x-m4v", "video/webm",
+    "image/jpeg", "image/png", "image/gif", "image/svg+xml",
+    "image/x-icon", "image/vnd.microsoft.icon",
+];
+
+/// Get the media type as a static string if it's one of
+/// `COMMON_MIMETYPES`, so that the same media types repeated in a large
+/// archive don't allocate each time.  The others are copied as they are.
+///
+/// ```
+/// # use std::borrow::Cow;
+/// # use earth::mimetype::intern;
+/// assert!(match intern("audio/mpeg") { Cow::Borrowed(_) => true,
+///                                      Cow::Owned(_) => false });
+/// assert_eq!(intern("audio/x-unknown"), "audio/x-unknown");
+/// ```
+pub fn intern(mimetype: &str) -> Cow<'static, str> {
+    match COMMON_MIMETYPES.iter().find(|&&m| m == mimetype) {
+        Some(&m) => Cow::Borrowed(m),
+        None => Cow::Owned(mimetype.to_owned()),
+    }
+}
+
 fn mimetype_pattern() -> regex::Regex {
     regex::Regex::new(concat!(
         r#"^"#,
@@ -58,3 +87,26 @@ impl fmt::Display for MimeType {
         write!(f, "{}", self.mimetype())
     }
 }
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for MimeType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ::serde::Serializer
+    {
+        serializer.serialize_str(self.mimetype())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for MimeType {
+    fn deserialize<D>(deserializer: D) -> Result<MimeType, D::Error>
+        where D: ::serde::Deserializer<'de>
+    {
+        use serde::de::Error;
+        let mimetype: String =
+            try!(::serde::Deserialize::deserialize(deserializer));
+        MimeType::from_str(&mimetype).ok_or_else(|| {
+            D::Error::custom(format!("invalid mimetype: {:?}", mimetype))
+        })
+    }
+}
@@ -4,27 +4,30 @@
 //!
 //! [RFC 4287]: https://tools.ietf.org/html/rfc4287
 //!
-//! ### Todo
-//!
-//! Parsing text construct which `type` is `"xhtml"`.
+//! Text constructs and contents of `type="xhtml"` are read as HTML, without
+//! the `div` element wrapping them.
 use std::borrow::{Cow, ToOwned};
 use std::default::Default;
 use std::io;
-use std::str::FromStr;
 
 use chrono::{DateTime, FixedOffset};
+use url::Url;
 use xml;
 
+use super::{ParseDiagnostics, ParseOptions, Warning};
 use super::base::{NestedEventReader, DecodeError, DecodeResult,
-                  XmlAttribute, XmlElement, XmlName};
+                  XmlAttribute, XmlElement, XmlName, write_event_xml};
 use super::base::DecodeError::{AttributeNotFound, SchemaError};
-use super::base::NestedEvent::{EndDocument, Nested};
+use super::base::NestedEvent::{EndDocument, Nested, Whitespace};
+use super::diagnostics::fix_datetime;
+use super::geo::GeoReader;
 use feed;
 use codecs;
-use mimetype::MimeType;
+use mimetype::{self, MimeType};
 use schema::Codec;
 
-static ATOM_XMLNS_SET: [&'static str; 2] = [
+/// XML namespace names of Atom 1.0 and pre-standard Atom 0.3.
+pub static ATOM_XMLNS_SET: [&'static str; 2] = [
     "http://www.w3.org/2005/Atom",
     "http://purl.org/atom/ns#",
 ];
@@ -35,6 +38,7 @@ static XML_XMLNS: &'static str = "http://www.w3.org/XML/1998/namespace";
 struct AtomSession<'a> {
     xml_base: Cow<'a, str>,
     element_ns: Cow<'a, str>,
+    diagnostics: &'a ParseDiagnostics,
 }
 
 impl<'a> AtomSession<'a> {
@@ -45,8 +49,10 @@ impl<'a> AtomSession<'a> {
     }
 }
 
-pub fn parse_atom<B: io::BufRead>(xml: B, feed_url: &str, need_entries: bool)
-                             -> DecodeResult<feed::Feed>
+pub fn parse_atom<B: io::BufRead>(xml: B, feed_url: &str,
+                                  options: &ParseOptions,
+                                  diagnostics: &ParseDiagnostics)
+                                  -> DecodeResult<feed::Feed>
 {
     let mut parser = xml::EventReader::new(xml);
     let mut events = NestedEventReader::new(&mut parser);
@@ -62,19 +68,25 @@ pub fn parse_atom<B: io::BufRead>(xml: B, feed_url: &str, need_entries: bool)
                         .unwrap_or(feed_url);
                     AtomSession {
                         xml_base: xml_base.to_owned().into(),
-                        element_ns: (*atom_xmlns).into()
+                        element_ns: (*atom_xmlns).into(),
+                        diagnostics: diagnostics,
                     }
                 };
-                let feed_data = parse_feed(element, feed_url,
-                                           need_entries, session);
+                let feed_data = parse_feed(element, feed_url, options,
+                                           session);
                 result = Some(feed_data);
             }
-            EndDocument => { panic!(); }
+            EndDocument => { break; }
             _ => { }
         }
     }
     match result {
-        Some(Ok(r)) => Ok(r),
+        Some(Ok(mut r)) => {
+            if options.inherit_authors {
+                r.inherit_authors();
+            }
+            Ok(r)
+        }
         Some(Err(e)) => Err(e),
         None => Err(DecodeError::NoResult),
     }
@@ -132,16 +144,27 @@ macro_rules! assign_field {
 }
 
 fn parse_feed<B: io::BufRead>(mut element: XmlElement<B>, feed_url: &str,
-                         need_entries: bool, session: AtomSession)
+                         options: &ParseOptions, session: AtomSession)
                          -> DecodeResult<feed::Feed> {
     let mut feed: feed::Feed = Default::default();
+    let mut index = 0;
     while let Some(event) = element.children.next() {
         if let Nested { name, element: child } = try!(event) {
-            if need_entries && name_matches(&name,
-                                            Some(&session.element_ns),
-                                            "entry") {
-                let result = try!(parse_entry(child, session.clone()));
-                feed.entries.push(result);
+            if options.need_entries && name_matches(&name,
+                                                    Some(&session.element_ns),
+                                                    "entry") {
+                let mut entry = Default::default();
+                match parse_entry(child, options, session.clone(),
+                                  &mut entry) {
+                    Ok(true) => { feed.entries.push(entry); }
+                    Ok(false) => { }
+                    Err(e) => {
+                        let id = if entry.id.is_empty() { None }
+                                 else { Some(&entry.id[..]) };
+                        try!(session.diagnostics.invalid_entry(index, id, e));
+                    }
+                }
+                index += 1;
                 continue;
             }
             parse_field! {
@@ -171,25 +194,72 @@ fn parse_feed<B: io::BufRead>(mut element: XmlElement<B>, feed_url: &str,
     Ok(feed)
 }
 
-fn parse_entry<B: io::BufRead>(mut element: XmlElement<B>, session: AtomSession)
-                          -> DecodeResult<feed::Entry> {
-    let mut entry: feed::Entry = Default::default();
-    parse_fields! { (entry, element, session)
-        "id"          => id:           required     by parse_icon;
-        "title"       => title:        required     by parse_text_construct;
-        "link"        => links:        multiple     by parse_link;
-        "updated"     => updated_at:   required     by parse_datetime;
-        "modified"    => updated_at:   required     by parse_datetime;
-        "author"      => authors:      multiple_opt by parse_person_construct;
-        "contributor" => contributors: multiple_opt by parse_person_construct;
-        "category"    => categories:   multiple     by parse_category;
-        "rights"      => rights:       optional     by parse_text_construct;
-        "published"   => published_at: optional     by parse_datetime;
-        "summary"     => summary:      optional     by parse_text_construct;
-        "content"     => content:      optional     by parse_content;
-        "source"      => source:       optional     by parse_source;
+/// Parse the entry into `entry`, which is left partially parsed if it
+/// fails.  It's `false` if it's skipped as known; see
+/// `ParseOptions::known_entries`.
+fn parse_entry<B: io::BufRead>(mut element: XmlElement<B>,
+                               options: &ParseOptions, session: AtomSession,
+                               entry: &mut feed::Entry) -> DecodeResult<bool> {
+    let mut geo: GeoReader = Default::default();
+    let mut link_count: Option<u64> = None;
+    let (mut has_id, mut has_updated) = (false, false);
+    while let Some(event) = element.children.next() {
+        if let Nested { name, element: child } = try!(event) {
+            if GeoReader::matches(&name) {
+                try!(geo.read(&name, child));
+                continue;
+            } else if name_matches(&name, Some(feed::THREADING_XMLNS),
+                                   "total") {
+                let text = try!(child.read_whole_text());
+                entry.reply_count = text.trim().parse().ok();
+                continue;
+            } else if name.local_name == "link" {
+                let count = try!(session.diagnostics.parse_attr(&child,
+                                                                "count"));
+                link_count = link_count.into_iter().chain(count).max();
+            }
+            parse_field! {
+                (entry, name, child, session)
+                "id"          => id:           required by parse_icon;
+                "title"       => title:        required
+                                 by parse_text_construct;
+                "link"        => links:        multiple by parse_link;
+                "updated"     => updated_at:   required by parse_datetime;
+                "modified"    => updated_at:   required by parse_datetime;
+                "author"      => authors:      multiple_opt
+                                 by parse_person_construct;
+                "contributor" => contributors: multiple_opt
+                                 by parse_person_construct;
+                "category"    => categories:   multiple by parse_category;
+                "rights"      => rights:       optional
+                                 by parse_text_construct;
+                "published"   => published_at: optional by parse_datetime;
+                "summary"     => summary:      optional
+                                 by parse_text_construct;
+                "content"     => content:      optional by parse_content;
+                "source"      => source:       optional by parse_source;
+            }
+            match &name.local_name[..] {
+                "id" => { has_id = true; }
+                "updated" | "modified" => { has_updated = true; }
+                _ => { continue; }
+            }
+            if has_id && has_updated &&
+               options.is_known(&entry.id, &entry.updated_at) {
+                // the rest of the entry is drained when it's dropped
+                session.diagnostics.skip_entry(&entry.id);
+                return Ok(false);
+            }
+        }
     }
-    Ok(entry)
+    entry.location = geo.location();
+    // thr:count of each link is the number of replies there, so the
+    // largest one is the closest to thr:total if it's missing
+    entry.reply_count = entry.reply_count.or(link_count);
+    let guid = entry.id.clone();
+    let date = entry.published_at.or(Some(entry.updated_at));
+    entry.id = options.entry_id(entry, Some(&guid), date);
+    Ok(true)
 }
 
 fn parse_source<B: io::BufRead>(mut element: XmlElement<B>,
@@ -215,27 +285,72 @@ fn parse_source<B: io::BufRead>(mut element: XmlElement<B>,
 fn parse_icon<B: io::BufRead>(element: XmlElement<B>, mut session: AtomSession)
                          -> DecodeResult<String> {
     session.reset_xml_base(&element.attributes);
-    let mut xml_base = session.xml_base.into_owned();
-    xml_base.push_str(&try!(element.read_whole_text())[..]);
-    Ok(xml_base)
+    let text = try!(element.read_whole_text());
+    Ok(resolve_uri(&session.xml_base, text.trim()))
+}
+
+/// Resolve the URI reference against `xml:base`.  It's returned as it is
+/// if the base isn't an absolute URI.
+fn resolve_uri(base: &str, uri: &str) -> String {
+    match Url::parse(base).and_then(|b| b.join(uri)) {
+        Ok(resolved) => resolved.into_string(),
+        Err(_) => uri.to_owned(),
+    }
 }
 
 fn parse_text_construct<B: io::BufRead>(element: XmlElement<B>,
-                                   _session: AtomSession)
+                                   session: AtomSession)
                                    -> DecodeResult<feed::Text>
 {
     let text_type = match element.get_attr("type") {
         Ok("text/plaln") | Ok("text") => "text",
         Ok("text/html") | Ok("html") => "html",
         Ok("application/xhtml+xml") | Ok("xhtml") => "xhtml",
-        Ok(_) => "text",
+        Ok(type_) => {
+            let warning = Warning::UnknownType(type_.to_owned());
+            try!(session.diagnostics.warn(warning));
+            "text"
+        }
         Err(AttributeNotFound(_)) => "text",
         Err(e) => { return Err(e); }
     };
-    let text = feed::Text::new(text_type, try!(element.read_whole_text()));
+    let text = if text_type == "xhtml" {
+        feed::Text::html(try!(read_xhtml(element)))
+    } else {
+        feed::Text::new(text_type, try!(element.read_whole_text()))
+    };
     Ok(text)
 }
 
+/// Read the XHTML of the text construct or the content.  The `div` element
+/// wrapping it, which :rfc:`4287#section-3.1.1.3` requires, is unwrapped if
+/// there is.
+fn read_xhtml<B: io::BufRead>(element: XmlElement<B>)
+                              -> DecodeResult<String> {
+    let XmlElement { namespace, mut children, .. } = element;
+    let mut xml = String::new();
+    let mut unwrapped = false;
+    while let Some(event) = children.next() {
+        let event = try!(event);
+        let is_wrapper = match event {
+            Nested { ref name, .. } => {
+                !unwrapped && name.local_name == "div" &&
+                    xml.trim().is_empty()
+            }
+            _ => false,
+        };
+        match event {
+            Nested { element: div, .. } if is_wrapper => {
+                xml = try!(div.read_inner_xml());
+                unwrapped = true;
+            }
+            Whitespace(_) if unwrapped => { }
+            event => { try!(write_event_xml(event, &namespace, &mut xml)); }
+        }
+    }
+    Ok(xml)
+}
+
 fn parse_person_construct<B: io::BufRead>(mut element: XmlElement<B>,
                                      mut session: AtomSession)
                                      -> DecodeResult<Option<feed::Person>> {
@@ -274,27 +389,35 @@ fn parse_link<B: io::BufRead>(element: XmlElement<B>, mut session: AtomSession)
     session.reset_xml_base(&element.attributes);
     Ok(feed::Link {
         uri: try!(element.get_attr("href")).to_string(),
-        relation: element.get_attr("rel").unwrap_or("alternate").to_string(),
-        mimetype: element.get_attr("type").ok().map(|v| v.to_string()),
+        relation: feed::LinkRel::from(element.get_attr("rel")
+                                             .unwrap_or("alternate")),
+        mimetype: element.get_attr("type").ok().map(mimetype::intern),
         language: element.get_attr("hreflang").ok().map(|v| v.to_string()),
         title: element.get_attr("title").ok().map(|v| v.to_string()),
-        byte_size: element.get_attr("length").ok()
-                          .and_then(|v| FromStr::from_str(v).ok()),
+        byte_size: try!(session.diagnostics.parse_attr(&element, "length")),
     })
 }
 
-fn parse_datetime<B: io::BufRead>(element: XmlElement<B>, _session: AtomSession)
+fn parse_datetime<B: io::BufRead>(element: XmlElement<B>, session: AtomSession)
                              -> DecodeResult<DateTime<FixedOffset>> {
-    match codecs::RFC3339.decode(&*try!(element.read_whole_text())) {
+    let text = try!(element.read_whole_text());
+    match codecs::RFC3339.decode(&text) {
         Ok(v) => Ok(v),
-        Err(e) => Err(SchemaError(e)),
+        Err(e) => match fix_datetime(&text) {
+            Some(v) => {
+                let warning = Warning::FixedDate(text.trim().to_owned());
+                try!(session.diagnostics.warn(warning));
+                Ok(v)
+            }
+            None => Err(SchemaError(e)),
+        },
     }
 }
 
 fn parse_category<B: io::BufRead>(element: XmlElement<B>, _session: AtomSession)
                              -> DecodeResult<feed::Category> {
     Ok(feed::Category {
-        term: try!(element.get_attr("term")).to_string(),
+        term: try!(element.get_attr("term")).to_string().into(),
         scheme_uri: element.get_attr("scheme").ok().map(|v| v.to_string()),
         label: element.get_attr("label").ok().map(|v| v.to_string()),
     })
@@ -319,12 +442,20 @@ fn parse_content<B: io::BufRead>(element: XmlElement<B>, mut session: AtomSessio
         Ok("text/plaln") | Ok("text") => MimeType::Text,
         Ok("text/html") | Ok("html") => MimeType::Html,
         Ok("application/xhtml+xml") | Ok("xhtml") => MimeType::Xhtml,
-        Ok(_) => MimeType::Text,
+        Ok(type_) => {
+            let warning = Warning::UnknownType(type_.to_owned());
+            try!(session.diagnostics.warn(warning));
+            MimeType::Text
+        }
         Err(AttributeNotFound(_)) => MimeType::Text,
         Err(e) => { return Err(e); }
     };
     let source_uri = element.get_attr("src").ok().map(|v| v.to_string());  // TODO
-    Ok(feed::Content::new(content_type,
-                          try!(element.read_whole_text()).into_bytes(),
+    let body = if content_type == MimeType::Xhtml {
+        try!(read_xhtml(element))
+    } else {
+        try!(element.read_whole_text())
+    };
+    Ok(feed::Content::new(content_type, body.into_bytes(),
                           source_uri).unwrap())
 }
@@ -6,7 +6,9 @@ use std::io;
 use xml;
 use xml::reader::XmlEvent as x;
 
+use sanitizer::escape;
 use schema;
+use super::diagnostics::Warning;
 
 pub use xml::attribute::OwnedAttribute as XmlAttribute;
 pub use xml::name::OwnedName as XmlName;
@@ -20,6 +22,9 @@ pub enum DecodeError {
     NoResult,
     AttributeNotFound(String),
     SchemaError(schema::SchemaError),
+
+    /// The warning reported in strict mode.  See also `ParseDiagnostics`.
+    Warning(Warning),
 }
 
 impl fmt::Display for DecodeError {
@@ -32,6 +37,9 @@ impl fmt::Display for DecodeError {
             DecodeError::AttributeNotFound(ref attr) => {
                 try!(write!(f, ": {}", attr));
             }
+            DecodeError::Warning(ref warning) => {
+                try!(write!(f, ": {}", warning));
+            }
             _ => { }
         }
         if let Some(cause) = self.cause() {
@@ -49,6 +57,7 @@ impl Error for DecodeError {
             DecodeError::NoResult => "No result",
             DecodeError::AttributeNotFound(..) => "Attribute not found",
             DecodeError::SchemaError(..) => "Schema error",
+            DecodeError::Warning(..) => "Warning in strict mode",
         }
     }
 }
@@ -93,12 +102,120 @@ impl<'a, B: io::BufRead + 'a> XmlElement<'a, B> {
             } else {
                 break;
             };
-            if let NestedEvent::Characters(s) = try!(c) {
-                text.push_str(&s);
+            match try!(c) {
+                NestedEvent::Characters(s) | NestedEvent::CData(s) => {
+                    text.push_str(&s);
+                }
+                _ => { }
             }
         }
         Ok(text)
     }
+
+    /// Read children of the element as the XML string, e.g. for contents
+    /// of `type="xhtml"`.  Unlike `read_whole_text()`, nested elements are
+    /// kept along with their attributes and namespace declarations, and
+    /// CDATA sections are kept as they are.  Elements without any content
+    /// are written as empty-element tags e.g. `<br/>`.
+    pub fn read_inner_xml(self) -> DecodeResult<String> {
+        let XmlElement { namespace, mut children, .. } = self;
+        let mut xml = String::new();
+        try!(write_inner_xml(&mut children, &namespace, &mut xml));
+        Ok(xml)
+    }
+}
+
+fn write_inner_xml<B: io::BufRead>(children: &mut NestedEventReader<B>,
+                                   parent_ns: &XmlNamespace, xml: &mut String)
+                                   -> DecodeResult<()>
+{
+    while let Some(event) = children.next() {
+        try!(write_event_xml(try!(event), parent_ns, xml));
+    }
+    Ok(())
+}
+
+/// Write the event as XML to `xml`, reading all children if it's an
+/// element.  Namespaces already declared in `parent_ns` aren't declared
+/// again.  See also `XmlElement::read_inner_xml()`.
+pub fn write_event_xml<B: io::BufRead>(event: NestedEvent<B>,
+                                       parent_ns: &XmlNamespace,
+                                       xml: &mut String)
+                                       -> DecodeResult<()>
+{
+    use xml::namespace::{NS_XML_PREFIX, NS_XMLNS_PREFIX};
+    match event {
+        NestedEvent::Nested { name, mut element } => {
+            let qname = match name.prefix {
+                Some(ref prefix) => format!("{}:{}", prefix, name.local_name),
+                None => name.local_name.clone(),
+            };
+            xml.push('<');
+            xml.push_str(&qname);
+            for (prefix, uri) in element.namespace.0.iter() {
+                if prefix == NS_XML_PREFIX || prefix == NS_XMLNS_PREFIX ||
+                   parent_ns.0.get(prefix) == Some(uri) {
+                    continue;
+                }
+                if prefix.is_empty() {
+                    xml.push_str(" xmlns=\"");
+                } else {
+                    xml.push_str(" xmlns:");
+                    xml.push_str(prefix);
+                    xml.push_str("=\"");
+                }
+                xml.push_str(&escape(uri, true).to_string());
+                xml.push('"');
+            }
+            for attr in element.attributes.iter() {
+                xml.push(' ');
+                if let Some(ref prefix) = attr.name.prefix {
+                    xml.push_str(prefix);
+                    xml.push(':');
+                }
+                xml.push_str(&attr.name.local_name);
+                xml.push_str("=\"");
+                xml.push_str(&escape(&attr.value, true).to_string());
+                xml.push('"');
+            }
+            let mut inner = String::new();
+            try!(write_inner_xml(&mut element.children,
+                                 &element.namespace, &mut inner));
+            if inner.is_empty() {
+                xml.push_str("/>");
+            } else {
+                xml.push('>');
+                xml.push_str(&inner);
+                xml.push_str("</");
+                xml.push_str(&qname);
+                xml.push('>');
+            }
+        }
+        NestedEvent::Characters(s) | NestedEvent::Whitespace(s) => {
+            xml.push_str(&escape(&s, false).to_string());
+        }
+        NestedEvent::CData(s) => {
+            xml.push_str("<![CDATA[");
+            xml.push_str(&s);
+            xml.push_str("]]>");
+        }
+        NestedEvent::Comment(s) => {
+            xml.push_str("<!--");
+            xml.push_str(&s);
+            xml.push_str("-->");
+        }
+        NestedEvent::ProcessingInstruction { name, data } => {
+            xml.push_str("<?");
+            xml.push_str(&name);
+            if let Some(data) = data {
+                xml.push(' ');
+                xml.push_str(&data);
+            }
+            xml.push_str("?>");
+        }
+        NestedEvent::StartDocument { .. } | NestedEvent::EndDocument => { }
+    }
+    Ok(())
 }
 
 impl<'a, 'b, A, B> PartialEq<XmlElement<'b, B>> for XmlElement<'a, A>
@@ -206,8 +323,8 @@ impl<'a, B: io::Read + 'a> Drop for NestedEventReader<'a, B> {
             return;
         }
         // drain all remained events
+        let mut depth = 0;
         loop {
-            let mut depth = 0;
             match self.reader.next() {
                 Err(_) => break,
                 Ok(x::EndDocument) => break,
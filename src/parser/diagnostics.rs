@@ -0,0 +1,261 @@
+//! Collecting non-fatal problems found while parsing feeds.
+//!
+//! Feeds in the wild are often slightly broken e.g. unknown text types,
+//! dates in the wrong format.  Parsers fix them up or ignore them, and
+//! report each of them to `ParseDiagnostics` as a `Warning`, so that apps
+//! can log them.  In strict mode these warnings become `DecodeError`s
+//! instead.
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset};
+
+use codecs;
+use schema::Codec;
+use super::base::{DecodeError, DecodeResult, XmlElement};
+
+/// A non-fatal problem found while parsing.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Warning {
+    /// The `type` of the text construct or content is unknown, so it's
+    /// regarded as a plain text.
+    UnknownType(String),
+
+    /// The value of the attribute is invalid, so the attribute is ignored.
+    InvalidAttribute { name: String, value: String },
+
+    /// The date isn't in the format it should be, but could be parsed
+    /// by guessing.
+    FixedDate(String),
+
+    /// The date couldn't be parsed at all, so it's ignored.
+    InvalidDate(String),
+
+    /// The `original` bytes at the `offset` of the document weren't
+    /// well-formed XML, so they were `repaired`; see the `recover` module.
+    RepairedXml { offset: usize, original: String, repaired: String },
+
+    /// The entry at the `index`, i.e. the number of entries before it in
+    /// the document, failed to decode by the `error`, so it's skipped.  Its
+    /// `id` is known if it was read before the failure.
+    InvalidEntry { index: usize, id: Option<String>, error: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::UnknownType(ref type_) => {
+                write!(f, "unknown type {:?}; regarded as text", type_)
+            }
+            Warning::InvalidAttribute { ref name, ref value } => {
+                write!(f, "invalid value of attribute {}: {:?}", name, value)
+            }
+            Warning::FixedDate(ref date) => {
+                write!(f, "malformed date {:?}; fixed up", date)
+            }
+            Warning::InvalidDate(ref date) => {
+                write!(f, "invalid date {:?}; ignored", date)
+            }
+            Warning::RepairedXml { offset, ref original, ref repaired } => {
+                write!(f, "malformed XML {:?} at byte {}; repaired to {:?}",
+                       original, offset, repaired)
+            }
+            Warning::InvalidEntry { index, ref id, ref error } => {
+                try!(write!(f, "invalid entry #{}", index));
+                if let Some(ref id) = *id {
+                    try!(write!(f, " ({})", id));
+                }
+                write!(f, ": {}; skipped", error)
+            }
+        }
+    }
+}
+
+/// The collector of warnings passed through parsers.  It's lenient by
+/// default; warnings are just accumulated.
+///
+/// ```
+/// # #[cfg(feature = "parser")]
+/// # fn main() {
+/// # use earth::parser::{ParseDiagnostics, Warning};
+/// # use earth::parser::rss2::parse_rss;
+/// let xml = br#"<rss version="2.0"><channel><title>Earth Reader</title>
+///     <pubDate>yesterday</pubDate></channel></rss>"#;
+/// let diagnostics = ParseDiagnostics::new();
+/// assert!(parse_rss(&xml[..], "", &Default::default(), &diagnostics).is_ok());
+/// assert_eq!(diagnostics.warnings(),
+///            [Warning::InvalidDate("yesterday".to_owned())]);
+/// let strict = ParseDiagnostics::strict();
+/// assert!(parse_rss(&xml[..], "", &Default::default(), &strict).is_err());
+/// # }
+/// # #[cfg(not(feature = "parser"))]
+/// # fn main() { }
+/// ```
+#[derive(Debug, Default)]
+pub struct ParseDiagnostics {
+    strict: bool,
+    warnings: RefCell<Vec<Warning>>,
+    skipped_entries: RefCell<Vec<String>>,
+}
+
+impl ParseDiagnostics {
+    /// The lenient collector.
+    pub fn new() -> ParseDiagnostics { Default::default() }
+
+    /// The collector that fails parsing at the first warning.
+    pub fn strict() -> ParseDiagnostics {
+        ParseDiagnostics { strict: true, ..Default::default() }
+    }
+
+    pub fn is_strict(&self) -> bool { self.strict }
+
+    /// Report the warning.  It returns `DecodeError::Warning` in strict
+    /// mode, which parsers should propagate.
+    pub fn warn(&self, warning: Warning) -> DecodeResult<()> {
+        if self.strict {
+            return Err(DecodeError::Warning(warning));
+        }
+        self.warnings.borrow_mut().push(warning);
+        Ok(())
+    }
+
+    /// Warnings reported so far.
+    pub fn warnings(&self) -> Vec<Warning> { self.warnings.borrow().clone() }
+
+    pub fn into_warnings(self) -> Vec<Warning> {
+        self.warnings.into_inner()
+    }
+
+    /// Record the entry of `id` skipped as already known; see
+    /// `ParseOptions::known_entries`.  It isn't a warning, so it doesn't
+    /// fail even in strict mode.
+    pub fn skip_entry(&self, id: &str) {
+        self.skipped_entries.borrow_mut().push(id.to_owned());
+    }
+
+    /// Report the entry at the `index` which failed to decode by the
+    /// `error`, so that parsers skip it and go on with remaining entries.
+    /// In strict mode, or if the document itself is malformed so that
+    /// parsing can't go on, the `error` is returned as it is instead.
+    pub fn invalid_entry(&self, index: usize, id: Option<&str>,
+                         error: DecodeError) -> DecodeResult<()> {
+        match error {
+            DecodeError::XmlError(..) | DecodeError::UnexpectedEvent { .. } |
+            DecodeError::Warning(..) => { return Err(error); }
+            _ if self.strict => { return Err(error); }
+            _ => { }
+        }
+        self.warnings.borrow_mut().push(Warning::InvalidEntry {
+            index: index,
+            id: id.map(|id| id.to_owned()),
+            error: error.to_string(),
+        });
+        Ok(())
+    }
+
+    /// The number of entries skipped so far.
+    pub fn skipped_entries(&self) -> usize {
+        self.skipped_entries.borrow().len()
+    }
+
+    /// IDs of entries skipped so far, in the order they were skipped.
+    pub fn skipped_ids(&self) -> Vec<String> {
+        self.skipped_entries.borrow().clone()
+    }
+
+    /// Parse the optional attribute of the element.  Invalid values are
+    /// reported and regarded as missing.
+    pub fn parse_attr<B, T>(&self, element: &XmlElement<B>, name: &str)
+                            -> DecodeResult<Option<T>>
+        where B: io::BufRead, T: FromStr
+    {
+        let value = match element.get_attr(name) {
+            Ok(value) => value,
+            Err(_) => { return Ok(None); }
+        };
+        match value.trim().parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => {
+                try!(self.warn(Warning::InvalidAttribute {
+                    name: name.to_owned(),
+                    value: value.to_owned(),
+                }));
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Guess the date of the malformed string, which is common in the wild:
+/// RFC 3339 and RFC 822 mixed up, the time or the timezone omitted, and
+/// a space instead of `T`.  Dates without timezones are regarded as UTC.
+pub fn fix_datetime(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(date) = codecs::RFC3339.decode(text) {
+        return Some(date);
+    }
+    if let Ok(date) = codecs::RFC822.decode(text) {
+        return Some(date);
+    }
+    let text = text.replacen(' ', "T", 1);
+    let candidates = [format!("{}T00:00:00Z", text), format!("{}Z", text),
+                      format!("{}:00Z", text), text];
+    candidates.iter().filter_map(|c| codecs::RFC3339.decode(c).ok()).next()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{ParseDiagnostics, Warning, fix_datetime};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use parser::base::DecodeError;
+
+    #[test]
+    fn test_fix_datetime() {
+        let date = FixedOffset::east(0).ymd(2013, 9, 5).and_hms(0, 0, 0);
+        assert_eq!(fix_datetime("2013-09-05"), Some(date));
+        assert_eq!(fix_datetime(" 2013-09-05T00:00:00 "), Some(date));
+        assert_eq!(fix_datetime("2013-09-05 00:00"), Some(date));
+        assert_eq!(fix_datetime("2013-09-05 00:00:00Z"), Some(date));
+        assert_eq!(fix_datetime("Thu, 05 Sep 2013 00:00:00 GMT"), Some(date));
+        assert_eq!(fix_datetime("yesterday"), None);
+        assert_eq!(fix_datetime(""), None);
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let diagnostics = ParseDiagnostics::new();
+        assert!(!diagnostics.is_strict());
+        unwrap!(diagnostics.warn(Warning::UnknownType("html5".to_owned())));
+        unwrap!(diagnostics.warn(Warning::FixedDate("2013-09-05".to_owned())));
+        assert_eq!(diagnostics.into_warnings(),
+                   [Warning::UnknownType("html5".to_owned()),
+                    Warning::FixedDate("2013-09-05".to_owned())]);
+        let strict = ParseDiagnostics::strict();
+        assert_err!(strict.warn(Warning::InvalidDate("".to_owned())),
+                    DecodeError::Warning(Warning::InvalidDate(_)) => { });
+        assert!(strict.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_entry() {
+        let diagnostics = ParseDiagnostics::new();
+        let error = || DecodeError::AttributeNotFound("href".to_owned());
+        unwrap!(diagnostics.invalid_entry(2, Some("urn:a"), error()));
+        assert_eq!(diagnostics.warnings(), [Warning::InvalidEntry {
+            index: 2,
+            id: Some("urn:a".to_owned()),
+            error: "Attribute not found: href".to_owned(),
+        }]);
+        assert_eq!(diagnostics.warnings()[0].to_string(),
+                   "invalid entry #2 (urn:a): Attribute not found: href; \
+                    skipped");
+        assert_err!(ParseDiagnostics::strict().invalid_entry(0, None,
+                                                             error()),
+                    DecodeError::AttributeNotFound(_) => { });
+    }
+}
@@ -0,0 +1,488 @@
+//! Detecting formats of feed documents, and parsing them by options.
+use std::collections::HashMap;
+use std::default::Default;
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+use xml;
+use xml::reader::XmlEvent;
+
+use codecs;
+use digest::Sha1;
+use feed::{Blob, Entry, Feed, LinkIteratorExt};
+use schema::Codec;
+use super::{atom, recover, rss2};
+use super::base::{DecodeError, DecodeResult};
+use super::diagnostics::ParseDiagnostics;
+
+/// Feed formats which can be parsed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FeedFormat { Atom, Rss2 }
+
+/// Detect the format of the feed document from its root element.  It
+/// returns `None` if the document isn't a feed of the known formats.
+pub fn detect_format(xml: &[u8]) -> Option<FeedFormat> {
+    let mut parser = xml::EventReader::new(xml);
+    loop {
+        match parser.next() {
+            Ok(XmlEvent::StartElement { name, .. }) => {
+                return match (&name.local_name[..], name.namespace_ref()) {
+                    ("feed", Some(ns)) if atom::ATOM_XMLNS_SET.contains(&ns) => {
+                        Some(FeedFormat::Atom)
+                    }
+                    ("rss", None) => Some(FeedFormat::Rss2),
+                    _ => None,
+                };
+            }
+            Ok(XmlEvent::EndDocument) | Err(_) => { return None; }
+            Ok(_) => { }
+        }
+    }
+}
+
+/// Parse the feed document of whichever format `detect_format()` finds.
+/// If `ParseOptions::repair_xml` is set, the document is repaired by
+/// `recover::repair_xml()` first.
+pub fn parse(xml: &[u8], feed_url: &str, options: &ParseOptions,
+             diagnostics: &ParseDiagnostics) -> DecodeResult<Feed>
+{
+    let repaired;
+    let xml = if options.repair_xml {
+        repaired = try!(recover::repair_xml(xml, diagnostics));
+        &repaired[..]
+    } else {
+        xml
+    };
+    match detect_format(xml) {
+        Some(FeedFormat::Atom) => {
+            atom::parse_atom(xml, feed_url, options, diagnostics)
+        }
+        Some(FeedFormat::Rss2) => {
+            rss2::parse_rss(xml, feed_url, options, diagnostics)
+        }
+        None => Err(DecodeError::NoResult),
+    }
+}
+
+/// A source of the entry ID, tried in order of `ParseOptions::entry_id`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EntryIdSource {
+    /// The identifier the feed provides: `atom:id` or RSS `guid`.
+    Guid,
+
+    /// The permalink of the entry, e.g. `alternate` link.
+    Permalink,
+
+    /// `urn:sha1:` URI of the SHA-1 digest of the title, the date, and
+    /// the content of the entry.  It's stable across crawls as long as
+    /// the entry itself isn't changed.
+    Digest,
+}
+
+/// Entries an archive already has, as their IDs and when they were updated
+/// last.  See `ParseOptions::known_entries`.
+pub type KnownEntries = HashMap<String, DateTime<FixedOffset>>;
+
+/// Options to configure how feeds are parsed.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseOptions {
+    /// Whether to parse entries as well.  Parsing only the metadata of
+    /// feeds is way faster.  `true` by default.
+    pub need_entries: bool,
+
+    /// The fallback chain of sources used for `Entry::id`.  The first source
+    /// available for the entry is used, and the ID is left empty if none
+    /// of them is.  It's `Guid`, `Permalink`, and `Digest` by default.
+    pub entry_id: Vec<EntryIdSource>,
+
+    /// Whether entries without authors get authors of their `source`, or
+    /// of the feed otherwise, as :rfc:`4287#section-4.2.1` (section 4.2.1)
+    /// describes.  `false` by default; `Entry::display_authors()` resolves
+    /// the inheritance without copying authors into every entry.
+    pub inherit_authors: bool,
+
+    /// Whether to repair common problems of documents which aren't
+    /// well-formed XML, e.g. raw ampersands, by `parse()`.  Each repair is
+    /// reported as a warning.  `false` by default.  See also the `recover`
+    /// module.
+    pub repair_xml: bool,
+
+    /// Entries already archived, which needn't be parsed again.  An Atom
+    /// entry is skipped as soon as its `atom:id` and `atom:updated` are
+    /// read and tell it's known and unchanged, without parsing the rest of
+    /// it e.g. its content, and it's counted by
+    /// `ParseDiagnostics::skipped_entries()` instead of being returned.
+    /// It applies only if `entry_id` starts with `Guid`, since the other
+    /// IDs are known only after the whole entry is parsed, and not to RSS,
+    /// whose dates are resolved after the whole item is read as well.
+    /// `None` by default.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub known_entries: Option<Arc<KnownEntries>>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            need_entries: true,
+            entry_id: vec![EntryIdSource::Guid, EntryIdSource::Permalink,
+                           EntryIdSource::Digest],
+            inherit_authors: false,
+            repair_xml: false,
+            known_entries: None,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Options that skip entries, and parse the feed metadata only.
+    pub fn without_entries() -> ParseOptions {
+        ParseOptions { need_entries: false, ..Default::default() }
+    }
+
+    /// Determine the ID of the parsed `entry` by the `entry_id` chain.
+    /// `guid` is the identifier the feed provided if any, and `date` is
+    /// the date of the entry which the feed actually provided.
+    pub fn entry_id(&self, entry: &Entry, guid: Option<&str>,
+                    date: Option<DateTime<FixedOffset>>) -> String {
+        for source in self.entry_id.iter() {
+            let id = match *source {
+                EntryIdSource::Guid => {
+                    guid.and_then(|g| if g.is_empty() { None } else { Some(g) })
+                        .map(|g| g.to_owned())
+                }
+                EntryIdSource::Permalink => {
+                    entry.links.iter().permalink().map(|l| l.uri.clone())
+                }
+                EntryIdSource::Digest => Some(entry_digest(entry, date)),
+            };
+            if let Some(id) = id {
+                return id;
+            }
+        }
+        String::new()
+    }
+
+    /// Whether the entry of the `id` updated at `updated_at` is known and
+    /// unchanged, so that it can be skipped; see `known_entries`.
+    pub fn is_known(&self, id: &str, updated_at: &DateTime<FixedOffset>)
+                    -> bool {
+        let by_guid = self.entry_id.first() == Some(&EntryIdSource::Guid);
+        if !by_guid || id.is_empty() {
+            return false;
+        }
+        self.known_entries.as_ref().and_then(|known| known.get(id))
+                          .is_some_and(|known| known >= updated_at)
+    }
+}
+
+fn entry_digest(entry: &Entry, date: Option<DateTime<FixedOffset>>)
+                -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(entry.title.as_bytes());
+    hasher.update(b"\0");
+    if let Some(date) = date {
+        let mut buf = vec![];
+        if codecs::RFC3339.encode(&date, &mut buf).is_ok() {
+            hasher.update(&buf);
+        }
+    }
+    hasher.update(b"\0");
+    if let Some(ref content) = entry.content {
+        hasher.update(content.as_bytes());
+    }
+    format!("urn:sha1:{}", hasher.hexdigest())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{EntryIdSource, FeedFormat, KnownEntries, ParseOptions,
+                detect_format, parse};
+
+    use std::default::Default;
+    use std::sync::Arc;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Blob, Entry, Link, Text};
+    use parser::{ParseDiagnostics, Warning};
+    use parser::base::DecodeError;
+
+    #[test]
+    fn test_entry_id() {
+        let date = FixedOffset::east(0).ymd(2013, 9, 5).and_hms(0, 0, 0);
+        let mut entry = Entry::new(String::new(), Text::plain("Title"), date);
+        let options: ParseOptions = Default::default();
+        let digest = options.entry_id(&entry, None, Some(date));
+        assert!(digest.starts_with("urn:sha1:"));
+        assert_eq!(digest.len(), "urn:sha1:".len() + 40);
+        assert_eq!(options.entry_id(&entry, None, Some(date)), digest);
+        assert!(options.entry_id(&entry, None, None) != digest);
+        entry.title = Text::plain("Other");
+        assert!(options.entry_id(&entry, None, Some(date)) != digest);
+        entry.links.push(Link::new("http://earthreader.org/"));
+        assert_eq!(options.entry_id(&entry, None, Some(date)),
+                   "http://earthreader.org/");
+        assert_eq!(options.entry_id(&entry, Some("urn:guid"), None),
+                   "urn:guid");
+        assert_eq!(options.entry_id(&entry, Some(""), None),
+                   "http://earthreader.org/");
+        let options = ParseOptions {
+            entry_id: vec![EntryIdSource::Guid],
+            ..Default::default()
+        };
+        assert_eq!(options.entry_id(&entry, None, Some(date)), "");
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader:atom</id><title>Atom</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+            </feed>"#;
+        let rss = br#"<rss version="2.0"><channel><title>RSS</title>
+            </channel></rss>"#;
+        assert_eq!(detect_format(atom), Some(FeedFormat::Atom));
+        assert_eq!(detect_format(rss), Some(FeedFormat::Rss2));
+        assert_eq!(detect_format(b"<feed/>"), None);
+        assert_eq!(detect_format(b"<html></html>"), None);
+        assert_eq!(detect_format(b"not xml"), None);
+        let options = Default::default();
+        let diagnostics = ParseDiagnostics::new();
+        assert_eq!(unwrap!(parse(atom, "", &options, &diagnostics)).id,
+                   "urn:earthreader:atom");
+        assert_eq!(unwrap!(parse(rss, "http://earthreader.org/rss",
+                                 &options, &diagnostics)).title,
+                   Text::plain("RSS"));
+        assert!(parse(b"<html></html>", "", &options, &diagnostics).is_err());
+        assert!(diagnostics.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_parse_atom_diagnostics() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader:atom</id>
+                <title type="html5">Atom</title>
+                <updated>2013-11-06 14:36:00</updated>
+                <link href="http://earthreader.org/" length="big"/>
+            </feed>"#;
+        let options = Default::default();
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse(atom, "", &options, &diagnostics));
+        assert_eq!(feed.title, Text::plain("Atom"));
+        assert_eq!(feed.updated_at,
+                   FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0));
+        assert_eq!(feed.links[0].byte_size, None);
+        assert_eq!(diagnostics.into_warnings(), [
+            Warning::UnknownType("html5".to_owned()),
+            Warning::FixedDate("2013-11-06 14:36:00".to_owned()),
+            Warning::InvalidAttribute { name: "length".to_owned(),
+                                        value: "big".to_owned() },
+        ]);
+        let strict = ParseDiagnostics::strict();
+        assert_err!(parse(atom, "", &options, &strict),
+                    DecodeError::Warning(Warning::UnknownType(_)) => { });
+    }
+
+    #[test]
+    fn test_parse_atom_invalid_entry() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader:atom</id><title>Atom</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+                <entry>
+                    <id>urn:earthreader:atom:1</id><title>1</title>
+                    <link rel="alternate"><nested/></link>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                </entry>
+                <entry>
+                    <id>urn:earthreader:atom:2</id><title>2</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                </entry>
+            </feed>"#;
+        let options = Default::default();
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse(atom, "", &options, &diagnostics));
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(feed.entries[0].id, "urn:earthreader:atom:2");
+        assert_eq!(diagnostics.into_warnings(), [Warning::InvalidEntry {
+            index: 0,
+            id: Some("urn:earthreader:atom:1".to_owned()),
+            error: "Attribute not found: href".to_owned(),
+        }]);
+        let strict = ParseDiagnostics::strict();
+        assert_err!(parse(atom, "", &options, &strict),
+                    DecodeError::AttributeNotFound(_) => { });
+    }
+
+    #[test]
+    fn test_parse_inherit_authors() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader:atom</id><title>Atom</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+                <author><name>Feed Author</name></author>
+                <entry>
+                    <id>urn:earthreader:atom:1</id><title>1</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                </entry>
+                <entry>
+                    <id>urn:earthreader:atom:2</id><title>2</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <source>
+                        <id>urn:earthreader:source</id><title>Source</title>
+                        <updated>2013-11-06T14:36:00Z</updated>
+                        <author><name>Source Author</name></author>
+                    </source>
+                </entry>
+                <entry>
+                    <id>urn:earthreader:atom:3</id><title>3</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <author><name>Entry Author</name></author>
+                </entry>
+            </feed>"#;
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse(atom, "", &Default::default(),
+                                 &diagnostics));
+        assert!(feed.entries[0].authors.is_empty());
+        let options = ParseOptions {
+            inherit_authors: true,
+            ..Default::default()
+        };
+        let feed = unwrap!(parse(atom, "", &options, &diagnostics));
+        let names: Vec<&str> = feed.entries.iter()
+                                   .map(|e| &e.authors[0].name[..])
+                                   .collect();
+        assert_eq!(names, ["Feed Author", "Source Author", "Entry Author"]);
+        let rss = br#"<rss version="2.0"><channel><title>RSS</title>
+            <managingEditor>editor@earthreader.org (Editor)</managingEditor>
+            <item><title>A</title><guid>urn:a</guid></item>
+        </channel></rss>"#;
+        let feed = unwrap!(parse(rss, "", &options, &diagnostics));
+        assert_eq!(feed.authors.len(), 1);
+        assert_eq!(feed.entries[0].authors, feed.authors);
+    }
+
+    #[test]
+    fn test_parse_atom_replies() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom"
+                  xmlns:thr="http://purl.org/syndication/thread/1.0">
+                <id>urn:earthreader:atom</id><title>Atom</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+                <entry>
+                    <id>urn:earthreader:atom:1</id><title>1</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <link rel="replies" type="application/atom+xml"
+                          href="http://earthreader.org/1/replies"
+                          thr:count="3"/>
+                    <link rel="replies" type="text/html"
+                          href="http://earthreader.org/1#replies"
+                          thr:count="5"/>
+                </entry>
+                <entry>
+                    <id>urn:earthreader:atom:2</id><title>2</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <link rel="replies" href="http://earthreader.org/2"
+                          thr:count="3"/>
+                    <thr:total>10</thr:total>
+                </entry>
+            </feed>"#;
+        let diagnostics = ParseDiagnostics::strict();
+        let feed = unwrap!(parse(atom, "", &Default::default(),
+                                 &diagnostics));
+        let replies = feed.entries[0].replies();
+        assert_eq!(replies.links.len(), 2);
+        assert_eq!(replies.count, Some(5));
+        assert_eq!(replies.page().unwrap().uri,
+                   "http://earthreader.org/1#replies");
+        assert_eq!(feed.entries[1].replies().count, Some(10));
+    }
+
+    #[test]
+    fn test_parse_repair_xml() {
+        let rss = br#"<rss version="2.0"><channel><title>Q&A&hellip;</title>
+            </channel></rss>"#;
+        let diagnostics = ParseDiagnostics::new();
+        assert!(parse(rss, "", &Default::default(), &diagnostics).is_err());
+        let options = ParseOptions { repair_xml: true, ..Default::default() };
+        let feed = unwrap!(parse(rss, "", &options, &diagnostics));
+        assert_eq!(feed.title, Text::plain("Q&A\u{2026}"));
+        assert_eq!(diagnostics.warnings().len(), 2);
+    }
+
+    #[test]
+    fn test_parse_known_entries() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader:atom</id><title>Atom</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+                <entry>
+                    <id>urn:earthreader:atom:1</id><title>1</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <content type="bogus">skipped</content>
+                </entry>
+                <entry>
+                    <id>urn:earthreader:atom:2</id><title>2</title>
+                    <updated>2013-11-07T14:36:00Z</updated>
+                </entry>
+            </feed>"#;
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut known = KnownEntries::new();
+        known.insert("urn:earthreader:atom:1".to_owned(), date);
+        known.insert("urn:earthreader:atom:2".to_owned(), date);
+        let options = ParseOptions {
+            known_entries: Some(Arc::new(known)),
+            ..Default::default()
+        };
+        // the unknown type of the skipped content isn't reported
+        let diagnostics = ParseDiagnostics::strict();
+        let feed = unwrap!(parse(atom, "", &options, &diagnostics));
+        let ids: Vec<&str> = feed.entries.iter().map(|e| &e.id[..]).collect();
+        assert_eq!(ids, ["urn:earthreader:atom:2"]);
+        assert_eq!(diagnostics.skipped_entries(), 1);
+
+        let options = ParseOptions {
+            entry_id: vec![EntryIdSource::Digest],
+            ..options
+        };
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse(atom, "", &options, &diagnostics));
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(diagnostics.skipped_entries(), 0);
+    }
+
+    #[test]
+    fn test_parse_xhtml() {
+        let atom = br#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader:atom</id>
+                <title type="xhtml">
+                    <div xmlns="http://www.w3.org/1999/xhtml">Fish &amp;
+                        <b class="a">chips</b><br/></div>
+                </title>
+                <updated>2013-11-06T14:36:00Z</updated>
+                <entry>
+                    <id>urn:earthreader:atom:1</id><title>1</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <content type="xhtml"><x:p xmlns:x="urn:x"
+                        ><![CDATA[<raw>]]><!-- dropped --></x:p></content>
+                </entry>
+            </feed>"#;
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse(atom, "", &Default::default(),
+                                 &diagnostics));
+        assert_eq!(feed.title, Text::html(concat!(
+            "Fish &amp;\n                        ",
+            "<b class=\"a\">chips</b><br/>")));
+        // comments are never seen, since the XML reader skips them
+        let content = feed.entries[0].content.as_ref().unwrap();
+        assert_eq!(content.as_bytes(),
+                   &br#"<x:p xmlns:x="urn:x"><![CDATA[<raw>]]></x:p>"#[..]);
+    }
+}
@@ -0,0 +1,70 @@
+//! Parsing geographic extensions shared by feed parsers: [GeoRSS Simple][]
+//! `georss:point` and [W3C Basic Geo][] `geo:lat`/`geo:long` (optionally
+//! wrapped in `geo:Point`).
+//!
+//! [GeoRSS Simple]: http://www.georss.org/simple.html
+//! [W3C Basic Geo]: http://www.w3.org/2003/01/geo/
+use std::io;
+use std::str::FromStr;
+
+use super::base::{DecodeResult, XmlElement, XmlName};
+use super::base::NestedEvent::Nested;
+use feed::{GEORSS_XMLNS, W3C_GEO_XMLNS, GeoPoint};
+
+/// Collects geographic elements of an entry, and builds its location.
+#[derive(Default)]
+pub struct GeoReader {
+    point: Option<GeoPoint>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl GeoReader {
+    /// Whether the element is one of geographic elements.
+    pub fn matches(name: &XmlName) -> bool {
+        match name.namespace_ref() {
+            Some(GEORSS_XMLNS) => name.local_name == "point",
+            Some(W3C_GEO_XMLNS) => true,
+            _ => false,
+        }
+    }
+
+    /// Read the element which `matches()`.  Invalid coordinates are
+    /// ignored, since they're not worth failing the whole feed.
+    pub fn read<B: io::BufRead>(&mut self, name: &XmlName,
+                                mut element: XmlElement<B>)
+                                -> DecodeResult<()>
+    {
+        match (name.namespace_ref(), &name.local_name[..]) {
+            (Some(GEORSS_XMLNS), "point") => {
+                self.point = try!(element.read_whole_text()).parse().ok();
+            }
+            (Some(W3C_GEO_XMLNS), "lat") => {
+                self.latitude =
+                    f64::from_str(try!(element.read_whole_text()).trim()).ok();
+            }
+            (Some(W3C_GEO_XMLNS), "long") => {
+                self.longitude =
+                    f64::from_str(try!(element.read_whole_text()).trim()).ok();
+            }
+            (Some(W3C_GEO_XMLNS), "Point") => {
+                while let Some(event) = element.children.next() {
+                    if let Nested { name, element: child } = try!(event) {
+                        try!(self.read(&name, child));
+                    }
+                }
+            }
+            _ => { }
+        }
+        Ok(())
+    }
+
+    /// The location read so far.  `georss:point` takes precedence over
+    /// `geo:lat`/`geo:long`.
+    pub fn location(&self) -> Option<GeoPoint> {
+        self.point.or_else(|| match (self.latitude, self.longitude) {
+            (Some(lat), Some(long)) => GeoPoint::new(lat, long),
+            _ => None,
+        })
+    }
+}
@@ -1,3 +1,23 @@
 //! Parsing various RSS formats.
+//!
+//! Parsers of feed formats are built by the `parser` feature.  `base` and
+//! `diagnostics` are always built, since the feed model reads its own XML
+//! documents through them.
+#[cfg(feature = "parser")]
+pub use self::format::{EntryIdSource, FeedFormat, KnownEntries, ParseOptions,
+                       detect_format, parse};
+pub use self::diagnostics::{ParseDiagnostics, Warning};
+
+#[cfg(feature = "parser")]
 pub mod atom;
 pub mod base;
+pub mod diagnostics;
+#[cfg(feature = "parser")]
+pub mod geo;
+#[cfg(feature = "parser")]
+pub mod recover;
+#[cfg(feature = "parser")]
+pub mod rss2;
+
+#[cfg(feature = "parser")]
+mod format;
@@ -0,0 +1,178 @@
+//! Recovering documents which aren't well-formed XML in common ways, so
+//! that they can be parsed at all.
+//!
+//! A large fraction of feeds in the wild contain raw ampersands e.g. in
+//! URLs, or HTML entities like `&nbsp;` XML doesn't define, each of which
+//! aborts the XML parser.  `repair_xml()` fixes them on the bytes before
+//! they're tokenized:
+//!
+//! - ampersands which don't start any reference are escaped to `&amp;`;
+//! - known HTML entities are replaced by numeric character references e.g.
+//!   `&nbsp;` to `&#160;`.
+//!
+//! CDATA sections and comments are left as they are.  Each repair is
+//! reported to `ParseDiagnostics` as `Warning::RepairedXml`.  It's opt-in
+//! by `ParseOptions::repair_xml`.
+use std::borrow::Cow;
+
+use super::base::DecodeResult;
+use super::diagnostics::{ParseDiagnostics, Warning};
+
+/// HTML entities commonly found in feeds and their code points.  Entities
+/// XML predefines aren't listed.
+pub const HTML_ENTITIES: &'static [(&'static str, u32)] = &[
+    ("nbsp", 160), ("iexcl", 161), ("cent", 162), ("pound", 163),
+    ("yen", 165), ("sect", 167), ("uml", 168), ("copy", 169),
+    ("laquo", 171), ("not", 172), ("shy", 173), ("reg", 174),
+    ("deg", 176), ("plusmn", 177), ("acute", 180), ("micro", 181),
+    ("para", 182), ("middot", 183), ("raquo", 187), ("frac14", 188),
+    ("frac12", 189), ("frac34", 190), ("iquest", 191), ("Agrave", 192),
+    ("Aacute", 193), ("Auml", 196), ("Ccedil", 199), ("Eacute", 201),
+    ("Ouml", 214), ("times", 215), ("Uuml", 220), ("szlig", 223),
+    ("agrave", 224), ("aacute", 225), ("acirc", 226), ("atilde", 227),
+    ("auml", 228), ("aring", 229), ("ccedil", 231), ("egrave", 232),
+    ("eacute", 233), ("ecirc", 234), ("euml", 235), ("iacute", 237),
+    ("ntilde", 241), ("oacute", 243), ("ocirc", 244), ("ouml", 246),
+    ("divide", 247), ("oslash", 248), ("uacute", 250), ("uuml", 252),
+    ("ensp", 8194), ("emsp", 8195), ("thinsp", 8201), ("zwnj", 8204),
+    ("zwj", 8205), ("lrm", 8206), ("rlm", 8207), ("ndash", 8211),
+    ("mdash", 8212), ("lsquo", 8216), ("rsquo", 8217), ("sbquo", 8218),
+    ("ldquo", 8220), ("rdquo", 8221), ("bdquo", 8222), ("dagger", 8224),
+    ("Dagger", 8225), ("bull", 8226), ("hellip", 8230), ("permil", 8240),
+    ("lsaquo", 8249), ("rsaquo", 8250), ("euro", 8364), ("trade", 8482),
+    ("larr", 8592), ("uarr", 8593), ("rarr", 8594), ("darr", 8595),
+    ("hearts", 9829),
+];
+
+/// Entities XML predefines.
+const XML_ENTITIES: &'static [&'static str] = &[
+    "amp", "lt", "gt", "quot", "apos",
+];
+
+/// The longest reference `repair_xml()` looks for.
+const MAX_REFERENCE_LEN: usize = 32;
+
+/// Repair common problems of the document as the module documentation
+/// describes.  The document is borrowed as it is if nothing is repaired.
+/// It fails only if the `diagnostics` is strict.
+pub fn repair_xml<'a>(xml: &'a [u8], diagnostics: &ParseDiagnostics)
+                      -> DecodeResult<Cow<'a, [u8]>>
+{
+    let mut repaired: Option<Vec<u8>> = None;
+    let mut copied = 0;
+    let mut i = 0;
+    while i < xml.len() {
+        let rest = &xml[i..];
+        if rest.starts_with(b"<![CDATA[") {
+            i += skip_past(rest, b"]]>");
+            continue;
+        } else if rest.starts_with(b"<!--") {
+            i += skip_past(rest, b"-->");
+            continue;
+        } else if xml[i] != b'&' {
+            i += 1;
+            continue;
+        }
+        let (len, replacement) = match repair_reference(rest) {
+            Some(v) => v,
+            None => { i += 1; continue; }
+        };
+        try!(diagnostics.warn(Warning::RepairedXml {
+            offset: i,
+            original: String::from_utf8_lossy(&rest[..len]).into_owned(),
+            repaired: replacement.clone(),
+        }));
+        let buf = repaired.get_or_insert_with(|| {
+            Vec::with_capacity(xml.len() + 64)
+        });
+        buf.extend_from_slice(&xml[copied..i]);
+        buf.extend_from_slice(replacement.as_bytes());
+        i += len;
+        copied = i;
+    }
+    Ok(match repaired {
+        Some(mut buf) => {
+            buf.extend_from_slice(&xml[copied..]);
+            Cow::Owned(buf)
+        }
+        None => Cow::Borrowed(xml),
+    })
+}
+
+/// The length of `s` up to the end of the `terminator`, or of the whole
+/// `s` if it's unterminated.
+fn skip_past(s: &[u8], terminator: &[u8]) -> usize {
+    s.windows(terminator.len()).position(|w| w == terminator)
+     .map_or(s.len(), |p| p + terminator.len())
+}
+
+/// Decide how the reference at the start of `s` should be repaired, as a
+/// pair of the length of the original and its replacement.  It returns
+/// `None` if it's already a valid reference.
+fn repair_reference(s: &[u8]) -> Option<(usize, String)> {
+    let escaped = Some((1, "&amp;".to_owned()));
+    let semicolon = s.iter().take(MAX_REFERENCE_LEN).position(|&b| b == b';');
+    let end = match semicolon {
+        Some(end) => end,
+        None => { return escaped; }
+    };
+    let name = match ::std::str::from_utf8(&s[1..end]) {
+        Ok(name) if !name.is_empty() => name,
+        _ => { return escaped; }
+    };
+    let hex = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X"));
+    let valid = if let Some(hex) = hex {
+        !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else if let Some(decimal) = name.strip_prefix('#') {
+        !decimal.is_empty() && decimal.chars().all(|c| c.is_ascii_digit())
+    } else {
+        XML_ENTITIES.contains(&name)
+    };
+    if valid {
+        return None;
+    }
+    match HTML_ENTITIES.iter().find(|&&(n, _)| n == name) {
+        Some(&(_, code)) => Some((end + 1, format!("&#{};", code))),
+        None => escaped,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::repair_xml;
+
+    use std::borrow::Cow;
+
+    use parser::base::DecodeError;
+    use parser::diagnostics::{ParseDiagnostics, Warning};
+
+    #[test]
+    fn test_repair_xml() {
+        let diagnostics = ParseDiagnostics::new();
+        let valid = b"<a href=\"?a=1&amp;b=2\">&#160;&#xA0;&lt;</a>";
+        assert_eq!(unwrap!(repair_xml(valid, &diagnostics)),
+                   Cow::Borrowed(&valid[..]));
+        assert!(diagnostics.warnings().is_empty());
+        let xml = concat!("<a href=\"?a=1&b=2\">Fish & chips&nbsp;&bogus;",
+                          "<![CDATA[& &nbsp;]]><!-- & --></a>").as_bytes();
+        assert_eq!(String::from_utf8(unwrap!(repair_xml(xml, &diagnostics))
+                                         .into_owned()).unwrap(),
+                   concat!("<a href=\"?a=1&amp;b=2\">Fish &amp; ",
+                           "chips&#160;&amp;bogus;",
+                           "<![CDATA[& &nbsp;]]><!-- & --></a>"));
+        assert_eq!(diagnostics.warnings(), [
+            Warning::RepairedXml { offset: 13, original: "&".to_owned(),
+                                   repaired: "&amp;".to_owned() },
+            Warning::RepairedXml { offset: 24, original: "&".to_owned(),
+                                   repaired: "&amp;".to_owned() },
+            Warning::RepairedXml { offset: 31, original: "&nbsp;".to_owned(),
+                                   repaired: "&#160;".to_owned() },
+            Warning::RepairedXml { offset: 37, original: "&".to_owned(),
+                                   repaired: "&amp;".to_owned() },
+        ]);
+        let strict = ParseDiagnostics::strict();
+        assert_err!(repair_xml(xml, &strict),
+                    DecodeError::Warning(Warning::RepairedXml { .. }) => { });
+    }
+}
@@ -0,0 +1,698 @@
+//! Parsing RSS 2.0 feed.
+//!
+//! RSS 2.0 specification is [RSS 2.0 at Harvard Law][rss2].  It also
+//! understands some widely used extension modules:
+//!
+//! - [Dublin Core][dc]: `dc:creator` and `dc:date`.
+//! - [content][]: `content:encoded`.
+//! - [GeoRSS][] and [W3C Basic Geo][geo]: `georss:point`, `geo:lat` and
+//!   `geo:long`.
+//! - [Well-Formed Web][wfw], [Slash][] and [Atom Threading Extensions
+//!   ][RFC 4685]: `wfw:commentRss`, `slash:comments` and `thr:total`.
+//!
+//! When both core elements and extension elements exist, the values are
+//! chosen by the following rules:
+//!
+//! - `authors` come from `author` elements, or `dc:creator` elements if
+//!   there are no `author` elements.
+//! - `published_at` comes from `pubDate`, and `updated_at` from `dc:date`.
+//!   Either falls back to the other if it's missing.  The `updated_at` of
+//!   the channel prefers `lastBuildDate` to both.
+//! - `content` comes from `content:encoded`, and `description` becomes
+//!   `summary` in that case.  Otherwise `description` becomes `content`.
+//! - `comments` and `wfw:commentRss` become `LinkRel::Replies` links, and
+//!   `reply_count` comes from `slash:comments` or `thr:total`.
+//!
+//! - `id` is chosen by `ParseOptions::entry_id`, where `guid` is the
+//!   identifier the feed provides and `link` is the permalink.
+//!
+//! [rss2]: http://cyber.law.harvard.edu/rss/rss.html
+//! [dc]: http://purl.org/dc/elements/1.1/
+//! [content]: http://purl.org/rss/1.0/modules/content/
+//! [GeoRSS]: http://www.georss.org/simple.html
+//! [geo]: http://www.w3.org/2003/01/geo/
+//! [wfw]: http://wellformedweb.org/CommentAPI/
+//! [Slash]: http://web.resource.org/rss/1.0/modules/slash/
+//! [RFC 4685]: https://tools.ietf.org/html/rfc4685
+use std::default::Default;
+use std::io;
+
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+use xml;
+
+use super::base::{NestedEventReader, DecodeError, DecodeResult, XmlElement,
+                  XmlName};
+use super::base::NestedEvent::Nested;
+use super::{ParseDiagnostics, ParseOptions, Warning};
+use super::diagnostics::fix_datetime;
+use super::geo::GeoReader;
+use codecs;
+use feed;
+use mimetype::{self, MimeType};
+use schema::Codec;
+
+/// The XML namespace name of Dublin Core elements.
+pub const DC_XMLNS: &'static str = "http://purl.org/dc/elements/1.1/";
+
+/// The XML namespace name of the content module.
+pub const CONTENT_XMLNS: &'static str =
+    "http://purl.org/rss/1.0/modules/content/";
+
+// Patterns are compiled once per thread rather than for every element.
+thread_local! {
+    static PERSON_PATTERN: Regex =
+        Regex::new(r"^(?P<email>\S+@\S+)\s*\((?P<name>[^)]*)\)$").unwrap();
+    static DATE_ONLY_PATTERN: Regex =
+        Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap();
+}
+
+pub fn parse_rss<B: io::BufRead>(xml: B, feed_url: &str,
+                                 options: &ParseOptions,
+                                 diagnostics: &ParseDiagnostics)
+                                 -> DecodeResult<feed::Feed>
+{
+    let mut parser = xml::EventReader::new(xml);
+    let mut events = NestedEventReader::new(&mut parser);
+    while let Some(event) = events.next() {
+        if let Nested { name, mut element } = try!(event) {
+            if name.local_name != "rss" {
+                return Err(DecodeError::NoResult);
+            }
+            while let Some(event) = element.children.next() {
+                if let Nested { name, element: channel } = try!(event) {
+                    if name.local_name == "channel" {
+                        let mut feed = try!(parse_channel(channel, feed_url,
+                                                          options,
+                                                          diagnostics));
+                        if options.inherit_authors {
+                            feed.inherit_authors();
+                        }
+                        return Ok(feed);
+                    }
+                }
+            }
+        }
+    }
+    Err(DecodeError::NoResult)
+}
+
+/// Which module the element belongs to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Module { Core, DublinCore, Content, Slash, Threading, Wfw, Unknown }
+
+fn module_of(name: &XmlName) -> Module {
+    match name.namespace_ref() {
+        None | Some("") => Module::Core,
+        Some(DC_XMLNS) => Module::DublinCore,
+        Some(CONTENT_XMLNS) => Module::Content,
+        Some(feed::SLASH_XMLNS) => Module::Slash,
+        Some(feed::THREADING_XMLNS) => Module::Threading,
+        Some(feed::WFW_XMLNS) => Module::Wfw,
+        Some(_) => Module::Unknown,
+    }
+}
+
+fn parse_channel<B: io::BufRead>(mut element: XmlElement<B>, feed_url: &str,
+                                 options: &ParseOptions,
+                                 diagnostics: &ParseDiagnostics)
+                                 -> DecodeResult<feed::Feed>
+{
+    let mut feed: feed::Feed = Default::default();
+    feed.source.id = feed_url.to_owned();
+    let mut self_link = feed::Link::new(feed_url);
+    self_link.relation = feed::LinkRel::SelfLink;
+    self_link.mimetype = Some("application/rss+xml".into());
+    feed.links.push(self_link);
+    let mut creators = vec![];
+    let mut last_build_date = None;
+    let mut pub_date = None;
+    let mut dc_date = None;
+    let mut undated = vec![];
+    let mut index = 0;
+    while let Some(event) = element.children.next() {
+        let (name, child) = match try!(event) {
+            Nested { name, element } => (name, element),
+            _ => { continue; }
+        };
+        match (module_of(&name), &name.local_name[..]) {
+            (Module::Core, "title") => {
+                feed.title = feed::Text::plain(try!(child.read_whole_text()));
+            }
+            (Module::Core, "link") => {
+                let uri = try!(child.read_whole_text());
+                feed.links.push(alternate_link(uri.trim()));
+            }
+            (Module::Core, "description") => {
+                let text = try!(child.read_whole_text());
+                feed.subtitle = Some(feed::Text::plain(text));
+            }
+            (Module::Core, "copyright") => {
+                let text = try!(child.read_whole_text());
+                feed.rights = Some(feed::Text::plain(text));
+            }
+            (Module::Core, "managingEditor") => {
+                let text = try!(child.read_whole_text());
+                feed.authors.extend(parse_person(&text));
+            }
+            (Module::Core, "webMaster") => {
+                let text = try!(child.read_whole_text());
+                feed.contributors.extend(parse_person(&text));
+            }
+            (Module::Core, "category") => {
+                feed.categories.push(try!(parse_category(child)));
+            }
+            (Module::Core, "generator") => {
+                feed.generator = Some(feed::Generator {
+                    value: try!(child.read_whole_text()),
+                    ..Default::default()
+                });
+            }
+            (Module::Core, "image") => {
+                feed.logo = try!(parse_image(child));
+            }
+            (Module::Core, "lastBuildDate") => {
+                let text = try!(child.read_whole_text());
+                last_build_date = try!(parse_rfc822(&text, diagnostics));
+            }
+            (Module::Core, "pubDate") => {
+                let text = try!(child.read_whole_text());
+                pub_date = try!(parse_rfc822(&text, diagnostics));
+            }
+            (Module::Core, "item") if options.need_entries => {
+                let mut entry = Default::default();
+                match parse_item(child, options, diagnostics, &mut entry) {
+                    Ok(dated) => {
+                        if !dated {
+                            undated.push(feed.entries.len());
+                        }
+                        feed.entries.push(entry);
+                    }
+                    Err(e) => {
+                        let id = if entry.id.is_empty() { None }
+                                 else { Some(&entry.id[..]) };
+                        try!(diagnostics.invalid_entry(index, id, e));
+                    }
+                }
+                index += 1;
+            }
+            (Module::DublinCore, "creator") => {
+                let text = try!(child.read_whole_text());
+                creators.extend(parse_person(&text));
+            }
+            (Module::DublinCore, "date") => {
+                let text = try!(child.read_whole_text());
+                dc_date = try!(parse_w3cdtf(&text, diagnostics));
+            }
+            _ => { }
+        }
+    }
+    if feed.authors.is_empty() {
+        feed.authors = creators;
+    }
+    let latest_entry = feed.entries.iter().enumerate()
+                           .filter(|&(i, _)| !undated.contains(&i))
+                           .map(|(_, e)| e.updated_at).max();
+    if let Some(updated_at) = last_build_date.or(dc_date).or(pub_date)
+                                             .or(latest_entry) {
+        feed.updated_at = updated_at;
+    }
+    // entries without any date are regarded as updated with the channel
+    for i in undated {
+        feed.entries[i].updated_at = feed.updated_at;
+    }
+    Ok(feed)
+}
+
+/// Parse the item into `entry`, and return whether it has any date.  The
+/// `entry` is left partially parsed if it fails, with its `guid` as the ID
+/// if it was read.
+fn parse_item<B: io::BufRead>(mut element: XmlElement<B>,
+                              options: &ParseOptions,
+                              diagnostics: &ParseDiagnostics,
+                              entry: &mut feed::Entry) -> DecodeResult<bool>
+{
+    let mut guid = None;
+    let mut link = None;
+    let mut description = None;
+    let mut encoded = None;
+    let mut creators = vec![];
+    let mut pub_date = None;
+    let mut dc_date = None;
+    let mut geo: GeoReader = Default::default();
+    while let Some(event) = element.children.next() {
+        let (name, child) = match try!(event) {
+            Nested { name, element } => (name, element),
+            _ => { continue; }
+        };
+        if GeoReader::matches(&name) {
+            try!(geo.read(&name, child));
+            continue;
+        }
+        match (module_of(&name), &name.local_name[..]) {
+            (Module::Core, "title") => {
+                entry.title = feed::Text::plain(try!(child.read_whole_text()));
+            }
+            (Module::Core, "link") => {
+                link = Some(try!(child.read_whole_text()).trim().to_owned());
+            }
+            (Module::Core, "guid") => {
+                let permalink = child.get_attr("isPermaLink")
+                                     .map_or(true, |v| v != "false");
+                let id = try!(child.read_whole_text()).trim().to_owned();
+                entry.id = id.clone();
+                guid = Some((id, permalink));
+            }
+            (Module::Core, "description") => {
+                description = Some(try!(child.read_whole_text()));
+            }
+            (Module::Core, "author") => {
+                let text = try!(child.read_whole_text());
+                entry.authors.extend(parse_person(&text));
+            }
+            (Module::Core, "category") => {
+                entry.categories.push(try!(parse_category(child)));
+            }
+            (Module::Core, "enclosure") => {
+                entry.links.push(try!(parse_enclosure(child, diagnostics)));
+            }
+            (Module::Core, "pubDate") => {
+                let text = try!(child.read_whole_text());
+                pub_date = try!(parse_rfc822(&text, diagnostics));
+            }
+            (Module::DublinCore, "creator") => {
+                let text = try!(child.read_whole_text());
+                creators.extend(parse_person(&text));
+            }
+            (Module::DublinCore, "date") => {
+                let text = try!(child.read_whole_text());
+                dc_date = try!(parse_w3cdtf(&text, diagnostics));
+            }
+            (Module::Content, "encoded") => {
+                encoded = Some(try!(child.read_whole_text()));
+            }
+            (Module::Core, "comments") => {
+                let uri = try!(child.read_whole_text());
+                entry.links.push(replies_link(uri.trim(), "text/html"));
+            }
+            (Module::Wfw, "commentRss") => {
+                let uri = try!(child.read_whole_text());
+                entry.links.push(replies_link(uri.trim(),
+                                              "application/rss+xml"));
+            }
+            (Module::Slash, "comments") | (Module::Threading, "total") => {
+                let text = try!(child.read_whole_text());
+                entry.reply_count = text.trim().parse().ok();
+            }
+            _ => { }
+        }
+    }
+    if let Some((ref id, true)) = guid {
+        if link.is_none() && id.contains("://") {
+            link = Some(id.clone());
+        }
+    }
+    if let Some(ref uri) = link {
+        entry.links.push(alternate_link(uri));
+    }
+    if entry.authors.is_empty() {
+        entry.authors = creators;
+    }
+    entry.location = geo.location();
+    entry.published_at = pub_date.or(dc_date);
+    let dated = match dc_date.or(pub_date) {
+        Some(updated_at) => { entry.updated_at = updated_at; true }
+        None => false,
+    };
+    let (content, summary) = match (encoded, description) {
+        (Some(encoded), description) => (Some(encoded), description),
+        (None, description) => (description, None),
+    };
+    entry.content = content.map(|c| {
+        feed::Content::new(MimeType::Html, c.into_bytes(), None::<String>)
+            .unwrap()
+    });
+    entry.summary = summary.map(feed::Text::html);
+    let date = pub_date.or(dc_date);
+    entry.id = options.entry_id(entry, guid.as_ref().map(|g| &g.0[..]),
+                                date);
+    Ok(dated)
+}
+
+fn alternate_link(uri: &str) -> feed::Link {
+    let mut link = feed::Link::new(uri);
+    link.mimetype = Some("text/html".into());
+    link
+}
+
+fn replies_link(uri: &str, mimetype: &'static str) -> feed::Link {
+    let mut link = feed::Link::new(uri);
+    link.relation = feed::LinkRel::Replies;
+    link.mimetype = Some(mimetype.into());
+    link
+}
+
+/// Parse RSS person construct that is an email address optionally followed
+/// by the name in parentheses e.g. `john@example.com (John Doe)`.  Names
+/// without email addresses are also allowed since they're common in the
+/// wild.
+fn parse_person(text: &str) -> Option<feed::Person> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let captures = PERSON_PATTERN.with(|pattern| {
+        pattern.captures(text).map(|caps| {
+            (caps.name("email").unwrap(), caps.name("name").unwrap())
+        })
+    });
+    let (name, email) = match captures {
+        Some((email, name)) => {
+            let name = name.trim();
+            (if name.is_empty() { email } else { name }, Some(email))
+        }
+        None if text.contains('@') && !text.contains(' ') => {
+            (text, Some(text))
+        }
+        None => (text, None),
+    };
+    Some(feed::Person {
+        name: name.to_owned(),
+        uri: None,
+        email: email.map(|e| e.to_owned()),
+    })
+}
+
+fn parse_category<B: io::BufRead>(element: XmlElement<B>)
+                                  -> DecodeResult<feed::Category> {
+    let scheme_uri = element.get_attr("domain").ok().map(|v| v.to_owned());
+    Ok(feed::Category {
+        term: try!(element.read_whole_text()).trim().to_owned().into(),
+        scheme_uri: scheme_uri,
+        label: None,
+    })
+}
+
+fn parse_enclosure<B: io::BufRead>(element: XmlElement<B>,
+                                   diagnostics: &ParseDiagnostics)
+                                   -> DecodeResult<feed::Link> {
+    let mut link = feed::Link::new(try!(element.get_attr("url")));
+    link.relation = feed::LinkRel::Enclosure;
+    link.mimetype = element.get_attr("type").ok().map(mimetype::intern);
+    link.byte_size = try!(diagnostics.parse_attr(&element, "length"));
+    Ok(link)
+}
+
+fn parse_image<B: io::BufRead>(mut element: XmlElement<B>)
+                               -> DecodeResult<Option<String>> {
+    let mut url = None;
+    while let Some(event) = element.children.next() {
+        if let Nested { name, element: child } = try!(event) {
+            if name.local_name == "url" {
+                url = Some(try!(child.read_whole_text()).trim().to_owned());
+            }
+        }
+    }
+    Ok(url)
+}
+
+fn parse_rfc822(text: &str, diagnostics: &ParseDiagnostics)
+                -> DecodeResult<Option<DateTime<FixedOffset>>> {
+    recover_date(codecs::RFC822.decode(text).ok(), text, diagnostics)
+}
+
+/// Parse [W3C-DTF][] which `dc:date` uses.  It's a profile of ISO 8601
+/// similar to RFC 3339, but the time part can be omitted.
+///
+/// [W3C-DTF]: http://www.w3.org/TR/NOTE-datetime
+fn parse_w3cdtf(text: &str, diagnostics: &ParseDiagnostics)
+                -> DecodeResult<Option<DateTime<FixedOffset>>> {
+    let text = text.trim();
+    let date = codecs::RFC3339.decode(text).ok().or_else(|| {
+        if DATE_ONLY_PATTERN.with(|pattern| pattern.is_match(text)) {
+            codecs::RFC3339.decode(&format!("{}T00:00:00Z", text)).ok()
+        } else {
+            None
+        }
+    });
+    recover_date(date, text, diagnostics)
+}
+
+/// Dates in the wild are often broken, so malformed ones are fixed up if
+/// possible, and otherwise just ignored rather than failing the whole feed
+/// unless the `diagnostics` is strict.
+fn recover_date(date: Option<DateTime<FixedOffset>>, text: &str,
+                diagnostics: &ParseDiagnostics)
+                -> DecodeResult<Option<DateTime<FixedOffset>>> {
+    if date.is_some() {
+        return Ok(date);
+    }
+    let text = text.trim();
+    match fix_datetime(text) {
+        Some(date) => {
+            try!(diagnostics.warn(Warning::FixedDate(text.to_owned())));
+            Ok(Some(date))
+        }
+        None => {
+            try!(diagnostics.warn(Warning::InvalidDate(text.to_owned())));
+            Ok(None)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::parse_rss;
+    use super::super::{EntryIdSource, ParseDiagnostics, ParseOptions,
+                       Warning};
+    use super::super::base::DecodeError;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Blob, GeoPoint, Person, Text};
+    use mimetype::MimeType;
+
+    static FX_RSS: &'static [u8] = br##"<?xml version="1.0"?>
+    <rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/"
+         xmlns:content="http://purl.org/rss/1.0/modules/content/"
+         xmlns:georss="http://www.georss.org/georss"
+         xmlns:geo="http://www.w3.org/2003/01/geo/wgs84_pos#">
+        <channel>
+            <title>Earth Reader</title>
+            <link>http://earthreader.org/</link>
+            <description>Open source news reader</description>
+            <image>
+                <url>http://earthreader.org/logo.png</url>
+                <title>Earth Reader</title>
+            </image>
+            <dc:creator>Earth Reader team</dc:creator>
+            <lastBuildDate>Sat, 07 Sep 2013 00:00:01 GMT</lastBuildDate>
+            <item>
+                <title>Both</title>
+                <link>http://earthreader.org/both</link>
+                <guid isPermaLink="false">urn:earthreader:both</guid>
+                <author>team@earthreader.org (Earth Reader)</author>
+                <dc:creator>Someone</dc:creator>
+                <pubDate>Fri, 06 Sep 2013 09:00:00 +0900</pubDate>
+                <dc:date>2013-09-06T12:00:00Z</dc:date>
+                <description>Short summary</description>
+                <content:encoded><![CDATA[<p>Full <b>content</b></p>]]></content:encoded>
+                <enclosure url="http://earthreader.org/a.mp3" length="1234"
+                           type="audio/mpeg"/>
+                <georss:point>45.256 -71.92</georss:point>
+            </item>
+            <item>
+                <title>Extension only</title>
+                <guid>http://earthreader.org/ext</guid>
+                <dc:creator>Someone</dc:creator>
+                <dc:date>2013-09-05</dc:date>
+                <geo:Point><geo:lat>37.5</geo:lat><geo:long>127</geo:long></geo:Point>
+                <description>&lt;p&gt;Description only&lt;/p&gt;</description>
+            </item>
+        </channel>
+    </rss>
+    "##;
+
+    #[test]
+    fn test_parse_rss() {
+        let diagnostics = ParseDiagnostics::strict();
+        let feed = parse_rss(FX_RSS, "http://earthreader.org/rss",
+                             &Default::default(), &diagnostics)
+                       .unwrap();
+        assert_eq!(feed.id, "http://earthreader.org/rss");
+        assert_eq!(feed.title, Text::plain("Earth Reader"));
+        assert_eq!(feed.subtitle, Some(Text::plain("Open source news reader")));
+        assert_eq!(feed.logo.as_ref().unwrap(),
+                   "http://earthreader.org/logo.png");
+        assert_eq!(feed.links.iter().map(|l| (l.relation.as_str(), &l.uri[..]))
+                                    .collect::<Vec<_>>(),
+                   [("self", "http://earthreader.org/rss"),
+                    ("alternate", "http://earthreader.org/")]);
+        assert_eq!(feed.authors[0].name, "Earth Reader team");
+        assert_eq!(feed.updated_at,
+                   FixedOffset::east(0).ymd(2013, 9, 7).and_hms(0, 0, 1));
+        assert_eq!(feed.entries.len(), 2);
+
+        let entry = &feed.entries[0];
+        assert_eq!(entry.id, "urn:earthreader:both");
+        assert_eq!(entry.authors, [Person {
+            name: "Earth Reader".to_owned(),
+            uri: None,
+            email: Some("team@earthreader.org".to_owned()),
+        }]);
+        assert_eq!(entry.published_at,
+                   Some(FixedOffset::east(9 * 60 * 60).ymd(2013, 9, 6)
+                                                      .and_hms(9, 0, 0)));
+        assert_eq!(entry.updated_at,
+                   FixedOffset::east(0).ymd(2013, 9, 6).and_hms(12, 0, 0));
+        let content = entry.content.as_ref().unwrap();
+        assert_eq!(content.mimetype(), MimeType::Html);
+        assert_eq!(content.as_str(), Some("<p>Full <b>content</b></p>"));
+        assert_eq!(entry.summary, Some(Text::html("Short summary")));
+        assert_eq!(entry.links.iter().map(|l| (l.relation.as_str(), &l.uri[..]))
+                                     .collect::<Vec<_>>(),
+                   [("enclosure", "http://earthreader.org/a.mp3"),
+                    ("alternate", "http://earthreader.org/both")]);
+        assert_eq!(entry.links[0].byte_size, Some(1234));
+        assert_eq!(entry.location,
+                   Some(GeoPoint { latitude: 45.256, longitude: -71.92 }));
+
+        let entry = &feed.entries[1];
+        assert_eq!(entry.id, "http://earthreader.org/ext");
+        assert_eq!(entry.links[0].uri, "http://earthreader.org/ext");
+        assert_eq!(entry.authors[0].name, "Someone");
+        let dt = FixedOffset::east(0).ymd(2013, 9, 5).and_hms(0, 0, 0);
+        assert_eq!(entry.updated_at, dt);
+        assert_eq!(entry.published_at, Some(dt));
+        assert_eq!(entry.content.as_ref().unwrap().as_str(),
+                   Some("<p>Description only</p>"));
+        assert!(entry.summary.is_none());
+        assert_eq!(entry.location,
+                   Some(GeoPoint { latitude: 37.5, longitude: 127.0 }));
+    }
+
+    #[test]
+    fn test_parse_rss_replies() {
+        let xml = br##"
+        <rss version="2.0" xmlns:slash="http://purl.org/rss/1.0/modules/slash/"
+             xmlns:wfw="http://wellformedweb.org/CommentAPI/"><channel>
+            <title>Blog</title>
+            <item>
+                <title>A</title><link>http://earthreader.org/a</link>
+                <comments>http://earthreader.org/a#comments</comments>
+                <wfw:commentRss>http://earthreader.org/a/feed</wfw:commentRss>
+                <slash:comments>12</slash:comments>
+            </item>
+            <item><title>B</title><link>http://earthreader.org/b</link></item>
+        </channel></rss>
+        "##;
+        let feed = unwrap!(parse_rss(&xml[..], "", &Default::default(),
+                                     &ParseDiagnostics::strict()));
+        let replies = feed.entries[0].replies();
+        assert_eq!(replies.count, Some(12));
+        assert_eq!(replies.links.iter().map(|l| &l.uri[..])
+                                .collect::<Vec<_>>(),
+                   ["http://earthreader.org/a#comments",
+                    "http://earthreader.org/a/feed"]);
+        assert_eq!(replies.page().unwrap().uri,
+                   "http://earthreader.org/a#comments");
+        assert!(feed.entries[1].replies().is_empty());
+    }
+
+    #[test]
+    fn test_parse_rss_without_entries() {
+        let feed = parse_rss(FX_RSS, "http://earthreader.org/rss",
+                             &ParseOptions::without_entries(),
+                             &ParseDiagnostics::new())
+                       .unwrap();
+        assert!(feed.entries.is_empty());
+        assert!(parse_rss(&b"<feed/>"[..], "", &Default::default(),
+                          &ParseDiagnostics::new()).is_err());
+    }
+
+    #[test]
+    fn test_parse_rss_entry_id() {
+        let xml = br##"
+        <rss version="2.0"><channel>
+            <title>IDs</title>
+            <item><title>Link</title><link>http://earthreader.org/a</link></item>
+            <item><title>Nothing</title><description>Hello</description></item>
+        </channel></rss>
+        "##;
+        let diagnostics = ParseDiagnostics::new();
+        let feed = parse_rss(&xml[..], "http://earthreader.org/rss",
+                             &Default::default(), &diagnostics).unwrap();
+        assert_eq!(feed.entries[0].id, "http://earthreader.org/a");
+        let digest = feed.entries[1].id.clone();
+        assert!(digest.starts_with("urn:sha1:"));
+        let again = parse_rss(&xml[..], "http://earthreader.org/rss",
+                              &Default::default(), &diagnostics).unwrap();
+        assert_eq!(again.entries[1].id, digest);
+        let options = ParseOptions {
+            entry_id: vec![EntryIdSource::Digest],
+            ..Default::default()
+        };
+        let feed = parse_rss(&xml[..], "http://earthreader.org/rss",
+                             &options, &diagnostics).unwrap();
+        assert!(feed.entries[0].id.starts_with("urn:sha1:"));
+        assert!(feed.entries[0].id != digest);
+    }
+
+    #[test]
+    fn test_parse_rss_diagnostics() {
+        let xml = br##"
+        <rss version="2.0"><channel>
+            <title>Broken</title>
+            <lastBuildDate>2013-09-07T00:00:01Z</lastBuildDate>
+            <item>
+                <title>A</title>
+                <pubDate>yesterday</pubDate>
+                <enclosure url="http://earthreader.org/a.mp3" length="big"
+                           type="audio/mpeg"/>
+            </item>
+        </channel></rss>
+        "##;
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse_rss(&xml[..], "", &Default::default(),
+                                     &diagnostics));
+        assert_eq!(feed.updated_at,
+                   FixedOffset::east(0).ymd(2013, 9, 7).and_hms(0, 0, 1));
+        assert_eq!(feed.entries[0].published_at, None);
+        assert_eq!(feed.entries[0].links[0].byte_size, None);
+        assert_eq!(diagnostics.into_warnings(), [
+            Warning::FixedDate("2013-09-07T00:00:01Z".to_owned()),
+            Warning::InvalidDate("yesterday".to_owned()),
+            Warning::InvalidAttribute { name: "length".to_owned(),
+                                        value: "big".to_owned() },
+        ]);
+        assert_err!(parse_rss(&xml[..], "", &Default::default(),
+                              &ParseDiagnostics::strict()),
+                    DecodeError::Warning(Warning::FixedDate(_)) => { });
+    }
+
+    #[test]
+    fn test_parse_rss_invalid_entry() {
+        let xml = br##"
+        <rss version="2.0"><channel>
+            <title>Earth Reader</title>
+            <item><title>A</title><enclosure length="1"/></item>
+            <item><title>B</title><guid>urn:b</guid><enclosure/></item>
+            <item><title>C</title><guid>urn:c</guid></item>
+        </channel></rss>
+        "##;
+        let diagnostics = ParseDiagnostics::new();
+        let feed = unwrap!(parse_rss(&xml[..], "", &Default::default(),
+                                     &diagnostics));
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(feed.entries[0].id, "urn:c");
+        let warnings = diagnostics.into_warnings();
+        assert_eq!(warnings.iter().map(|w| match *w {
+            Warning::InvalidEntry { index, ref id, .. } => (index, id.clone()),
+            _ => panic!("unexpected warning: {:?}", w),
+        }).collect::<Vec<_>>(), [(0, None), (1, Some("urn:b".to_owned()))]);
+        assert_err!(parse_rss(&xml[..], "", &Default::default(),
+                              &ParseDiagnostics::strict()),
+                    DecodeError::AttributeNotFound(_) => { });
+    }
+}
@@ -0,0 +1,448 @@
+//! The tiny query language to filter entries, shared by UIs and power
+//! users e.g.:
+//!
+//! ```
+//! # use earth::query::Query;
+//! let query: Query = "unread AND (category:rust OR feed:planet) since:7d"
+//!                        .parse().unwrap();
+//! ```
+//!
+//! A query consists of terms, which are combined by `AND` (or just
+//! spaces), `OR`, `NOT` (or `-`), and grouped by parentheses.  `AND` binds
+//! tighter than `OR`.  Terms are:
+//!
+//! - `read`, `unread`, `starred`, `unstarred`: states of marks.
+//! - `category:<term>`: entries in the category.
+//! - `author:<name>`: entries by the author of the name,
+//!   case-insensitively.
+//! - `feed:<feed_id>`: entries of the feed.
+//! - `since:<when>`, `before:<when>`: entries updated since or before the
+//!   time, which is either a date e.g. `2013-11-06`, or a duration ago
+//!   e.g. `30m`, `12h`, `7d`, `2w`.
+//! - anything else e.g. `earth`, `"earth reader"`: entries containing the
+//!   text in their title, summary, or content, case-insensitively.  Texts
+//!   are matched without markup; see `Blob::normalized_text()`.
+//!
+//! Values can be quoted e.g. `category:"web development"`.
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{self, DateTime, FixedOffset};
+
+use feed::{Blob, Entry, MarkKind};
+use parser::diagnostics::fix_datetime;
+
+/// The time `since:` and `before:` terms refer to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum When {
+    /// The duration before the time the query is evaluated at.
+    Ago(chrono::Duration),
+
+    At(DateTime<FixedOffset>),
+}
+
+impl When {
+    fn resolve(&self, now: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match *self {
+            When::Ago(duration) => now - duration,
+            When::At(time) => time,
+        }
+    }
+}
+
+/// The parsed query, which is a predicate over entries.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Query {
+    /// Matches every entry.  It's what the empty query means.
+    All,
+
+    /// Entries whose mark of the kind is in the state.
+    Marked(MarkKind, bool),
+
+    Category(String),
+    Author(String),
+    Feed(String),
+    Since(When),
+    Before(When),
+    Text(String),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+impl Query {
+    /// Whether the entry of the feed matches the query.  `now` is the time
+    /// relative times e.g. `since:7d` are resolved against.
+    pub fn matches(&self, feed_id: &str, entry: &Entry,
+                   now: DateTime<FixedOffset>) -> bool {
+        match *self {
+            Query::All => true,
+            Query::Marked(kind, marked) => entry.mark(kind).marked == marked,
+            Query::Category(ref term) => {
+                entry.categories.iter().any(|c| c.term == *term)
+            }
+            Query::Author(ref name) => {
+                let name = name.to_lowercase();
+                entry.authors.iter().any(|a| a.name.to_lowercase() == name)
+            }
+            Query::Feed(ref id) => feed_id == id,
+            Query::Since(ref when) => entry.updated_at >= when.resolve(now),
+            Query::Before(ref when) => entry.updated_at < when.resolve(now),
+            Query::Text(ref text) => contains_text(entry, text),
+            Query::Not(ref query) => !query.matches(feed_id, entry, now),
+            Query::And(ref queries) => {
+                queries.iter().all(|q| q.matches(feed_id, entry, now))
+            }
+            Query::Or(ref queries) => {
+                queries.iter().any(|q| q.matches(feed_id, entry, now))
+            }
+        }
+    }
+}
+
+fn contains_text(entry: &Entry, text: &str) -> bool {
+    let text = text.to_lowercase();
+    let found = |s: Option<&str>| {
+        s.is_some_and(|s| s.to_lowercase().contains(&text))
+    };
+    let summary = entry.summary.as_ref().map(|s| s.normalized_text());
+    let content = entry.content.as_ref().map(|c| c.normalized_text());
+    found(Some(&entry.title.normalized_text())) ||
+        found(summary.as_ref().map(|s| &s[..])) ||
+        found(content.as_ref().map(|c| &c[..]))
+}
+
+/// The query is malformed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseError {
+    /// The byte offset in the query where the problem is found.
+    pub position: usize,
+
+    pub message: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.position)
+    }
+}
+
+impl ErrorTrait for ParseError {
+    fn description(&self) -> &str { self.message }
+}
+
+impl FromStr for Query {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Query, ParseError> {
+        let tokens = try!(tokenize(s));
+        let mut parser = Parser { tokens: tokens, position: 0, end: s.len() };
+        if parser.peek().is_none() {
+            return Ok(Query::All);
+        }
+        let query = try!(parser.parse_or());
+        match parser.peek() {
+            Some(&(offset, _)) => Err(ParseError {
+                position: offset,
+                message: "unexpected closing parenthesis",
+            }),
+            None => Ok(query),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Open,
+    Close,
+
+    /// A word, and whether it's quoted as a whole e.g. `"OR"`; such words
+    /// are always text terms rather than operators or fields.
+    Word(String, bool),
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        match c {
+            '(' => { chars.next(); tokens.push((offset, Token::Open)); }
+            ')' => { chars.next(); tokens.push((offset, Token::Close)); }
+            c if c.is_whitespace() => { chars.next(); }
+            _ => {
+                let mut word = String::new();
+                let quoted = c == '"';
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c != '"' {
+                        word.push(c);
+                        continue;
+                    }
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => { break; }
+                            Some((_, c)) => { word.push(c); }
+                            None => {
+                                return Err(ParseError {
+                                    position: i,
+                                    message: "unterminated quote",
+                                });
+                            }
+                        }
+                    }
+                }
+                tokens.push((offset, Token::Word(word, quoted)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+
+    /// The length of the query, for errors at the end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.position)
+    }
+
+    fn is_operator(&self, operator: &str) -> bool {
+        match self.peek() {
+            Some(&(_, Token::Word(ref w, false))) => w == operator,
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Query, ParseError> {
+        let mut queries = vec![try!(self.parse_and())];
+        while self.is_operator("OR") {
+            self.position += 1;
+            queries.push(try!(self.parse_and()));
+        }
+        Ok(if queries.len() == 1 { queries.pop().unwrap() }
+           else { Query::Or(queries) })
+    }
+
+    fn parse_and(&mut self) -> Result<Query, ParseError> {
+        let mut queries = vec![try!(self.parse_unary())];
+        loop {
+            if self.is_operator("AND") {
+                self.position += 1;
+            } else {
+                match self.peek() {
+                    None | Some(&(_, Token::Close)) => { break; }
+                    _ if self.is_operator("OR") => { break; }
+                    _ => { }
+                }
+            }
+            queries.push(try!(self.parse_unary()));
+        }
+        Ok(if queries.len() == 1 { queries.pop().unwrap() }
+           else { Query::And(queries) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, ParseError> {
+        let (offset, token) = match self.peek() {
+            Some(&(offset, ref token)) => (offset, token.clone()),
+            None => {
+                return Err(ParseError { position: self.end,
+                                        message: "unexpected end" });
+            }
+        };
+        self.position += 1;
+        match token {
+            Token::Open => {
+                let query = try!(self.parse_or());
+                match self.peek() {
+                    Some(&(_, Token::Close)) => {
+                        self.position += 1;
+                        Ok(query)
+                    }
+                    _ => Err(ParseError { position: offset,
+                                          message: "unclosed parenthesis" }),
+                }
+            }
+            Token::Close => {
+                Err(ParseError { position: offset,
+                                 message: "unexpected closing parenthesis" })
+            }
+            Token::Word(ref w, false) if w == "NOT" => {
+                Ok(Query::Not(Box::new(try!(self.parse_unary()))))
+            }
+            Token::Word(ref w, false) if w == "AND" || w == "OR" => {
+                Err(ParseError { position: offset,
+                                 message: "missing operand" })
+            }
+            Token::Word(ref w, false) if w.starts_with('-') && w.len() > 1 => {
+                let term = try!(parse_term(&w[1..], offset + 1));
+                Ok(Query::Not(Box::new(term)))
+            }
+            Token::Word(w, false) => parse_term(&w, offset),
+            Token::Word(w, true) => Ok(Query::Text(w)),
+        }
+    }
+}
+
+fn parse_term(word: &str, offset: usize) -> Result<Query, ParseError> {
+    match word {
+        "read" => { return Ok(Query::Marked(MarkKind::Read, true)); }
+        "unread" => { return Ok(Query::Marked(MarkKind::Read, false)); }
+        "starred" => { return Ok(Query::Marked(MarkKind::Starred, true)); }
+        "unstarred" => {
+            return Ok(Query::Marked(MarkKind::Starred, false));
+        }
+        _ => { }
+    }
+    let mut parts = word.splitn(2, ':');
+    let (field, value) = match (parts.next(), parts.next()) {
+        (Some(field), Some(value)) => (field, value),
+        _ => { return Ok(Query::Text(word.to_owned())); }
+    };
+    let invalid_date = ParseError { position: offset,
+                                    message: "invalid date or duration" };
+    match field {
+        "category" => Ok(Query::Category(value.to_owned())),
+        "author" => Ok(Query::Author(value.to_owned())),
+        "feed" => Ok(Query::Feed(value.to_owned())),
+        "since" => parse_when(value).map(Query::Since).ok_or(invalid_date),
+        "before" => parse_when(value).map(Query::Before).ok_or(invalid_date),
+        _ => Ok(Query::Text(word.to_owned())),
+    }
+}
+
+/// The longest duration `since:` and `before:` take, about 10,000 years.
+const MAX_AGO_SECS: i64 = 10000 * 366 * 24 * 60 * 60;
+
+fn parse_when(value: &str) -> Option<When> {
+    if let Some(date) = fix_datetime(value) {
+        return Some(When::At(date));
+    }
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, _)) if i > 0 => value.split_at(i),
+        _ => { return None; }
+    };
+    let number: i64 = match number.parse() {
+        Ok(number) => number,
+        Err(_) => { return None; }
+    };
+    let unit_secs = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => { return None; }
+    };
+    // chrono panics on durations and datetimes out of its range
+    match number.checked_mul(unit_secs) {
+        Some(secs) if secs.abs() <= MAX_AGO_SECS => {
+            Some(When::Ago(chrono::Duration::seconds(secs)))
+        }
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{ParseError, Query, When};
+
+    use chrono::{self, FixedOffset, TimeZone};
+
+    use feed::{Category, Content, Entry, MarkKind, Person, Text};
+
+    fn parse(query: &str) -> Query { unwrap!(query.parse()) }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(parse(""), Query::All);
+        assert_eq!(parse("unread"), Query::Marked(MarkKind::Read, false));
+        assert_eq!(parse("unread AND category:rust since:7d"), Query::And(vec![
+            Query::Marked(MarkKind::Read, false),
+            Query::Category("rust".to_owned()),
+            Query::Since(When::Ago(chrono::Duration::days(7))),
+        ]));
+        assert_eq!(parse("a OR b c"), Query::Or(vec![
+            Query::Text("a".to_owned()),
+            Query::And(vec![Query::Text("b".to_owned()),
+                            Query::Text("c".to_owned())]),
+        ]));
+        assert_eq!(parse("(a OR b) -starred"), Query::And(vec![
+            Query::Or(vec![Query::Text("a".to_owned()),
+                           Query::Text("b".to_owned())]),
+            Query::Not(Box::new(Query::Marked(MarkKind::Starred, true))),
+        ]));
+        assert_eq!(parse(r#"NOT category:"web dev" "OR" "a:b""#),
+                   Query::And(vec![
+            Query::Not(Box::new(Query::Category("web dev".to_owned()))),
+            Query::Text("OR".to_owned()),
+            Query::Text("a:b".to_owned()),
+        ]));
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(0, 0, 0);
+        assert_eq!(parse("before:2013-11-06"), Query::Before(When::At(date)));
+        assert_eq!(parse("author:dahlia"), Query::Author("dahlia".to_owned()));
+        assert_err!("since:yesterday".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_err!("since:1\u{bd84}".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_err!("since:\u{bd84}".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_err!("since:99999999999999w".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_err!("before:-99999999999999d".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_eq!(parse("since:2w"),
+                   Query::Since(When::Ago(chrono::Duration::days(14))));
+        assert_err!("(a OR b".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_err!("a )".parse::<Query>(),
+                    ParseError { position: 2, .. } => { });
+        assert_err!("a AND".parse::<Query>(),
+                    ParseError { position: 5, .. } => { });
+        assert_err!("OR a".parse::<Query>(),
+                    ParseError { position: 0, .. } => { });
+        assert_err!(r#"a "b"#.parse::<Query>(),
+                    ParseError { position: 2, .. } => { });
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let now = FixedOffset::east(0).ymd(2013, 11, 10).and_hms(0, 0, 0);
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(0, 0, 0);
+        let mut entry = Entry::new("urn:a".to_owned(),
+                                   Text::plain("Earth Reader"), updated_at);
+        entry.summary = Some(Text::html("<p>decentralized</p>"));
+        entry.categories.push(Category {
+            term: "rust".into(),
+            ..Default::default()
+        });
+        let matches = |query: &str, entry: &Entry| {
+            parse(query).matches("planet", entry, now)
+        };
+        assert!(matches("unread category:rust since:7d", &entry));
+        assert!(!matches("unread since:3d", &entry));
+        assert!(matches("before:2013-11-07 feed:planet", &entry));
+        assert!(!matches("feed:other OR starred", &entry));
+        assert!(!matches("author:hong", &entry));
+        entry.authors.push(Person::new("Hong Minhee"));
+        assert!(matches(r#"author:"hong minhee""#, &entry));
+        assert!(matches(r#""earth reader" DECENTRALIZED"#, &entry));
+        assert!(!matches("-earth", &entry));
+        entry.content = Content::from_str(
+            "html", "<b>open</b>\n  source".to_owned(), None::<String>);
+        assert!(matches(r#""open source""#, &entry));
+        assert!(!matches("<b>", &entry));
+        entry.read.marked = true;
+        assert!(!matches("unread", &entry));
+        assert!(matches("", &entry));
+    }
+}
@@ -0,0 +1,380 @@
+//! Helpers for rendering the model for people, e.g. displaying datetimes in
+//! their timezone.
+//!
+//! Datetimes are stored with the offsets they're published in, which are
+//! rarely what readers want to see.  `TimeDisplay` converts them to the
+//! configured offset before they're formatted:
+//!
+//! ```
+//! # extern crate chrono;
+//! # extern crate earth;
+//! # fn main() {
+//! use chrono::{FixedOffset, TimeZone};
+//! use earth::render::{TimeDisplay, TimeZoneSetting};
+//!
+//! let published = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+//! let seoul = TimeDisplay {
+//!     zone: TimeZoneSetting::Fixed(FixedOffset::east(9 * 3600)),
+//!     format: "%Y-%m-%d %H:%M".to_owned(),
+//! };
+//! assert_eq!(seoul.format(&published), "2013-11-06 23:36");
+//! # }
+//! ```
+//!
+//! Titles and summaries in lists are shortened by `text_preview()` and
+//! `entry_preview()`, which never cut characters in the middle.
+//!
+//! Plain texts with extremely long lines are displayed with break
+//! opportunities in them; see `TextDisplay`.
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset, Local, TimeZone, UTC};
+
+use feed::{Blob, Entry, Text};
+use sanitizer::{QUOTE_BR, Escape, SanitizerPreset, SoftWrap};
+use util::truncate_graphemes;
+
+/// Decides the offset of the local time at the moment, e.g. by the device
+/// settings.  Offsets may differ by moments because of daylight saving
+/// time.
+pub trait LocalOffset {
+    fn offset_at(&self, datetime: &DateTime<UTC>) -> FixedOffset;
+}
+
+/// The local time of the system, through `chrono::Local`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SystemLocal;
+
+impl LocalOffset for SystemLocal {
+    fn offset_at(&self, datetime: &DateTime<UTC>) -> FixedOffset {
+        *Local.from_utc_datetime(&datetime.naive_utc()).offset()
+    }
+}
+
+/// Which offset datetimes are displayed in.
+#[derive(Clone)]
+pub enum TimeZoneSetting {
+    /// The offset they're published in, i.e. without conversion.
+    Original,
+
+    Fixed(FixedOffset),
+
+    /// The local time the provider decides.
+    Local(Arc<LocalOffset + Send + Sync>),
+}
+
+impl fmt::Debug for TimeZoneSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeZoneSetting::Original => write!(f, "Original"),
+            TimeZoneSetting::Fixed(ref offset) => {
+                write!(f, "Fixed({:?})", offset)
+            }
+            TimeZoneSetting::Local(_) => write!(f, "Local(..)"),
+        }
+    }
+}
+
+/// How datetimes are displayed by rendering helpers.
+#[derive(Clone, Debug)]
+pub struct TimeDisplay {
+    pub zone: TimeZoneSetting,
+
+    /// The `strftime`-style format of `chrono::format::strftime`.
+    pub format: String,
+}
+
+impl Default for TimeDisplay {
+    /// The local time of the system, in the ISO 8601 style without
+    /// seconds.
+    fn default() -> TimeDisplay {
+        TimeDisplay {
+            zone: TimeZoneSetting::Local(Arc::new(SystemLocal)),
+            format: "%Y-%m-%d %H:%M".to_owned(),
+        }
+    }
+}
+
+impl TimeDisplay {
+    /// Convert the datetime to the offset it's displayed in.  It's the same
+    /// moment as the given one.
+    pub fn convert(&self, datetime: &DateTime<FixedOffset>)
+                   -> DateTime<FixedOffset> {
+        let offset = match self.zone {
+            TimeZoneSetting::Original => { return *datetime; }
+            TimeZoneSetting::Fixed(offset) => offset,
+            TimeZoneSetting::Local(ref provider) => {
+                provider.offset_at(&datetime.with_timezone(&UTC))
+            }
+        };
+        datetime.with_timezone(&offset)
+    }
+
+    pub fn format(&self, datetime: &DateTime<FixedOffset>) -> String {
+        self.convert(datetime).format(&self.format).to_string()
+    }
+
+    /// Display the datetime as an HTML `<time>` element, whose `datetime`
+    /// attribute is machine-readable.
+    pub fn html<'a>(&'a self, datetime: &'a DateTime<FixedOffset>)
+                    -> TimeHtml<'a> {
+        TimeHtml { display: self, datetime: datetime }
+    }
+}
+
+pub struct TimeHtml<'a> {
+    display: &'a TimeDisplay,
+    datetime: &'a DateTime<FixedOffset>,
+}
+
+impl<'a> fmt::Display for TimeHtml<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let converted = self.display.convert(self.datetime);
+        write!(f, "<time datetime=\"{}\">{}</time>", converted.to_rfc3339(),
+               converted.format(&self.display.format))
+    }
+}
+
+/// The number of characters without whitespace after which a break
+/// opportunity is inserted by default, e.g. by `entry_html()`.
+pub const DEFAULT_SOFT_WRAP: usize = 80;
+
+/// How plain texts are displayed as HTML.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextDisplay {
+    /// Insert `<wbr>` into runs of more than this number of characters
+    /// without whitespace, so that megabyte-long lines don't break
+    /// layouts.  `None` means they're left as they are.
+    /// See `sanitizer::soft_wrap()`.
+    pub soft_wrap: Option<usize>,
+}
+
+impl Default for TextDisplay {
+    fn default() -> TextDisplay {
+        TextDisplay { soft_wrap: Some(DEFAULT_SOFT_WRAP) }
+    }
+}
+
+impl TextDisplay {
+    /// Display the plain text as HTML, escaped and with `<br>` for line
+    /// breaks.
+    pub fn html<'a>(&self, text: &'a str) -> SoftWrap<'a> {
+        SoftWrap(Escape(text, QUOTE_BR), self.soft_wrap.unwrap_or(0))
+    }
+}
+
+/// Display the entry as an HTML `<article>` like `Entry::to_html()`, with
+/// its published (or updated) time.  Its HTML is filtered by the default
+/// `SanitizerPreset`, which `EntryHtml::preset()` overrides, e.g. by the
+/// one of `SubscriptionSettings::sanitizer_policy()`.  Its plain texts are
+/// soft-wrapped by the default `TextDisplay`, which `EntryHtml::text()`
+/// overrides.
+pub fn entry_html<'a>(entry: &'a Entry, time: &'a TimeDisplay)
+                      -> EntryHtml<'a> {
+    EntryHtml {
+        entry: entry,
+        time: time,
+        preset: Default::default(),
+        text: Default::default(),
+    }
+}
+
+pub struct EntryHtml<'a> {
+    entry: &'a Entry,
+    time: &'a TimeDisplay,
+    preset: SanitizerPreset,
+    text: TextDisplay,
+}
+
+impl<'a> EntryHtml<'a> {
+    pub fn preset(mut self, preset: SanitizerPreset) -> EntryHtml<'a> {
+        self.preset = preset;
+        self
+    }
+
+    pub fn text(mut self, text: TextDisplay) -> EntryHtml<'a> {
+        self.text = text;
+        self
+    }
+}
+
+impl<'a> fmt::Display for EntryHtml<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let datetime = self.entry.published_at.as_ref()
+                           .unwrap_or(&self.entry.updated_at);
+        self.entry.write_html(f, Some(&self.time.html(datetime)),
+                              self.preset, self.text.soft_wrap.unwrap_or(0))
+    }
+}
+
+/// Shorten the plain text for previews: runs of whitespace are collapsed
+/// into a space, and it's truncated to `max_graphemes` characters ending
+/// with an ellipsis if it's longer.  See also `util::truncate_graphemes()`.
+pub fn preview(text: &str, max_graphemes: usize) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if max_graphemes == 0 {
+        return String::new();
+    }
+    let truncated = truncate_graphemes(&collapsed, max_graphemes);
+    if truncated.len() == collapsed.len() {
+        return collapsed;
+    }
+    let mut result = truncate_graphemes(truncated, max_graphemes - 1)
+                         .trim_end().to_owned();
+    result.push('\u{2026}');
+    result
+}
+
+/// The preview of the text, e.g. the title of an entry, without markup.
+pub fn text_preview(text: &Text, max_graphemes: usize) -> String {
+    preview(&text.normalized_text(), max_graphemes)
+}
+
+/// The preview of the entry's summary, or its content if it has no
+/// summary.  It's `None` if it has neither, or the content isn't a text.
+pub fn entry_preview(entry: &Entry, max_graphemes: usize) -> Option<String> {
+    if let Some(ref summary) = entry.summary {
+        return Some(text_preview(summary, max_graphemes));
+    }
+    let content = match entry.content {
+        Some(ref content) => content,
+        None => { return None; }
+    };
+    if !content.is_text() {
+        return None;
+    }
+    Some(preview(&content.normalized_text(), max_graphemes))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{LocalOffset, TextDisplay, TimeDisplay, TimeZoneSetting,
+                entry_html, entry_preview, preview, text_preview};
+
+    use std::sync::Arc;
+
+    use chrono::{DateTime, FixedOffset, TimeZone, UTC};
+
+    use feed::{Content, Entry, Text};
+    use sanitizer::SanitizerPreset;
+
+    struct Dst;
+
+    impl LocalOffset for Dst {
+        /// +02:00 from April to October, and +01:00 otherwise.
+        fn offset_at(&self, datetime: &DateTime<UTC>) -> FixedOffset {
+            use chrono::Datelike;
+            match datetime.month() {
+                4..=10 => FixedOffset::east(2 * 3600),
+                _ => FixedOffset::east(3600),
+            }
+        }
+    }
+
+    #[test]
+    fn test_time_display() {
+        let winter = FixedOffset::west(5 * 3600).ymd(2013, 11, 6)
+                                                .and_hms(9, 36, 0);
+        let summer = FixedOffset::east(0).ymd(2013, 7, 1).and_hms(23, 0, 0);
+        let mut display = TimeDisplay {
+            zone: TimeZoneSetting::Original,
+            format: "%Y-%m-%d %H:%M %z".to_owned(),
+        };
+        assert_eq!(display.format(&winter), "2013-11-06 09:36 -0500");
+        display.zone = TimeZoneSetting::Fixed(FixedOffset::east(9 * 3600));
+        assert_eq!(display.format(&winter), "2013-11-06 23:36 +0900");
+        assert_eq!(display.convert(&winter), winter);
+        display.zone = TimeZoneSetting::Local(Arc::new(Dst));
+        assert_eq!(display.format(&winter), "2013-11-06 15:36 +0100");
+        assert_eq!(display.format(&summer), "2013-07-02 01:00 +0200");
+        display.format = "%H:%M".to_owned();
+        assert_eq!(display.html(&summer).to_string(),
+                   concat!("<time datetime=\"2013-07-02T01:00:00+02:00\">",
+                           "01:00</time>"));
+    }
+
+    #[test]
+    fn test_entry_html() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("Hello"),
+                                   t);
+        let display = TimeDisplay {
+            zone: TimeZoneSetting::Fixed(FixedOffset::east(9 * 3600)),
+            format: "%H:%M".to_owned(),
+        };
+        assert_eq!(entry_html(&entry, &display).to_string(),
+                   concat!("<article><h1>Hello</h1>",
+                           "<time datetime=\"2013-11-06T23:36:00+09:00\">",
+                           "23:36</time></article>"));
+        entry.published_at = Some(t - ::chrono::Duration::hours(1));
+        assert!(entry_html(&entry, &display).to_string()
+                    .contains(">22:36</time>"));
+
+        let html = "<p>A<img src=a.png><script>x()</script></p>".to_owned();
+        entry.content = Content::from_str("html", html, None::<String>);
+        assert!(entry_html(&entry, &display).to_string()
+                    .ends_with("<div><p>A</p></div></article>"));
+        let article = entry_html(&entry, &display)
+                          .preset(SanitizerPreset::Article).to_string();
+        assert!(article.ends_with("<div><p>A<img src=\"a.png\"></p></div>\
+                                   </article>"));
+    }
+
+    #[test]
+    fn test_entry_html_soft_wrap() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("Hello"),
+                                   t);
+        let long = "a".repeat(10000);
+        entry.content = Content::from_str("text", long.clone(),
+                                          None::<String>);
+        let display = TimeDisplay {
+            zone: TimeZoneSetting::Original,
+            format: "%H:%M".to_owned(),
+        };
+        let html = entry_html(&entry, &display).to_string();
+        assert_eq!(html.matches("<wbr>").count(), 10000 / 80 - 1);
+        assert!(!html.contains(&long[..81]));
+        let html = entry_html(&entry, &display)
+                       .text(TextDisplay { soft_wrap: None }).to_string();
+        assert!(html.contains(&long[..]));
+    }
+
+    #[test]
+    fn test_text_display() {
+        let display = TextDisplay { soft_wrap: Some(4) };
+        assert_eq!(display.html("abcdefghij\nab <cde>").to_string(),
+                   "abcd<wbr>efgh<wbr>ij<br>\nab &lt;cde<wbr>&gt;");
+        assert_eq!(display.html("e\u{301}e\u{301}e\u{301}e\u{301}e").to_string(),
+                   "e\u{301}e\u{301}e\u{301}e\u{301}<wbr>e");
+        let long = "a".repeat(1000);
+        assert_eq!(TextDisplay::default().html(&long).to_string()
+                       .matches("<wbr>").count(), 12);
+        assert_eq!(TextDisplay { soft_wrap: None }.html(&long).to_string(),
+                   long);
+    }
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview("  Earth\n  Reader ", 20), "Earth Reader");
+        assert_eq!(preview("Earth Reader", 7), "Earth\u{2026}");
+        assert_eq!(preview("홍민희 한글", 3), "홍민\u{2026}");
+        assert_eq!(preview("홍민희", 3), "홍민희");
+        assert_eq!(preview("abc", 0), "");
+        assert_eq!(text_preview(&Text::html("<b>Fish</b> &amp; chips"), 5),
+                   "Fish\u{2026}");
+
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("Hello"),
+                                   t);
+        assert_eq!(entry_preview(&entry, 10), None);
+        let html = "<p>안녕하세요</p>".to_owned();
+        entry.content = Content::from_str("html", html, None::<String>);
+        assert_eq!(entry_preview(&entry, 4),
+                   Some("안녕하\u{2026}".to_owned()));
+        entry.summary = Some(Text::plain("Summary"));
+        assert_eq!(entry_preview(&entry, 10), Some("Summary".to_owned()));
+    }
+}
@@ -0,0 +1,86 @@
+//! Batches of repository operations which can run in a background job,
+//! e.g. to read or write many documents of remote storages without
+//! blocking UI threads.
+use std::sync::{Arc, Mutex};
+
+use job::{self, JobHandle};
+
+use super::{Repository, Result};
+
+/// An operation in a batch.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Operation {
+    Read(Vec<String>),
+    Write(Vec<String>, Vec<u8>),
+    Delete(Vec<String>),
+}
+
+/// What an operation resulted in.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Read(Vec<u8>),
+    Written,
+    Deleted,
+}
+
+/// Run operations in order.  A failed operation doesn't stop the rest of
+/// the batch; each of result is in the same position as its operation.
+pub fn run<R: Repository>(repository: &mut R, operations: &[Operation])
+                          -> Vec<Result<Outcome>>
+{
+    operations.iter().map(|operation| match *operation {
+        Operation::Read(ref key) => repository.read(key).map(Outcome::Read),
+        Operation::Write(ref key, ref buf) => {
+            repository.write(key, Some(&buf[..])).map(|_| Outcome::Written)
+        }
+        Operation::Delete(ref key) => {
+            repository.delete(key).map(|_| Outcome::Deleted)
+        }
+    }).collect()
+}
+
+/// `run()` in a background job.  The repository is locked while the whole
+/// batch runs.
+pub fn spawn<R>(repository: Arc<Mutex<R>>, operations: Vec<Operation>)
+                -> JobHandle<Vec<Result<Outcome>>>
+    where R: Repository + Send + 'static
+{
+    job::spawn(move || {
+        let mut repository = repository.lock().unwrap();
+        run(&mut *repository, &operations)
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Operation, Outcome, spawn};
+
+    use std::sync::{Arc, Mutex};
+
+    use repository::{FileSystemRepository, Repository};
+    use test_utils::temp_dir;
+
+    fn key(name: &str) -> Vec<String> { vec!["dir".to_owned(), name.to_owned()] }
+
+    #[test]
+    fn test_batch_spawn() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let repo = Arc::new(Mutex::new(repo));
+        let handle = spawn(repo.clone(), vec![
+            Operation::Write(key("a"), b"hello".to_vec()),
+            Operation::Read(key("a")),
+            Operation::Read(key("b")),
+            Operation::Delete(key("a")),
+        ]);
+        let results = handle.join();
+        assert_eq!(results.len(), 4);
+        assert_eq!(unwrap!(results[0].as_ref()), &Outcome::Written);
+        assert_eq!(unwrap!(results[1].as_ref()),
+                   &Outcome::Read(b"hello".to_vec()));
+        assert!(results[2].is_err());
+        assert_eq!(unwrap!(results[3].as_ref()), &Outcome::Deleted);
+        assert!(!repo.lock().unwrap().exists(&key("a")));
+    }
+}
@@ -1,20 +1,37 @@
-use super::{Names, Repository, ToRepository};
+use super::{Names, Repository, ToRepository, normalize_url};
+use super::temp::{STALE_TEMP_AGE, clean_stale_temps};
 
-use std::borrow::ToOwned;
-use std::io;
+use std::borrow::Cow;
+use std::io::{self, Read, Seek};
 use std::iter::IntoIterator;
-use std::fs::{File, OpenOptions, create_dir_all, metadata, read_dir};
+use std::fs::{File, OpenOptions, create_dir_all, metadata, read_dir,
+              remove_file, rename};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use url::{Url};
 
+use util::WINDOWS_RESERVED_NAMES;
+
 /// Builtin implementation of `Repository` trait which uses the ordinary
 /// file system.
+///
+/// Keys are escaped so that they are always valid filenames on every
+/// platform (see `escape_name()`), hence the repository made on a system
+/// can be read on another system e.g. Windows.
+///
+/// Repositories made before keys were escaped may have files of unescaped
+/// names, e.g. `100%`.  Such a legacy file is still found by its key when
+/// there's no file of the escaped name, and is listed by its filename as
+/// it is if the filename isn't what `escape_name()` makes.
+#[derive(Clone)]
 pub struct FileSystemRepository {
     path: PathBuf,
 }
 
 impl FileSystemRepository {
+    /// Open the repository of the `path`, and delete temporaries left by
+    /// crashed writers in it; see the `repository::temp` module.
     pub fn from_path<P>(path: P, mkdir: bool) ->
         super::Result<FileSystemRepository>
         where P: AsRef<Path>
@@ -36,22 +53,124 @@ impl FileSystemRepository {
         if !_is_dir(&path) {
             return Err(super::Error::NotADirectory(path.into()));
         }
-        Ok(FileSystemRepository {
-            path: path.into()
-        })
+        let mut repository = FileSystemRepository { path: path.into() };
+        // stale temporaries are only garbage, so failing to list them
+        // must not keep the repository from being opened
+        let _ = clean_stale_temps(&mut repository,
+                                  Duration::from_secs(STALE_TEMP_AGE));
+        Ok(repository)
     }
 }
 
+/// Escape the key component to be a portable filename.  Characters which
+/// are not allowed in Windows filenames and `%` itself are percent-encoded,
+/// as well as the first character of reserved device names (e.g. `CON`,
+/// `aux.txt`) and trailing dots and spaces.  Other names are left as they
+/// are.
+pub fn escape_name(name: &str) -> Cow<str> {
+    fn needs_escape(c: char) -> bool {
+        c < ' ' || "%<>:\"/\\|?*".contains(c)
+    }
+    let base = name.split('.').next().unwrap_or("");
+    let reserved =
+        WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(base));
+    let trailing = name.ends_with('.') || name.ends_with(' ');
+    if !reserved && !trailing && !name.chars().any(needs_escape) {
+        return Cow::Borrowed(name);
+    }
+    let last = name.chars().count() - 1;
+    let mut escaped = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if needs_escape(c) || (i == 0 && reserved) || (i == last && trailing) {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Revert `escape_name()`.  It returns `None` if the filename isn't a valid
+/// escaped name, i.e. it's not made by the repository.
+pub fn unescape_name(filename: &str) -> Option<String> {
+    let mut name = String::with_capacity(filename.len());
+    let mut chars = filename.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            name.push(c);
+            continue;
+        }
+        let hex: String = chars.by_ref().take(2).collect();
+        match u8::from_str_radix(&hex, 16) {
+            Ok(b) if hex.len() == 2 && b < 0x80 => { name.push(b as char); }
+            _ => { return None; }
+        }
+    }
+    Some(name)
+}
+
+/// Get the key component of the `filename`.  It's `None` for filenames
+/// which can't be a key component, i.e. `.` and `..`.
+fn filename_to_name(filename: &str) -> Option<String> {
+    match unescape_name(filename) {
+        Some(ref name) if escape_name(name) == filename => Some(name.clone()),
+        // a legacy file made before keys were escaped
+        _ if is_legacy_name(filename) => Some(filename.to_owned()),
+        _ => None,
+    }
+}
+
+/// Whether the key component can be a filename as it is, as the repository
+/// used to store keys before they were escaped.
+fn is_legacy_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." &&
+        !name.contains(['/', '\\', '\0'])
+}
+
 fn _join<'a, T, I>(p: &PathBuf, key: I) -> PathBuf
     where T: AsRef<str> + 'a, I: IntoIterator<Item=T>
 {
     let mut p = p.clone();
     for k in key {
-        p.push(k.as_ref());
+        let k = k.as_ref();
+        let escaped = escape_name(k);
+        // fall back to the legacy file of the unescaped name if any
+        if escaped != k && is_legacy_name(k) &&
+           !_exists(_long_path(p.join(&*escaped))) &&
+           _exists(_long_path(p.join(k))) {
+            p.push(k);
+        } else {
+            p.push(&*escaped);
+        }
+    }
+    _long_path(p)
+}
+
+/// Windows limits paths to 260 characters unless they're prefixed by
+/// `\\?\`, which requires an absolute path.
+#[cfg(windows)]
+fn _long_path(p: PathBuf) -> PathBuf {
+    use std::path::{Component, Prefix};
+    const MAX_PATH: usize = 260;
+    let verbatim = match p.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Verbatim(_) | Prefix::VerbatimUNC(_, _) |
+            Prefix::VerbatimDisk(_) => true,
+            _ => false,
+        },
+        _ => false,
+    };
+    if verbatim || !p.is_absolute() || p.as_os_str().len() < MAX_PATH {
+        return p;
     }
-    p
+    let mut prefixed = ::std::ffi::OsString::from(r"\\?\");
+    prefixed.push(p.as_os_str());
+    PathBuf::from(prefixed)
 }
 
+#[cfg(not(windows))]
+fn _long_path(p: PathBuf) -> PathBuf { p }
+
 fn _exists<P>(path: P) -> bool where P: AsRef<Path> { metadata(path).is_ok() }
 
 fn _is_file<P>(path: P) -> bool where P: AsRef<Path> {
@@ -62,6 +181,18 @@ fn _is_dir<P>(path: P) -> bool where P: AsRef<Path> {
     metadata(path).ok().map_or(false, |m| m.is_dir())
 }
 
+fn _usage<P>(path: P) -> io::Result<u64> where P: AsRef<Path> {
+    let meta = try!(metadata(&path));
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in try!(read_dir(&path)) {
+        total += try!(_usage(try!(entry).path()));
+    }
+    Ok(total)
+}
+
 impl Repository for FileSystemRepository {
     fn get_reader<'a, T: AsRef<str>>(&'a self, key: &[T]) ->
         super::Result<Box<io::BufRead + 'a>>
@@ -74,6 +205,23 @@ impl Repository for FileSystemRepository {
         Ok(Box::new(io::BufReader::new(file)) as Box<io::BufRead>)
     }
 
+    fn get_reader_range<'a, T: AsRef<str>>(&'a self, key: &[T], start: u64,
+                                           len: Option<u64>) ->
+        super::Result<Box<io::BufRead + 'a>>
+    {
+        let path = _join(&self.path, key.iter());
+        if !_is_file(&path) {
+            return Err(super::Error::invalid_key(key, None));
+        }
+        let mut file = try!(File::open(&path));
+        try!(file.seek(io::SeekFrom::Start(start)));
+        let reader = io::BufReader::new(file);
+        match len {
+            Some(len) => Ok(Box::new(reader.take(len)) as Box<io::BufRead>),
+            None => Ok(Box::new(reader) as Box<io::BufRead>),
+        }
+    }
+
     fn get_writer<'a, T: AsRef<str>>(&'a mut self, key: &[T]) ->
         super::Result<Box<io::Write + 'a>>
     {
@@ -112,40 +260,73 @@ impl Repository for FileSystemRepository {
         _exists(_join(&self.path, key.iter()))
     }
 
+    fn delete<T: AsRef<str>>(&mut self, key: &[T]) -> super::Result<()> {
+        let path = _join(&self.path, key.iter());
+        if !_is_file(&path) {
+            return Err(super::Error::invalid_key(key, None));
+        }
+        match remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(super::Error::invalid_key(key, Some(e))),
+        }
+    }
+
+    fn rename<T, U>(&mut self, from: &[T], to: &[U]) -> super::Result<()>
+        where T: AsRef<str>, U: AsRef<str>
+    {
+        let from_path = _join(&self.path, from.iter());
+        if !_is_file(&from_path) {
+            return Err(super::Error::invalid_key(from, None));
+        }
+        let to_path = _join(&self.path, to.iter());
+        if to.is_empty() || _is_dir(&to_path) {
+            return Err(super::Error::invalid_key(to, None));
+        }
+        if let Some(dir_path) = to_path.parent() {
+            if let Err(e) = create_dir_all(dir_path) {
+                return Err(super::Error::invalid_key(to, Some(e)));
+            }
+        }
+        match rename(&from_path, &to_path) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(super::Error::invalid_key(to, Some(e))),
+        }
+    }
+
+    fn usage<T: AsRef<str>>(&self, key: &[T]) -> super::Result<u64> {
+        match _usage(_join(&self.path, key.iter())) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(super::Error::invalid_key(key, Some(e))),
+        }
+    }
+
     fn list<'a, T: AsRef<str>>(&'a self, key: &[T]) -> super::Result<Names> {
         let names = match read_dir(&_join(&self.path, key.iter())) {
             Ok(v) => v,
             Err(e) => return Err(super::Error::invalid_key(key, Some(e))),
         };
-        let iter = names.filter_map(|entry| {
-            match entry {
-                Ok(v) => {
-                    let path = v.path();
-                    let name = path.file_name().and_then(|s| s.to_str());
-                    if let Some(name) = name {
-                        Some(Ok(name.to_owned()))
-                    } else {
-                        None
-                    }
-                }
-                Err(e) => Some(Err(super::Error::Io(e))),
+        let mut listed = vec![];
+        for entry in names {
+            let entry = try!(entry.map_err(super::Error::Io));
+            let path = entry.path();
+            let name = path.file_name().and_then(|s| s.to_str())
+                           .and_then(filename_to_name);
+            if let Some(name) = name {
+                listed.push(name);
             }
-        });
+        }
+        // the order read_dir() yields names in depends on the filesystem
+        listed.sort();
+        listed.dedup();
+        let iter = listed.into_iter().map(Ok);
         Ok(Box::new(iter) as Names)
     }
 }
 
 impl ToRepository<FileSystemRepository> for Url {
     fn to_repo(&self) -> super::Result<FileSystemRepository> {
-        if self.scheme() != "file" {
-            return Err(super::Error::invalid_url(
-                "FileSystemRepository only accepts file:// scheme"));
-        } else if self.query() != None || self.fragment() != None {
-            return Err(super::Error::invalid_url(
-                concat!("file:// must not contain any host/port/user/",
-                        "password/parameters/query/fragment")));
-        }
-        let path = match self.to_file_path() {
+        let url = try!(normalize_url(self, &["file"], &[]));
+        let path = match url.to_file_path() {
             Ok(p) => p,
             Err(_) => {
                 return Err(super::Error::invalid_url("invalid file path"));
@@ -166,6 +347,7 @@ mod test {
 
     use super::super::{Repository, ToRepository};
     use super::super::Error as RepositoryError;
+    use super::super::UrlPart;
     use super::FileSystemRepository as FsRepo;
 
     use std::collections::BTreeSet;
@@ -212,6 +394,26 @@ mod test {
         let u1: Url = ToRepository::from_repo(&fs);
     }
 
+    #[cfg(not(windows))]
+    #[test]
+    fn test_file_from_url_errors() {
+        let tmpdir = temp_dir();
+        let path_str: &str = tmpdir.path().to_str().unwrap();
+        let url = Url::parse(&format!("FILE://{}", path_str)).unwrap();
+        let fs: FsRepo = url.to_repo().unwrap();
+        assert_eq!(fs.path.as_path(), tmpdir.path());
+        let url = Url::parse(&format!("http://{}", path_str)).unwrap();
+        match ToRepository::<FsRepo>::to_repo(&url) {
+            Err(RepositoryError::UnsupportedScheme(ref s)) if s == "http" => { }
+            r => panic!("unexpected result: {:?}", r.err()),
+        }
+        let url = Url::parse(&format!("file://{}?q", path_str)).unwrap();
+        match ToRepository::<FsRepo>::to_repo(&url) {
+            Err(RepositoryError::DisallowedUrlPart(UrlPart::Query)) => { }
+            r => panic!("unexpected result: {:?}", r.err()),
+        }
+    }
+
     #[test]
     fn test_file_read() {
         let tmpdir = temp_dir();
@@ -324,6 +526,67 @@ mod test {
         assert_eq!(paths, expected);
     }
 
+    #[test]
+    fn test_escape_name() {
+        for &(name, filename) in [
+            ("key", "key"), ("feed.2", "feed.2"), ("subscriptions.xml",
+                                                   "subscriptions.xml"),
+            ("CON", "%43ON"), ("aux.txt", "%61ux.txt"), ("com1", "%63om1"),
+            ("console", "console"), ("dir.", "dir%2E"), ("..", ".%2E"),
+            ("a b ", "a b%20"), ("a:b/c", "a%3Ab%2Fc"), ("100%", "100%25"),
+        ].iter() {
+            assert_eq!(super::escape_name(name), filename);
+            assert_eq!(super::unescape_name(filename).unwrap(), name);
+        }
+        assert!(super::unescape_name("%4").is_none());
+        assert!(super::unescape_name("%ZZ").is_none());
+    }
+
+    #[test]
+    fn test_file_reserved_names() {
+        let tmpdir = temp_dir();
+        let mut f = FsRepo::from_path(tmpdir.path(), true).unwrap();
+        unwrap!(f.write(&["AUX", "con.xml"], ["contents"]));
+        unwrap!(f.write(&["AUX", "dir. "], ["contents"]));
+        assert!(tmpdir.path().join("%41UX").join("%63on.xml").is_file());
+        assert!(f.exists(&["AUX", "con.xml"]));
+        assert_eq!(unwrap!(f.read(&["AUX", "con.xml"])), b"contents");
+        let names = f.list(&["AUX"]).unwrap()
+            .map(|e| e.unwrap())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(names, ["con.xml", "dir. "].iter().map(|&e| e.to_owned())
+                                              .collect::<BTreeSet<_>>());
+    }
+
+    #[test]
+    fn test_file_legacy_names() {
+        let tmpdir = temp_dir();
+        let dir = tmpdir.path().join("dir");
+        unwrap!(create_dir_all(&dir));
+        unwrap!(unwrap!(File::create(dir.join("100%"))).write_all(b"100"));
+        unwrap!(unwrap!(File::create(dir.join("a%20b"))).write_all(b"a b"));
+        unwrap!(unwrap!(File::create(dir.join("%41UX"))).write_all(b"aux"));
+        let mut f = FsRepo::from_path(tmpdir.path(), true).unwrap();
+        let names = f.list(&["dir"]).unwrap()
+            .map(|e| e.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["100%", "AUX", "a%20b"]);
+        assert!(f.exists(&["dir", "100%"]));
+        assert_eq!(unwrap!(f.read(&["dir", "100%"])), b"100");
+        assert_eq!(unwrap!(f.read(&["dir", "a%20b"])), b"a b");
+        assert!(!f.exists(&["dir", "a b"]));
+        assert_eq!(unwrap!(f.read(&["dir", "AUX"])), b"aux");
+        // the legacy file is written in place, not duplicated
+        unwrap!(f.write(&["dir", "100%"], ["updated"]));
+        assert_eq!(unwrap!(f.read(&["dir", "100%"])), b"updated");
+        assert!(!dir.join("100%25").exists());
+        unwrap!(f.delete(&["dir", "100%"]));
+        assert!(!f.exists(&["dir", "100%"]));
+        unwrap!(f.write(&["dir", "100%"], ["new"]));
+        assert!(dir.join("100%25").is_file());
+        assert!(!f.exists(&[".."]));
+    }
+
     #[test]
     fn test_file_list_on_wrong_key() {
         let tmpdir = temp_dir();
@@ -0,0 +1,269 @@
+//! Lightweight counters of repository operations, so that apps can display
+//! sync statistics and spot pathological behavior e.g. the same documents
+//! read over and over, without external profilers.
+//!
+//! `MeteredRepository` counts operations of any repository, and
+//! `DirtyBuffer` counts its own as well along with cache hits and flushes;
+//! see `Stage::metrics()`.
+use std::cell::Cell;
+use std::io;
+use std::time::Duration;
+
+use super::{Names, Repository, Result};
+
+/// A snapshot of counters.  Counters which don't apply to the wrapper,
+/// e.g. `cache_hits` and `flushes` of `MeteredRepository`, stay zero.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Metrics {
+    /// The number of documents opened to read.
+    pub reads: u64,
+
+    /// The number of documents opened to write, or deleted.
+    pub writes: u64,
+
+    /// The total bytes read from documents.
+    pub bytes_read: u64,
+
+    /// The total bytes written to documents.
+    pub bytes_written: u64,
+
+    /// The number of reads served from the buffer without touching the
+    /// underlying repository.
+    pub cache_hits: u64,
+
+    /// The number of flushes.
+    pub flushes: u64,
+
+    /// The time taken by the last flush.
+    pub last_flush: Option<Duration>,
+}
+
+/// Counters which wrappers keep.  They're cells since documents are read
+/// through shared references.
+#[derive(Default, Debug)]
+pub struct Counters {
+    reads: Cell<u64>,
+    writes: Cell<u64>,
+    bytes_read: Cell<u64>,
+    bytes_written: Cell<u64>,
+    cache_hits: Cell<u64>,
+}
+
+impl Counters {
+    pub fn new() -> Counters { Default::default() }
+
+    /// Count a read, and wrap its `reader` to count bytes read.
+    pub fn read<'a>(&'a self, reader: Box<io::BufRead + 'a>)
+                    -> Box<io::BufRead + 'a> {
+        bump(&self.reads, 1);
+        Box::new(CountingReader { inner: reader, count: &self.bytes_read })
+    }
+
+    /// Count a write, and wrap its `writer` to count bytes written.
+    pub fn write<'a>(&'a self, writer: Box<io::Write + 'a>)
+                     -> Box<io::Write + 'a> {
+        bump(&self.writes, 1);
+        Box::new(CountingWriter { inner: writer, count: &self.bytes_written })
+    }
+
+    /// Count a deletion, which is a kind of writes.
+    pub fn delete(&self) { bump(&self.writes, 1); }
+
+    /// Count a rename, which is a kind of writes.
+    pub fn rename(&self) { bump(&self.writes, 1); }
+
+    /// Count `n` bytes appended to a document, which is a kind of writes.
+    pub fn append(&self, n: usize) {
+        bump(&self.writes, 1);
+        bump(&self.bytes_written, n as u64);
+    }
+
+    /// Count a read served from the buffer.
+    pub fn hit(&self) { bump(&self.cache_hits, 1); }
+
+    /// Take a snapshot.  `flushes` and `last_flush` are left for wrappers
+    /// that flush to fill.
+    pub fn snapshot(&self) -> Metrics {
+        Metrics {
+            reads: self.reads.get(),
+            writes: self.writes.get(),
+            bytes_read: self.bytes_read.get(),
+            bytes_written: self.bytes_written.get(),
+            cache_hits: self.cache_hits.get(),
+            flushes: 0,
+            last_flush: None,
+        }
+    }
+
+    /// Reset all counters to zero.
+    pub fn reset(&self) {
+        for cell in &[&self.reads, &self.writes, &self.bytes_read,
+                      &self.bytes_written, &self.cache_hits] {
+            cell.set(0);
+        }
+    }
+}
+
+fn bump(cell: &Cell<u64>, n: u64) {
+    cell.set(cell.get().saturating_add(n));
+}
+
+struct CountingReader<'a> {
+    inner: Box<io::BufRead + 'a>,
+    count: &'a Cell<u64>,
+}
+
+impl<'a> io::Read for CountingReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        bump(self.count, n as u64);
+        Ok(n)
+    }
+}
+
+impl<'a> io::BufRead for CountingReader<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> { self.inner.fill_buf() }
+
+    fn consume(&mut self, amt: usize) {
+        bump(self.count, amt as u64);
+        self.inner.consume(amt)
+    }
+}
+
+struct CountingWriter<'a> {
+    inner: Box<io::Write + 'a>,
+    count: &'a Cell<u64>,
+}
+
+impl<'a> io::Write for CountingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        bump(self.count, n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+/// Repository wrapper which counts operations on the `inner` repository.
+pub struct MeteredRepository<R> {
+    inner: R,
+    counters: Counters,
+}
+
+impl<R: Repository> MeteredRepository<R> {
+    pub fn new(inner: R) -> MeteredRepository<R> {
+        MeteredRepository { inner: inner, counters: Counters::new() }
+    }
+
+    /// Counters as of now.
+    pub fn metrics(&self) -> Metrics { self.counters.snapshot() }
+
+    /// Reset counters to zero, e.g. at the start of each sync.
+    pub fn reset_metrics(&self) { self.counters.reset() }
+
+    pub fn inner(&self) -> &R { &self.inner }
+
+    pub fn into_inner(self) -> R { self.inner }
+}
+
+impl<R: Repository> Repository for MeteredRepository<R> {
+    fn get_reader<'a, T: AsRef<str>>(&'a self, key: &[T])
+                                     -> Result<Box<io::BufRead + 'a>> {
+        let reader = try!(self.inner.get_reader(key));
+        Ok(self.counters.read(reader))
+    }
+
+    fn get_reader_range<'a, T: AsRef<str>>(&'a self, key: &[T], start: u64,
+                                           len: Option<u64>)
+                                           -> Result<Box<io::BufRead + 'a>> {
+        let reader = try!(self.inner.get_reader_range(key, start, len));
+        Ok(self.counters.read(reader))
+    }
+
+    fn get_writer<'a, T: AsRef<str>>(&'a mut self, key: &[T])
+                                     -> Result<Box<io::Write + 'a>> {
+        let writer = try!(self.inner.get_writer(key));
+        Ok(self.counters.write(writer))
+    }
+
+    fn exists<T: AsRef<str>>(&self, key: &[T]) -> bool {
+        self.inner.exists(key)
+    }
+
+    fn list<'a, T: AsRef<str>>(&'a self, key: &[T]) -> Result<Names<'a>> {
+        self.inner.list(key)
+    }
+
+    fn delete<T: AsRef<str>>(&mut self, key: &[T]) -> Result<()> {
+        try!(self.inner.delete(key));
+        self.counters.delete();
+        Ok(())
+    }
+
+    fn rename<T, U>(&mut self, from: &[T], to: &[U]) -> Result<()>
+        where T: AsRef<str>, U: AsRef<str>
+    {
+        try!(self.inner.rename(from, to));
+        self.counters.rename();
+        Ok(())
+    }
+
+    fn usage<T: AsRef<str>>(&self, key: &[T]) -> Result<u64> {
+        self.inner.usage(key)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use test_utils::temp_dir;
+    use super::super::test::test_repository;
+
+    use super::super::{FileSystemRepository, Repository};
+    use super::{MeteredRepository, Metrics};
+
+    #[test]
+    fn test_metered_repository() {
+        let tmpdir = temp_dir();
+        let fs = unwrap!(FileSystemRepository::from_path(tmpdir.path(), true));
+        let mut repository = MeteredRepository::new(fs);
+        unwrap!(repository.write(&["dir", "key"], ["cont", "ents"]));
+        assert_eq!(unwrap!(repository.read(&["dir", "key"])), b"contents");
+        unwrap!(repository.delete(&["dir", "key"]));
+        assert!(repository.read(&["dir", "key"]).is_err());
+        assert_eq!(repository.metrics(), Metrics {
+            reads: 1,
+            writes: 2,
+            bytes_read: 8,
+            bytes_written: 8,
+            ..Default::default()
+        });
+        repository.reset_metrics();
+        assert_eq!(repository.metrics(), Metrics::default());
+    }
+
+    #[test]
+    fn test_metered_repository_rename() {
+        let tmpdir = temp_dir();
+        let fs = unwrap!(FileSystemRepository::from_path(tmpdir.path(), true));
+        let mut repository = MeteredRepository::new(fs);
+        unwrap!(repository.write(&["from"], ["contents"]));
+        repository.reset_metrics();
+        unwrap!(repository.rename(&["from"], &["to"]));
+        assert!(!repository.exists(&["from"]));
+        assert_eq!(unwrap!(repository.inner().read(&["to"])), b"contents");
+        // renamed by the inner repository rather than copied
+        assert_eq!(repository.metrics(), Metrics {
+            writes: 1,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_metered_repository_conforms() {
+        let tmpdir = temp_dir();
+        let fs = unwrap!(FileSystemRepository::from_path(tmpdir.path(), true));
+        test_repository(MeteredRepository::new(fs));
+    }
+}
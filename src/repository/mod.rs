@@ -15,10 +15,18 @@ use std::io;
 use std::iter::IntoIterator;
 use std::path::PathBuf;
 
+use url::{Url, idna};
+use url::percent_encoding::percent_decode;
+
 pub use self::utils::{Bytes, Names};
 pub use self::fs::FileSystemRepository;
+pub use self::metrics::{MeteredRepository, Metrics};
+pub use self::temp::write_atomically;
 
+pub mod batch;
 pub mod fs;
+pub mod metrics;
+pub mod temp;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -26,6 +34,8 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 pub enum Error {
     InvalidKey(Vec<String>, Option<io::Error>),
     InvalidUrl(&'static str),
+    UnsupportedScheme(String),
+    DisallowedUrlPart(UrlPart),
     NotADirectory(PathBuf),
     CannotBorrow,
     Io(io::Error),
@@ -64,6 +74,12 @@ impl fmt::Display for Error {
             Error::InvalidUrl(ref msg) => {
                 try!(write!(f, ": {}", msg));
             }
+            Error::UnsupportedScheme(ref scheme) => {
+                try!(write!(f, ": {:?}", scheme));
+            }
+            Error::DisallowedUrlPart(ref part) => {
+                try!(write!(f, ": {}", part));
+            }
             _ => { }
         }
         if let Some(cause) = self.cause() {
@@ -78,6 +94,8 @@ impl ::std::error::Error for Error {
         match *self {
             Error::InvalidKey(_, _) => "invalid key",
             Error::InvalidUrl(_) => "invalid URL",
+            Error::UnsupportedScheme(_) => "unsupported URL scheme",
+            Error::DisallowedUrlPart(_) => "URL must not contain the part",
             Error::NotADirectory(_) => "not a directory",
             Error::CannotBorrow => "can't borrow",
             Error::Io(_) => "IO error"
@@ -125,6 +143,22 @@ pub trait Repository {
     fn get_writer<'a, T>(&'a mut self, key: &[T]) -> Result<Box<io::Write + 'a>>
         where T: AsRef<str>;
 
+    /// Read `len` bytes of the content of the `key` from the `start`
+    /// offset, or to the end if `len` is `None`, e.g. to read only the head
+    /// of a large document.  It reads less bytes, or nothing, if the
+    /// content ends before.
+    ///
+    /// The default implementation reads the whole content and slices it, so
+    /// backends should redefine it if they can seek e.g. file systems.
+    fn get_reader_range<'a, T>(&'a self, key: &[T], start: u64,
+                               len: Option<u64>)
+                               -> Result<Box<io::BufRead + 'a>>
+        where T: AsRef<str>
+    {
+        let buf = try!(self.read(key));
+        Ok(range_reader(io::Cursor::new(buf), start, len))
+    }
+
     fn read<T>(&self, key: &[T]) -> Result<Vec<u8>>
         where T: AsRef<str>
     {
@@ -146,8 +180,71 @@ pub trait Repository {
     /// Return whether the `key` exists or not.
     fn exists<T: AsRef<str>>(&self, key: &[T]) -> bool;
 
-    /// List all subkeys in the `key`.
+    /// List all subkeys in the `key`, in the lexicographic order of their
+    /// names, so that every backend lists them in the same order.
     fn list<'a, T: AsRef<str>>(&'a self, key: &[T]) -> Result<Names<'a>>;
+
+    /// Remove the content of the `key`.  Keys which have subkeys can't be
+    /// removed.
+    ///
+    /// Not every storage can remove contents, so the default implementation
+    /// fails with an `Error::Io` of `io::ErrorKind::Other`.  Backends which
+    /// can remove contents should redefine it, otherwise the stage can't
+    /// drop documents e.g. to enforce quotas.
+    fn delete<T: AsRef<str>>(&mut self, _key: &[T]) -> Result<()> {
+        let message = "the repository can't delete contents";
+        Err(Error::Io(io::Error::other(message)))
+    }
+
+    /// Move the content of the `from` key to the `to` key, replacing its
+    /// content if any.  See `temp::write_atomically()`.
+    ///
+    /// The default implementation copies the content and then deletes the
+    /// `from` key, so backends should redefine it if they can rename
+    /// atomically e.g. file systems, or move contents on the server side.
+    fn rename<T, U>(&mut self, from: &[T], to: &[U]) -> Result<()>
+        where T: AsRef<str>, U: AsRef<str>
+    {
+        let buf = try!(self.read(from));
+        try!(self.write(to, &[buf]));
+        self.delete(from)
+    }
+
+    /// Return the total size in bytes of the content of the `key`, or of
+    /// all contents under the `key` if it has subkeys.  The empty `key`
+    /// means the whole repository.
+    ///
+    /// The default implementation reads every content, so backends should
+    /// redefine it with a more efficient way if they can e.g. file
+    /// metadata, or an estimate for remote storages.
+    fn usage<T: AsRef<str>>(&self, key: &[T]) -> Result<u64> {
+        let mut key: Vec<String> =
+            key.iter().map(|k| k.as_ref().to_owned()).collect();
+        if !key.is_empty() && !self.exists(&key) {
+            return Err(Error::invalid_key(&key, None));
+        }
+        let names: Vec<String> = match self.list(&key) {
+            Ok(names) => try!(names.collect()),
+            Err(_) => { return Ok(try!(self.read(&key)).len() as u64); }
+        };
+        let mut total = 0;
+        for name in names {
+            key.push(name);
+            total += try!(self.usage(&key));
+            key.pop();
+        }
+        Ok(total)
+    }
+}
+
+/// Limit the `cursor` to the range of `Repository::get_reader_range()`.
+#[doc(hidden)]
+pub fn range_reader<'a, T>(mut cursor: io::Cursor<T>, start: u64,
+                           len: Option<u64>) -> Box<io::BufRead + 'a>
+    where T: AsRef<[u8]> + 'a
+{
+    cursor.set_position(start);
+    Box::new(io::Read::take(cursor, len.unwrap_or(u64::MAX)))
 }
 
 pub trait ToRepository<R: Repository> {
@@ -162,6 +259,80 @@ pub trait ToRepository<R: Repository> {
     fn from_repo(repo: &R) -> Self;
 }
 
+/// Parts of URL that `normalize_url()` can allow or disallow.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum UrlPart {
+    Host,
+    Port,
+    Username,
+    Password,
+    Query,
+    Fragment,
+}
+
+impl fmt::Display for UrlPart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            UrlPart::Host => "host",
+            UrlPart::Port => "port",
+            UrlPart::Username => "username",
+            UrlPart::Password => "password",
+            UrlPart::Query => "query",
+            UrlPart::Fragment => "fragment",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Validate and normalize the URL which configures a repository.  It's
+/// shared by `ToRepository` implementations for `Url`.
+///
+/// The scheme is compared case-insensitively with `schemes`, and the URL
+/// must not contain any part except for `allowed`.  The host is lowercased
+/// and international domain names are converted to punycode, so the same
+/// repository is always configured by the same URL.
+pub fn normalize_url(url: &Url, schemes: &[&str], allowed: &[UrlPart])
+                     -> Result<Url>
+{
+    let scheme = url.scheme().to_lowercase();
+    if !schemes.iter().any(|s| s.to_lowercase() == scheme) {
+        return Err(Error::UnsupportedScheme(scheme));
+    }
+    let parts = [
+        (UrlPart::Host, url.host_str().is_some_and(|h| !h.is_empty())),
+        (UrlPart::Port, url.port().is_some()),
+        (UrlPart::Username, !url.username().is_empty()),
+        (UrlPart::Password, url.password().is_some()),
+        (UrlPart::Query, url.query().is_some()),
+        (UrlPart::Fragment, url.fragment().is_some()),
+    ];
+    for &(part, present) in parts.iter() {
+        if present && !allowed.contains(&part) {
+            return Err(Error::DisallowedUrlPart(part));
+        }
+    }
+    let mut url = url.clone();
+    // Hosts of non-special schemes are kept percent-encoded by the parser.
+    let host = match url.domain() {
+        Some(domain) => {
+            let decoded = percent_decode(domain.as_bytes()).decode_utf8();
+            match decoded.ok().and_then(|d| idna::domain_to_ascii(&d).ok()) {
+                Some(h) => Some(h),
+                None => {
+                    return Err(Error::invalid_url("invalid domain name"));
+                }
+            }
+        }
+        None => None,
+    };
+    if let Some(host) = host {
+        if url.set_host(Some(&host)).is_err() {
+            return Err(Error::invalid_url("invalid domain name"));
+        }
+    }
+    Ok(url)
+}
+
 mod utils {
     pub type Names<'a> = Box<Iterator<Item=super::Result<String>> + 'a>;
 
@@ -193,13 +364,15 @@ mod utils {
 #[cfg(test)]
 #[macro_use]
 pub mod test {
-    use super::{Names, Repository};
+    use super::{Error, Names, Repository, UrlPart, normalize_url};
 
     use std::borrow::ToOwned;
     use std::collections::BTreeSet;
     use std::io;
     use std::marker::PhantomData;
 
+    use url::Url;
+
     struct RepositoryImplemented;
 
     impl Repository for RepositoryImplemented {
@@ -244,8 +417,49 @@ pub mod test {
             writer.write_all("Hello".as_bytes()).unwrap();
         }
         assert!(repository.exists(&["key"]));
-        let mut path_list = repository.list(&["key"]).unwrap();
-        assert!(path_list.next().is_none());
+        assert!(unwrap!(read_range(&repository, &["key"], 1, None))
+                    .is_empty());
+        {
+            let mut path_list = repository.list(&["key"]).unwrap();
+            assert!(path_list.next().is_none());
+        }
+        match repository.delete(&["key"]) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::Other => { }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        let all = [UrlPart::Host, UrlPart::Query];
+        let url = Url::parse("HTTP://Example.COM/Path?q").unwrap();
+        assert_eq!(unwrap!(normalize_url(&url, &["http"], &all)).as_str(),
+                   "http://example.com/Path?q");
+        let url = Url::parse("Dropbox://B\u{fc}cher.EXAMPLE/Path").unwrap();
+        assert_eq!(unwrap!(normalize_url(&url, &["dropbox"], &all)).as_str(),
+                   "dropbox://xn--bcher-kva.example/Path");
+        match normalize_url(&url, &["file"], &all) {
+            Err(Error::UnsupportedScheme(ref s)) if s == "dropbox" => { }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let url = Url::parse("http://user@example.com/").unwrap();
+        match normalize_url(&url, &["http"], &all) {
+            Err(Error::DisallowedUrlPart(UrlPart::Username)) => { }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let url = Url::parse("http://example.com/#frag").unwrap();
+        match normalize_url(&url, &["http"], &all) {
+            Err(Error::DisallowedUrlPart(UrlPart::Fragment)) => { }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    fn read_range<R: Repository>(repository: &R, key: &[&str], start: u64,
+                                 len: Option<u64>) -> super::Result<Vec<u8>> {
+        let mut buf = vec![];
+        let mut reader = try!(repository.get_reader_range(key, start, len));
+        try!(reader.read_to_end(&mut buf));
+        Ok(buf)
     }
 
     pub fn test_repository<R: Repository>(mut repository: R) {
@@ -262,6 +476,16 @@ pub mod test {
             ["key"]);
         assert!(repository.exists(&["key"]));
         assert_eq!(unwrap!(repository.read(&["key"])), b"contents");
+        // range test
+        assert_eq!(unwrap!(read_range(&repository, &["key"], 2, Some(3))),
+                   b"nte");
+        assert_eq!(unwrap!(read_range(&repository, &["key"], 2, None)),
+                   b"ntents");
+        assert_eq!(unwrap!(read_range(&repository, &["key"], 6, Some(9))),
+                   b"ts");
+        assert!(unwrap!(read_range(&repository, &["key"], 9, None))
+                    .is_empty());
+        assert!(read_range(&repository, &["not-exist"], 0, None).is_err());
         assert!(!repository.exists(&["dir", "key"]));
         expect_invalid_key!(repository.read, &["dir", "key"]);
         unwrap!(repository.write(&["dir", "key"], &["cont", "ents"]));
@@ -277,5 +501,27 @@ pub mod test {
         // directory test
         expect_invalid_key!(repository.get_writer, &["key", "key"]);
         expect_invalid_key!(repository.list, &["key"]);
+        // usage test
+        assert_eq!(unwrap!(repository.usage(&["key"])), 8);
+        assert_eq!(unwrap!(repository.usage(&["dir"])), 8);
+        assert_eq!(unwrap!(repository.usage(empty)), 16);
+        expect_invalid_key!(repository.usage, &["not-exist"]);
+        // delete test
+        expect_invalid_key!(repository.delete, &["dir"]);
+        expect_invalid_key!(repository.delete, &["not-exist"]);
+        unwrap!(repository.delete(&["dir", "key"]));
+        assert!(!repository.exists(&["dir", "key"]));
+        expect_invalid_key!(repository.read, &["dir", "key"]);
+        assert_eq!(unwrap!(repository.usage(empty)), 8);
+        unwrap!(repository.write(&["dir", "key"], ["contents"]));
+        assert_eq!(unwrap!(repository.read(&["dir", "key"])), b"contents");
+        // order test
+        for name in &["b", "c", "Z", "a", "a.2"] {
+            unwrap!(repository.write(&["dir", name], ["contents"]));
+        }
+        assert_eq!(
+            repository.list(&["dir"]).unwrap().map(|e| e.unwrap())
+                .collect::<Vec<_>>(),
+            ["Z", "a", "a.2", "b", "c", "key"]);
     }
 }
@@ -0,0 +1,186 @@
+//! Temporary keys for two-phase writes, e.g. partial uploads to remote
+//! repositories: contents are written to a temporary key first, and then
+//! renamed to the real key once they're complete, so that readers never
+//! see half-written documents.  See `write_atomically()`.
+//!
+//! Temporary keys are under the `.tmp` key, and named after when they were
+//! allocated followed by a random part, so that they don't collide even
+//! across processes and devices sharing the repository.  Temporaries left
+//! by crashed writers are deleted by `clean_stale_temps()`, which
+//! repositories call when they're opened.
+use std::iter::IntoIterator;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use util::{IdGenerator, UuidGenerator};
+
+use super::{Bytes, Repository, Result};
+
+/// The key temporary keys are under.
+pub const TEMP_DIR: &'static str = ".tmp";
+
+/// How old, in seconds, temporaries are regarded as left by crashed
+/// writers.  Uploads of the largest documents should finish within it.
+pub const STALE_TEMP_AGE: u64 = 24 * 60 * 60;
+
+/// Allocate a temporary key which doesn't exist in the `repository`.
+pub fn allocate_temp_key<R: Repository>(repository: &R) -> Vec<String> {
+    loop {
+        let key = vec![TEMP_DIR.to_owned(), temp_name(unix_now())];
+        if !repository.exists(&key) {
+            return key;
+        }
+    }
+}
+
+fn temp_name(now: u64) -> String {
+    let uuid = UuidGenerator.generate();
+    format!("{}-{}", now, &uuid["urn:uuid:".len()..])
+}
+
+/// When the temporary of the name was allocated, in seconds since the
+/// epoch.  It's `None` if it isn't made by `allocate_temp_key()`.
+fn allocated_at(name: &str) -> Option<u64> {
+    name.split('-').next().and_then(|secs| secs.parse().ok())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs())
+                     .unwrap_or(0)
+}
+
+/// Write the `buf` to the `key` through a temporary key, so that the
+/// `key` has either its old or new contents but nothing in between, as far
+/// as the `repository` renames atomically.
+pub fn write_atomically<R, T, U, I>(repository: &mut R, key: &[T], buf: I)
+                                    -> Result<()>
+    where R: Repository, T: AsRef<str>, U: Bytes,
+          I: IntoIterator<Item=U>
+{
+    let temp = allocate_temp_key(repository);
+    if let Err(e) = repository.write(&temp, buf) {
+        let _ = repository.delete(&temp);
+        return Err(e);
+    }
+    repository.rename(&temp, key)
+}
+
+/// Delete temporaries allocated more than `max_age` ago, and return how
+/// many were deleted.  Keys under `TEMP_DIR` not made by
+/// `allocate_temp_key()` are left as they are, and so are temporaries which
+/// fail to be deleted, e.g. in read-only repositories; they're tried again
+/// next time.
+pub fn clean_stale_temps<R>(repository: &mut R, max_age: Duration)
+                            -> Result<usize>
+    where R: Repository
+{
+    if !repository.exists(&[TEMP_DIR]) {
+        return Ok(0);
+    }
+    let now = unix_now();
+    let names: Vec<String> = try!(try!(repository.list(&[TEMP_DIR]))
+                                      .collect());
+    let mut deleted = 0;
+    for name in names {
+        match allocated_at(&name) {
+            Some(at) if now.saturating_sub(at) > max_age.as_secs() &&
+                repository.delete(&[TEMP_DIR, &name[..]]).is_ok() => {
+                deleted += 1;
+            }
+            _ => { }
+        }
+    }
+    Ok(deleted)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{TEMP_DIR, allocate_temp_key, clean_stale_temps, temp_name,
+                write_atomically};
+
+    use std::collections::HashSet;
+    use std::io;
+    use std::time::Duration;
+
+    use test_utils::temp_dir;
+    use super::super::{FileSystemRepository, Names, Repository, Result};
+
+    #[test]
+    fn test_write_atomically() {
+        let tmpdir = temp_dir();
+        let mut repository = unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true));
+        let keys: HashSet<Vec<String>> =
+            (0..100).map(|_| allocate_temp_key(&repository)).collect();
+        assert_eq!(keys.len(), 100);
+        assert!(keys.iter().all(|k| k[0] == TEMP_DIR));
+
+        unwrap!(repository.write(&["dir", "key"], ["old"]));
+        unwrap!(write_atomically(&mut repository, &["dir", "key"],
+                                 ["new ", "contents"]));
+        assert_eq!(unwrap!(repository.read(&["dir", "key"])),
+                   b"new contents");
+        assert_eq!(unwrap!(repository.list(&[TEMP_DIR])).count(), 0);
+    }
+
+    #[test]
+    fn test_clean_stale_temps() {
+        let tmpdir = temp_dir();
+        let mut repository = unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true));
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(unwrap!(clean_stale_temps(&mut repository, day)), 0);
+        let stale = temp_name(1383748560);
+        unwrap!(repository.write(&[TEMP_DIR, &stale[..]], ["partial"]));
+        unwrap!(repository.write(&[TEMP_DIR, "other"], ["kept"]));
+        let fresh = allocate_temp_key(&repository);
+        unwrap!(repository.write(&fresh, ["partial"]));
+        assert_eq!(unwrap!(clean_stale_temps(&mut repository, day)), 1);
+        assert!(!repository.exists(&[TEMP_DIR, &stale[..]]));
+        assert!(repository.exists(&[TEMP_DIR, "other"]));
+        assert!(repository.exists(&fresh));
+
+        // repositories clean them up when they're opened
+        unwrap!(repository.write(&[TEMP_DIR, &stale[..]], ["partial"]));
+        let repository = unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true));
+        assert!(!repository.exists(&[TEMP_DIR, &stale[..]]));
+    }
+
+    /// A repository which can't delete contents, as if it's read-only.
+    struct Undeletable(FileSystemRepository);
+
+    impl Repository for Undeletable {
+        fn get_reader<'a, T: AsRef<str>>(&'a self, key: &[T])
+                                         -> Result<Box<io::BufRead + 'a>> {
+            self.0.get_reader(key)
+        }
+
+        fn get_writer<'a, T: AsRef<str>>(&'a mut self, key: &[T])
+                                         -> Result<Box<io::Write + 'a>> {
+            self.0.get_writer(key)
+        }
+
+        fn exists<T: AsRef<str>>(&self, key: &[T]) -> bool {
+            self.0.exists(key)
+        }
+
+        fn list<'a, T: AsRef<str>>(&'a self, key: &[T])
+                                   -> Result<Names<'a>> {
+            self.0.list(key)
+        }
+    }
+
+    #[test]
+    fn test_clean_stale_temps_undeletable() {
+        let tmpdir = temp_dir();
+        let mut fs = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                             true));
+        let stale = temp_name(1383748560);
+        unwrap!(fs.write(&[TEMP_DIR, &stale[..]], ["partial"]));
+        let mut repository = Undeletable(fs);
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(unwrap!(clean_stale_temps(&mut repository, day)), 0);
+        assert!(repository.exists(&[TEMP_DIR, &stale[..]]));
+    }
+}
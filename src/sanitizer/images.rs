@@ -0,0 +1,206 @@
+//! Sizing inline images, so that layouts don't jump while they're loaded.
+//!
+//! `size_images()` makes `<img>` elements have both `width` and `height`
+//! attributes: the missing one is calculated from the other by the aspect
+//! ratio, or both are taken as they are if neither is written.  Sizes of
+//! images are looked up through `ImageSizes`, e.g. from metadata of images
+//! the app cached, or by `image_size()` from cached images themselves.
+use std::collections::HashMap;
+
+use super::escape;
+use super::preset::{Tag, parse_tag};
+
+/// Looks up the size of the image, as a pair of width and height in pixels,
+/// by its URL as it's written in `src`.
+pub trait ImageSizes {
+    fn image_size(&self, src: &str) -> Option<(u32, u32)>;
+}
+
+impl ImageSizes for HashMap<String, (u32, u32)> {
+    fn image_size(&self, src: &str) -> Option<(u32, u32)> {
+        self.get(src).cloned()
+    }
+}
+
+/// Read the size of the image from the header of its data.  PNG, GIF,
+/// JPEG, and WebP are recognized; it's `None` for other formats.
+///
+/// ```
+/// # use earth::sanitizer::image_size;
+/// assert_eq!(image_size(b"GIF89a\x10\x00\x20\x00"), Some((16, 32)));
+/// assert_eq!(image_size(b"<svg/>"), None);
+/// ```
+pub fn image_size(data: &[u8]) -> Option<(u32, u32)> {
+    fn be16(b: &[u8]) -> u32 { (b[0] as u32) << 8 | b[1] as u32 }
+    fn le16(b: &[u8]) -> u32 { (b[1] as u32) << 8 | b[0] as u32 }
+    fn le24(b: &[u8]) -> u32 { (b[2] as u32) << 16 | le16(b) }
+    fn be32(b: &[u8]) -> u32 { be16(b) << 16 | be16(&b[2..]) }
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.len() >= 24 &&
+       &data[12..16] == b"IHDR" {
+        return Some((be32(&data[16..]), be32(&data[20..])));
+    }
+    if (data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")) &&
+       data.len() >= 10 {
+        return Some((le16(&data[6..]), le16(&data[8..])));
+    }
+    if data.starts_with(b"RIFF") && data.len() >= 30 &&
+       &data[8..12] == b"WEBP" {
+        return match &data[12..16] {
+            b"VP8X" => Some((le24(&data[24..]) + 1, le24(&data[27..]) + 1)),
+            b"VP8 " => Some((le16(&data[26..]) & 0x3fff,
+                             le16(&data[28..]) & 0x3fff)),
+            b"VP8L" if data[20] == 0x2f => {
+                let bits = le16(&data[21..]) | le16(&data[23..]) << 16;
+                Some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+            }
+            _ => None,
+        };
+    }
+    if data.starts_with(b"\xff\xd8") {
+        let mut i = 2;
+        while i + 4 <= data.len() {
+            if data[i] != 0xff {
+                return None;
+            }
+            let marker = data[i + 1];
+            if marker == 0xff {
+                i += 1;
+                continue;
+            }
+            if marker == 0x01 || (0xd0..=0xd9).contains(&marker) {
+                i += 2;
+                continue;
+            }
+            let len = be16(&data[i + 2..]) as usize;
+            let is_frame = (0xc0..=0xcf).contains(&marker) &&
+                           marker != 0xc4 && marker != 0xc8 &&
+                           marker != 0xcc;
+            if is_frame {
+                if i + 9 > data.len() {
+                    return None;
+                }
+                return Some((be16(&data[i + 7..]), be16(&data[i + 5..])));
+            }
+            i += 2 + len;
+        }
+    }
+    None
+}
+
+/// Write `width` and `height` attributes to `<img>` elements of the HTML
+/// which lack either of them, by sizes the `sizes` know.  Elements whose
+/// sizes are unknown, or whose attributes aren't in pixels, e.g. `50%`, are
+/// left as they are.
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use earth::sanitizer::size_images;
+/// let mut sizes = HashMap::new();
+/// sizes.insert("a.png".to_owned(), (400, 300));
+/// assert_eq!(size_images("<p><img src=a.png width=200></p>", &sizes),
+///            "<p><img src=\"a.png\" width=\"200\" height=\"150\"></p>");
+/// ```
+pub fn size_images<S: ImageSizes + ?Sized>(html: &str, sizes: &S) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(i) = rest.find('<') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let (tag, len) = match parse_tag(rest) {
+            Some((ref tag, len)) if tag.name == "img" && !tag.end => {
+                (size_image(tag, sizes), len)
+            }
+            Some((_, len)) => (None, len),
+            None => (None, 1),
+        };
+        match tag {
+            Some(tag) => { result.push_str(&tag); }
+            None => { result.push_str(&rest[..len]); }
+        }
+        rest = &rest[len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The `<img>` tag with both `width` and `height`, or `None` if it's left
+/// as it is.
+fn size_image<S>(tag: &Tag, sizes: &S) -> Option<String>
+    where S: ImageSizes + ?Sized
+{
+    fn pixels(value: Option<&str>) -> Option<Option<u32>> {
+        match value {
+            Some(v) => {
+                let v = v.trim();
+                let v = v.strip_suffix("px").unwrap_or(v);
+                v.parse().ok().map(Some)
+            }
+            None => Some(None),
+        }
+    }
+    let (width, height) = match (pixels(tag.attribute("width")),
+                                 pixels(tag.attribute("height"))) {
+        (Some(Some(_)), Some(Some(_))) => { return None; }
+        (Some(width), Some(height)) => (width, height),
+        _ => { return None; }
+    };
+    let src = tag.attribute("src").map(str::trim);
+    let (w, h) = match src.and_then(|src| sizes.image_size(src)) {
+        Some((w, h)) if w > 0 && h > 0 => (w, h),
+        _ => { return None; }
+    };
+    let scale = |n: u32, num: u32, den: u32| {
+        ((n as u64 * num as u64 + den as u64 / 2) / den as u64) as u32
+    };
+    let (width, height) = match (width, height) {
+        (Some(width), None) => (width, scale(width, h, w)),
+        (None, Some(height)) => (scale(height, w, h), height),
+        _ => (w, h),
+    };
+    let mut sized = String::from("<img");
+    for (name, value) in tag.attributes.iter() {
+        if name != "width" && name != "height" {
+            sized.push_str(&format!(" {}=\"{}\"", name, escape(value, true)));
+        }
+    }
+    sized.push_str(&format!(" width=\"{}\" height=\"{}\">", width, height));
+    Some(sized)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{image_size, size_images};
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_image_size() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\
+                    \x00\x00\x01\x90\x00\x00\x01\x2c\x08\x06";
+        assert_eq!(image_size(png), Some((400, 300)));
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04JF\
+                     \xff\xc0\x00\x11\x08\x01\x2c\x01\x90\x03";
+        assert_eq!(image_size(jpeg), Some((400, 300)));
+        let webp = b"RIFF\x00\x00\x00\x00WEBPVP8X\x0a\x00\x00\x00\
+                     \x00\x00\x00\x00\x8f\x01\x00\x2b\x01\x00";
+        assert_eq!(image_size(webp), Some((400, 300)));
+        assert_eq!(image_size(b"\xff\xd8\xff"), None);
+        assert_eq!(image_size(b""), None);
+    }
+
+    #[test]
+    fn test_size_images() {
+        let mut sizes = HashMap::new();
+        sizes.insert("a.png".to_owned(), (400, 300));
+        assert_eq!(size_images("<img src=a.png alt='A &amp; B'>", &sizes),
+                   "<img src=\"a.png\" alt=\"A &amp; B\" \
+                    width=\"400\" height=\"300\">");
+        assert_eq!(size_images("<img src=a.png height=30px>", &sizes),
+                   "<img src=\"a.png\" width=\"40\" height=\"30\">");
+        let untouched = "<img src=a.png width=50%><img src=b.png>\
+                         <img src=a.png width=1 height=2></img> 1 < 2";
+        assert_eq!(size_images(untouched, &sizes), untouched);
+    }
+}
@@ -2,8 +2,34 @@
 use std::borrow::Cow;
 use std::fmt;
 
+use util::graphemes;
+
 #[cfg(html_sanitizer)] mod html;
 #[cfg(html_sanitizer)] pub use html::{clean_html, sanitize_html};
+mod images;
+pub use self::images::{ImageSizes, image_size, size_images};
+mod preset;
+pub use self::preset::{EMBED_HOSTS, Filter, SanitizerPreset, code_language};
+#[doc(hidden)]
+pub use self::preset::{Tag, decode_references, parse_tag};
+
+/// Options of sanitizing HTML which can differ by feeds.  Subscriptions
+/// override the app-wide policy through
+/// `SubscriptionSettings::sanitizer_policy()`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SanitizerPolicy {
+    /// Whether `<iframe>` elements e.g. embedded videos are kept.
+    pub allow_iframes: bool,
+
+    /// Whether images are loaded through the app's image proxy rather than
+    /// from their origins.
+    pub proxy_images: bool,
+
+    /// Which elements are kept.  `SanitizerPreset::Embed` keeps iframes of
+    /// `EMBED_HOSTS` even if `allow_iframes` is false.
+    pub preset: SanitizerPreset,
+}
 
 /// Convert given string to HTML-safe sequences by replacing the characters
 /// `&`, `<` and `>`.  If the optional `flag` quote is true, the characters `"`
@@ -55,21 +81,154 @@ pub struct Escape<'a>(#[doc(hidden)] pub &'a str,
 
 impl<'a> fmt::Display for Escape<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let table = &self.1;
+        write_escaped(f, self.0, &self.1)
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter, s: &str,
+                 table: &[(char, &'static str)]) -> fmt::Result {
+    let mut last_written = 0usize;
+    for (i, ch) in s.char_indices() {
+        let q = table.iter().filter_map(|&(m, alter)| {
+            if ch == m { Some(alter) } else { None }
+        }).next();
+        if let Some(quoted) = q {
+            try!(f.write_str(&s[last_written..i]));
+            try!(f.write_str(quoted));
+            last_written = i + ch.len_utf8();
+        }
+    }
+    if last_written < s.len() {
+        try!(f.write_str(&s[last_written..]));
+    }
+    Ok(())
+}
+
+/// Escape the text like `escape()` does, and insert break opportunities
+/// (`<wbr>`) into runs of more than `width` characters without whitespace,
+/// so that megabyte-long lines e.g. pasted base64 don't stretch layouts.
+/// Breaks are only inserted between grapheme clusters, and never inside
+/// escaped character references.  See also `render::TextDisplay`.
+///
+/// ### Example
+///
+/// ```
+/// # use earth::sanitizer::soft_wrap;
+/// assert_eq!(format!("{}", soft_wrap("a&b&c d", false, 2)),
+///            "a&amp;<wbr>b&amp;<wbr>c d");
+/// ```
+pub fn soft_wrap<'a>(text: &'a str, quote: bool, width: usize)
+                     -> SoftWrap<'a> {
+    SoftWrap(escape(text, quote), width)
+}
+
+pub struct SoftWrap<'a>(#[doc(hidden)] pub Escape<'a>,
+                        #[doc(hidden)] pub usize);
+
+impl<'a> fmt::Display for SoftWrap<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let SoftWrap(Escape(text, ref table), width) = *self;
+        if width == 0 {
+            return write_escaped(f, text, table);
+        }
+        let mut run = 0usize;
         let mut last_written = 0usize;
-        for (i, ch) in self.0.char_indices() {
-            let q = table.iter().filter_map(|&(m, alter)| {
-                if ch == m { Some(alter) } else { None }
-            }).next();
-            if let Some(quoted) = q {
-                try!(f.write_str(&self.0[last_written..i]));
-                try!(f.write_str(quoted));
-                last_written = i + ch.len_utf8();
+        for (i, cluster) in graphemes(text) {
+            if cluster.starts_with(char::is_whitespace) {
+                run = 0;
+                continue;
+            }
+            if run == width {
+                try!(write_escaped(f, &text[last_written..i], table));
+                try!(f.write_str("<wbr>"));
+                last_written = i;
+                run = 0;
+            }
+            run += 1;
+        }
+        write_escaped(f, &text[last_written..], table)
+    }
+}
+
+/// Get the plain text of the HTML string, by removing markup tags and
+/// decoding character references.  Contents of `<script>` and `<style>`
+/// are dropped as well.  Unlike `clean_html()`, it doesn't need the HTML
+/// sanitizer, but it isn't a full HTML tokenizer either.
+///
+/// ### Example
+///
+/// ```
+/// # use earth::sanitizer::strip_tags;
+/// let s = "<p>Fish &amp; <em>chips</em></p><script>x()</script>";
+/// assert_eq!(strip_tags(s), "Fish & chips");
+/// ```
+pub fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut skip_until = None;
+    while let Some(i) = rest.find(['<', '&']) {
+        if skip_until.is_none() {
+            text.push_str(&rest[..i]);
+        }
+        rest = &rest[i..];
+        if rest.starts_with('&') {
+            let (decoded, len) = decode_reference(rest);
+            if skip_until.is_none() {
+                text.push_str(&decoded);
+            }
+            rest = &rest[len..];
+            continue;
+        }
+        let end = rest.find('>').map_or(rest.len(), |e| e + 1);
+        let tag = rest[1..end].trim_end_matches('>').to_lowercase();
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/')
+                      .find(|s| !s.is_empty()).unwrap_or("");
+        match skip_until {
+            Some(skipped) if tag.starts_with('/') && name == skipped => {
+                skip_until = None;
+            }
+            Some(_) => { }
+            None if !tag.starts_with('/') &&
+                    (name == "script" || name == "style") => {
+                skip_until = Some(if name == "script" { "script" }
+                                  else { "style" });
             }
+            None => { }
+        }
+        rest = &rest[end..];
+    }
+    if skip_until.is_none() {
+        text.push_str(rest);
+    }
+    text
+}
+
+/// Decode the character reference at the start of `s`, and return it with
+/// the length it takes.  Unknown references are kept as they are.
+fn decode_reference(s: &str) -> (Cow<'static, str>, usize) {
+    let end = match s.find(';') {
+        Some(end) if end <= 10 => end,
+        _ => { return (Cow::Borrowed("&"), 1); }
+    };
+    let name = &s[1..end];
+    let decoded = match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ if name.starts_with("#x") || name.starts_with("#X") => {
+            u32::from_str_radix(&name[2..], 16).ok()
+                .and_then(::std::char::from_u32)
         }
-        if last_written < self.0.len() {
-            try!(f.write_str(&self.0[last_written..]));
+        _ if name.starts_with('#') => {
+            name[1..].parse().ok().and_then(::std::char::from_u32)
         }
-        Ok(())
+        _ => None,
+    };
+    match decoded {
+        Some(c) => (Cow::Owned(c.to_string()), end + 1),
+        None => (Cow::Borrowed("&"), 1),
     }
 }
@@ -0,0 +1,465 @@
+//! Named sets of HTML elements and attributes kept by sanitizing, so that
+//! apps don't have to hand-roll allowed-tag lists.  Unlike `clean_html()`,
+//! filtering by presets doesn't need the HTML sanitizer, so it's what
+//! `Entry` uses to render its HTML.
+use std::fmt;
+use std::str::FromStr;
+
+use url::Url;
+
+use super::{decode_reference, escape};
+
+/// How much of HTML is kept by sanitizing.  Each preset keeps everything
+/// the preceding one keeps.  Elements not allowed are dropped while their
+/// contents are kept, except for `<script>` and `<style>` whose contents
+/// are dropped as well.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SanitizerPreset {
+    /// Inline formatting, paragraphs, lists, quotations and links.
+    #[default]
+    Strict,
+
+    /// `Strict` and tables, figures, code blocks and images.  Classes
+    /// telling languages of code blocks (see `code_language()`) and ones
+    /// marking TeX math, and MathML are kept as well, so that apps can
+    /// highlight code and render math on their side.
+    Article,
+
+    /// `Article` and `<iframe>` elements embedding players of well-known
+    /// hosts e.g. YouTube and Vimeo.  See `EMBED_HOSTS`.
+    Embed,
+}
+
+const STRICT_ELEMENTS: &'static [&'static str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "blockquote", "br", "cite", "code", "dd",
+    "del", "dfn", "div", "dl", "dt", "em", "h1", "h2", "h3", "h4", "h5", "h6",
+    "hr", "i", "ins", "kbd", "li", "mark", "ol", "p", "q", "s", "samp",
+    "small", "span", "strong", "sub", "sup", "time", "u", "ul", "var",
+];
+
+const ARTICLE_ELEMENTS: &'static [&'static str] = &[
+    "caption", "col", "colgroup", "figcaption", "figure", "img", "pre",
+    "table", "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+const MATH_ELEMENTS: &'static [&'static str] = &[
+    "annotation", "math", "menclose", "mfrac", "mi", "mn", "mo", "mover",
+    "mpadded", "mphantom", "mroot", "mrow", "ms", "mspace", "msqrt",
+    "mstyle", "msub", "msubsup", "msup", "mtable", "mtd", "mtext", "mtr",
+    "munder", "munderover", "semantics",
+];
+
+/// Classes of `<span>` and `<div>` elements marking TeX math, which
+/// renderers e.g. MathJax and KaTeX look for.
+const MATH_CLASSES: &'static [&'static str] = &[
+    "math", "math-display", "math-inline", "tex",
+];
+
+/// Hosts whose `<iframe>` elements are kept by `SanitizerPreset::Embed`.
+pub const EMBED_HOSTS: &'static [&'static str] = &[
+    "www.youtube.com",
+    "www.youtube-nocookie.com",
+    "player.vimeo.com",
+    "w.soundcloud.com",
+];
+
+/// Attributes whose values are URLs.
+const URL_ATTRIBUTES: &'static [&'static str] = &["cite", "href", "src"];
+
+/// Schemes URLs of which are kept.  Relative URLs are kept as well.
+const URL_SCHEMES: &'static [&'static str] = &["http", "https", "mailto"];
+
+impl SanitizerPreset {
+    /// The name used in settings, e.g. `"article"`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SanitizerPreset::Strict => "strict",
+            SanitizerPreset::Article => "article",
+            SanitizerPreset::Embed => "embed",
+        }
+    }
+
+    /// Whether the element of the lowercase `name` is kept.
+    pub fn allows_element(&self, name: &str) -> bool {
+        STRICT_ELEMENTS.contains(&name) ||
+            *self != SanitizerPreset::Strict &&
+                (ARTICLE_ELEMENTS.contains(&name) ||
+                 MATH_ELEMENTS.contains(&name)) ||
+            *self == SanitizerPreset::Embed && name == "iframe"
+    }
+
+    /// Filter the HTML string by the preset.
+    ///
+    /// ### Example
+    ///
+    /// ```
+    /// # use earth::sanitizer::SanitizerPreset;
+    /// let html = "<table><tr><td onclick=\"x()\">1</td></tr></table>";
+    /// assert_eq!(SanitizerPreset::Strict.filter(html).to_string(), "1");
+    /// assert_eq!(SanitizerPreset::Article.filter(html).to_string(),
+    ///            "<table><tr><td>1</td></tr></table>");
+    /// ```
+    pub fn filter<'a>(&self, html: &'a str) -> Filter<'a> {
+        Filter(*self, html)
+    }
+}
+
+impl fmt::Display for SanitizerPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SanitizerPreset {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SanitizerPreset, ()> {
+        match &s.to_lowercase()[..] {
+            "strict" => Ok(SanitizerPreset::Strict),
+            "article" => Ok(SanitizerPreset::Article),
+            "embed" => Ok(SanitizerPreset::Embed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The language the `class` attribute of a code block tells, e.g. `rust`
+/// of `language-rust` or `lang-rust`, as Markdown renderers and syntax
+/// highlighters mark it.
+///
+/// ```
+/// # use earth::sanitizer::code_language;
+/// assert_eq!(code_language("highlight language-rust"), Some("rust"));
+/// assert_eq!(code_language("highlight"), None);
+/// ```
+pub fn code_language(class: &str) -> Option<&str> {
+    class.split_whitespace().filter_map(|c| {
+        c.strip_prefix("language-").or_else(|| c.strip_prefix("lang-"))
+    }).find(|language| !language.is_empty())
+}
+
+pub struct Filter<'a>(#[doc(hidden)] pub SanitizerPreset,
+                      #[doc(hidden)] pub &'a str);
+
+impl<'a> fmt::Display for Filter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Filter(preset, html) = *self;
+        let mut rest = html;
+        let mut skip_until: Option<String> = None;
+        // end tags of iframes which were dropped for their hosts
+        let mut dropped_iframes = 0usize;
+        while let Some(i) = rest.find('<') {
+            if skip_until.is_none() {
+                try!(f.write_str(&rest[..i]));
+            }
+            rest = &rest[i..];
+            if rest.starts_with("<!--") {
+                rest = rest.find("-->").map_or("", |e| &rest[e + 3..]);
+                continue;
+            }
+            if rest.starts_with("<!") || rest.starts_with("<?") {
+                rest = rest.find('>').map_or("", |e| &rest[e + 1..]);
+                continue;
+            }
+            let (tag, len) = match parse_tag(rest) {
+                Some(parsed) => parsed,
+                None => {
+                    if skip_until.is_none() {
+                        try!(f.write_str("&lt;"));
+                    }
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            rest = &rest[len..];
+            if let Some(ref skipped) = skip_until {
+                if !(tag.end && tag.name == *skipped) {
+                    continue;
+                }
+            }
+            if skip_until.take().is_some() {
+                continue;
+            }
+            if !tag.end && (tag.name == "script" || tag.name == "style") {
+                skip_until = Some(tag.name);
+                continue;
+            }
+            if !preset.allows_element(&tag.name) {
+                continue;
+            }
+            if tag.name == "iframe" {
+                if tag.end && dropped_iframes > 0 {
+                    dropped_iframes -= 1;
+                    continue;
+                }
+                let src = tag.attribute("src");
+                if !tag.end && !src.is_some_and(is_embeddable) {
+                    dropped_iframes += 1;
+                    continue;
+                }
+            }
+            try!(write_tag(f, preset, &tag));
+        }
+        if skip_until.is_none() {
+            try!(f.write_str(rest));
+        }
+        Ok(())
+    }
+}
+
+/// A start or end tag.  Names are lowercased, and attribute values are
+/// decoded.
+pub struct Tag {
+    pub name: String,
+    pub end: bool,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    /// The value of the attribute, if the tag has it.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| &a.1[..])
+    }
+}
+
+/// Parse the tag at the start of `s`, and return it with the length it
+/// takes.  It's `None` if `s` doesn't start with a tag, e.g. `"< 3"`.
+pub fn parse_tag(s: &str) -> Option<(Tag, usize)> {
+    let end = s[1..].starts_with('/');
+    let mut i = if end { 2 } else { 1 };
+    let name_len = s[i..].find(|c: char| !(c.is_alphanumeric() || c == '-'))
+                         .unwrap_or(s.len() - i);
+    if name_len == 0 {
+        return None;
+    }
+    let name = s[i..i + name_len].to_lowercase();
+    i += name_len;
+    let mut attributes = vec![];
+    loop {
+        i += s[i..].find(|c: char| !(c.is_whitespace() || c == '/'))
+                   .unwrap_or(s.len() - i);
+        if i >= s.len() || s[i..].starts_with('>') {
+            break;
+        }
+        let attr_len = s[i..].find(|c: char| {
+            c.is_whitespace() || c == '=' || c == '>' || c == '/'
+        }).unwrap_or(s.len() - i).max(1);
+        let attr = s[i..i + attr_len].to_lowercase();
+        i += attr_len;
+        let after = s[i..].trim_start();
+        let mut value = String::new();
+        if after.starts_with('=') {
+            i = s.len() - after.len() + 1;
+            i += s[i..].find(|c: char| !c.is_whitespace())
+                       .unwrap_or(s.len() - i);
+            let quote = s[i..].chars().next()
+                              .filter(|&c| c == '"' || c == '\'');
+            let raw = match quote {
+                Some(q) => {
+                    let len = s[i + 1..].find(q).unwrap_or(s.len() - i - 1);
+                    let raw = &s[i + 1..i + 1 + len];
+                    i = (i + len + 2).min(s.len());
+                    raw
+                }
+                None => {
+                    let len = s[i..].find(|c: char| {
+                        c.is_whitespace() || c == '>'
+                    }).unwrap_or(s.len() - i);
+                    let raw = &s[i..i + len];
+                    i += len;
+                    raw
+                }
+            };
+            value = decode_references(raw);
+        }
+        attributes.push((attr, value));
+    }
+    let len = (i + 1).min(s.len());
+    Some((Tag { name: name, end: end, attributes: attributes }, len))
+}
+
+/// Decode character references in the text.
+pub fn decode_references(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        let (reference, len) = decode_reference(&rest[i..]);
+        decoded.push_str(&reference);
+        rest = &rest[i + len..];
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Whether the attribute is kept on the element.  Event handlers and
+/// styles are never kept.
+fn allows_attribute(element: &str, attribute: &str) -> bool {
+    matches!((element, attribute),
+             (_, "title") | (_, "lang") | (_, "dir") |
+             ("a", "href") |
+             ("blockquote", "cite") | ("q", "cite") | ("del", "cite") |
+             ("ins", "cite") |
+             ("time", "datetime") | ("del", "datetime") |
+             ("ins", "datetime") |
+             ("ol", "start") | ("ol", "reversed") |
+             ("col", "span") | ("colgroup", "span") |
+             ("td", "colspan") | ("td", "rowspan") | ("th", "colspan") |
+             ("th", "rowspan") | ("th", "scope") |
+             ("img", "src") | ("img", "alt") | ("img", "width") |
+             ("img", "height") |
+             ("iframe", "src") | ("iframe", "width") | ("iframe", "height") |
+             ("iframe", "allowfullscreen") |
+             ("math", "display") | ("annotation", "encoding") |
+             ("mi", "mathvariant"))
+}
+
+/// Classes of the `class` attribute kept on the element by presets other
+/// than `Strict`, separated by spaces.
+fn kept_classes(element: &str, class: &str) -> String {
+    let classes: Vec<&str> = class.split_whitespace().filter(|c| {
+        match element {
+            "pre" | "code" => code_language(c).is_some(),
+            "span" | "div" => MATH_CLASSES.contains(c),
+            _ => false,
+        }
+    }).collect();
+    classes.join(" ")
+}
+
+fn allows_url(url: &str) -> bool {
+    let url = url.trim();
+    let scheme_end = url.find([':', '/', '?', '#']);
+    match scheme_end {
+        Some(i) if url[i..].starts_with(':') => {
+            URL_SCHEMES.contains(&&url[..i].to_lowercase()[..])
+        }
+        _ => true,
+    }
+}
+
+fn is_embeddable(src: &str) -> bool {
+    let src = src.trim();
+    let absolute = if src.starts_with("//") {
+        format!("https:{}", src)
+    } else {
+        src.to_owned()
+    };
+    match Url::parse(&absolute) {
+        Ok(url) => {
+            (url.scheme() == "https" || url.scheme() == "http") &&
+                url.host_str().is_some_and(|h| EMBED_HOSTS.contains(&h))
+        }
+        Err(_) => false,
+    }
+}
+
+fn write_tag(f: &mut fmt::Formatter, preset: SanitizerPreset, tag: &Tag)
+             -> fmt::Result {
+    if tag.end {
+        return write!(f, "</{}>", tag.name);
+    }
+    try!(write!(f, "<{}", tag.name));
+    for (name, value) in tag.attributes.iter() {
+        if name == "class" {
+            let classes = kept_classes(&tag.name, value);
+            if preset != SanitizerPreset::Strict && !classes.is_empty() {
+                try!(write!(f, " class=\"{}\"", escape(&classes, true)));
+            }
+            continue;
+        }
+        if !allows_attribute(&tag.name, name) ||
+           URL_ATTRIBUTES.contains(&&name[..]) && !allows_url(value) {
+            continue;
+        }
+        try!(write!(f, " {}=\"{}\"", name, escape(value, true)));
+    }
+    write!(f, ">")
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::SanitizerPreset;
+
+    #[test]
+    fn test_strict() {
+        let strict = SanitizerPreset::Strict;
+        assert_eq!(strict.filter("<code class=language-rust>fn</code>\
+                                  <math><mi>x</mi></math>").to_string(),
+                   "<code>fn</code>x");
+        assert_eq!(strict.filter("<p class=x>Fish &amp; <EM>chips</em></p>")
+                         .to_string(),
+                   "<p>Fish &amp; <em>chips</em></p>");
+        assert_eq!(strict.filter("a<script>x('<p>')</script>b<!-- c -->d")
+                         .to_string(),
+                   "abd");
+        assert_eq!(strict.filter("<img src=a.png alt=A>1 < 2").to_string(),
+                   "1 &lt; 2");
+        assert_eq!(strict.filter("<a href=\"javascript:x()\" \
+                                  onclick=x()>a</a>").to_string(),
+                   "<a>a</a>");
+        assert_eq!(strict.filter("<a href='/a?b=1&amp;c=\"2\"' title=T>a</a>")
+                         .to_string(),
+                   "<a href=\"/a?b=1&amp;c=&quot;2&quot;\" title=\"T\">a</a>");
+    }
+
+    #[test]
+    fn test_article() {
+        let article = SanitizerPreset::Article;
+        assert_eq!(article.filter("<figure><img src=a.png alt=\"A > B\" \
+                                   style=x><figcaption>A</figcaption>\
+                                   </figure>").to_string(),
+                   "<figure><img src=\"a.png\" alt=\"A &gt; B\">\
+                    <figcaption>A</figcaption></figure>");
+        assert_eq!(article.filter("<pre><code>a &lt; b</code></pre>")
+                          .to_string(),
+                   "<pre><code>a &lt; b</code></pre>");
+        assert_eq!(article.filter("<pre class=\"x lang-rs\"><code \
+                                   class=\"language-rust hl\">fn</code>\
+                                   </pre>").to_string(),
+                   "<pre class=\"lang-rs\"><code class=\"language-rust\">\
+                    fn</code></pre>");
+        assert_eq!(article.filter("<span class=\"math inline\">\\(x^2\\)\
+                                   </span><p class=math>a</p>").to_string(),
+                   "<span class=\"math\">\\(x^2\\)</span><p>a</p>");
+        assert_eq!(article.filter("<math display=block><msup><mi>x</mi>\
+                                   <mn>2</mn></msup></math>").to_string(),
+                   "<math display=\"block\"><msup><mi>x</mi><mn>2</mn>\
+                    </msup></math>");
+        assert_eq!(article.filter("<img src=\"data:image/png;base64,AA\">")
+                          .to_string(),
+                   "<img>");
+        assert_eq!(article.filter("<td colspan=2>1</td>").to_string(),
+                   "<td colspan=\"2\">1</td>");
+        assert_eq!(article.filter("<iframe \
+                                   src=\"https://www.youtube.com/embed/a\">\
+                                   </iframe>").to_string(),
+                   "");
+    }
+
+    #[test]
+    fn test_embed() {
+        let embed = SanitizerPreset::Embed;
+        assert_eq!(embed.filter("<iframe src=\"//www.youtube.com/embed/a\" \
+                                 width=640></iframe>").to_string(),
+                   "<iframe src=\"//www.youtube.com/embed/a\" width=\"640\">\
+                    </iframe>");
+        assert_eq!(embed.filter("<iframe src=\"https://evil.com/\">a</iframe>\
+                                 <table></table>").to_string(),
+                   "a<table></table>");
+        assert_eq!(embed.filter("<iframe>a</iframe>").to_string(), "a");
+    }
+
+    #[test]
+    fn test_preset_names() {
+        for &preset in &[SanitizerPreset::Strict, SanitizerPreset::Article,
+                         SanitizerPreset::Embed] {
+            assert_eq!(preset.name().parse(), Ok(preset));
+        }
+        assert_eq!("Article".parse(), Ok(SanitizerPreset::Article));
+        assert_eq!("all".parse::<SanitizerPreset>(), Err(()));
+        assert_eq!(SanitizerPreset::default(), SanitizerPreset::Strict);
+    }
+}
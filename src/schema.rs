@@ -1,3 +1,4 @@
+use std::any::Any;
 use std::borrow::{Cow, ToOwned};
 use std::collections::hash_map::{Entry, HashMap};
 use std::default::Default;
@@ -5,11 +6,16 @@ use std::error::Error;
 use std::fmt;
 use std::hash::Hash;
 use std::io;
+use std::marker::PhantomData;
 use std::mem;
 
 use chrono::{DateTime, TimeZone};
+use xml;
+use xml::writer::events::StartElementBuilder;
+use xml::writer::XmlEvent;
 
-use parser::base::{DecodeResult, XmlElement, XmlName};
+use parser::base::{DecodeError, DecodeResult, NestedEventReader, XmlElement,
+                   XmlName};
 use parser::base::NestedEvent::Nested;
 
 pub type SchemaResult<T> = Result<T, SchemaError>;
@@ -28,6 +34,12 @@ impl fmt::Display for SchemaError {
     }
 }
 
+impl From<xml::writer::Error> for SchemaError {
+    fn from(_e: xml::writer::Error) -> SchemaError {
+        SchemaError::EncodeError
+    }
+}
+
 impl Error for SchemaError {
     fn description(&self) -> &str {
         match *self {
@@ -139,3 +151,295 @@ pub trait FromSchemaReader: Default + Sized {
                                    _child: XmlElement<B>) -> DecodeResult<()>
     { Ok(()) }
 }
+
+pub type XmlWriter<W> = xml::EventWriter<W>;
+
+/// The counterpart of `FromSchemaReader`, which writes the value as an XML
+/// element.
+pub trait ToSchemaWriter {
+    /// Write the whole element.  `element` is the start tag the caller wants
+    /// to open, so implementors can append their own attributes to it before
+    /// writing.
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element));
+        try!(self.write_children(writer));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+
+    fn write_children<W: io::Write>(&self, _writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    { Ok(()) }
+}
+
+/// Read the document element from the given XML stream.
+pub fn read<T, B>(xml: B) -> DecodeResult<T>
+    where T: DocumentElement + FromSchemaReader, B: io::BufRead
+{
+    let mut parser = xml::EventReader::new(xml);
+    let mut events = NestedEventReader::new(&mut parser);
+    while let Some(event) = events.next() {
+        if let Nested { name, element } = try!(event) {
+            if name.local_name != T::tag() ||
+                name.namespace_ref() != T::xmlns() {
+                return Err(DecodeError::NoResult);
+            }
+            return T::build_from(element);
+        }
+    }
+    Err(DecodeError::NoResult)
+}
+
+/// Write the given document element into the `w` stream as a complete XML
+/// document.
+pub fn write<T, W>(document: &T, w: W) -> SchemaResult<()>
+    where T: DocumentElement + ToSchemaWriter, W: io::Write
+{
+    let mut writer = xml::EventWriter::new(w);
+    try!(writer.write(XmlEvent::StartDocument {
+        version: xml::common::XmlVersion::Version10,
+        encoding: Some("utf-8"),
+        standalone: None,
+    }));
+    let element = XmlEvent::start_element(T::tag());
+    let element = match T::xmlns() {
+        Some(xmlns) => element.default_ns(xmlns),
+        None => element,
+    };
+    document.write_to(&mut writer, element)
+}
+
+/// Identifies a field of the schema, i.e. an element or an attribute by its
+/// namespace and local name, like descriptors of libearth's schema do.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FieldDescriptor {
+    pub xmlns: Option<Cow<'static, str>>,
+    pub name: Cow<'static, str>,
+    pub attribute: bool,
+}
+
+impl FieldDescriptor {
+    pub fn element<N, T>(xmlns: Option<N>, name: T) -> FieldDescriptor
+        where N: Into<Cow<'static, str>>, T: Into<Cow<'static, str>>
+    {
+        FieldDescriptor {
+            xmlns: xmlns.map(Into::into),
+            name: name.into(),
+            attribute: false,
+        }
+    }
+
+    pub fn attribute<N, T>(xmlns: Option<N>, name: T) -> FieldDescriptor
+        where N: Into<Cow<'static, str>>, T: Into<Cow<'static, str>>
+    {
+        FieldDescriptor {
+            attribute: true,
+            ..FieldDescriptor::element(xmlns, name)
+        }
+    }
+}
+
+impl fmt::Display for FieldDescriptor {
+    /// Format in Clark notation e.g. `{http://www.w3.org/2005/Atom}updated`,
+    /// prefixed with `@` for attributes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.attribute {
+            try!(write!(f, "@"));
+        }
+        if let Some(ref xmlns) = self.xmlns {
+            try!(write!(f, "{{{}}}", xmlns));
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Type-erased `Codec`, so that codecs of different types can be kept in a
+/// `CodecRegistry`.
+trait AnyCodec: Send + Sync {
+    fn encode_any(&self, value: &Any, w: &mut io::Write) -> SchemaResult<()>;
+    fn decode_any(&self, r: &str) -> SchemaResult<Box<Any>>;
+}
+
+struct Typed<C, T> {
+    codec: C,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<C, T> AnyCodec for Typed<C, T>
+    where C: Codec<T> + Send + Sync, T: Any
+{
+    fn encode_any(&self, value: &Any, w: &mut io::Write) -> SchemaResult<()> {
+        match value.downcast_ref::<T>() {
+            Some(value) => self.codec.encode(value, w),
+            None => Err(SchemaError::EncodeError),
+        }
+    }
+
+    fn decode_any(&self, r: &str) -> SchemaResult<Box<Any>> {
+        self.codec.decode(r).map(|v| Box::new(v) as Box<Any>)
+    }
+}
+
+/// Maps schema fields to codecs which encode and decode their typed values,
+/// so that fields can be read and written by looking up the table rather
+/// than calling codecs ad hoc.  Apps can register codecs for their own
+/// extension elements at runtime:
+///
+/// ```
+/// # use earth::codecs::Boolean;
+/// # use earth::schema::{CodecRegistry, FieldDescriptor};
+/// let mut registry = CodecRegistry::default();
+/// let pinned = FieldDescriptor::element(Some("http://example.com/app"),
+///                                       "pinned");
+/// registry.register(pinned.clone(), Boolean::new(&["yes"], &["no"], false));
+/// assert_eq!(registry.decode::<bool>(&pinned, "yes").unwrap(), true);
+/// ```
+pub struct CodecRegistry {
+    codecs: HashMap<FieldDescriptor, Box<AnyCodec>>,
+}
+
+impl CodecRegistry {
+    /// An empty registry.  See also `default()`, which has codecs of
+    /// standard fields registered.
+    pub fn new() -> CodecRegistry {
+        CodecRegistry { codecs: HashMap::new() }
+    }
+
+    /// Register the `codec` of `T` values for the `field`.  It replaces
+    /// the codec registered for the field before, and returns whether
+    /// there was one.
+    pub fn register<T, C>(&mut self, field: FieldDescriptor, codec: C) -> bool
+        where T: Any, C: Codec<T> + Send + Sync + 'static
+    {
+        let typed = Typed { codec: codec, _type: PhantomData };
+        self.codecs.insert(field, Box::new(typed)).is_some()
+    }
+
+    /// Unregister the codec for the `field`, and return whether there was
+    /// one.
+    pub fn unregister(&mut self, field: &FieldDescriptor) -> bool {
+        self.codecs.remove(field).is_some()
+    }
+
+    pub fn contains(&self, field: &FieldDescriptor) -> bool {
+        self.codecs.contains_key(field)
+    }
+
+    /// Decode the text of the `field`.  It fails if no codec is registered
+    /// for the field, or the codec doesn't decode `T` values.
+    pub fn decode<T: Any>(&self, field: &FieldDescriptor, r: &str)
+                          -> SchemaResult<T> {
+        match try!(self.decode_any(field, r)).downcast::<T>() {
+            Ok(value) => Ok(*value),
+            Err(_) => Err(SchemaError::DecodeError(
+                "the codec registered for the field decodes another type",
+                Some(field.to_string()))),
+        }
+    }
+
+    /// Decode the text of the `field` into the value of whatever type its
+    /// codec decodes, e.g. for extension elements unknown at compile time.
+    pub fn decode_any(&self, field: &FieldDescriptor, r: &str)
+                      -> SchemaResult<Box<Any>> {
+        match self.codecs.get(field) {
+            Some(codec) => codec.decode_any(r),
+            None => Err(SchemaError::DecodeError(
+                "no codec is registered for the field",
+                Some(field.to_string()))),
+        }
+    }
+
+    /// Encode the `value` of the `field`.  It fails if no codec is
+    /// registered for the field, or the codec doesn't encode `T` values.
+    pub fn encode<T: Any>(&self, field: &FieldDescriptor, value: &T,
+                          w: &mut io::Write) -> SchemaResult<()> {
+        match self.codecs.get(field) {
+            Some(codec) => codec.encode_any(value, w),
+            None => Err(SchemaError::EncodeError),
+        }
+    }
+}
+
+impl Default for CodecRegistry {
+    /// A registry with codecs of date times of Atom, RSS 2.0, and marks.
+    fn default() -> CodecRegistry {
+        use codecs::{RFC3339, RFC822};
+        use feed::ATOM_XMLNS;
+
+        let mut registry = CodecRegistry::new();
+        for name in &["updated", "published"] {
+            registry.register(FieldDescriptor::element(Some(ATOM_XMLNS), *name),
+                              RFC3339);
+        }
+        // e.g. <read updated="..."> of marks
+        registry.register(FieldDescriptor::attribute(None::<&str>, "updated"),
+                          RFC3339);
+        for name in &["pubDate", "lastBuildDate"] {
+            registry.register(FieldDescriptor::element(None::<&str>, *name),
+                              RFC822);
+        }
+        registry
+    }
+}
+
+impl fmt::Debug for CodecRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut fields: Vec<String> =
+            self.codecs.keys().map(|k| k.to_string()).collect();
+        fields.sort();
+        write!(f, "CodecRegistry {{ fields: {:?} }}", fields)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{CodecRegistry, FieldDescriptor};
+
+    use std::str::from_utf8;
+
+    use chrono::{DateTime, FixedOffset, TimeZone};
+
+    use codecs::{Boolean, RFC3339};
+
+    #[test]
+    fn test_codec_registry() {
+        let mut registry = CodecRegistry::new();
+        let field = FieldDescriptor::element(Some("urn:x"), "at");
+        assert!(registry.decode::<bool>(&field, "").is_err());
+        assert!(!registry.register(field.clone(), RFC3339));
+        let dt: DateTime<FixedOffset> =
+            unwrap!(registry.decode(&field, "2013-11-06T14:36:00Z"));
+        assert_eq!(dt, FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0));
+        assert!(registry.decode::<bool>(&field, "2013-11-06T14:36:00Z")
+                        .is_err());
+        let mut buf = vec![];
+        unwrap!(registry.encode(&field, &dt, &mut buf));
+        assert_eq!(from_utf8(&buf).unwrap(), "2013-11-06T14:36:00Z");
+        assert!(registry.encode(&field, &true, &mut vec![]).is_err());
+
+        let any = unwrap!(registry.decode_any(&field, "2013-11-06T14:36:00Z"));
+        assert!(any.is::<DateTime<FixedOffset>>());
+
+        assert!(registry.register(field.clone(), Boolean::default()));
+        assert!(unwrap!(registry.decode::<bool>(&field, "true")));
+        assert!(registry.unregister(&field));
+        assert!(!registry.contains(&field));
+    }
+
+    #[test]
+    fn test_default_codec_registry() {
+        let registry = CodecRegistry::default();
+        let pub_date = FieldDescriptor::element(None::<&str>, "pubDate");
+        let dt: DateTime<FixedOffset> =
+            unwrap!(registry.decode(&pub_date, "Sat, 07 Sep 2002 00:00:01 GMT"));
+        assert_eq!(dt, FixedOffset::east(0).ymd(2002, 9, 7).and_hms(0, 0, 1));
+        let updated = FieldDescriptor::element(
+            Some("http://www.w3.org/2005/Atom"), "updated");
+        assert!(registry.contains(&updated));
+        assert_eq!(updated.to_string(), "{http://www.w3.org/2005/Atom}updated");
+    }
+}
@@ -0,0 +1,327 @@
+//! Storing secrets e.g. access tokens of authenticated repositories and
+//! feeds, which shouldn't be written in plain text anywhere.
+//!
+//! `SecretStore` is the interface.  `EncryptedSecretStore` stores secrets
+//! encrypted in a repository, and `SecretHooks` delegates to the app, so
+//! that mobile apps can back it with the platform's secure storage e.g.
+//! Keychain on iOS, Keystore on Android:
+//!
+//! ```
+//! # use std::collections::HashMap;
+//! # use std::sync::{Arc, Mutex};
+//! # use earth::secrets::{SecretHooks, SecretStore};
+//! // imagine it's the Keychain
+//! let keychain = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+//! let (k1, k2, k3) = (keychain.clone(), keychain.clone(), keychain.clone());
+//! let mut store = SecretHooks {
+//!     get: Box::new(move |name| Ok(k1.lock().unwrap().get(name).cloned())),
+//!     put: Box::new(move |name, secret| {
+//!         k2.lock().unwrap().insert(name.to_owned(), secret.to_owned());
+//!         Ok(())
+//!     }),
+//!     delete: Box::new(move |name| {
+//!         k3.lock().unwrap().remove(name);
+//!         Ok(())
+//!     }),
+//! };
+//! store.put("dropbox", "token").unwrap();
+//! assert_eq!(store.get("dropbox").unwrap(), Some("token".to_owned()));
+//! ```
+use std::collections::HashMap;
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::str;
+
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::aead::rand_core::RngCore;
+
+use repository as repo;
+use repository::Repository;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Repository(repo::Error),
+
+    /// The stored secret can't be decrypted; it's corrupted, or the key is
+    /// wrong.
+    Corrupted(String),
+
+    /// The platform's secure storage failed, with its message.
+    Platform(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.description()));
+        match *self {
+            Error::Corrupted(ref name) => { try!(write!(f, ": {}", name)); }
+            Error::Platform(ref msg) => { try!(write!(f, ": {}", msg)); }
+            Error::Repository(_) => { }
+        }
+        if let Some(cause) = self.cause() {
+            try!(write!(f, " caused by `{}`", cause));
+        }
+        Ok(())
+    }
+}
+
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Repository(_) => "repository error",
+            Error::Corrupted(_) => "failed to decrypt the secret",
+            Error::Platform(_) => "secure storage error",
+        }
+    }
+
+    fn cause(&self) -> Option<&ErrorTrait> {
+        match *self {
+            Error::Repository(ref err) => Some(err as &ErrorTrait),
+            _ => None,
+        }
+    }
+}
+
+impl From<repo::Error> for Error {
+    fn from(err: repo::Error) -> Error { Error::Repository(err) }
+}
+
+/// Storage of secrets by their names e.g. `"dropbox"`.
+pub trait SecretStore {
+    /// The secret of the name, or `None` if there's no such secret.
+    fn get(&self, name: &str) -> Result<Option<String>>;
+
+    /// Store the secret.  The existing secret of the same name is replaced.
+    fn put(&mut self, name: &str, secret: &str) -> Result<()>;
+
+    /// Remove the secret.  It does nothing if there's no such secret.
+    fn delete(&mut self, name: &str) -> Result<()>;
+}
+
+/// Secrets only in memory, which are lost when it's dropped.
+impl SecretStore for HashMap<String, String> {
+    fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(HashMap::get(self, name).cloned())
+    }
+
+    fn put(&mut self, name: &str, secret: &str) -> Result<()> {
+        self.insert(name.to_owned(), secret.to_owned());
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        self.remove(name);
+        Ok(())
+    }
+}
+
+/// The function `SecretHooks` gets secrets of names by.
+pub type GetHook = Box<Fn(&str) -> Result<Option<String>> + Send>;
+
+/// The function `SecretHooks` puts secrets of names by.
+pub type PutHook = Box<Fn(&str, &str) -> Result<()> + Send>;
+
+/// The function `SecretHooks` deletes secrets of names by.
+pub type DeleteHook = Box<Fn(&str) -> Result<()> + Send>;
+
+/// The secret store which delegates to the functions the app provides.
+/// See the module documentation.
+pub struct SecretHooks {
+    pub get: GetHook,
+    pub put: PutHook,
+    pub delete: DeleteHook,
+}
+
+impl SecretStore for SecretHooks {
+    fn get(&self, name: &str) -> Result<Option<String>> { (self.get)(name) }
+
+    fn put(&mut self, name: &str, secret: &str) -> Result<()> {
+        (self.put)(name, secret)
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> { (self.delete)(name) }
+}
+
+/// The version byte of the encrypted record format.  Records of version 1,
+/// encrypted by the former HMAC-SHA1 construction, aren't read anymore.
+const RECORD_VERSION: u8 = 2;
+
+/// The size of nonces of XChaCha20-Poly1305.
+const NONCE_SIZE: usize = 24;
+
+/// The size of authentication tags of Poly1305.
+const TAG_SIZE: usize = 16;
+
+/// The size in bytes of master keys of `EncryptedSecretStore`.
+pub const MASTER_KEY_LEN: usize = 32;
+
+/// Stores secrets encrypted with the master key, as documents in the
+/// directory of the repository, so that they can be synced along with the
+/// other documents.  The master key has to be `MASTER_KEY_LEN` random
+/// bytes kept somewhere other than the repository, e.g. generated once and
+/// kept in the platform's secure storage.
+///
+/// Each secret is encrypted by XChaCha20-Poly1305, an AEAD of the
+/// `chacha20poly1305` crate, with a nonce of 24 random bytes from the
+/// operating system, and its name as the associated data, so that secrets
+/// swapped between names or tampered are rejected.  Nonces are long
+/// enough that random ones don't collide even if many devices sharing the
+/// repository write secrets independently.
+///
+/// ### Threat model
+///
+/// It protects secrets from whoever can read the synced repository, e.g.
+/// the storage provider, or a leaked backup, as long as the master key
+/// isn't kept along with it.  It doesn't protect them on a device which
+/// is compromised, since the master key is there.  Names of secrets and
+/// their lengths aren't hidden, and an attacker who can write to the
+/// repository can delete secrets or restore their older versions.
+///
+/// The master key must not be derived from a passphrase: keys aren't
+/// stretched by a password KDF, so secrets synced along with the
+/// repository could be brute-forced offline.  Apps which need passphrases
+/// should derive the key by a vetted KDF with a salt of their own, e.g.
+/// Argon2, or use `SecretHooks` instead.
+pub struct EncryptedSecretStore<R: Repository> {
+    repository: R,
+    key: Vec<String>,
+    cipher: XChaCha20Poly1305,
+}
+
+impl<R: Repository> EncryptedSecretStore<R> {
+    /// Store secrets under the `key` directory of the `repository`,
+    /// encrypted with the random `master_key`.
+    pub fn new<T: AsRef<str>>(repository: R, key: &[T],
+                              master_key: &[u8; MASTER_KEY_LEN])
+                              -> EncryptedSecretStore<R> {
+        EncryptedSecretStore {
+            repository: repository,
+            key: key.iter().map(|k| k.as_ref().to_owned()).collect(),
+            cipher: XChaCha20Poly1305::new(Key::from_slice(master_key)),
+        }
+    }
+
+    pub fn into_inner(self) -> R { self.repository }
+
+    fn secret_key(&self, name: &str) -> Vec<String> {
+        let mut key = self.key.clone();
+        key.push(name.to_owned());
+        key
+    }
+}
+
+impl<R: Repository> SecretStore for EncryptedSecretStore<R> {
+    fn get(&self, name: &str) -> Result<Option<String>> {
+        let key = self.secret_key(name);
+        if !self.repository.exists(&key) {
+            return Ok(None);
+        }
+        let record = try!(self.repository.read(&key));
+        let corrupted = || Error::Corrupted(name.to_owned());
+        if record.len() < 1 + NONCE_SIZE + TAG_SIZE ||
+           record[0] != RECORD_VERSION {
+            return Err(corrupted());
+        }
+        let (nonce, ciphertext) = record[1..].split_at(NONCE_SIZE);
+        let payload = Payload { msg: ciphertext, aad: name.as_bytes() };
+        let plaintext = match self.cipher.decrypt(XNonce::from_slice(nonce),
+                                                  payload) {
+            Ok(plaintext) => plaintext,
+            Err(_) => { return Err(corrupted()); }
+        };
+        match String::from_utf8(plaintext) {
+            Ok(secret) => Ok(Some(secret)),
+            Err(_) => Err(corrupted()),
+        }
+    }
+
+    fn put(&mut self, name: &str, secret: &str) -> Result<()> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        if let Err(e) = OsRng.try_fill_bytes(&mut nonce) {
+            return Err(Error::Platform(e.to_string()));
+        }
+        let payload = Payload { msg: secret.as_bytes(),
+                                aad: name.as_bytes() };
+        let ciphertext = match self.cipher.encrypt(XNonce::from_slice(&nonce),
+                                                   payload) {
+            Ok(ciphertext) => ciphertext,
+            Err(_) => {
+                return Err(Error::Platform("failed to encrypt".to_owned()));
+            }
+        };
+        let mut record = vec![RECORD_VERSION];
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(&ciphertext);
+        let key = self.secret_key(name);
+        try!(self.repository.write(&key, Some(record)));
+        Ok(())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        let key = self.secret_key(name);
+        if self.repository.exists(&key) {
+            try!(self.repository.delete(&key));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{EncryptedSecretStore, Error, MASTER_KEY_LEN, SecretStore};
+
+    use repository::{FileSystemRepository, Repository};
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_encrypted_secret_store() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let master_key = [0x5a; MASTER_KEY_LEN];
+        let mut store = EncryptedSecretStore::new(repo, &["secrets"],
+                                                  &master_key);
+        assert_eq!(unwrap!(store.get("dropbox")), None);
+        unwrap!(store.put("dropbox", "s3cr3t-t0k3n"));
+        unwrap!(store.put("webdav", ""));
+        assert_eq!(unwrap!(store.get("dropbox")),
+                   Some("s3cr3t-t0k3n".to_owned()));
+        assert_eq!(unwrap!(store.get("webdav")), Some("".to_owned()));
+        unwrap!(store.delete("webdav"));
+        unwrap!(store.delete("webdav"));
+        assert_eq!(unwrap!(store.get("webdav")), None);
+
+        let mut repo = store.into_inner();
+        let record = unwrap!(repo.read(&["secrets", "dropbox"]));
+        assert!(!String::from_utf8_lossy(&record).contains("s3cr3t"));
+        let store = EncryptedSecretStore::new(repo, &["secrets"],
+                                              &[0xa5; MASTER_KEY_LEN]);
+        assert_err!(store.get("dropbox"), Error::Corrupted(_) => { });
+
+        repo = store.into_inner();
+        let mut tampered = record.clone();
+        tampered[25] ^= 1;
+        unwrap!(repo.write(&["secrets", "swapped"], Some(record)));
+        unwrap!(repo.write(&["secrets", "dropbox"], Some(tampered)));
+        let store = EncryptedSecretStore::new(repo, &["secrets"],
+                                              &master_key);
+        assert_err!(store.get("dropbox"), Error::Corrupted(_) => { });
+        assert_err!(store.get("swapped"), Error::Corrupted(_) => { });
+    }
+
+    #[test]
+    fn test_encrypted_secret_store_nonce() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut store = EncryptedSecretStore::new(repo, &["secrets"],
+                                                  &[1; MASTER_KEY_LEN]);
+        unwrap!(store.put("a", "same secret"));
+        unwrap!(store.put("b", "same secret"));
+        let repo = store.into_inner();
+        assert!(unwrap!(repo.read(&["secrets", "a"])) !=
+                unwrap!(repo.read(&["secrets", "b"])));
+    }
+}
@@ -0,0 +1,254 @@
+//! Several archives, e.g. for work and personal use, open in one process.
+//!
+//! Each archive is a `Stage` of its own repository, named by the app.  The
+//! crawler isn't tied to stages, so a single crawler serves all archives:
+//! feeds subscribed in more than one archive are crawled once, and stored
+//! to each of them by `Archives::store_crawled()`.
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry as MapEntry;
+
+use url::Url;
+
+use feed::Feed;
+use repository as repo;
+use repository::{Repository, ToRepository};
+use subscribe::{Subscription, SubscriptionSet};
+
+use super::{DocumentKey, Result, Stage};
+
+/// Stages of named archives.
+pub struct Archives<R: Repository> {
+    stages: BTreeMap<String, Stage<R>>,
+}
+
+impl<R: Repository> Default for Archives<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Repository> Archives<R> {
+    pub fn new() -> Archives<R> {
+        Archives { stages: BTreeMap::new() }
+    }
+
+    /// Open archives configured by repository URLs, as pairs of a name and
+    /// a URL, e.g. `("work", "file:///home/me/work")`.  See also
+    /// `repository::ToRepository`.
+    pub fn from_urls<I, T>(archives: I) -> repo::Result<Archives<R>>
+        where I: IntoIterator<Item=(T, Url)>, T: Into<String>,
+              Url: ToRepository<R>
+    {
+        let mut result = Archives::new();
+        for (name, url) in archives {
+            result.open(name, try!(url.to_repo()));
+        }
+        Ok(result)
+    }
+
+    /// Open the archive of the repository as `name`.  The archive of the
+    /// same name already open is replaced, and its stage is returned.
+    pub fn open<T: Into<String>>(&mut self, name: T, repository: R)
+                                 -> Option<Stage<R>> {
+        self.stages.insert(name.into(), Stage::new(repository))
+    }
+
+    /// Close the archive, and return its stage.  It isn't flushed; call
+    /// `Stage::flush()` if necessary.
+    pub fn close(&mut self, name: &str) -> Option<Stage<R>> {
+        self.stages.remove(name)
+    }
+
+    /// Names of open archives in lexical order.
+    pub fn names(&self) -> Vec<&str> {
+        self.stages.keys().map(|k| &k[..]).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Stage<R>> {
+        self.stages.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Stage<R>> {
+        self.stages.get_mut(name)
+    }
+
+    /// Flush all archives.
+    pub fn flush(&mut self) -> repo::Result<()> {
+        for stage in self.stages.values_mut() {
+            try!(stage.flush());
+        }
+        Ok(())
+    }
+
+    /// Subscriptions of all archives, as pairs of an archive name and a
+    /// subscription, for the crawler to schedule.  A feed subscribed in
+    /// several archives appears for each of them.
+    pub fn subscriptions(&self) -> Result<Vec<(String, Subscription)>> {
+        let mut result = vec![];
+        for (name, stage) in self.stages.iter() {
+            let list = try!(stage.read_subscriptions());
+            for subscription in list.recursive_subscriptions() {
+                result.push((name.clone(), subscription.clone()));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Store the crawled feed to every archive subscribing to `feed_id`,
+    /// merged with the stored one.  It returns names of the archives.
+    pub fn store_crawled(&mut self, feed_id: &str, feed: &Feed)
+                         -> Result<Vec<String>> {
+        let mut stored = vec![];
+        for (name, stage) in self.stages.iter_mut() {
+            if try!(stage.read_subscriptions()).find(feed_id).is_none() {
+                continue;
+            }
+            let feed = if stage.repository.exists(&stage.feed_key(feed_id)) {
+                let stored = try!(stage.read_feed(feed_id));
+                try!(stage.merge_feed(feed_id, stored, feed.clone()))
+            } else {
+                feed.clone()
+            };
+            try!(stage.write_feed(feed_id, &feed));
+            stored.push(name.clone());
+        }
+        Ok(stored)
+    }
+
+    /// Move the subscription of `feed_id` from the archive `from` to `to`
+    /// along with its feed, i.e. entries and their marks, and its settings.
+    /// It's subscribed in the category of the same path.  If `to` already
+    /// has the feed, they're merged.  It returns `false` if either archive
+    /// isn't open, or `from` doesn't subscribe to the feed.
+    pub fn move_subscription(&mut self, feed_id: &str, from: &str, to: &str)
+                             -> Result<bool>
+    {
+        if from == to || !self.stages.contains_key(to) {
+            return Ok(false);
+        }
+        let mut source = match self.stages.entry(from.to_owned()) {
+            MapEntry::Occupied(e) => e.remove(),
+            MapEntry::Vacant(_) => { return Ok(false); }
+        };
+        let result = move_between(&mut source,
+                                  self.stages.get_mut(to).unwrap(), feed_id);
+        self.stages.insert(from.to_owned(), source);
+        result
+    }
+}
+
+fn move_between<R: Repository>(source: &mut Stage<R>, target: &mut Stage<R>,
+                               feed_id: &str) -> Result<bool> {
+    let mut list = try!(source.read_subscriptions());
+    let (path, subscription) = match list.unsubscribe(feed_id) {
+        Some(v) => v,
+        None => { return Ok(false); }
+    };
+    try!(target.subscribe(&path, subscription));
+    if source.repository.exists(&source.feed_key(feed_id)) {
+        let feed = try!(source.read_feed(feed_id));
+        let feed = if target.repository.exists(&target.feed_key(feed_id)) {
+            let stored = try!(target.read_feed(feed_id));
+            try!(target.merge_feed(feed_id, stored, feed))
+        } else {
+            feed
+        };
+        try!(target.write_feed(feed_id, &feed));
+        for i in 0.. {
+            let key = source.page_key(feed_id, i);
+            if !source.repository.exists(&key) {
+                break;
+            }
+            try!(source.repository.delete(&key));
+        }
+    }
+    let settings_key =
+        source.document_key(DocumentKey::Settings(feed_id.to_owned()));
+    if source.repository.exists(&settings_key) {
+        if !target.repository.exists(&settings_key) {
+            let settings = try!(source.read_settings(feed_id));
+            try!(target.write_settings(feed_id, &settings));
+        }
+        try!(source.repository.delete(&settings_key));
+    }
+    try!(source.unsubscribe(feed_id));
+    Ok(true)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Archives;
+
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, TimeZone};
+    use url::Url;
+
+    use feed::{Entry, Feed, MarkKind, Text};
+    use repository::FileSystemRepository;
+    use subscribe::{Subscription, SubscriptionSet};
+    use test_utils::temp_dir;
+
+    fn fx_feed(entries: &[&str]) -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        for id in entries {
+            feed.entries.push(Entry::new(id.to_string(), Text::plain(*id),
+                                         updated_at));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_archives() {
+        let work = temp_dir();
+        let personal = temp_dir();
+        let url = |dir: &::tempdir::TempDir| {
+            unwrap!(Url::from_directory_path(dir.path()))
+        };
+        let mut archives: Archives<FileSystemRepository> =
+            unwrap!(Archives::from_urls(vec![("work", url(&work)),
+                                             ("personal", url(&personal))]));
+        assert_eq!(archives.names(), ["personal", "work"]);
+        {
+            let stage = archives.get_mut("work").unwrap();
+            unwrap!(stage.subscribe(&["News"],
+                                    Subscription::new("feed", "", "Feed")));
+        }
+        {
+            let stage = archives.get_mut("personal").unwrap();
+            unwrap!(stage.subscribe(&[] as &[&str],
+                                    Subscription::new("other", "", "Other")));
+        }
+        assert_eq!(unwrap!(archives.subscriptions()).len(), 2);
+        assert_eq!(unwrap!(archives.store_crawled("feed", &fx_feed(&["a"]))),
+                   ["work"]);
+        {
+            let stage = archives.get_mut("work").unwrap();
+            unwrap!(stage.set_mark("feed", "a", MarkKind::Starred, true));
+            let mut settings = unwrap!(stage.read_settings("feed"));
+            settings.set_crawl_interval(Some(Duration::from_secs(60)),
+                                        fx_feed(&[]).updated_at);
+            unwrap!(stage.write_settings("feed", &settings));
+        }
+
+        assert!(!unwrap!(archives.move_subscription("feed", "personal",
+                                                    "work")));
+        assert!(unwrap!(archives.move_subscription("feed", "work",
+                                                   "personal")));
+        let work = archives.get("work").unwrap();
+        assert!(unwrap!(work.read_subscriptions()).find("feed").is_none());
+        assert!(work.read_feed("feed").is_err());
+        let personal = archives.get("personal").unwrap();
+        let list = unwrap!(personal.read_subscriptions());
+        assert_eq!(list.categories[0].label, "News");
+        assert!(list.find("feed").is_some());
+        let feed = unwrap!(personal.read_feed("feed"));
+        assert!(feed.entries[0].starred.marked);
+        assert_eq!(unwrap!(personal.read_settings("feed")).crawl_interval(),
+                   Some(Duration::from_secs(60)));
+    }
+}
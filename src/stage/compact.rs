@@ -0,0 +1,206 @@
+//! Compaction, i.e. removing entries older than the retention of their
+//! feeds, e.g. news kept for a week while essays are kept forever.
+//!
+//! The retention of a feed is resolved in order of:
+//!
+//! 1. the subscription setting, i.e. `SubscriptionSettings::retention()`;
+//! 2. the retention of the innermost category the feed is subscribed in,
+//!    by its label, in `RetentionPolicy::categories`;
+//! 3. `RetentionPolicy::default`.
+//!
+//! Entries are aged by their `updated_at`, and which of expired entries are
+//! removed is limited by `RetentionPolicy::drop` as `Stage::enforce_quota()`
+//! is, so starred entries are never removed.  `Stage::compact()` can also
+//! only list entries it'd remove, without removing them.
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+
+use job::CancellationToken;
+use repository::Repository;
+use subscribe::{Retention, SubscriptionSet};
+
+use super::{Error, QuotaPolicy, Result, Stage};
+
+/// How long entries are kept by `Stage::compact()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RetentionPolicy {
+    /// The retention of feeds which override nothing, including stored
+    /// feeds which aren't subscribed.  It's `Forever` by default.
+    pub default: Retention,
+
+    /// Retentions by category labels, e.g. `"News"`.
+    pub categories: HashMap<String, Retention>,
+
+    /// Which of expired entries are removed.  It's `DropReadEntries` by
+    /// default, i.e. unread entries are kept until they're read.
+    pub drop: QuotaPolicy,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> RetentionPolicy {
+        RetentionPolicy {
+            default: Retention::Forever,
+            categories: HashMap::new(),
+            drop: QuotaPolicy::DropReadEntries,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Retentions of subscribed feeds resolved by categories, by their feed
+    /// IDs.  Feeds subscribed in no category of `categories` aren't
+    /// included.
+    fn by_categories<S>(&self, set: &S, inherited: Option<Retention>,
+                        result: &mut HashMap<String, Retention>)
+        where S: SubscriptionSet
+    {
+        if let Some(retention) = inherited {
+            for subscription in set.subscriptions() {
+                result.insert(subscription.feed_id.clone(), retention);
+            }
+        }
+        for category in set.categories() {
+            let retention = self.categories.get(&category.label).cloned()
+                                .or(inherited);
+            self.by_categories(category, retention, result);
+        }
+    }
+}
+
+impl<R: Repository> Stage<R> {
+    /// Remove entries older than the retention of their feeds at `now`.
+    /// If `dry_run` is `true`, nothing is removed.  It returns removed (or
+    /// to be removed) entries as pairs of a feed ID and an entry ID, in the
+    /// order of feed IDs and then of entries in the feed.
+    ///
+    /// Unless it's a dry run, marks pending by `set_marks()` are folded into
+    /// feed documents first; see `fold_marks()`.
+    ///
+    /// It's checked whether `cancel` is cancelled before each feed, and it
+    /// fails with `Error::Cancelled` if it is.  Feeds compacted until then
+    /// are written as a whole, so compacting again resumes with the rest.
+    pub fn compact(&mut self, policy: &RetentionPolicy,
+                   now: DateTime<FixedOffset>, dry_run: bool,
+                   cancel: &CancellationToken)
+                   -> Result<Vec<(String, String)>> {
+        if !dry_run {
+            try!(self.fold_marks(cancel));
+        }
+        let subscriptions = try!(self.read_subscriptions());
+        let mut by_categories = HashMap::new();
+        policy.by_categories(&subscriptions, None, &mut by_categories);
+        let mut removed = vec![];
+        for feed_id in try!(self.feed_ids()) {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let retention = match try!(self.read_settings(&feed_id))
+                                      .retention() {
+                Some(retention) => retention,
+                None => by_categories.get(&feed_id).cloned()
+                                     .unwrap_or(policy.default),
+            };
+            if retention == Retention::Forever {
+                continue;
+            }
+            let mut feed = try!(self.read_feed(&feed_id));
+            let count = feed.entries.len();
+            feed.entries.retain(|e| {
+                let expired = policy.drop.can_drop(e) &&
+                              retention.expired(e.updated_at, now);
+                if expired {
+                    removed.push((feed_id.clone(), e.id.clone()));
+                }
+                !expired
+            });
+            if !dry_run && feed.entries.len() < count {
+                try!(self.write_feed(&feed_id, &feed));
+            }
+        }
+        Ok(removed)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::{Error, QuotaPolicy, Stage};
+    use super::RetentionPolicy;
+
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Text};
+    use job::CancellationToken;
+    use repository::FileSystemRepository;
+    use subscribe::{Category, Retention, Subscription};
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_compact() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let old = FixedOffset::east(0).ymd(2013, 11, 1).and_hms(0, 0, 0);
+        let now = FixedOffset::east(0).ymd(2013, 11, 30).and_hms(0, 0, 0);
+        for feed_id in &["news", "essays", "override", "loose"] {
+            let mut feed = Feed::new(format!("urn:{}", feed_id),
+                                     Text::plain(*feed_id), now);
+            for (i, &date) in [old, now].iter().enumerate() {
+                let mut entry = Entry::new(format!("urn:{}", i),
+                                           Text::plain("Entry"), date);
+                entry.read.marked = true;
+                feed.entries.push(entry);
+            }
+            feed.entries.push(Entry::new("urn:unread".to_owned(),
+                                         Text::plain("Unread"), old));
+            unwrap!(stage.write_feed(feed_id, &feed));
+        }
+        let subscription = |feed_id: &str| Subscription::new(
+            feed_id, format!("http://example.com/{}", feed_id), feed_id);
+        let mut news = Category::new("News");
+        news.subscriptions.push(subscription("news"));
+        news.subscriptions.push(subscription("override"));
+        let mut essays = Category::new("Essays");
+        essays.subscriptions.push(subscription("essays"));
+        // nested categories inherit retentions
+        news.categories.push(essays);
+        let mut list = unwrap!(stage.read_subscriptions());
+        list.categories.push(news);
+        unwrap!(stage.write_subscriptions(&list));
+        let mut settings = unwrap!(stage.read_settings("override"));
+        settings.set_retention(Some(Retention::Forever), now);
+        unwrap!(stage.write_settings("override", &settings));
+
+        let week = Retention::For(Duration::from_secs(7 * 24 * 60 * 60));
+        let mut policy = RetentionPolicy::default();
+        let cancel = CancellationToken::new();
+        let compacted = unwrap!(stage.compact(&policy, now, false, &cancel));
+        assert!(compacted.is_empty());
+        policy.categories.insert("News".to_owned(), week);
+        assert_eq!(unwrap!(stage.compact(&policy, now, true, &cancel)),
+                   [("essays".to_owned(), "urn:0".to_owned()),
+                    ("news".to_owned(), "urn:0".to_owned())]);
+        assert_eq!(unwrap!(stage.read_feed("news")).entries.len(), 3);
+        policy.categories.insert("Essays".to_owned(), Retention::Forever);
+        policy.drop = QuotaPolicy::DropOldestEntries;
+        policy.default = week;
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        assert_err!(stage.compact(&policy, now, false, &cancelled),
+                    Error::Cancelled => { });
+        assert_eq!(unwrap!(stage.read_feed("news")).entries.len(), 3);
+        assert_eq!(unwrap!(stage.compact(&policy, now, false, &cancel)),
+                   [("loose".to_owned(), "urn:0".to_owned()),
+                    ("loose".to_owned(), "urn:unread".to_owned()),
+                    ("news".to_owned(), "urn:0".to_owned()),
+                    ("news".to_owned(), "urn:unread".to_owned())]);
+        assert_eq!(unwrap!(stage.read_feed("news")).entries.len(), 1);
+        assert_eq!(unwrap!(stage.read_feed("essays")).entries.len(), 3);
+        assert_eq!(unwrap!(stage.read_feed("override")).entries.len(), 3);
+        let compacted = unwrap!(stage.compact(&policy, now, false, &cancel));
+        assert!(compacted.is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+//! Out-of-line contents, i.e. `Content`s which have only `source_uri` and
+//! no body, e.g. `<content src="...">` of Atom.
+//!
+//! If `Stage::content_policy` is set, `Stage::resolve_contents()` fetches
+//! bodies of out-of-line contents of a feed, e.g. right after it's
+//! refreshed, by `Content::fetch()` within the size limit of the policy,
+//! and caches them in a `BlobStore`, referred by their entries.  Cached
+//! contents are read by `Stage::cached_content()`; feed documents keep
+//! contents out-of-line as they're crawled.  A content is fetched again
+//! only if its `source_uri` changes.
+//!
+//! The index of cached contents of a feed is plain text, and each line is
+//! a content of tab-separated fields: the entry ID, the `source_uri`, and
+//! the hash of the blob.
+use std::collections::HashMap;
+
+use blobs::{BlobStore, entry_referrer};
+use crawler::Backend;
+use feed::{Blob, Content, Entry};
+use repository::Repository;
+
+use super::{DocumentKey, Result, Stage};
+use super::journal::{escape, unescape};
+
+/// How out-of-line contents are fetched.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ContentPolicy {
+    /// The maximum size in bytes of a content.  Larger ones aren't fetched.
+    pub max_size: usize,
+
+    /// Whether only textual contents, e.g. HTML, are fetched, rather than
+    /// binary ones too, e.g. videos.
+    pub text_only: bool,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> ContentPolicy {
+        ContentPolicy { max_size: 1024 * 1024, text_only: true }
+    }
+}
+
+impl<R: Repository> Stage<R> {
+    /// Fetch out-of-line contents of entries of the feed of `feed_id` which
+    /// aren't cached yet by `content_policy`, and cache them in the
+    /// `blobs`.  Contents failed to fetch, e.g. too large ones, are skipped,
+    /// and tried again next time.  It returns the number of fetched
+    /// contents, and does nothing if `content_policy` is `None`.
+    pub fn resolve_contents<B, S>(&mut self, backend: &B,
+                                  blobs: &mut BlobStore<S>, feed_id: &str)
+                                  -> Result<usize>
+        where B: Backend + ?Sized, S: Repository
+    {
+        let policy = match self.content_policy {
+            Some(policy) => policy,
+            None => { return Ok(0); }
+        };
+        let feed = try!(self.read_feed(feed_id));
+        let mut index = try!(self.read_content_index(feed_id));
+        let mut fetched = 0;
+        for entry in feed.entries.iter() {
+            let content = match entry.content {
+                Some(ref content) if content.as_bytes().is_empty() => content,
+                _ => { continue; }
+            };
+            let uri = match content.source_uri() {
+                Some(uri) => uri,
+                None => { continue; }
+            };
+            let cached = index.get(&entry.id).is_some_and(|c| c.0 == uri);
+            if cached || policy.text_only && !content.is_text() {
+                continue;
+            }
+            let resolved = match content.fetch(backend,
+                                               Some(policy.max_size)) {
+                Ok(resolved) => resolved,
+                Err(_) => { continue; }
+            };
+            let hash = try!(blobs.put(resolved.as_bytes()));
+            try!(blobs.add_ref(&hash, &entry_referrer(feed_id, &entry.id)));
+            if let Some((_, old)) = index.insert(entry.id.clone(),
+                                                 (uri.to_owned(), hash)) {
+                try!(blobs.remove_ref(&old,
+                                      &entry_referrer(feed_id, &entry.id)));
+            }
+            fetched += 1;
+        }
+        if fetched > 0 {
+            try!(self.write_content_index(feed_id, &index));
+        }
+        Ok(fetched)
+    }
+
+    /// The out-of-line content of the entry of the feed of `feed_id` with
+    /// its body cached by `resolve_contents()`.  It's `None` if the entry
+    /// has no such content, or it isn't cached yet, or it's changed since.
+    pub fn cached_content<S>(&self, blobs: &BlobStore<S>, feed_id: &str,
+                             entry: &Entry) -> Result<Option<Content>>
+        where S: Repository
+    {
+        let content = match entry.content {
+            Some(ref content) => content,
+            None => { return Ok(None); }
+        };
+        let index = try!(self.read_content_index(feed_id));
+        let hash = match (index.get(&entry.id), content.source_uri()) {
+            (Some((uri, hash)), Some(source)) if uri == source => {
+                hash
+            }
+            _ => { return Ok(None); }
+        };
+        if !blobs.contains(hash) {
+            return Ok(None);
+        }
+        let body = try!(blobs.read(hash));
+        Ok(Content::new(content.mimetype(), body, content.source_uri()).ok())
+    }
+
+    fn read_content_index(&self, feed_id: &str)
+                          -> Result<HashMap<String, (String, String)>> {
+        let key = self.content_index_key(feed_id);
+        if !self.repository.exists(&key) {
+            return Ok(HashMap::new());
+        }
+        let buf = try!(self.repository.read(&key));
+        let text = String::from_utf8_lossy(&buf);
+        Ok(text.lines().filter_map(|line| {
+            let fields: Vec<String> = line.split('\t').map(unescape).collect();
+            if fields.len() != 3 {
+                return None;
+            }
+            let mut fields = fields.into_iter();
+            let entry_id = fields.next().unwrap();
+            let uri = fields.next().unwrap();
+            Some((entry_id, (uri, fields.next().unwrap())))
+        }).collect())
+    }
+
+    fn write_content_index(&mut self, feed_id: &str,
+                           index: &HashMap<String, (String, String)>)
+                           -> Result<()> {
+        let mut lines: Vec<String> = index.iter().map(|(id, value)| {
+            [escape(id), escape(&value.0), escape(&value.1)].join("\t")
+        }).collect();
+        lines.sort();
+        let mut buf = String::new();
+        for line in lines {
+            buf.push_str(&line);
+            buf.push('\n');
+        }
+        let key = self.content_index_key(feed_id);
+        try!(self.repository.write(&key, Some(buf.into_bytes())));
+        Ok(())
+    }
+
+    fn content_index_key(&self, feed_id: &str) -> Vec<String> {
+        self.document_key(DocumentKey::Contents(feed_id.to_owned()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+    use super::ContentPolicy;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use blobs::BlobStore;
+    use crawler::test::MockBackend;
+    use feed::{Blob, Content, Entry, Feed, Text};
+    use mimetype::MimeType;
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_resolve_contents() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let blobdir = temp_dir();
+        let mut blobs = BlobStore::new(unwrap!(
+            FileSystemRepository::from_path(blobdir.path(), true)), &["b"]);
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        for &(id, ref mimetype, uri) in &[(
+            "urn:small", MimeType::Html, "http://example.com/small"
+        ), (
+            "urn:large", MimeType::Html, "http://example.com/large"
+        ), (
+            "urn:video",
+            MimeType::Other("video/mp4".to_owned()),
+            "http://example.com/video"
+        )] {
+            let mut entry = Entry::new(id.to_owned(), Text::plain(id), t);
+            entry.content = Some(unwrap!(Content::new(mimetype.clone(),
+                                                      vec![], Some(uri))));
+            feed.entries.push(entry);
+        }
+        unwrap!(stage.write_feed("feed", &feed));
+        let backend = MockBackend::default()
+            .with("http://example.com/small", 200, b"<p>Hi</p>")
+            .with("http://example.com/large", 200, &[b'a'; 64])
+            .with("http://example.com/video", 200, b"\x00\x00");
+
+        assert_eq!(unwrap!(stage.resolve_contents(&backend, &mut blobs,
+                                                  "feed")), 0);
+        stage.content_policy = Some(ContentPolicy {
+            max_size: 32,
+            ..Default::default()
+        });
+        assert_eq!(unwrap!(stage.resolve_contents(&backend, &mut blobs,
+                                                  "feed")), 1);
+        assert_eq!(unwrap!(stage.resolve_contents(&backend, &mut blobs,
+                                                  "feed")), 0);
+        let stored = unwrap!(stage.read_feed("feed"));
+        let cached = unwrap!(stage.cached_content(&blobs, "feed",
+                                                  &stored.entries[0]));
+        let cached = cached.unwrap();
+        assert_eq!(cached.as_str(), Some("<p>Hi</p>"));
+        assert_eq!(cached.source_uri(), Some("http://example.com/small"));
+        assert!(unwrap!(stage.cached_content(&blobs, "feed",
+                                             &stored.entries[1])).is_none());
+        assert!(unwrap!(stage.cached_content(&blobs, "feed",
+                                             &stored.entries[2])).is_none());
+        assert!(blobs.collect_garbage().unwrap().is_empty());
+    }
+}
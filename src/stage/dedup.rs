@@ -0,0 +1,123 @@
+//! Collapsing duplicate entries of aggregator feeds, e.g. planets.
+//!
+//! Planet feeds are rebuilt from time to time, and their entries often get
+//! new IDs on every rebuild while their permalinks and titles stay the
+//! same.  Since entries are merged by their IDs, such feeds bloat archives
+//! with copies of the same posts.  Subscriptions can opt in to collapsing
+//! them at merge time by `SubscriptionSettings::set_dedup_window()`:
+//! entries with the identical permalink and title, published within the
+//! window of each other, are collapsed into the earliest published one.
+//! Marks of collapsed entries are merged into the kept one, so that
+//! read and starred states aren't lost.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+
+use feed::{Blob, Entry, LinkSliceExt};
+use schema::Mergeable;
+
+/// Collapse duplicates of `entries` as the module documentation describes,
+/// and return the number of collapsed entries.  The order of the rest is
+/// kept.
+pub fn collapse_duplicates(entries: &mut Vec<Entry>, window: Duration)
+                           -> usize {
+    let window = ::chrono::Duration::seconds(window.as_secs() as i64);
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&i| published(&entries[i]));
+    let mut merges: Vec<(usize, usize)> = vec![];
+    {
+        // the earliest entry of the current window by each key
+        let mut survivors: HashMap<(&str, Cow<str>), usize> = HashMap::new();
+        for i in order {
+            let key = match duplicate_key(&entries[i]) {
+                Some(key) => key,
+                None => { continue; }
+            };
+            let at = published(&entries[i]);
+            match survivors.get(&key) {
+                Some(&s) if at - published(&entries[s]) <= window => {
+                    merges.push((s, i));
+                    continue;
+                }
+                _ => { }
+            }
+            survivors.insert(key, i);
+        }
+    }
+    if merges.is_empty() {
+        return 0;
+    }
+    let mut removed = vec![false; entries.len()];
+    for &(s, d) in merges.iter() {
+        let (read, starred, seen) = {
+            let duplicate = &entries[d];
+            (duplicate.read.clone(), duplicate.starred.clone(),
+             duplicate.seen.clone())
+        };
+        let kept = &mut entries[s];
+        kept.read.merge_with(read);
+        kept.starred.merge_with(starred);
+        kept.seen.merge_with(seen);
+        removed[d] = true;
+    }
+    let mut i = 0;
+    entries.retain(|_| {
+        let keep = !removed[i];
+        i += 1;
+        keep
+    });
+    merges.len()
+}
+
+fn published(entry: &Entry) -> DateTime<FixedOffset> {
+    entry.published_at.unwrap_or(entry.updated_at)
+}
+
+/// Entries without permalinks are never regarded as duplicates.
+fn duplicate_key(entry: &Entry) -> Option<(&str, Cow<str>)> {
+    entry.links.permalink().map(|link| {
+        (&link.uri[..], entry.title.normalized_text())
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::collapse_duplicates;
+
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Link, Text};
+
+    fn fx_entry(id: &str, title: &str, uri: &str, day: u32) -> Entry {
+        let t = FixedOffset::east(0).ymd(2013, 11, day).and_hms(0, 0, 0);
+        let mut entry = Entry::new(id.to_owned(), Text::plain(title), t);
+        entry.published_at = Some(t);
+        entry.links.push(Link::new(uri));
+        entry
+    }
+
+    #[test]
+    fn test_collapse_duplicates() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let mut entries = vec![
+            fx_entry("urn:b", "Post", "http://example.com/post", 2),
+            fx_entry("urn:other", "Other", "http://example.com/other", 2),
+            fx_entry("urn:a", "Post", "http://example.com/post", 1),
+            fx_entry("urn:c", "Post", "http://example.com/post", 4),
+            fx_entry("urn:d", "Post!", "http://example.com/post", 1),
+        ];
+        entries[0].read.marked = true;
+        entries[0].read.updated_at = entries[0].published_at;
+        assert_eq!(collapse_duplicates(&mut entries, day), 1);
+        let ids: Vec<&str> = entries.iter().map(|e| &e.id[..]).collect();
+        assert_eq!(ids, ["urn:other", "urn:a", "urn:c", "urn:d"]);
+        assert!(entries[1].read.marked);
+        assert_eq!(collapse_duplicates(&mut entries, day), 0);
+        assert_eq!(collapse_duplicates(&mut entries, day * 3), 1);
+    }
+}
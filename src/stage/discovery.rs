@@ -0,0 +1,106 @@
+//! Subscribing to websites by their URLs.  See also the `crawler::discovery`
+//! module.
+use crawler::{Backend, Discovery, discover};
+use digest::sha1_hex;
+use parser::ParseOptions;
+use repository::Repository;
+use subscribe::Subscription;
+
+use super::{Result, Stage};
+
+/// What `Stage::subscribe_by_url()` chose.
+#[derive(Debug)]
+pub struct SubscribedByUrl {
+    /// The identifier the feed is stored as, which is the SHA-1 hexdigest
+    /// of the feed's `id`, or that of the existing subscription the feed
+    /// belongs to; see `Stage::route_crawled()`.
+    pub feed_id: String,
+
+    pub subscription: Subscription,
+
+    /// Whether it's newly subscribed; it's `false` if the feed was already
+    /// subscribed, while the feed is still stored.
+    pub subscribed: bool,
+
+    /// Which feed was chosen for the URL, and why.
+    pub discovery: Discovery,
+}
+
+impl<R: Repository> Stage<R> {
+    /// Find the feed of the website or the feed `url`, fetch it, subscribe
+    /// to it in the category of `path`, and store its initial entries.
+    /// See `crawler::discover()` for how the feed is chosen.
+    pub fn subscribe_by_url<B, T>(&mut self, backend: &B, url: &str,
+                                  path: &[T], options: &ParseOptions)
+                                  -> Result<SubscribedByUrl>
+        where B: Backend + ?Sized, T: AsRef<str>
+    {
+        let discovery = try!(discover(backend, url, options));
+        let feed = discovery.result.feed.clone();
+        let feed_id = match try!(self.route_crawled(url, &discovery.result)) {
+            Some(feed_id) => feed_id,
+            None => sha1_hex(feed.id.as_bytes()),
+        };
+        let label = feed.display_title().as_plain_text().into_owned();
+        let subscription = Subscription::new(&feed_id[..],
+                                             &discovery.result.url[..],
+                                             label);
+        let feed = if self.repository.exists(&self.feed_key(&feed_id)) {
+            let stored = try!(self.read_feed(&feed_id));
+            try!(self.merge_feed(&feed_id, stored, feed))
+        } else {
+            feed
+        };
+        try!(self.write_feed(&feed_id, &feed));
+        let subscribed = try!(self.subscribe(path, subscription.clone()));
+        Ok(SubscribedByUrl {
+            feed_id: feed_id,
+            subscription: subscription,
+            subscribed: subscribed,
+            discovery: discovery,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use crawler::Reason;
+    use crawler::test::{FX_RSS, MockBackend};
+    use digest::sha1_hex;
+    use repository::FileSystemRepository;
+    use subscribe::SubscriptionSet;
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_subscribe_by_url() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = Stage::new(repo);
+        let backend = MockBackend::default()
+            .with("http://earthreader.org/", 200, concat!(
+                r#"<link rel="alternate" type="application/rss+xml""#,
+                r#" href="/rss">"#
+            ).as_bytes())
+            .with("http://earthreader.org/rss", 200, FX_RSS);
+        let options = Default::default();
+        let result = unwrap!(stage.subscribe_by_url(
+            &backend, "http://earthreader.org/", &["News"], &options));
+        assert!(result.subscribed);
+        assert_eq!(result.discovery.reason, Reason::LinkElement);
+        assert_eq!(result.subscription.feed_uri, "http://earthreader.org/rss");
+        assert_eq!(result.subscription.label, "Earth Reader");
+        let feed_id = sha1_hex(b"http://earthreader.org/rss");
+        assert_eq!(result.feed_id, feed_id);
+        assert_eq!(unwrap!(stage.read_feed(&feed_id)).entries.len(), 1);
+        let list = unwrap!(stage.read_subscriptions());
+        assert!(list.find(&feed_id).is_some());
+        let result = unwrap!(stage.subscribe_by_url(
+            &backend, "http://earthreader.org/rss", &["News"], &options));
+        assert!(!result.subscribed);
+        assert_eq!(result.discovery.reason, Reason::Direct);
+    }
+}
@@ -0,0 +1,456 @@
+//! Downloading enclosures of entries e.g. podcast episodes into a
+//! `BlobStore`.
+//!
+//! Files are fetched through the crawler `Backend` in chunks by HTTP range
+//! requests, and each chunk is appended to a partial blob, so that an
+//! interrupted download is resumed from where it stopped.  Downloads are
+//! queued in a document of the stage, so the queue survives restarts of
+//! the app.
+use std::io;
+
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use blobs::{self, BlobStore, entry_referrer};
+use crawler::{self, Backend, Request};
+use feed::Link;
+use parser::base::{DecodeResult, XmlElement, XmlName};
+use repository::Repository;
+use schema::{self, DocumentElement, FromSchemaReader, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+use super::{DocumentKey, Error, Result, Stage};
+
+/// The XML namespace name used for download queue documents.
+pub const DOWNLOADS_XMLNS: &'static str = "http://earthreader.org/downloads/";
+
+/// How enclosures are downloaded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DownloadPolicy {
+    /// The maximum number of downloads `Stage::start_downloads()` lets run
+    /// at the same time.
+    pub max_concurrent: usize,
+
+    /// The number of bytes requested at once.  Smaller chunks lose less
+    /// when interrupted, and report progress more often.
+    pub chunk_size: u64,
+}
+
+impl Default for DownloadPolicy {
+    fn default() -> DownloadPolicy {
+        DownloadPolicy { max_concurrent: 2, chunk_size: 1024 * 1024 }
+    }
+}
+
+/// A queued download of the enclosure of an entry.  It corresponds to
+/// `download` element.
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug)]
+pub struct Download {
+    pub feed_id: String,
+    pub entry_id: String,
+
+    /// The URL of the enclosure.
+    pub url: String,
+}
+
+impl FromSchemaReader for Download {
+    fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        self.feed_id = try!(element.get_attr("feed")).to_owned();
+        self.entry_id = try!(element.get_attr("entry")).to_owned();
+        self.url = try!(element.get_attr("url")).to_owned();
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for Download {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.attr("feed", &self.feed_id)
+                                 .attr("entry", &self.entry_id)
+                                 .attr("url", &self.url)));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+/// The download queue document.  It corresponds to `downloads` element.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+struct DownloadQueue {
+    downloads: Vec<Download>,
+}
+
+impl DocumentElement for DownloadQueue {
+    fn tag() -> &'static str { "downloads" }
+    fn xmlns() -> Option<&'static str> { Some(DOWNLOADS_XMLNS) }
+}
+
+impl FromSchemaReader for DownloadQueue {
+    fn match_child<B: io::BufRead>(&mut self, name: &XmlName,
+                                   child: XmlElement<B>) -> DecodeResult<()>
+    {
+        if name.local_name == "download" {
+            self.downloads.push(try!(FromSchemaReader::build_from(child)));
+        }
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for DownloadQueue {
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        for download in self.downloads.iter() {
+            try!(download.write_to(writer,
+                                   XmlEvent::start_element("download")));
+        }
+        Ok(())
+    }
+}
+
+/// How far a download went, which is passed to progress callbacks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Progress {
+    /// The number of bytes downloaded so far, including ones downloaded
+    /// before the download was resumed.
+    pub received: u64,
+
+    /// The size of the file, if the server tells.
+    pub total: Option<u64>,
+}
+
+impl<R: Repository> Stage<R> {
+    /// Downloads in the queue, in the order they were queued.
+    pub fn download_queue(&self) -> Result<Vec<Download>> {
+        Ok(try!(self.read_download_queue()).downloads)
+    }
+
+    /// Queue the download of the enclosure of the entry.  It returns
+    /// `false` if it's already queued.
+    pub fn queue_download(&mut self, feed_id: &str, entry_id: &str,
+                          enclosure: &Link) -> Result<bool> {
+        let download = Download {
+            feed_id: feed_id.to_owned(),
+            entry_id: entry_id.to_owned(),
+            url: enclosure.uri.clone(),
+        };
+        let mut queue = try!(self.read_download_queue());
+        if queue.downloads.contains(&download) {
+            return Ok(false);
+        }
+        queue.downloads.push(download);
+        try!(self.write_download_queue(&queue));
+        Ok(true)
+    }
+
+    /// Take queued downloads to start, as many as `download_policy`
+    /// allows to run along with ones already running.  Call
+    /// `finish_download()` when each of them finishes.
+    pub fn start_downloads(&mut self) -> Result<Vec<Download>> {
+        let queue = try!(self.read_download_queue());
+        let available = self.download_policy.max_concurrent
+                            .saturating_sub(self.running_downloads.len());
+        let started: Vec<Download> = queue.downloads.into_iter().filter(|d| {
+            !self.running_downloads.contains(d)
+        }).take(available).collect();
+        for download in started.iter() {
+            self.running_downloads.insert(download.clone());
+        }
+        Ok(started)
+    }
+
+    /// Let the stage know the download finished.  It's removed from the
+    /// queue if it `succeeded`, and otherwise it's left to be retried.
+    pub fn finish_download(&mut self, download: &Download, succeeded: bool)
+                           -> Result<()> {
+        self.running_downloads.remove(download);
+        if !succeeded {
+            return Ok(());
+        }
+        let mut queue = try!(self.read_download_queue());
+        let count = queue.downloads.len();
+        queue.downloads.retain(|d| d != download);
+        if queue.downloads.len() != count {
+            try!(self.write_download_queue(&queue));
+        }
+        Ok(())
+    }
+
+    /// Download the enclosure of the entry into the blob store right away,
+    /// and return the hash of the blob, which is referred by the entry.
+    /// It's queued while downloading, so that it can be resumed by
+    /// `start_downloads()` if the app is terminated meanwhile.
+    pub fn download_enclosure<B, S, F>(&mut self, backend: &B,
+                                       blobs: &mut BlobStore<S>,
+                                       feed_id: &str, entry_id: &str,
+                                       enclosure: &Link, progress: F)
+                                       -> Result<String>
+        where B: Backend + ?Sized, S: Repository, F: FnMut(Progress)
+    {
+        try!(self.queue_download(feed_id, entry_id, enclosure));
+        let download = Download {
+            feed_id: feed_id.to_owned(),
+            entry_id: entry_id.to_owned(),
+            url: enclosure.uri.clone(),
+        };
+        self.running_downloads.insert(download.clone());
+        let result = download_to(backend, blobs, &download,
+                                 self.download_policy.chunk_size, progress);
+        try!(self.finish_download(&download, result.is_ok()));
+        result
+    }
+
+    fn read_download_queue(&self) -> Result<DownloadQueue> {
+        let key = self.document_key(DocumentKey::Downloads);
+        if !self.repository.exists(&key) {
+            return Ok(Default::default());
+        }
+        self.read_document(&key)
+    }
+
+    fn write_download_queue(&mut self, queue: &DownloadQueue) -> Result<()> {
+        let key = self.document_key(DocumentKey::Downloads);
+        let w = try!(self.repository.get_writer(&key));
+        try!(schema::write(queue, w));
+        Ok(())
+    }
+}
+
+/// Download the file into the blob store, resuming the partial blob left
+/// by the previous attempt if any, and return the hash of the blob.  The
+/// entry of the download is recorded as a referrer of the blob.  It can be
+/// called from background jobs; see `Stage::start_downloads()`.
+pub fn download_to<B, S, F>(backend: &B, blobs: &mut BlobStore<S>,
+                            download: &Download, chunk_size: u64,
+                            mut progress: F) -> Result<String>
+    where B: Backend + ?Sized, S: Repository, F: FnMut(Progress)
+{
+    let chunk_size = if chunk_size < 1 { 1 } else { chunk_size };
+    let url = &download.url;
+    loop {
+        let offset = try!(blobs.partial_len(url));
+        let request = Request::new(&url[..])
+            .header("Range", format!("bytes={}-{}", offset,
+                                     offset + chunk_size - 1));
+        let response = try!(crawler::fetch(backend, &request));
+        let (received, total) = match response.status {
+            206 => {
+                let total = response.header("Content-Range")
+                                    .and_then(content_range_total);
+                let received = try!(blobs.append_partial(url,
+                                                         &response.body));
+                if response.body.is_empty() {
+                    break;
+                }
+                (received, total)
+            }
+            // the server doesn't support ranges; it's the whole file
+            200 => {
+                try!(blobs.discard_partial(url));
+                let received = try!(blobs.append_partial(url,
+                                                         &response.body));
+                progress(Progress { received: received,
+                                    total: Some(received) });
+                break;
+            }
+            // the range starts at the end; the file was done
+            416 if offset > 0 => { break; }
+            status => {
+                return Err(Error::Crawl(crawler::Error::Status(status)));
+            }
+        };
+        progress(Progress { received: received, total: total });
+        let done = match total {
+            Some(total) => received >= total,
+            None => (response.body.len() as u64) < chunk_size,
+        };
+        if done {
+            break;
+        }
+    }
+    let hash = try!(blobs.finish_partial(url));
+    try!(blobs.add_ref(&hash, &entry_referrer(&download.feed_id,
+                                              &download.entry_id)));
+    Ok(hash)
+}
+
+/// The total length in `Content-Range` header e.g. `bytes 0-99/1234`.
+fn content_range_total(value: &str) -> Option<u64> {
+    value.rsplit('/').next().and_then(|t| t.trim().parse().ok())
+}
+
+impl From<blobs::Error> for Error {
+    fn from(err: blobs::Error) -> Error { Error::Blob(err) }
+}
+
+impl From<crawler::Error> for Error {
+    fn from(err: crawler::Error) -> Error { Error::Crawl(err) }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error { Error::Crawl(crawler::Error::Io(err)) }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::{Error, Stage};
+    use super::{Download, Progress, content_range_total, download_to};
+
+    use std::cell::Cell;
+    use std::io;
+
+    use blobs::{BlobStore, entry_referrer};
+    use crawler::{self, Backend, Request, Response};
+    use digest::sha1_hex;
+    use feed::{Link, LinkRel};
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    static FX_BODY: &'static [u8] = b"Earth Reader is a decentralized reader";
+
+    /// The backend which serves `FX_BODY` honoring ranges, and fails after
+    /// the number of requests.
+    struct RangeBackend {
+        ranges: bool,
+        fail_after: Option<usize>,
+        requests: Cell<usize>,
+    }
+
+    impl RangeBackend {
+        fn new(ranges: bool, fail_after: Option<usize>) -> RangeBackend {
+            RangeBackend { ranges: ranges, fail_after: fail_after,
+                           requests: Cell::new(0) }
+        }
+    }
+
+    impl Backend for RangeBackend {
+        fn fetch(&self, request: &Request) -> io::Result<Response> {
+            let count = self.requests.get();
+            if self.fail_after.map(|n| count >= n).unwrap_or(false) {
+                return Err(io::Error::other("connection reset"));
+            }
+            self.requests.set(count + 1);
+            let range = request.headers.iter()
+                               .find(|&(n, _)| n == "Range")
+                               .map(|(_, v)| v.clone());
+            let (status, headers, body) = match range {
+                Some(ref range) if self.ranges => {
+                    let range = range.trim_start_matches("bytes=");
+                    let mut parts = range.split('-');
+                    let start: usize = parts.next().unwrap().parse().unwrap();
+                    let end: usize = parts.next().unwrap().parse().unwrap();
+                    if start >= FX_BODY.len() {
+                        (416, vec![], vec![])
+                    } else {
+                        let end = ::std::cmp::min(end + 1, FX_BODY.len());
+                        let value = format!("bytes {}-{}/{}", start, end - 1,
+                                            FX_BODY.len());
+                        (206, vec![("Content-Range".to_owned(), value)],
+                         FX_BODY[start..end].to_vec())
+                    }
+                }
+                _ => (200, vec![], FX_BODY.to_vec()),
+            };
+            Ok(Response { url: request.url.clone(), status: status,
+                          headers: headers, body: body, redirects: vec![] })
+        }
+    }
+
+    fn fx_download() -> Download {
+        Download {
+            feed_id: "feed".to_owned(),
+            entry_id: "urn:entry:1".to_owned(),
+            url: "http://earthreader.org/episode.mp3".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_content_range_total() {
+        assert_eq!(content_range_total("bytes 0-99/1234"), Some(1234));
+        assert_eq!(content_range_total("bytes 0-99/*"), None);
+    }
+
+    #[test]
+    fn test_download_resume() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut blobs = BlobStore::new(repo, &["blobs"]);
+        let download = fx_download();
+        let interrupted = RangeBackend::new(true, Some(2));
+        assert_err!(download_to(&interrupted, &mut blobs, &download, 10,
+                                |_| ()),
+                    Error::Crawl(crawler::Error::Io(_)) => { });
+        assert_eq!(unwrap!(blobs.partial_len(&download.url)), 20);
+        let backend = RangeBackend::new(true, None);
+        let mut progress = vec![];
+        let hash = unwrap!(download_to(&backend, &mut blobs, &download, 10,
+                                       |p| progress.push(p)));
+        assert_eq!(backend.requests.get(), 2);
+        assert_eq!(progress, [Progress { received: 30, total: Some(38) },
+                              Progress { received: 38, total: Some(38) }]);
+        assert_eq!(hash, sha1_hex(FX_BODY));
+        assert_eq!(unwrap!(blobs.read(&hash)), FX_BODY);
+        assert_eq!(unwrap!(blobs.refs(&hash)),
+                   [entry_referrer("feed", "urn:entry:1")]);
+
+        let hash = unwrap!(download_to(&RangeBackend::new(false, None),
+                                       &mut blobs, &download, 10, |_| ()));
+        assert_eq!(unwrap!(blobs.read(&hash)), FX_BODY);
+    }
+
+    #[test]
+    fn test_stage_download_enclosure() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = Stage::new(repo);
+        stage.download_policy.chunk_size = 16;
+        let blobdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(blobdir.path(),
+                                                           true));
+        let mut blobs = BlobStore::new(repo, &["blobs"]);
+        let mut link = Link::new("http://earthreader.org/episode.mp3");
+        link.relation = LinkRel::Enclosure;
+        assert_err!(stage.download_enclosure(
+                        &RangeBackend::new(true, Some(1)), &mut blobs,
+                        "feed", "urn:entry:1", &link, |_| ()),
+                    Error::Crawl(_) => { });
+        assert_eq!(unwrap!(stage.download_queue()), [fx_download()]);
+        unwrap!(stage.flush());
+
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = Stage::new(repo);
+        stage.download_policy.max_concurrent = 1;
+        let mut second = link.clone();
+        second.uri = "http://earthreader.org/second.mp3".to_owned();
+        assert!(unwrap!(stage.queue_download("feed", "urn:entry:2",
+                                             &second)));
+        assert!(!unwrap!(stage.queue_download("feed", "urn:entry:2",
+                                              &second)));
+        let started = unwrap!(stage.start_downloads());
+        assert_eq!(started, [fx_download()]);
+        assert!(unwrap!(stage.start_downloads()).is_empty());
+        let backend = RangeBackend::new(true, None);
+        let hash = unwrap!(download_to(&backend, &mut blobs, &started[0],
+                                       stage.download_policy.chunk_size,
+                                       |_| ()));
+        assert_eq!(unwrap!(blobs.read(&hash)), FX_BODY);
+        unwrap!(stage.finish_download(&started[0], true));
+        let started = unwrap!(stage.start_downloads());
+        assert_eq!(started.len(), 1);
+        assert_eq!(started[0].url, second.uri);
+        unwrap!(stage.finish_download(&started[0], false));
+        assert_eq!(unwrap!(stage.download_queue()).len(), 1);
+        let hash = unwrap!(stage.download_enclosure(&backend, &mut blobs,
+                                                    "feed", "urn:entry:2",
+                                                    &second, |_| ()));
+        assert_eq!(unwrap!(blobs.read(&hash)), FX_BODY);
+        assert!(unwrap!(stage.download_queue()).is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+//! Checking the consistency of documents in the stage, e.g. after a sync
+//! conflict or a crash in the middle of writing.
+//!
+//! `Stage::fsck()` finds problems, and plans repairs for ones that can be
+//! fixed safely; `Stage::repair()` applies them.  Problems without repairs,
+//! e.g. documents that can't be decoded, are only reported, since fixing
+//! them would lose data.
+use std::collections::{HashMap, HashSet};
+
+use feed::Feed;
+use repository::Repository;
+use schema::Mergeable;
+use subscribe::{SubscriptionSet, SubscriptionSettings};
+
+use super::{DocumentKey, KeyLayout, Result, Stage, is_paging_link,
+            next_page_index, page_document};
+use super::layout::SETTINGS_KEY;
+
+/// An inconsistency found by `Stage::fsck()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Problem {
+    /// The document can't be decoded, with the reason.
+    InvalidDocument { key: Vec<String>, error: String },
+
+    /// The same entries are stored in more than one place of the feed,
+    /// e.g. in two pages.
+    DuplicateEntries { feed_id: String, entry_ids: Vec<String> },
+
+    /// The archive page isn't reachable from the feed it belongs to.
+    OrphanedPage { key: Vec<String> },
+
+    /// The feed document or the page is laid out in the key layout other
+    /// than the current one, so the stage can't see it.
+    MisplacedDocument { key: Vec<String> },
+
+    /// The settings of the feed which isn't subscribed.
+    OrphanedSettings { feed_id: String },
+
+    /// The subscribed feed has no document.  It's normal for feeds not
+    /// crawled yet.
+    MissingFeed { feed_id: String },
+}
+
+/// A fix planned by `Stage::fsck()`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Repair {
+    Delete { key: Vec<String> },
+
+    /// Move the document to the key, e.g. into the current key layout.
+    /// It's merged with the document already there if any.
+    Move { from: Vec<String>, to: Vec<String> },
+
+    /// Rewrite the feed with duplicate entries merged into one.
+    MergeDuplicates { feed_id: String },
+}
+
+/// The result of `Stage::fsck()`.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct FsckReport {
+    pub problems: Vec<Problem>,
+
+    /// Repairs to fix problems, which can be passed to `Stage::repair()`.
+    pub repairs: Vec<Repair>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool { self.problems.is_empty() }
+}
+
+impl<R: Repository> Stage<R> {
+    /// Check the consistency of stored documents.  It doesn't change
+    /// anything; see `repair()`.
+    pub fn fsck(&self) -> Result<FsckReport> {
+        let mut report: FsckReport = Default::default();
+        try!(self.check_feeds(&mut report));
+        let other = match self.key_layout {
+            KeyLayout::Flat => KeyLayout::Sharded,
+            KeyLayout::Sharded => KeyLayout::Flat,
+        };
+        let mut misplaced: Vec<DocumentKey> =
+            try!(self.document_names(other)).into_iter()
+                .map(DocumentKey::Feed).collect();
+        for (feed_id, number) in try!(self.page_documents(other)) {
+            misplaced.push(DocumentKey::FeedPage(feed_id, number));
+        }
+        for document in misplaced {
+            let key = document.key(other);
+            report.problems.push(Problem::MisplacedDocument {
+                key: key.clone(),
+            });
+            let to = self.document_key(document);
+            report.repairs.push(Repair::Move { from: key, to: to });
+        }
+        try!(self.check_subscriptions(&mut report));
+        Ok(report)
+    }
+
+    /// Apply the repairs `fsck()` planned.
+    pub fn repair(&mut self, repairs: &[Repair]) -> Result<()> {
+        for repair in repairs {
+            match *repair {
+                Repair::Delete { ref key } => {
+                    if self.repository.exists(key) {
+                        try!(self.repository.delete(key));
+                    }
+                }
+                Repair::Move { ref from, ref to } => {
+                    if self.repository.exists(to) {
+                        let buf = try!(self.repository.read(from));
+                        let mut feed: Feed = try!(self.read_document(to));
+                        let moved: Feed = try!(::schema::read(&buf[..]));
+                        feed.merge_with(moved);
+                        let w = try!(self.repository.get_writer(to));
+                        try!(::schema::write(&feed, w));
+                        try!(self.repository.delete(from));
+                    } else {
+                        try!(self.repository.rename(from, to));
+                    }
+                }
+                Repair::MergeDuplicates { ref feed_id } => {
+                    let mut feed = try!(self.read_feed(feed_id));
+                    let mut entries: Vec<::feed::Entry> = vec![];
+                    let mut indices = HashMap::new();
+                    for entry in feed.entries {
+                        match indices.get(&entry.id).cloned() {
+                            Some(i) => {
+                                let e: &mut ::feed::Entry = &mut entries[i];
+                                e.merge_with(entry);
+                            }
+                            None => {
+                                indices.insert(entry.id.clone(),
+                                               entries.len());
+                                entries.push(entry);
+                            }
+                        }
+                    }
+                    feed.entries = entries;
+                    try!(self.write_feed(feed_id, &feed));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_feeds(&self, report: &mut FsckReport) -> Result<()> {
+        let mut reachable = HashSet::new();
+        for feed_id in try!(self.feed_ids()) {
+            let mut seen = HashSet::new();
+            let mut duplicates = vec![];
+            let mut next_page = Some(0);
+            while let Some(index) = next_page.take() {
+                let document = page_document(&feed_id, index);
+                let key = self.document_key(document.clone());
+                if !reachable.insert(document) ||
+                    !self.repository.exists(&key) {
+                    break;
+                }
+                let page: Feed = match self.read_document(&key) {
+                    Ok(page) => page,
+                    Err(err) => {
+                        report.problems.push(Problem::InvalidDocument {
+                            key: key,
+                            error: err.to_string(),
+                        });
+                        break;
+                    }
+                };
+                for entry in page.entries.iter() {
+                    if !seen.insert(entry.id.clone()) &&
+                        !duplicates.contains(&entry.id) {
+                        duplicates.push(entry.id.clone());
+                    }
+                }
+                if page.links.iter().any(is_paging_link) {
+                    next_page = next_page_index(&feed_id, &page);
+                }
+            }
+            if !duplicates.is_empty() {
+                report.problems.push(Problem::DuplicateEntries {
+                    feed_id: feed_id.clone(),
+                    entry_ids: duplicates,
+                });
+                report.repairs.push(Repair::MergeDuplicates {
+                    feed_id: feed_id,
+                });
+            }
+        }
+        for (feed_id, number) in try!(self.page_documents(self.key_layout)) {
+            let document = DocumentKey::FeedPage(feed_id, number);
+            if !reachable.contains(&document) {
+                let key = self.document_key(document);
+                report.problems.push(Problem::OrphanedPage {
+                    key: key.clone(),
+                });
+                report.repairs.push(Repair::Delete { key: key });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_subscriptions(&self, report: &mut FsckReport) -> Result<()> {
+        let list = match self.read_subscriptions() {
+            Ok(list) => list,
+            Err(err) => {
+                let key = self.document_key(DocumentKey::Subscriptions);
+                report.problems.push(Problem::InvalidDocument {
+                    key: key,
+                    error: err.to_string(),
+                });
+                return Ok(());
+            }
+        };
+        for subscription in list.recursive_subscriptions() {
+            let feed_id = &subscription.feed_id;
+            if !self.repository.exists(&self.feed_key(feed_id)) {
+                report.problems.push(Problem::MissingFeed {
+                    feed_id: feed_id.clone(),
+                });
+            }
+        }
+        if !self.repository.exists(&[SETTINGS_KEY]) {
+            return Ok(());
+        }
+        let mut feed_ids: Vec<String> =
+            try!(try!(self.repository.list(&[SETTINGS_KEY])).collect());
+        feed_ids.sort();
+        for feed_id in feed_ids {
+            let key =
+                self.document_key(DocumentKey::Settings(feed_id.clone()));
+            if let Err(err) = self.read_document::<SubscriptionSettings, _>(
+                                  &key) {
+                report.problems.push(Problem::InvalidDocument {
+                    key: key,
+                    error: err.to_string(),
+                });
+                continue;
+            }
+            if list.find(&feed_id).is_none() {
+                report.problems.push(Problem::OrphanedSettings {
+                    feed_id: feed_id,
+                });
+                report.repairs.push(Repair::Delete { key: key });
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::{KeyLayout, Stage};
+    use super::{Problem, Repair};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Link, LinkRel, Text};
+    use repository::{FileSystemRepository, Repository};
+    use subscribe::Subscription;
+    use test_utils::temp_dir;
+
+    fn fx_feed(entries: &[&str]) -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        for id in entries {
+            feed.entries.push(Entry::new(id.to_string(), Text::plain(*id),
+                                         updated_at));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_stage_fsck() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = Stage::new(repo);
+        unwrap!(stage.write_feed("feed", &fx_feed(&["a", "b"])));
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", "", "Feed")));
+        assert!(unwrap!(stage.fsck()).is_ok());
+
+        // a page which duplicates "b", and one unreachable from the feed
+        let mut head = fx_feed(&["a", "b"]);
+        let mut next = Link::new("feed.2");
+        next.relation = LinkRel::Next;
+        head.links.push(next);
+        unwrap!(stage.write_feed("feed", &head));
+        let page = fx_feed(&["b", "c"]);
+        let w = unwrap!(stage.repository.get_writer(&["pages", "feed", "2"]));
+        unwrap!(::schema::write(&page, w));
+        let w = unwrap!(stage.repository.get_writer(&["pages", "feed", "3"]));
+        unwrap!(::schema::write(&fx_feed(&["d"]), w));
+        unwrap!(stage.repository.write(&["feeds", "broken"],
+                                       Some(&b"<feed"[..])));
+        let moved = KeyLayout::Sharded.key("feeds", "moved");
+        let w = unwrap!(stage.repository.get_writer(&moved));
+        unwrap!(::schema::write(&fx_feed(&["e"]), w));
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("missing", "", "Missing")));
+        unwrap!(stage.write_settings("gone", &Default::default()));
+
+        let report = unwrap!(stage.fsck());
+        assert!(report.problems.contains(&Problem::DuplicateEntries {
+            feed_id: "feed".to_owned(),
+            entry_ids: vec!["b".to_owned()],
+        }));
+        assert!(report.problems.contains(&Problem::OrphanedPage {
+            key: vec!["pages".to_owned(), "feed".to_owned(),
+                      "3".to_owned()],
+        }));
+        assert!(report.problems.iter().any(|p| match *p {
+            Problem::InvalidDocument { ref key, .. } => key[1] == "broken",
+            _ => false,
+        }));
+        assert!(report.problems.contains(&Problem::MisplacedDocument {
+            key: moved.clone(),
+        }));
+        assert!(report.problems.contains(&Problem::MissingFeed {
+            feed_id: "missing".to_owned(),
+        }));
+        assert!(report.problems.contains(&Problem::OrphanedSettings {
+            feed_id: "gone".to_owned(),
+        }));
+        assert_eq!(report.repairs.len(), 4);
+        assert!(report.repairs.contains(&Repair::MergeDuplicates {
+            feed_id: "feed".to_owned(),
+        }));
+
+        unwrap!(stage.repair(&report.repairs));
+        let feed = unwrap!(stage.read_feed("feed"));
+        let ids: Vec<&str> = feed.entries.iter().map(|e| &e.id[..]).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(unwrap!(stage.read_feed("moved")).entries.len(), 1);
+        let report = unwrap!(stage.fsck());
+        assert!(report.repairs.is_empty());
+        assert_eq!(report.problems.len(), 2);
+    }
+}
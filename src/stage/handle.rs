@@ -0,0 +1,256 @@
+//! Typed handles of stage documents for read-modify-write cycles.
+//!
+//! Instead of reading a document, changing it, and writing it back by
+//! hand, apps can take a `DocumentHandle` by `Stage::edit_feed()`,
+//! `Stage::edit_subscriptions()` or `Stage::edit_settings()`.  The handle
+//! keeps the document read from the stage, and tracks whether it was
+//! borrowed mutably by `get_mut()`.  A dirty document is written back
+//! through the stage's buffer on `save()`, or when the handle goes out of
+//! scope.  For example, to mark every entry of a feed as read:
+//!
+//! ```
+//! # use earth::feed::MarkKind;
+//! # use earth::repository::Repository;
+//! # use earth::stage::{Result, Stage};
+//! # fn mark_all_read<R: Repository>(stage: &mut Stage<R>) -> Result<()> {
+//! let mut feed = try!(stage.edit_feed("feed-id"));
+//! for entry in feed.get_mut().entries.iter_mut() {
+//!     entry.mark_mut(MarkKind::Read).marked = true;
+//! }
+//! try!(feed.save());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Errors writing back on drop can't be reported, so call `save()` to
+//! handle them.  Like `Stage::write_feed()` and the rest, writes by
+//! handles aren't recorded to the journal nor to the undo history.
+use std::fmt;
+
+use feed::Feed;
+use repository::Repository;
+use subscribe::{SubscriptionList, SubscriptionSettings};
+
+use super::{Result, Stage};
+
+/// Documents the stage can read and write by an identifier.
+pub trait Document: Sized {
+    /// Read the document of `id` from the stage.
+    fn load<R: Repository>(stage: &Stage<R>, id: &str) -> Result<Self>;
+
+    /// Write the document as `id` to the stage.
+    fn store<R: Repository>(&self, stage: &mut Stage<R>, id: &str)
+                            -> Result<()>;
+}
+
+/// Feeds by their feed ID.
+impl Document for Feed {
+    fn load<R: Repository>(stage: &Stage<R>, id: &str) -> Result<Feed> {
+        stage.read_feed(id)
+    }
+
+    fn store<R: Repository>(&self, stage: &mut Stage<R>, id: &str)
+                            -> Result<()> {
+        stage.write_feed(id, self)
+    }
+}
+
+/// The subscription list; there's only one, so the identifier is ignored.
+impl Document for SubscriptionList {
+    fn load<R: Repository>(stage: &Stage<R>, _: &str)
+                           -> Result<SubscriptionList> {
+        stage.read_subscriptions()
+    }
+
+    fn store<R: Repository>(&self, stage: &mut Stage<R>, _: &str)
+                            -> Result<()> {
+        stage.write_subscriptions(self)
+    }
+}
+
+/// Subscription settings by their feed ID.
+impl Document for SubscriptionSettings {
+    fn load<R: Repository>(stage: &Stage<R>, id: &str)
+                           -> Result<SubscriptionSettings> {
+        stage.read_settings(id)
+    }
+
+    fn store<R: Repository>(&self, stage: &mut Stage<R>, id: &str)
+                            -> Result<()> {
+        stage.write_settings(id, self)
+    }
+}
+
+/// A document read from the stage, which is written back if it's changed.
+/// See the module documentation.
+pub struct DocumentHandle<'a, R: Repository + 'a, T: Document> {
+    stage: &'a mut Stage<R>,
+    id: String,
+    value: T,
+    dirty: bool,
+}
+
+impl<'a, R: Repository, T: Document> DocumentHandle<'a, R, T> {
+    /// Read the document of `id` from the `stage`.
+    pub fn open(stage: &'a mut Stage<R>, id: &str)
+                -> Result<DocumentHandle<'a, R, T>> {
+        let value = try!(T::load(stage, id));
+        Ok(DocumentHandle {
+            stage: stage,
+            id: id.to_owned(),
+            value: value,
+            dirty: false,
+        })
+    }
+
+    /// The identifier of the document, e.g. the feed ID.
+    pub fn id(&self) -> &str { &self.id }
+
+    pub fn get(&self) -> &T { &self.value }
+
+    /// Borrow the document mutably, which makes it dirty even if nothing
+    /// is actually changed.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.dirty = true;
+        &mut self.value
+    }
+
+    /// Whether the document has to be written back.
+    pub fn is_dirty(&self) -> bool { self.dirty }
+
+    /// Write the document back to the stage if it's dirty.  It returns
+    /// whether it was written.
+    pub fn save(&mut self) -> Result<bool> {
+        if !self.dirty {
+            return Ok(false);
+        }
+        try!(self.value.store(self.stage, &self.id));
+        self.dirty = false;
+        Ok(true)
+    }
+
+    /// Drop the handle without writing back changes made so far.
+    pub fn discard(mut self) {
+        self.dirty = false;
+    }
+}
+
+impl<'a, R: Repository, T: Document> Drop for DocumentHandle<'a, R, T> {
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
+
+impl<'a, R, T> fmt::Debug for DocumentHandle<'a, R, T>
+    where R: Repository, T: Document + fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DocumentHandle")
+         .field("id", &self.id)
+         .field("value", &self.value)
+         .field("dirty", &self.dirty)
+         .finish()
+    }
+}
+
+impl<R: Repository> Stage<R> {
+    /// Take the handle of the feed of `feed_id`.  See the module
+    /// documentation.
+    pub fn edit_feed(&mut self, feed_id: &str)
+                     -> Result<DocumentHandle<R, Feed>> {
+        DocumentHandle::open(self, feed_id)
+    }
+
+    /// Take the handle of the subscription list.
+    pub fn edit_subscriptions(&mut self)
+                              -> Result<DocumentHandle<R, SubscriptionList>>
+    {
+        DocumentHandle::open(self, "")
+    }
+
+    /// Take the handle of the settings of the subscription of `feed_id`.
+    pub fn edit_settings(&mut self, feed_id: &str)
+                         -> Result<DocumentHandle<R, SubscriptionSettings>>
+    {
+        DocumentHandle::open(self, feed_id)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use chrono::{DateTime, FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, MarkKind, Text};
+    use repository::FileSystemRepository;
+    use subscribe::{Subscription, SubscriptionSet};
+    use subscribe::settings::CRAWL_INTERVAL;
+    use test_utils::temp_dir;
+
+    fn date() -> DateTime<FixedOffset> {
+        FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0)
+    }
+
+    fn fx_feed() -> Feed {
+        let date = date();
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), date);
+        feed.entries.push(Entry::new("urn:entry".to_owned(),
+                                     Text::plain("Entry"), date));
+        feed
+    }
+
+    #[test]
+    fn test_document_handle() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        unwrap!(stage.write_feed("a", &fx_feed()));
+        unwrap!(stage.flush());
+        {
+            let mut feed = unwrap!(stage.edit_feed("a"));
+            assert_eq!(feed.id(), "a");
+            assert_eq!(feed.get().entries.len(), 1);
+            assert!(!unwrap!(feed.save()));
+            feed.get_mut().entries[0].mark_mut(MarkKind::Read).marked = true;
+            assert!(feed.is_dirty());
+            assert!(unwrap!(feed.save()));
+            assert!(!feed.is_dirty());
+        }
+        assert!(unwrap!(stage.read_feed("a")).entries[0].read.marked);
+
+        // written back on drop
+        {
+            let mut feed = unwrap!(stage.edit_feed("a"));
+            feed.get_mut().entries[0].mark_mut(MarkKind::Starred).marked =
+                true;
+        }
+        assert!(unwrap!(stage.read_feed("a")).entries[0].starred.marked);
+
+        {
+            let mut feed = unwrap!(stage.edit_feed("a"));
+            feed.get_mut().entries.clear();
+            feed.discard();
+        }
+        assert_eq!(unwrap!(stage.read_feed("a")).entries.len(), 1);
+
+        {
+            let mut list = unwrap!(stage.edit_subscriptions());
+            let subscription = Subscription {
+                feed_id: "a".to_owned(),
+                ..Default::default()
+            };
+            list.get_mut().subscriptions.push(subscription);
+        }
+        assert!(unwrap!(stage.read_subscriptions()).find("a").is_some());
+        {
+            let mut settings = unwrap!(stage.edit_settings("a"));
+            assert_eq!(settings.get(), &Default::default());
+            settings.get_mut().set(CRAWL_INTERVAL, Some("60"), date());
+        }
+        assert_eq!(unwrap!(stage.read_settings("a")).get(CRAWL_INTERVAL),
+                   Some("60"));
+        assert!(stage.edit_feed("b").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! Fetching icons of feeds into a `BlobStore`, e.g. to show them in
+//! subscription lists.
+//!
+//! `Stage::fetch_icon()` tries, in order:
+//!
+//! 1. the icon the feed declares, i.e. `atom:icon` or its `icon` link;
+//! 2. if the feed declares none, icons the home page of the website its
+//!    permalink is on links by `<link rel="icon">`; see
+//!    `crawler::discovery::autodiscover_icons()`;
+//! 3. and then `/favicon.ico` of the website.
+//!
+//! URLs which turned out to have no icon are remembered for
+//! `MISSING_ICON_TTL` seconds, so that they aren't fetched again on every
+//! refresh.  They're kept in a plain text document, and each line is a URL
+//! and when it was found missing in RFC 3339, separated by a tab.  Network
+//! failures aren't remembered, since they're usually transient.
+use chrono::{DateTime, Duration, FixedOffset};
+use url::Url;
+
+use blobs::{BlobStore, icon_referrer};
+use codecs::RFC3339;
+use crawler::{Backend, Request, Response, fetch};
+use crawler::discovery::autodiscover_icons;
+use feed::{Feed, LinkSliceExt};
+use repository::Repository;
+use schema::Codec;
+
+use super::{DocumentKey, Result, Stage};
+use super::journal::{escape, unescape};
+
+/// How long, in seconds, a URL found to have no icon isn't fetched again.
+pub const MISSING_ICON_TTL: i64 = 7 * 24 * 60 * 60;
+
+/// A URL found to have no icon, and when.
+type Missing = (String, DateTime<FixedOffset>);
+
+impl<R: Repository> Stage<R> {
+    /// Fetch the icon of the feed of `feed_id` into the blob store, as the
+    /// module documentation describes, and return the hash of the blob,
+    /// which is referred by the feed.  It's `None` if no icon is found.
+    pub fn fetch_icon<B, S>(&mut self, backend: &B, blobs: &mut BlobStore<S>,
+                            feed_id: &str, now: DateTime<FixedOffset>)
+                            -> Result<Option<String>>
+        where B: Backend + ?Sized, S: Repository
+    {
+        let feed = try!(self.read_feed(feed_id));
+        let stored = try!(self.read_missing_icons());
+        let ttl = Duration::seconds(MISSING_ICON_TTL);
+        let mut missing: Vec<Missing> =
+            stored.iter().filter(|m| now - m.1 < ttl).cloned().collect();
+        let result = find_icon(backend, blobs, feed_id, &feed, &mut missing,
+                               now);
+        if missing != stored {
+            try!(self.write_missing_icons(&missing));
+        }
+        result
+    }
+
+    /// Icon URLs found missing and when, the oldest first.  Malformed lines
+    /// are skipped.
+    fn read_missing_icons(&self) -> Result<Vec<Missing>> {
+        let key = self.document_key(DocumentKey::MissingIcons);
+        if !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let buf = try!(self.repository.read(&key));
+        let text = String::from_utf8_lossy(&buf);
+        Ok(text.lines().filter_map(|line| {
+            let mut fields = line.splitn(2, '\t');
+            match (fields.next(), fields.next()) {
+                (Some(url), Some(at)) => {
+                    RFC3339.decode(at).ok().map(|at| (unescape(url), at))
+                }
+                _ => None,
+            }
+        }).collect())
+    }
+
+    fn write_missing_icons(&mut self, missing: &[Missing]) -> Result<()> {
+        let mut buf = String::new();
+        for (url, at) in missing {
+            let mut at_buf = vec![];
+            RFC3339.encode(at, &mut at_buf).unwrap();
+            buf.push_str(&escape(url));
+            buf.push('\t');
+            buf.push_str(&String::from_utf8(at_buf).unwrap());
+            buf.push('\n');
+        }
+        let key = self.document_key(DocumentKey::MissingIcons);
+        try!(self.repository.write(&key, Some(buf.into_bytes())));
+        Ok(())
+    }
+}
+
+fn find_icon<B, S>(backend: &B, blobs: &mut BlobStore<S>, feed_id: &str,
+                   feed: &Feed, missing: &mut Vec<Missing>,
+                   now: DateTime<FixedOffset>) -> Result<Option<String>>
+    where B: Backend + ?Sized, S: Repository
+{
+    let mut candidates: Vec<String> = feed.icon.iter().cloned().collect();
+    candidates.extend(feed.links.favicon().map(|link| link.uri.clone()));
+    if candidates.is_empty() {
+        if let Some(home) = home_page(feed) {
+            if !is_missing(missing, &home) {
+                let response = try!(fetch(backend, &Request::new(&home[..])));
+                let found = if response.status == 200 {
+                    let html = String::from_utf8_lossy(&response.body);
+                    autodiscover_icons(&html, &response.url)
+                } else {
+                    vec![]
+                };
+                if found.is_empty() {
+                    missing.push((home.clone(), now));
+                }
+                candidates.extend(found);
+            }
+            if let Ok(favicon) = Url::parse(&home).and_then(|u| {
+                u.join("/favicon.ico")
+            }) {
+                candidates.push(favicon.to_string());
+            }
+        }
+    }
+    for url in candidates {
+        if is_missing(missing, &url) {
+            continue;
+        }
+        let response = try!(fetch(backend, &Request::new(&url[..])));
+        if !is_icon(&response) {
+            missing.push((url, now));
+            continue;
+        }
+        let hash = try!(blobs.put(&response.body));
+        try!(blobs.add_ref(&hash, &icon_referrer(feed_id)));
+        return Ok(Some(hash));
+    }
+    Ok(None)
+}
+
+/// The home page of the website the permalink of the feed is on.
+fn home_page(feed: &Feed) -> Option<String> {
+    let permalink = match feed.links.permalink() {
+        Some(link) => link,
+        None => { return None; }
+    };
+    Url::parse(&permalink.uri).and_then(|u| u.join("/")).ok()
+                              .map(|u| u.to_string())
+}
+
+fn is_missing(missing: &[Missing], url: &str) -> bool {
+    missing.iter().any(|m| m.0 == url)
+}
+
+/// Whether the response is an icon, rather than e.g. an error page served
+/// with 200.
+fn is_icon(response: &Response) -> bool {
+    let html = response.header("Content-Type").is_some_and(|t| {
+        t.trim().to_lowercase().starts_with("text/")
+    });
+    response.status == 200 && !response.body.is_empty() && !html
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use blobs::{BlobStore, icon_referrer};
+    use crawler::test::MockBackend;
+    use feed::{Feed, Link, Text};
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_fetch_icon() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let blobs_dir = temp_dir();
+        let mut blobs = BlobStore::new(unwrap!(
+            FileSystemRepository::from_path(blobs_dir.path(), true)), &["b"]);
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), now);
+        feed.links.push(Link::new("http://example.com/blog/"));
+        unwrap!(stage.write_feed("feed", &feed));
+
+        let backend = MockBackend::default()
+            .with("http://example.com/", 200, b"<html></html>")
+            .with("http://example.com/favicon.ico", 404, b"");
+        assert_eq!(unwrap!(stage.fetch_icon(&backend, &mut blobs, "feed",
+                                            now)), None);
+        // missing icons aren't fetched again, even if they're back
+        let backend = MockBackend::default()
+            .with("http://example.com/", 200,
+                  b"<link rel=icon href=/icon.png>")
+            .with("http://example.com/icon.png", 200, b"PNG");
+        let later = now + Duration::days(1);
+        assert_eq!(unwrap!(stage.fetch_icon(&backend, &mut blobs, "feed",
+                                            later)), None);
+        let later = now + Duration::days(8);
+        let hash = unwrap!(stage.fetch_icon(&backend, &mut blobs, "feed",
+                                            later)).unwrap();
+        assert_eq!(unwrap!(blobs.read(&hash)), b"PNG");
+        assert_eq!(unwrap!(blobs.refs(&hash)), [icon_referrer("feed")]);
+
+        feed.icon = Some("http://cdn.example.com/feed.ico".to_owned());
+        unwrap!(stage.write_feed("feed", &feed));
+        let backend = MockBackend::default()
+            .with("http://cdn.example.com/feed.ico", 200, b"ICO");
+        let hash = unwrap!(stage.fetch_icon(&backend, &mut blobs, "feed",
+                                            later)).unwrap();
+        assert_eq!(unwrap!(blobs.read(&hash)), b"ICO");
+    }
+}
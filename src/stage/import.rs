@@ -0,0 +1,198 @@
+//! Importing feed documents saved by other tools into the stage.
+use std::path::Path;
+
+use digest::sha1_hex;
+use job::{Phase, ProgressReporter};
+use parser;
+use parser::{ParseDiagnostics, ParseOptions, Warning};
+use repository::{FileSystemRepository, Repository};
+
+use super::{Error, Result, Stage};
+
+/// What happened to a single document by `Stage::import_repository()`.
+#[derive(Debug)]
+pub struct ImportReport {
+    /// The key of the document in the source repository.
+    pub key: Vec<String>,
+
+    /// The imported feed, or why it failed.
+    pub result: ::std::result::Result<ImportedFeed, Error>,
+}
+
+/// The feed imported from a document.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ImportedFeed {
+    /// The identifier the feed is stored as, which is the SHA-1 hexdigest
+    /// of the feed's `id`.
+    pub feed_id: String,
+
+    /// The number of entries the document contained.
+    pub entries: usize,
+
+    /// Problems found while parsing the document.
+    pub warnings: Vec<Warning>,
+}
+
+impl<R: Repository> Stage<R> {
+    /// Import all feed documents in the directory.  See also
+    /// `import_repository()`.
+    pub fn import_directory<P: AsRef<Path>>(&mut self, path: P)
+                                            -> Result<Vec<ImportReport>>
+    {
+        let source = try!(FileSystemRepository::from_path(path, false));
+        self.import_repository(&source, &mut ())
+    }
+
+    /// Walk all keys of the `source` repository, and import documents of
+    /// any format `parser::parse()` understands.  Feeds already in the
+    /// stage are merged with imported ones.  It returns the report of each
+    /// document in the order of keys; documents that aren't feeds are
+    /// reported as failures as well.  The number of processed documents is
+    /// reported to the `progress` as `Phase::Importing`.
+    pub fn import_repository<S, P>(&mut self, source: &S, progress: &mut P)
+                                   -> Result<Vec<ImportReport>>
+        where S: Repository, P: ProgressReporter + ?Sized
+    {
+        let mut keys = vec![];
+        try!(walk(source, &mut vec![], &mut keys));
+        let options = Default::default();
+        let total = Some(keys.len());
+        let mut reports = vec![];
+        progress.report(Phase::Importing, 0, total);
+        for (i, key) in keys.into_iter().enumerate() {
+            let result = self.import_document(source, &key, &options);
+            reports.push(ImportReport { key: key, result: result });
+            progress.report(Phase::Importing, i + 1, total);
+        }
+        Ok(reports)
+    }
+
+    fn import_document<S: Repository>(&mut self, source: &S, key: &[String],
+                                      options: &ParseOptions)
+                                      -> Result<ImportedFeed>
+    {
+        let buf = try!(source.read(key));
+        let diagnostics = ParseDiagnostics::new();
+        let feed = try!(parser::parse(&buf, &key.join("/"), options,
+                                      &diagnostics));
+        let imported = ImportedFeed {
+            feed_id: sha1_hex(feed.id.as_bytes()),
+            entries: feed.entries.len(),
+            warnings: diagnostics.into_warnings(),
+        };
+        let feed_key = self.feed_key(&imported.feed_id);
+        let feed = if self.repository.exists(&feed_key) {
+            let stored = try!(self.read_feed(&imported.feed_id));
+            try!(self.merge_feed(&imported.feed_id, stored, feed))
+        } else {
+            feed
+        };
+        try!(self.write_feed(&imported.feed_id, &feed));
+        Ok(imported)
+    }
+}
+
+/// Collect keys of all documents under the `key` in lexical order.
+fn walk<S: Repository>(source: &S, key: &mut Vec<String>,
+                       keys: &mut Vec<Vec<String>>) -> Result<()> {
+    let mut names: Vec<String> = match source.list(key) {
+        Ok(names) => try!(names.collect()),
+        Err(e) => {
+            if key.is_empty() {
+                return Err(From::from(e));
+            }
+            keys.push(key.clone());
+            return Ok(());
+        }
+    };
+    names.sort();
+    for name in names {
+        key.push(name);
+        try!(walk(source, key, keys));
+        key.pop();
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use std::fs::{File, create_dir};
+    use std::io::Write;
+
+    use digest::sha1_hex;
+    use feed::Text;
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    static FX_ATOM: &'static str = r#"<?xml version="1.0"?>
+        <feed xmlns="http://www.w3.org/2005/Atom"
+              xmlns:mark="http://earthreader.org/mark/">
+            <id>urn:earthreader:atom</id>
+            <title>Atom</title>
+            <updated>2013-11-06T14:36:00Z</updated>
+            <entry>
+                <id>urn:earthreader:atom:1</id>
+                <title>First</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+            </entry>
+        </feed>"#;
+
+    static FX_ATOM_NEWER: &'static str = r#"<?xml version="1.0"?>
+        <feed xmlns="http://www.w3.org/2005/Atom">
+            <id>urn:earthreader:atom</id>
+            <title>Atom (renamed)</title>
+            <updated>2013-11-07T14:36:00Z</updated>
+            <entry>
+                <id>urn:earthreader:atom:2</id>
+                <title>Second</title>
+                <updated>2013-11-07T14:36:00Z</updated>
+            </entry>
+        </feed>"#;
+
+    static FX_RSS: &'static str = r#"<rss version="2.0"><channel>
+            <title>RSS</title>
+            <item><title>A</title><guid>urn:earthreader:rss:a</guid></item>
+            <item><title>B</title><guid>urn:earthreader:rss:b</guid></item>
+        </channel></rss>"#;
+
+    fn write_file(path: &::std::path::Path, content: &str) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(content.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_stage_import_directory() {
+        let source = temp_dir();
+        write_file(&source.path().join("a.atom"), FX_ATOM);
+        create_dir(source.path().join("old")).unwrap();
+        write_file(&source.path().join("old").join("a.xml"), FX_ATOM_NEWER);
+        write_file(&source.path().join("old").join("b.rss"), FX_RSS);
+        write_file(&source.path().join("readme.txt"), "Hello");
+
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        let reports = unwrap!(stage.import_directory(source.path()));
+        assert_eq!(reports.iter().map(|r| r.key.join("/"))
+                                 .collect::<Vec<_>>(),
+                   ["a.atom", "old/a.xml", "old/b.rss", "readme.txt"]);
+        let atom_id = sha1_hex(b"urn:earthreader:atom");
+        assert_eq!(reports[0].result.as_ref().unwrap().feed_id, atom_id);
+        assert_eq!(reports[0].result.as_ref().unwrap().entries, 1);
+        assert_eq!(reports[1].result.as_ref().unwrap().feed_id, atom_id);
+        assert_eq!(reports[2].result.as_ref().unwrap().entries, 2);
+        assert!(reports[3].result.is_err());
+
+        let feed = unwrap!(stage.read_feed(&atom_id));
+        assert_eq!(feed.title, Text::plain("Atom (renamed)"));
+        let mut ids: Vec<_> = feed.entries.iter().map(|e| &e.id[..]).collect();
+        ids.sort();
+        assert_eq!(ids, ["urn:earthreader:atom:1", "urn:earthreader:atom:2"]);
+        assert_eq!(unwrap!(stage.feed_ids()).len(), 2);
+
+        assert!(stage.import_directory(source.path().join("missing")).is_err());
+    }
+}
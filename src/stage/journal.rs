@@ -0,0 +1,288 @@
+//! Append-only journal of high-level operations made through the stage.
+//!
+//! It's for support and debugging tools to reconstruct how an archive
+//! reached its current state, e.g. which crawl brought an entry, or which
+//! side won when an entry was merged.  Records are stored as lines of
+//! tab-separated fields in segments under the `journal` key, and the oldest
+//! segments are removed when there are more than `JournalPolicy` allows.
+use chrono::{DateTime, FixedOffset};
+
+use codecs::RFC3339;
+use feed::MarkKind;
+use schema::Codec;
+
+/// How the journal is split into segments.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct JournalPolicy {
+    /// The maximum number of records a segment contains.
+    pub records_per_segment: usize,
+
+    /// The maximum number of segments kept.  `None` means segments are
+    /// never removed.
+    pub max_segments: Option<usize>,
+}
+
+impl Default for JournalPolicy {
+    fn default() -> JournalPolicy {
+        JournalPolicy { records_per_segment: 1000, max_segments: Some(10) }
+    }
+}
+
+/// Which side's value was taken when an entry was merged.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MergeSide { Local, Remote }
+
+/// A high-level operation recorded to the journal.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Operation {
+    /// The feed was crawled, and `entries` entries were fetched.
+    Crawled { feed_id: String, entries: usize },
+
+    /// The `field` of the entry was taken from the `side` when it's merged.
+    Merged {
+        feed_id: String,
+        entry_id: String,
+        field: String,
+        side: MergeSide,
+    },
+
+    /// The user marked or unmarked the entry.
+    Marked {
+        feed_id: String,
+        entry_id: String,
+        kind: MarkKind,
+        marked: bool,
+    },
+
+    /// The user subscribed the feed.
+    Subscribed { feed_id: String },
+
+    /// The user unsubscribed the feed.
+    Unsubscribed { feed_id: String },
+
+    /// The user undid the last group of changes.
+    Undone,
+
+    /// The user redid the last undone group of changes.
+    Redone,
+}
+
+/// A single record of the journal.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+    pub recorded_at: DateTime<FixedOffset>,
+    pub operation: Operation,
+}
+
+impl Record {
+    /// Encode the record as a single line, without the trailing newline.
+    pub fn to_line(&self) -> String {
+        let mut buf = vec![];
+        RFC3339.encode(&self.recorded_at, &mut buf).unwrap();
+        let mut fields = vec![String::from_utf8(buf).unwrap()];
+        {
+            let mut push = |field: &str| fields.push(escape(field));
+            match self.operation {
+                Operation::Crawled { ref feed_id, entries } => {
+                    push("crawled");
+                    push(feed_id);
+                    push(&entries.to_string());
+                }
+                Operation::Merged { ref feed_id, ref entry_id, ref field,
+                                    side } => {
+                    push("merged");
+                    push(feed_id);
+                    push(entry_id);
+                    push(field);
+                    push(match side {
+                        MergeSide::Local => "local",
+                        MergeSide::Remote => "remote",
+                    });
+                }
+                Operation::Marked { ref feed_id, ref entry_id, kind,
+                                    marked } => {
+                    push("marked");
+                    push(feed_id);
+                    push(entry_id);
+                    push(match kind {
+                        MarkKind::Read => "read",
+                        MarkKind::Starred => "starred",
+                    });
+                    push(if marked { "true" } else { "false" });
+                }
+                Operation::Subscribed { ref feed_id } => {
+                    push("subscribed");
+                    push(feed_id);
+                }
+                Operation::Unsubscribed { ref feed_id } => {
+                    push("unsubscribed");
+                    push(feed_id);
+                }
+                Operation::Undone => { push("undone"); }
+                Operation::Redone => { push("redone"); }
+            }
+        }
+        fields.join("\t")
+    }
+
+    /// Decode the line encoded by `to_line()`.  It returns `None` if the
+    /// line is malformed, or written by a newer version of the library.
+    pub fn from_line(line: &str) -> Option<Record> {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        let recorded_at = match fields.first() {
+            Some(f) => match RFC3339.decode(f) {
+                Ok(dt) => dt,
+                Err(_) => { return None; }
+            },
+            None => { return None; }
+        };
+        if fields.len() < 2 {
+            return None;
+        }
+        let args = &fields[2..];
+        let operation = match (&fields[1][..], args.len()) {
+            ("crawled", 2) => Operation::Crawled {
+                feed_id: args[0].clone(),
+                entries: match args[1].parse() {
+                    Ok(n) => n,
+                    Err(_) => { return None; }
+                },
+            },
+            ("merged", 4) => Operation::Merged {
+                feed_id: args[0].clone(),
+                entry_id: args[1].clone(),
+                field: args[2].clone(),
+                side: match &args[3][..] {
+                    "local" => MergeSide::Local,
+                    "remote" => MergeSide::Remote,
+                    _ => { return None; }
+                },
+            },
+            ("marked", 4) => Operation::Marked {
+                feed_id: args[0].clone(),
+                entry_id: args[1].clone(),
+                kind: match &args[2][..] {
+                    "read" => MarkKind::Read,
+                    "starred" => MarkKind::Starred,
+                    _ => { return None; }
+                },
+                marked: match &args[3][..] {
+                    "true" => true,
+                    "false" => false,
+                    _ => { return None; }
+                },
+            },
+            ("subscribed", 1) => {
+                Operation::Subscribed { feed_id: args[0].clone() }
+            }
+            ("unsubscribed", 1) => {
+                Operation::Unsubscribed { feed_id: args[0].clone() }
+            }
+            ("undone", 0) => Operation::Undone,
+            ("redone", 0) => Operation::Redone,
+            _ => { return None; }
+        };
+        Some(Record { recorded_at: recorded_at, operation: operation })
+    }
+}
+
+/// The key name of the `index`-th segment.  They're zero-padded so that
+/// listing them in lexical order is also chronological.
+pub fn segment_name(index: u64) -> String {
+    format!("{:010}", index)
+}
+
+/// The index of the segment from its key name.
+pub fn segment_index(name: &str) -> Option<u64> {
+    if name.len() == 10 { name.parse().ok() } else { None }
+}
+
+/// Escape tabs and newlines in the field, so that it fits in a line.
+pub fn escape(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Decode the field escaped by `escape()`.
+pub fn unescape(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{MergeSide, Operation, Record, segment_index, segment_name};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::MarkKind;
+
+    #[test]
+    fn test_record_line() {
+        let recorded_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                              .and_hms(14, 36, 0);
+        let operations = vec![
+            Operation::Crawled { feed_id: "feed".to_owned(), entries: 3 },
+            Operation::Merged {
+                feed_id: "feed".to_owned(),
+                entry_id: "urn:entry:\t1\\".to_owned(),
+                field: "title".to_owned(),
+                side: MergeSide::Remote,
+            },
+            Operation::Marked {
+                feed_id: "feed".to_owned(),
+                entry_id: "urn:entry:\n2".to_owned(),
+                kind: MarkKind::Starred,
+                marked: true,
+            },
+            Operation::Subscribed { feed_id: "feed".to_owned() },
+            Operation::Unsubscribed { feed_id: "feed".to_owned() },
+            Operation::Undone,
+            Operation::Redone,
+        ];
+        for operation in operations {
+            let record = Record { recorded_at: recorded_at,
+                                  operation: operation };
+            let line = record.to_line();
+            assert!(!line.contains('\n'));
+            assert_eq!(Record::from_line(&line), Some(record));
+        }
+        assert_eq!(Record::from_line("2013-11-06T14:36:00Z\tcrawled\tfeed"),
+                   None);
+        assert_eq!(Record::from_line("yesterday\tundone"), None);
+        assert_eq!(Record::from_line(""), None);
+    }
+
+    #[test]
+    fn test_segment_name() {
+        assert_eq!(segment_name(12), "0000000012");
+        assert_eq!(segment_index("0000000012"), Some(12));
+        assert_eq!(segment_index("12"), None);
+        assert_eq!(segment_index("000000001x"), None);
+    }
+}
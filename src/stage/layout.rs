@@ -0,0 +1,380 @@
+//! Key layouts of documents in the repository.
+//!
+//! It's the single place that decides where each kind of document is
+//! stored, so that the stage, `fsck`, and tools e.g. for compatibility
+//! with libearth agree on paths.  Every document belongs to a top-level
+//! namespace of its kind:
+//!
+//! - `feeds/` has feed documents, laid out by `KeyLayout`;
+//! - `pages/` has archive pages of feeds by feed IDs and page numbers,
+//!   laid out by `KeyLayout` as well, so that pages can't be taken for
+//!   feeds e.g. a feed `foo.2` along with a feed `foo`;
+//! - `subscriptions.xml` is the subscription list;
+//! - `settings/` has subscription settings by feed IDs;
+//! - `journal/` has journal segments;
+//! - `pending-marks/` has marks set in bulk but not folded into feed
+//!   documents yet by feed IDs;
+//! - `downloads.xml` is the download queue;
+//! - `crawls/` has crawl logs by feed IDs;
+//! - `originals/` has crawled documents as they were fetched by feed IDs;
+//! - `links/` has logs of checks of permalinks by feed IDs;
+//! - `contents/` has indices of fetched out-of-line contents by feed IDs;
+//! - `missing-icons` lists icon URLs found missing;
+//! - `quarantine/` has crawled feeds which failed sanity checks by feed
+//!   IDs;
+//! - `report` is the latest statistics report of the archive;
+//! - `manifest` tells how the archive is stored;
+//! - `vocabularies/` has shared vocabularies of tags by the SHA-1 digests
+//!   of their URIs;
+//! - `virtual-feeds` lists definitions of virtual feeds.
+//!
+//! `DocumentKey` is the typed key of a document, which can be converted to
+//! and from repository keys.
+use digest::sha1_hex;
+
+use super::journal::{segment_index, segment_name};
+
+/// The key of the directory that feed documents are stored in.
+pub const FEEDS_KEY: &'static str = "feeds";
+
+/// The key of the directory that archive pages of feeds are stored in.
+pub const PAGES_KEY: &'static str = "pages";
+
+/// The key of the subscription list document.
+pub const SUBSCRIPTIONS_KEY: &'static str = "subscriptions.xml";
+
+/// The key of the directory that subscription settings are stored in.
+pub const SETTINGS_KEY: &'static str = "settings";
+
+/// The key of the directory that journal segments are stored in.
+pub const JOURNAL_KEY: &'static str = "journal";
+
+/// The key of the directory that pending marks are stored in.
+pub const PENDING_MARKS_KEY: &'static str = "pending-marks";
+
+/// The key of the download queue document.
+pub const DOWNLOADS_KEY: &'static str = "downloads.xml";
+
+/// The key of the directory that crawl logs are stored in.
+pub const CRAWLS_KEY: &'static str = "crawls";
+
+/// The key of the directory that logs of checks of permalinks are stored
+/// in.
+pub const LINKS_KEY: &'static str = "links";
+
+/// The key of the directory that indices of fetched out-of-line contents
+/// are stored in.
+pub const CONTENTS_KEY: &'static str = "contents";
+
+/// The key of the directory that original crawled documents are stored in.
+pub const ORIGINALS_KEY: &'static str = "originals";
+
+/// The key of the document of icon URLs found missing.
+pub const MISSING_ICONS_KEY: &'static str = "missing-icons";
+
+/// The key of the directory that quarantined feeds are stored in.
+pub const QUARANTINE_KEY: &'static str = "quarantine";
+
+/// The key of the statistics report document.
+pub const REPORT_KEY: &'static str = "report";
+
+/// The key of the manifest document.
+pub const MANIFEST_KEY: &'static str = "manifest";
+
+/// The key of the directory that vocabularies of tags are stored in.
+pub const VOCABULARIES_KEY: &'static str = "vocabularies";
+
+/// The key of the document of virtual feed definitions.
+pub const VIRTUAL_FEEDS_KEY: &'static str = "virtual-feeds";
+
+/// How `Stage` lays feed documents out in the repository.  Feeds split into
+/// many pages, or just many feeds, make a single directory get crowded,
+/// and listing such a directory is slow on some backends e.g. FAT, remote
+/// storages.
+///
+/// Documents written in a layout can't be read in the other layout; use
+/// `Stage::migrate_key_layout()` to change the layout of stored documents.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub enum KeyLayout {
+    /// Documents are stored right in the directory e.g. `feeds/<feed_id>`.
+    #[default]
+    Flat,
+
+    /// Documents are distributed to subdirectories named by the first two
+    /// hexadecimal digits of the SHA-1 digests of their names e.g.
+    /// `feeds/3f/<feed_id>`, so that each directory has about 1/256 of
+    /// documents.
+    Sharded,
+}
+
+impl KeyLayout {
+    /// The key of the document `name` in the `directory`.
+    pub fn key(&self, directory: &str, name: &str) -> Vec<String> {
+        match *self {
+            KeyLayout::Flat => vec![directory.to_owned(), name.to_owned()],
+            KeyLayout::Sharded => {
+                vec![directory.to_owned(), shard_name(name), name.to_owned()]
+            }
+        }
+    }
+}
+
+/// A document the stage stores, which knows its key.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DocumentKey {
+    /// The feed document of the feed ID; see `Stage::read_feed()`.
+    Feed(String),
+
+    /// The archive page of the feed ID and the page number, which starts
+    /// from 2 since the feed document is the first page.  Pages link each
+    /// other by names like `<feed_id>.<number>`; see `Stage::read_feed()`.
+    FeedPage(String, usize),
+
+    Subscriptions,
+
+    /// Subscription settings of the feed ID.
+    Settings(String),
+
+    /// The journal segment of the index.
+    JournalSegment(u64),
+
+    /// Marks of entries of the feed ID set by `Stage::set_marks()` which
+    /// aren't folded into the feed document yet; see the `marks` module.
+    PendingMarks(String),
+
+    Downloads,
+
+    /// The crawl log of the feed ID; see `Stage::refresh()`.
+    CrawlLog(String),
+
+    /// The crawled document of the feed ID and the name as it was
+    /// fetched; see the `originals` module.
+    Original(String, String),
+
+    /// The log of checks of permalinks of entries of the feed ID; see
+    /// `Stage::check_starred_links()`.
+    LinkLog(String),
+
+    /// The index of fetched out-of-line contents of entries of the feed ID;
+    /// see `Stage::resolve_contents()`.
+    Contents(String),
+
+    /// Icon URLs found missing; see `Stage::fetch_icon()`.
+    MissingIcons,
+
+    /// The crawled feed of the feed ID which failed sanity checks; see the
+    /// `sanity` module.
+    Quarantined(String),
+
+    /// The statistics report of the archive; see `Stage::latest_report()`.
+    Report,
+
+    /// The manifest of the archive; see the `manifest` module.
+    Manifest,
+
+    /// The category document of the name, i.e. the SHA-1 digest of its
+    /// URI; see `Stage::read_vocabulary()`.
+    Vocabulary(String),
+
+    /// Definitions of virtual feeds; see the `virtual_feeds` module.
+    VirtualFeeds,
+}
+
+impl DocumentKey {
+    /// The repository key of the document.  Only feed documents and their
+    /// pages depend on the `layout`.
+    pub fn key(&self, layout: KeyLayout) -> Vec<String> {
+        match *self {
+            DocumentKey::Feed(ref name) => layout.key(FEEDS_KEY, name),
+            DocumentKey::FeedPage(ref feed_id, number) => {
+                let mut key = layout.key(PAGES_KEY, feed_id);
+                key.push(number.to_string());
+                key
+            }
+            DocumentKey::Subscriptions => vec![SUBSCRIPTIONS_KEY.to_owned()],
+            DocumentKey::Settings(ref feed_id) => {
+                vec![SETTINGS_KEY.to_owned(), feed_id.clone()]
+            }
+            DocumentKey::JournalSegment(index) => {
+                vec![JOURNAL_KEY.to_owned(), segment_name(index)]
+            }
+            DocumentKey::PendingMarks(ref feed_id) => {
+                vec![PENDING_MARKS_KEY.to_owned(), feed_id.clone()]
+            }
+            DocumentKey::Downloads => vec![DOWNLOADS_KEY.to_owned()],
+            DocumentKey::CrawlLog(ref feed_id) => {
+                vec![CRAWLS_KEY.to_owned(), feed_id.clone()]
+            }
+            DocumentKey::Original(ref feed_id, ref name) => {
+                vec![ORIGINALS_KEY.to_owned(), feed_id.clone(), name.clone()]
+            }
+            DocumentKey::LinkLog(ref feed_id) => {
+                vec![LINKS_KEY.to_owned(), feed_id.clone()]
+            }
+            DocumentKey::Contents(ref feed_id) => {
+                vec![CONTENTS_KEY.to_owned(), feed_id.clone()]
+            }
+            DocumentKey::MissingIcons => vec![MISSING_ICONS_KEY.to_owned()],
+            DocumentKey::Quarantined(ref feed_id) => {
+                vec![QUARANTINE_KEY.to_owned(), feed_id.clone()]
+            }
+            DocumentKey::Report => vec![REPORT_KEY.to_owned()],
+            DocumentKey::Manifest => vec![MANIFEST_KEY.to_owned()],
+            DocumentKey::Vocabulary(ref name) => {
+                vec![VOCABULARIES_KEY.to_owned(), name.clone()]
+            }
+            DocumentKey::VirtualFeeds => vec![VIRTUAL_FEEDS_KEY.to_owned()],
+        }
+    }
+
+    /// Tell which document the repository key is of, along with the key
+    /// layout it's in if it's a feed document or a page.  It's `None` if the
+    /// key isn't of any document the stage stores.
+    pub fn parse<T: AsRef<str>>(key: &[T])
+                                -> Option<(DocumentKey, Option<KeyLayout>)> {
+        let key: Vec<&str> = key.iter().map(|k| k.as_ref()).collect();
+        let (head, rest) = match key.split_first() {
+            Some((head, rest)) => (*head, rest),
+            None => { return None; }
+        };
+        match (head, rest.len()) {
+            (FEEDS_KEY, 1) if !is_shard_name(rest[0]) => {
+                Some((DocumentKey::Feed(rest[0].to_owned()),
+                      Some(KeyLayout::Flat)))
+            }
+            (FEEDS_KEY, 2) if shard_name(rest[1]) == rest[0] => {
+                Some((DocumentKey::Feed(rest[1].to_owned()),
+                      Some(KeyLayout::Sharded)))
+            }
+            (PAGES_KEY, 2) if !is_shard_name(rest[0]) => {
+                page_number(rest[1]).map(|n| {
+                    (DocumentKey::FeedPage(rest[0].to_owned(), n),
+                     Some(KeyLayout::Flat))
+                })
+            }
+            (PAGES_KEY, 3) if shard_name(rest[1]) == rest[0] => {
+                page_number(rest[2]).map(|n| {
+                    (DocumentKey::FeedPage(rest[1].to_owned(), n),
+                     Some(KeyLayout::Sharded))
+                })
+            }
+            (SUBSCRIPTIONS_KEY, 0) => Some((DocumentKey::Subscriptions, None)),
+            (SETTINGS_KEY, 1) => {
+                Some((DocumentKey::Settings(rest[0].to_owned()), None))
+            }
+            (JOURNAL_KEY, 1) => segment_index(rest[0]).map(|index| {
+                (DocumentKey::JournalSegment(index), None)
+            }),
+            (PENDING_MARKS_KEY, 1) => {
+                Some((DocumentKey::PendingMarks(rest[0].to_owned()), None))
+            }
+            (DOWNLOADS_KEY, 0) => Some((DocumentKey::Downloads, None)),
+            (CRAWLS_KEY, 1) => {
+                Some((DocumentKey::CrawlLog(rest[0].to_owned()), None))
+            }
+            (ORIGINALS_KEY, 2) => {
+                Some((DocumentKey::Original(rest[0].to_owned(),
+                                            rest[1].to_owned()), None))
+            }
+            (LINKS_KEY, 1) => {
+                Some((DocumentKey::LinkLog(rest[0].to_owned()), None))
+            }
+            (CONTENTS_KEY, 1) => {
+                Some((DocumentKey::Contents(rest[0].to_owned()), None))
+            }
+            (MISSING_ICONS_KEY, 0) => Some((DocumentKey::MissingIcons, None)),
+            (QUARANTINE_KEY, 1) => {
+                Some((DocumentKey::Quarantined(rest[0].to_owned()), None))
+            }
+            (REPORT_KEY, 0) => Some((DocumentKey::Report, None)),
+            (MANIFEST_KEY, 0) => Some((DocumentKey::Manifest, None)),
+            (VOCABULARIES_KEY, 1) => {
+                Some((DocumentKey::Vocabulary(rest[0].to_owned()), None))
+            }
+            (VIRTUAL_FEEDS_KEY, 0) => Some((DocumentKey::VirtualFeeds, None)),
+            _ => None,
+        }
+    }
+}
+
+/// The page number of the name of a page document, which is from 2.
+fn page_number(name: &str) -> Option<usize> {
+    match name.parse::<usize>() {
+        Ok(n) if n > 1 && n.to_string() == name => Some(n),
+        _ => None,
+    }
+}
+
+/// The name of the subdirectory the document `name` belongs to in the
+/// `Sharded` layout.
+pub fn shard_name(name: &str) -> String {
+    sha1_hex(name.as_bytes())[..2].to_owned()
+}
+
+/// Whether the name is of a subdirectory in the `Sharded` layout.
+pub fn is_shard_name(name: &str) -> bool {
+    name.len() == 2 &&
+        name.chars().all(|c| c.is_ascii_hexdigit() && !c.is_uppercase())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{DocumentKey, KeyLayout, is_shard_name, shard_name};
+
+    #[test]
+    fn test_key_layout() {
+        assert_eq!(KeyLayout::Flat.key("feeds", "feed"), ["feeds", "feed"]);
+        let shard = shard_name("feed");
+        assert!(is_shard_name(&shard));
+        assert_eq!(KeyLayout::Sharded.key("feeds", "feed"),
+                   ["feeds", &shard[..], "feed"]);
+        assert_eq!(shard_name("feed"), shard);
+        assert!(!is_shard_name("feed"));
+        assert!(!is_shard_name("3F"));
+    }
+
+    #[test]
+    fn test_document_key() {
+        let keys = [DocumentKey::Feed("feed".to_owned()),
+                    DocumentKey::FeedPage("feed".to_owned(), 2),
+                    DocumentKey::Subscriptions,
+                    DocumentKey::Settings("feed".to_owned()),
+                    DocumentKey::JournalSegment(3),
+                    DocumentKey::PendingMarks("feed".to_owned()),
+                    DocumentKey::Downloads,
+                    DocumentKey::CrawlLog("feed".to_owned()),
+                    DocumentKey::Original("feed".to_owned(),
+                                          "0000000001".to_owned()),
+                    DocumentKey::LinkLog("feed".to_owned()),
+                    DocumentKey::Contents("feed".to_owned()),
+                    DocumentKey::MissingIcons,
+                    DocumentKey::Quarantined("feed".to_owned()),
+                    DocumentKey::Report,
+                    DocumentKey::Manifest,
+                    DocumentKey::Vocabulary("name".to_owned()),
+                    DocumentKey::VirtualFeeds];
+        for key in keys.iter() {
+            for &layout in &[KeyLayout::Flat, KeyLayout::Sharded] {
+                let parsed = DocumentKey::parse(&key.key(layout)).unwrap();
+                assert_eq!(parsed.0, *key);
+                match *key {
+                    DocumentKey::Feed(_) | DocumentKey::FeedPage(..) => {
+                        assert_eq!(parsed.1, Some(layout));
+                    }
+                    _ => { }
+                }
+            }
+        }
+        assert_eq!(DocumentKey::JournalSegment(3).key(KeyLayout::Flat),
+                   ["journal", "0000000003"]);
+        assert_eq!(DocumentKey::parse(&["feeds", "00", "feed"]), None);
+        assert_eq!(DocumentKey::parse(&["journal", "3"]), None);
+        assert_eq!(DocumentKey::FeedPage("feed.2".to_owned(), 3)
+                               .key(KeyLayout::Flat),
+                   ["pages", "feed.2", "3"]);
+        assert_eq!(DocumentKey::parse(&["pages", "feed", "1"]), None);
+        assert_eq!(DocumentKey::parse(&["pages", "feed", "02"]), None);
+        assert_eq!(DocumentKey::parse(&["other.xml"]), None);
+    }
+}
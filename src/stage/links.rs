@@ -0,0 +1,404 @@
+//! Detecting dead links of starred entries, for the read-it-later use of
+//! stars: pages users saved for later often disappear before they're read.
+//!
+//! `Stage::check_starred_links()` checks permalinks of starred entries by
+//! HEAD requests in background jobs, at most as many at the same time as
+//! `LinkCheckPolicy::max_concurrent` allows.  Servers which fail HEAD are
+//! asked again by a GET of the first `crawler::liveness::PROBE_BODY_LIMIT`
+//! bytes, like `crawler::check_liveness()` does.  If
+//! `LinkCheckPolicy::snapshot` is set, pages found alive are fetched in
+//! full into a `BlobStore`, referred by their entries, so that they're
+//! kept after they disappear.  A page is snapshotted only once, as long as
+//! the link log remembers it.
+//!
+//! Checks are appended to link logs of feeds, which are plain text like
+//! crawl logs (see the `refresh` module).  Each line is a check of
+//! tab-separated fields, the latest last:
+//!
+//! 1. when it was checked in RFC 3339;
+//! 2. the ID of the entry;
+//! 3. the permalink;
+//! 4. the HTTP status of the response, or empty if it failed;
+//! 5. the hash of the snapshot blob, or empty;
+//! 6. the error message, or empty.
+//!
+//! Only the last `LINK_LOG_SIZE` checks are kept.
+//!
+//! Checks aren't recorded in crawl logs, though they're alike: crawl logs
+//! keep only the last `refresh::CRAWL_LOG_SIZE` attempts, and validators
+//! of the last successful crawl are taken from them, so checks of many
+//! starred entries would push crawls out and make every refresh download
+//! unchanged feeds again.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+
+use blobs::{BlobStore, entry_referrer};
+use codecs::RFC3339;
+use crawler::{Backend, Method, Request, fetch_with_retries};
+use crawler::liveness::PROBE_BODY_LIMIT;
+use feed::LinkSliceExt;
+use job::{self, CancellationToken, Phase, ProgressReporter};
+use repository::Repository;
+use schema::Codec;
+
+use super::{DocumentKey, Error, Result, Stage, now};
+use super::journal::{escape, unescape};
+
+/// The number of checks a link log keeps.
+pub const LINK_LOG_SIZE: usize = 200;
+
+/// How permalinks are checked.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LinkCheckPolicy {
+    /// The maximum number of requests made at the same time.
+    pub max_concurrent: usize,
+
+    /// Whether to snapshot pages found alive.
+    pub snapshot: bool,
+}
+
+impl Default for LinkCheckPolicy {
+    fn default() -> LinkCheckPolicy {
+        LinkCheckPolicy { max_concurrent: 4, snapshot: false }
+    }
+}
+
+/// A check of the permalink of an entry in the link log.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinkCheck {
+    pub checked_at: DateTime<FixedOffset>,
+    pub entry_id: String,
+    pub url: String,
+
+    /// The HTTP status of the response, if the server responded.
+    pub status: Option<u16>,
+
+    /// The hash of the blob the page was snapshotted to, if it was by the
+    /// check.
+    pub snapshot: Option<String>,
+
+    /// The message of the error, if the request failed.
+    pub error: Option<String>,
+}
+
+impl LinkCheck {
+    /// Whether the page is gone, i.e. the server responded with 404 or 410.
+    /// Other failures, e.g. 5xx or network errors, are usually transient.
+    pub fn is_dead(&self) -> bool {
+        self.status == Some(404) || self.status == Some(410)
+    }
+
+    /// Whether the page was there, i.e. the status was 2xx.
+    pub fn is_alive(&self) -> bool {
+        self.status.is_some_and(|s| (200..300).contains(&s))
+    }
+
+    /// Encode the check as a single line, without the trailing newline.
+    pub fn to_line(&self) -> String {
+        let mut buf = vec![];
+        RFC3339.encode(&self.checked_at, &mut buf).unwrap();
+        let optional = |v: &Option<String>| {
+            v.as_ref().map_or(String::new(), |v| escape(v))
+        };
+        let fields = [String::from_utf8(buf).unwrap(),
+                      escape(&self.entry_id),
+                      escape(&self.url),
+                      self.status.map_or(String::new(), |s| s.to_string()),
+                      optional(&self.snapshot),
+                      optional(&self.error)];
+        fields.join("\t")
+    }
+
+    /// Decode the line encoded by `to_line()`.  It's `None` if the line is
+    /// malformed.
+    pub fn from_line(line: &str) -> Option<LinkCheck> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        let optional = |v: &str| {
+            if v.is_empty() { None } else { Some(unescape(v)) }
+        };
+        Some(LinkCheck {
+            checked_at: match RFC3339.decode(fields[0]) {
+                Ok(t) => t,
+                Err(_) => { return None; }
+            },
+            entry_id: unescape(fields[1]),
+            url: unescape(fields[2]),
+            status: if fields[3].is_empty() {
+                None
+            } else {
+                match fields[3].parse() {
+                    Ok(s) => Some(s),
+                    Err(_) => { return None; }
+                }
+            },
+            snapshot: optional(fields[4]),
+            error: optional(fields[5]),
+        })
+    }
+}
+
+/// A permalink to check, as a feed ID, an entry ID, the URL, and whether
+/// to snapshot the page.
+type Target = (String, String, String, bool);
+
+/// What a job of a check found: the status or the error, and the page if
+/// it was snapshotted.
+type Found = (::std::result::Result<u16, String>, Option<Vec<u8>>);
+
+impl<R: Repository> Stage<R> {
+    /// Check permalinks of starred entries of every feed, as the module
+    /// documentation describes, and return the checks in the order of
+    /// feeds and their entries.  It's checked whether `cancel` is cancelled
+    /// before each batch of `max_concurrent` requests, and it fails with
+    /// `Error::Cancelled` if it is; checks made until then are logged.  The
+    /// number of checked links is reported to the `progress` as
+    /// `Phase::CheckingLinks`.
+    pub fn check_starred_links<B, S, P>(&mut self, backend: Arc<B>,
+                                        blobs: &mut BlobStore<S>,
+                                        policy: &LinkCheckPolicy,
+                                        cancel: &CancellationToken,
+                                        progress: &mut P)
+                                        -> Result<Vec<LinkCheck>>
+        where B: Backend + Send + Sync + 'static, S: Repository,
+              P: ProgressReporter + ?Sized
+    {
+        let mut targets: Vec<Target> = vec![];
+        for feed_id in try!(self.feed_ids()) {
+            let feed = try!(self.read_feed(&feed_id));
+            let log = try!(self.read_link_log(&feed_id));
+            for entry in feed.entries.iter().filter(|e| e.starred.marked) {
+                let url = match entry.links.permalink() {
+                    Some(link) => link.uri.clone(),
+                    None => { continue; }
+                };
+                let snapshotted = log.iter().any(|c| {
+                    c.entry_id == entry.id && c.snapshot.is_some()
+                });
+                targets.push((feed_id.clone(), entry.id.clone(), url,
+                              policy.snapshot && !snapshotted));
+            }
+        }
+        let total = Some(targets.len());
+        let mut checks = vec![];
+        progress.report(Phase::CheckingLinks, 0, total);
+        let batch_size = if policy.max_concurrent > 0 {
+            policy.max_concurrent
+        } else {
+            1
+        };
+        for batch in targets.chunks(batch_size) {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let handles: Vec<_> = batch.iter().map(|target| {
+                let backend = backend.clone();
+                let url = target.2.clone();
+                let snapshot = target.3;
+                job::spawn(move || check_link(&*backend, &url, snapshot))
+            }).collect();
+            let mut logs: BTreeMap<&str, Vec<LinkCheck>> = BTreeMap::new();
+            for (target, handle) in batch.iter().zip(handles) {
+                let (found, page) = handle.join();
+                let mut check = LinkCheck {
+                    checked_at: now(),
+                    entry_id: target.1.clone(),
+                    url: target.2.clone(),
+                    status: found.as_ref().ok().cloned(),
+                    snapshot: None,
+                    error: found.err(),
+                };
+                if let Some(page) = page {
+                    let hash = try!(blobs.put(&page));
+                    try!(blobs.add_ref(&hash,
+                                       &entry_referrer(&target.0,
+                                                       &target.1)));
+                    check.snapshot = Some(hash);
+                }
+                logs.entry(&target.0[..]).or_default()
+                    .push(check.clone());
+                checks.push(check);
+                progress.report(Phase::CheckingLinks, checks.len(), total);
+            }
+            for (feed_id, new_checks) in logs {
+                let mut log = try!(self.read_link_log(feed_id));
+                log.extend(new_checks);
+                if log.len() > LINK_LOG_SIZE {
+                    let excess = log.len() - LINK_LOG_SIZE;
+                    log.drain(..excess);
+                }
+                try!(self.write_link_log(feed_id, &log));
+            }
+        }
+        Ok(checks)
+    }
+
+    /// Read the link log of `feed_id`, the oldest check first.  It's empty
+    /// if no links of the feed were checked.  Malformed lines are skipped.
+    pub fn read_link_log(&self, feed_id: &str) -> Result<Vec<LinkCheck>> {
+        let key = self.document_key(DocumentKey::LinkLog(feed_id.to_owned()));
+        if !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let buf = try!(self.repository.read(&key));
+        let text = String::from_utf8_lossy(&buf);
+        Ok(text.lines().filter_map(LinkCheck::from_line).collect())
+    }
+
+    fn write_link_log(&mut self, feed_id: &str, log: &[LinkCheck])
+                      -> Result<()> {
+        let mut buf = String::new();
+        for check in log {
+            buf.push_str(&check.to_line());
+            buf.push('\n');
+        }
+        let key = self.document_key(DocumentKey::LinkLog(feed_id.to_owned()));
+        try!(self.repository.write(&key, Some(buf.into_bytes())));
+        Ok(())
+    }
+}
+
+/// Check the link by HEAD, falling back to GET of the first bytes if the
+/// server fails it, and fetch the page in full if it's alive and to be
+/// snapshotted.
+fn check_link<B: Backend + ?Sized>(backend: &B, url: &str, snapshot: bool)
+                                   -> Found {
+    let mut request = Request::new(url);
+    request.method = Method::Head;
+    request.body_limit = Some(0);
+    let mut response = fetch_with_retries(backend, &request);
+    if response.as_ref().map(|r| r.status >= 400).unwrap_or(true) {
+        request.method = Method::Get;
+        request.body_limit = Some(PROBE_BODY_LIMIT);
+        response = fetch_with_retries(backend, &request);
+    }
+    let status = match response {
+        Ok(response) => response.status,
+        Err(err) => { return (Err(err.to_string()), None); }
+    };
+    if !snapshot || !(200..300).contains(&status) {
+        return (Ok(status), None);
+    }
+    request.method = Method::Get;
+    request.body_limit = None;
+    match fetch_with_retries(backend, &request) {
+        Ok(ref page) if page.status >= 200 && page.status < 300 => {
+            (Ok(page.status), Some(page.body.clone()))
+        }
+        Ok(page) => (Ok(page.status), None),
+        Err(err) => (Err(err.to_string()), None),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+    use super::{LinkCheck, LinkCheckPolicy};
+
+    use std::sync::Arc;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use blobs::{BlobStore, entry_referrer};
+    use crawler::test::MockBackend;
+    use feed::{Entry, Feed, Link, Text};
+    use job::{CancellationToken, Phase};
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    fn fx_feed() -> Feed {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        for (i, path) in ["alive", "gone", "unstarred", "moved"].iter()
+                                                                .enumerate() {
+            let mut entry = Entry::new(format!("urn:entry:{}", i),
+                                       Text::plain("Entry"), t);
+            entry.links.push(Link::new(format!("http://example.com/{}",
+                                               path)));
+            entry.starred.marked = *path != "unstarred";
+            feed.entries.push(entry);
+        }
+        feed
+    }
+
+    #[test]
+    fn test_check_starred_links() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let mut blobs = BlobStore::new(
+            unwrap!(FileSystemRepository::from_path(tmpdir.path(), true)),
+            &["blobs"]);
+        unwrap!(stage.write_feed("feed", &fx_feed()));
+        let mut backend = MockBackend::default()
+            .with("http://example.com/alive", 200, b"<p>Saved</p>")
+            .with("http://example.com/gone", 410, b"")
+            .with("http://example.com/unstarred", 404, b"");
+        backend.head_status = Some(405);
+        let backend = Arc::new(backend);
+        let policy = LinkCheckPolicy { max_concurrent: 2, snapshot: true };
+
+        let mut reported = vec![];
+        let checks = unwrap!(stage.check_starred_links(
+            backend.clone(), &mut blobs, &policy, &CancellationToken::new(),
+            &mut |phase, done, total| {
+                assert_eq!(phase, Phase::CheckingLinks);
+                reported.push((done, total));
+            }));
+        assert_eq!(reported, [(0, Some(3)), (1, Some(3)), (2, Some(3)),
+                              (3, Some(3))]);
+        assert_eq!(checks.len(), 3);
+        assert!(checks[0].is_alive());
+        let hash = checks[0].snapshot.clone().unwrap();
+        assert_eq!(unwrap!(blobs.read(&hash)), b"<p>Saved</p>");
+        assert_eq!(unwrap!(blobs.refs(&hash)),
+                   [entry_referrer("feed", "urn:entry:0")]);
+        assert!(checks[1].is_dead());
+        assert_eq!(checks[1].snapshot, None);
+        assert_eq!(checks[2].url, "http://example.com/moved");
+        assert_eq!(checks[2].status, None);
+        assert!(checks[2].error.is_some());
+        assert!(!checks[2].is_dead());
+        // times are logged in microseconds
+        let lines = |checks: &[LinkCheck]| -> Vec<String> {
+            checks.iter().map(LinkCheck::to_line).collect()
+        };
+        assert_eq!(lines(&unwrap!(stage.read_link_log("feed"))),
+                   lines(&checks));
+
+        // pages are snapshotted only once
+        let checks = unwrap!(stage.check_starred_links(
+            backend.clone(), &mut blobs, &policy, &CancellationToken::new(),
+            &mut ()));
+        assert!(checks[0].is_alive());
+        assert_eq!(checks[0].snapshot, None);
+        assert_eq!(unwrap!(stage.read_link_log("feed")).len(), 6);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(stage.check_starred_links(backend, &mut blobs, &policy,
+                                          &cancel, &mut ()).is_err());
+        assert_eq!(unwrap!(stage.read_link_log("feed")).len(), 6);
+    }
+
+    #[test]
+    fn test_link_check_line() {
+        let check = LinkCheck {
+            checked_at: FixedOffset::east(0).ymd(2013, 11, 6)
+                                            .and_hms(14, 36, 0),
+            entry_id: "urn:entry\t1".to_owned(),
+            url: "http://example.com/".to_owned(),
+            status: Some(404),
+            snapshot: None,
+            error: None,
+        };
+        assert_eq!(LinkCheck::from_line(&check.to_line()), Some(check));
+        assert_eq!(LinkCheck::from_line("malformed"), None);
+    }
+}
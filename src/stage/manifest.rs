@@ -0,0 +1,236 @@
+//! The manifest of the archive, which tells how the archive is stored, so
+//! that apps don't misread archives written by other versions or builds of
+//! the crate.
+//!
+//! `Stage::open()` checks the manifest of the repository, and writes one if
+//! there's none yet.  The manifest is a plain text document, and each line
+//! is tab-separated fields whose first one tells its kind:
+//!
+//! - `format`, and the version of the manifest and the key layouts it
+//!   describes, i.e. `MANIFEST_VERSION` of the crate which wrote it;
+//! - `crate`, and the version of the crate which wrote it;
+//! - `layout`, and the `KeyLayout` of feed documents, `flat` or `sharded`;
+//! - `feature`, and the name of a feature the archive uses, e.g.
+//!   `sharding`.  Archives using features the build doesn't support can't
+//!   be opened;
+//! - `compat`, and the name of a compatibility flag.  Unlike features,
+//!   unknown flags are kept as they are and ignored, so they're for hints
+//!   older builds can safely miss.
+//!
+//! Other lines are ignored.
+use std::collections::BTreeSet;
+
+use repository::Repository;
+
+use super::{DocumentKey, Error, KeyLayout, Result, Stage};
+
+/// The version of the manifest and the key layouts this build writes.
+/// Archives of later versions can't be opened.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Features of archives this build supports.  Features reserved for later,
+/// e.g. `compression` and `encryption` of documents, aren't supported yet.
+pub const SUPPORTED_FEATURES: &'static [&'static str] = &["sharding"];
+
+/// The feature archives in the `Sharded` key layout use.
+const SHARDING_FEATURE: &'static str = "sharding";
+
+/// How the archive is stored.  See the module documentation.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Manifest {
+    pub format: u32,
+
+    /// The version of the crate which wrote the manifest.
+    pub crate_version: String,
+
+    pub key_layout: KeyLayout,
+    pub features: BTreeSet<String>,
+    pub compat: BTreeSet<String>,
+}
+
+impl Manifest {
+    /// The manifest this build writes for the archive in the `layout`.
+    pub fn new(layout: KeyLayout) -> Manifest {
+        let mut features = BTreeSet::new();
+        if layout == KeyLayout::Sharded {
+            features.insert(SHARDING_FEATURE.to_owned());
+        }
+        Manifest {
+            format: MANIFEST_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+            key_layout: layout,
+            features: features,
+            compat: BTreeSet::new(),
+        }
+    }
+
+    /// Check whether this build can open the archive.  It fails with
+    /// `Error::Incompatible` if it can't.
+    pub fn check(&self) -> Result<()> {
+        if self.format > MANIFEST_VERSION {
+            return Err(Error::Incompatible(format!(
+                "archive format {} is newer than the supported format {}",
+                self.format, MANIFEST_VERSION
+            )));
+        }
+        for feature in self.features.iter() {
+            if !SUPPORTED_FEATURES.contains(&&feature[..]) {
+                return Err(Error::Incompatible(format!(
+                    "archive uses {} but the feature isn't enabled", feature
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn to_text(&self) -> String {
+        let layout = match self.key_layout {
+            KeyLayout::Flat => "flat",
+            KeyLayout::Sharded => "sharded",
+        };
+        let mut text = format!("format\t{}\ncrate\t{}\nlayout\t{}\n",
+                               self.format, self.crate_version, layout);
+        for feature in self.features.iter() {
+            text.push_str(&format!("feature\t{}\n", feature));
+        }
+        for flag in self.compat.iter() {
+            text.push_str(&format!("compat\t{}\n", flag));
+        }
+        text
+    }
+
+    /// Parse the manifest.  It fails with `Error::Incompatible` if it
+    /// doesn't tell its format or the key layout.
+    fn from_text(text: &str) -> Result<Manifest> {
+        let mut format = None;
+        let mut crate_version = String::new();
+        let mut key_layout = None;
+        let mut features = BTreeSet::new();
+        let mut compat = BTreeSet::new();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match (fields[0], fields.len()) {
+                ("format", 2) => { format = fields[1].parse().ok(); }
+                ("crate", 2) => { crate_version = fields[1].to_owned(); }
+                ("layout", 2) => {
+                    key_layout = match fields[1] {
+                        "flat" => Some(KeyLayout::Flat),
+                        "sharded" => Some(KeyLayout::Sharded),
+                        _ => {
+                            return Err(Error::Incompatible(format!(
+                                "archive uses unknown layout {}", fields[1]
+                            )));
+                        }
+                    };
+                }
+                ("feature", 2) => { features.insert(fields[1].to_owned()); }
+                ("compat", 2) => { compat.insert(fields[1].to_owned()); }
+                _ => { }
+            }
+        }
+        match (format, key_layout) {
+            (Some(format), Some(key_layout)) => Ok(Manifest {
+                format: format,
+                crate_version: crate_version,
+                key_layout: key_layout,
+                features: features,
+                compat: compat,
+            }),
+            _ => Err(Error::Incompatible("malformed manifest".to_owned())),
+        }
+    }
+}
+
+impl<R: Repository> Stage<R> {
+    /// Open the archive stored in the `repository`.  If it has the
+    /// manifest, the manifest is checked, and `key_layout` is set to the
+    /// layout it tells; otherwise the manifest is written.  Like other
+    /// writes, it's buffered until the stage is flushed.
+    ///
+    /// It fails with `Error::Incompatible` if the archive can't be opened by
+    /// this build, e.g. it uses features the build doesn't support.
+    pub fn open(repository: R) -> Result<Stage<R>> {
+        let mut stage = Stage::new(repository);
+        match try!(stage.read_manifest()) {
+            Some(manifest) => {
+                try!(manifest.check());
+                stage.key_layout = manifest.key_layout;
+            }
+            None => { try!(stage.write_manifest()); }
+        }
+        Ok(stage)
+    }
+
+    /// The manifest of the archive, if any.
+    pub fn read_manifest(&self) -> Result<Option<Manifest>> {
+        let key = self.document_key(DocumentKey::Manifest);
+        if !self.repository.exists(&key) {
+            return Ok(None);
+        }
+        let buf = try!(self.repository.read(&key));
+        Manifest::from_text(&String::from_utf8_lossy(&buf)).map(Some)
+    }
+
+    /// Write the manifest of the archive as this build stores it, e.g.
+    /// after `migrate_key_layout()`.  Compatibility flags of the previous
+    /// manifest are kept.
+    pub fn write_manifest(&mut self) -> Result<Manifest> {
+        let mut manifest = Manifest::new(self.key_layout);
+        if let Some(previous) = try!(self.read_manifest()) {
+            manifest.compat = previous.compat;
+        }
+        let key = self.document_key(DocumentKey::Manifest);
+        try!(self.repository.write(&key,
+                                   Some(manifest.to_text().into_bytes())));
+        Ok(manifest)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{MANIFEST_VERSION, Manifest};
+    use super::super::{Error, KeyLayout, Stage};
+
+    use repository::{FileSystemRepository, Repository};
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_manifest() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = unwrap!(Stage::open(repo.clone()));
+        assert_eq!(unwrap!(stage.read_manifest()),
+                   Some(Manifest::new(KeyLayout::Flat)));
+        unwrap!(stage.migrate_key_layout(KeyLayout::Sharded));
+        unwrap!(stage.flush());
+
+        let stage = unwrap!(Stage::open(repo.clone()));
+        assert_eq!(stage.key_layout, KeyLayout::Sharded);
+        let manifest = unwrap!(stage.read_manifest()).unwrap();
+        assert_eq!(manifest.format, MANIFEST_VERSION);
+        assert!(manifest.features.contains("sharding"));
+
+        let mut repo = repo;
+        unwrap!(repo.write(&["manifest"], [
+            "format\t1\ncrate\t9.9.9\nlayout\tflat\nfeature\tencryption\n"
+        ]));
+        assert_err!(Stage::open(repo.clone()), Error::Incompatible(m) => {
+            assert_eq!(m, "archive uses encryption but the feature isn't \
+                           enabled");
+        });
+        unwrap!(repo.write(&["manifest"], [
+            "format\t2\nlayout\tflat\n"
+        ]));
+        assert_err!(Stage::open(repo.clone()), Error::Incompatible(_) => { });
+        unwrap!(repo.write(&["manifest"], [
+            "format\t1\nlayout\tflat\ncompat\tfuture-hint\nunknown\tline\n"
+        ]));
+        let mut stage = unwrap!(Stage::open(repo.clone()));
+        let manifest = unwrap!(stage.write_manifest());
+        assert!(manifest.compat.contains("future-hint"));
+        unwrap!(repo.write(&["manifest"], ["layout\tflat\n"]));
+        assert_err!(Stage::open(repo), Error::Incompatible(_) => { });
+    }
+}
@@ -0,0 +1,368 @@
+//! Exporting and importing only marks, i.e. which entries are read or
+//! starred, to carry them to another device without whole feeds.
+//!
+//! The format is plain text.  The first line is the header `MARKS_HEADER`,
+//! and each following line is a mark of tab-separated fields:
+//!
+//! 1. the feed ID, i.e. the key the feed is stored as in the stage;
+//! 2. the entry ID;
+//! 3. the kind of the mark, `read` or `starred`;
+//! 4. whether it's marked, `true` or `false`;
+//! 5. when it was updated in RFC 3339, or empty if unknown.
+//!
+//! Tabs and newlines in fields are escaped by backslashes, as in journal
+//! records.  Marks are imported by `Mark::merge_with()`, so that the one
+//! updated later wins.
+//!
+//! Marks set in bulk by `Stage::set_marks()` are kept in the same format,
+//! with the session which set them as the sixth field, as *pending marks*
+//! of each feed, rather than rewriting whole feed documents.  They're
+//! merged into entries whenever feeds are read, and folded into feed
+//! documents by `Stage::fold_marks()`, which `Stage::compact()` calls.
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use chrono::{DateTime, FixedOffset};
+
+use codecs::RFC3339;
+use feed::{Feed, Mark, MarkKind};
+use job::{CancellationToken, Phase, ProgressReporter};
+use repository as repo;
+use repository::Repository;
+use schema::{Codec, Mergeable};
+
+use super::{Error, Result, Stage};
+use super::journal::{escape, unescape};
+use super::layout::{DocumentKey, KeyLayout, PENDING_MARKS_KEY};
+
+/// The first line of exported marks, which tells the version of the
+/// format.
+pub const MARKS_HEADER: &'static str = "earthreader-marks\t1";
+
+/// What `Stage::import_marks()` did.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct MarksImport {
+    /// The number of marks changed by imported ones.
+    pub changed: usize,
+
+    /// The number of marks of entries the stage doesn't have.
+    pub unknown: usize,
+
+    /// Line numbers, starting from 1, of lines that couldn't be decoded.
+    pub malformed: Vec<usize>,
+}
+
+struct MarkLine {
+    feed_id: String,
+    entry_id: String,
+    kind: MarkKind,
+    mark: Mark,
+}
+
+fn mark_kind_name(kind: MarkKind) -> &'static str {
+    match kind {
+        MarkKind::Read => "read",
+        MarkKind::Starred => "starred",
+    }
+}
+
+fn encode_line(feed_id: &str, entry_id: &str, kind: MarkKind, mark: &Mark)
+               -> String {
+    let updated_at = match mark.updated_at {
+        Some(ref t) => {
+            let mut buf = vec![];
+            RFC3339.encode(t, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        }
+        None => String::new(),
+    };
+    let fields = [escape(feed_id), escape(entry_id),
+                  mark_kind_name(kind).to_owned(),
+                  (if mark.marked { "true" } else { "false" }).to_owned(),
+                  updated_at];
+    fields.join("\t")
+}
+
+/// Encode the pending mark, i.e. `encode_line()` followed by the session
+/// if any.
+fn encode_pending_line(feed_id: &str, entry_id: &str, kind: MarkKind,
+                       mark: &Mark) -> String {
+    let mut line = encode_line(feed_id, entry_id, kind, mark);
+    if let Some(ref session) = mark.session {
+        line.push('\t');
+        line.push_str(&escape(session));
+    }
+    line
+}
+
+fn decode_line(line: &str) -> Option<MarkLine> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 5 && fields.len() != 6 {
+        return None;
+    }
+    let kind = match fields[2] {
+        "read" => MarkKind::Read,
+        "starred" => MarkKind::Starred,
+        _ => { return None; }
+    };
+    let marked = match fields[3] {
+        "true" => true,
+        "false" => false,
+        _ => { return None; }
+    };
+    let updated_at: Option<DateTime<FixedOffset>> = if fields[4].is_empty() {
+        None
+    } else {
+        match RFC3339.decode(fields[4]) {
+            Ok(t) => Some(t),
+            Err(_) => { return None; }
+        }
+    };
+    Some(MarkLine {
+        feed_id: unescape(fields[0]),
+        entry_id: unescape(fields[1]),
+        kind: kind,
+        mark: Mark { marked: marked, updated_at: updated_at,
+                     session: fields.get(5).map(|s| unescape(s)),
+                     ..Default::default() },
+    })
+}
+
+/// Merge pending marks of the feed of `feed_id` in the repository into the
+/// `feed`.  `Stage::read_feed()` does it, so that pending marks are seen
+/// as if they're in the feed document.
+pub fn merge_pending_marks<S>(repository: &S, feed_id: &str,
+                              feed: &mut Feed) -> Result<()>
+    where S: Repository
+{
+    let key = DocumentKey::PendingMarks(feed_id.to_owned())
+                          .key(KeyLayout::default());
+    if !repository.exists(&key) {
+        return Ok(());
+    }
+    let buf = try!(repository.read(&key));
+    let text = String::from_utf8_lossy(&buf);
+    let index: HashMap<String, usize> =
+        feed.entries.iter().enumerate().map(|(i, e)| (e.id.clone(), i))
+                    .collect();
+    for line in text.lines().filter_map(decode_line) {
+        if let Some(&i) = index.get(&line.entry_id) {
+            feed.entries[i].mark_mut(line.kind).merge_with(line.mark);
+        }
+    }
+    Ok(())
+}
+
+impl<R: Repository> Stage<R> {
+    /// Append the marks to pending marks of the feed of `feed_id`, as
+    /// pairs of an entry ID and the mark kind, read from the `feed` they've
+    /// been set in.
+    pub fn append_pending_marks(&mut self, feed_id: &str, feed: &Feed,
+                                marks: &[(&str, MarkKind)]) -> Result<()> {
+        let mut buf = String::new();
+        for &(entry_id, kind) in marks {
+            if let Some(entry) = feed.entries.iter()
+                                     .find(|e| e.id == entry_id) {
+                buf.push_str(&encode_pending_line(feed_id, entry_id, kind,
+                                                  entry.mark(kind)));
+                buf.push('\n');
+            }
+        }
+        let key =
+            self.document_key(DocumentKey::PendingMarks(feed_id.to_owned()));
+        try!(self.repository.append(&key, buf.as_bytes()));
+        Ok(())
+    }
+
+    /// Fold pending marks into feed documents, and remove them.  It's
+    /// checked whether `cancel` is cancelled before each feed, and it fails
+    /// with `Error::Cancelled` if it is; pending marks of feeds not folded
+    /// yet are left as they are.  It returns the number of folded feeds.
+    pub fn fold_marks(&mut self, cancel: &CancellationToken)
+                      -> Result<usize> {
+        if !self.repository.exists(&[PENDING_MARKS_KEY]) {
+            return Ok(0);
+        }
+        let feed_ids: Vec<String> =
+            try!(try!(self.repository.list(&[PENDING_MARKS_KEY])).collect());
+        let mut count = 0;
+        for feed_id in feed_ids {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            // read_feed() merges pending marks, so it only has to be
+            // written back
+            if self.repository.exists(&self.feed_key(&feed_id)) {
+                let feed = try!(self.read_feed(&feed_id));
+                try!(self.write_feed(&feed_id, &feed));
+                count += 1;
+            }
+            let key = self.document_key(DocumentKey::PendingMarks(feed_id));
+            try!(self.repository.delete(&key));
+        }
+        Ok(count)
+    }
+
+    /// Write marks of all entries in the stage to the `writer`, in the
+    /// format described in the module documentation.  Marks never set,
+    /// i.e. unmarked without updated time, are omitted.  It returns the
+    /// number of written marks.
+    ///
+    /// It's checked whether `cancel` is cancelled before each feed, and it
+    /// fails with `Error::Cancelled` if it is.  What's written to the
+    /// `writer` until then is a valid export of some feeds, which can be
+    /// imported as well.  The number of exported feeds is reported to the
+    /// `progress` as `Phase::Exporting`.
+    pub fn export_marks<W, P>(&self, writer: &mut W,
+                              cancel: &CancellationToken, progress: &mut P)
+                              -> Result<usize>
+        where W: io::Write, P: ProgressReporter + ?Sized
+    {
+        let io = |err: io::Error| repo::Error::Io(err);
+        try!(writeln!(writer, "{}", MARKS_HEADER).map_err(&io));
+        let mut count = 0;
+        let feed_ids = try!(self.feed_ids());
+        let total = Some(feed_ids.len());
+        progress.report(Phase::Exporting, 0, total);
+        for (i, feed_id) in feed_ids.iter().enumerate() {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            let feed = try!(self.read_feed(feed_id));
+            for entry in feed.entries.iter() {
+                for &kind in &[MarkKind::Read, MarkKind::Starred] {
+                    let mark = entry.mark(kind);
+                    if !mark.marked && mark.updated_at.is_none() {
+                        continue;
+                    }
+                    let line = encode_line(feed_id, &entry.id, kind, mark);
+                    try!(writeln!(writer, "{}", line).map_err(&io));
+                    count += 1;
+                }
+            }
+            progress.report(Phase::Exporting, i + 1, total);
+        }
+        Ok(count)
+    }
+
+    /// Merge marks exported by `export_marks()` into entries of the stage.
+    /// Marks of feeds or entries the stage doesn't have are ignored, and
+    /// so are lines that can't be decoded, e.g. of a newer format.
+    pub fn import_marks<B: io::BufRead>(&mut self, reader: B)
+                                        -> Result<MarksImport> {
+        let mut report: MarksImport = Default::default();
+        let mut by_feed: BTreeMap<String, Vec<MarkLine>> = BTreeMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = try!(line.map_err(repo::Error::Io));
+            if i == 0 && line == MARKS_HEADER {
+                continue;
+            }
+            match decode_line(&line) {
+                Some(mark) => {
+                    by_feed.entry(mark.feed_id.clone()).or_insert(vec![])
+                           .push(mark);
+                }
+                None if line.is_empty() => { }
+                None => { report.malformed.push(i + 1); }
+            }
+        }
+        for (feed_id, marks) in by_feed {
+            if !self.repository.exists(&self.feed_key(&feed_id)) {
+                report.unknown += marks.len();
+                continue;
+            }
+            let mut feed = try!(self.read_feed(&feed_id));
+            let mut changed = 0;
+            for line in marks {
+                let entry = feed.entries.iter_mut()
+                                .find(|e| e.id == line.entry_id);
+                let entry = match entry {
+                    Some(e) => e,
+                    None => { report.unknown += 1; continue; }
+                };
+                let mark = entry.mark_mut(line.kind);
+                let previous = mark.clone();
+                mark.merge_with(line.mark);
+                if *mark != previous {
+                    changed += 1;
+                }
+            }
+            if changed > 0 {
+                try!(self.write_feed(&feed_id, &feed));
+                report.changed += changed;
+            }
+        }
+        Ok(report)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::{Error, Stage};
+    use super::MARKS_HEADER;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Mark, MarkKind, Text};
+    use job::CancellationToken;
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    fn fx_feed() -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        for id in &["a", "b\tc"] {
+            feed.entries.push(Entry::new(id.to_string(), Text::plain(*id),
+                                         updated_at));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_export_import_marks() {
+        let source_dir = temp_dir();
+        let mut source = Stage::new(unwrap!(FileSystemRepository::from_path(
+            source_dir.path(), true)));
+        unwrap!(source.write_feed("feed", &fx_feed()));
+        unwrap!(source.set_mark("feed", "a", MarkKind::Read, true));
+        unwrap!(source.set_mark("feed", "b\tc", MarkKind::Starred, true));
+        let mut exported = vec![];
+        let cancel = CancellationToken::new();
+        let mut feeds = 0;
+        assert_eq!(unwrap!(source.export_marks(
+            &mut exported, &cancel,
+            &mut |_, done, _| { feeds = done; })), 2);
+        assert_eq!(feeds, 1);
+        let text = String::from_utf8(exported.clone()).unwrap();
+        assert!(text.starts_with(MARKS_HEADER));
+        assert!(text.contains("feed\tb\\tc\tstarred\ttrue\t"));
+
+        let target_dir = temp_dir();
+        let mut target = Stage::new(unwrap!(FileSystemRepository::from_path(
+            target_dir.path(), true)));
+        let mut feed = fx_feed();
+        let later = FixedOffset::east(0).ymd(2099, 1, 1).and_hms(0, 0, 0);
+        feed.entries[1].starred = Mark { marked: false,
+                                         updated_at: Some(later),
+                                         ..Default::default() };
+        unwrap!(target.write_feed("feed", &feed));
+        exported.extend_from_slice(
+            b"other\ta\tread\ttrue\t\nfeed\ta\tliked\ttrue\t\n");
+        let report = unwrap!(target.import_marks(&exported[..]));
+        assert_eq!(report.changed, 1);
+        assert_eq!(report.unknown, 1);
+        assert_eq!(report.malformed, [5]);
+        let feed = unwrap!(target.read_feed("feed"));
+        assert!(feed.entries[0].read.marked);
+        assert!(!feed.entries[1].starred.marked);
+        let report = unwrap!(target.import_marks(&exported[..]));
+        assert_eq!(report.changed, 0);
+
+        cancel.cancel();
+        assert_err!(source.export_marks(&mut vec![], &cancel, &mut ()),
+                    Error::Cancelled => { });
+    }
+}
@@ -0,0 +1,447 @@
+//! Strategies the stage merges two revisions of a document by, e.g. the
+//! stored feed and the crawled one, or the subscription list of this
+//! device and the one synchronized from another.
+//!
+//! The default is to merge field by field with `Mergeable::merge_with()`,
+//! so that marks and subscriptions made on either side are kept.  Some
+//! users prefer one side to win as a whole instead; the strategy is set
+//! for each document type by `Stage::merge_strategies`.
+//!
+//! `Stage::merge_duplicate()` merges two subscriptions of the same feed,
+//! e.g. subscribed twice by different URLs, into one.  Both sides are
+//! always merged field by field regardless of the strategies, since
+//! neither is a revision of the other.
+use chrono::{DateTime, FixedOffset};
+
+use feed::{Entry, Feed};
+use repository::Repository;
+use schema::Mergeable;
+use subscribe::{SubscriptionList, SubscriptionSet};
+
+use super::{DocumentKey, MergeSide, Operation, Result, Stage, merge_feeds};
+
+/// How two revisions of a document are merged.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MergeStrategy {
+    /// Merge field by field by `Mergeable::merge_with()`, and the newer
+    /// revision wins conflicting fields.  It's the default.
+    #[default]
+    FieldWise,
+
+    /// The incoming revision, e.g. crawled or synchronized from another
+    /// device, replaces the stored one as a whole.  Marks set only on the
+    /// stored revision are lost.
+    RemoteWins,
+
+    /// The newer revision replaces the other as a whole; see
+    /// `Merge::revised_at()`.  The incoming one wins ties.
+    NewestWins,
+}
+
+/// Merge strategies of each document type.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MergeStrategies {
+    /// Feeds merged on refresh, import, discovery, and the like.
+    pub feeds: MergeStrategy,
+
+    /// The subscription list merged by `Stage::merge_subscriptions()`.
+    pub subscriptions: MergeStrategy,
+}
+
+/// Documents which can be merged by any `MergeStrategy`.
+pub trait Merge: Sized {
+    /// Merge field by field, for `MergeStrategy::FieldWise`.
+    fn merge_fields(stored: Self, incoming: Self) -> Self;
+
+    /// When the revision was made, by which `MergeStrategy::NewestWins`
+    /// tells the newer one.  `None` is older than any time.
+    fn revised_at(&self) -> Option<DateTime<FixedOffset>>;
+
+    fn merge(stored: Self, incoming: Self, strategy: MergeStrategy) -> Self {
+        match strategy {
+            MergeStrategy::FieldWise => Merge::merge_fields(stored, incoming),
+            MergeStrategy::RemoteWins => incoming,
+            MergeStrategy::NewestWins => {
+                if stored.revised_at() > incoming.revised_at() {
+                    stored
+                } else {
+                    incoming
+                }
+            }
+        }
+    }
+}
+
+/// Feeds are merged field by field regarding the one updated later as the
+/// newer, and revised when they were crawled last, i.e. the latest
+/// `Provenance::last_seen_at` of their entries, or `updated_at` if none of
+/// them were crawled.
+impl Merge for Feed {
+    fn merge_fields(stored: Feed, incoming: Feed) -> Feed {
+        merge_feeds(stored, incoming)
+    }
+
+    fn revised_at(&self) -> Option<DateTime<FixedOffset>> {
+        let crawled_at = self.entries.iter()
+                             .filter_map(|e| e.seen.as_ref())
+                             .map(|seen| seen.last_seen_at)
+                             .max();
+        Some(crawled_at.unwrap_or(self.updated_at))
+    }
+}
+
+/// Subscription lists are merged field by field regarding the incoming one
+/// as the newer, and revised when the latest subscription in them was
+/// made, i.e. `Subscription::created_at`.
+impl Merge for SubscriptionList {
+    fn merge_fields(stored: SubscriptionList, mut incoming: SubscriptionList)
+                    -> SubscriptionList {
+        incoming.merge_with(stored);
+        incoming
+    }
+
+    fn revised_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.recursive_subscriptions().iter()
+            .filter_map(|s| s.created_at)
+            .max()
+    }
+}
+
+/// Fields of entries whose conflicts are recorded by `Stage::merge_feed()`.
+/// Marks conflict only if both sides have set them; crawled entries have
+/// no marks.
+pub static MERGED_FIELDS: [&'static str; 6] = [
+    "title", "summary", "content", "updated", "read", "starred",
+];
+
+fn field_eq(field: &str, a: &Entry, b: &Entry) -> bool {
+    match field {
+        "title" => a.title == b.title,
+        "summary" => a.summary == b.summary,
+        "content" => a.content == b.content,
+        "updated" => a.updated_at == b.updated_at,
+        "read" => a.read == b.read,
+        "starred" => a.starred == b.starred,
+        _ => true,
+    }
+}
+
+fn conflicts_in(field: &str, ours: &Entry, theirs: &Entry) -> bool {
+    let touched = match field {
+        "read" => ours.read.updated_at.is_some() &&
+                  theirs.read.updated_at.is_some(),
+        "starred" => ours.starred.updated_at.is_some() &&
+                     theirs.starred.updated_at.is_some(),
+        _ => true,
+    };
+    touched && !field_eq(field, ours, theirs)
+}
+
+impl<R: Repository> Stage<R> {
+    /// Merge the incoming revision of the feed of `feed_id` into the
+    /// stored one by `merge_strategies.feeds`.  Which side each field of
+    /// entries in conflict between both revisions was taken from is
+    /// recorded to the journal as `Operation::Merged`, if it's enabled;
+    /// see `MERGED_FIELDS`.
+    pub fn merge_feed(&mut self, feed_id: &str, stored: Feed,
+                      incoming: Feed) -> Result<Feed> {
+        if self.journal_policy.is_none() {
+            return Ok(Merge::merge(stored, incoming,
+                                   self.merge_strategies.feeds));
+        }
+        let mut conflicts = vec![];
+        for theirs in incoming.entries.iter() {
+            let ours = match stored.entries.iter()
+                                   .find(|e| e.id == theirs.id) {
+                Some(ours) => ours,
+                None => { continue; }
+            };
+            let fields: Vec<&str> = MERGED_FIELDS.iter().cloned()
+                .filter(|f| conflicts_in(f, ours, theirs))
+                .collect();
+            if !fields.is_empty() {
+                conflicts.push((ours.clone(), theirs.clone(), fields));
+            }
+        }
+        let merged = Merge::merge(stored, incoming,
+                                  self.merge_strategies.feeds);
+        let mut operations = vec![];
+        for (ours, theirs, fields) in conflicts {
+            let entry = match merged.entries.iter()
+                                    .find(|e| e.id == ours.id) {
+                Some(entry) => entry,
+                None => { continue; }
+            };
+            for field in fields {
+                let side = if field_eq(field, entry, &theirs) {
+                    MergeSide::Remote
+                } else if field_eq(field, entry, &ours) {
+                    MergeSide::Local
+                } else {
+                    continue;
+                };
+                operations.push(Operation::Merged {
+                    feed_id: feed_id.to_owned(),
+                    entry_id: ours.id.clone(),
+                    field: field.to_owned(),
+                    side: side,
+                });
+            }
+        }
+        try!(self.record_all(operations));
+        Ok(merged)
+    }
+
+    /// Merge the subscription list e.g. synchronized from another device
+    /// into the stored one by `merge_strategies.subscriptions`, and write
+    /// the result.  Like `write_subscriptions()`, it isn't recorded to the
+    /// undo history.
+    pub fn merge_subscriptions(&mut self, incoming: SubscriptionList)
+                               -> Result<SubscriptionList> {
+        let stored = try!(self.read_subscriptions());
+        let merged = Merge::merge(stored, incoming,
+                                  self.merge_strategies.subscriptions);
+        try!(self.write_subscriptions(&merged));
+        Ok(merged)
+    }
+
+    /// Merge the subscription of `duplicate` into the one of `keep`, e.g.
+    /// when the same feed was subscribed twice by different URLs.  Stored
+    /// entries, including their marks, and settings of both are merged by
+    /// `Mergeable::merge_with()`, and the URLs of the duplicate become
+    /// aliases of the kept subscription, so that it's still recognized by
+    /// them.  The duplicate is then unsubscribed, and its feed documents
+    /// and settings are removed.  It returns `false` and does nothing if
+    /// either isn't subscribed, or they're the same.
+    ///
+    /// The kept side is written before the duplicate is removed, so that
+    /// it can be merged again if it's interrupted.  Like
+    /// `merge_subscriptions()`, it isn't recorded to the undo history.
+    pub fn merge_duplicate(&mut self, keep: &str, duplicate: &str)
+                           -> Result<bool> {
+        let mut list = try!(self.read_subscriptions());
+        if keep == duplicate || list.find(keep).is_none() {
+            return Ok(false);
+        }
+        let removed = match list.unsubscribe(duplicate) {
+            Some((_, subscription)) => subscription,
+            None => { return Ok(false); }
+        };
+        {
+            let kept = list.find_mut(keep).unwrap();
+            let mut uris = removed.aliases.clone();
+            uris.push(removed.feed_uri.clone());
+            for uri in uris {
+                if !kept.is_known_as(&uri) {
+                    kept.aliases.push(uri);
+                }
+            }
+            kept.merge_with(removed);
+        }
+
+        let duplicate_key = self.feed_key(duplicate);
+        if self.repository.exists(&duplicate_key) {
+            let incoming = try!(self.read_feed(duplicate));
+            let feed = if self.repository.exists(&self.feed_key(keep)) {
+                let mut feed = try!(self.read_feed(keep));
+                feed.merge_with(incoming);
+                feed
+            } else {
+                incoming
+            };
+            try!(self.write_feed(keep, &feed));
+        }
+        let mut settings = try!(self.read_settings(keep));
+        settings.merge_with(try!(self.read_settings(duplicate)));
+        if !settings.settings.is_empty() {
+            try!(self.write_settings(keep, &settings));
+        }
+
+        try!(self.write_subscriptions(&list));
+        try!(self.record(Operation::Unsubscribed {
+            feed_id: duplicate.to_owned(),
+        }));
+        try!(self.delete_pages_from(duplicate, 0));
+        let settings_key =
+            self.document_key(DocumentKey::Settings(duplicate.to_owned()));
+        if self.repository.exists(&settings_key) {
+            try!(self.repository.delete(&settings_key));
+        }
+        Ok(true)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Merge, MergeStrategy};
+    use super::super::{MergeSide, Operation, Stage};
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Provenance, Text};
+    use repository::FileSystemRepository;
+    use subscribe::{Retention, Subscription, SubscriptionList,
+                    SubscriptionSet};
+    use test_utils::temp_dir;
+
+    fn fx_feed(ids: &[&str], crawled_days: i64) -> Feed {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), date);
+        for id in ids {
+            let mut entry = Entry::new(id.to_string(), Text::plain("Entry"),
+                                       date);
+            let crawled_at = date + Duration::days(crawled_days);
+            entry.seen = Some(Provenance {
+                first_seen_at: crawled_at,
+                first_seen_uri: "http://example.com/".to_owned(),
+                last_seen_at: crawled_at,
+                last_seen_uri: "http://example.com/".to_owned(),
+            });
+            feed.entries.push(entry);
+        }
+        feed
+    }
+
+    fn ids(feed: &Feed) -> Vec<&str> {
+        let mut ids: Vec<&str> = feed.entries.iter().map(|e| &e.id[..])
+                                     .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_merge_feeds() {
+        let newer = fx_feed(&["a", "b"], 2);
+        let older = fx_feed(&["b", "c"], 1);
+        let merge = |a: &Feed, b: &Feed, strategy| {
+            Merge::merge(a.clone(), b.clone(), strategy)
+        };
+        assert_eq!(ids(&merge(&newer, &older, MergeStrategy::FieldWise)),
+                   ["a", "b", "c"]);
+        assert_eq!(ids(&merge(&newer, &older, MergeStrategy::RemoteWins)),
+                   ["b", "c"]);
+        assert_eq!(ids(&merge(&older, &newer, MergeStrategy::RemoteWins)),
+                   ["a", "b"]);
+        assert_eq!(ids(&merge(&newer, &older, MergeStrategy::NewestWins)),
+                   ["a", "b"]);
+        assert_eq!(ids(&merge(&older, &newer, MergeStrategy::NewestWins)),
+                   ["a", "b"]);
+        assert_eq!(MergeStrategy::default(), MergeStrategy::FieldWise);
+    }
+
+    #[test]
+    fn test_merge_feed_journal() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        stage.journal_policy = Some(Default::default());
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut stored = fx_feed(&["a", "b"], 1);
+        stored.entries[0].read.marked = true;
+        stored.entries[0].read.updated_at = Some(date + Duration::days(3));
+        let mut incoming = fx_feed(&["a", "b", "c"], 2);
+        incoming.updated_at = date + Duration::days(1);
+        incoming.entries[0].title = Text::plain("Renamed");
+        incoming.entries[0].read.updated_at = Some(date + Duration::days(2));
+        let merged = unwrap!(stage.merge_feed("feed", stored, incoming));
+        let a = merged.entries.iter().find(|e| e.id == "a").unwrap();
+        assert_eq!(a.title, Text::plain("Renamed"));
+        assert!(a.read.marked);
+        let operations: Vec<Operation> = unwrap!(stage.read_journal())
+            .into_iter().map(|r| r.operation).collect();
+        let merged = |field: &str, side| Operation::Merged {
+            feed_id: "feed".to_owned(),
+            entry_id: "a".to_owned(),
+            field: field.to_owned(),
+            side: side,
+        };
+        assert_eq!(operations, [merged("title", MergeSide::Remote),
+                                merged("read", MergeSide::Local)]);
+    }
+
+    #[test]
+    fn test_merge_subscriptions() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let list = |ids: &[&str], days: i64| {
+            let mut list = SubscriptionList::default();
+            for id in ids {
+                let mut subscription = Subscription::new(
+                    *id, format!("http://example.com/{}", id), *id);
+                subscription.created_at = Some(date + Duration::days(days));
+                list.subscriptions.push(subscription);
+            }
+            list
+        };
+        let feed_ids = |list: &SubscriptionList| {
+            let mut ids: Vec<String> = list.recursive_subscriptions().iter()
+                                           .map(|s| s.feed_id.clone())
+                                           .collect();
+            ids.sort();
+            ids
+        };
+        unwrap!(stage.write_subscriptions(&list(&["a", "b"], 2)));
+        let merged = unwrap!(stage.merge_subscriptions(list(&["c"], 1)));
+        assert_eq!(feed_ids(&merged), ["a", "b", "c"]);
+
+        stage.merge_strategies.subscriptions = MergeStrategy::NewestWins;
+        let merged = unwrap!(stage.merge_subscriptions(list(&["d"], 1)));
+        assert_eq!(feed_ids(&merged), ["a", "b", "c"]);
+
+        stage.merge_strategies.subscriptions = MergeStrategy::RemoteWins;
+        unwrap!(stage.merge_subscriptions(list(&["d"], 1)));
+        assert_eq!(feed_ids(&unwrap!(stage.read_subscriptions())), ["d"]);
+    }
+
+    #[test]
+    fn test_merge_duplicate() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut a = Subscription::new("a", "http://example.com/feed", "A");
+        a.aliases.push("http://example.com/rss".to_owned());
+        let mut b = Subscription::new("b", "https://example.com/feed", "B");
+        b.aliases.push("http://example.com/rss".to_owned());
+        b.aliases.push("http://example.com/atom".to_owned());
+        unwrap!(stage.subscribe(&[] as &[&str], a));
+        unwrap!(stage.subscribe(&["Dup"], b));
+        let mut feed = fx_feed(&["x", "y"], 1);
+        unwrap!(stage.write_feed("a", &feed));
+        feed = fx_feed(&["y", "z"], 2);
+        feed.entries[0].read.marked = true;
+        feed.entries[0].read.updated_at = Some(date);
+        unwrap!(stage.write_feed("b", &feed));
+        let mut settings = unwrap!(stage.read_settings("b"));
+        settings.set_retention(Some(Retention::Forever), date);
+        unwrap!(stage.write_settings("b", &settings));
+
+        assert!(!unwrap!(stage.merge_duplicate("a", "a")));
+        assert!(!unwrap!(stage.merge_duplicate("a", "c")));
+        assert!(!unwrap!(stage.merge_duplicate("c", "b")));
+        assert!(unwrap!(stage.merge_duplicate("a", "b")));
+        let list = unwrap!(stage.read_subscriptions());
+        assert!(list.find("b").is_none());
+        let kept = list.find("a").unwrap();
+        assert_eq!(kept.feed_uri, "http://example.com/feed");
+        assert_eq!(kept.aliases, ["http://example.com/rss",
+                                  "http://example.com/atom",
+                                  "https://example.com/feed"]);
+        assert_eq!(list.find_by_uri("https://example.com/feed")
+                       .map(|s| &s.feed_id[..]), Some("a"));
+        let merged = unwrap!(stage.read_feed("a"));
+        assert_eq!(ids(&merged), ["x", "y", "z"]);
+        let y = merged.entries.iter().find(|e| e.id == "y").unwrap();
+        assert!(y.read.marked);
+        assert_eq!(unwrap!(stage.read_settings("a")).retention(),
+                   Some(Retention::Forever));
+        assert_eq!(unwrap!(stage.feed_ids()), ["a"]);
+        assert!(unwrap!(stage.read_settings("b")).settings.is_empty());
+        assert!(!unwrap!(stage.merge_duplicate("a", "b")));
+    }
+}
@@ -0,0 +1,2787 @@
+//! Staging layer which reads and writes documents through repositories.
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::hash_map::Entry as MapEntry;
+use std::error::Error as ErrorTrait;
+use std::fmt;
+use std::time::Instant;
+
+use chrono::{DateTime, FixedOffset, UTC};
+
+use blobs;
+#[cfg(feature = "crawler")]
+use crawler;
+
+use feed::{Entry, Feed, FeedWriter, LinkRel, Mark, MarkKind, Source};
+use feed::paging::{is_paging_link, page_links, paginate};
+use job::CancellationToken;
+use parser::base::DecodeError;
+#[cfg(feature = "search")]
+use query::Query;
+use repository as repo;
+use repository::{Metrics, Repository};
+use sanitizer::SanitizerPreset;
+use schema;
+use schema::{Mergeable, SchemaError};
+use subscribe::{Subscription, SubscriptionFormat, SubscriptionList,
+                SubscriptionSet, SubscriptionSettings};
+use subscribe::atom::read_subscription_list;
+
+use self::layout::{FEEDS_KEY, JOURNAL_KEY, PAGES_KEY};
+use self::sanitize::sanitize_feed;
+
+pub use self::dirtybuffer::{DirtyBuffer, FlushPolicy, FlushStats,
+                            LifecycleEvent};
+#[cfg(feature = "auto-flush")]
+pub use self::dirtybuffer::{AutoFlusher, Flushable};
+pub use self::archives::Archives;
+pub use self::compact::RetentionPolicy;
+#[cfg(feature = "crawler")]
+pub use self::contents::ContentPolicy;
+#[cfg(feature = "crawler")]
+pub use self::discovery::SubscribedByUrl;
+#[cfg(feature = "crawler")]
+pub use self::download::{Download, DownloadPolicy, Progress, download_to};
+pub use self::fsck::{FsckReport, Problem, Repair};
+pub use self::handle::{Document, DocumentHandle};
+#[cfg(feature = "crawler")]
+pub use self::icons::MISSING_ICON_TTL;
+pub use self::import::{ImportReport, ImportedFeed};
+pub use self::journal::{JournalPolicy, MergeSide, Operation, Record};
+pub use self::layout::{DocumentKey, KeyLayout};
+#[cfg(feature = "crawler")]
+pub use self::links::{LINK_LOG_SIZE, LinkCheck, LinkCheckPolicy};
+pub use self::manifest::{MANIFEST_VERSION, Manifest};
+pub use self::merge::{Merge, MergeStrategies, MergeStrategy};
+#[cfg(feature = "export")]
+pub use self::marks::{MARKS_HEADER, MarksImport};
+pub use self::preview::{FlushPreview, MarkChange};
+#[cfg(feature = "crawler")]
+pub use self::refresh::{CRAWL_LOG_SIZE, CrawlRecord, CrawlStatus,
+                        RefreshOutcome};
+pub use self::report::{FeedReport, Report, Totals};
+pub use self::sanity::{ClampedDate, Insanity, SanityPolicy};
+pub use self::snapshot::Snapshot;
+pub use self::undo::{Change, History, UNDO_HISTORY_SIZE};
+#[cfg(feature = "search")]
+pub use self::virtual_feeds::VirtualFeed;
+
+pub mod archives;
+pub mod compact;
+#[cfg(feature = "crawler")]
+pub mod contents;
+pub mod dedup;
+#[cfg(feature = "crawler")]
+pub mod discovery;
+#[cfg(feature = "crawler")]
+pub mod download;
+pub mod fsck;
+pub mod handle;
+#[cfg(feature = "crawler")]
+pub mod icons;
+pub mod import;
+pub mod journal;
+pub mod layout;
+#[cfg(feature = "crawler")]
+pub mod links;
+pub mod manifest;
+pub mod originals;
+pub mod merge;
+#[cfg(feature = "export")]
+pub mod marks;
+pub mod preview;
+#[cfg(feature = "crawler")]
+pub mod redirects;
+#[cfg(feature = "crawler")]
+pub mod refresh;
+pub mod report;
+pub mod sanitize;
+pub mod sanity;
+pub mod snapshot;
+pub mod undo;
+#[cfg(feature = "search")]
+pub mod virtual_feeds;
+pub mod vocabularies;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Repository(repo::Error),
+    Decode(DecodeError),
+    Encode(SchemaError),
+
+    /// Failed to fetch the enclosure.
+    #[cfg(feature = "crawler")]
+    Crawl(crawler::Error),
+
+    /// Failed to store the blob.
+    Blob(blobs::Error),
+
+    /// The operation was stopped by its `CancellationToken`.
+    Cancelled,
+
+    /// The crawled feed failed sanity checks, so it was quarantined
+    /// instead of being stored.  See the `sanity` module.
+    Quarantined(Insanity),
+
+    /// The archive can't be opened by this build, and why.  See the
+    /// `manifest` module.
+    Incompatible(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.description()));
+        if let Error::Quarantined(ref insanity) = *self {
+            try!(write!(f, ": {}", insanity));
+        }
+        if let Error::Incompatible(ref message) = *self {
+            try!(write!(f, ": {}", message));
+        }
+        if let Some(cause) = self.cause() {
+            try!(write!(f, " caused by `{}`", cause));
+        }
+        Ok(())
+    }
+}
+
+impl ErrorTrait for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Repository(_) => "repository error",
+            Error::Decode(_) => "failed to decode the document",
+            Error::Encode(_) => "failed to encode the document",
+            #[cfg(feature = "crawler")]
+            Error::Crawl(_) => "failed to download",
+            Error::Blob(_) => "blob store error",
+            Error::Cancelled => "the operation was cancelled",
+            Error::Quarantined(_) => "the feed was quarantined",
+            Error::Incompatible(_) => "the archive is incompatible",
+        }
+    }
+
+    fn cause(&self) -> Option<&ErrorTrait> {
+        match *self {
+            Error::Repository(ref err) => Some(err as &ErrorTrait),
+            Error::Decode(ref err) => Some(err as &ErrorTrait),
+            Error::Encode(ref err) => Some(err as &ErrorTrait),
+            #[cfg(feature = "crawler")]
+            Error::Crawl(ref err) => Some(err as &ErrorTrait),
+            Error::Blob(ref err) => Some(err as &ErrorTrait),
+            Error::Cancelled | Error::Quarantined(_) |
+            Error::Incompatible(_) => None,
+        }
+    }
+}
+
+impl From<repo::Error> for Error {
+    fn from(err: repo::Error) -> Error { Error::Repository(err) }
+}
+
+impl From<DecodeError> for Error {
+    fn from(err: DecodeError) -> Error { Error::Decode(err) }
+}
+
+impl From<SchemaError> for Error {
+    fn from(err: SchemaError) -> Error { Error::Encode(err) }
+}
+
+/// What `Stage::enforce_quota()` removes to reduce the usage.  Starred
+/// entries are never removed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuotaPolicy {
+    /// Remove read entries, the oldest first.
+    DropReadEntries,
+
+    /// Remove entries regardless of whether they're read, the oldest first.
+    DropOldestEntries,
+}
+
+impl QuotaPolicy {
+    fn can_drop(&self, entry: &Entry) -> bool {
+        !entry.starred.marked && match *self {
+            QuotaPolicy::DropReadEntries => entry.read.marked,
+            QuotaPolicy::DropOldestEntries => true,
+        }
+    }
+}
+
+/// Stage is the interface to read and write documents e.g. `Feed` through
+/// the repository.  Written documents are buffered in a `DirtyBuffer` until
+/// `flush()` is called.
+pub struct Stage<R: Repository> {
+    repository: DirtyBuffer<R>,
+
+    /// The maximum number of entries stored in a single feed document.
+    /// Feeds that have more entries than this are split into several
+    /// documents, and the rest entries go to archive documents linked from
+    /// the current document as [RFC 5005][] describes.  `None` means feeds
+    /// are never split, and so does `Some(0)`.
+    ///
+    /// [RFC 5005]: https://tools.ietf.org/html/rfc5005
+    pub entries_per_page: Option<usize>,
+
+    /// How operations are recorded to the journal.  `None` (default) means
+    /// nothing is recorded.
+    pub journal_policy: Option<JournalPolicy>,
+
+    /// How feed documents are laid out in the repository.  It's `Flat` by
+    /// default.  Setting it doesn't move documents already stored; see
+    /// `migrate_key_layout()`.
+    pub key_layout: KeyLayout,
+
+    /// The format the subscription list is written in.  It's `Opml` by
+    /// default.  The list is read in either format regardless of it.
+    pub subscription_format: SubscriptionFormat,
+
+    /// The identifier of this session, e.g. of the device, recorded on
+    /// marks the stage updates.  It breaks ties of marks updated at the
+    /// same time by different sessions; see `Mark::session`.  `None` by
+    /// default.
+    pub session: Option<String>,
+
+    /// Caps of crawled feeds, beyond which they're quarantined rather than
+    /// stored.  See the `sanity` module.
+    pub sanity_policy: SanityPolicy,
+
+    /// How enclosures are downloaded.  See the `download` module.
+    #[cfg(feature = "crawler")]
+    pub download_policy: DownloadPolicy,
+
+    /// How out-of-line contents of entries are fetched.  `None` (default)
+    /// means they aren't.  See the `contents` module.
+    #[cfg(feature = "crawler")]
+    pub content_policy: Option<ContentPolicy>,
+
+    /// What verifies fetched feed documents by `refresh()`, along with
+    /// fingerprints pinned by subscription settings.  `None` by default.
+    /// See the `crawler::verify` module.
+    #[cfg(feature = "crawler")]
+    pub verifier: Option<Box<crawler::Verifier + Send + Sync>>,
+
+    /// How revisions of each document type are merged, e.g. the crawled
+    /// feed into the stored one.  See the `merge` module.
+    pub merge_strategies: MergeStrategies,
+
+    /// The preset HTML of entries is sanitized with when feeds are
+    /// written, so that it isn't sanitized again on every render.  `None`
+    /// (default) stores HTML as it's crawled.  See the `sanitize` module.
+    pub sanitize_on_write: Option<SanitizerPreset>,
+
+    history: History,
+
+    /// The index of the last journal segment and the number of records in
+    /// it, once it's known, so that the segment isn't read again for every
+    /// record.
+    journal_tail: Option<(u64, usize)>,
+
+    /// Downloads taken by `start_downloads()` and not finished yet.
+    #[cfg(feature = "crawler")]
+    running_downloads: HashSet<Download>,
+}
+
+impl<R: Repository> Stage<R> {
+    pub fn new(repository: R) -> Stage<R> {
+        Stage {
+            repository: DirtyBuffer::new(repository),
+            entries_per_page: None,
+            journal_policy: None,
+            key_layout: Default::default(),
+            subscription_format: Default::default(),
+            session: None,
+            sanity_policy: Default::default(),
+            #[cfg(feature = "crawler")]
+            download_policy: Default::default(),
+            #[cfg(feature = "crawler")]
+            content_policy: None,
+            #[cfg(feature = "crawler")]
+            verifier: None,
+            merge_strategies: Default::default(),
+            sanitize_on_write: None,
+            history: Default::default(),
+            journal_tail: None,
+            #[cfg(feature = "crawler")]
+            running_downloads: HashSet::new(),
+        }
+    }
+
+    /// Write all buffered documents to the underlying repository.
+    pub fn flush(&mut self) -> repo::Result<()> {
+        self.repository.flush()
+    }
+
+    /// Write buffered documents to the underlying repository one by one
+    /// until the `cancel` token is cancelled.  Documents not written yet
+    /// are left buffered, so that the next flush resumes.  It returns
+    /// whether everything was written.
+    pub fn flush_cancellable(&mut self, cancel: &CancellationToken)
+                             -> repo::Result<bool> {
+        self.repository.flush_cancellable(cancel)
+    }
+
+    /// Set when `maybe_flush()` and `handle_event()` flush the buffer.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.repository.policy = policy;
+    }
+
+    /// Flush buffered documents if the flush policy says so.  See
+    /// `DirtyBuffer::maybe_flush()`.
+    pub fn maybe_flush(&mut self, now: Instant) -> repo::Result<bool> {
+        self.repository.maybe_flush(now)
+    }
+
+    /// Let the stage know the app's lifecycle event.  See
+    /// `DirtyBuffer::handle_event()`.
+    pub fn handle_event(&mut self, event: LifecycleEvent)
+                        -> repo::Result<bool> {
+        self.repository.handle_event(event)
+    }
+
+    /// Metrics on flushes made so far.
+    pub fn flush_stats(&self) -> &FlushStats {
+        self.repository.stats()
+    }
+
+    /// Counters of documents read and written through the stage, reads
+    /// served from buffered documents, and flushes.  Apps may show them as
+    /// sync statistics.
+    pub fn metrics(&self) -> Metrics {
+        self.repository.metrics()
+    }
+
+    /// Reset `metrics()` and `flush_stats()` to zero, e.g. at the start of
+    /// each sync.
+    pub fn reset_metrics(&mut self) {
+        self.repository.reset_metrics()
+    }
+
+    /// Read the feed of `feed_id`.  If the feed was split into several
+    /// pages, they are reassembled into a single `Feed` value.  Marks set
+    /// by `set_marks()` but not folded into the document yet are merged.
+    pub fn read_feed(&self, feed_id: &str) -> Result<Feed> {
+        read_feed_from(&self.repository, self.key_layout, feed_id)
+    }
+
+    /// Write the feed as `feed_id`.  It's split into several pages if
+    /// `entries_per_page` is set.  Pages left by the previous revision of
+    /// the feed which are no longer necessary are removed.
+    pub fn write_feed(&mut self, feed_id: &str, feed: &Feed) -> Result<()> {
+        let sanitized = self.sanitize_on_write.and_then(|preset| {
+            sanitize_feed(feed, preset)
+        });
+        let feed = sanitized.as_ref().unwrap_or(feed);
+        let count = match self.entries_per_page {
+            Some(entries_per_page) if entries_per_page > 0 => {
+                let pages = paginate(feed, entries_per_page,
+                                     |i| page_name(feed_id, i));
+                for (i, page) in pages.iter().enumerate() {
+                    let key = self.page_key(feed_id, i);
+                    let w = try!(self.repository.get_writer(&key));
+                    try!(schema::write(page, w));
+                }
+                pages.len()
+            }
+            _ => {
+                let key = self.feed_key(feed_id);
+                let w = try!(self.repository.get_writer(&key));
+                try!(schema::write(feed, w));
+                1
+            }
+        };
+        self.delete_pages_from(feed_id, count)
+    }
+
+    /// Write the feed as `feed_id` like `write_feed()`, from its `source`
+    /// and `entries` given one by one, so that the whole `Feed` doesn't
+    /// have to be built in memory, e.g. to store thousands of entries read
+    /// from another archive.  Entries are serialized as they come; if
+    /// `entries_per_page` is set, only a page of entries is held at a time.
+    /// It returns the number of written entries.
+    pub fn stream_feed<I>(&mut self, feed_id: &str, source: &Source,
+                          entries: I) -> Result<usize>
+        where I: IntoIterator<Item=Entry>
+    {
+        let preset = self.sanitize_on_write;
+        let mut entries = entries.into_iter().map(|mut entry| {
+            if let Some(preset) = preset {
+                entry.sanitize(preset);
+            }
+            entry
+        }).peekable();
+        let mut written = 0;
+        let count = match self.entries_per_page {
+            Some(entries_per_page) if entries_per_page > 0 => {
+                let mut i = 0;
+                loop {
+                    let page: Vec<Entry> =
+                        entries.by_ref().take(entries_per_page).collect();
+                    let has_next = entries.peek().is_some();
+                    let links = page_links(i, has_next,
+                                           |i| page_name(feed_id, i));
+                    let key = self.page_key(feed_id, i);
+                    let w = try!(self.repository.get_writer(&key));
+                    let mut writer = try!(FeedWriter::start_page(
+                        w, source, &links, i > 0));
+                    for entry in page.iter() {
+                        try!(writer.write_entry(entry));
+                    }
+                    written += writer.entries();
+                    try!(writer.finish());
+                    i += 1;
+                    if !has_next {
+                        break;
+                    }
+                }
+                i
+            }
+            _ => {
+                let key = self.feed_key(feed_id);
+                let w = try!(self.repository.get_writer(&key));
+                let mut writer = try!(FeedWriter::start_feed(w, source));
+                for entry in entries {
+                    try!(writer.write_entry(&entry));
+                }
+                written = writer.entries();
+                try!(writer.finish());
+                1
+            }
+        };
+        try!(self.delete_pages_from(feed_id, count));
+        Ok(written)
+    }
+
+    /// Remove pages of the feed from the `index`-th, which are left by the
+    /// previous revision of the feed.
+    fn delete_pages_from(&mut self, feed_id: &str, index: usize)
+                         -> Result<()> {
+        for i in index.. {
+            let key = self.page_key(feed_id, i);
+            if !self.repository.exists(&key) {
+                break;
+            }
+            try!(self.repository.delete(&key));
+        }
+        Ok(())
+    }
+
+    /// The size in bytes of all pages of the feed.
+    fn feed_usage(&self, feed_id: &str) -> Result<u64> {
+        let mut total = 0;
+        for i in 0.. {
+            let key = self.page_key(feed_id, i);
+            if !self.repository.exists(&key) {
+                break;
+            }
+            total += try!(self.repository.usage(&key));
+        }
+        Ok(total)
+    }
+
+    /// List identifiers of all stored feeds.  Archive pages of feeds aren't
+    /// included, since they're stored apart from feeds.
+    pub fn feed_ids(&self) -> Result<Vec<String>> {
+        self.document_names(self.key_layout)
+    }
+
+    /// Entries of all stored feeds which match the query, as pairs of a
+    /// feed id and an entry, in the order of feed ids and then of
+    /// `sorted_entries()`.  See the `query` module.
+    #[cfg(feature = "search")]
+    pub fn query_entries(&self, query: &Query, now: DateTime<FixedOffset>)
+                         -> Result<Vec<(String, Entry)>> {
+        let mut entries = vec![];
+        for feed_id in try!(self.feed_ids()) {
+            for entry in try!(self.sorted_entries(&feed_id)) {
+                if query.matches(&feed_id, &entry, now) {
+                    entries.push((feed_id.clone(), entry));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Unread entries of the subscribed feeds, as pairs of a feed id and
+    /// an entry, in the order of `SubscriptionSet::recursive_subscriptions()`
+    /// and then of `sorted_entries()`.  Subscriptions whose feeds aren't
+    /// stored yet, i.e. never refreshed, and muted ones are skipped.
+    pub fn unread_entries(&self) -> Result<Vec<(String, Entry)>> {
+        let subscriptions = try!(self.read_subscriptions());
+        let now = now();
+        let mut entries = vec![];
+        for subscription in subscriptions.recursive_subscriptions() {
+            let feed_id = &subscription.feed_id;
+            if subscription.is_muted(now) ||
+               !self.repository.exists(&self.feed_key(feed_id)) {
+                continue;
+            }
+            for entry in try!(self.sorted_entries(feed_id)) {
+                if !entry.read.marked {
+                    entries.push((feed_id.clone(), entry));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Entries of the feed of `feed_id` in the order of its sort policy,
+    /// newest first.  See `SubscriptionSettings::sort_policy()`.
+    pub fn sorted_entries(&self, feed_id: &str) -> Result<Vec<Entry>> {
+        let policy = try!(self.read_settings(feed_id)).sort_policy();
+        let mut entries = try!(self.read_feed(feed_id)).entries;
+        policy.sort(&mut entries);
+        Ok(entries)
+    }
+
+    /// The total size in bytes of the stored documents, including buffered
+    /// ones which aren't flushed yet.
+    pub fn usage(&self) -> Result<u64> {
+        let empty: &[&str] = &[];
+        Ok(try!(self.repository.usage(empty)))
+    }
+
+    /// Remove entries by the `policy` until the usage gets under `limit`
+    /// bytes, and return the usage after that.  It may be still over the
+    /// `limit` if there are no more entries that the `policy` allows to
+    /// remove.
+    pub fn enforce_quota(&mut self, limit: u64, policy: QuotaPolicy)
+                         -> Result<u64>
+    {
+        let mut usage = try!(self.usage());
+        if usage <= limit {
+            return Ok(usage);
+        }
+        let mut candidates = vec![];
+        for feed_id in try!(self.feed_ids()) {
+            let feed = try!(self.read_feed(&feed_id));
+            for entry in feed.entries.iter().filter(|e| policy.can_drop(e)) {
+                candidates.push((entry.updated_at, feed_id.clone(),
+                                 entry.id.clone()));
+            }
+        }
+        candidates.sort();
+        // Entries are removed by chunks not to rewrite feeds for every entry.
+        let chunk_size = max(1, candidates.len() / 10);
+        for chunk in candidates.chunks(chunk_size) {
+            let mut by_feed: HashMap<&str, HashSet<&str>> = HashMap::new();
+            for (_, feed_id, entry_id) in chunk {
+                by_feed.entry(feed_id).or_default()
+                       .insert(entry_id);
+            }
+            // Only rewritten feeds are measured again, not the whole
+            // repository for every chunk.
+            for (feed_id, entry_ids) in by_feed {
+                let mut feed = try!(self.read_feed(feed_id));
+                feed.entries.retain(|e| !entry_ids.contains(&e.id[..]));
+                let before = try!(self.feed_usage(feed_id));
+                try!(self.write_feed(feed_id, &feed));
+                let after = try!(self.feed_usage(feed_id));
+                usage = usage.saturating_sub(before) + after;
+            }
+            if usage <= limit {
+                break;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Read the subscription list.  It's empty if nothing was subscribed
+    /// yet.
+    pub fn read_subscriptions(&self) -> Result<SubscriptionList> {
+        let key = self.document_key(DocumentKey::Subscriptions);
+        if !self.repository.exists(&key) {
+            return Ok(Default::default());
+        }
+        let buf = try!(self.repository.read(&key));
+        Ok(try!(read_subscription_list(&buf)))
+    }
+
+    /// Write the whole subscription list.  Unlike `subscribe()` and
+    /// `unsubscribe()` it isn't recorded to the undo history.
+    pub fn write_subscriptions(&mut self, subscriptions: &SubscriptionList)
+                               -> Result<()>
+    {
+        let key = self.document_key(DocumentKey::Subscriptions);
+        let w = try!(self.repository.get_writer(&key));
+        try!(self.subscription_format.write(subscriptions, w));
+        Ok(())
+    }
+
+    /// Read the settings of the subscription of `feed_id`.  They're empty
+    /// if nothing was overridden yet.
+    pub fn read_settings(&self, feed_id: &str)
+                         -> Result<SubscriptionSettings> {
+        let key = self.document_key(DocumentKey::Settings(feed_id.to_owned()));
+        if !self.repository.exists(&key) {
+            return Ok(Default::default());
+        }
+        self.read_document(&key)
+    }
+
+    /// Write the settings of the subscription of `feed_id`.  Settings are
+    /// kept even if the feed is unsubscribed, so that they're restored when
+    /// it's subscribed again.
+    pub fn write_settings(&mut self, feed_id: &str,
+                          settings: &SubscriptionSettings) -> Result<()> {
+        let key = self.document_key(DocumentKey::Settings(feed_id.to_owned()));
+        let w = try!(self.repository.get_writer(&key));
+        try!(schema::write(settings, w));
+        Ok(())
+    }
+
+    /// Add the subscription to the category of `path` (the root of the list
+    /// if it's empty).  It returns `false` and does nothing if the feed is
+    /// already subscribed.
+    pub fn subscribe<T: AsRef<str>>(&mut self, path: &[T],
+                                    subscription: Subscription)
+                                    -> Result<bool>
+    {
+        let mut list = try!(self.read_subscriptions());
+        if list.find(&subscription.feed_id).is_some() {
+            return Ok(false);
+        }
+        let feed_id = subscription.feed_id.clone();
+        subscribe_at(&mut list, path, subscription);
+        try!(self.write_subscriptions(&list));
+        try!(self.record(Operation::Subscribed { feed_id: feed_id.clone() }));
+        self.history.record(Change::Subscribe {
+            path: path.iter().map(|p| p.as_ref().to_owned()).collect(),
+            feed_id: feed_id,
+        });
+        Ok(true)
+    }
+
+    /// Remove the subscription of `feed_id` from the list.
+    pub fn unsubscribe(&mut self, feed_id: &str)
+                       -> Result<Option<Subscription>>
+    {
+        let mut list = try!(self.read_subscriptions());
+        let (path, subscription) = match list.unsubscribe(feed_id) {
+            Some(v) => v,
+            None => { return Ok(None); }
+        };
+        try!(self.write_subscriptions(&list));
+        try!(self.record(Operation::Unsubscribed {
+            feed_id: feed_id.to_owned(),
+        }));
+        self.history.record(Change::Unsubscribe {
+            path: path,
+            subscription: subscription.clone(),
+        });
+        Ok(Some(subscription))
+    }
+
+    /// Mark or unmark the entry of `entry_id` in the feed of `feed_id`.
+    /// It returns whether the mark was changed.
+    pub fn set_mark(&mut self, feed_id: &str, entry_id: &str,
+                    kind: MarkKind, marked: bool) -> Result<bool> {
+        let count = try!(self.update_marks(feed_id, kind, marked,
+                                           |e| e.id == entry_id));
+        Ok(count > 0)
+    }
+
+    /// Mark or unmark all entries in the feed of `feed_id`, e.g. "mark all
+    /// as read".  It returns the number of changed entries.
+    pub fn mark_all(&mut self, feed_id: &str, kind: MarkKind, marked: bool)
+                    -> Result<usize> {
+        self.update_marks(feed_id, kind, marked, |_| true)
+    }
+
+    /// Mark or unmark many entries at once, as pairs of a feed ID and an
+    /// entry ID, e.g. "mark all as read" over search results.  Feed
+    /// documents aren't rewritten; changed marks are appended to pending
+    /// marks of each feed, and the journal is appended in a single pass;
+    /// see `record_all()` and the `marks` module.  Pending marks are seen
+    /// by `read_feed()`, and folded into feed documents by `fold_marks()`
+    /// later.  It returns the number of changed entries.
+    ///
+    /// All feeds are read before any is changed, so that nothing is
+    /// changed if any of them can't be read, e.g. it doesn't exist.  If
+    /// appending fails in the middle, marks appended until then are
+    /// recorded to the journal and the history as usual before the error
+    /// is returned, so that they can be undone.
+    pub fn set_marks<'a, I>(&mut self, entries: I, kind: MarkKind,
+                            marked: bool) -> Result<usize>
+        where I: IntoIterator<Item=(&'a str, &'a str)>
+    {
+        let mut by_feed: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        for (feed_id, entry_id) in entries {
+            by_feed.entry(feed_id).or_default()
+                   .insert(entry_id);
+        }
+        let mut feeds = Vec::with_capacity(by_feed.len());
+        for (feed_id, entry_ids) in by_feed {
+            feeds.push((feed_id, entry_ids, try!(self.read_feed(feed_id))));
+        }
+        let now = now();
+        let mut changes = vec![];
+        for (feed_id, entry_ids, mut feed) in feeds {
+            let changed = self.change_marks(feed_id, &mut feed, kind, marked,
+                                            now,
+                                            |e| entry_ids.contains(&e.id[..]));
+            if changed.is_empty() {
+                continue;
+            }
+            let result = {
+                let marks: Vec<(&str, MarkKind)> = changed.iter()
+                    .filter_map(|c| match *c {
+                        Change::Mark { ref entry_id, kind, .. } => {
+                            Some((&entry_id[..], kind))
+                        }
+                        _ => None,
+                    }).collect();
+                self.append_pending_marks(feed_id, &feed, &marks)
+            };
+            if let Err(err) = result {
+                try!(self.commit_marks(changes, marked));
+                return Err(err);
+            }
+            changes.extend(changed);
+        }
+        self.commit_marks(changes, marked)
+    }
+
+    fn update_marks<F>(&mut self, feed_id: &str, kind: MarkKind,
+                       marked: bool, filter: F) -> Result<usize>
+        where F: Fn(&Entry) -> bool
+    {
+        let mut feed = try!(self.read_feed(feed_id));
+        let changes = self.change_marks(feed_id, &mut feed, kind, marked,
+                                        now(), filter);
+        if !changes.is_empty() {
+            try!(self.write_feed(feed_id, &feed));
+        }
+        self.commit_marks(changes, marked)
+    }
+
+    /// Update marks of entries the `filter` accepts in the `feed` of
+    /// `feed_id`.  It returns the changes to revert them; the feed isn't
+    /// written.
+    fn change_marks<F>(&self, feed_id: &str, feed: &mut Feed, kind: MarkKind,
+                       marked: bool, now: DateTime<FixedOffset>, filter: F)
+                       -> Vec<Change>
+        where F: Fn(&Entry) -> bool
+    {
+        let mut changes = vec![];
+        let session = self.session.as_ref().map(|s| &s[..]);
+        for entry in feed.entries.iter_mut().filter(|e| filter(e)) {
+            if let Some(previous) = update_mark(entry.mark_mut(kind), marked,
+                                                now, session) {
+                changes.push(Change::Mark {
+                    feed_id: feed_id.to_owned(),
+                    entry_id: entry.id.clone(),
+                    kind: kind,
+                    marked: previous,
+                });
+            }
+        }
+        changes
+    }
+
+    /// Record the changes of marks to the journal and the history.
+    fn commit_marks(&mut self, changes: Vec<Change>, marked: bool)
+                    -> Result<usize> {
+        let operations: Vec<Operation> = changes.iter().filter_map(|c| {
+            match *c {
+                Change::Mark { ref feed_id, ref entry_id, kind, .. } => {
+                    Some(Operation::Marked {
+                        feed_id: feed_id.clone(),
+                        entry_id: entry_id.clone(),
+                        kind: kind,
+                        marked: marked,
+                    })
+                }
+                _ => None,
+            }
+        }).collect();
+        try!(self.record_all(operations));
+        let count = changes.len();
+        for change in changes {
+            self.history.record(change);
+        }
+        Ok(count)
+    }
+
+    /// Close the current group of changes, so that changes made after this
+    /// are undone separately from the changes before.  For example, a UI
+    /// would call this before each user action.
+    pub fn checkpoint(&mut self) {
+        self.history.checkpoint();
+    }
+
+    /// Revert changes of marks and subscriptions made since the last
+    /// checkpoint.  It returns `false` if there's nothing to undo.
+    pub fn undo(&mut self) -> Result<bool> {
+        let group = match self.history.pop_undo() {
+            Some(g) => g,
+            None => { return Ok(false); }
+        };
+        let inverse = try!(self.revert(group));
+        self.history.push_redo(inverse);
+        try!(self.record(Operation::Undone));
+        Ok(true)
+    }
+
+    /// Reapply the changes reverted by the last `undo()`.  It returns
+    /// `false` if there's nothing to redo.
+    pub fn redo(&mut self) -> Result<bool> {
+        let group = match self.history.pop_redo() {
+            Some(g) => g,
+            None => { return Ok(false); }
+        };
+        let inverse = try!(self.revert(group));
+        self.history.push_undo(inverse);
+        try!(self.record(Operation::Redone));
+        Ok(true)
+    }
+
+    /// Revert the changes in reverse order, and return the changes that
+    /// revert them back.  Marks are reverted with the current time, so that
+    /// the reverted state wins when it's merged with other sessions.
+    fn revert(&mut self, changes: Vec<Change>) -> Result<Vec<Change>> {
+        let now = now();
+        let mut inverse = vec![];
+        let mut feeds: HashMap<String, Feed> = HashMap::new();
+        let mut list = None;
+        for change in changes.into_iter().rev() {
+            match change {
+                Change::Mark { feed_id, entry_id, kind, marked } => {
+                    let feed = match feeds.entry(feed_id.clone()) {
+                        MapEntry::Occupied(e) => e.into_mut(),
+                        MapEntry::Vacant(e) => {
+                            e.insert(try!(self.read_feed(&feed_id)))
+                        }
+                    };
+                    let entry = feed.entries.iter_mut()
+                                            .find(|e| e.id == entry_id);
+                    if let Some(entry) = entry {
+                        let mark = entry.mark_mut(kind);
+                        let previous = mark.marked;
+                        update_mark(mark, marked, now,
+                                    self.session.as_ref().map(|s| &s[..]));
+                        inverse.push(Change::Mark {
+                            feed_id: feed_id, entry_id: entry_id,
+                            kind: kind, marked: previous,
+                        });
+                    }
+                }
+                Change::Subscribe { feed_id, .. } => {
+                    if list.is_none() {
+                        list = Some(try!(self.read_subscriptions()));
+                    }
+                    let list = list.as_mut().unwrap();
+                    if let Some((path, s)) = list.unsubscribe(&feed_id) {
+                        inverse.push(Change::Unsubscribe {
+                            path: path, subscription: s,
+                        });
+                    }
+                }
+                Change::Unsubscribe { path, subscription } => {
+                    if list.is_none() {
+                        list = Some(try!(self.read_subscriptions()));
+                    }
+                    let list = list.as_mut().unwrap();
+                    if list.find(&subscription.feed_id).is_none() {
+                        let feed_id = subscription.feed_id.clone();
+                        subscribe_at(list, &path, subscription);
+                        inverse.push(Change::Subscribe {
+                            path: path, feed_id: feed_id,
+                        });
+                    }
+                }
+            }
+        }
+        for (feed_id, feed) in feeds.iter() {
+            try!(self.write_feed(feed_id, feed));
+        }
+        if let Some(list) = list {
+            try!(self.write_subscriptions(&list));
+        }
+        Ok(inverse)
+    }
+
+    /// Append the operation to the journal with the current time.  Marks,
+    /// subscriptions, and undo/redo made through the stage are recorded
+    /// automatically; the others e.g. crawls and merges are up to callers.
+    /// It does nothing if `journal_policy` is `None`.
+    pub fn record(&mut self, operation: Operation) -> Result<()> {
+        self.record_all(vec![operation])
+    }
+
+    /// Append the operations to the journal like `record()`, but in a
+    /// single pass: each segment is appended at most once, rather than
+    /// once for every operation.  The last segment is read only to count
+    /// its records the first time the stage records anything.
+    pub fn record_all<I>(&mut self, operations: I) -> Result<()>
+        where I: IntoIterator<Item=Operation>
+    {
+        let policy = match self.journal_policy {
+            Some(p) => p,
+            None => { return Ok(()); }
+        };
+        let mut operations = operations.into_iter().peekable();
+        if operations.peek().is_none() {
+            return Ok(());
+        }
+        let recorded_at = now();
+        let mut segments = try!(self.journal_segments());
+        let (mut index, mut count) = match (segments.last(),
+                                            self.journal_tail) {
+            (Some(&last), Some((index, count))) if last == index => {
+                (index, count)
+            }
+            (Some(&last), _) => {
+                let key = self.document_key(DocumentKey::JournalSegment(last));
+                let buf = try!(self.repository.read(&key));
+                (last, buf.iter().filter(|&&b| b == b'\n').count())
+            }
+            (None, _) => (0, 0),
+        };
+        let mut buf = vec![];
+        for operation in operations {
+            if count >= policy.records_per_segment {
+                if !buf.is_empty() {
+                    try!(self.append_segment(index, &buf, &mut segments));
+                    buf.clear();
+                }
+                index += 1;
+                count = 0;
+            }
+            let record = Record {
+                recorded_at: recorded_at,
+                operation: operation,
+            };
+            buf.extend_from_slice(record.to_line().as_bytes());
+            buf.push(b'\n');
+            count += 1;
+        }
+        try!(self.append_segment(index, &buf, &mut segments));
+        self.journal_tail = Some((index, count));
+        if let Some(max_segments) = policy.max_segments {
+            let max_segments = max_segments as u64;
+            for &old in segments.iter()
+                                .filter(|&&i| i + max_segments <= index) {
+                let key = self.document_key(DocumentKey::JournalSegment(old));
+                try!(self.repository.delete(&key));
+            }
+        }
+        Ok(())
+    }
+
+    fn append_segment(&mut self, index: u64, buf: &[u8],
+                      segments: &mut Vec<u64>) -> Result<()> {
+        let key = self.document_key(DocumentKey::JournalSegment(index));
+        try!(self.repository.append(&key, buf));
+        if segments.last() != Some(&index) {
+            segments.push(index);
+        }
+        Ok(())
+    }
+
+    /// Read all records remaining in the journal, the oldest first.
+    /// Malformed records are skipped.
+    pub fn read_journal(&self) -> Result<Vec<Record>> {
+        let mut records = vec![];
+        for index in try!(self.journal_segments()) {
+            let key = self.document_key(DocumentKey::JournalSegment(index));
+            let buf = try!(self.repository.read(&key));
+            let text = String::from_utf8_lossy(&buf);
+            records.extend(text.lines().filter_map(Record::from_line));
+        }
+        Ok(records)
+    }
+
+    fn journal_segments(&self) -> Result<Vec<u64>> {
+        if !self.repository.exists(&[JOURNAL_KEY]) {
+            return Ok(vec![]);
+        }
+        let names: Vec<String> =
+            try!(try!(self.repository.list(&[JOURNAL_KEY])).collect());
+        let mut segments: Vec<u64> =
+            names.iter().filter_map(|n| journal::segment_index(n)).collect();
+        segments.sort();
+        Ok(segments)
+    }
+
+    /// Move all feed documents stored in the current `key_layout` to the
+    /// `layout`, and then switch `key_layout` to it.  The manifest is
+    /// updated as well if the archive has one.  It returns the number of
+    /// moved documents, including archive pages.
+    pub fn migrate_key_layout(&mut self, layout: KeyLayout) -> Result<usize> {
+        if layout == self.key_layout {
+            return Ok(0);
+        }
+        let mut documents: Vec<DocumentKey> =
+            try!(self.document_names(self.key_layout)).into_iter()
+                .map(DocumentKey::Feed).collect();
+        for (feed_id, number) in try!(self.page_documents(self.key_layout)) {
+            documents.push(DocumentKey::FeedPage(feed_id, number));
+        }
+        for document in documents.iter() {
+            let old_key = self.document_key(document.clone());
+            let buf = try!(self.repository.read(&old_key));
+            try!(self.repository.write(&document.key(layout), Some(buf)));
+            try!(self.repository.delete(&old_key));
+        }
+        self.key_layout = layout;
+        if try!(self.read_manifest()).is_some() {
+            try!(self.write_manifest());
+        }
+        Ok(documents.len())
+    }
+
+    /// The key of the `document` in the current `key_layout`.
+    fn document_key(&self, document: DocumentKey) -> Vec<String> {
+        document.key(self.key_layout)
+    }
+
+    /// The key of the feed document `name` in the current `key_layout`.
+    fn feed_key(&self, name: &str) -> Vec<String> {
+        self.document_key(DocumentKey::Feed(name.to_owned()))
+    }
+
+    /// The key of the `index`-th page of the feed in the current
+    /// `key_layout`.  See `page_document()`.
+    fn page_key(&self, feed_id: &str, index: usize) -> Vec<String> {
+        self.document_key(page_document(feed_id, index))
+    }
+
+    /// Names of all feed documents stored in the `layout`.  Keys in the
+    /// other layout are ignored.
+    fn document_names(&self, layout: KeyLayout) -> Result<Vec<String>> {
+        if !self.repository.exists(&[FEEDS_KEY]) {
+            return Ok(vec![]);
+        }
+        let names: Vec<String> =
+            try!(try!(self.repository.list(&[FEEDS_KEY])).collect());
+        let mut documents = vec![];
+        for name in names {
+            let key = [FEEDS_KEY, &name];
+            let children = self.repository.list(&key).ok();
+            match (layout, children) {
+                (KeyLayout::Flat, None) => { documents.push(name.clone()); }
+                (KeyLayout::Sharded, Some(children))
+                        if layout::is_shard_name(&name) => {
+                    for child in children {
+                        documents.push(try!(child));
+                    }
+                }
+                _ => { }
+            }
+        }
+        documents.sort();
+        Ok(documents)
+    }
+
+    /// Archive pages of feeds stored in the `layout`, as pairs of a feed ID
+    /// and a page number.  Keys in the other layout are ignored.
+    fn page_documents(&self, layout: KeyLayout)
+                      -> Result<Vec<(String, usize)>> {
+        let mut pages = vec![];
+        let mut dirs = vec![vec![PAGES_KEY.to_owned()]];
+        while let Some(dir) = dirs.pop() {
+            let names: Vec<String> = match self.repository.list(&dir) {
+                Ok(names) => try!(names.collect()),
+                Err(_) => { continue; }
+            };
+            for name in names {
+                let mut key = dir.clone();
+                key.push(name);
+                match DocumentKey::parse(&key) {
+                    Some((DocumentKey::FeedPage(feed_id, number),
+                          Some(l))) if l == layout => {
+                        pages.push((feed_id, number));
+                    }
+                    // shards and feed IDs are directories of pages
+                    _ if key.len() < 4 => { dirs.push(key); }
+                    _ => { }
+                }
+            }
+        }
+        pages.sort();
+        Ok(pages)
+    }
+
+    fn read_document<T, K>(&self, key: &[K]) -> Result<T>
+        where T: schema::DocumentElement + schema::FromSchemaReader,
+              K: AsRef<str>
+    {
+        let r = try!(self.repository.get_reader(key));
+        Ok(try!(schema::read(r)))
+    }
+}
+
+/// Share the stage with an `AutoFlusher` through `Arc<Mutex<Stage<R>>>` to
+/// flush its buffer in the background.
+#[cfg(feature = "auto-flush")]
+impl<R: Repository> Flushable for Stage<R> {
+    fn maybe_flush(&mut self, now: Instant) -> repo::Result<bool> {
+        Stage::maybe_flush(self, now)
+    }
+
+    fn is_dirty(&self) -> bool { self.repository.is_dirty() }
+
+    fn flush(&mut self) -> repo::Result<()> { Stage::flush(self) }
+}
+
+/// Read the feed of `feed_id` from the repository, reassembling its pages.
+/// See `Stage::read_feed()`.
+fn read_feed_from<S: Repository>(repository: &S, layout: KeyLayout,
+                                 feed_id: &str) -> Result<Feed> {
+    let read_page = |index: usize| -> Result<Feed> {
+        let key = page_document(feed_id, index).key(layout);
+        let r = try!(repository.get_reader(&key));
+        Ok(try!(schema::read(r)))
+    };
+    let mut feed = try!(read_page(0));
+    let mut visited = HashSet::new();
+    visited.insert(0);
+    let mut next_page = next_page_index(feed_id, &feed);
+    feed.links.retain(|l| !is_paging_link(l));
+    while let Some(index) = next_page {
+        if !visited.insert(index) ||
+            !repository.exists(&page_document(feed_id, index).key(layout)) {
+            break;
+        }
+        let page = try!(read_page(index));
+        next_page = next_page_index(feed_id, &page);
+        feed.entries.extend(page.entries);
+    }
+    try!(marks::merge_pending_marks(repository, feed_id, &mut feed));
+    Ok(feed)
+}
+
+/// The name the `index`-th page of the feed is linked by from other pages.
+/// The first page has the same name as the feed itself.
+fn page_name(feed_id: &str, index: usize) -> String {
+    if index == 0 {
+        feed_id.to_owned()
+    } else {
+        format!("{}.{}", feed_id, index + 1)
+    }
+}
+
+/// The document of the `index`-th page of the feed.  The first page is the
+/// feed document itself, and the others are stored apart under `pages/`.
+fn page_document(feed_id: &str, index: usize) -> DocumentKey {
+    if index == 0 {
+        DocumentKey::Feed(feed_id.to_owned())
+    } else {
+        DocumentKey::FeedPage(feed_id.to_owned(), index + 1)
+    }
+}
+
+/// The index of the page of the feed the `name` made by `page_name()` is
+/// of.  It's `None` if the name isn't of any page of the feed.
+fn page_index(feed_id: &str, name: &str) -> Option<usize> {
+    if name == feed_id {
+        return Some(0);
+    }
+    if !name.starts_with(feed_id) || !name[feed_id.len()..].starts_with('.') {
+        return None;
+    }
+    let number = &name[feed_id.len() + 1..];
+    match number.parse::<usize>() {
+        Ok(n) if n > 1 && n.to_string() == number => Some(n - 1),
+        _ => None,
+    }
+}
+
+fn now() -> DateTime<FixedOffset> {
+    UTC::now().with_timezone(&FixedOffset::east(0))
+}
+
+/// Set the mark, and return the previous state if it was changed.
+fn update_mark(mark: &mut Mark, marked: bool, now: DateTime<FixedOffset>,
+               session: Option<&str>) -> Option<bool> {
+    if mark.marked == marked {
+        return None;
+    }
+    mark.marked = marked;
+    mark.updated_at = Some(now);
+    mark.session = session.map(|s| s.to_owned());
+    Some(!marked)
+}
+
+fn subscribe_at<T: AsRef<str>>(list: &mut SubscriptionList, path: &[T],
+                               subscription: Subscription) {
+    if path.is_empty() {
+        list.subscribe(subscription);
+    } else {
+        list.category_mut(path).subscribe(subscription);
+    }
+}
+
+/// Merge two revisions of the feed, regarding the one updated later as
+/// the newer.
+fn merge_feeds(a: Feed, b: Feed) -> Feed {
+    let (mut newer, older) = if a.updated_at > b.updated_at { (a, b) }
+                             else { (b, a) };
+    newer.merge_with(older);
+    newer
+}
+
+/// The index of the page the `page` of the feed links as the next one.
+fn next_page_index(feed_id: &str, page: &Feed) -> Option<usize> {
+    page.links.iter()
+        .find(|l| l.relation == LinkRel::Next ||
+                  l.relation == LinkRel::PrevArchive)
+        .and_then(|l| page_index(feed_id, &l.uri))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JournalPolicy, KeyLayout, Operation, QuotaPolicy, Stage};
+    use super::layout::shard_name;
+
+    use std::str;
+    use std::time::Duration;
+
+    use chrono;
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Link, Mark, MarkKind, SortPolicy, Text};
+    use job::CancellationToken;
+    use repository::{FileSystemRepository, Repository};
+    use schema;
+    use subscribe::{Subscription, SubscriptionSet};
+    use test_utils::temp_dir;
+
+    fn fx_feed(entries: usize) -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        feed.links.push(Link::new("http://example.com/"));
+        for i in 0..entries {
+            feed.entries.push(Entry::new(format!("urn:entry:{}", i),
+                                         Text::plain(format!("{}", i)),
+                                         updated_at));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_stage_feed() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.id, "urn:earthreader:test");
+        assert_eq!(feed.entries.len(), 3);
+        unwrap!(stage.flush());
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        assert!(repo.exists(&["feeds", "feed"]));
+        assert!(!repo.exists(&["pages", "feed", "2"]));
+    }
+
+    #[test]
+    fn test_stage_paged_feed() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("feed", &fx_feed(5)));
+        unwrap!(stage.flush());
+
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let head = unwrap!(repo.read(&["feeds", "feed"]));
+        let head = str::from_utf8(&head).unwrap();
+        assert!(head.contains(r#"href="feed.2" rel="next""#));
+        assert!(head.contains(r#"href="feed.2" rel="prev-archive""#));
+        assert!(!head.contains("<fh:archive"));
+        let last = unwrap!(repo.read(&["pages", "feed", "3"]));
+        let last = str::from_utf8(&last).unwrap();
+        assert!(last.contains("<fh:archive"));
+        assert!(last.contains(r#"href="feed.2" rel="next-archive""#));
+        assert!(!repo.exists(&["pages", "feed", "4"]));
+
+        let stage = Stage::new(repo);
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.entries.iter().map(|e| &e.id[..]).collect::<Vec<_>>(),
+                   ["urn:entry:0", "urn:entry:1", "urn:entry:2",
+                    "urn:entry:3", "urn:entry:4"]);
+        assert_eq!(feed.links, [Link::new("http://example.com/")]);
+    }
+
+    #[test]
+    fn test_stage_shrink_paged_feed() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("feed", &fx_feed(5)));
+        unwrap!(stage.write_feed("feed.tmp", &fx_feed(1)));
+        unwrap!(stage.flush());
+        assert_eq!(unwrap!(stage.feed_ids()), ["feed", "feed.tmp"]);
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        unwrap!(stage.flush());
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        assert!(repo.exists(&["pages", "feed", "2"]));
+        assert!(!repo.exists(&["pages", "feed", "3"]));
+        let mut stage = Stage::new(repo);
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        assert!(!stage.repository.exists(&["pages", "feed", "2"]));
+        assert_eq!(unwrap!(stage.read_feed("feed")).entries.len(), 3);
+    }
+
+    #[test]
+    fn test_stage_paged_feed_ids() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("foo", &fx_feed(5)));
+        unwrap!(stage.write_feed("foo.2", &fx_feed(1)));
+        unwrap!(stage.flush());
+        assert_eq!(unwrap!(stage.feed_ids()), ["foo", "foo.2"]);
+        assert_eq!(unwrap!(stage.read_feed("foo")).entries.len(), 5);
+        assert_eq!(unwrap!(stage.read_feed("foo.2")).entries.len(), 1);
+    }
+
+    #[test]
+    fn test_stage_stream_feed() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        let feed = fx_feed(5);
+        let written = unwrap!(stage.stream_feed("feed", &feed.source,
+                                                feed.entries.clone()));
+        assert_eq!(written, 5);
+        assert_eq!(unwrap!(stage.read_feed("feed")), feed);
+
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("paged", &fx_feed(7)));
+        let written = unwrap!(stage.stream_feed("paged", &feed.source,
+                                                feed.entries.clone()));
+        assert_eq!(written, 5);
+        let read = unwrap!(stage.read_feed("paged"));
+        assert_eq!(read.entries, feed.entries);
+        unwrap!(stage.write_feed("expected", &feed));
+        for i in 0..3 {
+            let read_page = |id| {
+                let key = stage.page_key(id, i);
+                String::from_utf8(unwrap!(stage.repository.read(&key)))
+                    .unwrap()
+            };
+            assert_eq!(read_page("paged"),
+                       read_page("expected").replace("expected", "paged"));
+        }
+        assert!(!stage.repository.exists(&stage.page_key("paged", 3)));
+
+        // zero entries per page means feeds aren't split
+        stage.entries_per_page = Some(0);
+        let written = unwrap!(stage.stream_feed("paged", &feed.source,
+                                                feed.entries.clone()));
+        assert_eq!(written, 5);
+        unwrap!(stage.write_feed("expected", &feed));
+        assert!(!stage.repository.exists(&stage.page_key("paged", 1)));
+        assert!(!stage.repository.exists(&stage.page_key("expected", 1)));
+        assert_eq!(unwrap!(stage.read_feed("paged")).entries, feed.entries);
+    }
+
+    #[test]
+    fn test_stage_sharded_feed() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        stage.key_layout = KeyLayout::Sharded;
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("feed", &fx_feed(5)));
+        unwrap!(stage.write_feed("other", &fx_feed(1)));
+        unwrap!(stage.flush());
+        assert_eq!(unwrap!(stage.feed_ids()), ["feed", "other"]);
+        assert_eq!(unwrap!(stage.read_feed("feed")).entries.len(), 5);
+
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        for name in ["feed", "other"].iter() {
+            assert!(repo.exists(&["feeds", &shard_name(name), name]));
+            assert!(!repo.exists(&["feeds", name]));
+        }
+        for number in ["2", "3"].iter() {
+            let key = ["pages", &shard_name("feed"), "feed", number];
+            assert!(repo.exists(&key));
+            assert!(!repo.exists(&["pages", "feed", number]));
+        }
+        let stage = Stage::new(repo);
+        assert!(unwrap!(stage.feed_ids()).is_empty());
+    }
+
+    #[test]
+    fn test_stage_migrate_key_layout() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("feed", &fx_feed(5)));
+        unwrap!(stage.write_feed("other", &fx_feed(1)));
+        assert_eq!(unwrap!(stage.migrate_key_layout(KeyLayout::Flat)), 0);
+        assert_eq!(unwrap!(stage.migrate_key_layout(KeyLayout::Sharded)), 4);
+        assert_eq!(stage.key_layout, KeyLayout::Sharded);
+        unwrap!(stage.flush());
+        assert!(!stage.repository.exists(&["feeds", "feed"]));
+        assert!(stage.repository.exists(&["pages", &shard_name("feed"),
+                                          "feed", "3"]));
+        assert_eq!(unwrap!(stage.feed_ids()), ["feed", "other"]);
+        assert_eq!(unwrap!(stage.read_feed("feed")).entries.len(), 5);
+
+        assert_eq!(unwrap!(stage.migrate_key_layout(KeyLayout::Flat)), 4);
+        unwrap!(stage.flush());
+        assert!(stage.repository.exists(&["pages", "feed", "3"]));
+        assert_eq!(unwrap!(stage.feed_ids()), ["feed", "other"]);
+        assert_eq!(unwrap!(stage.read_feed("feed")).entries.len(), 5);
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn test_stage_query_entries() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        unwrap!(stage.write_feed("a", &fx_feed(3)));
+        unwrap!(stage.write_feed("b", &fx_feed(2)));
+        unwrap!(stage.set_mark("a", "urn:entry:1", MarkKind::Read, true));
+        let now = FixedOffset::east(0).ymd(2013, 11, 7).and_hms(0, 0, 0);
+        let query = unwrap!("unread AND NOT feed:b".parse());
+        let entries = unwrap!(stage.query_entries(&query, now));
+        let ids: Vec<(&str, &str)> = entries.iter()
+            .map(|(feed_id, e)| (&feed_id[..], &e.id[..]))
+            .collect();
+        assert_eq!(ids, [("a", "urn:entry:0"), ("a", "urn:entry:2")]);
+        let query = unwrap!("since:2013-11-07".parse());
+        assert!(unwrap!(stage.query_entries(&query, now)).is_empty());
+    }
+
+    #[test]
+    fn test_stage_unread_entries() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        let root: &[&str] = &[];
+        unwrap!(stage.subscribe(root, Subscription::new(
+            "b", "http://example.com/b", "B")));
+        unwrap!(stage.subscribe(root, Subscription::new(
+            "a", "http://example.com/a", "A")));
+        unwrap!(stage.subscribe(root, Subscription::new(
+            "new", "http://example.com/new", "New")));
+        unwrap!(stage.write_feed("a", &fx_feed(3)));
+        unwrap!(stage.write_feed("b", &fx_feed(1)));
+        unwrap!(stage.write_feed("unsubscribed", &fx_feed(1)));
+        unwrap!(stage.set_mark("a", "urn:entry:1", MarkKind::Read, true));
+        let entries = unwrap!(stage.unread_entries());
+        let ids: Vec<(&str, &str)> = entries.iter()
+            .map(|(feed_id, e)| (&feed_id[..], &e.id[..]))
+            .collect();
+        assert_eq!(ids, [("b", "urn:entry:0"), ("a", "urn:entry:0"),
+                         ("a", "urn:entry:2")]);
+    }
+
+    #[test]
+    fn test_stage_sorted_entries() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        let mut feed = fx_feed(3);
+        let date = feed.updated_at;
+        let days_ago = |days| date - chrono::Duration::days(days);
+        feed.entries[0].published_at = Some(days_ago(1));
+        feed.entries[1].updated_at = days_ago(2);
+        feed.entries[2].published_at = Some(days_ago(3));
+        unwrap!(stage.write_feed("feed", &feed));
+        let ids = |stage: &Stage<_>| -> Vec<String> {
+            unwrap!(stage.sorted_entries("feed")).into_iter()
+                .map(|e| e.id.clone()).collect()
+        };
+        assert_eq!(ids(&stage), ["urn:entry:0", "urn:entry:2",
+                                 "urn:entry:1"]);
+        feed.entries.reverse();
+        unwrap!(stage.write_feed("feed", &feed));
+        assert_eq!(ids(&stage), ["urn:entry:0", "urn:entry:2",
+                                 "urn:entry:1"]);
+
+        let mut settings = unwrap!(stage.read_settings("feed"));
+        settings.set_sort_policy(Some(SortPolicy::PublishedFirst), date);
+        unwrap!(stage.write_settings("feed", &settings));
+        assert_eq!(ids(&stage), ["urn:entry:0", "urn:entry:1",
+                                 "urn:entry:2"]);
+    }
+
+    #[test]
+    fn test_stage_settings() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        assert!(unwrap!(stage.read_settings("feed")).settings.is_empty());
+        let mut settings = unwrap!(stage.read_settings("feed"));
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        settings.set_crawl_interval(Some(Duration::from_secs(3600)), now);
+        unwrap!(stage.write_settings("feed", &settings));
+        unwrap!(stage.flush());
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let stage = Stage::new(repo);
+        assert_eq!(unwrap!(stage.read_settings("feed")), settings);
+        assert!(unwrap!(stage.read_settings("other")).settings.is_empty());
+    }
+
+    #[test]
+    fn test_stage_enforce_quota() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        let mut feed = fx_feed(20);
+        for (i, entry) in feed.entries.iter_mut().enumerate() {
+            let updated_at = Some(entry.updated_at);
+            entry.read = Mark { marked: i % 2 == 0, updated_at: updated_at,
+                                ..Default::default() };
+            entry.starred = Mark { marked: i % 4 == 0,
+                                   updated_at: updated_at,
+                                   ..Default::default() };
+        }
+        unwrap!(stage.write_feed("feed", &feed));
+        let usage = unwrap!(stage.usage());
+        let policy = QuotaPolicy::DropReadEntries;
+        assert_eq!(unwrap!(stage.enforce_quota(usage, policy)), usage);
+        // read and unstarred entries are 2, 6, 10, 14, 18
+        let reduced = unwrap!(stage.enforce_quota(usage - 1, policy));
+        assert!(reduced < usage);
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.entries.len(), 19);
+        let usage = unwrap!(stage.enforce_quota(0, policy));
+        assert_eq!(usage, unwrap!(stage.usage()));
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.entries.len(), 15);
+        assert!(feed.entries.iter()
+                    .all(|e| e.starred.marked || !e.read.marked));
+        unwrap!(stage.enforce_quota(0, QuotaPolicy::DropOldestEntries));
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.entries.len(), 5);
+        assert!(feed.entries.iter().all(|e| e.starred.marked));
+    }
+
+    fn read_marks<R: Repository>(stage: &Stage<R>) -> Vec<bool> {
+        let feed = unwrap!(stage.read_feed("feed"));
+        feed.entries.iter().map(|e| e.read.marked).collect()
+    }
+
+    #[test]
+    fn test_stage_undo_marks() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        assert!(!unwrap!(stage.undo()));
+
+        assert!(unwrap!(stage.set_mark("feed", "urn:entry:1",
+                                       MarkKind::Read, true)));
+        assert!(!unwrap!(stage.set_mark("feed", "urn:entry:1",
+                                        MarkKind::Read, true)));
+        stage.checkpoint();
+        assert_eq!(unwrap!(stage.mark_all("feed", MarkKind::Read, true)), 2);
+        assert_eq!(read_marks(&stage), [true, true, true]);
+
+        assert!(unwrap!(stage.undo()));
+        assert_eq!(read_marks(&stage), [false, true, false]);
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert!(feed.entries[0].read.updated_at.is_some());
+        assert!(unwrap!(stage.undo()));
+        assert_eq!(read_marks(&stage), [false, false, false]);
+        assert!(!unwrap!(stage.undo()));
+
+        assert!(unwrap!(stage.redo()));
+        assert_eq!(read_marks(&stage), [false, true, false]);
+        assert!(unwrap!(stage.redo()));
+        assert_eq!(read_marks(&stage), [true, true, true]);
+        assert!(!unwrap!(stage.redo()));
+        assert!(unwrap!(stage.undo()));
+        assert_eq!(read_marks(&stage), [false, true, false]);
+    }
+
+    #[test]
+    fn test_stage_set_marks() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        stage.journal_policy = Some(JournalPolicy {
+            records_per_segment: 2,
+            max_segments: None,
+        });
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        unwrap!(stage.write_feed("other", &fx_feed(2)));
+        unwrap!(stage.record(Operation::Redone));
+        let entries = [("feed", "urn:entry:0"), ("other", "urn:entry:1"),
+                       ("feed", "urn:entry:2"), ("feed", "urn:entry:0"),
+                       ("other", "urn:entry:9")];
+        assert_eq!(unwrap!(stage.set_marks(entries.iter().cloned(),
+                                           MarkKind::Read, true)), 3);
+        assert_eq!(read_marks(&stage), [true, false, true]);
+        let other = unwrap!(stage.read_feed("other"));
+        assert!(other.entries[1].read.marked);
+        // feed documents aren't rewritten until marks are folded
+        let stored = |stage: &Stage<_>, feed_id: &str| -> Vec<bool> {
+            let buf = unwrap!(stage.repository.read(&stage.feed_key(feed_id)));
+            let feed: Feed = unwrap!(schema::read(&buf[..]));
+            feed.entries.iter().map(|e| e.read.marked).collect()
+        };
+        assert_eq!(stored(&stage, "feed"), [false, false, false]);
+        let records = unwrap!(stage.read_journal());
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].operation, Operation::Marked {
+            feed_id: "feed".to_owned(),
+            entry_id: "urn:entry:0".to_owned(),
+            kind: MarkKind::Read,
+            marked: true,
+        });
+        unwrap!(stage.flush());
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let names: Vec<String> = unwrap!(unwrap!(repo.list(&["journal"]))
+                                             .collect());
+        assert_eq!(names.len(), 2);
+
+        assert!(unwrap!(stage.undo()));
+        assert_eq!(read_marks(&stage), [false, false, false]);
+        assert_eq!(unwrap!(stage.set_marks(vec![], MarkKind::Read, true)), 0);
+
+        // nothing is changed if any of the feeds is missing
+        let entries = [("feed", "urn:entry:1"), ("missing", "urn:entry:0")];
+        assert!(stage.set_marks(entries.iter().cloned(), MarkKind::Read,
+                                true).is_err());
+        assert_eq!(read_marks(&stage), [false, false, false]);
+        assert_eq!(unwrap!(stage.read_journal()).len(), 5);
+
+        let entries = [("feed", "urn:entry:1"), ("other", "urn:entry:0")];
+        assert_eq!(unwrap!(stage.set_marks(entries.iter().cloned(),
+                                           MarkKind::Read, true)), 2);
+        let cancel = CancellationToken::new();
+        assert_eq!(unwrap!(stage.fold_marks(&cancel)), 2);
+        assert_eq!(stored(&stage, "feed"), [false, true, false]);
+        assert_eq!(stored(&stage, "other"), [true, false]);
+        assert_eq!(unwrap!(stage.repository.list(&["pending-marks"]))
+                       .count(), 0);
+        assert_eq!(read_marks(&stage), [false, true, false]);
+        assert_eq!(unwrap!(stage.fold_marks(&cancel)), 0);
+    }
+
+    #[test]
+    fn test_stage_undo_unsubscribe() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        assert!(unwrap!(stage.read_subscriptions()).subscriptions.is_empty());
+        let subscription = Subscription::new("feed", "http://example.com/feed",
+                                             "Example");
+        assert!(unwrap!(stage.subscribe(&["News"], subscription.clone())));
+        assert!(!unwrap!(stage.subscribe(&["News"], subscription.clone())));
+        stage.checkpoint();
+        assert_eq!(unwrap!(stage.unsubscribe("feed")), Some(subscription));
+        assert!(unwrap!(stage.read_subscriptions()).find("feed").is_none());
+        assert!(unwrap!(stage.undo()));
+        let list = unwrap!(stage.read_subscriptions());
+        assert_eq!(list.categories[0].label, "News");
+        assert_eq!(list.categories[0].subscriptions[0].feed_id, "feed");
+        assert!(unwrap!(stage.redo()));
+        assert!(unwrap!(stage.read_subscriptions()).find("feed").is_none());
+    }
+
+    #[test]
+    fn test_stage_journal() {
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let mut stage = Stage::new(repo);
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        unwrap!(stage.set_mark("feed", "urn:entry:0", MarkKind::Read, true));
+        assert!(unwrap!(stage.read_journal()).is_empty());
+
+        stage.journal_policy = Some(JournalPolicy {
+            records_per_segment: 2,
+            max_segments: Some(2),
+        });
+        unwrap!(stage.record(Operation::Crawled {
+            feed_id: "feed".to_owned(),
+            entries: 3,
+        }));
+        unwrap!(stage.mark_all("feed", MarkKind::Read, true));
+        unwrap!(stage.undo());
+        let operations: Vec<_> = unwrap!(stage.read_journal())
+            .into_iter().map(|r| r.operation).collect();
+        assert_eq!(operations.len(), 4);
+        assert_eq!(operations[0], Operation::Crawled {
+            feed_id: "feed".to_owned(),
+            entries: 3,
+        });
+        assert_eq!(operations[1], Operation::Marked {
+            feed_id: "feed".to_owned(),
+            entry_id: "urn:entry:1".to_owned(),
+            kind: MarkKind::Read,
+            marked: true,
+        });
+        assert_eq!(operations[3], Operation::Undone);
+
+        // the oldest segment is removed by rotation
+        unwrap!(stage.record(Operation::Redone));
+        let operations: Vec<_> = unwrap!(stage.read_journal())
+            .into_iter().map(|r| r.operation).collect();
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[2], Operation::Redone);
+        unwrap!(stage.flush());
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let names: Vec<String> = unwrap!(unwrap!(repo.list(&["journal"]))
+                                             .collect());
+        assert_eq!(names.len(), 2);
+    }
+
+    #[cfg(feature = "auto-flush")]
+    #[test]
+    fn test_stage_auto_flusher() {
+        use std::sync::{Arc, Mutex};
+        use super::AutoFlusher;
+
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let stage = Arc::new(Mutex::new(Stage::new(repo)));
+        let flusher = AutoFlusher::spawn(stage.clone(),
+                                         Duration::from_millis(10));
+        unwrap!(stage.lock().unwrap().write_feed("feed", &fx_feed(1)));
+        unwrap!(flusher.stop());
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        assert!(repo.exists(&["feeds", "feed"]));
+    }
+
+    #[cfg(feature = "auto-flush")]
+    #[test]
+    fn test_auto_flusher_poisoned() {
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+        use repository::Error;
+        use super::AutoFlusher;
+
+        let tmpdir = temp_dir();
+        let repo = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+        let stage = Arc::new(Mutex::new(Stage::new(repo)));
+        let poisoner = stage.clone();
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison the stage");
+        }).join();
+        let flusher = AutoFlusher::spawn(stage, Duration::from_millis(10));
+        match flusher.stop() {
+            Err(Error::CannotBorrow) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+mod dirtybuffer {
+    use repository as repo;
+    use repository::{Names, Repository, write_atomically};
+    use repository::metrics::{Counters, Metrics};
+
+    use std::borrow::ToOwned;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::collections::hash_map::Entry;
+    use std::io;
+    use std::sync::{Mutex, Weak};
+    use std::time::{Duration, Instant};
+
+    use job::CancellationToken;
+
+    use super::snapshot::{Preserved, preserving};
+
+    #[cfg(feature = "auto-flush")]
+    pub use self::auto::{AutoFlusher, Flushable};
+
+    enum NestedItem<K, V> {
+        Item(V), Map(HashMap<K, NestedItem<K, V>>)
+    }
+    type NestedMap<K, V> = HashMap<K, NestedItem<K, V>>;
+
+    type PathKey = String;
+    type Dictionary = NestedMap<PathKey, Option<Vec<u8>>>;
+
+    /// When `DirtyBuffer::maybe_flush()` flushes the buffer.  Any of the
+    /// conditions which is set triggers a flush.  The default policy never
+    /// flushes automatically.
+    #[derive(Clone, Default, PartialEq, Eq, Debug)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct FlushPolicy {
+        /// Flush after this number of writes.
+        pub max_writes: Option<usize>,
+
+        /// Flush when nothing has been written for this duration since the
+        /// last write.
+        pub idle_timeout: Option<Duration>,
+
+        /// Flush when the app goes background.  Apps are always flushed
+        /// when they terminate regardless of this.
+        pub on_background: bool,
+    }
+
+    /// Lifecycle events of apps that `DirtyBuffer::handle_event()` takes.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum LifecycleEvent {
+        /// The app went background, e.g. mobile apps which might be killed
+        /// by the system without any notice.
+        Background,
+
+        /// The app is about to terminate.
+        Terminate,
+    }
+
+    /// Metrics on flushes.
+    #[derive(Clone, Default, PartialEq, Eq, Debug)]
+    pub struct FlushStats {
+        /// The number of flushes.
+        pub count: u64,
+
+        /// The total time taken by flushes.
+        pub total: Duration,
+
+        /// The time taken by the slowest flush.
+        pub max: Duration,
+
+        /// The time taken by the last flush.
+        pub last: Option<Duration>,
+    }
+
+    pub struct DirtyBuffer<R> {
+        inner: R,
+        dictionary: Dictionary,
+        pub policy: FlushPolicy,
+        pending_writes: usize,
+        last_write: Option<Instant>,
+        stats: FlushStats,
+        counters: Counters,
+        generation: u64,
+        snapshots: Vec<Weak<Mutex<Preserved>>>,
+        writing: HashSet<Vec<String>>,
+    }
+
+    impl<R: Repository> DirtyBuffer<R> {
+        pub fn new(repo: R) -> DirtyBuffer<R> {
+            DirtyBuffer {
+                inner: repo,
+                dictionary: HashMap::new(),
+                policy: Default::default(),
+                pending_writes: 0,
+                last_write: None,
+                stats: Default::default(),
+                counters: Counters::new(),
+                generation: 0,
+                snapshots: vec![],
+                writing: HashSet::new(),
+            }
+        }
+
+        pub fn flush(&mut self) -> repo::Result<()> {
+            try!(self.flush_cancellable(&CancellationToken::new()));
+            Ok(())
+        }
+
+        /// Flush documents one by one until `cancel` is cancelled.  It
+        /// returns whether all of them were flushed; the rest are left
+        /// buffered.
+        pub fn flush_cancellable(&mut self, cancel: &CancellationToken)
+                                 -> repo::Result<bool> {
+            let started_at = Instant::now();
+            let keys: Vec<Vec<String>> =
+                self.pending().into_iter().map(|(key, _)| key).collect();
+            let done = {
+                let dictionary = &mut self.dictionary;
+                try!(preserving(&mut self.inner, &mut self.snapshots, &keys,
+                                |inner| {
+                    _flush(inner, dictionary, vec![], cancel)
+                }))
+            };
+            let remaining = self.pending().len();
+            if remaining < keys.len() {
+                self.generation += 1;
+            }
+            let elapsed = started_at.elapsed();
+            self.pending_writes = remaining;
+            if remaining == 0 {
+                self.last_write = None;
+            }
+            self.stats.count += 1;
+            self.stats.total += elapsed;
+            if elapsed > self.stats.max {
+                self.stats.max = elapsed;
+            }
+            self.stats.last = Some(elapsed);
+            Ok(done)
+        }
+
+        /// Whether there are buffered changes that aren't flushed yet.
+        pub fn is_dirty(&self) -> bool { self.pending_writes > 0 }
+
+        /// Flush the buffer if it's dirty and the `policy` says so at the
+        /// time of `now`.  Apps may call this periodically e.g. from their
+        /// event loop.  It returns whether it flushed.
+        pub fn maybe_flush(&mut self, now: Instant) -> repo::Result<bool> {
+            if !self.is_dirty() {
+                return Ok(false);
+            }
+            let by_writes = self.policy.max_writes
+                                .is_some_and(|n| self.pending_writes >= n);
+            let by_idle = match (self.policy.idle_timeout, self.last_write) {
+                (Some(timeout), Some(last)) => now >= last + timeout,
+                _ => false,
+            };
+            if !by_writes && !by_idle {
+                return Ok(false);
+            }
+            try!(self.flush());
+            Ok(true)
+        }
+
+        /// Flush the buffer on the lifecycle event if the `policy` says so.
+        /// It returns whether it flushed.
+        pub fn handle_event(&mut self, event: LifecycleEvent)
+                            -> repo::Result<bool> {
+            let flush = match event {
+                LifecycleEvent::Background => self.policy.on_background,
+                LifecycleEvent::Terminate => true,
+            };
+            if !flush || !self.is_dirty() {
+                return Ok(false);
+            }
+            try!(self.flush());
+            Ok(true)
+        }
+
+        pub fn stats(&self) -> &FlushStats { &self.stats }
+
+        /// Counters of documents read and written through the buffer, and
+        /// of flushes.
+        pub fn metrics(&self) -> Metrics {
+            Metrics {
+                flushes: self.stats.count,
+                last_flush: self.stats.last,
+                ..self.counters.snapshot()
+            }
+        }
+
+        /// Reset `metrics()` to zero.  `stats()` is reset as well.
+        pub fn reset_metrics(&mut self) {
+            self.counters.reset();
+            self.stats = Default::default();
+        }
+
+        /// The underlying repository, without buffered changes.
+        pub fn inner(&self) -> &R { &self.inner }
+
+        /// The number of flushes which wrote documents so far.
+        pub fn generation(&self) -> u64 { self.generation }
+
+        /// Keep the snapshot seeing documents as of now; see the `snapshot`
+        /// module.
+        pub fn register_snapshot(&mut self,
+                                 preserved: Weak<Mutex<Preserved>>) {
+            self.snapshots.push(preserved);
+        }
+
+        /// Keys of buffered documents along with their contents, or `None`
+        /// for deleted ones, sorted by keys.
+        pub fn pending(&self) -> Vec<(Vec<String>, Option<&[u8]>)> {
+            let mut pending = vec![];
+            _pending(&self.dictionary, &mut vec![], &mut pending);
+            pending.sort_by(|a, b| a.0.cmp(&b.0));
+            pending
+        }
+
+        /// Append `data` to the document of the `key`, or write it if
+        /// there's no such document.  A buffered document is extended in
+        /// place rather than rewritten, so that appending records one by
+        /// one, e.g. to the journal, costs only as much as the records.
+        pub fn append<T: AsRef<str>>(&mut self, key: &[T], data: &[u8])
+                                     -> repo::Result<()> {
+            let key: Vec<String> =
+                key.iter().map(|k| k.as_ref().to_owned()).collect();
+            if !is_writable(&self.dictionary, &key) {
+                return Err(repo::Error::invalid_key(&key[..], None));
+            }
+            if self.writing.iter().any(|k| overlaps(k, &key)) {
+                return Err(repo::Error::CannotBorrow);
+            }
+            let stored = match find_item(&self.dictionary, &key) {
+                FindResult::NotFound if self.inner.exists(&key) => {
+                    Some(try!(self.inner.read(&key)))
+                }
+                _ => None,
+            };
+            self.touch();
+            match dig(&mut self.dictionary, &key) {
+                Some(slot) => {
+                    if let Some(stored) = stored {
+                        *slot = Some(stored);
+                    }
+                    match *slot {
+                        Some(ref mut buf) => buf.extend_from_slice(data),
+                        None => { *slot = Some(data.to_vec()); }
+                    }
+                }
+                None => {
+                    return Err(repo::Error::invalid_key(&key[..], None));
+                }
+            }
+            self.counters.append(data.len());
+            Ok(())
+        }
+
+        fn touch(&mut self) {
+            self.pending_writes += 1;
+            self.last_write = Some(Instant::now());
+        }
+    }
+
+    #[cfg(feature = "auto-flush")]
+    mod auto {
+        use repository as repo;
+        use repository::Repository;
+
+        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        use super::DirtyBuffer;
+
+        /// Buffers which `AutoFlusher` can flush in the background:
+        /// `DirtyBuffer` itself, and `Stage` which owns one.
+        pub trait Flushable {
+            /// See `DirtyBuffer::maybe_flush()`.
+            fn maybe_flush(&mut self, now: Instant) -> repo::Result<bool>;
+            /// Whether there are buffered writes not flushed yet.
+            fn is_dirty(&self) -> bool;
+            /// Write all buffered documents.
+            fn flush(&mut self) -> repo::Result<()>;
+        }
+
+        impl<R: Repository> Flushable for DirtyBuffer<R> {
+            fn maybe_flush(&mut self, now: Instant) -> repo::Result<bool> {
+                DirtyBuffer::maybe_flush(self, now)
+            }
+
+            fn is_dirty(&self) -> bool { DirtyBuffer::is_dirty(self) }
+
+            fn flush(&mut self) -> repo::Result<()> {
+                DirtyBuffer::flush(self)
+            }
+        }
+
+        /// The background thread which periodically calls `maybe_flush()`
+        /// of the shared buffer or stage.  It's flushed for the last time
+        /// when the thread is stopped or dropped.
+        pub struct AutoFlusher {
+            stopped: Arc<AtomicBool>,
+            thread: Option<thread::JoinHandle<repo::Result<()>>>,
+        }
+
+        impl AutoFlusher {
+            /// Start the thread which checks the buffer every `interval`.
+            /// The thread ends with `Error::CannotBorrow` if the mutex is
+            /// poisoned by a panic of another thread holding it.
+            pub fn spawn<F>(buffer: Arc<Mutex<F>>,
+                            interval: Duration) -> AutoFlusher
+                where F: Flushable + Send + 'static
+            {
+                let stopped = Arc::new(AtomicBool::new(false));
+                let flag = stopped.clone();
+                let thread = thread::spawn(move || {
+                    while !flag.load(Ordering::SeqCst) {
+                        thread::sleep(interval);
+                        let mut buffer = try!(
+                            buffer.lock()
+                                .map_err(|_| repo::Error::CannotBorrow)
+                        );
+                        try!(buffer.maybe_flush(Instant::now()));
+                    }
+                    let mut buffer = try!(
+                        buffer.lock().map_err(|_| repo::Error::CannotBorrow)
+                    );
+                    if buffer.is_dirty() {
+                        try!(buffer.flush());
+                    }
+                    Ok(())
+                });
+                AutoFlusher { stopped: stopped, thread: Some(thread) }
+            }
+
+            /// Stop the thread, and return the error it met if any.
+            pub fn stop(mut self) -> repo::Result<()> { self.join() }
+
+            fn join(&mut self) -> repo::Result<()> {
+                self.stopped.store(true, Ordering::SeqCst);
+                match self.thread.take() {
+                    Some(thread) => match thread.join() {
+                        Ok(result) => result,
+                        Err(_) => Err(repo::Error::CannotBorrow),
+                    },
+                    None => Ok(()),
+                }
+            }
+        }
+
+        impl Drop for AutoFlusher {
+            fn drop(&mut self) {
+                let _ = self.join();
+            }
+        }
+    }
+
+    fn _flush<R: Repository>(repo: &mut R,
+                             _dictionary: &mut Dictionary,
+                             _key: Vec<String>,
+                             cancel: &CancellationToken)
+                             -> repo::Result<bool> {
+        let names: Vec<PathKey> = _dictionary.keys().cloned().collect();
+        for k in names {
+            if cancel.is_cancelled() {
+                return Ok(false);
+            }
+            let mut key = _key.clone();
+            key.push(k.clone());
+            let done = match *_dictionary.get_mut(&k).unwrap() {
+                NestedItem::Map(ref mut m) => {
+                    try!(_flush(repo, m, key, cancel))
+                }
+                NestedItem::Item(Some(ref v)) => {
+                    // TODO: merge with inner repo
+                    try!(write_atomically(repo, &key, Some(&v[..])));
+                    true
+                }
+                NestedItem::Item(None) => {
+                    // deleted
+                    if repo.exists(&key) {
+                        try!(repo.delete(&key));
+                    }
+                    true
+                }
+            };
+            if !done {
+                return Ok(false);
+            }
+            // flushed documents are dropped one by one, so that the rest is
+            // left buffered if it's cancelled
+            _dictionary.remove(&k);
+        }
+        Ok(true)
+    }
+
+    fn _pending<'a>(dictionary: &'a Dictionary, key: &mut Vec<String>,
+                    pending: &mut Vec<(Vec<String>, Option<&'a [u8]>)>) {
+        for (k, value) in dictionary.iter() {
+            key.push(k.clone());
+            match *value {
+                NestedItem::Map(ref m) => { _pending(m, key, pending); }
+                NestedItem::Item(ref v) => {
+                    pending.push((key.clone(), v.as_ref().map(|v| &v[..])));
+                }
+            }
+            key.pop();
+        }
+    }
+
+    impl<R: Repository> Repository for DirtyBuffer<R> {
+        fn get_reader<'a, T: AsRef<str>>(&'a self, key: &[T]) ->
+            repo::Result<Box<io::BufRead + 'a>>
+        {
+            let b = match find_item(&self.dictionary, key) {
+                FindResult::Found(&NestedItem::Item(Some(ref v))) => v,
+                FindResult::NotFound => {
+                    let reader = try!(self.inner.get_reader(key));
+                    return Ok(self.counters.read(reader));
+                }
+                _ => { return Err(repo::Error::invalid_key(key, None)); }
+            };
+            self.counters.hit();
+            let reader = io::BufReader::new(&b[..]);
+            Ok(self.counters.read(Box::new(reader) as Box<io::BufRead>))
+        }
+
+        fn get_reader_range<'a, T: AsRef<str>>(&'a self, key: &[T],
+                                               start: u64,
+                                               len: Option<u64>) ->
+            repo::Result<Box<io::BufRead + 'a>>
+        {
+            let b = match find_item(&self.dictionary, key) {
+                FindResult::Found(&NestedItem::Item(Some(ref v))) => v,
+                FindResult::NotFound => {
+                    let reader = try!(self.inner.get_reader_range(key, start,
+                                                                  len));
+                    return Ok(self.counters.read(reader));
+                }
+                _ => { return Err(repo::Error::invalid_key(key, None)); }
+            };
+            self.counters.hit();
+            let reader = repo::range_reader(io::Cursor::new(&b[..]), start,
+                                            len);
+            Ok(self.counters.read(reader))
+        }
+
+        fn get_writer<'a, T: AsRef<str>>(&'a mut self, key: &[T]) ->
+            repo::Result<Box<io::Write + 'a>>
+        {
+            let key: Vec<String> =
+                key.iter().map(|k| k.as_ref().to_owned()).collect();
+            if !is_writable(&self.dictionary, &key) {
+                return Err(repo::Error::invalid_key(&key[..], None));
+            }
+            if self.writing.iter().any(|k| overlaps(k, &key)) {
+                return Err(repo::Error::CannotBorrow);
+            }
+            self.touch();
+            self.writing.insert(key.clone());
+            let writer = DirtyWriter {
+                dictionary: &mut self.dictionary,
+                writing: &mut self.writing,
+                key: key,
+                buffer: Vec::new(),
+            };
+            Ok(self.counters.write(Box::new(writer) as Box<io::Write>))
+        }
+
+        fn exists<T: AsRef<str>>(&self, key: &[T]) -> bool {
+            match find_item(&self.dictionary, key) {
+                FindResult::Found(&NestedItem::Item(None)) => false,
+                FindResult::Found(_) => true,
+                FindResult::NotFound => self.inner.exists(key),
+                FindResult::InvalidKey => false,
+            }
+        }
+
+        fn delete<T: AsRef<str>>(&mut self, key: &[T]) -> repo::Result<()> {
+            let deletable = match find_item(&self.dictionary, key) {
+                FindResult::Found(&NestedItem::Item(Some(_))) => true,
+                FindResult::NotFound => {
+                    self.inner.exists(key) && self.inner.list(key).is_err()
+                }
+                _ => false,
+            };
+            if !deletable {
+                return Err(repo::Error::invalid_key(key, None));
+            }
+            self.touch();
+            match dig(&mut self.dictionary, key) {
+                Some(slot) => { *slot = None; }
+                None => { return Err(repo::Error::invalid_key(key, None)); }
+            }
+            self.counters.delete();
+            Ok(())
+        }
+
+        /// Buffered as well: the document is moved in the buffer, and the
+        /// `from` key is marked as deleted.  It's written through
+        /// `write_atomically()` on flush as other documents are.
+        fn rename<T, U>(&mut self, from: &[T], to: &[U]) -> repo::Result<()>
+            where T: AsRef<str>, U: AsRef<str>
+        {
+            let from: Vec<String> =
+                from.iter().map(|k| k.as_ref().to_owned()).collect();
+            let to: Vec<String> =
+                to.iter().map(|k| k.as_ref().to_owned()).collect();
+            if self.writing.iter().any(|k| overlaps(k, &from) ||
+                                           overlaps(k, &to)) {
+                return Err(repo::Error::CannotBorrow);
+            }
+            let to_dir = match find_item(&self.dictionary, &to) {
+                FindResult::Found(&NestedItem::Map(_)) => true,
+                FindResult::NotFound => self.inner.list(&to).is_ok(),
+                _ => false,
+            };
+            if to_dir || overlaps(&from, &to) ||
+               !is_writable(&self.dictionary, &to) {
+                return Err(repo::Error::invalid_key(&to[..], None));
+            }
+            let data = match find_item(&self.dictionary, &from) {
+                FindResult::Found(&NestedItem::Item(Some(_))) => {
+                    dig(&mut self.dictionary, &from).and_then(|s| s.take())
+                }
+                FindResult::NotFound if self.inner.exists(&from) &&
+                                        self.inner.list(&from).is_err() => {
+                    let data = try!(self.inner.read(&from));
+                    dig(&mut self.dictionary, &from).map(|_| data)
+                }
+                _ => None,
+            };
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    return Err(repo::Error::invalid_key(&from[..], None));
+                }
+            };
+            self.touch();
+            match dig(&mut self.dictionary, &to) {
+                Some(slot) => { *slot = Some(data); }
+                None => {
+                    return Err(repo::Error::invalid_key(&to[..], None));
+                }
+            }
+            self.counters.rename();
+            Ok(())
+        }
+
+        fn list<T: AsRef<str>>(&self, key: &[T]) -> repo::Result<Names> {
+            let d = if key.is_empty() {
+                &self.dictionary
+            } else {
+                match find_item(&self.dictionary, key) {
+                    FindResult::Found(NestedItem::Map(v)) => v,
+                    FindResult::NotFound => { return self.inner.list(key); }
+                    _ => {
+                        return Err(repo::Error::invalid_key(key, None));
+                    }
+                }
+            };
+            // deleted keys must not be listed from the inner repository
+            let mut names = BTreeSet::new();
+            if let Ok(inner) = self.inner.list(key) {
+                for name in inner {
+                    let name = try!(name);
+                    if !d.contains_key(&name) {
+                        names.insert(name);
+                    }
+                }
+            }
+            names.extend(d.iter().filter_map(|(k, v)| match *v {
+                NestedItem::Item(None) => None,
+                _ => Some(k.clone()),
+            }));
+            let names: Vec<repo::Result<String>> =
+                names.into_iter().map(Ok).collect();
+            Ok(Box::new(names.into_iter()) as Names)
+        }
+
+        /// The usage of the inner repository, corrected by buffered
+        /// documents, so that unchanged documents aren't read.
+        fn usage<T: AsRef<str>>(&self, key: &[T]) -> repo::Result<u64> {
+            let mut key: Vec<String> =
+                key.iter().map(|k| k.as_ref().to_owned()).collect();
+            let d = if key.is_empty() {
+                &self.dictionary
+            } else {
+                match find_item(&self.dictionary, &key) {
+                    FindResult::Found(NestedItem::Item(Some(v))) => {
+                        return Ok(v.len() as u64);
+                    }
+                    FindResult::Found(NestedItem::Map(m)) => m,
+                    FindResult::NotFound => { return self.inner.usage(&key); }
+                    _ => {
+                        return Err(repo::Error::invalid_key(&key, None));
+                    }
+                }
+            };
+            let mut total = if key.is_empty() || self.inner.exists(&key) {
+                try!(self.inner.usage(&key))
+            } else {
+                0
+            };
+            try!(_buffered_usage(&self.inner, d, &mut key, &mut total));
+            Ok(total)
+        }
+    }
+
+    /// Replace the inner usage of documents buffered in `_dictionary` with
+    /// their buffered sizes.
+    fn _buffered_usage<R: Repository>(inner: &R, _dictionary: &Dictionary,
+                                      key: &mut Vec<String>, total: &mut u64)
+                                      -> repo::Result<()> {
+        for (name, item) in _dictionary.iter() {
+            key.push(name.clone());
+            match *item {
+                NestedItem::Map(ref m) => {
+                    try!(_buffered_usage(inner, m, key, total));
+                }
+                NestedItem::Item(ref v) => {
+                    if inner.exists(key) {
+                        let size = try!(inner.usage(key));
+                        *total = total.saturating_sub(size);
+                    }
+                    if let Some(ref v) = *v {
+                        *total += v.len() as u64;
+                    }
+                }
+            }
+            key.pop();
+        }
+        Ok(())
+    }
+
+    /// The writer `DirtyBuffer::get_writer()` returns.  What's written is
+    /// kept aside, and replaces the document only when the writer is
+    /// dropped, so readers see the document as it was until then.  While
+    /// a writer is alive, no other writer for the same key, nor for keys
+    /// under or above it, can be obtained; `get_writer()` fails with
+    /// `CannotBorrow` instead.  A leaked writer, e.g. by `mem::forget()`,
+    /// never replaces the document and keeps holding its key.
+    pub struct DirtyWriter<'a> {
+        dictionary: &'a mut Dictionary,
+        writing: &'a mut HashSet<Vec<String>>,
+        key: Vec<String>,
+        buffer: Vec<u8>,
+    }
+
+    impl<'a> io::Write for DirtyWriter<'a> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffer.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.buffer.flush()
+        }
+    }
+
+    impl<'a> Drop for DirtyWriter<'a> {
+        fn drop(&mut self) {
+            if let Some(slot) = dig(self.dictionary, &self.key[..]) {
+                *slot = Some(std::mem::take(&mut self.buffer));
+            }
+            self.writing.remove(&self.key);
+        }
+    }
+
+    /// Whether one of the keys is the other or under it.
+    fn overlaps(a: &[String], b: &[String]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| a == b)
+    }
+
+    /// Whether a document can be written to the key, i.e. neither the key
+    /// nor its parents are the other kind of item in the dictionary.
+    fn is_writable(dict: &Dictionary, key: &[String]) -> bool {
+        let (head, tail) = match key.split_first() {
+            Some(v) => v,
+            None => { return false; }
+        };
+        match dict.get(head) {
+            None => true,
+            Some(&NestedItem::Item(_)) => tail.is_empty(),
+            Some(NestedItem::Map(m)) => {
+                !tail.is_empty() && is_writable(m, tail)
+            }
+        }
+    }
+
+    enum FindResult<T> {
+        Found(T),
+        NotFound,
+        InvalidKey,
+    }
+
+    fn find_item<'a, T: AsRef<str>>(dict: &'a Dictionary, key: &[T]) ->
+        FindResult<&'a NestedItem<PathKey, Option<Vec<u8>>>>
+    {
+        let head = match key.first() {
+            Some(k) => k,
+            None => { return FindResult::InvalidKey; }
+        };
+        let tail = &key[1..];
+        match dict.get(head.as_ref()) {
+            Some(v) if tail.is_empty() => FindResult::Found(v),
+            Some(NestedItem::Map(m)) => find_item(m, &key[1..]),
+            None => FindResult::NotFound,
+            _ => FindResult::InvalidKey,
+        }
+    }
+
+    fn dig<'a, T: AsRef<str>>(map: &'a mut Dictionary, key: &[T]) ->
+        Option<&'a mut Option<Vec<u8>>>
+    {
+        let head = match key.first() {
+            Some(k) => k.as_ref().to_owned(),
+            None => { return None; }
+        };
+        let tail = &key[1..];
+        let next = match map.entry(head) {
+            Entry::Occupied(slot) => match *slot.into_mut() {
+                NestedItem::Item(ref mut v) if tail.is_empty() => {
+                    return Some(v);
+                }
+                NestedItem::Map(ref mut m) => m,
+                _ => { return None; }
+            },
+            Entry::Vacant(slot) => {
+                if tail.is_empty() {
+                    match slot.insert(NestedItem::Item(None)) {
+                        &mut NestedItem::Item(ref mut v) => {
+                            return Some(v);
+                        }
+                        _ => unreachable!()
+                    }
+                } else {
+                    match slot.insert(NestedItem::Map(HashMap::new())) {
+                        &mut NestedItem::Map(ref mut m) => m,
+                        _ => unreachable!()
+                    }
+                }
+            }
+        };
+        dig(next, tail)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::{DirtyBuffer, FlushPolicy, LifecycleEvent};
+
+        use std::time::{Duration, Instant};
+
+        use job::CancellationToken;
+        use test_utils::temp_dir;
+        use repository::{FileSystemRepository, Repository};
+        use repository::temp::TEMP_DIR;
+        use repository::test::test_repository;
+        
+        #[test]
+        fn test_dirty_buffer() {
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            let dirty_buffer = DirtyBuffer::new(f);
+            test_repository(dirty_buffer);
+        }
+
+        #[test]
+        fn test_dirty_buffer_append() {
+            let tmpdir = temp_dir();
+            let mut f =
+                FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            unwrap!(f.write(&["stored"], ["a"]));
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.append(&["stored"], b"b"));
+            unwrap!(dirty_buffer.append(&["stored"], b"c"));
+            unwrap!(dirty_buffer.append(&["new", "key"], b"d"));
+            assert_eq!(unwrap!(dirty_buffer.read(&["stored"])), b"abc");
+            assert_eq!(unwrap!(dirty_buffer.read(&["new", "key"])), b"d");
+            unwrap!(dirty_buffer.flush());
+            assert_eq!(unwrap!(dirty_buffer.inner().read(&["stored"])),
+                       b"abc");
+            assert!(dirty_buffer.append(&["new"], b"e").is_err());
+        }
+
+        #[test]
+        fn test_dirty_buffer_flush() {
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.write(&["a", "key"], ["a"]));
+            unwrap!(dirty_buffer.write(&["b", "key"], ["b"]));
+            unwrap!(dirty_buffer.write(&["key"], ["key"]));
+            unwrap!(dirty_buffer.flush());
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert_eq!(unwrap!(f.read(&["a", "key"])), b"a");
+            assert_eq!(unwrap!(f.read(&["b", "key"])), b"b");
+            assert_eq!(unwrap!(f.read(&["key"])), b"key");
+
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.delete(&["a", "key"]));
+            assert!(!dirty_buffer.exists(&["a", "key"]));
+            assert!(unwrap!(dirty_buffer.list(&["a"])).next().is_none());
+            unwrap!(dirty_buffer.flush());
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert!(!f.exists(&["a", "key"]));
+            assert!(f.exists(&["b", "key"]));
+        }
+
+        #[test]
+        fn test_dirty_buffer_rename() {
+            let tmpdir = temp_dir();
+            let mut f =
+                FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            unwrap!(f.write(&["stored"], ["stored"]));
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.write(&["new"], ["new"]));
+            unwrap!(dirty_buffer.rename(&["stored"], &["dir", "moved"]));
+            unwrap!(dirty_buffer.rename(&["new"], &["renamed"]));
+            assert!(!dirty_buffer.exists(&["stored"]));
+            assert!(!dirty_buffer.exists(&["new"]));
+            assert_eq!(unwrap!(dirty_buffer.read(&["dir", "moved"])),
+                       b"stored");
+            assert_eq!(unwrap!(dirty_buffer.read(&["renamed"])), b"new");
+            // not written until flushed
+            assert!(dirty_buffer.inner().exists(&["stored"]));
+            assert!(dirty_buffer.rename(&["missing"], &["key"]).is_err());
+            assert!(dirty_buffer.rename(&["renamed"], &["dir"]).is_err());
+            unwrap!(dirty_buffer.flush());
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert!(!f.exists(&["stored"]));
+            assert!(!f.exists(&["new"]));
+            assert_eq!(unwrap!(f.read(&["dir", "moved"])), b"stored");
+            assert_eq!(unwrap!(f.read(&["renamed"])), b"new");
+            // flushed through temporary keys, which are renamed
+            assert_eq!(unwrap!(f.list(&[TEMP_DIR])).count(), 0);
+        }
+
+        #[test]
+        fn test_dirty_buffer_usage() {
+            let tmpdir = temp_dir();
+            let mut f =
+                FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            unwrap!(f.write(&["dir", "kept"], ["kept"]));
+            unwrap!(f.write(&["dir", "changed"], ["changed"]));
+            unwrap!(f.write(&["dir", "deleted"], ["deleted"]));
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.write(&["dir", "changed"], ["new"]));
+            unwrap!(dirty_buffer.delete(&["dir", "deleted"]));
+            unwrap!(dirty_buffer.write(&["dir", "added"], ["added"]));
+            unwrap!(dirty_buffer.write(&["other", "key"], ["other"]));
+            let empty: &[&str] = &[];
+            assert_eq!(unwrap!(dirty_buffer.usage(&["dir", "changed"])), 3);
+            assert_eq!(unwrap!(dirty_buffer.usage(&["dir", "kept"])), 4);
+            assert_eq!(unwrap!(dirty_buffer.usage(&["dir"])), 12);
+            assert_eq!(unwrap!(dirty_buffer.usage(&["other"])), 5);
+            assert_eq!(unwrap!(dirty_buffer.usage(empty)), 17);
+            assert!(dirty_buffer.usage(&["dir", "deleted"]).is_err());
+            // no document is read to measure them
+            assert_eq!(dirty_buffer.metrics().reads, 0);
+            unwrap!(dirty_buffer.flush());
+            assert_eq!(unwrap!(dirty_buffer.usage(empty)), 17);
+        }
+
+        #[test]
+        fn test_dirty_buffer_metrics() {
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            unwrap!(f.clone().write(&["old"], ["old"]));
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.write(&["new"], ["new!"]));
+            assert_eq!(unwrap!(dirty_buffer.read(&["new"])), b"new!");
+            assert_eq!(unwrap!(dirty_buffer.read(&["old"])), b"old");
+            unwrap!(dirty_buffer.flush());
+            let metrics = dirty_buffer.metrics();
+            assert_eq!(metrics.reads, 2);
+            assert_eq!(metrics.writes, 1);
+            assert_eq!(metrics.bytes_read, 7);
+            assert_eq!(metrics.bytes_written, 4);
+            assert_eq!(metrics.cache_hits, 1);
+            assert_eq!(metrics.flushes, 1);
+            assert!(metrics.last_flush.is_some());
+            dirty_buffer.reset_metrics();
+            assert_eq!(dirty_buffer.metrics(), Default::default());
+        }
+
+        #[test]
+        fn test_dirty_buffer_flush_cancellable() {
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            unwrap!(dirty_buffer.write(&["a", "key"], ["a"]));
+            unwrap!(dirty_buffer.write(&["key"], ["key"]));
+            let cancel = CancellationToken::new();
+            cancel.cancel();
+            assert!(!unwrap!(dirty_buffer.flush_cancellable(&cancel)));
+            assert!(dirty_buffer.is_dirty());
+            assert_eq!(dirty_buffer.pending().len(), 2);
+            assert!(unwrap!(dirty_buffer.flush_cancellable(
+                &CancellationToken::new())));
+            assert!(!dirty_buffer.is_dirty());
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert_eq!(unwrap!(f.read(&["a", "key"])), b"a");
+            assert_eq!(unwrap!(f.read(&["key"])), b"key");
+        }
+
+        #[test]
+        fn test_dirty_buffer_flush_policy() {
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            dirty_buffer.policy = FlushPolicy {
+                max_writes: Some(3),
+                idle_timeout: Some(Duration::from_secs(60)),
+                on_background: false,
+            };
+            let now = Instant::now();
+            assert!(!unwrap!(dirty_buffer.maybe_flush(now)));
+            unwrap!(dirty_buffer.write(&["a"], ["a"]));
+            unwrap!(dirty_buffer.write(&["b"], ["b"]));
+            assert!(dirty_buffer.is_dirty());
+            assert!(!unwrap!(dirty_buffer.maybe_flush(now)));
+            assert!(!unwrap!(dirty_buffer.handle_event(
+                LifecycleEvent::Background)));
+            unwrap!(dirty_buffer.write(&["c"], ["c"]));
+            assert!(unwrap!(dirty_buffer.maybe_flush(now)));
+            assert!(!dirty_buffer.is_dirty());
+            assert_eq!(dirty_buffer.stats().count, 1);
+
+            unwrap!(dirty_buffer.write(&["d"], ["d"]));
+            assert!(!unwrap!(dirty_buffer.maybe_flush(Instant::now())));
+            let later = Instant::now() + Duration::from_secs(61);
+            assert!(unwrap!(dirty_buffer.maybe_flush(later)));
+            unwrap!(dirty_buffer.write(&["e"], ["e"]));
+            assert!(unwrap!(dirty_buffer.handle_event(
+                LifecycleEvent::Terminate)));
+            assert!(!unwrap!(dirty_buffer.handle_event(
+                LifecycleEvent::Terminate)));
+            let stats = dirty_buffer.stats();
+            assert_eq!(stats.count, 3);
+            assert!(stats.last.is_some() && stats.max <= stats.total);
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert_eq!(unwrap!(f.read(&["e"])), b"e");
+        }
+
+        #[test]
+        fn test_dirty_buffer_writers() {
+            use std::io::Write;
+            use std::mem;
+            use repository::Error;
+
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            unwrap!(f.clone().write(&["dir", "key"], ["old"]));
+            let mut dirty_buffer = DirtyBuffer::new(f);
+            {
+                let mut writer = unwrap!(dirty_buffer.get_writer(&["dir",
+                                                                  "key"]));
+                unwrap!(writer.write_all(b"first"));
+            }
+            assert_eq!(unwrap!(dirty_buffer.read(&["dir", "key"])), b"first");
+            {
+                let mut writer = unwrap!(dirty_buffer.get_writer(&["dir",
+                                                                  "key"]));
+                unwrap!(writer.write_all(b"second"));
+            }
+            assert_eq!(unwrap!(dirty_buffer.read(&["dir", "key"])),
+                       b"second");
+
+            // a writer which is never dropped keeps holding its key
+            {
+                let mut writer = unwrap!(dirty_buffer.get_writer(&["dir",
+                                                                  "key"]));
+                unwrap!(writer.write_all(b"leaked"));
+                mem::forget(writer);
+                let writer = unwrap!(dirty_buffer.get_writer(&["new", "key"]));
+                mem::forget(writer);
+            }
+            assert_eq!(unwrap!(dirty_buffer.read(&["dir", "key"])),
+                       b"second");
+            assert!(dirty_buffer.exists(&["dir", "key"]));
+            assert!(!dirty_buffer.exists(&["new", "key"]));
+            for key in &[&["dir", "key"][..], &["new"][..],
+                         &["new", "key", "sub"][..]] {
+                match dirty_buffer.get_writer(key) {
+                    Err(Error::CannotBorrow) => { }
+                    Err(e) => { panic!("unexpected error: {}", e); }
+                    Ok(_) => { panic!("a writer for {:?} is alive", key); }
+                }
+            }
+            unwrap!(dirty_buffer.write(&["dir", "other"], ["other"]));
+            unwrap!(dirty_buffer.flush());
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert_eq!(unwrap!(f.read(&["dir", "key"])), b"second");
+            assert_eq!(unwrap!(f.read(&["dir", "other"])), b"other");
+        }
+
+        #[cfg(feature = "auto-flush")]
+        #[test]
+        fn test_auto_flusher() {
+            use std::sync::{Arc, Mutex};
+            use super::AutoFlusher;
+
+            let tmpdir = temp_dir();
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            let buffer = Arc::new(Mutex::new(DirtyBuffer::new(f)));
+            let flusher = AutoFlusher::spawn(buffer.clone(),
+                                             Duration::from_millis(10));
+            unwrap!(buffer.lock().unwrap().write(&["key"], ["contents"]));
+            unwrap!(flusher.stop());
+            assert!(!buffer.lock().unwrap().is_dirty());
+            let f = FileSystemRepository::from_path(tmpdir.path(), true).unwrap();
+            assert_eq!(unwrap!(f.read(&["key"])), b"contents");
+        }
+    }
+}
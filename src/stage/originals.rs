@@ -0,0 +1,226 @@
+//! Crawled documents kept as they were fetched, for debugging and to parse
+//! them again by `Stage::reparse()` after parser bugs are fixed, which
+//! repairs entries the buggy parser mangled or dropped.
+//!
+//! Feeds opt in to it by `SubscriptionSettings::set_keep_originals()`,
+//! which tells how many of the latest crawls are kept; `Stage::refresh()`
+//! stores each fetched document, and removes older ones.  Each original is
+//! a document of its own, named by its zero-padded sequence number, and
+//! consists of the URL it was fetched from, a line feed, and the body as
+//! it was fetched.  Originals aren't compressed, since the repository
+//! doesn't compress documents yet; see the `manifest` module.
+use parser::{self, ParseDiagnostics, ParseOptions};
+use repository::Repository;
+
+use super::{DocumentKey, Result, Stage};
+use super::layout::ORIGINALS_KEY;
+
+/// A kept original, i.e. the URL it was fetched from and the body.
+pub type Original = (String, Vec<u8>);
+
+impl<R: Repository> Stage<R> {
+    /// Names of kept originals of the feed of `feed_id`, the oldest first.
+    pub fn original_names(&self, feed_id: &str) -> Result<Vec<String>> {
+        let key = [ORIGINALS_KEY, feed_id];
+        if !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let mut names: Vec<String> =
+            try!(try!(self.repository.list(&key)).collect());
+        names.retain(|n| {
+            !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())
+        });
+        names.sort();
+        Ok(names)
+    }
+
+    /// The kept original of the name, if it exists.
+    pub fn read_original(&self, feed_id: &str, name: &str)
+                         -> Result<Option<Original>> {
+        let key = self.original_key(feed_id, name);
+        if !self.repository.exists(&key) {
+            return Ok(None);
+        }
+        let mut buf = try!(self.repository.read(&key));
+        let original = match buf.iter().position(|&b| b == b'\n') {
+            Some(i) => {
+                let body = buf.split_off(i + 1);
+                buf.pop();
+                (String::from_utf8_lossy(&buf).into_owned(), body)
+            }
+            None => (String::new(), buf),
+        };
+        Ok(Some(original))
+    }
+
+    /// Keep the document fetched from the `url` as the latest original of
+    /// the feed, and remove older ones than the last `keep` originals.
+    pub fn keep_original(&mut self, feed_id: &str, url: &str, body: &[u8],
+                         keep: usize) -> Result<()> {
+        let mut names = try!(self.original_names(feed_id));
+        if keep > 0 {
+            let next = names.last().and_then(|n| n.parse::<u64>().ok())
+                            .map_or(1, |n| n + 1);
+            let name = format!("{:010}", next);
+            let mut buf = Vec::with_capacity(url.len() + 1 + body.len());
+            buf.extend_from_slice(url.as_bytes());
+            buf.push(b'\n');
+            buf.extend_from_slice(body);
+            let key = self.original_key(feed_id, &name);
+            try!(self.repository.write(&key, Some(buf)));
+            names.push(name);
+        }
+        if names.len() > keep {
+            let excess = names.len() - keep;
+            for name in names.drain(..excess) {
+                let key = self.original_key(feed_id, &name);
+                try!(self.repository.delete(&key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse kept originals of the feed of `feed_id` again by the current
+    /// parser, the oldest first, and store entries as they're parsed now.
+    /// Marks, locations and provenance of stored entries are kept, and
+    /// metadata of the feed is replaced by the one of the latest original.
+    /// Originals which still fail to parse are skipped.  It returns the
+    /// number of parsed originals.
+    pub fn reparse(&mut self, feed_id: &str, options: &ParseOptions)
+                   -> Result<usize> {
+        let names = try!(self.original_names(feed_id));
+        let key = self.feed_key(feed_id);
+        let mut stored = if self.repository.exists(&key) {
+            Some(try!(self.read_feed(feed_id)))
+        } else {
+            None
+        };
+        let mut parsed = 0;
+        for name in names {
+            let (url, body) = match try!(self.read_original(feed_id, &name)) {
+                Some(original) => original,
+                None => { continue; }
+            };
+            let diagnostics = ParseDiagnostics::new();
+            let reparsed = match parser::parse(&body, &url, options,
+                                               &diagnostics) {
+                Ok(feed) => feed,
+                Err(_) => { continue; }
+            };
+            parsed += 1;
+            let mut feed = match stored.take() {
+                Some(feed) => feed,
+                None => {
+                    stored = Some(reparsed);
+                    continue;
+                }
+            };
+            feed.source = reparsed.source;
+            for mut entry in reparsed.entries {
+                match feed.entries.iter_mut().find(|e| e.id == entry.id) {
+                    Some(previous) => {
+                        entry.read = previous.read.clone();
+                        entry.starred = previous.starred.clone();
+                        entry.location = previous.location;
+                        entry.seen = previous.seen.clone();
+                        *previous = entry;
+                    }
+                    None => { feed.entries.push(entry); }
+                }
+            }
+            stored = Some(feed);
+        }
+        if parsed > 0 {
+            if let Some(feed) = stored {
+                try!(self.write_feed(feed_id, &feed));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn original_key(&self, feed_id: &str, name: &str) -> Vec<String> {
+        self.document_key(DocumentKey::Original(feed_id.to_owned(),
+                                                name.to_owned()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Text};
+    use parser::ParseOptions;
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    static FX_ATOM: &'static [u8] = br#"
+    <feed xmlns="http://www.w3.org/2005/Atom">
+        <id>urn:feed</id>
+        <title>Fixed title</title>
+        <updated>2013-11-06T14:36:00Z</updated>
+        <entry>
+            <id>urn:entry:1</id>
+            <title>Fixed entry</title>
+            <updated>2013-11-06T14:36:00Z</updated>
+        </entry>
+        <entry>
+            <id>urn:entry:2</id>
+            <title>Dropped entry</title>
+            <updated>2013-11-06T14:36:00Z</updated>
+        </entry>
+    </feed>"#;
+
+    #[test]
+    fn test_keep_original() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        assert!(unwrap!(stage.original_names("feed")).is_empty());
+        for i in 0..3 {
+            let body = format!("<rss>{}</rss>", i);
+            unwrap!(stage.keep_original("feed", "http://example.com/",
+                                        body.as_bytes(), 2));
+        }
+        assert_eq!(unwrap!(stage.original_names("feed")),
+                   ["0000000002", "0000000003"]);
+        assert_eq!(unwrap!(stage.read_original("feed", "0000000003")),
+                   Some(("http://example.com/".to_owned(),
+                         b"<rss>2</rss>".to_vec())));
+        assert_eq!(unwrap!(stage.read_original("feed", "0000000001")), None);
+        unwrap!(stage.keep_original("feed", "http://example.com/", b"", 0));
+        assert!(unwrap!(stage.original_names("feed")).is_empty());
+    }
+
+    #[test]
+    fn test_reparse() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let options = ParseOptions::default();
+        assert_eq!(unwrap!(stage.reparse("feed", &options)), 0);
+
+        // what a buggy parser stored
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Mangled"), t);
+        let mut entry = Entry::new("urn:entry:1".to_owned(),
+                                   Text::plain("Mangled"), t);
+        entry.starred.marked = true;
+        feed.entries.push(entry);
+        unwrap!(stage.write_feed("feed", &feed));
+        unwrap!(stage.keep_original("feed", "http://example.com/feed",
+                                    b"<malformed", 3));
+        unwrap!(stage.keep_original("feed", "http://example.com/feed",
+                                    FX_ATOM, 3));
+
+        assert_eq!(unwrap!(stage.reparse("feed", &options)), 1);
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.title, Text::plain("Fixed title"));
+        assert_eq!(feed.entries.len(), 2);
+        assert_eq!(feed.entries[0].title, Text::plain("Fixed entry"));
+        assert!(feed.entries[0].starred.marked);
+        assert_eq!(feed.entries[1].id, "urn:entry:2");
+    }
+}
@@ -0,0 +1,205 @@
+//! Previewing what `Stage::flush()` will change, so that apps can show
+//! pending changes before they're synchronized.
+use std::collections::{BTreeSet, HashMap};
+
+use feed::{Feed, MarkKind};
+use repository::Repository;
+
+use super::{DocumentKey, Result, Stage, read_feed_from};
+
+/// The summary of buffered changes which aren't flushed yet.  Documents are
+/// compared with ones in the underlying repository; buffered documents that
+/// are the same as stored ones aren't counted.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct FlushPreview {
+    /// Keys of documents which will be newly created.
+    pub added: Vec<Vec<String>>,
+
+    /// Keys of documents which will be overwritten.
+    pub updated: Vec<Vec<String>>,
+
+    /// Keys of documents which will be removed.
+    pub deleted: Vec<Vec<String>>,
+
+    /// Entries which will be added, as pairs of a feed id and an entry id.
+    /// Entries of newly created feeds are included as well.
+    pub entries_added: Vec<(String, String)>,
+
+    /// Marks of stored entries which will be changed.
+    pub marks_changed: Vec<MarkChange>,
+}
+
+impl FlushPreview {
+    /// Whether flushing will change nothing.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() &&
+            self.deleted.is_empty()
+    }
+}
+
+/// The mark of an entry which will be changed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MarkChange {
+    pub feed_id: String,
+    pub entry_id: String,
+    pub kind: MarkKind,
+
+    /// The state the mark will be in.
+    pub marked: bool,
+}
+
+impl<R: Repository> Stage<R> {
+    /// Summarize what `flush()` will write to the underlying repository,
+    /// without writing anything.
+    pub fn preview_flush(&self) -> Result<FlushPreview> {
+        let inner = self.repository.inner();
+        let mut preview: FlushPreview = Default::default();
+        let mut feed_ids = BTreeSet::new();
+        for (key, content) in self.repository.pending() {
+            let exists = inner.exists(&key);
+            match content {
+                Some(content) => {
+                    if !exists {
+                        preview.added.push(key.clone());
+                    } else if try!(inner.read(&key)) != content {
+                        preview.updated.push(key.clone());
+                    } else {
+                        continue;
+                    }
+                }
+                None if exists => { preview.deleted.push(key.clone()); }
+                None => { continue; }
+            }
+            match DocumentKey::parse(&key) {
+                Some((DocumentKey::Feed(feed_id), _)) |
+                Some((DocumentKey::FeedPage(feed_id, _), _)) => {
+                    feed_ids.insert(feed_id);
+                }
+                // marks set by set_marks() change the feed as well
+                Some((DocumentKey::PendingMarks(feed_id), _)) => {
+                    feed_ids.insert(feed_id);
+                }
+                _ => { }
+            }
+        }
+        for feed_id in feed_ids {
+            let key = self.feed_key(&feed_id);
+            if !self.repository.exists(&key) {
+                continue;
+            }
+            let buffered = try!(self.read_feed(&feed_id));
+            let stored = if inner.exists(&key) {
+                Some(try!(read_feed_from(inner, self.key_layout, &feed_id)))
+            } else {
+                None
+            };
+            diff_feeds(&feed_id, stored.as_ref(), &buffered, &mut preview);
+        }
+        Ok(preview)
+    }
+}
+
+fn diff_feeds(feed_id: &str, stored: Option<&Feed>, buffered: &Feed,
+              preview: &mut FlushPreview) {
+    let stored: HashMap<&str, _> = match stored {
+        Some(feed) => feed.entries.iter().map(|e| (&e.id[..], e)).collect(),
+        None => HashMap::new(),
+    };
+    for entry in buffered.entries.iter() {
+        let old = match stored.get(&entry.id[..]) {
+            Some(old) => old,
+            None => {
+                preview.entries_added.push((feed_id.to_owned(),
+                                            entry.id.clone()));
+                continue;
+            }
+        };
+        for &kind in [MarkKind::Read, MarkKind::Starred].iter() {
+            let marked = entry.mark(kind).marked;
+            if old.mark(kind).marked != marked {
+                preview.marks_changed.push(MarkChange {
+                    feed_id: feed_id.to_owned(),
+                    entry_id: entry.id.clone(),
+                    kind: kind,
+                    marked: marked,
+                });
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+    use super::MarkChange;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, MarkKind, Text};
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    fn fx_feed(entries: usize) -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        for i in 0..entries {
+            feed.entries.push(Entry::new(format!("urn:entry:{}", i),
+                                         Text::plain(format!("{}", i)),
+                                         updated_at));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_preview_flush() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = Stage::new(repo);
+        stage.entries_per_page = Some(2);
+        assert!(unwrap!(stage.preview_flush()).is_empty());
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        let preview = unwrap!(stage.preview_flush());
+        assert_eq!(preview.added, [vec!["feeds", "feed"],
+                                   vec!["pages", "feed", "2"]]);
+        assert_eq!(preview.entries_added.len(), 3);
+        unwrap!(stage.flush());
+        assert!(unwrap!(stage.preview_flush()).is_empty());
+
+        unwrap!(stage.write_feed("feed", &fx_feed(3)));
+        assert!(unwrap!(stage.preview_flush()).is_empty());
+        unwrap!(stage.write_feed("feed", &fx_feed(4)));
+        unwrap!(stage.set_mark("feed", "urn:entry:0", MarkKind::Read, true));
+        let preview = unwrap!(stage.preview_flush());
+        assert!(preview.added.is_empty());
+        assert_eq!(preview.updated, [vec!["feeds", "feed"],
+                                     vec!["pages", "feed", "2"]]);
+        assert_eq!(preview.entries_added,
+                   [("feed".to_owned(), "urn:entry:3".to_owned())]);
+        assert_eq!(preview.marks_changed, [MarkChange {
+            feed_id: "feed".to_owned(),
+            entry_id: "urn:entry:0".to_owned(),
+            kind: MarkKind::Read,
+            marked: true,
+        }]);
+        unwrap!(stage.write_feed("feed", &fx_feed(2)));
+        let preview = unwrap!(stage.preview_flush());
+        assert_eq!(preview.deleted, [vec!["pages", "feed", "2"]]);
+
+        // marks pending by set_marks() are previewed as well
+        unwrap!(stage.flush());
+        let entries = vec![("feed", "urn:entry:1")];
+        unwrap!(stage.set_marks(entries, MarkKind::Starred, true));
+        let preview = unwrap!(stage.preview_flush());
+        assert_eq!(preview.added, [vec!["pending-marks", "feed"]]);
+        assert_eq!(preview.marks_changed, [MarkChange {
+            feed_id: "feed".to_owned(),
+            entry_id: "urn:entry:1".to_owned(),
+            kind: MarkKind::Starred,
+            marked: true,
+        }]);
+    }
+}
@@ -0,0 +1,174 @@
+//! Following feeds which have moved, and recognizing them by their old
+//! URLs.
+//!
+//! When a feed is crawled through permanent redirects, i.e. 301 or 308,
+//! its subscription should be updated so that the old URL is no longer
+//! requested.  The old URL is kept in `Subscription::aliases`, so that the
+//! feed is still recognized by it.  Temporary redirects don't change the
+//! subscription.
+//!
+//! Feeds are stored by IDs derived from their `id`, which often is the URL
+//! of the feed itself, so it changes when the feed moves to another
+//! domain.  `Stage::route_crawled()` finds the subscription the crawled
+//! feed belongs to by its URLs including aliases first, so that entries
+//! crawled before and after the move are merged into the same feed rather
+//! than duplicated.
+use crawler::CrawlResult;
+use digest::sha1_hex;
+use repository::Repository;
+use subscribe::SubscriptionSet;
+
+use super::{Error, Result, Stage, now};
+use super::sanity::check_feed;
+
+impl<R: Repository> Stage<R> {
+    /// Update the subscription of `feed_id` to the URL the crawled feed
+    /// has permanently moved to; see `CrawlResult::permanent_url()`.  It
+    /// returns the new URL if the subscription was updated, or `None` if
+    /// it hasn't moved or isn't subscribed.
+    pub fn apply_redirects(&mut self, feed_id: &str, result: &CrawlResult)
+                           -> Result<Option<String>> {
+        let url = match result.permanent_url() {
+            Some(url) => url.to_owned(),
+            None => { return Ok(None); }
+        };
+        let mut list = try!(self.read_subscriptions());
+        let moved = match list.find_mut(feed_id) {
+            Some(subscription) => subscription.move_to(&url[..]),
+            None => false,
+        };
+        if !moved {
+            return Ok(None);
+        }
+        try!(self.write_subscriptions(&list));
+        Ok(Some(url))
+    }
+
+    /// The ID of the subscribed feed the feed crawled from `url` belongs
+    /// to.  The subscription is looked up by the final URL, the URLs
+    /// redirected through, the requested `url`, and the feed's `id`, in
+    /// order, each compared with aliases as well; see
+    /// `SubscriptionSet::find_by_uri()`.  The feed ID derived from the
+    /// feed's `id` is tried last.  It's `None` if no subscription matches.
+    pub fn route_crawled(&self, url: &str, result: &CrawlResult)
+                         -> Result<Option<String>> {
+        let list = try!(self.read_subscriptions());
+        let mut uris = vec![&result.url[..]];
+        uris.extend(result.redirects.iter().rev().map(|r| &r.url[..]));
+        uris.push(url);
+        uris.push(&result.feed.id);
+        for uri in uris {
+            if let Some(subscription) = list.find_by_uri(uri) {
+                return Ok(Some(subscription.feed_id.clone()));
+            }
+        }
+        let feed_id = sha1_hex(result.feed.id.as_bytes());
+        Ok(list.find(&feed_id).map(|s| s.feed_id.clone()))
+    }
+
+    /// Store the feed crawled from `url` to the subscription it belongs
+    /// to, merged with the stored one, and follow its permanent redirects.
+    /// It returns the feed ID it's stored as, or `None` and does nothing
+    /// if it doesn't belong to any subscription.  Feeds failing sanity
+    /// checks are quarantined, and it fails with `Error::Quarantined`; see
+    /// the `sanity` module.  See also `route_crawled()` and
+    /// `apply_redirects()`.
+    pub fn store_crawled(&mut self, url: &str, result: &CrawlResult)
+                         -> Result<Option<String>> {
+        let feed_id = match try!(self.route_crawled(url, result)) {
+            Some(feed_id) => feed_id,
+            None => { return Ok(None); }
+        };
+        let mut crawled = result.feed.clone();
+        if let Err(insanity) = check_feed(&mut crawled, &self.sanity_policy,
+                                          now()) {
+            try!(self.quarantine(&feed_id, &crawled));
+            return Err(Error::Quarantined(insanity));
+        }
+        try!(self.apply_redirects(&feed_id, result));
+        let feed = if self.repository.exists(&self.feed_key(&feed_id)) {
+            let stored = try!(self.read_feed(&feed_id));
+            try!(self.merge_feed(&feed_id, stored, crawled))
+        } else {
+            crawled
+        };
+        try!(self.write_feed(&feed_id, &feed));
+        Ok(Some(feed_id))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use crawler::{Request, crawl};
+    use crawler::test::{FX_RSS, MockBackend};
+    use digest::sha1_hex;
+    use repository::FileSystemRepository;
+    use subscribe::{Subscription, SubscriptionSet};
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_apply_redirects() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let old = "http://earthreader.org/feed";
+        let new = "http://earthreader.org/rss";
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", old, "Feed")));
+        let backend = MockBackend::default()
+            .with(new, 200, FX_RSS)
+            .redirect(old, 301, new)
+            .redirect("http://earthreader.org/tmp", 302, new);
+        let options = Default::default();
+        let temporary = unwrap!(crawl(
+            &backend, &Request::new("http://earthreader.org/tmp"), &options));
+        assert_eq!(unwrap!(stage.apply_redirects("feed", &temporary)), None);
+        let result = unwrap!(crawl(&backend, &Request::new(old), &options));
+        assert_eq!(unwrap!(stage.apply_redirects("other", &result)), None);
+        assert_eq!(unwrap!(stage.apply_redirects("feed", &result)),
+                   Some(new.to_owned()));
+        let list = unwrap!(stage.read_subscriptions());
+        let subscription = list.find("feed").unwrap();
+        assert_eq!(subscription.feed_uri, new);
+        assert_eq!(subscription.aliases, [old]);
+        assert_eq!(unwrap!(stage.apply_redirects("feed", &result)), None);
+    }
+
+    #[test]
+    fn test_store_crawled() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let old = "http://old.earthreader.org/rss";
+        let new = "http://earthreader.org/rss";
+        let feed_id = sha1_hex(old.as_bytes());
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new(&feed_id[..], old, "")));
+        let backend = MockBackend::default()
+            .with(new, 200, FX_RSS)
+            .with("http://example.com/rss", 200, FX_RSS)
+            .redirect(old, 301, new);
+        let options = Default::default();
+        let result = unwrap!(crawl(&backend, &Request::new(old), &options));
+        assert_eq!(result.feed.id, new);
+        assert_eq!(unwrap!(stage.route_crawled(old, &result)),
+                   Some(feed_id.clone()));
+        assert_eq!(unwrap!(stage.store_crawled(old, &result)),
+                   Some(feed_id.clone()));
+        let list = unwrap!(stage.read_subscriptions());
+        assert_eq!(list.find(&feed_id).unwrap().feed_uri, new);
+        assert_eq!(unwrap!(stage.feed_ids()), [&feed_id[..]]);
+
+        let result = unwrap!(crawl(&backend, &Request::new(new), &options));
+        assert_eq!(unwrap!(stage.store_crawled(new, &result)),
+                   Some(feed_id.clone()));
+        assert_eq!(unwrap!(stage.feed_ids()), [&feed_id[..]]);
+        let url = "http://example.com/rss";
+        let result = unwrap!(crawl(&backend, &Request::new(url), &options));
+        assert_eq!(unwrap!(stage.store_crawled(url, &result)), None);
+        assert_eq!(unwrap!(stage.feed_ids()), [feed_id]);
+    }
+}
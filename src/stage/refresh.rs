@@ -0,0 +1,747 @@
+//! Refreshing subscriptions, which is what apps do most: bringing a
+//! subscribed feed up to date in a single call.
+//!
+//! `Stage::refresh()` runs the whole pipeline of the crawler and the stage:
+//!
+//! 1. the request is built by the subscription settings, and made
+//!    conditional on validators of the last successful crawl, so that
+//!    unchanged feeds aren't downloaded again;
+//! 2. the response is verified by the fingerprint pinned in the
+//!    subscription settings and by `Stage::verifier`, if any; see the
+//!    `crawler::verify` module.  Then it's parsed, and checked for its
+//!    sanity; feeds failing the checks are quarantined instead of being
+//!    stored, and dates in the future are clamped; see the `sanity`
+//!    module.  Entries stored as they are, i.e. below the high-water mark
+//!    of the archive, are skipped without parsing their contents; see
+//!    `ParseOptions::known_entries`.
+//!    The fetched document is kept as it is if the subscription opts in
+//!    to it; see the `originals` module;
+//! 3. the feed is merged into the stored one by the merge strategy of
+//!    feeds, which keeps marks set locally by default, and permanent
+//!    redirects are followed; see the `merge` and `redirects` modules.
+//!    Crawled entries are stamped with when and from which URL they were
+//!    seen, i.e. `Entry::seen`, and duplicates are collapsed if the
+//!    subscription opts in to it; see the `dedup` module;
+//! 4. the crawl is recorded to the journal, and the attempt is appended to
+//!    the crawl log of the feed.
+//!
+//! `Stage::refresh_all()` refreshes every subscription, and can be stopped
+//! between feeds, and between retries of a feed, by a `CancellationToken`.
+//!
+//! Entries are queried straight from feed documents, so there's no index
+//! to update.  Crawl logs are plain text, and each line is an attempt of
+//! tab-separated fields, the latest last:
+//!
+//! 1. when it was crawled in RFC 3339;
+//! 2. the status, `ok`, `not-modified`, or `error`;
+//! 3. the `ETag` of the response, or empty;
+//! 4. the `Last-Modified` of the response, or empty;
+//! 5. the number of new entries;
+//! 6. the number of updated entries;
+//! 7. the error message, or empty.
+//!
+//! Only the last `CRAWL_LOG_SIZE` attempts are kept.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+
+use codecs::RFC3339;
+use crawler::{self, Backend, Request, Verifier, fetch_cancellable,
+              parse_response, verify_response};
+use feed::Provenance;
+use job::{CancellationToken, Phase, ProgressReporter};
+use parser::ParseOptions;
+use repository::Repository;
+use schema::{Codec, Mergeable};
+use subscribe::SubscriptionSet;
+
+use super::{DocumentKey, Error, Operation, Result, Stage, now};
+use super::dedup::collapse_duplicates;
+use super::merge::MergeStrategy;
+use super::sanity::{ClampedDate, Insanity, check_feed};
+use super::journal::{escape, unescape};
+
+/// The number of attempts a crawl log keeps.
+pub const CRAWL_LOG_SIZE: usize = 20;
+
+/// How a crawl went.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CrawlStatus {
+    /// The feed was fetched and stored.
+    Ok,
+
+    /// The server told the feed hasn't changed.
+    NotModified,
+
+    /// The feed couldn't be fetched or parsed.
+    Error,
+}
+
+impl CrawlStatus {
+    fn name(&self) -> &'static str {
+        match *self {
+            CrawlStatus::Ok => "ok",
+            CrawlStatus::NotModified => "not-modified",
+            CrawlStatus::Error => "error",
+        }
+    }
+}
+
+/// An attempt to crawl a feed in its crawl log.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CrawlRecord {
+    pub crawled_at: DateTime<FixedOffset>,
+    pub status: CrawlStatus,
+
+    /// The `ETag` of the response, if the feed was fetched.
+    pub etag: Option<String>,
+
+    /// The `Last-Modified` of the response, if the feed was fetched.
+    pub last_modified: Option<String>,
+
+    pub new_entries: usize,
+    pub updated_entries: usize,
+
+    /// The message of the error, if it failed.
+    pub error: Option<String>,
+}
+
+impl CrawlRecord {
+    /// Encode the record as a single line, without the trailing newline.
+    pub fn to_line(&self) -> String {
+        let mut buf = vec![];
+        RFC3339.encode(&self.crawled_at, &mut buf).unwrap();
+        let optional = |v: &Option<String>| {
+            v.as_ref().map_or(String::new(), |v| escape(v))
+        };
+        let fields = [String::from_utf8(buf).unwrap(),
+                      self.status.name().to_owned(),
+                      optional(&self.etag),
+                      optional(&self.last_modified),
+                      self.new_entries.to_string(),
+                      self.updated_entries.to_string(),
+                      optional(&self.error)];
+        fields.join("\t")
+    }
+
+    /// Decode the line encoded by `to_line()`.  It's `None` if the line is
+    /// malformed.
+    pub fn from_line(line: &str) -> Option<CrawlRecord> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return None;
+        }
+        let status = match fields[1] {
+            "ok" => CrawlStatus::Ok,
+            "not-modified" => CrawlStatus::NotModified,
+            "error" => CrawlStatus::Error,
+            _ => { return None; }
+        };
+        let optional = |v: &str| {
+            if v.is_empty() { None } else { Some(unescape(v)) }
+        };
+        Some(CrawlRecord {
+            crawled_at: match RFC3339.decode(fields[0]) {
+                Ok(t) => t,
+                Err(_) => { return None; }
+            },
+            status: status,
+            etag: optional(fields[2]),
+            last_modified: optional(fields[3]),
+            new_entries: match fields[4].parse() {
+                Ok(n) => n,
+                Err(_) => { return None; }
+            },
+            updated_entries: match fields[5].parse() {
+                Ok(n) => n,
+                Err(_) => { return None; }
+            },
+            error: optional(fields[6]),
+        })
+    }
+}
+
+/// What `Stage::refresh()` did.
+#[derive(Debug)]
+pub struct RefreshOutcome {
+    pub feed_id: String,
+
+    /// The number of crawled entries the stage didn't have.
+    pub new_entries: usize,
+
+    /// The number of crawled entries updated since they were stored.
+    pub updated_entries: usize,
+
+    /// The number of crawled entries already stored as they are.
+    pub unchanged_entries: usize,
+
+    /// The number of entries collapsed as duplicates, if the subscription
+    /// opts in to it.  See the `dedup` module.
+    pub collapsed_entries: usize,
+
+    /// Whether the server told the feed hasn't changed since the last
+    /// crawl, so that nothing was fetched.
+    pub not_modified: bool,
+
+    /// The URL the subscription was moved to by permanent redirects.
+    pub moved_to: Option<String>,
+
+    /// Dates of the crawled feed in the future, which were clamped to when
+    /// it was crawled.  See the `sanity` module.
+    pub clamped_dates: Vec<ClampedDate>,
+
+    /// Why the crawled feed was quarantined instead of being stored, if it
+    /// failed sanity checks.  It's recorded in the crawl log as an error.
+    pub quarantined: Option<Insanity>,
+
+    /// Why the feed couldn't be crawled, if it failed.  Nothing is stored
+    /// but the crawl log then.
+    pub error: Option<crawler::Error>,
+}
+
+impl<R: Repository> Stage<R> {
+    /// Crawl the subscribed feed of `feed_id`, and store it as the module
+    /// documentation describes.  Failures of crawling are reported in
+    /// `RefreshOutcome::error` rather than returned, since they're usual
+    /// e.g. on flaky networks; only failures of the stage are returned as
+    /// errors.  It's `None` if the feed isn't subscribed.
+    ///
+    /// A single refresh isn't cancelled in the middle, since the backend
+    /// can't be interrupted anyway; see `refresh_all()`.
+    pub fn refresh<B>(&mut self, backend: &B, feed_id: &str,
+                      options: &ParseOptions)
+                      -> Result<Option<RefreshOutcome>>
+        where B: Backend + ?Sized
+    {
+        self.refresh_cancellable(backend, feed_id, options,
+                                 &CancellationToken::new())
+    }
+
+    /// `refresh()` which makes no more retries of the request once the
+    /// `cancel` token is cancelled; the failure is stored in the crawl log
+    /// as other failures are, so the feed is crawled again on resumption.
+    fn refresh_cancellable<B>(&mut self, backend: &B, feed_id: &str,
+                              options: &ParseOptions,
+                              cancel: &CancellationToken)
+                              -> Result<Option<RefreshOutcome>>
+        where B: Backend + ?Sized
+    {
+        let feed_uri = match try!(self.read_subscriptions()).find(feed_id) {
+            Some(subscription) => subscription.feed_uri.clone(),
+            None => { return Ok(None); }
+        };
+        let settings = try!(self.read_settings(feed_id));
+        let mut log = try!(self.read_crawl_log(feed_id));
+        let mut outcome = RefreshOutcome {
+            feed_id: feed_id.to_owned(),
+            new_entries: 0,
+            updated_entries: 0,
+            unchanged_entries: 0,
+            collapsed_entries: 0,
+            not_modified: false,
+            moved_to: None,
+            clamped_dates: vec![],
+            quarantined: None,
+            error: None,
+        };
+        let mut record = CrawlRecord {
+            crawled_at: now(),
+            status: CrawlStatus::Ok,
+            etag: None,
+            last_modified: None,
+            new_entries: 0,
+            updated_entries: 0,
+            error: None,
+        };
+        let fetched = Request::new(feed_uri).with_settings(&settings)
+                                            .and_then(|request| {
+            let request = match log.iter().rev()
+                                   .find(|r| r.status == CrawlStatus::Ok) {
+                Some(last) => request.conditional(
+                    last.etag.as_ref().map(|v| &v[..]),
+                    last.last_modified.as_ref().map(|v| &v[..])),
+                None => request,
+            };
+            let response = try!(fetch_cancellable(backend, &request,
+                                                  cancel));
+            let verifier = self.verifier.as_ref().map(|v| &**v as &Verifier);
+            try!(verify_response(&response, &settings, verifier));
+            Ok(response)
+        });
+        // the stored feed is read only if there's a document to merge
+        let mut stored = None;
+        if let Ok(ref response) = fetched {
+            if response.status >= 200 && response.status < 300 {
+                try!(self.keep_original(feed_id, &response.url,
+                                        &response.body,
+                                        settings.keep_originals()));
+                if self.repository.exists(&self.feed_key(feed_id)) {
+                    stored = Some(try!(self.read_feed(feed_id)));
+                }
+            }
+        }
+        let mut options = options.clone();
+        // entries skipped as known would be lost if the crawled feed
+        // replaced the stored one as a whole
+        let field_wise =
+            self.merge_strategies.feeds == MergeStrategy::FieldWise;
+        if options.known_entries.is_none() && field_wise {
+            options.known_entries = stored.as_ref().map(|feed| {
+                Arc::new(feed.entries.iter()
+                             .map(|e| (e.id.clone(), e.updated_at))
+                             .collect())
+            });
+        }
+        let crawled = fetched.and_then(|response| {
+            parse_response(response, &options)
+        });
+        match crawled {
+            Ok(mut result) => match check_feed(&mut result.feed,
+                                               &self.sanity_policy,
+                                               record.crawled_at) {
+                Err(insanity) => {
+                    try!(self.quarantine(feed_id, &result.feed));
+                    record.status = CrawlStatus::Error;
+                    record.error = Some(insanity.to_string());
+                    outcome.quarantined = Some(insanity);
+                }
+                Ok(clamped) => {
+                    outcome.clamped_dates = clamped;
+                    let seen = Provenance::new(record.crawled_at,
+                                               &result.url[..]);
+                    for entry in result.feed.entries.iter_mut() {
+                        entry.seen = Some(seen.clone());
+                    }
+                    outcome.unchanged_entries = result.skipped_entries.len();
+                    for entry in result.feed.entries.iter() {
+                        let previous = stored.as_ref().and_then(|feed| {
+                            feed.entries.iter().find(|e| e.id == entry.id)
+                        });
+                        match previous {
+                            None => { outcome.new_entries += 1; }
+                            Some(e) if e.updated_at < entry.updated_at => {
+                                outcome.updated_entries += 1;
+                            }
+                            Some(_) => { outcome.unchanged_entries += 1; }
+                        }
+                    }
+                    outcome.moved_to = try!(self.apply_redirects(feed_id,
+                                                                 &result));
+                    let entries = result.feed.entries.len();
+                    let mut feed = match stored {
+                        Some(stored) => try!(self.merge_feed(
+                            feed_id, stored, result.feed)),
+                        None => result.feed,
+                    };
+                    // skipped entries were seen as well, though unparsed
+                    let skipped: HashSet<&str> =
+                        result.skipped_entries.iter().map(|id| &id[..])
+                                              .collect();
+                    for entry in feed.entries.iter_mut() {
+                        if !skipped.contains(&entry.id[..]) {
+                            continue;
+                        }
+                        match entry.seen {
+                            Some(ref mut s) => { s.merge_with(seen.clone()); }
+                            None => { entry.seen = Some(seen.clone()); }
+                        }
+                    }
+                    if let Some(window) = settings.dedup_window() {
+                        outcome.collapsed_entries =
+                            collapse_duplicates(&mut feed.entries, window);
+                    }
+                    try!(self.write_feed(feed_id, &feed));
+                    try!(self.record(Operation::Crawled {
+                        feed_id: feed_id.to_owned(),
+                        entries: entries,
+                    }));
+                    record.etag = result.etag;
+                    record.last_modified = result.last_modified;
+                    record.new_entries = outcome.new_entries;
+                    record.updated_entries = outcome.updated_entries;
+                }
+            },
+            Err(crawler::Error::NotModified) => {
+                outcome.not_modified = true;
+                record.status = CrawlStatus::NotModified;
+            }
+            Err(err) => {
+                record.status = CrawlStatus::Error;
+                record.error = Some(err.to_string());
+                outcome.error = Some(err);
+            }
+        }
+        log.push(record);
+        if log.len() > CRAWL_LOG_SIZE {
+            let excess = log.len() - CRAWL_LOG_SIZE;
+            log.drain(..excess);
+        }
+        try!(self.write_crawl_log(feed_id, &log));
+        Ok(Some(outcome))
+    }
+
+    /// Refresh all subscribed feeds in turn, and return their outcomes in
+    /// the order of `SubscriptionSet::recursive_subscriptions()`.  It's
+    /// checked whether `cancel` is cancelled before each feed, and it fails
+    /// with `Error::Cancelled` if it is; feeds refreshed until then are
+    /// stored, and recorded in their crawl logs, so that apps can skip
+    /// them when they resume by `crawler::is_due()` and `last_crawled_at()`.
+    /// A feed being refreshed makes no more retries once it's cancelled.
+    /// The number of refreshed feeds is reported to the `progress` as
+    /// `Phase::Crawling`.  Muted subscriptions are skipped; see
+    /// `Subscription::is_muted()`.
+    pub fn refresh_all<B, P>(&mut self, backend: &B, options: &ParseOptions,
+                             cancel: &CancellationToken, progress: &mut P)
+                             -> Result<Vec<RefreshOutcome>>
+        where B: Backend + ?Sized, P: ProgressReporter + ?Sized
+    {
+        let list = try!(self.read_subscriptions());
+        let now = now();
+        let feed_ids: Vec<String> = list.recursive_subscriptions().iter()
+                                        .filter(|s| !s.is_muted(now))
+                                        .map(|s| s.feed_id.clone())
+                                        .collect();
+        let total = Some(feed_ids.len());
+        let mut outcomes = vec![];
+        progress.report(Phase::Crawling, 0, total);
+        for (i, feed_id) in feed_ids.iter().enumerate() {
+            if cancel.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+            if let Some(outcome) = try!(self.refresh_cancellable(
+                    backend, feed_id, options, cancel)) {
+                outcomes.push(outcome);
+            }
+            progress.report(Phase::Crawling, i + 1, total);
+        }
+        Ok(outcomes)
+    }
+
+    /// Read the crawl log of `feed_id`, the oldest attempt first.  It's
+    /// empty if the feed was never refreshed.  Malformed lines are skipped.
+    pub fn read_crawl_log(&self, feed_id: &str) -> Result<Vec<CrawlRecord>> {
+        let key = self.crawl_log_key(feed_id);
+        if !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let buf = try!(self.repository.read(&key));
+        let text = String::from_utf8_lossy(&buf);
+        Ok(text.lines().filter_map(CrawlRecord::from_line).collect())
+    }
+
+    /// When the feed of `feed_id` was tried to be crawled last, whether it
+    /// succeeded or not.  Pass it to `crawler::is_due()`.
+    pub fn last_crawled_at(&self, feed_id: &str)
+                           -> Result<Option<DateTime<FixedOffset>>> {
+        let log = try!(self.read_crawl_log(feed_id));
+        Ok(log.last().map(|r| r.crawled_at))
+    }
+
+    fn crawl_log_key(&self, feed_id: &str) -> Vec<String> {
+        self.document_key(DocumentKey::CrawlLog(feed_id.to_owned()))
+    }
+
+    fn write_crawl_log(&mut self, feed_id: &str, log: &[CrawlRecord])
+                       -> Result<()> {
+        let mut buf = String::new();
+        for record in log {
+            buf.push_str(&record.to_line());
+            buf.push('\n');
+        }
+        let key = self.crawl_log_key(feed_id);
+        try!(self.repository.write(&key, Some(buf.into_bytes())));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::{Error as StageError, Insanity, Stage};
+    use super::{CRAWL_LOG_SIZE, CrawlRecord, CrawlStatus};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use crawler::Error;
+    use crawler::test::MockBackend;
+    use digest::sha256_hex;
+    use feed::{Blob, MarkKind};
+    use job::{CancellationToken, Phase};
+    use repository::FileSystemRepository;
+    use subscribe::Subscription;
+    use test_utils::temp_dir;
+
+    static FX_RSS: &'static [u8] = br#"<rss version="2.0"><channel>
+        <title>Earth Reader</title>
+        <item><title>A</title><guid>urn:earthreader:a</guid>
+              <pubDate>Wed, 06 Nov 2013 14:36:00 +0000</pubDate></item>
+    </channel></rss>"#;
+
+    static FX_RSS_UPDATED: &'static [u8] = br#"<rss version="2.0"><channel>
+        <title>Earth Reader</title>
+        <item><title>B</title><guid>urn:earthreader:b</guid>
+              <pubDate>Thu, 07 Nov 2013 14:36:00 +0000</pubDate></item>
+        <item><title>A</title><guid>urn:earthreader:a</guid>
+              <pubDate>Wed, 06 Nov 2013 14:36:00 +0000</pubDate></item>
+    </channel></rss>"#;
+
+    #[test]
+    fn test_crawl_record() {
+        let record = CrawlRecord {
+            crawled_at: FixedOffset::east(0).ymd(2013, 11, 6)
+                                            .and_hms(14, 36, 0),
+            status: CrawlStatus::Error,
+            etag: Some("\"a\tb\"".to_owned()),
+            last_modified: None,
+            new_entries: 1,
+            updated_entries: 2,
+            error: Some("failed\nto fetch".to_owned()),
+        };
+        let line = record.to_line();
+        assert!(!line.contains('\n'));
+        assert_eq!(CrawlRecord::from_line(&line), Some(record));
+        assert_eq!(CrawlRecord::from_line("2013-11-06T14:36:00Z\tok"), None);
+    }
+
+    #[test]
+    fn test_refresh() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let url = "http://earthreader.org/rss";
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", url, "Feed")));
+        let options = Default::default();
+        let backend = MockBackend::default().with(url, 200, FX_RSS);
+        assert!(unwrap!(stage.refresh(&backend, "other", &options))
+                    .is_none());
+        assert_eq!(unwrap!(stage.last_crawled_at("feed")), None);
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        assert_eq!(outcome.new_entries, 1);
+        assert!(!outcome.not_modified && outcome.error.is_none());
+        unwrap!(stage.set_mark("feed", "urn:earthreader:a", MarkKind::Read,
+                               true));
+
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        assert!(outcome.not_modified);
+        assert_eq!(outcome.new_entries, 0);
+
+        let backend = MockBackend::default().with(url, 200, FX_RSS_UPDATED);
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        assert_eq!((outcome.new_entries, outcome.updated_entries,
+                    outcome.unchanged_entries), (1, 0, 1));
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.entries.len(), 2);
+        let a = feed.entries.iter().find(|e| e.id == "urn:earthreader:a");
+        assert!(a.unwrap().read.marked);
+
+        let backend = MockBackend::default();
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        match outcome.error {
+            Some(Error::Io(_)) => { }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(unwrap!(stage.read_feed("feed")).entries.len(), 2);
+        let log = unwrap!(stage.read_crawl_log("feed"));
+        let statuses: Vec<CrawlStatus> = log.iter().map(|r| r.status)
+                                            .collect();
+        assert_eq!(statuses, [CrawlStatus::Ok, CrawlStatus::NotModified,
+                              CrawlStatus::Ok, CrawlStatus::Error]);
+        assert_eq!(log[2].new_entries, 1);
+        assert!(log[2].etag.is_some());
+        assert_eq!(unwrap!(stage.last_crawled_at("feed")),
+                   Some(log[3].crawled_at));
+        let feed = unwrap!(stage.read_feed("feed"));
+        let a = feed.entries.iter().find(|e| e.id == "urn:earthreader:a")
+                               .unwrap();
+        assert_eq!(a.first_seen_at(), Some(log[0].crawled_at));
+        assert_eq!(a.last_seen_at(), Some(log[2].crawled_at));
+        assert_eq!(a.seen.as_ref().unwrap().first_seen_uri, url);
+        for _ in 0..CRAWL_LOG_SIZE {
+            unwrap!(stage.refresh(&backend, "feed", &options));
+        }
+        assert_eq!(unwrap!(stage.read_crawl_log("feed")).len(),
+                   CRAWL_LOG_SIZE);
+    }
+
+    #[test]
+    fn test_refresh_quarantine() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let url = "http://earthreader.org/rss";
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", url, "Feed")));
+        stage.sanity_policy.max_entries = Some(0);
+        let backend = MockBackend::default().with(url, 200, FX_RSS);
+        let outcome = unwrap!(stage.refresh(&backend, "feed",
+                                            &Default::default())).unwrap();
+        assert_eq!(outcome.quarantined, Some(Insanity::TooManyEntries(1)));
+        assert!(stage.read_feed("feed").is_err());
+        let quarantined = unwrap!(stage.read_quarantined("feed")).unwrap();
+        assert_eq!(quarantined.entries.len(), 1);
+        let log = unwrap!(stage.read_crawl_log("feed"));
+        assert_eq!(log[0].status, CrawlStatus::Error);
+    }
+
+    #[test]
+    fn test_refresh_known_entries() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let url = "http://earthreader.org/atom";
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", url, "Feed")));
+        let backend = MockBackend::default().with(url, 200, br#"
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader</id><title>Earth Reader</title>
+                <updated>2013-11-06T14:36:00Z</updated>
+                <entry><id>urn:earthreader:a</id><title>A</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <content>A</content></entry>
+            </feed>"#);
+        let options = Default::default();
+        unwrap!(stage.refresh(&backend, "feed", &options));
+        let backend = MockBackend::default().with(url, 200, br#"
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <id>urn:earthreader</id><title>Earth Reader</title>
+                <updated>2013-11-07T14:36:00Z</updated>
+                <entry><id>urn:earthreader:b</id><title>B</title>
+                    <updated>2013-11-07T14:36:00Z</updated></entry>
+                <entry><id>urn:earthreader:a</id><title>A</title>
+                    <updated>2013-11-06T14:36:00Z</updated>
+                    <content>Changed</content></entry>
+            </feed>"#);
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        assert_eq!((outcome.new_entries, outcome.updated_entries,
+                    outcome.unchanged_entries), (1, 0, 1));
+        let feed = unwrap!(stage.read_feed("feed"));
+        assert_eq!(feed.entries.len(), 2);
+        let a = feed.entries.iter().find(|e| e.id == "urn:earthreader:a")
+                               .unwrap();
+        assert_eq!(a.content.as_ref().unwrap().as_bytes(), b"A");
+        let log = unwrap!(stage.read_crawl_log("feed"));
+        assert_eq!(a.last_seen_at(), Some(log[1].crawled_at));
+    }
+
+    #[test]
+    fn test_refresh_keep_originals() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let url = "http://earthreader.org/rss";
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", url, "Feed")));
+        let options = Default::default();
+        let backend = MockBackend::default().with(url, 200, FX_RSS);
+        unwrap!(stage.refresh(&backend, "feed", &options));
+        assert!(unwrap!(stage.original_names("feed")).is_empty());
+
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut settings = unwrap!(stage.read_settings("feed"));
+        settings.set_keep_originals(Some(2), now);
+        unwrap!(stage.write_settings("feed", &settings));
+        let backend = MockBackend::default().with(url, 200, FX_RSS_UPDATED);
+        unwrap!(stage.refresh(&backend, "feed", &options));
+        // not modified
+        unwrap!(stage.refresh(&backend, "feed", &options));
+        // documents failing to parse are kept as well
+        let backend = MockBackend::default().with(url, 200, b"<rss");
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        assert!(outcome.error.is_some());
+        let names = unwrap!(stage.original_names("feed"));
+        assert_eq!(names.len(), 2);
+        assert_eq!(unwrap!(stage.read_original("feed", &names[0])),
+                   Some((url.to_owned(), FX_RSS_UPDATED.to_vec())));
+        assert_eq!(unwrap!(stage.read_original("feed", &names[1])),
+                   Some((url.to_owned(), b"<rss".to_vec())));
+        assert_eq!(unwrap!(stage.reparse("feed", &options)), 1);
+        assert_eq!(unwrap!(stage.read_feed("feed")).entries.len(), 2);
+    }
+
+    #[test]
+    fn test_refresh_all() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let urls = ["http://earthreader.org/a", "http://earthreader.org/b"];
+        for (i, url) in urls.iter().enumerate() {
+            let feed_id = format!("feed{}", i);
+            unwrap!(stage.subscribe(&[] as &[&str],
+                                    Subscription::new(feed_id, *url, "")));
+        }
+        let mut muted = Subscription::new("muted", urls[0], "");
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        muted.mute(Some(FixedOffset::east(0).ymd(2100, 1, 1).and_hms(0, 0, 0)),
+                   t);
+        unwrap!(stage.subscribe(&[] as &[&str], muted));
+        let backend = MockBackend::default().with(urls[0], 200, FX_RSS);
+        let options = Default::default();
+        let cancel = CancellationToken::new();
+        let mut reports = vec![];
+        let outcomes = unwrap!(stage.refresh_all(
+            &backend, &options, &cancel,
+            &mut |phase, done, total| reports.push((phase, done, total))));
+        assert_eq!(reports, [(Phase::Crawling, 0, Some(2)),
+                             (Phase::Crawling, 1, Some(2)),
+                             (Phase::Crawling, 2, Some(2))]);
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].new_entries, 1);
+        assert!(outcomes[1].error.is_some());
+        cancel.cancel();
+        assert_err!(stage.refresh_all(&backend, &options, &cancel, &mut ()),
+                    StageError::Cancelled => { });
+        assert_eq!(unwrap!(stage.read_crawl_log("feed0")).len(), 1);
+        assert!(unwrap!(stage.read_crawl_log("muted")).is_empty());
+    }
+
+    #[test]
+    fn test_refresh_verified() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let url = "http://earthreader.org/rss";
+        unwrap!(stage.subscribe(&[] as &[&str],
+                                Subscription::new("feed", url, "Feed")));
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut settings = unwrap!(stage.read_settings("feed"));
+        settings.set_fingerprint(Some(&sha256_hex(b"forged")), t);
+        unwrap!(stage.write_settings("feed", &settings));
+        let options = Default::default();
+        let backend = MockBackend::default().with(url, 200, FX_RSS);
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        match outcome.error {
+            Some(Error::Unverified(_)) => { }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(unwrap!(stage.feed_ids()).is_empty());
+
+        settings.set_fingerprint(Some(&sha256_hex(FX_RSS)), t);
+        unwrap!(stage.write_settings("feed", &settings));
+        stage.verifier = Some(Box::new(|body: &[u8], _: &[(String, String)]| {
+            if body.starts_with(b"<?xml") { Ok(()) }
+            else { Err("no XML declaration".to_owned()) }
+        }));
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        match outcome.error {
+            Some(Error::Unverified(ref reason)) => {
+                assert_eq!(reason, "no XML declaration");
+            }
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        stage.verifier = None;
+        let outcome = unwrap!(stage.refresh(&backend, "feed", &options))
+                          .unwrap();
+        assert!(outcome.error.is_none());
+        assert_eq!(outcome.new_entries, 1);
+    }
+}
@@ -0,0 +1,279 @@
+//! Statistics reports of the whole archive, so that dashboards and the web
+//! frontend can show how the archive is doing without scanning every feed.
+//!
+//! `Stage::generate_report()` counts entries of every stored feed, and
+//! stores the report; apps call it periodically, e.g. after refreshing
+//! subscriptions, or by `generate_report_if_due()`.  Each report carries
+//! the archive-wide totals of reports before it as well, the last
+//! `REPORT_HISTORY_SIZE` of them, so that growth over time can be drawn.
+//!
+//! The report is a plain text document, and each line is tab-separated
+//! fields whose first one tells its kind:
+//!
+//! - `generated`, and when the report was generated in RFC 3339;
+//! - `total`, when, the number of feeds, entries and unread entries, for
+//!   each report in the history, the oldest first;
+//! - `feed`, the feed ID, the number of entries, unread entries and
+//!   starred entries, for each feed.
+use chrono::{DateTime, Duration, FixedOffset};
+
+use codecs::RFC3339;
+use repository::Repository;
+use schema::Codec;
+
+use super::{DocumentKey, Result, Stage};
+use super::journal::{escape, unescape};
+
+/// The number of totals a report keeps in its history.
+pub const REPORT_HISTORY_SIZE: usize = 90;
+
+/// How often, in seconds, `generate_report_if_due()` generates reports by
+/// default.
+pub const DEFAULT_REPORT_INTERVAL: i64 = 24 * 60 * 60;
+
+/// Counts of entries in a feed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FeedReport {
+    pub feed_id: String,
+    pub entries: usize,
+    pub unread: usize,
+    pub starred: usize,
+}
+
+/// Archive-wide counts at a time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Totals {
+    pub at: DateTime<FixedOffset>,
+    pub feeds: usize,
+    pub entries: usize,
+    pub unread: usize,
+}
+
+/// The statistics report of the archive.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Report {
+    pub generated_at: DateTime<FixedOffset>,
+
+    /// Counts of each stored feed, in the order of feed IDs.
+    pub feeds: Vec<FeedReport>,
+
+    /// Totals of the reports generated so far, the oldest first.  The last
+    /// one is of this report.
+    pub history: Vec<Totals>,
+}
+
+impl Report {
+    /// The totals of this report.
+    pub fn totals(&self) -> Totals {
+        Totals {
+            at: self.generated_at,
+            feeds: self.feeds.len(),
+            entries: self.feeds.iter().map(|f| f.entries).sum(),
+            unread: self.feeds.iter().map(|f| f.unread).sum(),
+        }
+    }
+
+    /// How many entries the archive gained since the report before, or
+    /// `None` if it's the first one.  It's negative if entries were
+    /// removed, e.g. by `Stage::enforce_quota()`.
+    pub fn growth(&self) -> Option<i64> {
+        let n = self.history.len();
+        if n < 2 {
+            return None;
+        }
+        Some(self.history[n - 1].entries as i64 -
+             self.history[n - 2].entries as i64)
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("generated\t{}\n",
+                               format_date(&self.generated_at));
+        for totals in self.history.iter() {
+            text.push_str(&format!("total\t{}\t{}\t{}\t{}\n",
+                                   format_date(&totals.at), totals.feeds,
+                                   totals.entries, totals.unread));
+        }
+        for feed in self.feeds.iter() {
+            text.push_str(&format!("feed\t{}\t{}\t{}\t{}\n",
+                                   escape(&feed.feed_id), feed.entries,
+                                   feed.unread, feed.starred));
+        }
+        text
+    }
+
+    /// Parse the report.  Malformed lines are skipped, and it's `None` if
+    /// when it was generated is unknown.
+    fn from_text(text: &str) -> Option<Report> {
+        let mut generated_at = None;
+        let mut feeds = vec![];
+        let mut history = vec![];
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let counts: Vec<usize> = fields.iter().skip(2)
+                                           .filter_map(|f| f.parse().ok())
+                                           .collect();
+            match (fields[0], fields.len(), counts.len()) {
+                ("generated", 2, _) => {
+                    generated_at = RFC3339.decode(fields[1]).ok();
+                }
+                ("total", 5, 3) => {
+                    if let Ok(at) = RFC3339.decode(fields[1]) {
+                        history.push(Totals {
+                            at: at,
+                            feeds: counts[0],
+                            entries: counts[1],
+                            unread: counts[2],
+                        });
+                    }
+                }
+                ("feed", 5, 3) => {
+                    feeds.push(FeedReport {
+                        feed_id: unescape(fields[1]),
+                        entries: counts[0],
+                        unread: counts[1],
+                        starred: counts[2],
+                    });
+                }
+                _ => { }
+            }
+        }
+        generated_at.map(|generated_at| Report {
+            generated_at: generated_at,
+            feeds: feeds,
+            history: history,
+        })
+    }
+}
+
+fn format_date(date: &DateTime<FixedOffset>) -> String {
+    let mut buf = vec![];
+    RFC3339.encode(date, &mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+impl<R: Repository> Stage<R> {
+    /// Count entries of every stored feed, and store the report, which
+    /// replaces the latest one.  Its history continues the latest one's.
+    pub fn generate_report(&mut self, now: DateTime<FixedOffset>)
+                           -> Result<Report> {
+        let mut feeds = vec![];
+        for feed_id in try!(self.feed_ids()) {
+            let feed = try!(self.read_feed(&feed_id));
+            feeds.push(FeedReport {
+                entries: feed.entries.len(),
+                unread: feed.entries.iter().filter(|e| !e.read.marked)
+                                           .count(),
+                starred: feed.entries.iter().filter(|e| e.starred.marked)
+                                            .count(),
+                feed_id: feed_id,
+            });
+        }
+        let history = match try!(self.latest_report()) {
+            Some(latest) => latest.history,
+            None => vec![],
+        };
+        let mut report = Report {
+            generated_at: now,
+            feeds: feeds,
+            history: history,
+        };
+        let totals = report.totals();
+        report.history.push(totals);
+        if report.history.len() > REPORT_HISTORY_SIZE {
+            let excess = report.history.len() - REPORT_HISTORY_SIZE;
+            report.history.drain(..excess);
+        }
+        let key = self.document_key(DocumentKey::Report);
+        try!(self.repository.write(&key,
+                                   Some(report.to_text().into_bytes())));
+        Ok(report)
+    }
+
+    /// Generate the report if the latest one is older than `interval`, or
+    /// there's none yet.  It's `None` if the latest one is recent enough.
+    pub fn generate_report_if_due(&mut self, now: DateTime<FixedOffset>,
+                                  interval: Duration)
+                                  -> Result<Option<Report>> {
+        if let Some(latest) = try!(self.latest_report()) {
+            if now - latest.generated_at < interval {
+                return Ok(None);
+            }
+        }
+        self.generate_report(now).map(Some)
+    }
+
+    /// The report generated last, if any.
+    pub fn latest_report(&self) -> Result<Option<Report>> {
+        let key = self.document_key(DocumentKey::Report);
+        if !self.repository.exists(&key) {
+            return Ok(None);
+        }
+        let buf = try!(self.repository.read(&key));
+        Ok(Report::from_text(&String::from_utf8_lossy(&buf)))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{REPORT_HISTORY_SIZE, Report};
+    use super::super::Stage;
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, MarkKind, Text};
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    fn fx_feed(entries: usize) -> Feed {
+        let date = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), date);
+        for i in 0..entries {
+            feed.entries.push(Entry::new(format!("urn:entry:{}", i),
+                                         Text::plain("Entry"), date));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_generate_report() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        assert_eq!(unwrap!(stage.latest_report()), None);
+        unwrap!(stage.write_feed("a", &fx_feed(3)));
+        unwrap!(stage.set_mark("a", "urn:entry:0", MarkKind::Read, true));
+        unwrap!(stage.set_mark("a", "urn:entry:1", MarkKind::Starred,
+                               true));
+        let report = unwrap!(stage.generate_report(now));
+        assert_eq!(report.feeds.len(), 1);
+        assert_eq!(report.feeds[0].feed_id, "a");
+        assert_eq!((report.feeds[0].entries, report.feeds[0].unread,
+                    report.feeds[0].starred), (3, 2, 1));
+        assert_eq!(report.growth(), None);
+        assert_eq!(unwrap!(stage.latest_report()), Some(report.clone()));
+
+        let day = Duration::days(1);
+        assert_eq!(unwrap!(stage.generate_report_if_due(
+            now + Duration::hours(1), day)), None);
+        unwrap!(stage.write_feed("c", &fx_feed(2)));
+        let later = now + day;
+        let report = unwrap!(stage.generate_report_if_due(later, day))
+                         .unwrap();
+        assert_eq!(report.history.len(), 2);
+        assert_eq!(report.totals(), report.history[1]);
+        assert_eq!((report.totals().feeds, report.totals().entries,
+                    report.totals().unread), (2, 5, 4));
+        assert_eq!(report.growth(), Some(2));
+
+        for i in 0..REPORT_HISTORY_SIZE {
+            unwrap!(stage.generate_report(
+                later + Duration::days(i as i64 + 1)));
+        }
+        let report = unwrap!(stage.latest_report()).unwrap();
+        assert_eq!(report.history.len(), REPORT_HISTORY_SIZE);
+        assert_eq!(report.growth(), Some(0));
+        assert_eq!(Report::from_text("total\tx"), None);
+    }
+}
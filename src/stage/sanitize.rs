@@ -0,0 +1,168 @@
+//! Sanitizing HTML of entries once when they're stored, instead of on
+//! every render, e.g. for apps on low-power devices.
+//!
+//! If `Stage::sanitize_on_write` is set, `write_feed()` and `stream_feed()`
+//! sanitize `content` and `summary` of entries with the preset, and record
+//! it to `Entry::sanitized`, so that rendering with the same preset writes
+//! the HTML as it is.  Entries already sanitized with the preset are left
+//! as they are.
+//!
+//! When the preset is changed, feeds already stored are sanitized again by
+//! `Stage::resanitize()`.  Note that a looser preset can't restore
+//! elements a stricter one dropped; they come back only when the entries
+//! are crawled again.
+//!
+//! Images in stored entries can also be sized by `Stage::size_images()`,
+//! e.g. with sizes of images the app cached, so that layouts don't jump
+//! while they're loaded.  See `sanitizer::size_images()`.
+use feed::Feed;
+use repository::Repository;
+use sanitizer::{ImageSizes, SanitizerPreset};
+
+use super::{Result, Stage};
+
+/// The copy of the feed whose entries are sanitized with the `preset`, or
+/// `None` if all of them already are.  See `Entry::sanitize()`.
+pub fn sanitize_feed(feed: &Feed, preset: SanitizerPreset) -> Option<Feed> {
+    if feed.entries.iter().all(|e| e.sanitized == Some(preset)) {
+        return None;
+    }
+    let mut feed = feed.clone();
+    for entry in feed.entries.iter_mut() {
+        entry.sanitize(preset);
+    }
+    Some(feed)
+}
+
+impl<R: Repository> Stage<R> {
+    /// Sanitize entries of every stored feed with `sanitize_on_write`
+    /// again, e.g. after it's changed.  Only feeds having entries which
+    /// aren't sanitized with the preset are rewritten.  It returns the
+    /// number of rewritten feeds, and does nothing if `sanitize_on_write`
+    /// is `None`.
+    pub fn resanitize(&mut self) -> Result<usize> {
+        let preset = match self.sanitize_on_write {
+            Some(preset) => preset,
+            None => { return Ok(0); }
+        };
+        let mut rewritten = 0;
+        for feed_id in try!(self.feed_ids()) {
+            let feed = try!(self.read_feed(&feed_id));
+            if let Some(sanitized) = sanitize_feed(&feed, preset) {
+                try!(self.write_feed(&feed_id, &sanitized));
+                rewritten += 1;
+            }
+        }
+        Ok(rewritten)
+    }
+
+    /// Make images of entries of every stored feed have both `width` and
+    /// `height` by sizes the `sizes` know.  Only feeds having entries which
+    /// are changed are rewritten.  It returns the number of rewritten
+    /// feeds.  See `Entry::size_images()`.
+    pub fn size_images<S>(&mut self, sizes: &S) -> Result<usize>
+        where S: ImageSizes + ?Sized
+    {
+        let mut rewritten = 0;
+        for feed_id in try!(self.feed_ids()) {
+            let mut feed = try!(self.read_feed(&feed_id));
+            let mut changed = false;
+            for entry in feed.entries.iter_mut() {
+                changed = entry.size_images(sizes) || changed;
+            }
+            if changed {
+                try!(self.write_feed(&feed_id, &feed));
+                rewritten += 1;
+            }
+        }
+        Ok(rewritten)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use std::collections::HashMap;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Blob, Content, Entry, Feed, Text};
+    use html::ToHtml;
+    use mimetype::MimeType;
+    use repository::FileSystemRepository;
+    use sanitizer::SanitizerPreset;
+    use test_utils::temp_dir;
+
+    fn fx_feed() -> Feed {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        let mut entry = Entry::new("urn:entry".to_owned(),
+                                   Text::plain("Entry"), t);
+        let html = "<p>Hi<script>x()</script><table><tr><td>1</td></tr>\
+                    </table></p>";
+        entry.content = Some(unwrap!(Content::new(MimeType::Html,
+                                                  html.as_bytes().to_vec(),
+                                                  None::<String>)));
+        entry.summary = Some(Text::html("<b>Hi</b><script>x()</script>\
+                                         <img src=a.png width=200>"));
+        feed.entries.push(entry);
+        feed
+    }
+
+    #[test]
+    fn test_sanitize_on_write() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        unwrap!(stage.write_feed("a", &fx_feed()));
+        assert_eq!(unwrap!(stage.read_feed("a")).entries[0].sanitized, None);
+        assert_eq!(unwrap!(stage.resanitize()), 0);
+
+        stage.sanitize_on_write = Some(SanitizerPreset::Article);
+        unwrap!(stage.write_feed("b", &fx_feed()));
+        let entry = unwrap!(stage.read_feed("b")).entries[0].clone();
+        assert_eq!(entry.sanitized, Some(SanitizerPreset::Article));
+        let content = entry.content.as_ref().unwrap().as_str().unwrap();
+        assert!(!content.contains("script"));
+        assert!(content.contains("<table>"));
+        assert_eq!(entry.summary,
+                   Some(Text::html("<b>Hi</b><img src=\"a.png\" \
+                                    width=\"200\">")));
+        // HTML sanitized with the preset isn't filtered again on render
+        let mut trusted = fx_feed().entries[0].clone();
+        assert!(!trusted.to_html().to_string().contains("<table>"));
+        trusted.sanitized = Some(SanitizerPreset::Strict);
+        assert!(trusted.to_html().to_string().contains("<table>"));
+
+        assert_eq!(unwrap!(stage.resanitize()), 1);
+        assert_eq!(unwrap!(stage.resanitize()), 0);
+        assert_eq!(unwrap!(stage.read_feed("a")).entries[0].sanitized,
+                   Some(SanitizerPreset::Article));
+
+        stage.sanitize_on_write = Some(SanitizerPreset::Strict);
+        assert_eq!(unwrap!(stage.resanitize()), 2);
+        let entry = unwrap!(stage.read_feed("b")).entries[0].clone();
+        assert_eq!(entry.sanitized, Some(SanitizerPreset::Strict));
+        let content = entry.content.as_ref().unwrap().as_str().unwrap();
+        assert!(!content.contains("<table>"));
+    }
+
+    #[test]
+    fn test_size_images() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        unwrap!(stage.write_feed("a", &fx_feed()));
+        let mut sizes = HashMap::new();
+        assert_eq!(unwrap!(stage.size_images(&sizes)), 0);
+        sizes.insert("a.png".to_owned(), (400, 300));
+        assert_eq!(unwrap!(stage.size_images(&sizes)), 1);
+        assert_eq!(unwrap!(stage.read_feed("a")).entries[0].summary,
+                   Some(Text::html("<b>Hi</b><script>x()</script>\
+                                    <img src=\"a.png\" width=\"200\" \
+                                    height=\"150\">")));
+        assert_eq!(unwrap!(stage.size_images(&sizes)), 0);
+    }
+}
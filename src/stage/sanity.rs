@@ -0,0 +1,169 @@
+//! Sanity checks of crawled feeds before they're merged into the archive.
+//!
+//! A feed claiming half a million entries, or entries dated year 9999,
+//! suggests corruption or abuse rather than news.  Crawled feeds are
+//! checked by `check_feed()` by the `SanityPolicy` of the stage:
+//!
+//! - feeds with more entries than `max_entries` are quarantined, i.e.
+//!   they're stored aside under `quarantine/` by feed IDs for inspection
+//!   instead of being merged; see `Stage::read_quarantined()`;
+//! - dates later than `max_future` from now are clamped to now, and
+//!   reported as `ClampedDate`s.
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+
+use feed::Feed;
+use repository::Repository;
+use schema;
+
+use super::{DocumentKey, Result, Stage};
+
+/// The default of `SanityPolicy::max_entries`.
+pub const DEFAULT_MAX_ENTRIES: usize = 10000;
+
+/// The default of `SanityPolicy::max_future`, in seconds.
+pub const DEFAULT_MAX_FUTURE: u64 = 24 * 60 * 60;
+
+/// Caps of crawled feeds.  `None` turns the check off.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SanityPolicy {
+    /// The maximum number of entries a single crawl may bring.
+    pub max_entries: Option<usize>,
+
+    /// How far in the future dates may be, to tolerate skewed clocks.
+    pub max_future: Option<Duration>,
+}
+
+impl Default for SanityPolicy {
+    fn default() -> SanityPolicy {
+        SanityPolicy {
+            max_entries: Some(DEFAULT_MAX_ENTRIES),
+            max_future: Some(Duration::from_secs(DEFAULT_MAX_FUTURE)),
+        }
+    }
+}
+
+/// Why the crawled feed was quarantined.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Insanity {
+    /// The feed had the number of entries, more than `max_entries`.
+    TooManyEntries(usize),
+}
+
+impl fmt::Display for Insanity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Insanity::TooManyEntries(n) => {
+                write!(f, "the feed has too many entries: {}", n)
+            }
+        }
+    }
+}
+
+/// A date in the future which was clamped to the time of the check.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ClampedDate {
+    /// The ID of the entry the date is of, or `None` if it's of the feed.
+    pub entry_id: Option<String>,
+
+    pub date: DateTime<FixedOffset>,
+}
+
+/// Check the crawled feed by the `policy`, and clamp its dates as the
+/// module documentation describes.  It fails if the feed should be
+/// quarantined.
+pub fn check_feed(feed: &mut Feed, policy: &SanityPolicy,
+                  now: DateTime<FixedOffset>)
+                  -> ::std::result::Result<Vec<ClampedDate>, Insanity> {
+    if let Some(max_entries) = policy.max_entries {
+        if feed.entries.len() > max_entries {
+            return Err(Insanity::TooManyEntries(feed.entries.len()));
+        }
+    }
+    let mut clamped = vec![];
+    let max_future = match policy.max_future {
+        Some(max_future) => {
+            ::chrono::Duration::seconds(max_future.as_secs() as i64)
+        }
+        None => { return Ok(clamped); }
+    };
+    {
+        let mut clamp = |date: &mut DateTime<FixedOffset>,
+                         entry_id: Option<&str>| {
+            if *date - now > max_future {
+                clamped.push(ClampedDate {
+                    entry_id: entry_id.map(|id| id.to_owned()),
+                    date: *date,
+                });
+                *date = now;
+            }
+        };
+        clamp(&mut feed.updated_at, None);
+        for entry in feed.entries.iter_mut() {
+            let metadata = &mut entry.metadata;
+            clamp(&mut metadata.updated_at, Some(&metadata.id));
+            if let Some(ref mut published_at) = entry.published_at {
+                clamp(published_at, Some(&metadata.id));
+            }
+        }
+    }
+    Ok(clamped)
+}
+
+impl<R: Repository> Stage<R> {
+    /// Store the crawled `feed` of `feed_id` aside for inspection, instead
+    /// of merging it.  It replaces the one quarantined before if any.
+    pub fn quarantine(&mut self, feed_id: &str, feed: &Feed) -> Result<()> {
+        let key = self.document_key(
+            DocumentKey::Quarantined(feed_id.to_owned()));
+        let w = try!(self.repository.get_writer(&key));
+        try!(schema::write(feed, w));
+        Ok(())
+    }
+
+    /// The feed of `feed_id` quarantined last, if any.
+    pub fn read_quarantined(&self, feed_id: &str) -> Result<Option<Feed>> {
+        let key = self.document_key(
+            DocumentKey::Quarantined(feed_id.to_owned()));
+        if !self.repository.exists(&key) {
+            return Ok(None);
+        }
+        let r = try!(self.repository.get_reader(&key));
+        Ok(Some(try!(schema::read(r))))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{ClampedDate, Insanity, SanityPolicy, check_feed};
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Text};
+
+    #[test]
+    fn test_check_feed() {
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let far = FixedOffset::east(0).ymd(9999, 1, 1).and_hms(0, 0, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), now);
+        let mut entry = Entry::new("urn:a".to_owned(), Text::plain("A"), far);
+        entry.published_at = Some(now + Duration::hours(1));
+        feed.entries.push(entry);
+        feed.entries.push(Entry::new("urn:b".to_owned(), Text::plain("B"),
+                                     now));
+        let policy = SanityPolicy::default();
+        assert_eq!(unwrap!(check_feed(&mut feed, &policy, now)),
+                   [ClampedDate { entry_id: Some("urn:a".to_owned()),
+                                  date: far }]);
+        assert_eq!(feed.entries[0].updated_at, now);
+        assert_eq!(feed.entries[0].published_at,
+                   Some(now + Duration::hours(1)));
+
+        let policy = SanityPolicy { max_entries: Some(1), max_future: None };
+        assert_eq!(check_feed(&mut feed, &policy, now),
+                   Err(Insanity::TooManyEntries(2)));
+    }
+}
@@ -0,0 +1,287 @@
+//! Read-only views of the stage pinned to a flush generation, for long
+//! running readers e.g. exports and searches which shouldn't observe
+//! documents half-flushed.
+//!
+//! A snapshot reads the underlying repository through its own handle, so
+//! the repository has to be `Clone`, e.g. `FileSystemRepository`.  Before
+//! the live stage overwrites or removes documents on flush, it preserves
+//! their previous contents in every snapshot still alive, so snapshots
+//! keep reading what was flushed when they were taken.  Buffered changes
+//! not flushed yet aren't visible to snapshots either.
+//!
+//! Snapshots block flushes while they read a document, and flushes block
+//! snapshots, so they can be read from other threads.
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+
+use repository as repo;
+use repository::{Names, Repository};
+
+use super::Stage;
+
+/// Documents snapshots see differently from the underlying repository, as
+/// pairs of a key and its content, or `None` if it didn't exist.
+#[derive(Default)]
+pub struct Preserved {
+    documents: HashMap<Vec<String>, Option<Vec<u8>>>,
+}
+
+pub type SharedPreserved = Arc<Mutex<Preserved>>;
+
+/// Preserve current contents of the `keys` in the live snapshots, and call
+/// `f` while they're blocked.  Dead snapshots are removed from the list.
+pub fn preserving<R, F, T>(inner: &mut R,
+                           snapshots: &mut Vec<Weak<Mutex<Preserved>>>,
+                           keys: &[Vec<String>], f: F) -> repo::Result<T>
+    where R: Repository, F: FnOnce(&mut R) -> repo::Result<T>
+{
+    let live: Vec<SharedPreserved> =
+        snapshots.iter().filter_map(|s| s.upgrade()).collect();
+    snapshots.retain(|s| s.upgrade().is_some());
+    let mut guards: Vec<_> = live.iter().map(|s| s.lock().unwrap())
+                                 .collect();
+    if !guards.is_empty() {
+        for key in keys {
+            if guards.iter().all(|g| g.documents.contains_key(key)) {
+                continue;
+            }
+            let content = if inner.exists(key) && inner.list(key).is_err() {
+                Some(try!(inner.read(key)))
+            } else {
+                None
+            };
+            for guard in guards.iter_mut() {
+                guard.documents.entry(key.clone())
+                     .or_insert_with(|| content.clone());
+            }
+        }
+    }
+    f(inner)
+}
+
+/// The repository of a snapshot.  It can't be written.
+pub struct Snapshot<R> {
+    inner: R,
+    preserved: SharedPreserved,
+    generation: u64,
+}
+
+impl<R: Repository> Snapshot<R> {
+    /// The flush generation the snapshot is pinned to; see
+    /// `Stage::generation()`.
+    pub fn generation(&self) -> u64 { self.generation }
+
+    fn visible(&self, preserved: &Preserved, key: &[String]) -> bool {
+        if let Some(content) = preserved.documents.get(key) {
+            return content.is_some();
+        }
+        let preserved_child = preserved.documents.iter().any(|(k, v)| {
+            v.is_some() && k.len() > key.len() && k.starts_with(key)
+        });
+        if preserved_child {
+            return true;
+        }
+        match self.inner.list(key) {
+            Ok(names) => {
+                let names: Vec<String> = names.filter_map(|n| n.ok())
+                                              .collect();
+                names.into_iter().any(|name| {
+                    let mut child = key.to_vec();
+                    child.push(name);
+                    self.visible(preserved, &child)
+                })
+            }
+            Err(_) => self.inner.exists(key),
+        }
+    }
+}
+
+fn to_key<T: AsRef<str>>(key: &[T]) -> Vec<String> {
+    key.iter().map(|k| k.as_ref().to_owned()).collect()
+}
+
+fn read_only() -> repo::Error {
+    repo::Error::Io(io::Error::new(io::ErrorKind::PermissionDenied,
+                                   "the snapshot is read-only"))
+}
+
+impl<R: Repository> Repository for Snapshot<R> {
+    fn get_reader<'a, T: AsRef<str>>(&'a self, key: &[T]) ->
+        repo::Result<Box<io::BufRead + 'a>>
+    {
+        let key = to_key(key);
+        let preserved = self.preserved.lock().unwrap();
+        let content = match preserved.documents.get(&key) {
+            Some(Some(content)) => content.clone(),
+            Some(None) => {
+                return Err(repo::Error::invalid_key(&key, None));
+            }
+            None => try!(self.inner.read(&key)),
+        };
+        Ok(Box::new(io::Cursor::new(content)) as Box<io::BufRead>)
+    }
+
+    fn get_reader_range<'a, T: AsRef<str>>(&'a self, key: &[T], start: u64,
+                                           len: Option<u64>) ->
+        repo::Result<Box<io::BufRead + 'a>>
+    {
+        let key = to_key(key);
+        let preserved = self.preserved.lock().unwrap();
+        let content = match preserved.documents.get(&key) {
+            Some(Some(content)) => content.clone(),
+            Some(None) => {
+                return Err(repo::Error::invalid_key(&key, None));
+            }
+            None => {
+                let mut buf = vec![];
+                let mut reader = try!(self.inner.get_reader_range(&key, start,
+                                                                  len));
+                try!(reader.read_to_end(&mut buf));
+                return Ok(Box::new(io::Cursor::new(buf)) as Box<io::BufRead>);
+            }
+        };
+        Ok(repo::range_reader(io::Cursor::new(content), start, len))
+    }
+
+    fn get_writer<'a, T: AsRef<str>>(&'a mut self, _key: &[T]) ->
+        repo::Result<Box<io::Write + 'a>>
+    {
+        Err(read_only())
+    }
+
+    fn exists<T: AsRef<str>>(&self, key: &[T]) -> bool {
+        let preserved = self.preserved.lock().unwrap();
+        self.visible(&preserved, &to_key(key))
+    }
+
+    fn delete<T: AsRef<str>>(&mut self, _key: &[T]) -> repo::Result<()> {
+        Err(read_only())
+    }
+
+    fn list<T: AsRef<str>>(&self, key: &[T]) -> repo::Result<Names> {
+        let key = to_key(key);
+        let preserved = self.preserved.lock().unwrap();
+        let mut names = BTreeSet::new();
+        let listed = match self.inner.list(&key) {
+            Ok(inner) => {
+                for name in inner {
+                    names.insert(try!(name));
+                }
+                true
+            }
+            Err(_) => false,
+        };
+        let mut preserved_dir = false;
+        for (k, v) in preserved.documents.iter() {
+            if v.is_some() && k.len() > key.len() && k.starts_with(&key) {
+                names.insert(k[key.len()].clone());
+                preserved_dir = true;
+            }
+        }
+        if !listed && !preserved_dir {
+            return Err(repo::Error::invalid_key(&key, None));
+        }
+        let names: Vec<String> = names.into_iter().filter(|name| {
+            let mut child = key.clone();
+            child.push(name.clone());
+            self.visible(&preserved, &child)
+        }).collect();
+        Ok(Box::new(names.into_iter().map(Ok)) as Names)
+    }
+}
+
+impl<R: Repository + Clone> Stage<R> {
+    /// Take a read-only view of the stage as of the last flush.  The stage
+    /// it returns shares the settings of this stage e.g. `key_layout`, and
+    /// its writes fail on flush.
+    pub fn snapshot(&mut self) -> Stage<Snapshot<R>> {
+        let preserved: SharedPreserved = Default::default();
+        self.repository.register_snapshot(Arc::downgrade(&preserved));
+        let snapshot = Snapshot {
+            inner: self.repository.inner().clone(),
+            preserved: preserved,
+            generation: self.generation(),
+        };
+        let mut stage = Stage::new(snapshot);
+        stage.entries_per_page = self.entries_per_page;
+        stage.key_layout = self.key_layout;
+        stage
+    }
+}
+
+impl<R: Repository> Stage<R> {
+    /// The number of flushes which wrote documents so far.
+    pub fn generation(&self) -> u64 { self.repository.generation() }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use std::thread;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Text};
+    use repository::{FileSystemRepository, Repository};
+    use test_utils::temp_dir;
+
+    fn fx_feed(entries: usize) -> Feed {
+        let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                             .and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:earthreader:test", Text::plain("Test"),
+                                 updated_at);
+        for i in 0..entries {
+            feed.entries.push(Entry::new(format!("urn:entry:{}", i),
+                                         Text::plain(format!("{}", i)),
+                                         updated_at));
+        }
+        feed
+    }
+
+    #[test]
+    fn test_stage_snapshot() {
+        let tmpdir = temp_dir();
+        let repo = unwrap!(FileSystemRepository::from_path(tmpdir.path(),
+                                                           true));
+        let mut stage = Stage::new(repo);
+        stage.entries_per_page = Some(2);
+        unwrap!(stage.write_feed("a", &fx_feed(3)));
+        unwrap!(stage.flush());
+        assert_eq!(stage.generation(), 1);
+        unwrap!(stage.write_feed("b", &fx_feed(1)));
+        let snapshot = stage.snapshot();
+        assert_eq!(snapshot.repository.inner().generation(), 1);
+        assert_eq!(unwrap!(snapshot.feed_ids()), ["a"]);
+
+        unwrap!(stage.write_feed("a", &fx_feed(1)));
+        unwrap!(stage.flush());
+        assert_eq!(stage.generation(), 2);
+        assert_eq!(unwrap!(stage.read_feed("a")).entries.len(), 1);
+        let mut ids = unwrap!(stage.feed_ids());
+        ids.sort();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(unwrap!(snapshot.feed_ids()), ["a"]);
+        assert!(!snapshot.repository.exists(&["feeds", "b"]));
+        {
+            let preserved = snapshot.repository.inner();
+            let head = unwrap!(preserved.read(&["feeds", "a"]));
+            let mut range = vec![];
+            unwrap!(unwrap!(preserved.get_reader_range(&["feeds", "a"], 5,
+                                                       Some(10)))
+                        .read_to_end(&mut range));
+            assert_eq!(&range[..], &head[5..15]);
+        }
+        let handle = thread::spawn(move || {
+            unwrap!(snapshot.read_feed("a")).entries.len()
+        });
+        assert_eq!(handle.join().unwrap(), 3);
+
+        let mut snapshot = stage.snapshot();
+        assert_eq!(unwrap!(snapshot.read_feed("a")).entries.len(), 1);
+        unwrap!(snapshot.write_feed("c", &fx_feed(1)));
+        assert!(snapshot.flush().is_err());
+    }
+}
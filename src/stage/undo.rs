@@ -0,0 +1,182 @@
+//! Undo and redo history of the user's changes made through the stage.
+use feed::MarkKind;
+use subscribe::Subscription;
+
+/// The number of change groups the history keeps to undo, and to redo.
+/// The oldest groups are forgotten beyond it.
+pub const UNDO_HISTORY_SIZE: usize = 100;
+
+/// A change which can be reverted.  It only keeps what is necessary to
+/// revert the change, not the whole documents.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Change {
+    /// The mark of the entry was changed from `marked`.
+    Mark {
+        feed_id: String,
+        entry_id: String,
+        kind: MarkKind,
+        marked: bool,
+    },
+
+    /// The feed of `feed_id` was subscribed in the category of `path`.
+    Subscribe {
+        path: Vec<String>,
+        feed_id: String,
+    },
+
+    /// The subscription was removed from the category of `path`.
+    Unsubscribe {
+        path: Vec<String>,
+        subscription: Subscription,
+    },
+}
+
+/// The stacks of change groups.  Each group is what a single `undo()`
+/// reverts, and groups are separated by `checkpoint()`.
+#[derive(Clone, Default, Debug)]
+pub struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+}
+
+impl History {
+    /// Record the new change into the current group.  Since the change
+    /// makes the redo history no longer valid, it's cleared.
+    pub fn record(&mut self, change: Change) {
+        self.redo.clear();
+        if self.undo.is_empty() {
+            self.undo.push(vec![]);
+        }
+        self.undo.last_mut().unwrap().push(change);
+    }
+
+    /// Close the current group so that the following changes are undone
+    /// separately.
+    pub fn checkpoint(&mut self) {
+        if self.undo.last().is_some_and(|g| !g.is_empty()) {
+            self.undo.push(vec![]);
+            truncate_front(&mut self.undo, UNDO_HISTORY_SIZE + 1);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.undo.iter().any(|g| !g.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+
+    /// Take the last group of changes to undo.  Changes recorded after
+    /// that go to a new group, not to the group before the taken one.
+    pub fn pop_undo(&mut self) -> Option<Vec<Change>> {
+        while let Some(group) = self.undo.pop() {
+            if !group.is_empty() {
+                self.undo.push(vec![]);
+                return Some(group);
+            }
+        }
+        None
+    }
+
+    /// Take the last undone group to redo.
+    pub fn pop_redo(&mut self) -> Option<Vec<Change>> { self.redo.pop() }
+
+    /// Push the group that reverts undone changes.
+    pub fn push_redo(&mut self, group: Vec<Change>) {
+        self.redo.push(group);
+        truncate_front(&mut self.redo, UNDO_HISTORY_SIZE);
+    }
+
+    /// Push the group that reverts redone changes.  Unlike `record()` it
+    /// doesn't clear the redo history.
+    pub fn push_undo(&mut self, group: Vec<Change>) {
+        self.checkpoint();
+        match self.undo.last_mut() {
+            Some(last) if last.is_empty() => { *last = group; }
+            _ => { self.undo.push(group); }
+        }
+        self.undo.push(vec![]);
+        truncate_front(&mut self.undo, UNDO_HISTORY_SIZE + 1);
+    }
+}
+
+/// Remove the oldest groups of the `stack` beyond `size`.
+fn truncate_front(stack: &mut Vec<Vec<Change>>, size: usize) {
+    if stack.len() > size {
+        let excess = stack.len() - size;
+        stack.drain(..excess);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Change, History, UNDO_HISTORY_SIZE};
+
+    use feed::MarkKind;
+
+    fn fx_change(entry_id: &str) -> Change {
+        Change::Mark {
+            feed_id: "feed".to_owned(),
+            entry_id: entry_id.to_owned(),
+            kind: MarkKind::Read,
+            marked: false,
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history: History = Default::default();
+        assert!(!history.can_undo());
+        history.checkpoint();
+        assert!(!history.can_undo());
+        history.record(fx_change("a"));
+        history.record(fx_change("b"));
+        history.checkpoint();
+        history.checkpoint();
+        history.record(fx_change("c"));
+        assert_eq!(history.pop_undo(), Some(vec![fx_change("c")]));
+        history.push_redo(vec![fx_change("c")]);
+        assert!(history.can_redo());
+        assert_eq!(history.pop_undo(),
+                   Some(vec![fx_change("a"), fx_change("b")]));
+        assert_eq!(history.pop_undo(), None);
+        history.record(fx_change("d"));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn test_history_record_after_undo() {
+        let mut history: History = Default::default();
+        history.record(fx_change("a"));
+        history.checkpoint();
+        history.record(fx_change("b"));
+        assert_eq!(history.pop_undo(), Some(vec![fx_change("b")]));
+        // recorded without checkpoint(), yet not merged into the group of a
+        history.record(fx_change("c"));
+        assert_eq!(history.pop_undo(), Some(vec![fx_change("c")]));
+        assert_eq!(history.pop_undo(), Some(vec![fx_change("a")]));
+        assert_eq!(history.pop_undo(), None);
+    }
+
+    #[test]
+    fn test_history_size() {
+        let mut history: History = Default::default();
+        for i in 0..(UNDO_HISTORY_SIZE + 10) {
+            history.record(fx_change(&i.to_string()));
+            history.checkpoint();
+        }
+        let mut undone = 0;
+        while let Some(group) = history.pop_undo() {
+            history.push_redo(group);
+            undone += 1;
+        }
+        assert_eq!(undone, UNDO_HISTORY_SIZE);
+        assert_eq!(history.pop_undo(), None);
+        history.push_redo(vec![fx_change("extra")]);
+        let mut redone = 0;
+        while history.pop_redo().is_some() {
+            redone += 1;
+        }
+        assert_eq!(redone, UNDO_HISTORY_SIZE);
+    }
+}
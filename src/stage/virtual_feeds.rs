@@ -0,0 +1,220 @@
+//! Virtual feeds, which split a busy feed e.g. an aggregating planet into
+//! several by rules, e.g. only entries tagged `rust` of a firehose.
+//!
+//! A virtual feed is defined by its source feed and a rule in the query
+//! language (see the `query` module), e.g. `category:rust` or
+//! `author:dahlia`.  Only definitions are stored; entries are filtered
+//! from the source feed whenever the virtual feed is read by
+//! `Stage::read_virtual_feed()`, so they aren't stored twice.  Marks of
+//! entries are set through the source feed, i.e. `VirtualFeed::source`.
+//!
+//! Definitions are kept in a plain text document, and each line is a
+//! definition of tab-separated fields: the ID, the source feed ID, the
+//! rule, and the title.  Lines whose rules don't parse are skipped.
+use chrono::{DateTime, FixedOffset};
+
+use feed::{Feed, Text};
+use query::{ParseError, Query};
+use repository::Repository;
+
+use super::{DocumentKey, Result, Stage};
+use super::journal::{escape, unescape};
+
+/// The definition of a virtual feed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VirtualFeed {
+    pub id: String,
+
+    /// The ID of the feed entries are filtered from.
+    pub source: String,
+
+    pub title: String,
+
+    /// The rule as it's written.
+    rule: String,
+
+    query: Query,
+}
+
+impl VirtualFeed {
+    /// Define the virtual feed of entries of the feed `source` matching the
+    /// `rule`.  It fails if the rule doesn't parse.
+    pub fn new<T, U, V>(id: T, source: U, title: V, rule: &str)
+                        -> ::std::result::Result<VirtualFeed, ParseError>
+        where T: Into<String>, U: Into<String>, V: Into<String>
+    {
+        Ok(VirtualFeed {
+            id: id.into(),
+            source: source.into(),
+            title: title.into(),
+            rule: rule.to_owned(),
+            query: try!(rule.parse()),
+        })
+    }
+
+    /// The rule as it's written, e.g. `category:rust`.
+    pub fn rule(&self) -> &str { &self.rule }
+
+    pub fn query(&self) -> &Query { &self.query }
+
+    fn to_line(&self) -> String {
+        [escape(&self.id), escape(&self.source), escape(&self.rule),
+         escape(&self.title)].join("\t")
+    }
+
+    fn from_line(line: &str) -> Option<VirtualFeed> {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        VirtualFeed::new(&fields[0][..], &fields[1][..], &fields[3][..],
+                         &fields[2]).ok()
+    }
+}
+
+impl<R: Repository> Stage<R> {
+    /// Definitions of virtual feeds, in the order they were defined.
+    pub fn virtual_feeds(&self) -> Result<Vec<VirtualFeed>> {
+        let key = self.document_key(DocumentKey::VirtualFeeds);
+        if !self.repository.exists(&key) {
+            return Ok(vec![]);
+        }
+        let buf = try!(self.repository.read(&key));
+        let text = String::from_utf8_lossy(&buf);
+        Ok(text.lines().filter_map(VirtualFeed::from_line).collect())
+    }
+
+    /// Store the definition of the virtual feed.  The one of the same ID
+    /// is replaced.
+    pub fn define_virtual_feed(&mut self, virtual_feed: &VirtualFeed)
+                               -> Result<()> {
+        let mut definitions = try!(self.virtual_feeds());
+        match definitions.iter().position(|v| v.id == virtual_feed.id) {
+            Some(i) => { definitions[i] = virtual_feed.clone(); }
+            None => { definitions.push(virtual_feed.clone()); }
+        }
+        self.write_virtual_feeds(&definitions)
+    }
+
+    /// Remove the definition of the virtual feed of `id`.  It returns
+    /// `false` if there's no such virtual feed.  Entries of its source
+    /// aren't touched.
+    pub fn remove_virtual_feed(&mut self, id: &str) -> Result<bool> {
+        let mut definitions = try!(self.virtual_feeds());
+        let count = definitions.len();
+        definitions.retain(|v| v.id != id);
+        if definitions.len() == count {
+            return Ok(false);
+        }
+        try!(self.write_virtual_feeds(&definitions));
+        Ok(true)
+    }
+
+    /// Materialize the virtual feed of `id`: the source feed titled as the
+    /// virtual feed, with only entries matching its rule, in the order of
+    /// `sorted_entries()`.  `now` is the time relative times of the rule
+    /// are resolved against.  It's `None` if there's no such virtual feed,
+    /// or its source isn't stored.
+    pub fn read_virtual_feed(&self, id: &str, now: DateTime<FixedOffset>)
+                             -> Result<Option<Feed>> {
+        let definitions = try!(self.virtual_feeds());
+        let virtual_feed = match definitions.iter().find(|v| v.id == id) {
+            Some(virtual_feed) => virtual_feed,
+            None => { return Ok(None); }
+        };
+        let source = &virtual_feed.source;
+        if !self.repository.exists(&self.feed_key(source)) {
+            return Ok(None);
+        }
+        let mut feed = try!(self.read_feed(source));
+        feed.title = Text::plain(&virtual_feed.title[..]);
+        feed.entries = try!(self.sorted_entries(source)).into_iter()
+            .filter(|e| virtual_feed.query.matches(source, e, now))
+            .collect();
+        Ok(Some(feed))
+    }
+
+    fn write_virtual_feeds(&mut self, definitions: &[VirtualFeed])
+                           -> Result<()> {
+        let mut buf = String::new();
+        for virtual_feed in definitions {
+            buf.push_str(&virtual_feed.to_line());
+            buf.push('\n');
+        }
+        let key = self.document_key(DocumentKey::VirtualFeeds);
+        try!(self.repository.write(&key, Some(buf.into_bytes())));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+    use super::VirtualFeed;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::{Category, Entry, Feed, Person, Text};
+    use repository::FileSystemRepository;
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_virtual_feed() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut feed = Feed::new("urn:planet", Text::plain("Planet"), now);
+        for i in 0..3 {
+            let mut entry = Entry::new(format!("urn:entry:{}", i),
+                                       Text::plain("Entry"), now);
+            if i != 1 {
+                entry.categories.push(Category {
+                    term: "rust".into(),
+                    ..Default::default()
+                });
+            }
+            entry.authors.push(Person::new(if i == 0 { "dahlia" }
+                                           else { "kroisse" }));
+            feed.entries.push(entry);
+        }
+        unwrap!(stage.write_feed("planet", &feed));
+
+        assert!(VirtualFeed::new("bad", "planet", "Bad", "(rust").is_err());
+        let rust = unwrap!(VirtualFeed::new("rust", "planet", "Rust\tnews",
+                                            "category:rust"));
+        assert_eq!(rust.rule(), "category:rust");
+        unwrap!(stage.define_virtual_feed(&rust));
+        unwrap!(stage.define_virtual_feed(&unwrap!(VirtualFeed::new(
+            "kroisse", "planet", "Kroisse", "author:kroisse"))));
+        unwrap!(stage.define_virtual_feed(&unwrap!(VirtualFeed::new(
+            "orphan", "gone", "Orphan", ""))));
+        assert_eq!(unwrap!(stage.virtual_feeds())[0], rust);
+
+        let materialized = unwrap!(stage.read_virtual_feed("rust", now))
+            .unwrap();
+        assert_eq!(materialized.title, Text::plain("Rust\tnews"));
+        let ids: Vec<&str> =
+            materialized.entries.iter().map(|e| &e.id[..]).collect();
+        assert_eq!(ids, ["urn:entry:0", "urn:entry:2"]);
+        let kroisse = unwrap!(stage.read_virtual_feed("kroisse", now))
+            .unwrap();
+        assert_eq!(kroisse.entries.len(), 2);
+        assert!(unwrap!(stage.read_virtual_feed("orphan", now)).is_none());
+        assert!(unwrap!(stage.read_virtual_feed("nothing", now)).is_none());
+        // entries aren't stored twice
+        assert_eq!(unwrap!(stage.feed_ids()), ["planet"]);
+
+        let rule = "author:kroisse -category:rust";
+        unwrap!(stage.define_virtual_feed(&unwrap!(VirtualFeed::new(
+            "kroisse", "planet", "Kroisse", rule))));
+        let kroisse = unwrap!(stage.read_virtual_feed("kroisse", now))
+            .unwrap();
+        assert_eq!(kroisse.entries[0].id, "urn:entry:1");
+        assert_eq!(unwrap!(stage.virtual_feeds()).len(), 3);
+        assert!(unwrap!(stage.remove_virtual_feed("kroisse")));
+        assert!(!unwrap!(stage.remove_virtual_feed("kroisse")));
+        assert!(unwrap!(stage.read_virtual_feed("kroisse", now)).is_none());
+    }
+}
@@ -0,0 +1,112 @@
+//! Shared vocabularies of tags the stage keeps, so that they're
+//! synchronized with the rest of the archive.  See the
+//! `subscribe::vocabulary` module for the documents themselves.
+use digest::sha1_hex;
+use feed::Category;
+use repository::Repository;
+use schema;
+use subscribe::CategoryDocument;
+
+use super::{DocumentKey, Result, Stage};
+
+impl<R: Repository> Stage<R> {
+    fn vocabulary_key(&self, uri: &str) -> Vec<String> {
+        self.document_key(DocumentKey::Vocabulary(sha1_hex(uri.as_bytes())))
+    }
+
+    /// The category document of the `uri`, if it's stored.
+    pub fn read_vocabulary(&self, uri: &str)
+                           -> Result<Option<CategoryDocument>> {
+        let key = self.vocabulary_key(uri);
+        if !self.repository.exists(&key) {
+            return Ok(None);
+        }
+        self.read_document(&key).map(Some)
+    }
+
+    /// Store the category document of the `uri`, e.g. after fetching it.
+    /// Documents which refer to out-of-line ones are stored as they are;
+    /// fetch and store the one of their `href` as well.
+    pub fn write_vocabulary(&mut self, uri: &str, document: &CategoryDocument)
+                            -> Result<()> {
+        let key = self.vocabulary_key(uri);
+        let w = try!(self.repository.get_writer(&key));
+        try!(schema::write(document, w));
+        Ok(())
+    }
+
+    /// Tags offered for entries of the feed of `feed_id`, i.e. categories
+    /// of the vocabulary its settings tell; see
+    /// `SubscriptionSettings::tag_vocabulary()`.  References to out-of-line
+    /// documents are followed once.  It's empty if the feed has no
+    /// vocabulary, or the vocabulary isn't stored yet.
+    pub fn offered_tags(&self, feed_id: &str) -> Result<Vec<Category>> {
+        let settings = try!(self.read_settings(feed_id));
+        let uri = match settings.tag_vocabulary() {
+            Some(uri) => uri,
+            None => { return Ok(vec![]); }
+        };
+        let mut document = match try!(self.read_vocabulary(uri)) {
+            Some(document) => document,
+            None => { return Ok(vec![]); }
+        };
+        if let Some(href) = document.href.clone() {
+            document = match try!(self.read_vocabulary(&href)) {
+                Some(document) => document,
+                None => { return Ok(vec![]); }
+            };
+        }
+        Ok(document.resolved_categories())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::Stage;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use feed::Category;
+    use repository::FileSystemRepository;
+    use subscribe::{CategoryDocument, SubscriptionSettings};
+    use test_utils::temp_dir;
+
+    #[test]
+    fn test_offered_tags() {
+        let tmpdir = temp_dir();
+        let mut stage = Stage::new(unwrap!(FileSystemRepository::from_path(
+            tmpdir.path(), true)));
+        assert!(unwrap!(stage.offered_tags("feed")).is_empty());
+
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut settings = SubscriptionSettings::default();
+        settings.set_tag_vocabulary(Some("http://example.com/ref.xml"), now);
+        unwrap!(stage.write_settings("feed", &settings));
+        assert!(unwrap!(stage.offered_tags("feed")).is_empty());
+
+        let reference = CategoryDocument {
+            href: Some("http://example.com/tags.xml".to_owned()),
+            ..Default::default()
+        };
+        unwrap!(stage.write_vocabulary("http://example.com/ref.xml",
+                                       &reference));
+        assert!(unwrap!(stage.offered_tags("feed")).is_empty());
+
+        let tags = CategoryDocument {
+            fixed: true,
+            scheme_uri: Some("http://example.com/tags/".to_owned()),
+            categories: vec![Category { term: "rust".into(),
+                                        ..Default::default() }],
+            ..Default::default()
+        };
+        unwrap!(stage.write_vocabulary("http://example.com/tags.xml", &tags));
+        unwrap!(stage.flush());
+        assert_eq!(unwrap!(stage.read_vocabulary(
+            "http://example.com/tags.xml")), Some(tags.clone()));
+        assert_eq!(unwrap!(stage.offered_tags("feed")),
+                   tags.resolved_categories());
+        assert_eq!(unwrap!(stage.offered_tags("feed"))[0].scheme_uri,
+                   Some("http://example.com/tags/".to_owned()));
+    }
+}
@@ -0,0 +1,308 @@
+//! Statistics of feeds derived from timestamps and titles of their entries,
+//! e.g. how often they're updated, or the site name every title repeats.
+//!
+//! Cadences are meant both for the crawler to schedule feeds, and for apps
+//! to tell people how active feeds are, e.g. "updates ~daily" in the
+//! subscription list:
+//!
+//! ```
+//! # extern crate chrono;
+//! # extern crate earth;
+//! # fn main() {
+//! use chrono::{Duration, FixedOffset, TimeZone};
+//! use earth::feed::{Entry, Feed, Text};
+//! use earth::stats::{Cadence, FeedStats};
+//!
+//! let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+//! let mut feed = Feed::new("urn:feed", Text::plain("Feed"), now);
+//! for day in 0..10 {
+//!     let t = now - Duration::days(day);
+//!     feed.entries.push(Entry::new(format!("urn:{}", day),
+//!                                  Text::plain("Entry"), t));
+//! }
+//! let stats = FeedStats::new(&feed);
+//! assert_eq!(stats.cadence(now), Some(Cadence::Daily));
+//! assert_eq!(stats.cadence(now).unwrap().to_string(), "updates ~daily");
+//! # }
+//! ```
+use std::fmt;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, FixedOffset};
+
+use feed::Feed;
+
+/// How often the feed is updated.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Cadence {
+    /// More than once a day.
+    MultiplePerDay,
+
+    /// About once a day.
+    Daily,
+
+    /// About once a week, or less often.
+    Weekly,
+
+    /// No longer updated, i.e. it's been silent much longer than it used
+    /// to be.
+    Dormant,
+}
+
+impl Cadence {
+    /// The interval the crawler should crawl the feed of the cadence at.
+    pub fn crawl_interval(&self) -> StdDuration {
+        let hours = match *self {
+            Cadence::MultiplePerDay => 1,
+            Cadence::Daily => 6,
+            Cadence::Weekly => 24,
+            Cadence::Dormant => 7 * 24,
+        };
+        StdDuration::from_secs(hours * 3600)
+    }
+}
+
+impl fmt::Display for Cadence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Cadence::MultiplePerDay => "updates several times a day",
+            Cadence::Daily => "updates ~daily",
+            Cadence::Weekly => "updates ~weekly",
+            Cadence::Dormant => "dormant",
+        })
+    }
+}
+
+/// Separators feeds put between titles of entries and the name of the
+/// site, e.g. `Site: Title` or `Title – Site`.
+const TITLE_SEPARATORS: &'static [char] = &[
+    ':', '|', '-', '–', '—', '»', '·', '/',
+];
+
+/// The fewest entries the common prefix and suffix of titles are detected
+/// from, so that a couple of titles which happen to begin with the same
+/// words aren't cut.
+const MIN_AFFIX_TITLES: usize = 3;
+
+/// Statistics of the feed at the moment it's made.  It doesn't borrow the
+/// feed.
+#[derive(Clone, Debug)]
+pub struct FeedStats {
+    /// When entries were posted, i.e. `published_at` or `updated_at` if
+    /// it's missing, in chronological order.
+    pub posted_at: Vec<DateTime<FixedOffset>>,
+
+    /// The prefix every entry title of the feed has, up to a separator,
+    /// e.g. `Blog Name: `.  It's empty if there's none.  See
+    /// `Entry::display_title()`.
+    pub title_prefix: String,
+
+    /// The suffix every entry title of the feed has, from a separator,
+    /// e.g. ` – Site Name`.  It's empty if there's none.
+    pub title_suffix: String,
+}
+
+impl FeedStats {
+    pub fn new(feed: &Feed) -> FeedStats {
+        let mut posted_at: Vec<_> = feed.entries.iter().map(|e| {
+            *e.published_at.as_ref().unwrap_or(&e.updated_at)
+        }).collect();
+        posted_at.sort();
+        let titles: Vec<_> = feed.entries.iter()
+                                 .map(|e| e.title.as_plain_text())
+                                 .collect();
+        let (prefix, suffix) = if titles.len() < MIN_AFFIX_TITLES {
+            ("", "")
+        } else {
+            let first = &titles[0][..];
+            let prefix = titles[1..].iter()
+                                    .fold(first, |p, t| common_prefix(p, t));
+            let suffix = titles[1..].iter()
+                                    .fold(first, |s, t| common_suffix(s, t));
+            (title_prefix(prefix), title_suffix(suffix))
+        };
+        FeedStats {
+            posted_at: posted_at,
+            title_prefix: prefix.to_owned(),
+            title_suffix: suffix.to_owned(),
+        }
+    }
+
+    pub fn last_posted_at(&self) -> Option<&DateTime<FixedOffset>> {
+        self.posted_at.last()
+    }
+
+    /// The median interval between consecutive posts.  It's `None` unless
+    /// there are at least two posts.
+    pub fn typical_interval(&self) -> Option<Duration> {
+        let mut intervals: Vec<Duration> = self.posted_at.windows(2)
+            .map(|w| w[1] - w[0])
+            .collect();
+        if intervals.is_empty() {
+            return None;
+        }
+        intervals.sort();
+        Some(intervals[intervals.len() / 2])
+    }
+
+    /// Classify how often the feed is updated as of `now`.  It's `None` if
+    /// there are too few posts to tell.
+    ///
+    /// The feed is dormant if it's been silent for four times its typical
+    /// interval, and at least 30 days.
+    pub fn cadence(&self, now: DateTime<FixedOffset>) -> Option<Cadence> {
+        let last = match self.last_posted_at() {
+            Some(t) => *t,
+            None => { return None; }
+        };
+        let silence = now - last;
+        let interval = match self.typical_interval() {
+            Some(i) => i,
+            None if silence > Duration::days(30) => {
+                return Some(Cadence::Dormant);
+            }
+            None => { return None; }
+        };
+        if silence > Duration::days(30) && silence > interval * 4 {
+            Some(Cadence::Dormant)
+        } else if interval < Duration::hours(20) {
+            Some(Cadence::MultiplePerDay)
+        } else if interval < Duration::hours(84) {
+            Some(Cadence::Daily)
+        } else {
+            Some(Cadence::Weekly)
+        }
+    }
+
+    /// The window the next post is expected in, as a pair of the earliest
+    /// and the latest moments: half to one and a half of the typical
+    /// interval after the last post.  It's `None` if the cadence is unknown
+    /// or dormant.
+    pub fn next_post_window(&self, now: DateTime<FixedOffset>)
+                            -> Option<(DateTime<FixedOffset>,
+                                       DateTime<FixedOffset>)>
+    {
+        match self.cadence(now) {
+            None | Some(Cadence::Dormant) => { return None; }
+            Some(_) => { }
+        }
+        let last = *self.last_posted_at().unwrap();
+        let interval = self.typical_interval().unwrap();
+        Some((last + interval / 2, last + interval * 3 / 2))
+    }
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len: usize = a.chars().zip(b.chars())
+                      .take_while(|&(x, y)| x == y)
+                      .map(|(x, _)| x.len_utf8())
+                      .sum();
+    &a[..len]
+}
+
+fn common_suffix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len: usize = a.chars().rev().zip(b.chars().rev())
+                      .take_while(|&(x, y)| x == y)
+                      .map(|(x, _)| x.len_utf8())
+                      .sum();
+    &a[a.len() - len..]
+}
+
+/// The common prefix of titles cut at the last separator followed by
+/// whitespaces, e.g. `Site: ` of `Site: Ti`.  The text before the separator
+/// mustn't be blank.
+fn title_prefix(common: &str) -> &str {
+    let mut cut = 0;
+    for (i, c) in common.char_indices() {
+        if !TITLE_SEPARATORS.contains(&c) || common[..i].trim().is_empty() {
+            continue;
+        }
+        let after = &common[i + c.len_utf8()..];
+        let trimmed = after.trim_start();
+        if trimmed.len() < after.len() {
+            cut = common.len() - trimmed.len();
+        }
+    }
+    &common[..cut]
+}
+
+/// The common suffix of titles cut at the first separator preceded by
+/// whitespaces, e.g. ` - Site` of `le - Site`.  The text after the
+/// separator mustn't be blank.
+fn title_suffix(common: &str) -> &str {
+    for (i, c) in common.char_indices() {
+        let after = &common[i + c.len_utf8()..];
+        if !TITLE_SEPARATORS.contains(&c) || after.trim().is_empty() {
+            continue;
+        }
+        let before = &common[..i];
+        let trimmed = before.trim_end();
+        if trimmed.len() < before.len() {
+            return &common[trimmed.len()..];
+        }
+    }
+    ""
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Cadence, FeedStats};
+
+    use std::time::Duration as StdDuration;
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use feed::{Entry, Feed, Text};
+
+    fn fx_feed(intervals: &[Duration]) -> Feed {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(0, 0, 0);
+        let mut feed = Feed::new("urn:feed", Text::plain("Feed"), t);
+        let mut posted_at = t;
+        feed.entries.push(Entry::new("urn:0".to_owned(),
+                                     Text::plain("Entry"), posted_at));
+        for (i, interval) in intervals.iter().enumerate() {
+            posted_at = posted_at - *interval;
+            let mut entry = Entry::new(format!("urn:{}", i + 1),
+                                       Text::plain("Entry"), t);
+            entry.published_at = Some(posted_at);
+            feed.entries.push(entry);
+        }
+        feed
+    }
+
+    #[test]
+    fn test_cadence() {
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(12, 0, 0);
+        let hourly = FeedStats::new(&fx_feed(&[Duration::hours(3); 5]));
+        assert_eq!(hourly.cadence(now), Some(Cadence::MultiplePerDay));
+        assert_eq!(hourly.typical_interval(), Some(Duration::hours(3)));
+        let weekly = FeedStats::new(&fx_feed(&[Duration::days(7),
+                                               Duration::days(6),
+                                               Duration::days(30)]));
+        assert_eq!(weekly.cadence(now), Some(Cadence::Weekly));
+        assert_eq!(weekly.cadence(now + Duration::days(29)),
+                   Some(Cadence::Weekly));
+        assert_eq!(weekly.cadence(now + Duration::days(31)),
+                   Some(Cadence::Dormant));
+        assert_eq!(Cadence::Dormant.crawl_interval(),
+                   StdDuration::from_secs(7 * 24 * 3600));
+
+        let single = FeedStats::new(&fx_feed(&[]));
+        assert_eq!(single.cadence(now), None);
+        assert_eq!(single.cadence(now + Duration::days(31)),
+                   Some(Cadence::Dormant));
+    }
+
+    #[test]
+    fn test_next_post_window() {
+        let now = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(12, 0, 0);
+        let stats = FeedStats::new(&fx_feed(&[Duration::days(1); 3]));
+        let last = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(0, 0, 0);
+        assert_eq!(stats.next_post_window(now),
+                   Some((last + Duration::hours(12),
+                         last + Duration::hours(36))));
+        assert_eq!(stats.next_post_window(now + Duration::days(60)), None);
+        assert_eq!(FeedStats::new(&fx_feed(&[])).next_post_window(now), None);
+    }
+}
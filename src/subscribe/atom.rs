@@ -0,0 +1,355 @@
+//! Storing subscription lists in Atom instead of OPML, for consistency with
+//! the rest of the archive.
+//!
+//! The list is an Atom `feed` whose subscriptions are `entry` elements, and
+//! categories are `e:category` elements in the Earth Reader namespace which
+//! contain their own subscriptions and nested categories:
+//!
+//! ```xml
+//! <feed xmlns="http://www.w3.org/2005/Atom"
+//!       xmlns:e="http://earthreader.org/subscription-list/">
+//!     <title>Earth Reader's Subscriptions</title>
+//!     <e:category>
+//!         <title>Programming</title>
+//!         <entry e:id="rust">
+//!             <title>Rust</title>
+//!             <link rel="self" href="http://blog.rust-lang.org/feed.xml"/>
+//!             <link rel="alternate" href="http://blog.rust-lang.org/"/>
+//!             <published>2013-11-06T14:36:00Z</published>
+//!             <e:alias>http://rust-lang.org/feed.xml</e:alias>
+//!         </entry>
+//!     </e:category>
+//! </feed>
+//! ```
+//!
+//! Both formats hold the same `SubscriptionList`, so they're converted to
+//! each other without loss; see `opml_to_atom()` and `atom_to_opml()`.
+use std::io;
+
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use codecs;
+use feed::ATOM_XMLNS;
+use parser::base::{DecodeError, DecodeResult, XmlElement, XmlName};
+use parser::base::NestedEvent::Nested;
+use schema::{self, Codec, DocumentElement, FromSchemaReader, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+use super::{Category, SUBSCRIPTION_XMLNS, Subscription, SubscriptionList,
+            SubscriptionSet};
+
+/// The format subscription lists are stored in.  See
+/// `Stage::subscription_format`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub enum SubscriptionFormat {
+    /// OPML, which other feed readers can import as well.
+    #[default]
+    Opml,
+
+    /// Atom with Earth Reader extensions; see the module documentation.
+    Atom,
+}
+
+impl SubscriptionFormat {
+    /// Write the list in the format.
+    pub fn write<W: io::Write>(&self, list: &SubscriptionList, w: W)
+                               -> SchemaResult<()> {
+        match *self {
+            SubscriptionFormat::Opml => schema::write(list, w),
+            SubscriptionFormat::Atom => {
+                schema::write(&AtomSubscriptionList(list.clone()), w)
+            }
+        }
+    }
+}
+
+/// The subscription list in Atom, i.e. the `feed` element.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct AtomSubscriptionList(pub SubscriptionList);
+
+impl DocumentElement for AtomSubscriptionList {
+    fn tag() -> &'static str { "feed" }
+    fn xmlns() -> Option<&'static str> { Some(ATOM_XMLNS) }
+}
+
+impl FromSchemaReader for AtomSubscriptionList {
+    fn match_child<B: io::BufRead>(&mut self, name: &XmlName,
+                                   child: XmlElement<B>) -> DecodeResult<()> {
+        if is_atom(name, "title") {
+            self.0.title = Some(try!(child.read_whole_text()));
+            return Ok(());
+        }
+        read_member(&mut self.0, name, child)
+    }
+}
+
+impl ToSchemaWriter for AtomSubscriptionList {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.ns("e", SUBSCRIPTION_XMLNS)));
+        if let Some(ref title) = self.0.title {
+            try!(write_text(writer, "title", title));
+        }
+        try!(write_members(&self.0, writer));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+/// Read the subscription list in either format.
+pub fn read_subscription_list(xml: &[u8]) -> DecodeResult<SubscriptionList> {
+    match schema::read(xml) {
+        Err(DecodeError::NoResult) => {
+            schema::read(xml).map(|list: AtomSubscriptionList| list.0)
+        }
+        result => result,
+    }
+}
+
+/// Convert the subscription list in OPML to Atom.
+pub fn opml_to_atom<B, W>(opml: B, atom: W) -> DecodeResult<()>
+    where B: io::BufRead, W: io::Write
+{
+    let list: SubscriptionList = try!(schema::read(opml));
+    try!(SubscriptionFormat::Atom.write(&list, atom));
+    Ok(())
+}
+
+/// Convert the subscription list in Atom to OPML.
+pub fn atom_to_opml<B, W>(atom: B, opml: W) -> DecodeResult<()>
+    where B: io::BufRead, W: io::Write
+{
+    let list: AtomSubscriptionList = try!(schema::read(atom));
+    try!(SubscriptionFormat::Opml.write(&list.0, opml));
+    Ok(())
+}
+
+fn is_atom(name: &XmlName, local_name: &str) -> bool {
+    name.local_name == local_name && name.namespace_ref() == Some(ATOM_XMLNS)
+}
+
+fn read_member<S, B>(set: &mut S, name: &XmlName, element: XmlElement<B>)
+                     -> DecodeResult<()>
+    where S: SubscriptionSet, B: io::BufRead
+{
+    if is_atom(name, "entry") {
+        let subscription = try!(read_subscription(element));
+        set.subscriptions_mut().push(subscription);
+    } else if name.local_name == "category" &&
+              name.namespace_ref() == Some(SUBSCRIPTION_XMLNS) {
+        let category = try!(read_category(element));
+        set.categories_mut().push(category);
+    }
+    Ok(())
+}
+
+fn read_category<B: io::BufRead>(mut element: XmlElement<B>)
+                                 -> DecodeResult<Category> {
+    let mut category = Category::default();
+    while let Some(event) = element.children.next() {
+        if let Nested { name, element: child } = try!(event) {
+            if is_atom(&name, "title") {
+                category.label = try!(child.read_whole_text());
+            } else {
+                try!(read_member(&mut category, &name, child));
+            }
+        }
+    }
+    Ok(category)
+}
+
+fn read_subscription<B: io::BufRead>(mut element: XmlElement<B>)
+                                     -> DecodeResult<Subscription> {
+    let mut subscription = Subscription {
+        feed_id: element.get_attr("id").unwrap_or("").to_owned(),
+        ..Default::default()
+    };
+    while let Some(event) = element.children.next() {
+        let (name, child) = match try!(event) {
+            Nested { name, element } => (name, element),
+            _ => { continue; }
+        };
+        match &name.local_name[..] {
+            "title" => { subscription.label = try!(child.read_whole_text()); }
+            "link" => {
+                let href = try!(child.get_attr("href")).to_owned();
+                match child.get_attr("rel").unwrap_or("alternate") {
+                    "self" => { subscription.feed_uri = href; }
+                    "alternate" => {
+                        subscription.alternate_uri = Some(href);
+                    }
+                    _ => { }
+                }
+            }
+            "published" => {
+                let text = try!(child.read_whole_text());
+                subscription.created_at =
+                    Some(try!(codecs::RFC3339.decode(text.trim())));
+            }
+            "alias" => {
+                subscription.aliases.push(try!(child.read_whole_text()));
+            }
+            "muted" => {
+                subscription.mute_updated_at =
+                    match child.get_attr("updated") {
+                        Ok(v) => Some(try!(codecs::RFC3339.decode(v))),
+                        Err(_) => None,
+                    };
+                let text = try!(child.read_whole_text());
+                let text = text.trim();
+                subscription.muted_until = if text.is_empty() {
+                    None
+                } else {
+                    Some(try!(codecs::RFC3339.decode(text)))
+                };
+            }
+            _ => { }
+        }
+    }
+    if subscription.feed_uri.is_empty() {
+        return Err(DecodeError::AttributeNotFound("href".to_owned()));
+    }
+    if subscription.feed_id.is_empty() {
+        subscription.feed_id = subscription.feed_uri.clone();
+    }
+    Ok(subscription)
+}
+
+fn write_members<S, W>(set: &S, writer: &mut XmlWriter<W>)
+                       -> SchemaResult<()>
+    where S: SubscriptionSet, W: io::Write
+{
+    for category in set.categories() {
+        try!(writer.write(XmlEvent::start_element("e:category")));
+        try!(write_text(writer, "title", &category.label));
+        try!(write_members(category, writer));
+        try!(writer.write(XmlEvent::end_element()));
+    }
+    for subscription in set.subscriptions() {
+        try!(write_subscription(subscription, writer));
+    }
+    Ok(())
+}
+
+fn write_subscription<W: io::Write>(subscription: &Subscription,
+                                    writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()> {
+    try!(writer.write(XmlEvent::start_element("entry")
+                                   .attr("e:id", &subscription.feed_id)));
+    try!(write_text(writer, "title", &subscription.label));
+    try!(write_link(writer, "self", &subscription.feed_uri));
+    if let Some(ref alternate_uri) = subscription.alternate_uri {
+        try!(write_link(writer, "alternate", alternate_uri));
+    }
+    if let Some(ref created_at) = subscription.created_at {
+        let mut buf = vec![];
+        try!(codecs::RFC3339.encode(created_at, &mut buf));
+        try!(write_text(writer, "published",
+                        &String::from_utf8(buf).unwrap()));
+    }
+    for alias in subscription.aliases.iter() {
+        try!(write_text(writer, "e:alias", alias));
+    }
+    if let Some(ref mute_updated_at) = subscription.mute_updated_at {
+        // it's written even if unmuted, so that unmuting wins on merge
+        let mut buf = vec![];
+        try!(codecs::RFC3339.encode(mute_updated_at, &mut buf));
+        let updated = String::from_utf8(buf).unwrap();
+        try!(writer.write(XmlEvent::start_element("e:muted")
+                                       .attr("updated", &updated)));
+        if let Some(ref muted_until) = subscription.muted_until {
+            let mut buf = vec![];
+            try!(codecs::RFC3339.encode(muted_until, &mut buf));
+            try!(writer.write(XmlEvent::characters(
+                &String::from_utf8(buf).unwrap())));
+        }
+        try!(writer.write(XmlEvent::end_element()));
+    }
+    try!(writer.write(XmlEvent::end_element()));
+    Ok(())
+}
+
+fn write_text<W: io::Write>(writer: &mut XmlWriter<W>, name: &str,
+                            text: &str) -> SchemaResult<()> {
+    try!(writer.write(XmlEvent::start_element(name)));
+    try!(writer.write(XmlEvent::characters(text)));
+    try!(writer.write(XmlEvent::end_element()));
+    Ok(())
+}
+
+fn write_link<W: io::Write>(writer: &mut XmlWriter<W>, rel: &str,
+                            href: &str) -> SchemaResult<()> {
+    try!(writer.write(XmlEvent::start_element("link").attr("rel", rel)
+                                                     .attr("href", href)));
+    try!(writer.write(XmlEvent::end_element()));
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{AtomSubscriptionList, atom_to_opml, opml_to_atom,
+                read_subscription_list};
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use schema;
+    use subscribe::{Category, Subscription, SubscriptionList,
+                    SubscriptionSet};
+
+    fn fx_subscription_list() -> SubscriptionList {
+        let mut list = SubscriptionList {
+            title: Some("Earth Reader's Subscriptions".to_owned()),
+            ..Default::default()
+        };
+        let mut subscription = Subscription::new(
+            "earthreader", "http://blog.earthreader.org/feed",
+            "Earth Reader");
+        subscription.alternate_uri =
+            Some("http://blog.earthreader.org/".to_owned());
+        subscription.created_at =
+            Some(FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0));
+        subscription.aliases = vec!["http://earthreader.org/feed".to_owned()];
+        subscription.mute(
+            Some(FixedOffset::east(0).ymd(2013, 11, 7).and_hms(0, 0, 0)),
+            FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0));
+        list.subscribe(subscription);
+        let mut programming = Category::new("Programming");
+        programming.subscribe(Subscription::new(
+            "rust", "http://blog.rust-lang.org/feed.xml", "Rust"));
+        programming.categories.push(Category::new("Languages"));
+        list.categories.push(programming);
+        list
+    }
+
+    #[test]
+    fn test_atom_subscription_list() {
+        let list = fx_subscription_list();
+        let mut atom = vec![];
+        unwrap!(schema::write(&AtomSubscriptionList(list.clone()),
+                              &mut atom));
+        let xml = String::from_utf8(atom.clone()).unwrap();
+        assert!(xml.contains("<e:category><title>Programming</title>"));
+        assert!(xml.contains("<link rel=\"self\" \
+                              href=\"http://blog.rust-lang.org/feed.xml\""));
+        let read: AtomSubscriptionList = unwrap!(schema::read(&atom[..]));
+        assert_eq!(read.0, list);
+        assert_eq!(unwrap!(read_subscription_list(&atom)), list);
+    }
+
+    #[test]
+    fn test_opml_atom_conversion() {
+        let list = fx_subscription_list();
+        let mut opml = vec![];
+        unwrap!(schema::write(&list, &mut opml));
+        assert_eq!(unwrap!(read_subscription_list(&opml)), list);
+        let mut atom = vec![];
+        unwrap!(opml_to_atom(&opml[..], &mut atom));
+        let mut converted = vec![];
+        unwrap!(atom_to_opml(&atom[..], &mut converted));
+        let read: SubscriptionList = unwrap!(schema::read(&converted[..]));
+        assert_eq!(read, list);
+    }
+}
@@ -0,0 +1,752 @@
+//! Maintaining the subscription list.
+//!
+//! The subscription list is stored in [OPML][] format like [libearth][]
+//! does, with some extension attributes in the Earth Reader namespace.
+//! It can be stored in Atom as well; see the `atom` module.
+//!
+//! Attributes of outlines which aren't understood, e.g. ones other readers
+//! stash, are kept in `extra_attributes` and written back as they were,
+//! so that importing and exporting a list doesn't lose them.  They're kept
+//! only in OPML, though.
+//!
+//! [OPML]: http://dev.opml.org/spec2.html
+//! [libearth]: https://github.com/earthreader/libearth
+use std::borrow::Cow;
+use std::default::Default;
+use std::io;
+
+use chrono::{DateTime, FixedOffset};
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use codecs;
+use parser::base::{DecodeResult, XmlElement, XmlName};
+use parser::base::NestedEvent::Nested;
+use schema::{Codec, DocumentElement, Entity, FromSchemaReader, Mergeable,
+             SchemaResult, ToSchemaWriter, XmlWriter};
+
+pub use self::atom::{AtomSubscriptionList, SubscriptionFormat};
+pub use self::settings::{Retention, Setting, SubscriptionSettings};
+pub use self::vocabulary::{APP_XMLNS, CategoryDocument};
+
+pub mod atom;
+pub mod settings;
+pub mod vocabulary;
+
+/// The XML namespace name used for Earth Reader subscription list metadata.
+pub const SUBSCRIPTION_XMLNS: &'static str =
+    "http://earthreader.org/subscription-list/";
+
+/// An attribute of an outline which isn't understood, kept to be written
+/// back as it was.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExtraAttribute {
+    /// The name as it was written, e.g. `"isOpen"` or `"x:sync-id"`.
+    pub name: String,
+
+    /// The namespace name of the attribute, if it's prefixed.
+    pub namespace: Option<String>,
+
+    pub value: String,
+}
+
+/// Common interface of `SubscriptionList` and `Category`, which contain
+/// subscriptions and nested categories.
+pub trait SubscriptionSet {
+    fn subscriptions(&self) -> &[Subscription];
+    fn subscriptions_mut(&mut self) -> &mut Vec<Subscription>;
+    fn categories(&self) -> &[Category];
+    fn categories_mut(&mut self) -> &mut Vec<Category>;
+
+    /// All subscriptions including ones in the nested categories.
+    fn recursive_subscriptions(&self) -> Vec<&Subscription> {
+        let mut result: Vec<&Subscription> =
+            self.subscriptions().iter().collect();
+        for category in self.categories() {
+            result.extend(category.recursive_subscriptions());
+        }
+        result
+    }
+
+    /// Find the subscription of `feed_id` including nested categories.
+    fn find(&self, feed_id: &str) -> Option<&Subscription> {
+        self.recursive_subscriptions().into_iter()
+            .find(|s| s.feed_id == feed_id)
+    }
+
+    /// Find the subscription the feed `uri` belongs to, including nested
+    /// categories.  Old URIs in `Subscription::aliases` match as well, but
+    /// the current `feed_uri` of any subscription takes precedence.
+    fn find_by_uri(&self, uri: &str) -> Option<&Subscription> {
+        let subscriptions = self.recursive_subscriptions();
+        match subscriptions.iter().position(|s| s.feed_uri == uri) {
+            Some(i) => Some(subscriptions[i]),
+            None => subscriptions.into_iter().find(|s| s.is_known_as(uri)),
+        }
+    }
+
+    /// Find the subscription of `feed_id` including nested categories.
+    fn find_mut(&mut self, feed_id: &str) -> Option<&mut Subscription> {
+        if let Some(i) = self.subscriptions().iter()
+                             .position(|s| s.feed_id == feed_id) {
+            return Some(&mut self.subscriptions_mut()[i]);
+        }
+        for category in self.categories_mut().iter_mut() {
+            if let Some(s) = category.find_mut(feed_id) {
+                return Some(s);
+            }
+        }
+        None
+    }
+
+    /// Add the subscription to the set.  If there's an existing subscription
+    /// of the same feed, it's replaced.
+    fn subscribe(&mut self, subscription: Subscription) {
+        let subscriptions = self.subscriptions_mut();
+        subscriptions.retain(|s| s.feed_id != subscription.feed_id);
+        subscriptions.push(subscription);
+    }
+
+    /// Remove the subscription of `feed_id` including nested categories,
+    /// and return it with the labels of categories it belonged to.
+    fn unsubscribe(&mut self, feed_id: &str)
+                   -> Option<(Vec<String>, Subscription)>
+    {
+        if let Some(i) = self.subscriptions().iter()
+                             .position(|s| s.feed_id == feed_id) {
+            return Some((vec![], self.subscriptions_mut().remove(i)));
+        }
+        for category in self.categories_mut().iter_mut() {
+            if let Some((mut path, s)) = category.unsubscribe(feed_id) {
+                path.insert(0, category.label.clone());
+                return Some((path, s));
+            }
+        }
+        None
+    }
+
+    /// Get the nested category by the path of labels.  Missing categories
+    /// are created.
+    fn category_mut<T: AsRef<str>>(&mut self, path: &[T]) -> &mut Category
+        where Self: Sized
+    {
+        let (head, tail) = match path.split_first() {
+            Some(v) => v,
+            None => panic!("path must not be empty"),
+        };
+        let i = match self.categories().iter()
+                          .position(|c| c.label == head.as_ref()) {
+            Some(i) => i,
+            None => {
+                self.categories_mut().push(Category::new(head.as_ref()));
+                self.categories().len() - 1
+            }
+        };
+        let category = &mut self.categories_mut()[i];
+        if tail.is_empty() { category } else { category.category_mut(tail) }
+    }
+}
+
+/// The subscription of a feed.  It corresponds to `outline` element which
+/// has `xmlUrl` attribute.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Subscription {
+    /// The identifier of the feed, which is used as the key of the feed
+    /// document in the stage.
+    pub feed_id: String,
+
+    /// The URI of the feed.  It corresponds to `xmlUrl` attribute.
+    pub feed_uri: String,
+
+    /// The URI of the web page the feed is for.  It corresponds to `htmlUrl`
+    /// attribute.
+    pub alternate_uri: Option<String>,
+
+    /// The human-readable name of the subscription.  It corresponds to
+    /// `text` attribute.
+    pub label: String,
+
+    /// When it was subscribed.
+    #[cfg_attr(feature = "serde",
+               serde(default, with = "::codecs::serde::rfc3339_option"))]
+    pub created_at: Option<DateTime<FixedOffset>>,
+
+    /// URIs the feed was subscribed at before, e.g. before it permanently
+    /// moved, oldest first.  They're written space-separated in
+    /// `e:aliases` attribute.
+    pub aliases: Vec<String>,
+
+    /// Until when the subscription is muted, i.e. it isn't crawled and its
+    /// entries are hidden from the timeline; see `is_muted()`.  It
+    /// corresponds to `e:muted` attribute.
+    #[cfg_attr(feature = "serde",
+               serde(default, with = "::codecs::serde::rfc3339_option"))]
+    pub muted_until: Option<DateTime<FixedOffset>>,
+
+    /// When `muted_until` was changed last, including when it's unmuted, so
+    /// that the later change wins when lists of different devices are
+    /// merged, like `Mark::updated_at`.  It corresponds to `e:mute-updated`
+    /// attribute.
+    #[cfg_attr(feature = "serde",
+               serde(default, with = "::codecs::serde::rfc3339_option"))]
+    pub mute_updated_at: Option<DateTime<FixedOffset>>,
+
+    /// Attributes of the outline which aren't understood, in the order
+    /// they were written.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extra_attributes: Vec<ExtraAttribute>,
+}
+
+/// Attributes of subscription outlines understood by their local names.
+static SUBSCRIPTION_ATTRIBUTES: [&'static str; 10] = [
+    "type", "text", "title", "xmlUrl", "htmlUrl",
+    "id", "created", "aliases", "muted", "mute-updated",
+];
+
+/// Attributes of category outlines understood by their local names.
+static CATEGORY_ATTRIBUTES: [&'static str; 2] = ["text", "title"];
+
+impl Subscription {
+    pub fn new<T, U, V>(feed_id: T, feed_uri: U, label: V) -> Subscription
+        where T: Into<String>, U: Into<String>, V: Into<String>
+    {
+        Subscription {
+            feed_id: feed_id.into(),
+            feed_uri: feed_uri.into(),
+            label: label.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether `uri` is the feed URI of the subscription or one of its
+    /// aliases.
+    pub fn is_known_as(&self, uri: &str) -> bool {
+        self.feed_uri == uri || self.aliases.iter().any(|a| a == uri)
+    }
+
+    /// Change the feed URI to `uri`, keeping the previous one as an alias.
+    /// It returns `false` and does nothing if it's already `uri`.
+    pub fn move_to<T: Into<String>>(&mut self, uri: T) -> bool {
+        let uri = uri.into();
+        if uri == self.feed_uri {
+            return false;
+        }
+        self.aliases.retain(|a| *a != uri);
+        let previous = ::std::mem::replace(&mut self.feed_uri, uri);
+        self.aliases.retain(|a| *a != previous);
+        self.aliases.push(previous);
+        true
+    }
+
+    /// Mute the subscription until `until`, or unmute it if it's `None`.
+    /// `now` is recorded as when it's changed.
+    pub fn mute(&mut self, until: Option<DateTime<FixedOffset>>,
+                now: DateTime<FixedOffset>) {
+        self.muted_until = until;
+        self.mute_updated_at = Some(now);
+    }
+
+    /// Whether the subscription is muted at `now`.
+    pub fn is_muted(&self, now: DateTime<FixedOffset>) -> bool {
+        self.muted_until.is_some_and(|until| now < until)
+    }
+}
+
+impl Entity for Subscription {
+    type Id = str;
+    fn entity_id(&self) -> Cow<str> { Cow::Borrowed(&self.feed_id) }
+}
+
+impl Mergeable for Subscription {
+    /// Other fields of `self` are kept, but the mute state changed later
+    /// wins.
+    fn merge_with(&mut self, other: Subscription) {
+        if other.mute_updated_at > self.mute_updated_at {
+            self.muted_until = other.muted_until;
+            self.mute_updated_at = other.mute_updated_at;
+        }
+    }
+}
+
+impl FromSchemaReader for Subscription {
+    fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        self.feed_uri = try!(element.get_attr("xmlUrl")).to_owned();
+        self.feed_id = element.get_attr("id").ok()
+                              .map_or_else(|| self.feed_uri.clone(),
+                                           |v| v.to_owned());
+        self.alternate_uri = element.get_attr("htmlUrl").ok()
+                                    .map(|v| v.to_owned());
+        self.label = element.get_attr("text")
+                            .or_else(|_| element.get_attr("title"))
+                            .unwrap_or("").to_owned();
+        self.created_at = match element.get_attr("created") {
+            Ok(v) => Some(try!(codecs::RFC3339.decode(v))),
+            Err(_) => None,
+        };
+        self.aliases = element.get_attr("aliases").unwrap_or("")
+                              .split_whitespace().map(|a| a.to_owned())
+                              .collect();
+        self.muted_until = match element.get_attr("muted") {
+            Ok(v) => Some(try!(codecs::RFC3339.decode(v))),
+            Err(_) => None,
+        };
+        self.mute_updated_at = match element.get_attr("mute-updated") {
+            Ok(v) => Some(try!(codecs::RFC3339.decode(v))),
+            Err(_) => None,
+        };
+        self.extra_attributes =
+            read_extra_attributes(&element, &SUBSCRIPTION_ATTRIBUTES);
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for Subscription {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        fn encode(date: &Option<DateTime<FixedOffset>>)
+                  -> SchemaResult<Option<String>> {
+            Ok(match *date {
+                Some(ref v) => {
+                    let mut buf = vec![];
+                    try!(codecs::RFC3339.encode(v, &mut buf));
+                    Some(String::from_utf8(buf).unwrap())
+                }
+                None => None,
+            })
+        }
+        let created_at = try!(encode(&self.created_at));
+        let muted_until = try!(encode(&self.muted_until));
+        let mute_updated_at = try!(encode(&self.mute_updated_at));
+        let aliases = self.aliases.join(" ");
+        let mut element = element.attr("type", "rss")
+                                 .attr("text", &self.label)
+                                 .attr("title", &self.label)
+                                 .attr("xmlUrl", &self.feed_uri);
+        if let Some(ref alternate_uri) = self.alternate_uri {
+            element = element.attr("htmlUrl", alternate_uri);
+        }
+        element = element.attr("e:id", &self.feed_id);
+        if let Some(ref created_at) = created_at {
+            element = element.attr("e:created", created_at);
+        }
+        if !aliases.is_empty() {
+            element = element.attr("e:aliases", &aliases);
+        }
+        if let Some(ref muted_until) = muted_until {
+            element = element.attr("e:muted", muted_until);
+        }
+        if let Some(ref mute_updated_at) = mute_updated_at {
+            element = element.attr("e:mute-updated", mute_updated_at);
+        }
+        element = write_extra_attributes(element, &self.extra_attributes);
+        try!(writer.write(element));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+/// The category which contains subscriptions and nested categories.  It
+/// corresponds to `outline` element which has no `xmlUrl` attribute.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Category {
+    /// The human-readable name of the category.  It corresponds to `text`
+    /// attribute.
+    pub label: String,
+    pub subscriptions: Vec<Subscription>,
+    pub categories: Vec<Category>,
+
+    /// Attributes of the outline which aren't understood, in the order
+    /// they were written.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extra_attributes: Vec<ExtraAttribute>,
+}
+
+impl Category {
+    pub fn new<T: Into<String>>(label: T) -> Category {
+        Category { label: label.into(), ..Default::default() }
+    }
+}
+
+impl SubscriptionSet for Category {
+    fn subscriptions(&self) -> &[Subscription] { &self.subscriptions }
+    fn subscriptions_mut(&mut self) -> &mut Vec<Subscription> {
+        &mut self.subscriptions
+    }
+    fn categories(&self) -> &[Category] { &self.categories }
+    fn categories_mut(&mut self) -> &mut Vec<Category> {
+        &mut self.categories
+    }
+}
+
+impl Entity for Category {
+    type Id = str;
+    fn entity_id(&self) -> Cow<str> { Cow::Borrowed(&self.label) }
+}
+
+impl_mergeable!(Category, subscriptions, categories);
+
+impl FromSchemaReader for Category {
+    fn read_from<B: io::BufRead>(&mut self, mut element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        self.label = element.get_attr("text")
+                            .or_else(|_| element.get_attr("title"))
+                            .unwrap_or("").to_owned();
+        self.extra_attributes =
+            read_extra_attributes(&element, &CATEGORY_ATTRIBUTES);
+        while let Some(event) = element.children.next() {
+            if let Nested { name, element: child } = try!(event) {
+                try!(self.match_child(&name, child));
+            }
+        }
+        Ok(())
+    }
+
+    fn match_child<B: io::BufRead>(&mut self, name: &XmlName,
+                                   child: XmlElement<B>) -> DecodeResult<()> {
+        read_outline(self, name, child)
+    }
+}
+
+impl ToSchemaWriter for Category {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let element = element.attr("text", &self.label)
+                             .attr("title", &self.label);
+        try!(writer.write(write_extra_attributes(element,
+                                                 &self.extra_attributes)));
+        try!(write_outlines(self, writer));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+/// The whole subscription list document.  It corresponds to `opml` element.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubscriptionList {
+    /// The title of the list.  It corresponds to `title` element in `head`.
+    pub title: Option<String>,
+    pub subscriptions: Vec<Subscription>,
+    pub categories: Vec<Category>,
+}
+
+impl SubscriptionSet for SubscriptionList {
+    fn subscriptions(&self) -> &[Subscription] { &self.subscriptions }
+    fn subscriptions_mut(&mut self) -> &mut Vec<Subscription> {
+        &mut self.subscriptions
+    }
+    fn categories(&self) -> &[Category] { &self.categories }
+    fn categories_mut(&mut self) -> &mut Vec<Category> {
+        &mut self.categories
+    }
+}
+
+impl_mergeable!(SubscriptionList, title, subscriptions, categories);
+
+impl DocumentElement for SubscriptionList {
+    fn tag() -> &'static str { "opml" }
+    fn xmlns() -> Option<&'static str> { None }
+}
+
+impl FromSchemaReader for SubscriptionList {
+    fn match_child<B: io::BufRead>(&mut self, name: &XmlName,
+                                   mut child: XmlElement<B>)
+                                   -> DecodeResult<()>
+    {
+        match &name.local_name[..] {
+            "head" => {
+                while let Some(event) = child.children.next() {
+                    if let Nested { name, element } = try!(event) {
+                        if name.local_name == "title" {
+                            self.title = Some(try!(element.read_whole_text()));
+                        }
+                    }
+                }
+            }
+            "body" => {
+                while let Some(event) = child.children.next() {
+                    if let Nested { name, element } = try!(event) {
+                        try!(read_outline(self, &name, element));
+                    }
+                }
+            }
+            _ => { }
+        }
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for SubscriptionList {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        try!(writer.write(element.attr("version", "2.0")
+                                 .ns("e", SUBSCRIPTION_XMLNS)));
+        try!(writer.write(XmlEvent::start_element("head")));
+        if let Some(ref title) = self.title {
+            try!(writer.write(XmlEvent::start_element("title")));
+            try!(writer.write(XmlEvent::characters(title)));
+            try!(writer.write(XmlEvent::end_element()));
+        }
+        try!(writer.write(XmlEvent::end_element()));
+        try!(writer.write(XmlEvent::start_element("body")));
+        try!(write_outlines(self, writer));
+        try!(writer.write(XmlEvent::end_element()));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+fn read_outline<S, B>(set: &mut S, name: &XmlName, element: XmlElement<B>)
+                      -> DecodeResult<()>
+    where S: SubscriptionSet, B: io::BufRead
+{
+    if name.local_name != "outline" {
+        return Ok(());
+    }
+    if element.get_attr("xmlUrl").is_ok() {
+        let subscription = try!(FromSchemaReader::build_from(element));
+        set.subscriptions_mut().push(subscription);
+    } else {
+        let category = try!(FromSchemaReader::build_from(element));
+        set.categories_mut().push(category);
+    }
+    Ok(())
+}
+
+fn read_extra_attributes<B>(element: &XmlElement<B>, known: &[&str])
+                            -> Vec<ExtraAttribute>
+    where B: io::BufRead
+{
+    element.attributes.iter().filter(|attr| {
+        !known.contains(&&attr.name.local_name[..])
+    }).map(|attr| {
+        let name = match attr.name.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, attr.name.local_name),
+            None => attr.name.local_name.clone(),
+        };
+        ExtraAttribute {
+            name: name,
+            namespace: attr.name.prefix.as_ref()
+                           .and(attr.name.namespace.clone()),
+            value: attr.value.clone(),
+        }
+    }).collect()
+}
+
+fn write_extra_attributes<'a>(mut element: StartElementBuilder<'a>,
+                              attributes: &'a [ExtraAttribute])
+                              -> StartElementBuilder<'a> {
+    for attr in attributes {
+        if let Some(ref namespace) = attr.namespace {
+            if let Some(i) = attr.name.find(':') {
+                element = element.ns(&attr.name[..i], &namespace[..]);
+            }
+        }
+        element = element.attr(&attr.name[..], &attr.value);
+    }
+    element
+}
+
+fn write_outlines<S, W>(set: &S, writer: &mut XmlWriter<W>)
+                        -> SchemaResult<()>
+    where S: SubscriptionSet, W: io::Write
+{
+    for category in set.categories() {
+        try!(category.write_to(writer, XmlEvent::start_element("outline")));
+    }
+    for subscription in set.subscriptions() {
+        try!(subscription.write_to(writer,
+                                   XmlEvent::start_element("outline")));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Category, Subscription, SubscriptionList, SubscriptionSet};
+
+    use chrono::{Duration, FixedOffset, TimeZone};
+
+    use schema;
+    use schema::Mergeable;
+
+    fn fx_subscription_list() -> SubscriptionList {
+        schema::read(&br##"
+        <opml version="2.0" xmlns:e="http://earthreader.org/subscription-list/">
+            <head><title>Earth Reader's Subscriptions</title></head>
+            <body>
+                <outline text="Earth Reader" title="Earth Reader"
+                         type="rss" xmlUrl="http://blog.earthreader.org/feed"
+                         htmlUrl="http://blog.earthreader.org/"
+                         e:id="earthreader" e:created="2013-11-06T14:36:00Z"
+                         e:aliases="http://earthreader.org/feed"
+                         e:muted="2013-11-07T00:00:00Z"
+                         e:mute-updated="2013-11-06T14:36:00Z"/>
+                <outline text="Programming">
+                    <outline text="Rust" type="rss"
+                             xmlUrl="http://blog.rust-lang.org/feed.xml"
+                             e:id="rust"/>
+                    <outline text="Languages">
+                        <outline text="Python" type="rss"
+                                 xmlUrl="http://planetpython.org/rss20.xml"/>
+                    </outline>
+                </outline>
+            </body>
+        </opml>
+        "##[..]).unwrap()
+    }
+
+    #[test]
+    fn test_subscription_list_read() {
+        let list = fx_subscription_list();
+        assert_eq!(list.title.as_ref().unwrap(),
+                   "Earth Reader's Subscriptions");
+        assert_eq!(list.subscriptions.len(), 1);
+        let s = &list.subscriptions[0];
+        assert_eq!(s.feed_id, "earthreader");
+        assert_eq!(s.label, "Earth Reader");
+        assert_eq!(s.feed_uri, "http://blog.earthreader.org/feed");
+        assert_eq!(s.alternate_uri.as_ref().unwrap(),
+                   "http://blog.earthreader.org/");
+        assert_eq!(s.created_at,
+                   Some(FixedOffset::east(0).ymd(2013, 11, 6)
+                                            .and_hms(14, 36, 0)));
+        assert_eq!(s.aliases, ["http://earthreader.org/feed"]);
+        assert_eq!(s.muted_until,
+                   Some(FixedOffset::east(0).ymd(2013, 11, 7)
+                                            .and_hms(0, 0, 0)));
+        assert!(list.find("rust").unwrap().muted_until.is_none());
+        assert_eq!(list.categories.len(), 1);
+        assert_eq!(list.categories[0].label, "Programming");
+        assert_eq!(list.recursive_subscriptions().iter()
+                       .map(|s| &s.feed_id[..]).collect::<Vec<_>>(),
+                   ["earthreader", "rust",
+                    "http://planetpython.org/rss20.xml"]);
+    }
+
+    #[test]
+    fn test_subscription_list_write() {
+        let list = fx_subscription_list();
+        let mut buf = vec![];
+        schema::write(&list, &mut buf).unwrap();
+        let written: SubscriptionList = schema::read(&buf[..]).unwrap();
+        assert_eq!(written, list);
+    }
+
+    #[test]
+    fn test_subscribe_unsubscribe() {
+        let mut list = fx_subscription_list();
+        assert!(list.find("rust").is_some());
+        let (path, s) = list.unsubscribe("rust").unwrap();
+        assert_eq!(path, ["Programming"]);
+        assert_eq!(s.feed_uri, "http://blog.rust-lang.org/feed.xml");
+        assert!(list.find("rust").is_none());
+        assert!(list.unsubscribe("rust").is_none());
+        list.category_mut(&["Programming", "Languages"]).subscribe(s);
+        assert!(list.find("rust").is_some());
+        assert_eq!(list.categories[0].categories[0].subscriptions.len(), 2);
+        list.category_mut(&["New"])
+            .subscribe(Subscription::new("new", "http://example.com/", "New"));
+        assert_eq!(list.categories[1], Category {
+            label: "New".to_owned(),
+            subscriptions: vec![Subscription::new("new",
+                                                  "http://example.com/",
+                                                  "New")],
+            categories: vec![],
+            extra_attributes: vec![],
+        });
+    }
+
+    #[test]
+    fn test_subscription_move_to() {
+        let mut s = Subscription::new("feed", "http://a.example.com/", "");
+        assert!(!s.move_to("http://a.example.com/"));
+        assert!(s.move_to("http://b.example.com/"));
+        assert!(s.move_to("http://a.example.com/"));
+        assert_eq!(s.feed_uri, "http://a.example.com/");
+        assert_eq!(s.aliases, ["http://b.example.com/"]);
+        assert!(s.move_to("http://c.example.com/"));
+        assert_eq!(s.aliases, ["http://b.example.com/",
+                               "http://a.example.com/"]);
+        assert!(s.is_known_as("http://a.example.com/"));
+        assert!(s.is_known_as("http://c.example.com/"));
+        assert!(!s.is_known_as("http://d.example.com/"));
+    }
+
+    #[test]
+    fn test_find_by_uri() {
+        let mut list = fx_subscription_list();
+        let find = |list: &SubscriptionList, uri| {
+            list.find_by_uri(uri).map(|s| s.feed_id.clone())
+        };
+        assert_eq!(find(&list, "http://blog.rust-lang.org/feed.xml"),
+                   Some("rust".to_owned()));
+        assert_eq!(find(&list, "http://earthreader.org/feed"),
+                   Some("earthreader".to_owned()));
+        assert_eq!(find(&list, "http://example.com/"), None);
+        list.subscribe(Subscription::new("new", "http://earthreader.org/feed",
+                                         "New"));
+        assert_eq!(find(&list, "http://earthreader.org/feed"),
+                   Some("new".to_owned()));
+    }
+
+    #[test]
+    fn test_subscription_mute() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let week = t + Duration::days(7);
+        let mut a = Subscription::new("feed", "http://example.com/", "");
+        assert!(!a.is_muted(t));
+        a.mute(Some(week), t);
+        assert!(a.is_muted(t));
+        assert!(!a.is_muted(week));
+        // unmuted later on another device
+        let mut b = a.clone();
+        b.mute(None, t + Duration::hours(1));
+        let mut merged = a.clone();
+        merged.merge_with(b.clone());
+        assert!(!merged.is_muted(t));
+        let mut merged = b.clone();
+        merged.merge_with(a.clone());
+        assert!(!merged.is_muted(t));
+    }
+
+    #[test]
+    fn test_extra_attributes() {
+        let opml = br##"
+        <opml version="2.0" xmlns:x="http://example.com/x">
+            <body>
+                <outline text="News" isOpen="true">
+                    <outline type="rss" text="A" xmlUrl="http://a.com/feed"
+                             category="/News" x:sync-id="42"/>
+                </outline>
+            </body>
+        </opml>
+        "##;
+        let list: SubscriptionList = unwrap!(schema::read(&opml[..]));
+        let category = &list.categories[0];
+        assert_eq!(category.extra_attributes.len(), 1);
+        assert_eq!(category.extra_attributes[0].name, "isOpen");
+        let extras = &category.subscriptions[0].extra_attributes;
+        assert_eq!(extras.iter().map(|a| (&a.name[..], &a.value[..]))
+                         .collect::<Vec<_>>(),
+                   [("category", "/News"), ("x:sync-id", "42")]);
+        assert_eq!(extras[1].namespace,
+                   Some("http://example.com/x".to_owned()));
+        let mut buf = vec![];
+        unwrap!(schema::write(&list, &mut buf));
+        let read: SubscriptionList = unwrap!(schema::read(&buf[..]));
+        assert_eq!(read, list);
+    }
+}
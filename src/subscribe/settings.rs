@@ -0,0 +1,517 @@
+//! Per-subscription settings which override app-wide defaults, e.g. feeds
+//! that should be crawled more often, or need iframes to be kept.
+//!
+//! Settings are stored in a document per subscription, and every setting
+//! remembers when it was changed, so that changes made on different
+//! devices are merged setting by setting; the later change wins.
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use codecs;
+#[cfg(feature = "crawler")]
+use crawler::Auth;
+use feed::SortPolicy;
+use parser::base::{DecodeResult, XmlElement, XmlName};
+use sanitizer::{SanitizerPolicy, SanitizerPreset};
+use schema::{Codec, DocumentElement, Entity, FromSchemaReader, Mergeable,
+             SchemaResult, ToSchemaWriter, XmlWriter};
+
+/// The XML namespace name used for subscription settings documents.
+pub const SETTINGS_XMLNS: &'static str =
+    "http://earthreader.org/subscription-settings/";
+
+/// How often the feed is crawled, in seconds.
+pub const CRAWL_INTERVAL: &'static str = "crawl.interval";
+
+/// Whether `<iframe>` elements are kept.  See `SanitizerPolicy`.
+pub const ALLOW_IFRAMES: &'static str = "sanitizer.allow-iframes";
+
+/// Whether images are loaded through the image proxy.  See
+/// `SanitizerPolicy`.
+pub const PROXY_IMAGES: &'static str = "sanitizer.proxy-images";
+
+/// Which elements are kept: `strict`, `article`, or `embed`.  See
+/// `SanitizerPreset`.
+pub const SANITIZER_PRESET: &'static str = "sanitizer.preset";
+
+/// How requests are authenticated: `basic`, `digest`, `bearer`, or
+/// `header`.  See `crawler::Auth`.
+pub const AUTH_SCHEME: &'static str = "auth.scheme";
+
+/// The username of `basic` and `digest` authentication.
+pub const AUTH_USERNAME: &'static str = "auth.username";
+
+/// The request header name of `header` authentication.
+pub const AUTH_HEADER: &'static str = "auth.header";
+
+/// The `User-Agent` header the crawler sends for the feed.
+pub const USER_AGENT: &'static str = "crawler.user-agent";
+
+/// The prefix of settings of additional request headers, followed by the
+/// header name, e.g. `crawler.header.Cookie`.
+pub const HEADER_PREFIX: &'static str = "crawler.header.";
+
+/// The window in seconds within which entries of the same permalink and
+/// title are collapsed into one.  See the `stage::dedup` module.
+pub const DEDUP_WINDOW: &'static str = "merge.dedup-window";
+
+/// Which date entries are listed by: `updated`, `published`, or `crawled`.
+/// See `SortPolicy`.
+pub const SORT_POLICY: &'static str = "entries.sort";
+
+/// The URI of the category document whose tags are offered for entries of
+/// the feed.  See the `subscribe::vocabulary` module.
+pub const TAG_VOCABULARY: &'static str = "tags.vocabulary";
+
+/// The number of the latest crawled documents kept as they were fetched.
+/// See the `stage::originals` module.
+pub const KEEP_ORIGINALS: &'static str = "crawler.keep-originals";
+
+/// The SHA-256 fingerprint in hexadecimal the fetched feed document has to
+/// match.  See `crawler::verify_response()`.
+pub const FINGERPRINT: &'static str = "crawler.fingerprint";
+
+/// How long entries are kept after they're updated: `forever`, or seconds.
+/// See `Retention` and the `stage::compact` module.
+pub const RETENTION: &'static str = "entries.retention";
+
+/// How long entries of a feed are kept by `Stage::compact()`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Retention {
+    /// Entries are never removed for their age.
+    #[default]
+    Forever,
+
+    /// Entries not updated for longer than the duration are removed.
+    For(Duration),
+}
+
+impl Retention {
+    /// Whether the entry updated at `updated_at` is older than the
+    /// retention at `now`.
+    pub fn expired(&self, updated_at: DateTime<FixedOffset>,
+                   now: DateTime<FixedOffset>) -> bool {
+        match *self {
+            Retention::Forever => false,
+            Retention::For(d) =>
+                (now - updated_at).num_seconds() > d.as_secs() as i64,
+        }
+    }
+}
+
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Retention::Forever => write!(f, "forever"),
+            Retention::For(d) => write!(f, "{}", d.as_secs()),
+        }
+    }
+}
+
+impl FromStr for Retention {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Retention, ()> {
+        if s.eq_ignore_ascii_case("forever") {
+            return Ok(Retention::Forever);
+        }
+        s.parse().map(|secs| Retention::For(Duration::from_secs(secs)))
+                 .map_err(|_| ())
+    }
+}
+
+/// A single setting.  It corresponds to `setting` element.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Setting {
+    pub name: String,
+
+    /// The value of the setting.  `None` means it's reset to the default,
+    /// which is kept to be merged with older changes.
+    pub value: Option<String>,
+
+    /// When it was changed.
+    #[cfg_attr(feature = "serde",
+               serde(default, with = "::codecs::serde::rfc3339_option"))]
+    pub updated_at: Option<DateTime<FixedOffset>>,
+}
+
+impl Entity for Setting {
+    type Id = str;
+    fn entity_id(&self) -> Cow<str> { Cow::Borrowed(&self.name) }
+}
+
+impl Mergeable for Setting {
+    fn merge_with(&mut self, other: Setting) {
+        if self.updated_at < other.updated_at {
+            *self = other;
+        }
+    }
+}
+
+impl FromSchemaReader for Setting {
+    fn read_from<B: io::BufRead>(&mut self, element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        self.name = try!(element.get_attr("name")).to_owned();
+        self.value = element.get_attr("value").ok().map(|v| v.to_owned());
+        self.updated_at = match element.get_attr("updated") {
+            Ok(v) => Some(try!(codecs::RFC3339.decode(v))),
+            Err(_) => None,
+        };
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for Setting {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let updated_at = match self.updated_at {
+            Some(ref v) => {
+                let mut buf = vec![];
+                try!(codecs::RFC3339.encode(v, &mut buf));
+                Some(String::from_utf8(buf).unwrap())
+            }
+            None => None,
+        };
+        let mut element = element.attr("name", &self.name);
+        if let Some(ref value) = self.value {
+            element = element.attr("value", value);
+        }
+        if let Some(ref updated_at) = updated_at {
+            element = element.attr("updated", updated_at);
+        }
+        try!(writer.write(element));
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+/// The settings document of a subscription.  It corresponds to `settings`
+/// element.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SubscriptionSettings {
+    pub settings: Vec<Setting>,
+}
+
+impl_mergeable!(SubscriptionSettings, settings);
+
+impl SubscriptionSettings {
+    /// The value of the setting, or `None` if it isn't overridden.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.settings.iter().find(|s| s.name == name)
+            .and_then(|s| s.value.as_ref()).map(|v| &v[..])
+    }
+
+    /// Override the setting, or reset it to the default if `value` is
+    /// `None`.
+    pub fn set<T: Into<String>>(&mut self, name: &str, value: Option<T>,
+                                now: DateTime<FixedOffset>) {
+        let value = value.map(|v| v.into());
+        if let Some(setting) = self.settings.iter_mut()
+                                   .find(|s| s.name == name) {
+            setting.value = value;
+            setting.updated_at = Some(now);
+            return;
+        }
+        self.settings.push(Setting {
+            name: name.to_owned(),
+            value: value,
+            updated_at: Some(now),
+        });
+    }
+
+    /// Parse the value of the setting.  Values that can't be parsed are
+    /// regarded as not overridden.
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name).and_then(|v| v.trim().parse().ok())
+    }
+
+    /// How often the feed should be crawled, if it's overridden.
+    pub fn crawl_interval(&self) -> Option<Duration> {
+        self.parse(CRAWL_INTERVAL).map(Duration::from_secs)
+    }
+
+    pub fn set_crawl_interval(&mut self, interval: Option<Duration>,
+                              now: DateTime<FixedOffset>) {
+        self.set(CRAWL_INTERVAL, interval.map(|i| i.as_secs().to_string()),
+                 now);
+    }
+
+    /// The window within which duplicate entries are collapsed at merge
+    /// time, if the feed opts in to it, e.g. planets which give entries
+    /// new IDs on every rebuild.  See the `stage::dedup` module.
+    pub fn dedup_window(&self) -> Option<Duration> {
+        self.parse(DEDUP_WINDOW).map(Duration::from_secs)
+    }
+
+    pub fn set_dedup_window(&mut self, window: Option<Duration>,
+                            now: DateTime<FixedOffset>) {
+        self.set(DEDUP_WINDOW, window.map(|w| w.as_secs().to_string()), now);
+    }
+
+    /// Which date entries of the feed are listed by.  It's `UpdatedFirst`
+    /// unless overridden.
+    pub fn sort_policy(&self) -> SortPolicy {
+        self.parse(SORT_POLICY).unwrap_or_default()
+    }
+
+    pub fn set_sort_policy(&mut self, policy: Option<SortPolicy>,
+                           now: DateTime<FixedOffset>) {
+        self.set(SORT_POLICY, policy.map(|p| p.name()), now);
+    }
+
+    /// The URI of the shared vocabulary of tags for the feed, if any.
+    pub fn tag_vocabulary(&self) -> Option<&str> { self.get(TAG_VOCABULARY) }
+
+    pub fn set_tag_vocabulary(&mut self, uri: Option<&str>,
+                              now: DateTime<FixedOffset>) {
+        self.set(TAG_VOCABULARY, uri, now);
+    }
+
+    /// How many of the latest crawled documents of the feed are kept as
+    /// they were fetched, e.g. to parse them again after parser fixes.
+    /// It's 0, i.e. none are kept, unless overridden.  See the
+    /// `stage::originals` module.
+    pub fn keep_originals(&self) -> usize {
+        self.parse(KEEP_ORIGINALS).unwrap_or(0)
+    }
+
+    pub fn set_keep_originals(&mut self, count: Option<usize>,
+                              now: DateTime<FixedOffset>) {
+        self.set(KEEP_ORIGINALS, count.map(|c| c.to_string()), now);
+    }
+
+    /// How long entries of the feed are kept, if it's overridden rather
+    /// than resolved by the category.  See `stage::RetentionPolicy`.
+    pub fn retention(&self) -> Option<Retention> {
+        self.parse(RETENTION)
+    }
+
+    pub fn set_retention(&mut self, retention: Option<Retention>,
+                         now: DateTime<FixedOffset>) {
+        self.set(RETENTION, retention.map(|r| r.to_string()), now);
+    }
+
+    /// Resolve the sanitizer policy of the feed by overriding the app-wide
+    /// `default` with the settings.
+    pub fn sanitizer_policy(&self, default: SanitizerPolicy)
+                            -> SanitizerPolicy {
+        SanitizerPolicy {
+            allow_iframes: self.parse(ALLOW_IFRAMES)
+                               .unwrap_or(default.allow_iframes),
+            proxy_images: self.parse(PROXY_IMAGES)
+                              .unwrap_or(default.proxy_images),
+            preset: self.parse(SANITIZER_PRESET).unwrap_or(default.preset),
+        }
+    }
+
+    pub fn set_sanitizer_preset(&mut self, preset: Option<SanitizerPreset>,
+                                now: DateTime<FixedOffset>) {
+        self.set(SANITIZER_PRESET, preset.map(|p| p.name()), now);
+    }
+
+    /// How requests of the feed are authenticated, if they are.  Secrets
+    /// aren't in settings but in a `SecretStore`; see `crawler::auth`.
+    #[cfg(feature = "crawler")]
+    pub fn auth(&self) -> Option<Auth> {
+        let username = self.get(AUTH_USERNAME).unwrap_or("").to_owned();
+        match self.get(AUTH_SCHEME).map(|s| s.trim()) {
+            Some("basic") => Some(Auth::Basic { username: username }),
+            Some("digest") => Some(Auth::Digest { username: username }),
+            Some("bearer") => Some(Auth::Bearer),
+            Some("header") => self.get(AUTH_HEADER).map(|name| {
+                Auth::Header { name: name.to_owned() }
+            }),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "crawler")]
+    pub fn set_auth(&mut self, auth: Option<&Auth>,
+                    now: DateTime<FixedOffset>) {
+        let (scheme, username, header) = match auth {
+            Some(Auth::Basic { username }) => {
+                (Some("basic"), Some(&username[..]), None)
+            }
+            Some(Auth::Digest { username }) => {
+                (Some("digest"), Some(&username[..]), None)
+            }
+            Some(Auth::Bearer) => (Some("bearer"), None, None),
+            Some(Auth::Header { name }) => {
+                (Some("header"), None, Some(&name[..]))
+            }
+            None => (None, None, None),
+        };
+        self.set(AUTH_SCHEME, scheme, now);
+        self.set(AUTH_USERNAME, username, now);
+        self.set(AUTH_HEADER, header, now);
+    }
+}
+
+impl SubscriptionSettings {
+    /// The `User-Agent` the crawler sends instead of the app's, if it's
+    /// overridden.
+    pub fn user_agent(&self) -> Option<&str> { self.get(USER_AGENT) }
+
+    pub fn set_user_agent(&mut self, user_agent: Option<&str>,
+                          now: DateTime<FixedOffset>) {
+        self.set(USER_AGENT, user_agent, now);
+    }
+
+    /// Additional request headers the crawler sends for the feed, as pairs
+    /// of a name and a value, in the order they were set.  They're applied
+    /// and validated by `crawler::Request::with_settings()`.
+    pub fn request_headers(&self) -> Vec<(&str, &str)> {
+        self.settings.iter().filter_map(|s| {
+            match (s.name.starts_with(HEADER_PREFIX), s.value.as_ref()) {
+                (true, Some(value)) => {
+                    Some((&s.name[HEADER_PREFIX.len()..], &value[..]))
+                }
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// Set the additional request header, or remove it if `value` is
+    /// `None`.  Secrets e.g. API keys should rather be in a `SecretStore`
+    /// through `set_auth()`, since settings are synchronized in plain text.
+    pub fn set_request_header(&mut self, name: &str, value: Option<&str>,
+                              now: DateTime<FixedOffset>) {
+        self.set(&format!("{}{}", HEADER_PREFIX, name), value, now);
+    }
+
+    /// The SHA-256 fingerprint the fetched feed document is pinned to, in
+    /// hexadecimal, e.g. of a private feed distributed with its expected
+    /// fingerprint.
+    pub fn fingerprint(&self) -> Option<&str> { self.get(FINGERPRINT) }
+
+    pub fn set_fingerprint(&mut self, fingerprint: Option<&str>,
+                           now: DateTime<FixedOffset>) {
+        self.set(FINGERPRINT, fingerprint, now);
+    }
+}
+
+impl DocumentElement for SubscriptionSettings {
+    fn tag() -> &'static str { "settings" }
+    fn xmlns() -> Option<&'static str> { Some(SETTINGS_XMLNS) }
+}
+
+impl FromSchemaReader for SubscriptionSettings {
+    fn match_child<B: io::BufRead>(&mut self, name: &XmlName,
+                                   child: XmlElement<B>) -> DecodeResult<()>
+    {
+        if name.local_name == "setting" {
+            self.settings.push(try!(FromSchemaReader::build_from(child)));
+        }
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for SubscriptionSettings {
+    fn write_children<W: io::Write>(&self, writer: &mut XmlWriter<W>)
+                                    -> SchemaResult<()>
+    {
+        for setting in self.settings.iter() {
+            try!(setting.write_to(writer,
+                                  XmlEvent::start_element("setting")));
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{ALLOW_IFRAMES, Retention, SubscriptionSettings};
+
+    use std::time::Duration;
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use sanitizer::{SanitizerPolicy, SanitizerPreset};
+    use schema::{self, Mergeable};
+
+    #[test]
+    fn test_subscription_settings() {
+        let t1 = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut settings: SubscriptionSettings = Default::default();
+        assert_eq!(settings.crawl_interval(), None);
+        settings.set_crawl_interval(Some(Duration::from_secs(3600)), t1);
+        settings.set(ALLOW_IFRAMES, Some("true"), t1);
+        settings.set_sanitizer_preset(Some(SanitizerPreset::Article), t1);
+        assert_eq!(settings.crawl_interval(), Some(Duration::from_secs(3600)));
+        let default = SanitizerPolicy { allow_iframes: false,
+                                        proxy_images: true,
+                                        preset: SanitizerPreset::Strict };
+        assert_eq!(settings.sanitizer_policy(default),
+                   SanitizerPolicy { allow_iframes: true,
+                                     proxy_images: true,
+                                     preset: SanitizerPreset::Article });
+        settings.set_crawl_interval(None, t1);
+        assert_eq!(settings.crawl_interval(), None);
+        assert_eq!(settings.settings.len(), 3);
+
+        let mut buf = vec![];
+        unwrap!(schema::write(&settings, &mut buf));
+        let read: SubscriptionSettings = unwrap!(schema::read(&buf[..]));
+        assert_eq!(read, settings);
+    }
+
+    #[test]
+    fn test_subscription_settings_merge() {
+        let t1 = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let t2 = FixedOffset::east(0).ymd(2013, 11, 7).and_hms(14, 36, 0);
+        let mut a: SubscriptionSettings = Default::default();
+        a.set(ALLOW_IFRAMES, Some("true"), t1);
+        a.set_crawl_interval(Some(Duration::from_secs(60)), t2);
+        let mut b: SubscriptionSettings = Default::default();
+        b.set(ALLOW_IFRAMES, None::<String>, t2);
+        b.set_crawl_interval(Some(Duration::from_secs(3600)), t1);
+        a.merge_with(b);
+        assert_eq!(a.get(ALLOW_IFRAMES), None);
+        assert_eq!(a.crawl_interval(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_subscription_settings_headers() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let mut settings: SubscriptionSettings = Default::default();
+        assert_eq!(settings.user_agent(), None);
+        settings.set_user_agent(Some("Mozilla/5.0"), t);
+        settings.set_request_header("Cookie", Some("session=1"), t);
+        settings.set_request_header("X-Debug", Some("1"), t);
+        settings.set_request_header("X-Debug", None, t);
+        assert_eq!(settings.user_agent(), Some("Mozilla/5.0"));
+        assert_eq!(settings.request_headers(), [("Cookie", "session=1")]);
+    }
+
+    #[test]
+    fn test_retention() {
+        let t = FixedOffset::east(0).ymd(2013, 11, 6).and_hms(14, 36, 0);
+        let week = Retention::For(Duration::from_secs(7 * 24 * 60 * 60));
+        let mut settings: SubscriptionSettings = Default::default();
+        assert_eq!(settings.retention(), None);
+        settings.set_retention(Some(week), t);
+        assert_eq!(settings.get("entries.retention"), Some("604800"));
+        assert_eq!(settings.retention(), Some(week));
+        settings.set_retention(Some(Retention::Forever), t);
+        assert_eq!(settings.retention(), Some(Retention::Forever));
+        assert_eq!("FOREVER".parse(), Ok(Retention::Forever));
+        assert_eq!("a week".parse::<Retention>(), Err(()));
+
+        let later = FixedOffset::east(0).ymd(2013, 11, 14).and_hms(0, 0, 0);
+        assert!(week.expired(t, later));
+        assert!(!week.expired(t, t));
+        assert!(!Retention::Forever.expired(t, later));
+    }
+}
@@ -0,0 +1,166 @@
+//! Shared vocabularies of tags, i.e. category documents of [RFC 5023][]
+//! (section 7.2.1), so that tags offered in apps come from a document
+//! synchronized between devices rather than being typed again on each.
+//!
+//! ```xml
+//! <categories xmlns="http://www.w3.org/2007/app"
+//!             xmlns:atom="http://www.w3.org/2005/Atom"
+//!             fixed="yes" scheme="http://example.com/tags/">
+//!     <atom:category term="rust" label="Rust"/>
+//!     <atom:category term="web"/>
+//! </categories>
+//! ```
+//!
+//! A subscription uses the vocabulary whose URI its settings tell by
+//! `SubscriptionSettings::set_tag_vocabulary()`, and the stage keeps the
+//! vocabulary document by the URI; see `Stage::offered_tags()`.
+//!
+//! [RFC 5023]: https://tools.ietf.org/html/rfc5023#section-7.2.1
+use std::io;
+
+use xml::writer::events::{StartElementBuilder, XmlEvent};
+
+use feed::{ATOM_XMLNS, Category};
+use parser::base::{DecodeResult, XmlElement};
+use parser::base::NestedEvent::Nested;
+use schema::{DocumentElement, FromSchemaReader, SchemaResult,
+             ToSchemaWriter, XmlWriter};
+
+/// The XML namespace name of the Atom Publishing Protocol.
+pub const APP_XMLNS: &'static str = "http://www.w3.org/2007/app";
+
+/// The category document, i.e. `app:categories` element.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CategoryDocument {
+    /// Whether the list of categories is closed, i.e. terms other than the
+    /// listed ones shouldn't be offered.  It corresponds to `fixed`
+    /// attribute.
+    pub fixed: bool,
+
+    /// The scheme categories without their own `scheme_uri` belong to.
+    pub scheme_uri: Option<String>,
+
+    /// The URI of the out-of-line document which lists the categories
+    /// instead, if this is a reference to it.
+    pub href: Option<String>,
+
+    pub categories: Vec<Category>,
+}
+
+impl CategoryDocument {
+    /// Whether it refers to an out-of-line document rather than listing
+    /// categories itself.
+    pub fn is_out_of_line(&self) -> bool { self.href.is_some() }
+
+    /// The categories with `scheme_uri` inherited from the document.
+    pub fn resolved_categories(&self) -> Vec<Category> {
+        self.categories.iter().map(|c| {
+            let mut category = c.clone();
+            if category.scheme_uri.is_none() {
+                category.scheme_uri = self.scheme_uri.clone();
+            }
+            category
+        }).collect()
+    }
+}
+
+impl DocumentElement for CategoryDocument {
+    fn tag() -> &'static str { "categories" }
+    fn xmlns() -> Option<&'static str> { Some(APP_XMLNS) }
+}
+
+impl FromSchemaReader for CategoryDocument {
+    fn read_from<B: io::BufRead>(&mut self, mut element: XmlElement<B>)
+                                 -> DecodeResult<()>
+    {
+        self.fixed = element.get_attr("fixed").ok() == Some("yes");
+        self.scheme_uri = element.get_attr("scheme").ok()
+                                 .map(|v| v.to_owned());
+        self.href = element.get_attr("href").ok().map(|v| v.to_owned());
+        while let Some(event) = element.children.next() {
+            if let Nested { name, element } = try!(event) {
+                if name.local_name == "category" &&
+                    name.namespace_ref() == Some(ATOM_XMLNS) {
+                    let category = try!(FromSchemaReader::build_from(element));
+                    self.categories.push(category);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ToSchemaWriter for CategoryDocument {
+    fn write_to<'a, W: io::Write>(&'a self, writer: &mut XmlWriter<W>,
+                                  element: StartElementBuilder<'a>)
+                                  -> SchemaResult<()>
+    {
+        let mut element = element.ns("atom", ATOM_XMLNS);
+        if self.fixed {
+            element = element.attr("fixed", "yes");
+        }
+        if let Some(ref scheme_uri) = self.scheme_uri {
+            element = element.attr("scheme", scheme_uri);
+        }
+        if let Some(ref href) = self.href {
+            element = element.attr("href", href);
+        }
+        try!(writer.write(element));
+        for category in self.categories.iter() {
+            try!(category.write_to(writer,
+                                   XmlEvent::start_element("atom:category")));
+        }
+        try!(writer.write(XmlEvent::end_element()));
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::CategoryDocument;
+
+    use feed::Category;
+    use schema::{read, write};
+
+    static FX_CATEGORIES: &'static [u8] = br#"<?xml version="1.0"?>
+    <app:categories xmlns:app="http://www.w3.org/2007/app"
+                    xmlns:atom="http://www.w3.org/2005/Atom"
+                    fixed="yes" scheme="http://example.com/tags/">
+        <atom:category term="rust" label="Rust"/>
+        <atom:category term="web" scheme="http://example.com/other/"/>
+        <category term="ignored"/>
+    </app:categories>"#;
+
+    #[test]
+    fn test_category_document() {
+        let doc: CategoryDocument = unwrap!(read(FX_CATEGORIES));
+        assert!(doc.fixed);
+        assert!(!doc.is_out_of_line());
+        assert_eq!(doc.scheme_uri,
+                   Some("http://example.com/tags/".to_owned()));
+        assert_eq!(doc.categories.len(), 2);
+        assert_eq!(doc.categories[0].label, Some("Rust".to_owned()));
+        let resolved = doc.resolved_categories();
+        assert_eq!(resolved[0], Category {
+            term: "rust".into(),
+            scheme_uri: Some("http://example.com/tags/".to_owned()),
+            label: None,
+        });
+        assert_eq!(resolved[1].scheme_uri,
+                   Some("http://example.com/other/".to_owned()));
+
+        let mut buf = vec![];
+        unwrap!(write(&doc, &mut buf));
+        let written: CategoryDocument = unwrap!(read(&buf[..]));
+        assert_eq!(written, doc);
+
+        let reference: CategoryDocument = unwrap!(read(&br#"
+            <categories xmlns="http://www.w3.org/2007/app"
+                        href="http://example.com/tags.xml"/>"#[..]));
+        assert!(reference.is_out_of_line());
+        assert!(!reference.fixed);
+        assert!(reference.categories.is_empty());
+    }
+}
@@ -1,12 +1,98 @@
 #![macro_use]
 #![doc(hidden)]
 
+use chrono::{FixedOffset, TimeZone};
+#[cfg(feature = "repository")]
 use tempdir::TempDir;
 
+use feed::{Entry, Feed, Text};
+
+#[cfg(feature = "repository")]
 pub fn temp_dir() -> TempDir {
     TempDir::new("rust-earth-test").unwrap()
 }
 
+/// Generate an Atom feed document of the given number of entries, each of
+/// which has an alternate link, an enclosure and a few categories.  It's
+/// for benchmarks, so entries look alike the way real archives do.
+pub fn atom_feed(entries: usize) -> String {
+    let mut xml = atom_feed_head();
+    for i in 0..entries {
+        push_atom_entry(&mut xml, i);
+    }
+    xml.push_str("</feed>");
+    xml
+}
+
+/// Generate an Atom feed document like `atom_feed()`, which is at least
+/// the given number of bytes long.
+pub fn atom_feed_of_size(bytes: usize) -> String {
+    let mut xml = atom_feed_head();
+    let mut i = 0;
+    while xml.len() < bytes {
+        push_atom_entry(&mut xml, i);
+        i += 1;
+    }
+    xml.push_str("</feed>");
+    xml
+}
+
+fn atom_feed_head() -> String {
+    String::from(concat!(
+        r#"<feed xmlns="http://www.w3.org/2005/Atom">"#,
+        "<id>urn:earthreader:bench</id><title>Benchmark</title>",
+        "<updated>2013-11-06T14:36:00Z</updated>",
+        r#"<link rel="self" type="application/atom+xml""#,
+        r#" href="http://example.com/feed.xml"/>"#,
+        "<author><name>Earth Reader</name></author>"))
+}
+
+fn push_atom_entry(xml: &mut String, i: usize) {
+    xml.push_str(&format!(concat!(
+        "<entry><id>urn:earthreader:bench:{0}</id>",
+        "<title>Entry {0}</title>",
+        "<updated>2013-11-06T14:36:00Z</updated>",
+        r#"<link rel="alternate" type="text/html""#,
+        r#" href="http://example.com/{0}"/>"#,
+        r#"<link rel="enclosure" type="audio/mpeg" length="1024""#,
+        r#" href="http://example.com/{0}.mp3"/>"#,
+        r#"<category term="news"/><category term="tag{1}"/>"#,
+        r#"<content type="html">&lt;p&gt;Entry {0}&lt;/p&gt;</content>"#,
+        "</entry>"), i, i % 10));
+}
+
+/// Generate a feed of the given number of entries, whose ids start from
+/// `first`.  Every other entry is read, so merging two of them overlapping
+/// each other has marks to merge as well.
+pub fn feed_of_entries(first: usize, entries: usize) -> Feed {
+    let updated_at = FixedOffset::east(0).ymd(2013, 11, 6)
+                                         .and_hms(14, 36, 0);
+    let mut feed = Feed::new("urn:earthreader:bench", Text::plain("Bench"),
+                             updated_at);
+    for i in first..first + entries {
+        let mut entry = Entry::new(format!("urn:earthreader:bench:{}", i),
+                                   Text::plain(format!("Entry {}", i)),
+                                   updated_at);
+        entry.read.marked = i % 2 == 0;
+        feed.entries.push(entry);
+    }
+    feed
+}
+
+/// Generate a long HTML text of the given number of paragraphs, with the
+/// markup sanitizers have to deal with e.g. scripts, event handlers and
+/// relative links.
+pub fn long_html(paragraphs: usize) -> String {
+    let mut html = String::new();
+    for i in 0..paragraphs {
+        html.push_str(&format!(concat!(
+            r#"<p class="p{0}" onclick="alert({0})">Paragraph {0} with "#,
+            r#"<a href="posts/{0}">a relative link</a>, <em>emphasis</em> "#,
+            r#"&amp; <img src="images/{0}.png" style="display: none;">"#,
+            "</p><script>track({0});</script>\n"), i));
+    }
+    html
+}
 
 #[cfg(test)]
 #[macro_use]
@@ -0,0 +1,184 @@
+//! Naming files after titles of feeds and entries, e.g. when they're
+//! exported, so that names are safe on every platform:
+//!
+//! ```
+//! # use earth::util::FileNames;
+//! let mut names = FileNames::new();
+//! assert_eq!(names.name_for("Fish & Chips: A <Review>", "md"),
+//!            "fish-chips-a-review.md");
+//! assert_eq!(names.name_for("Fish & chips: a review", "md"),
+//!            "fish-chips-a-review-2.md");
+//! assert_eq!(names.name_for("홍민희의 블로그", "md"), "홍민희의-블로그.md");
+//! ```
+//!
+//! Letters and digits of any script are kept, and everything else, e.g.
+//! punctuation and characters Windows doesn't allow in names, separates
+//! words by hyphens.  Names reserved by Windows e.g. `CON` are suffixed,
+//! and names are cut at word boundaries, or at grapheme clusters (see
+//! `graphemes()`) within a long word, so that they fit in the byte limit
+//! of filesystems.
+use std::collections::HashSet;
+
+use super::graphemes;
+
+/// The maximum length of a file name in bytes, including its collision
+/// suffix and extension.  Most filesystems allow 255 bytes, and some room
+/// is left for apps to add their own affixes.
+pub const MAX_FILE_NAME_LEN: usize = 200;
+
+/// Names reserved by Windows regardless of their extensions.
+pub static WINDOWS_RESERVED_NAMES: [&'static str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The slug used for titles which have no letters nor digits.
+const UNTITLED: &'static str = "untitled";
+
+/// Make the title a slug safe as a file name: lowercased words of letters
+/// and digits joined by hyphens, at most `max_len` bytes.  It's cut at a
+/// word boundary, or within the first word if even it doesn't fit.  It's
+/// `untitled` if the title has no letters nor digits.
+///
+/// ```
+/// # use earth::util::slugify;
+/// assert_eq!(slugify("  Hello, World!  ", 200), "hello-world");
+/// assert_eq!(slugify("Hello, World!", 8), "hello");
+/// assert_eq!(slugify("aux", 200), "aux-");
+/// assert_eq!(slugify("...", 200), "untitled");
+/// ```
+pub fn slugify(title: &str, max_len: usize) -> String {
+    let mut words: Vec<String> = vec![];
+    let mut separated = true;
+    for (_, cluster) in graphemes(title) {
+        let first = cluster.chars().next().unwrap();
+        if !first.is_alphanumeric() {
+            separated = true;
+            continue;
+        }
+        if separated {
+            words.push(String::new());
+            separated = false;
+        }
+        let word = words.last_mut().unwrap();
+        word.extend(cluster.chars().flat_map(|c| c.to_lowercase()));
+    }
+    let mut slug = String::with_capacity(title.len());
+    for word in words.iter() {
+        if slug.is_empty() {
+            slug.push_str(truncate_bytes(word, max_len));
+        } else if slug.len() + 1 + word.len() <= max_len {
+            slug.push('-');
+            slug.push_str(word);
+        } else {
+            break;
+        }
+        if slug.len() >= max_len {
+            break;
+        }
+    }
+    if slug.is_empty() {
+        return truncate_bytes(UNTITLED, max_len).to_owned();
+    }
+    if WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(&slug)) {
+        if slug.len() < max_len {
+            slug.push('-');
+        } else {
+            // a reserved name cut by a letter is no longer reserved
+            slug.pop();
+        }
+    }
+    slug
+}
+
+fn truncate_bytes(s: &str, max_len: usize) -> &str {
+    let mut end = 0;
+    for (i, cluster) in graphemes(s) {
+        if i + cluster.len() > max_len {
+            break;
+        }
+        end = i + cluster.len();
+    }
+    &s[..end]
+}
+
+/// File names given so far, to suffix colliding ones by numbers e.g.
+/// `title-2.md`.  Names are compared case-insensitively, since filesystems
+/// of macOS and Windows are.
+#[derive(Clone, Default, Debug)]
+pub struct FileNames {
+    taken: HashSet<String>,
+}
+
+impl FileNames {
+    pub fn new() -> FileNames { Default::default() }
+
+    /// Mark the name as taken, e.g. by a file which already exists.  It
+    /// returns `false` if it's already taken.
+    pub fn take(&mut self, name: &str) -> bool {
+        self.taken.insert(name.to_lowercase())
+    }
+
+    /// A name for the title with the `extension` (without the leading
+    /// dot, or empty for none) which isn't taken yet, at most
+    /// `MAX_FILE_NAME_LEN` bytes.  The name is taken.
+    pub fn name_for(&mut self, title: &str, extension: &str) -> String {
+        let extension = if extension.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", extension)
+        };
+        let max_len = MAX_FILE_NAME_LEN.saturating_sub(extension.len());
+        let slug = slugify(title, max_len);
+        let mut n = 1;
+        loop {
+            let suffix = if n == 1 { String::new() }
+                         else { format!("-{}", n) };
+            let len = max_len.saturating_sub(suffix.len());
+            let name = format!("{}{}{}", slugify(&slug, len), suffix,
+                               extension);
+            if self.take(&name) {
+                return name;
+            }
+            n += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{FileNames, MAX_FILE_NAME_LEN, slugify};
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Rust 1.0 — Released!", 200), "rust-1-0-released");
+        assert_eq!(slugify("C:\\Windows\\<con>|?*", 200), "c-windows-con");
+        assert_eq!(slugify("con", 200), "con-");
+        assert_eq!(slugify("con", 3), "co");
+        assert_eq!(slugify("com1 port", 4), "com");
+        assert_eq!(slugify("日本語のテキスト", 200), "日本語のテキスト");
+        assert_eq!(slugify("e\u{301}te\u{301}", 5), "e\u{301}t");
+        assert_eq!(slugify("hello world", 8), "hello");
+        assert_eq!(slugify("", 200), "untitled");
+        assert_eq!(slugify("", 3), "unt");
+    }
+
+    #[test]
+    fn test_file_names() {
+        let mut names = FileNames::new();
+        assert!(names.take("README.md"));
+        assert!(!names.take("readme.md"));
+        assert_eq!(names.name_for("ReadMe", "md"), "readme-2.md");
+        assert_eq!(names.name_for("ReadMe", ""), "readme");
+        assert_eq!(names.name_for("ReadMe", ""), "readme-2");
+        let long: String = (0..100).map(|_| "가").collect();
+        let name = names.name_for(&long, "md");
+        assert!(name.len() <= MAX_FILE_NAME_LEN);
+        let second = names.name_for(&long, "md");
+        assert!(second.len() <= MAX_FILE_NAME_LEN);
+        assert!(second.ends_with("-2.md"));
+        assert!(name != second);
+    }
+}
@@ -0,0 +1,144 @@
+//! Generating IDs of entries and feeds created locally, e.g. notes or saved
+//! pages, which don't come from any feed.
+//!
+//! Atom requires IDs to be permanent and universally unique, so they're
+//! made as `urn:uuid:` URNs by `UuidGenerator`, or as [tag URIs][] of the
+//! user's domain by `TagUriGenerator`:
+//!
+//! ```
+//! # extern crate chrono;
+//! # extern crate earth;
+//! # fn main() {
+//! use chrono::NaiveDate;
+//! use earth::util::{IdGenerator, TagUriGenerator};
+//!
+//! let generator = TagUriGenerator::new("earthreader.org",
+//!                                      NaiveDate::from_ymd(2013, 11, 6));
+//! let id = generator.generate();
+//! assert!(id.starts_with("tag:earthreader.org,2013-11-06:"));
+//! # }
+//! ```
+//!
+//! [tag URIs]: https://tools.ietf.org/html/rfc4151
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::NaiveDate;
+
+/// Makes unique IDs.  Implementations must never return the same ID twice,
+/// even across processes.
+pub trait IdGenerator {
+    fn generate(&self) -> String;
+}
+
+/// Makes random (version 4) UUIDs as URNs, e.g.
+/// `urn:uuid:6e8bc430-9c3a-41d8-9e0d-a2f56a5d1f3b`.  It's the default.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct UuidGenerator;
+
+impl IdGenerator for UuidGenerator {
+    fn generate(&self) -> String {
+        let mut bytes = random_bytes();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b))
+                                    .collect();
+        format!("urn:uuid:{}-{}-{}-{}-{}", hex[..4].concat(),
+                hex[4..6].concat(), hex[6..8].concat(), hex[8..10].concat(),
+                hex[10..].concat())
+    }
+}
+
+/// Makes tag URIs of the `authority` the user owns, e.g. their domain or
+/// email address, as of the `date` they owned it, e.g.
+/// `tag:earthreader.org,2013-11-06:6e8bc4309c3a41d89e0da2f56a5d1f3b`.
+/// Specific parts are random, so that they don't collide across devices.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TagUriGenerator {
+    pub authority: String,
+    pub date: NaiveDate,
+
+    /// Prepended to the specific part, e.g. `notes/`.
+    pub prefix: String,
+}
+
+impl TagUriGenerator {
+    pub fn new<T: Into<String>>(authority: T, date: NaiveDate)
+                                -> TagUriGenerator {
+        TagUriGenerator {
+            authority: authority.into(),
+            date: date,
+            prefix: String::new(),
+        }
+    }
+}
+
+impl IdGenerator for TagUriGenerator {
+    fn generate(&self) -> String {
+        let specific: String = random_bytes().iter()
+                                             .map(|b| format!("{:02x}", b))
+                                             .collect();
+        format!("tag:{},{}:{}{}", self.authority,
+                self.date.format("%Y-%m-%d"), self.prefix, specific)
+    }
+}
+
+/// Random bytes from randomly keyed hashers of the time and a counter.
+/// They're unpredictable enough for IDs, but not for cryptography.
+fn random_bytes() -> [u8; 16] {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH)
+                                   .map(|d| (d.as_secs(), d.subsec_nanos()))
+                                   .unwrap_or((0, 0));
+    let mut bytes = [0; 16];
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(count);
+        hasher.write_u64(elapsed.0);
+        hasher.write_u32(elapsed.1);
+        hasher.write_usize(i);
+        let value = hasher.finish();
+        for (j, b) in chunk.iter_mut().enumerate() {
+            *b = (value >> (j * 8)) as u8;
+        }
+    }
+    bytes
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{IdGenerator, TagUriGenerator, UuidGenerator};
+
+    use std::collections::HashSet;
+
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_uuid_generator() {
+        let ids: HashSet<String> = (0..100).map(|_| UuidGenerator.generate())
+                                           .collect();
+        assert_eq!(ids.len(), 100);
+        for id in ids {
+            assert_eq!(id.len(), 45);
+            assert!(id.starts_with("urn:uuid:"));
+            assert_eq!(&id[23..24], "4");
+            assert!("89ab".contains(&id[28..29]));
+        }
+    }
+
+    #[test]
+    fn test_tag_uri_generator() {
+        let mut generator =
+            TagUriGenerator::new("hong.minhee.org",
+                                 NaiveDate::from_ymd(2013, 11, 6));
+        generator.prefix = "notes/".to_owned();
+        let a = generator.generate();
+        assert!(a.starts_with("tag:hong.minhee.org,2013-11-06:notes/"));
+        assert_eq!(a.len(), 37 + 32);
+        assert!(a != generator.generate());
+    }
+}
@@ -0,0 +1,287 @@
+use std::borrow::Cow;
+use std::default::Default;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+pub use self::filename::{FileNames, MAX_FILE_NAME_LEN, WINDOWS_RESERVED_NAMES,
+                         slugify};
+pub use self::idgen::{IdGenerator, TagUriGenerator, UuidGenerator};
+
+pub mod filename;
+pub mod idgen;
+
+pub fn get_mut_or_set<T, F>(opt: &mut Option<T>, f: F) -> &mut T
+    where F: Fn() -> T
+{
+    if opt.is_none() {
+        *opt = Some(f());
+    }
+    opt.as_mut().unwrap()
+}
+
+pub fn set_default<T: Default>(opt: &mut Option<T>) -> &mut T {
+    get_mut_or_set(opt, Default::default)
+}
+
+pub fn replace<T>(opt: &mut Option<T>, target: Option<T>) {
+    let old = opt.take();
+    *opt = target.or(old);
+}
+
+pub fn merge_vec<T, I>(base: &mut Vec<T>, data: I)
+    where T: PartialEq, I: Iterator<Item=T>
+{
+    for i in data {
+        if !base.contains(&i) {
+            base.push(i);
+        }
+    }
+}
+
+/// Truncate the string to at most `n` grapheme clusters, i.e. characters
+/// as people perceive them, so that it's never cut in the middle of a
+/// character e.g. a Hangul syllable of conjoining jamo, an emoji sequence,
+/// or a letter with combining marks.  The result is a prefix of `s`, so
+/// it's always valid UTF-8.
+///
+/// Clusters are segmented by a simplified version of extended grapheme
+/// clusters of [UAX #29][], which covers scripts and emoji found in feeds
+/// without Unicode data tables.
+///
+/// [UAX #29]: http://www.unicode.org/reports/tr29/
+///
+/// ```
+/// # use earth::util::truncate_graphemes;
+/// assert_eq!(truncate_graphemes("홍민희", 2), "홍민");
+/// assert_eq!(truncate_graphemes("e\u{301}te\u{301}", 2), "e\u{301}t");
+/// assert_eq!(truncate_graphemes("👍🏽👍", 1), "👍🏽");
+/// ```
+pub fn truncate_graphemes(s: &str, n: usize) -> &str {
+    match graphemes(s).nth(n) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+/// Iterate over grapheme clusters of the string along with their byte
+/// offsets, segmented the same way as `truncate_graphemes()` does.
+///
+/// ```
+/// # use earth::util::graphemes;
+/// let clusters: Vec<_> = graphemes("e\u{301}t").collect();
+/// assert_eq!(clusters, [(0, "e\u{301}"), (3, "t")]);
+/// ```
+pub fn graphemes(s: &str) -> Graphemes {
+    Graphemes { s: s, chars: s.char_indices().peekable() }
+}
+
+pub struct Graphemes<'a> {
+    s: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let (start, first) = match self.chars.next() {
+            Some(c) => c,
+            None => { return None; }
+        };
+        let mut prev = first;
+        let mut end = start + first.len_utf8();
+        // a cluster never starts in the middle of a flag, so counting
+        // regional indicators from the start of the cluster is enough
+        let mut regional_indicators = if is_regional_indicator(first) {
+            1
+        } else {
+            0
+        };
+        while let Some(&(i, c)) = self.chars.peek() {
+            if !extends_grapheme(prev, c, regional_indicators) {
+                break;
+            }
+            regional_indicators = if is_regional_indicator(c) {
+                regional_indicators + 1
+            } else {
+                0
+            };
+            prev = c;
+            end = i + c.len_utf8();
+            self.chars.next();
+        }
+        Some((start, &self.s[start..end]))
+    }
+}
+
+/// Count words of the plain text, e.g. to estimate its reading time.
+/// Words are runs of characters between whitespaces, except that Chinese
+/// and Japanese aren't spaced between words, so each of their characters
+/// counts as a word of its own.  Korean is spaced, so its words are counted
+/// as usual.
+///
+/// ```
+/// # use earth::util::word_count;
+/// assert_eq!(word_count("Hello, world!"), 2);
+/// assert_eq!(word_count("日本語のテキスト。 text"), 9);
+/// ```
+pub fn word_count(text: &str) -> usize {
+    let mut count = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_unspaced(c) {
+            count += 1;
+            in_word = false;
+        } else if c.is_whitespace() || is_cjk_punctuation(c) {
+            in_word = false;
+        } else if !in_word {
+            count += 1;
+            in_word = true;
+        }
+    }
+    count
+}
+
+/// Collapse each run of whitespaces in the text into a single space, and
+/// trim both ends.  The text is borrowed as it is if it's already
+/// collapsed.
+///
+/// ```
+/// # use earth::util::collapse_whitespace;
+/// assert_eq!(collapse_whitespace("  Hello,\n\tworld! "), "Hello, world!");
+/// ```
+pub fn collapse_whitespace(text: &str) -> Cow<str> {
+    let mut prev_space = true;
+    let collapsed = text.chars().all(|c| {
+        let ok = if c.is_whitespace() { c == ' ' && !prev_space }
+                 else { true };
+        prev_space = c.is_whitespace();
+        ok
+    });
+    if collapsed && !prev_space {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// CJK ideographs, hiragana and katakana, i.e. scripts written without
+/// spaces between words.
+fn is_unspaced(c: char) -> bool {
+    matches!(c as u32,
+             0x3040..=0x309F | 0x30A0..=0x30FF | 0x3400..=0x4DBF |
+             0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0xFF66..=0xFF9F |
+             0x20000..=0x2FA1F)
+}
+
+/// CJK symbols and punctuation e.g. `、` and `。`, which separate words
+/// the way spaces do.
+fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c as u32, 0x3000..=0x303F | 0xFF01..=0xFF0F | 0xFF1A..=0xFF1F)
+}
+
+/// Whether `c` continues the grapheme cluster `prev` belongs to, given the
+/// number of regional indicators in a row up to `prev`.
+fn extends_grapheme(prev: char, c: char, regional_indicators: usize)
+                    -> bool {
+    if prev == '\r' && c == '\n' {
+        return true;
+    } else if prev.is_control() || c.is_control() {
+        return false;
+    }
+    if is_extending(c) || c == '\u{200D}' || prev == '\u{200D}' {
+        return true;
+    }
+    if is_regional_indicator(prev) && is_regional_indicator(c) {
+        return regional_indicators % 2 == 1;
+    }
+    match hangul_kind(prev) {
+        Some(Hangul::L) => hangul_kind(c).is_some(),
+        Some(Hangul::V) | Some(Hangul::Lv) => {
+            matches!(hangul_kind(c), Some(Hangul::V) | Some(Hangul::T))
+        }
+        Some(Hangul::T) | Some(Hangul::Lvt) => {
+            hangul_kind(c) == Some(Hangul::T)
+        }
+        None => false,
+    }
+}
+
+/// Combining marks, variation selectors and emoji modifiers, which never
+/// start a cluster.
+fn is_extending(c: char) -> bool {
+    matches!(c as u32,
+             0x0300..=0x036F | 0x0483..=0x0489 | 0x0591..=0x05BD | 0x05BF |
+             0x05C1..=0x05C2 | 0x05C4..=0x05C5 | 0x05C7 | 0x0610..=0x061A |
+             0x064B..=0x065F | 0x0670 | 0x06D6..=0x06DC | 0x06DF..=0x06E4 |
+             0x0900..=0x0903 | 0x093A..=0x094F | 0x0E31 | 0x0E34..=0x0E3A |
+             0x0E47..=0x0E4E | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF |
+             0x200C | 0x20D0..=0x20FF | 0x302A..=0x302F | 0x3099..=0x309A |
+             0xFE00..=0xFE0F | 0xFE20..=0xFE2F | 0x1F3FB..=0x1F3FF |
+             0xE0020..=0xE007F | 0xE0100..=0xE01EF)
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    (0x1F1E6..=0x1F1FF).contains(&(c as u32))
+}
+
+/// Kinds of Hangul characters which make syllables together.
+#[derive(PartialEq)]
+enum Hangul { L, V, T, Lv, Lvt }
+
+fn hangul_kind(c: char) -> Option<Hangul> {
+    match c as u32 {
+        0x1100..=0x115F | 0xA960..=0xA97C => Some(Hangul::L),
+        0x1160..=0x11A7 | 0xD7B0..=0xD7C6 => Some(Hangul::V),
+        0x11A8..=0x11FF | 0xD7CB..=0xD7FB => Some(Hangul::T),
+        n @ 0xAC00..=0xD7A3 => Some(if (n - 0xAC00) % 28 == 0 {
+            Hangul::Lv
+        } else {
+            Hangul::Lvt
+        }),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{collapse_whitespace, truncate_graphemes, word_count};
+
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_truncate_graphemes() {
+        assert_eq!(truncate_graphemes("", 3), "");
+        assert_eq!(truncate_graphemes("abc", 0), "");
+        assert_eq!(truncate_graphemes("abc", 5), "abc");
+        assert_eq!(truncate_graphemes("한글 텍스트", 3), "한글 ");
+        // conjoining jamo of 한 and 글
+        let jamo = "\u{1112}\u{1161}\u{11AB}\u{1100}\u{1173}\u{11AF}";
+        assert_eq!(truncate_graphemes(jamo, 1), "\u{1112}\u{1161}\u{11AB}");
+        assert_eq!(truncate_graphemes("שָׁלוֹם", 1), "שָׁ");
+        assert_eq!(truncate_graphemes("👨‍👩‍👧 family", 1), "👨‍👩‍👧");
+        assert_eq!(truncate_graphemes("🇰🇷🇯🇵", 1), "🇰🇷");
+        assert_eq!(truncate_graphemes("a\r\nb", 2), "a\r\n");
+    }
+
+    #[test]
+    fn test_word_count() {
+        assert_eq!(word_count(""), 0);
+        assert_eq!(word_count("  one two\tthree\n"), 3);
+        assert_eq!(word_count("한글 텍스트입니다."), 2);
+        assert_eq!(word_count("中文，文本"), 4);
+        assert_eq!(word_count("カタカナとEnglish"), 6);
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!(collapse_whitespace(""), "");
+        assert_eq!(collapse_whitespace(" \n "), "");
+        assert_eq!(collapse_whitespace(" one  two\tthree\n"),
+                   "one two three");
+        match collapse_whitespace("one two three") {
+            Cow::Borrowed(s) => assert_eq!(s, "one two three"),
+            Cow::Owned(_) => panic!("collapsed text was copied"),
+        }
+    }
+}